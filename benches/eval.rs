@@ -0,0 +1,73 @@
+//! Throughput of the three things loops spend their time on: evaluating
+//! expressions, assigning variables and printing. Each workload runs a few
+//! statements a million times, so the costs measured are those of each
+//! statement rather than of setting up the run. Run with
+//! `cargo bench --bench eval`.
+
+use knitlang::bench;
+use knitlang::{parse_src, Interpreter};
+use std::io;
+
+const PASSES: u32 = 1_000_000;
+
+struct Workload {
+    name: &'static str,
+    /// Set-up before the loop.
+    prelude: &'static str,
+    /// The loop body.
+    body: &'static str,
+    /// What is counted per pass, and how many of them there are.
+    unit: &'static str,
+    per_pass: f64,
+}
+
+const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "expressions",
+        prelude: "cast_on a = 7; cast_on b = 3; cast_on c = 0;",
+        body: "knit c = a * 3 + b; knit c = c - a / 2; knit c = b * b - 1; \
+               knit c = a + b * 4 - c / 3 + 2;",
+        unit: "operators",
+        per_pass: 10.0,
+    },
+    Workload {
+        name: "assignments",
+        prelude: "cast_on a = 0; cast_on b = 0; cast_on c = 0; cast_on d = 0;",
+        body: "knit a = 1; knit b = a; knit c = 2; knit d = c; knit a = d;",
+        unit: "assignments",
+        per_pass: 5.0,
+    },
+    Workload {
+        name: "printing",
+        prelude: "cast_on i = 0;",
+        body: "purl i; purl 12345; purl i;",
+        unit: "lines",
+        per_pass: 3.0,
+    },
+];
+
+fn main() {
+    for workload in WORKLOADS {
+        let src = format!(
+            "{}\nrepeat {PASSES} {{\n    {}\n}}\n",
+            workload.prelude, workload.body
+        );
+        let stmts = parse_src(&src).unwrap();
+        let timings = bench::measure(
+            1,
+            10,
+            || Interpreter::with_output(Box::new(io::sink())),
+            |mut interp| interp.run(&stmts).map(|_| interp),
+        )
+        .unwrap();
+        println!(
+            "{:<12} {PASSES} passes in {:?} (median of {}, best {:?}): {:.0} {}/s",
+            workload.name,
+            timings.median(),
+            timings.runs(),
+            timings.min(),
+            timings.per_sec(f64::from(PASSES) * workload.per_pass),
+            workload.unit,
+        );
+    }
+}
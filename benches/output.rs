@@ -0,0 +1,43 @@
+//! Output throughput of a program that purls a line per pass, comparing a
+//! sink that writes each line as it comes, as stdout does, with a buffered
+//! one, as the command line uses. Run with `cargo bench --bench output`.
+
+use knitlang::bench;
+use knitlang::{parse_src, Interpreter};
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{BufWriter, LineWriter, Write};
+
+const LINES: usize = 200_000;
+
+fn measure(name: &str, sink: impl Fn(File) -> Box<dyn Write>) {
+    let stmts = parse_src(&format!(
+        "cast_on x = 0; repeat {LINES} {{ knit x = x + 1; purl x; }}"
+    ))
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("knitlang-bench-{}", std::process::id()));
+    let timings = bench::measure(
+        1,
+        5,
+        || Interpreter::with_output(sink(File::create(&path).unwrap())),
+        |mut interp| {
+            interp.run(&stmts).unwrap();
+            interp.flush().unwrap();
+            Ok::<_, Infallible>(interp)
+        },
+    )
+    .unwrap();
+    let _ = std::fs::remove_file(&path);
+    println!(
+        "{name}: {LINES} lines in {:?} (median of {}, best {:?}): {:.0} lines/s",
+        timings.median(),
+        timings.runs(),
+        timings.min(),
+        timings.per_sec(LINES as f64),
+    );
+}
+
+fn main() {
+    measure("line by line", |file| Box::new(LineWriter::new(file)));
+    measure("buffered", |file| Box::new(BufWriter::new(file)));
+}
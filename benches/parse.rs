@@ -0,0 +1,77 @@
+//! Parse throughput and peak memory on a large generated program, comparing
+//! parsing straight from the lexer with lexing everything first. Run with
+//! `cargo bench --bench parse`.
+
+use knitlang::bench;
+use knitlang::{lex_all, parse_src, Parser, Stmt};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::convert::Infallible;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tracks live and peak heap use.
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// A program of `blocks` copies of a small pattern, about 120 bytes each.
+fn program(blocks: usize) -> String {
+    let block = "cast_on width_0 = 4 * 2 + 1;\nrepeat width_0 / 3 {\n    k 1;\n    p 1;\n    \
+                 knit width_0 = width_0 - 1;\n}\npurl width_0;\n";
+    (0..blocks)
+        .map(|i| block.replace("width_0", &format!("width_{}", i % 50)))
+        .collect()
+}
+
+/// The pipeline before parsing was lazy: every token, then the tree.
+fn parse_lexed(src: &str) -> Vec<Stmt> {
+    Parser::new(lex_all(src).unwrap()).parse().unwrap()
+}
+
+fn measure(name: &str, src: &str, parse: fn(&str) -> Vec<Stmt>) {
+    let mut peak = 0;
+    let setup = || {
+        let before = LIVE.load(Ordering::Relaxed);
+        PEAK.store(before, Ordering::Relaxed);
+        before
+    };
+    let timings = bench::measure(1, 20, setup, |before| {
+        let stmts = parse(black_box(src));
+        peak = peak.max(PEAK.load(Ordering::Relaxed) - before);
+        Ok::<_, Infallible>(black_box(stmts))
+    })
+    .unwrap();
+    println!(
+        "{name}: parsed {} KiB in {:?} (median of {}, best {:?}): {:.1} MB/s, \
+         peak {} KiB",
+        src.len() / 1024,
+        timings.median(),
+        timings.runs(),
+        timings.min(),
+        timings.per_sec(src.len() as f64) / 1e6,
+        peak / 1024,
+    );
+}
+
+fn main() {
+    let src = program(20_000);
+    measure("from the lexer", &src, |src| parse_src(src).unwrap());
+    measure("lexed first", &src, parse_lexed);
+}
@@ -0,0 +1,31 @@
+//! Execution throughput on a counter loop, which spends its time reading and
+//! writing variables. Run with `cargo bench --bench run`.
+
+use knitlang::bench;
+use knitlang::{parse_src, Interpreter};
+use std::io;
+
+const PASSES: u32 = 2_000_000;
+
+fn main() {
+    let src = format!(
+        "cast_on i = 0;\ncast_on total = 0;\ncast_on step = 3;\nrepeat {PASSES} {{\n    \
+         knit i = i + 1;\n    knit total = total + i * step - i / 2;\n}}\n"
+    );
+    let stmts = parse_src(&src).unwrap();
+    let timings = bench::measure(
+        1,
+        10,
+        || Interpreter::with_output(Box::new(io::sink())),
+        |mut interp| interp.run(&stmts).map(|_| interp),
+    )
+    .unwrap();
+    println!(
+        "counter loop: {PASSES} passes in {:?} (median of {}, best {:?}): \
+         {:.0} variable reads/s",
+        timings.median(),
+        timings.runs(),
+        timings.min(),
+        timings.per_sec(f64::from(PASSES) * 5.0),
+    );
+}
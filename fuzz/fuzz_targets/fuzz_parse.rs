@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|src: &str| {
+    // Whatever parses must print back to source that parses to the same tree.
+    if let Ok(stmts) = knitlang::try_parse(src) {
+        let formatted = knitlang::ast::format_program(&stmts);
+        let reparsed = knitlang::try_parse(&formatted).expect("formatted source parses");
+        assert_eq!(knitlang::ast::format_program(&reparsed), formatted);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use knitlang::{run_source, RunOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|src: &str| {
+    let options = RunOptions {
+        step_limit: Some(10_000),
+        ..RunOptions::default()
+    };
+    let _ = run_source(src, options);
+});
@@ -0,0 +1,99 @@
+/*
+ * C interface to the knitlang interpreter.
+ *
+ * Ownership rules:
+ * - A KnitInterp is created by knit_interp_new and must be released with
+ *   knit_interp_free, exactly once.
+ * - Strings passed in are NUL-terminated UTF-8 and are only read during the
+ *   call; the caller keeps ownership.
+ * - Strings handed out (knit_last_error, the output callback's text) are owned
+ *   by the library and only valid until the next call on the same
+ *   interpreter. Copy them to keep them.
+ * - No call unwinds into C; internal errors return KNIT_ERR_INTERNAL.
+ */
+
+#ifndef KNITLANG_H
+#define KNITLANG_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The program ran to the end or to `bind_off`.
+#define KNIT_OK 0
+
+// The program failed while running.
+#define KNIT_ERR_RUNTIME 1
+
+// The source did not lex or parse; nothing ran.
+#define KNIT_ERR_PARSE 2
+
+// A pointer was NULL or a string was not valid UTF-8.
+#define KNIT_ERR_ARGUMENT 3
+
+// The library hit a bug. The interpreter may be left in any state but is
+// still safe to free.
+#define KNIT_ERR_INTERNAL 4
+
+// An interpreter session. Variables and the fabric carry over from one
+// `knit_run_source` call to the next.
+typedef struct KnitInterp KnitInterp;
+
+// Receives program output. `text` is one line, including its newline, as
+// NUL-terminated UTF-8 that is only valid during the call; `user_data` is the
+// pointer given to `knit_set_output`.
+typedef void (*KnitOutputFn)(void *user_data, const char *text);
+
+// Creates an interpreter that discards output until `knit_set_output` is
+// called. Free it with `knit_interp_free`. Returns NULL only on an internal
+// error.
+struct KnitInterp *knit_interp_new(void);
+
+// Frees an interpreter from `knit_interp_new`. NULL is ignored.
+//
+// # Safety
+//
+// `interp` must be NULL or a pointer from `knit_interp_new` that has not been
+// freed yet.
+void knit_interp_free(struct KnitInterp *interp);
+
+// Sends `purl` output to `callback` one line at a time, passing `user_data`
+// along; a NULL callback discards output again.
+//
+// # Safety
+//
+// `interp` must be NULL or a live interpreter, and `callback` must be safe to
+// call with `user_data` for as long as it is set.
+void knit_set_output(struct KnitInterp *interp, KnitOutputFn callback, void *user_data);
+
+// Runs the NUL-terminated UTF-8 program `src`, returning `KNIT_OK` or one of
+// the `KNIT_ERR_*` codes. On failure `knit_last_error` describes it.
+//
+// # Safety
+//
+// `interp` must be NULL or a live interpreter, and `src` NULL or a
+// NUL-terminated string.
+int knit_run_source(struct KnitInterp *interp, const char *src);
+
+// Describes the last failed call on `interp` as NUL-terminated UTF-8, or
+// returns NULL if it succeeded. The string belongs to the interpreter and is
+// valid until the next call on it.
+//
+// # Safety
+//
+// `interp` must be NULL or a live interpreter.
+const char *knit_last_error(const struct KnitInterp *interp);
+
+// Stores the value of variable `name` in `*out` and returns true, or returns
+// false and leaves `*out` alone if there is no such variable.
+//
+// # Safety
+//
+// `interp` must be NULL or a live interpreter, `name` NULL or a
+// NUL-terminated string, and `out` NULL or valid for writing.
+bool knit_get_var(struct KnitInterp *interp, const char *name, int64_t *out);
+
+#endif  /* KNITLANG_H */
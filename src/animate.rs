@@ -0,0 +1,139 @@
+//! The chart of a piece redrawn in a terminal as its rows are knitted, for
+//! `--animate`. A [`frame`] is the text chart of as many of the latest rows
+//! as fit the terminal, cut to its width, over a status line giving the
+//! rows done and the stitches on the needles. An [`Animation`] writes one
+//! frame over the last with ANSI escape codes, hiding the cursor while it
+//! runs and showing it again when it is finished or dropped, so that the
+//! last frame stays on screen with the terminal as it was.
+
+use crate::chart::{self, ChartStyle, Orientation};
+use crate::interp::Fabric;
+use crate::plural;
+use std::io::{self, Write};
+
+/// Hides the cursor.
+const HIDE_CURSOR: &str = "\x1b[?25l";
+/// Shows the cursor again.
+pub const SHOW_CURSOR: &str = "\x1b[?25h";
+
+/// The frame for `fabric` on a terminal `columns` wide and `lines` high:
+/// the chart of its latest rows that fit in all but two lines, one for the
+/// status line and one for the cursor, with every line cut to `columns`
+/// characters:
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 4; repeat 6 { k 2; p 2; }").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let symbols = knitlang::chart::ChartStyle::default();
+/// assert_eq!(
+///     knitlang::animate::frame(interp.fabric(), &symbols, 8, 7),
+///     "WS 6 ••│\n     ••│\n     432\n│ knit o\n• purl o\nRow 6: 4\n"
+/// );
+/// ```
+pub fn frame(fabric: &Fabric, symbols: &ChartStyle, columns: usize, lines: usize) -> String {
+    let rows = fabric.rows().len();
+    let room = lines.saturating_sub(2);
+    let orientation = Orientation::default();
+    // The chart of fewer rows is never taller, so the most that fit is
+    // found by trying fewer and fewer.
+    let mut shown = rows.min(room);
+    let mut text = chart::text_rows(fabric, rows - shown..rows, orientation, symbols);
+    while shown > 0 && text.lines().count() > room {
+        shown -= 1;
+        text = chart::text_rows(fabric, rows - shown..rows, orientation, symbols);
+    }
+    if shown == 0 {
+        text.clear();
+    }
+    let status = match rows {
+        0 => format!("{} cast on", plural(fabric.width(), "stitch")),
+        _ => format!(
+            "Row {rows}: {} on the needles",
+            plural(fabric.width(), "stitch")
+        ),
+    };
+    let mut out = String::new();
+    for line in text.lines().chain([status.as_str()]) {
+        out.extend(line.chars().take(columns));
+        out.push('\n');
+    }
+    out
+}
+
+/// Frames drawn one over another in a terminal, or whatever stands in for
+/// one, such as a buffer in a test.
+pub struct Animation<W: Write> {
+    out: W,
+    symbols: ChartStyle,
+    columns: usize,
+    lines: usize,
+    /// How many lines the frame on screen takes, to move back over.
+    drawn: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<W: Write> Animation<W> {
+    /// An animation writing to `out`, a terminal `columns` wide and `lines`
+    /// high, charting stitches with `symbols`.
+    pub fn new(out: W, symbols: ChartStyle, columns: usize, lines: usize) -> Self {
+        Animation {
+            out,
+            symbols,
+            columns,
+            lines,
+            drawn: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Draws the frame for `fabric` over the last one:
+    ///
+    /// ```
+    /// let stmts = knitlang::parse_src("cast_on 2; k 2;").unwrap();
+    /// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+    /// interp.run(&stmts).unwrap();
+    /// let symbols = knitlang::chart::ChartStyle::default();
+    /// let mut screen = Vec::new();
+    /// let mut animation = knitlang::animate::Animation::new(&mut screen, symbols, 80, 24);
+    /// animation.draw(interp.fabric()).unwrap();
+    /// animation.draw(interp.fabric()).unwrap();
+    /// drop(animation);
+    /// let frame = "││ 1 RS\n21\n│ knit\nRow 1: 2 stitches on the needles\n";
+    /// assert_eq!(
+    ///     String::from_utf8(screen).unwrap(),
+    ///     format!("\x1b[?25l{frame}\x1b[4F\x1b[J{frame}\x1b[?25h")
+    /// );
+    /// ```
+    pub fn draw(&mut self, fabric: &Fabric) -> io::Result<()> {
+        let frame = frame(fabric, &self.symbols, self.columns, self.lines);
+        if !self.started {
+            self.out.write_all(HIDE_CURSOR.as_bytes())?;
+            self.started = true;
+        }
+        if self.drawn > 0 {
+            write!(self.out, "\x1b[{}F\x1b[J", self.drawn)?;
+        }
+        self.out.write_all(frame.as_bytes())?;
+        self.drawn = frame.lines().count();
+        self.out.flush()
+    }
+
+    /// Shows the cursor again, leaving the last frame on screen.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.started && !self.finished {
+            self.finished = true;
+            self.out.write_all(SHOW_CURSOR.as_bytes())?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for Animation<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
@@ -0,0 +1,1918 @@
+//! The syntax tree produced by the parser, and its canonical printer.
+
+use crate::chart::rgb;
+use crate::lexer::{lex_with_trivia, Span, TriviaKind};
+use crate::plural;
+use crate::resolve::{self, Slot};
+use crate::symbol::Symbol;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::iter;
+use std::mem;
+use std::slice;
+
+/// An integer expression. Its nodes are stored flat in one `Vec`, operands
+/// before their operator, so the root comes last and children are referred
+/// to by [`ExprId`]. Evaluating it is one pass over the nodes, and dropping
+/// it frees a single allocation however deep it is.
+#[derive(Clone)]
+pub struct Expr {
+    nodes: Vec<ExprNode>,
+    /// The same arithmetic as [`Op`]s, or empty if the expression calls a
+    /// function.
+    ops: Vec<Op>,
+    /// For the value of `cast_on name = ...;` or `knit name = ...;`, the
+    /// slot of `name`, numbered along with the ones the expression reads.
+    target: Slot,
+}
+
+/// One step of working out an expression that only does arithmetic on
+/// numbers and variables, with the value so far held aside from the stack.
+/// An operator with a number or a variable on its right is one step, so
+/// `a * 3 + b` is three: push `a`, multiply by 3, add `b`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Op {
+    /// Pushes the value so far and starts again from the operand.
+    Push(Operand),
+    /// Combines the value so far with the operand, on its right.
+    Apply(char, Operand),
+    /// Combines the value on top of the stack, on the left, with the value
+    /// so far.
+    Pop(char),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Operand {
+    Number(i64),
+    /// A variable, and where the run of its program keeps it; see
+    /// [`crate::resolve::number_slots`].
+    Var(Symbol, Slot),
+}
+
+/// The index of a node within its [`Expr`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExprId(u32);
+
+#[derive(Debug, Clone)]
+pub struct ExprNode {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(i64),
+    Var(Symbol),
+    Binary(ExprId, char, ExprId),
+    /// `name(args...)`: a function the host registered with
+    /// [`crate::Interpreter::register_builtin`].
+    Call(Symbol, Vec<ExprId>),
+    /// `stitches_to(marker)`: how many stitches the current row has left to
+    /// work before it reaches a stitch marker.
+    StitchesTo(Symbol),
+    /// `88 (96, 104, 112)`: a number for each size `sizes` declares, of
+    /// which the one for the size being knitted is the value.
+    Sizes(Vec<i64>),
+    /// `count(needle)`: how many stitches a needle declared with
+    /// `cast_on name = needle(n);` holds.
+    Count(Symbol),
+}
+
+impl Expr {
+    fn leaf(kind: ExprKind, span: Span) -> Self {
+        let operand = match kind {
+            ExprKind::Number(n) => Operand::Number(n),
+            ExprKind::Var(name) => Operand::Var(name, Slot::NONE),
+            _ => unreachable!("leaves are numbers and variables"),
+        };
+        Self {
+            nodes: vec![ExprNode { kind, span }],
+            ops: vec![Op::Push(operand)],
+            target: Slot::NONE,
+        }
+    }
+
+    pub fn number(n: i64, span: Span) -> Self {
+        Self::leaf(ExprKind::Number(n), span)
+    }
+
+    pub fn var(name: Symbol, span: Span) -> Self {
+        Self::leaf(ExprKind::Var(name), span)
+    }
+
+    /// `stitches_to(marker)`; `span` runs from `stitches_to` to the closing
+    /// parenthesis.
+    pub fn stitches_to(marker: Symbol, span: Span) -> Self {
+        Self {
+            nodes: vec![ExprNode {
+                kind: ExprKind::StitchesTo(marker),
+                span,
+            }],
+            ops: Vec::new(),
+            target: Slot::NONE,
+        }
+    }
+
+    /// `count(needle)`; `span` runs from `count` to the closing parenthesis.
+    pub fn count(needle: Symbol, span: Span) -> Self {
+        Self {
+            nodes: vec![ExprNode {
+                kind: ExprKind::Count(needle),
+                span,
+            }],
+            ops: Vec::new(),
+            target: Slot::NONE,
+        }
+    }
+
+    /// A size tuple of `values`, one for each declared size; `span` runs
+    /// from the first value to the closing parenthesis.
+    pub fn sizes(values: Vec<i64>, span: Span) -> Self {
+        Self {
+            nodes: vec![ExprNode {
+                kind: ExprKind::Sizes(values),
+                span,
+            }],
+            ops: Vec::new(),
+            target: Slot::NONE,
+        }
+    }
+
+    pub fn binary(lhs: Expr, op: char, rhs: Expr) -> Self {
+        let span = Span::new(lhs.span().start, rhs.span().end);
+        Self::binary_at(lhs, op, rhs, span)
+    }
+
+    pub(crate) fn binary_at(mut lhs: Expr, op: char, rhs: Expr, span: Span) -> Self {
+        let left = lhs.root();
+        match rhs.ops[..] {
+            _ if lhs.ops.is_empty() || rhs.ops.is_empty() => lhs.ops.clear(),
+            [Op::Push(operand)] => lhs.ops.push(Op::Apply(op, operand)),
+            _ => {
+                lhs.ops.extend_from_slice(&rhs.ops);
+                lhs.ops.push(Op::Pop(op));
+            }
+        }
+        let right = append(&mut lhs.nodes, rhs);
+        lhs.nodes.push(ExprNode {
+            kind: ExprKind::Binary(left, op, right),
+            span,
+        });
+        lhs
+    }
+
+    /// A call of `name` on `args`; `span` runs from the name to the closing
+    /// parenthesis.
+    pub fn call(name: Symbol, args: Vec<Expr>, span: Span) -> Self {
+        let mut nodes = Vec::new();
+        let args = args
+            .into_iter()
+            .map(|arg| append(&mut nodes, arg))
+            .collect();
+        nodes.push(ExprNode {
+            kind: ExprKind::Call(name, args),
+            span,
+        });
+        Self {
+            nodes,
+            ops: Vec::new(),
+            target: Slot::NONE,
+        }
+    }
+
+    /// The node the others hang off: the whole expression.
+    pub fn root(&self) -> ExprId {
+        ExprId(self.nodes.len() as u32 - 1)
+    }
+
+    pub fn node(&self, id: ExprId) -> &ExprNode {
+        &self.nodes[id.0 as usize]
+    }
+
+    /// Every node, each after its operands.
+    pub fn nodes(&self) -> &[ExprNode] {
+        &self.nodes
+    }
+
+    /// The arithmetic of the expression as a list of steps, or nothing if
+    /// it calls a function or looks for a marker.
+    pub(crate) fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// The variables [`Expr::ops`] read, with their slots.
+    pub(crate) fn slots(&self) -> impl Iterator<Item = (Symbol, Slot)> + '_ {
+        self.ops.iter().filter_map(|op| match *op {
+            Op::Push(Operand::Var(name, slot)) | Op::Apply(_, Operand::Var(name, slot)) => {
+                Some((name, slot))
+            }
+            _ => None,
+        })
+    }
+
+    /// Where the statement this is the value of stores it; see
+    /// [`Expr::slots`].
+    pub(crate) fn target(&self) -> Slot {
+        self.target
+    }
+
+    pub(crate) fn set_target(&mut self, slot: Slot) {
+        self.target = slot;
+    }
+
+    /// Like [`Expr::slots`], but the slots can be changed.
+    pub(crate) fn slots_mut(&mut self) -> impl Iterator<Item = (Symbol, &mut Slot)> {
+        self.ops.iter_mut().filter_map(|op| match op {
+            Op::Push(Operand::Var(name, slot)) | Op::Apply(_, Operand::Var(name, slot)) => {
+                Some((*name, slot))
+            }
+            _ => None,
+        })
+    }
+
+    /// What the expression is at its root.
+    pub fn kind(&self) -> &ExprKind {
+        &self.node(self.root()).kind
+    }
+
+    /// Where the whole expression is in the source.
+    pub fn span(&self) -> Span {
+        self.node(self.root()).span
+    }
+
+    /// Renames every variable the expression reads to what `rename` gives
+    /// for its name. Their slots are forgotten, to be numbered again.
+    pub fn rename_vars(&mut self, mut rename: impl FnMut(Symbol) -> Symbol) {
+        for node in &mut self.nodes {
+            if let ExprKind::Var(name) = &mut node.kind {
+                *name = rename(*name);
+            }
+        }
+        for op in &mut self.ops {
+            if let Op::Push(Operand::Var(name, slot)) | Op::Apply(_, Operand::Var(name, slot)) = op
+            {
+                *name = rename(*name);
+                *slot = Slot::NONE;
+            }
+        }
+    }
+}
+
+/// Expressions are equal if they have the same structure, wherever in the
+/// source they came from: spans are ignored.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        // Nodes are laid out in the same order for the same tree, so equal
+        // trees have equal nodes at every index.
+        self.nodes.len() == other.nodes.len()
+            && self
+                .nodes
+                .iter()
+                .zip(&other.nodes)
+                .all(|(a, b)| a.kind == b.kind)
+    }
+}
+
+/// Moves the nodes of `expr` onto the end of `nodes`, returning the id its
+/// root ends up with.
+fn append(nodes: &mut Vec<ExprNode>, expr: Expr) -> ExprId {
+    let offset = nodes.len() as u32;
+    nodes.extend(expr.nodes.into_iter().map(|mut node| {
+        match &mut node.kind {
+            ExprKind::Number(_)
+            | ExprKind::Var(_)
+            | ExprKind::StitchesTo(_)
+            | ExprKind::Count(_)
+            | ExprKind::Sizes(_) => {}
+            ExprKind::Binary(a, _, b) => {
+                a.0 += offset;
+                b.0 += offset;
+            }
+            ExprKind::Call(_, args) => args.iter_mut().for_each(|arg| arg.0 += offset),
+        }
+        node
+    }));
+    ExprId(nodes.len() as u32 - 1)
+}
+
+/// A node and everything below it, for tree-shaped `Debug` output.
+struct Tree<'a>(&'a Expr, ExprId);
+
+impl fmt::Debug for Tree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Tree(expr, id) = *self;
+        let node = expr.node(id);
+        f.debug_struct("Expr")
+            .field("kind", &KindTree(expr, id))
+            .field("span", &node.span)
+            .finish()
+    }
+}
+
+/// The kind of a node, with operands shown as [`Tree`]s.
+struct KindTree<'a>(&'a Expr, ExprId);
+
+impl fmt::Debug for KindTree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let KindTree(expr, id) = *self;
+        match &expr.node(id).kind {
+            ExprKind::Number(n) => f.debug_tuple("Number").field(n).finish(),
+            ExprKind::Var(name) => f.debug_tuple("Var").field(name).finish(),
+            ExprKind::Binary(lhs, op, rhs) => f
+                .debug_tuple("Binary")
+                .field(&Tree(expr, *lhs))
+                .field(op)
+                .field(&Tree(expr, *rhs))
+                .finish(),
+            ExprKind::Call(name, args) => f
+                .debug_tuple("Call")
+                .field(name)
+                .field(&ArgTrees(expr, args))
+                .finish(),
+            ExprKind::StitchesTo(marker) => f.debug_tuple("StitchesTo").field(marker).finish(),
+            ExprKind::Count(needle) => f.debug_tuple("Count").field(needle).finish(),
+            ExprKind::Sizes(values) => f.debug_tuple("Sizes").field(values).finish(),
+        }
+    }
+}
+
+/// The arguments of a call, as a list of [`Tree`]s.
+struct ArgTrees<'a>(&'a Expr, &'a [ExprId]);
+
+impl fmt::Debug for ArgTrees<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ArgTrees(expr, args) = *self;
+        f.debug_list()
+            .entries(args.iter().map(|&arg| Tree(expr, arg)))
+            .finish()
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Tree(self, self.root()).fmt(f)
+    }
+}
+
+impl Span {
+    pub(crate) fn to_json(self) -> serde_json::Value {
+        json!({ "start": self.start, "end": self.end })
+    }
+}
+
+/// The JSON shape of the syntax tree, as written by [`ast_to_json`]: every
+/// node is an object whose `"kind"` names it, with its span and its children
+/// under named keys. Operators and stitches are their source spelling.
+impl Expr {
+    pub fn to_json(&self) -> serde_json::Value {
+        self.node_json(self.root())
+    }
+
+    fn node_json(&self, id: ExprId) -> serde_json::Value {
+        let node = self.node(id);
+        let span = node.span.to_json();
+        match &node.kind {
+            ExprKind::Number(n) => json!({ "kind": "number", "value": n, "span": span }),
+            ExprKind::Var(name) => json!({ "kind": "var", "name": name, "span": span }),
+            ExprKind::Binary(lhs, op, rhs) => json!({
+                "kind": "binary",
+                "op": op.to_string(),
+                "lhs": self.node_json(*lhs),
+                "rhs": self.node_json(*rhs),
+                "span": span,
+            }),
+            ExprKind::Call(name, args) => json!({
+                "kind": "call",
+                "name": name,
+                "args": args.iter().map(|&arg| self.node_json(arg)).collect::<Vec<_>>(),
+                "span": span,
+            }),
+            ExprKind::StitchesTo(marker) => {
+                json!({ "kind": "stitches_to", "marker": marker, "span": span })
+            }
+            ExprKind::Count(needle) => json!({ "kind": "count", "needle": needle, "span": span }),
+            ExprKind::Sizes(values) => json!({ "kind": "sizes", "values": values, "span": span }),
+        }
+    }
+}
+
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+/// A statement, with the span of its source text.
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    CastOn(Symbol, Expr),       // cast_on name = expr;
+    CastOnStitches(Expr),       // cast_on expr;
+    CastOnNeedle(Symbol, Expr), // cast_on name = needle(expr);
+    Knit(Symbol, Expr),         // knit name = expr;
+    Purl(Expr),                 // purl expr;
+    Work(Stitch, Expr),         // k expr; / p expr; / c4f;
+    Repeat(Expr, Vec<Stmt>),
+    BindOff,
+    BindOffStitches(Expr),          // bind_off expr;
+    ExpectStitches(Expr),           // expect_stitches expr;
+    ExpectStitchesWarning(Expr),    // expect_stitches? expr;
+    Yarn(Symbol, String),           // yarn name = "#colour";
+    Color(Symbol),                  // color name;
+    Cable(Expr, Cross),             // cable n front;
+    WrapTurn,                       // wrap_turn; / w&t;
+    Mode(Mode),                     // mode flat; / mode round;
+    PlaceMarker(Symbol),            // place_marker name;
+    SlipMarker(Symbol),             // slip_marker name;
+    Use(String, Option<Symbol>),    // use "file.knit"; / use "file.knit" as name;
+    Sizes(Vec<Symbol>),             // sizes S, M, L;
+    Gauge(Expr, Expr),              // gauge 22, 30;
+    Distribute(Shaping, Expr),      // distribute inc 12;
+    Mirror(Vec<Stmt>),              // mirror { ... }
+    Alternate(Vec<Vec<Stmt>>),      // alternate { ... } { ... }
+    Pattern(Pattern, Vec<Expr>),    // seed(4); / rib(2, 2, 8);
+    Meta(Vec<MetaEntry>),           // meta { title = "Winter Hat"; }
+    Note(Vec<NotePart>),            // note "Switch to 3.5mm needles"; / note "Dec to ", n, " sts";
+    Chart(Option<String>),          // chart; / chart "ribbing";
+    Transfer(Symbol, Symbol, Expr), // transfer from to expr;
+    Hold(Symbol),                   // hold name;
+    Resume(Symbol),                 // resume name;
+}
+
+/// A piece of the text of a `note`: a string as written, or an expression
+/// whose value is written in its place.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotePart {
+    Text(String),
+    Expr(Expr),
+}
+
+/// One `key = value;` of a `meta` block, with the span of all of it.
+#[derive(Debug, Clone)]
+pub struct MetaEntry {
+    pub key: Symbol,
+    pub value: MetaValue,
+    pub span: Span,
+}
+
+impl PartialEq for MetaEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key && self.value == other.value
+    }
+}
+
+/// The value of a `meta` key: a string, or a number such as a gauge of
+/// `22`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaValue {
+    Str(String),
+    Number(i64),
+}
+
+impl MetaValue {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            MetaValue::Str(s) => json!(s),
+            MetaValue::Number(n) => json!(n),
+        }
+    }
+}
+
+/// As a reader sees it, without the quotes of a string.
+impl fmt::Display for MetaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaValue::Str(s) => f.write_str(s),
+            MetaValue::Number(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+/// Dropping a `repeat`, `mirror` or `alternate` takes the statements out of
+/// its bodies, and theirs, onto one list, so the tree is freed a level at a
+/// time however deeply it nests instead of by a call for every level.
+impl Drop for Stmt {
+    fn drop(&mut self) {
+        let bodies = self.kind.bodies_mut();
+        if !bodies.iter().flatten().any(|s| !s.kind.bodies().is_empty()) {
+            return;
+        }
+        let mut pending: Vec<Stmt> = bodies.iter_mut().flat_map(mem::take).collect();
+        while let Some(mut stmt) = pending.pop() {
+            for body in stmt.kind.bodies_mut() {
+                pending.append(body);
+            }
+        }
+    }
+}
+
+/// Like expressions, statements compare by structure and ignore spans.
+impl PartialEq for Stmt {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl StmtKind {
+    /// The `"kind"` that [`Stmt::to_json`] gives statements like this one.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StmtKind::CastOn(..) => "cast_on",
+            StmtKind::CastOnStitches(_) => "cast_on_stitches",
+            StmtKind::CastOnNeedle(..) => "cast_on_needle",
+            StmtKind::Knit(..) => "knit",
+            StmtKind::Purl(_) => "purl",
+            StmtKind::Work(..) => "work",
+            StmtKind::Repeat(..) => "repeat",
+            StmtKind::BindOff => "bind_off",
+            StmtKind::BindOffStitches(_) => "bind_off_stitches",
+            StmtKind::ExpectStitches(_) => "expect_stitches",
+            StmtKind::ExpectStitchesWarning(_) => "expect_stitches_warning",
+            StmtKind::Yarn(..) => "yarn",
+            StmtKind::Color(_) => "color",
+            StmtKind::WrapTurn => "wrap_turn",
+            StmtKind::Mode(_) => "mode",
+            StmtKind::PlaceMarker(_) => "place_marker",
+            StmtKind::SlipMarker(_) => "slip_marker",
+            StmtKind::Use(..) => "use",
+            StmtKind::Sizes(_) => "sizes",
+            StmtKind::Gauge(..) => "gauge",
+            StmtKind::Distribute(..) => "distribute",
+            StmtKind::Mirror(_) => "mirror",
+            StmtKind::Alternate(_) => "alternate",
+            StmtKind::Pattern(..) => "pattern",
+            StmtKind::Cable(..) => "cable",
+            StmtKind::Meta(_) => "meta",
+            StmtKind::Note(_) => "note",
+            StmtKind::Chart(_) => "chart",
+            StmtKind::Transfer(..) => "transfer",
+            StmtKind::Hold(_) => "hold",
+            StmtKind::Resume(_) => "resume",
+        }
+    }
+
+    /// The statements of a `repeat` or `mirror` block.
+    pub fn body(&self) -> Option<&[Stmt]> {
+        match self {
+            StmtKind::Repeat(_, body) | StmtKind::Mirror(body) => Some(body),
+            _ => None,
+        }
+    }
+
+    /// Every block of statements in it: the body of a `repeat` or `mirror`,
+    /// the bodies of an `alternate`, or none.
+    pub fn bodies(&self) -> &[Vec<Stmt>] {
+        match self {
+            StmtKind::Repeat(_, body) | StmtKind::Mirror(body) => slice::from_ref(body),
+            StmtKind::Alternate(bodies) => bodies,
+            _ => &[],
+        }
+    }
+
+    fn bodies_mut(&mut self) -> &mut [Vec<Stmt>] {
+        match self {
+            StmtKind::Repeat(_, body) | StmtKind::Mirror(body) => slice::from_mut(body),
+            StmtKind::Alternate(bodies) => bodies,
+            _ => &mut [],
+        }
+    }
+}
+
+impl Stmt {
+    pub fn to_json(&self) -> serde_json::Value {
+        let span = self.span.to_json();
+        match &self.kind {
+            StmtKind::CastOn(name, value) => {
+                json!({ "kind": "cast_on", "name": name, "value": value.to_json(), "span": span })
+            }
+            StmtKind::CastOnStitches(count) => {
+                json!({ "kind": "cast_on_stitches", "count": count.to_json(), "span": span })
+            }
+            StmtKind::CastOnNeedle(name, count) => json!({
+                "kind": "cast_on_needle",
+                "name": name,
+                "count": count.to_json(),
+                "span": span,
+            }),
+            StmtKind::Knit(name, value) => {
+                json!({ "kind": "knit", "name": name, "value": value.to_json(), "span": span })
+            }
+            StmtKind::Purl(value) => {
+                json!({ "kind": "purl", "value": value.to_json(), "span": span })
+            }
+            StmtKind::Work(stitch, count) => json!({
+                "kind": "work",
+                "stitch": stitch.to_string(),
+                "count": count.to_json(),
+                "span": span,
+            }),
+            StmtKind::Repeat(count, body) => json!({
+                "kind": "repeat",
+                "count": count.to_json(),
+                "body": body.iter().map(Stmt::to_json).collect::<Vec<_>>(),
+                "span": span,
+            }),
+            StmtKind::BindOff => json!({ "kind": "bind_off", "span": span }),
+            StmtKind::BindOffStitches(count) => {
+                json!({ "kind": "bind_off_stitches", "count": count.to_json(), "span": span })
+            }
+            StmtKind::ExpectStitches(count) => {
+                json!({ "kind": "expect_stitches", "count": count.to_json(), "span": span })
+            }
+            StmtKind::ExpectStitchesWarning(count) => json!({
+                "kind": "expect_stitches_warning",
+                "count": count.to_json(),
+                "span": span,
+            }),
+            StmtKind::Yarn(name, colour) => {
+                json!({ "kind": "yarn", "name": name, "color": colour, "span": span })
+            }
+            StmtKind::Color(name) => json!({ "kind": "color", "name": name, "span": span }),
+            StmtKind::WrapTurn => json!({ "kind": "wrap_turn", "span": span }),
+            StmtKind::Mode(mode) => json!({ "kind": "mode", "mode": mode.name(), "span": span }),
+            StmtKind::PlaceMarker(name) => {
+                json!({ "kind": "place_marker", "name": name, "span": span })
+            }
+            StmtKind::SlipMarker(name) => {
+                json!({ "kind": "slip_marker", "name": name, "span": span })
+            }
+            StmtKind::Use(path, None) => json!({ "kind": "use", "path": path, "span": span }),
+            StmtKind::Use(path, Some(alias)) => {
+                json!({ "kind": "use", "path": path, "alias": alias, "span": span })
+            }
+            StmtKind::Sizes(names) => json!({ "kind": "sizes", "names": names, "span": span }),
+            StmtKind::Gauge(stitches, rows) => json!({
+                "kind": "gauge",
+                "stitches": stitches.to_json(),
+                "rows": rows.to_json(),
+                "span": span,
+            }),
+            StmtKind::Distribute(shaping, count) => json!({
+                "kind": "distribute",
+                "shaping": shaping.name(),
+                "count": count.to_json(),
+                "span": span,
+            }),
+            StmtKind::Pattern(pattern, args) => json!({
+                "kind": "pattern",
+                "pattern": pattern.name(),
+                "args": args.iter().map(Expr::to_json).collect::<Vec<_>>(),
+                "span": span,
+            }),
+            StmtKind::Mirror(body) => json!({
+                "kind": "mirror",
+                "body": body.iter().map(Stmt::to_json).collect::<Vec<_>>(),
+                "span": span,
+            }),
+            StmtKind::Alternate(bodies) => json!({
+                "kind": "alternate",
+                "bodies": bodies
+                    .iter()
+                    .map(|body| body.iter().map(Stmt::to_json).collect::<Vec<_>>())
+                    .collect::<Vec<_>>(),
+                "span": span,
+            }),
+            StmtKind::Cable(count, cross) => json!({
+                "kind": "cable",
+                "count": count.to_json(),
+                "cross": cross.name(),
+                "span": span,
+            }),
+            StmtKind::Meta(entries) => json!({
+                "kind": "meta",
+                "entries": entries
+                    .iter()
+                    .map(|entry| json!({
+                        "key": entry.key,
+                        "value": entry.value.to_json(),
+                        "span": entry.span.to_json(),
+                    }))
+                    .collect::<Vec<_>>(),
+                "span": span,
+            }),
+            StmtKind::Transfer(from, to, count) => json!({
+                "kind": "transfer",
+                "from": from,
+                "to": to,
+                "count": count.to_json(),
+                "span": span,
+            }),
+            StmtKind::Hold(name) => json!({ "kind": "hold", "needle": name, "span": span }),
+            StmtKind::Resume(name) => json!({ "kind": "resume", "needle": name, "span": span }),
+            StmtKind::Chart(None) => json!({ "kind": "chart", "span": span }),
+            StmtKind::Chart(Some(label)) => {
+                json!({ "kind": "chart", "label": label, "span": span })
+            }
+            // Text is a JSON string, and an expression an object.
+            StmtKind::Note(parts) => json!({
+                "kind": "note",
+                "parts": parts
+                    .iter()
+                    .map(|part| match part {
+                        NotePart::Text(text) => json!(text),
+                        NotePart::Expr(e) => e.to_json(),
+                    })
+                    .collect::<Vec<_>>(),
+                "span": span,
+            }),
+        }
+    }
+}
+
+impl Serialize for Stmt {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+/// Serializes a whole program as a pretty-printed JSON array of statements.
+pub fn ast_to_json(stmts: &[Stmt]) -> String {
+    let program: Vec<_> = stmts.iter().map(Stmt::to_json).collect();
+    serde_json::to_string_pretty(&program).expect("a JSON value always serializes")
+}
+
+/// Why a document passed to [`ast_from_json`] is not a program.
+#[derive(Debug)]
+pub struct AstError {
+    /// Where in the document the problem is, e.g. `$[2].body[0].count`.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for AstError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for AstError {}
+
+fn ast_error<T>(path: &str, message: String) -> Result<T, AstError> {
+    Err(AstError {
+        path: path.to_string(),
+        message,
+    })
+}
+
+/// Reads a program back from the JSON written by [`ast_to_json`]. Spans may be
+/// left out, but every other key must be present and no others are allowed.
+pub fn ast_from_json(text: &str) -> Result<Vec<Stmt>, AstError> {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(e) => return ast_error("$", format!("invalid JSON: {e}")),
+    };
+    let mut stmts = stmts_from_json(&value, "$")?;
+    resolve::number_slots(&mut stmts);
+    Ok(stmts)
+}
+
+/// One JSON object in the tree, with the keys its kind allows.
+struct Node<'a> {
+    kind: &'a str,
+    fields: &'a Map<String, Value>,
+    path: &'a str,
+}
+
+impl<'a> Node<'a> {
+    fn new(value: &'a Value, path: &'a str, what: &str) -> Result<Self, AstError> {
+        let Some(fields) = value.as_object() else {
+            return ast_error(path, format!("expected an object for {what}"));
+        };
+        let Some(kind) = fields.get("kind") else {
+            return ast_error(path, format!("{what} is missing `kind`"));
+        };
+        let Some(kind) = kind.as_str() else {
+            return ast_error(path, format!("`kind` should be a string, not {kind}"));
+        };
+        Ok(Self { kind, fields, path })
+    }
+
+    /// Fails unless the node has exactly `keys` besides `kind` and `span`.
+    fn expect_keys(&self, keys: &[&str]) -> Result<(), AstError> {
+        for key in keys {
+            if !self.fields.contains_key(*key) {
+                return ast_error(self.path, format!("{} is missing `{key}`", self.kind));
+            }
+        }
+        for key in self.fields.keys() {
+            if !matches!(key.as_str(), "kind" | "span") && !keys.contains(&key.as_str()) {
+                return ast_error(
+                    self.path,
+                    format!("unexpected key `{key}` in {}", self.kind),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn child_path(&self, key: &str) -> String {
+        format!("{}.{key}", self.path)
+    }
+
+    fn str(&self, key: &str) -> Result<&'a str, AstError> {
+        match &self.fields[key] {
+            Value::String(s) => Ok(s),
+            other => ast_error(
+                &self.child_path(key),
+                format!("expected a string, not {other}"),
+            ),
+        }
+    }
+
+    fn expr(&self, key: &str) -> Result<Expr, AstError> {
+        expr_from_json(&self.fields[key], &self.child_path(key))
+    }
+
+    fn exprs(&self, key: &str) -> Result<Vec<Expr>, AstError> {
+        let path = self.child_path(key);
+        let Some(items) = self.fields[key].as_array() else {
+            return ast_error(
+                &path,
+                format!("expected an array of expressions, not {}", self.fields[key]),
+            );
+        };
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| expr_from_json(item, &format!("{path}[{i}]")))
+            .collect()
+    }
+
+    fn span(&self) -> Result<Span, AstError> {
+        let Some(span) = self.fields.get("span") else {
+            return Ok(Span::default());
+        };
+        let path = self.child_path("span");
+        let offset = |key| span.get(key).and_then(Value::as_u64).map(|n| n as usize);
+        match (
+            span.as_object().map(Map::len),
+            offset("start"),
+            offset("end"),
+        ) {
+            (Some(2), Some(start), Some(end)) if start <= end => Ok(Span::new(start, end)),
+            _ => ast_error(
+                &path,
+                format!("expected {{\"start\": <offset>, \"end\": <offset>}}, not {span}"),
+            ),
+        }
+    }
+}
+
+fn stmts_from_json(value: &Value, path: &str) -> Result<Vec<Stmt>, AstError> {
+    let Some(items) = value.as_array() else {
+        return ast_error(
+            path,
+            format!("expected an array of statements, not {value}"),
+        );
+    };
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| stmt_from_json(item, &format!("{path}[{i}]")))
+        .collect()
+}
+
+fn stmt_from_json(value: &Value, path: &str) -> Result<Stmt, AstError> {
+    let node = Node::new(value, path, "a statement")?;
+    let kind = match node.kind {
+        "cast_on" => {
+            node.expect_keys(&["name", "value"])?;
+            StmtKind::CastOn(Symbol::intern(node.str("name")?), node.expr("value")?)
+        }
+        "cast_on_stitches" => {
+            node.expect_keys(&["count"])?;
+            StmtKind::CastOnStitches(node.expr("count")?)
+        }
+        "knit" => {
+            node.expect_keys(&["name", "value"])?;
+            StmtKind::Knit(Symbol::intern(node.str("name")?), node.expr("value")?)
+        }
+        "purl" => {
+            node.expect_keys(&["value"])?;
+            StmtKind::Purl(node.expr("value")?)
+        }
+        "work" => {
+            node.expect_keys(&["stitch", "count"])?;
+            let name = node.str("stitch")?;
+            let Some(stitch) = Stitch::from_name(name) else {
+                return ast_error(
+                    &node.child_path("stitch"),
+                    format!("unknown stitch `{name}`"),
+                );
+            };
+            StmtKind::Work(stitch, node.expr("count")?)
+        }
+        "repeat" => {
+            node.expect_keys(&["count", "body"])?;
+            let body = stmts_from_json(&node.fields["body"], &node.child_path("body"))?;
+            StmtKind::Repeat(node.expr("count")?, body)
+        }
+        "pattern" => {
+            node.expect_keys(&["pattern", "args"])?;
+            let name = node.str("pattern")?;
+            let Some(pattern) = Pattern::from_name(name) else {
+                return ast_error(
+                    &node.child_path("pattern"),
+                    format!("unknown stitch pattern `{name}`"),
+                );
+            };
+            let args = node.exprs("args")?;
+            if args.len() != pattern.params().len() {
+                return ast_error(
+                    &node.child_path("args"),
+                    format!(
+                        "`{name}` takes {}, not {}",
+                        plural(pattern.params().len(), "argument"),
+                        args.len()
+                    ),
+                );
+            }
+            StmtKind::Pattern(pattern, args)
+        }
+        "alternate" => {
+            node.expect_keys(&["bodies"])?;
+            let path = node.child_path("bodies");
+            let bodies = match node.fields["bodies"].as_array() {
+                Some(bodies) if !bodies.is_empty() => bodies,
+                _ => {
+                    let bodies = &node.fields["bodies"];
+                    return ast_error(
+                        &path,
+                        format!("expected a non-empty array of statement arrays, not {bodies}"),
+                    );
+                }
+            };
+            let bodies = bodies
+                .iter()
+                .enumerate()
+                .map(|(i, body)| stmts_from_json(body, &format!("{path}[{i}]")))
+                .collect::<Result<_, _>>()?;
+            StmtKind::Alternate(bodies)
+        }
+        "mirror" => {
+            node.expect_keys(&["body"])?;
+            StmtKind::Mirror(stmts_from_json(
+                &node.fields["body"],
+                &node.child_path("body"),
+            )?)
+        }
+        "bind_off" => {
+            node.expect_keys(&[])?;
+            StmtKind::BindOff
+        }
+        "bind_off_stitches" => {
+            node.expect_keys(&["count"])?;
+            StmtKind::BindOffStitches(node.expr("count")?)
+        }
+        "expect_stitches" => {
+            node.expect_keys(&["count"])?;
+            StmtKind::ExpectStitches(node.expr("count")?)
+        }
+        "expect_stitches_warning" => {
+            node.expect_keys(&["count"])?;
+            StmtKind::ExpectStitchesWarning(node.expr("count")?)
+        }
+        "yarn" => {
+            node.expect_keys(&["name", "color"])?;
+            let colour = node.str("color")?;
+            if rgb(colour).is_none() {
+                return ast_error(
+                    &node.child_path("color"),
+                    format!("`{colour}` is not a colour; write it as #rgb or #rrggbb"),
+                );
+            }
+            StmtKind::Yarn(Symbol::intern(node.str("name")?), colour.to_string())
+        }
+        "color" => {
+            node.expect_keys(&["name"])?;
+            StmtKind::Color(Symbol::intern(node.str("name")?))
+        }
+        "wrap_turn" => {
+            node.expect_keys(&[])?;
+            StmtKind::WrapTurn
+        }
+        "cable" => {
+            node.expect_keys(&["count", "cross"])?;
+            let name = node.str("cross")?;
+            let Some(cross) = Cross::from_name(name) else {
+                return ast_error(
+                    &node.child_path("cross"),
+                    format!("a cable crosses `front` or `back`, not `{name}`"),
+                );
+            };
+            StmtKind::Cable(node.expr("count")?, cross)
+        }
+        "mode" => {
+            node.expect_keys(&["mode"])?;
+            let name = node.str("mode")?;
+            let Some(mode) = Mode::from_name(name) else {
+                return ast_error(
+                    &node.child_path("mode"),
+                    format!("a piece is knitted `flat` or `round`, not `{name}`"),
+                );
+            };
+            StmtKind::Mode(mode)
+        }
+        "place_marker" => {
+            node.expect_keys(&["name"])?;
+            StmtKind::PlaceMarker(Symbol::intern(node.str("name")?))
+        }
+        "slip_marker" => {
+            node.expect_keys(&["name"])?;
+            StmtKind::SlipMarker(Symbol::intern(node.str("name")?))
+        }
+        "use" => {
+            // The alias is left out of imports without one.
+            if node.fields.contains_key("alias") {
+                node.expect_keys(&["path", "alias"])?;
+                let alias = Symbol::intern(node.str("alias")?);
+                StmtKind::Use(node.str("path")?.to_string(), Some(alias))
+            } else {
+                node.expect_keys(&["path"])?;
+                StmtKind::Use(node.str("path")?.to_string(), None)
+            }
+        }
+        "sizes" => {
+            node.expect_keys(&["names"])?;
+            let names = node.fields["names"].as_array().and_then(|names| {
+                let names: Option<Vec<Symbol>> = names
+                    .iter()
+                    .map(|name| name.as_str().map(Symbol::intern))
+                    .collect();
+                names.filter(|names| !names.is_empty())
+            });
+            let Some(names) = names else {
+                let names = &node.fields["names"];
+                return ast_error(
+                    &node.child_path("names"),
+                    format!("expected a non-empty array of size names, not {names}"),
+                );
+            };
+            StmtKind::Sizes(names)
+        }
+        "gauge" => {
+            node.expect_keys(&["stitches", "rows"])?;
+            StmtKind::Gauge(node.expr("stitches")?, node.expr("rows")?)
+        }
+        "distribute" => {
+            node.expect_keys(&["shaping", "count"])?;
+            let name = node.str("shaping")?;
+            let Some(shaping) = Shaping::from_name(name) else {
+                return ast_error(
+                    &node.child_path("shaping"),
+                    format!("stitches are distributed as `inc` or `dec`, not `{name}`"),
+                );
+            };
+            StmtKind::Distribute(shaping, node.expr("count")?)
+        }
+        "meta" => {
+            node.expect_keys(&["entries"])?;
+            StmtKind::Meta(meta_from_json(
+                &node.fields["entries"],
+                &node.child_path("entries"),
+            )?)
+        }
+        "cast_on_needle" => {
+            node.expect_keys(&["name", "count"])?;
+            let name = Symbol::intern(node.str("name")?);
+            StmtKind::CastOnNeedle(name, node.expr("count")?)
+        }
+        "transfer" => {
+            node.expect_keys(&["from", "to", "count"])?;
+            let from = Symbol::intern(node.str("from")?);
+            let to = Symbol::intern(node.str("to")?);
+            StmtKind::Transfer(from, to, node.expr("count")?)
+        }
+        "hold" => {
+            node.expect_keys(&["needle"])?;
+            StmtKind::Hold(Symbol::intern(node.str("needle")?))
+        }
+        "resume" => {
+            node.expect_keys(&["needle"])?;
+            StmtKind::Resume(Symbol::intern(node.str("needle")?))
+        }
+        // The label is left out of charts without one.
+        "chart" if node.fields.contains_key("label") => {
+            node.expect_keys(&["label"])?;
+            let label = node.str("label")?;
+            if label.contains(['"', '\n']) {
+                return ast_error(
+                    &node.child_path("label"),
+                    format!("expected a string on one line, not {label:?}"),
+                );
+            }
+            StmtKind::Chart(Some(label.to_string()))
+        }
+        "chart" => {
+            node.expect_keys(&[])?;
+            StmtKind::Chart(None)
+        }
+        "note" => {
+            node.expect_keys(&["parts"])?;
+            StmtKind::Note(note_from_json(
+                &node.fields["parts"],
+                &node.child_path("parts"),
+            )?)
+        }
+        other => return ast_error(path, format!("unknown statement kind `{other}`")),
+    };
+    Ok(Stmt {
+        kind,
+        span: node.span()?,
+    })
+}
+
+/// The parts of a `note`, strings the parser could read back and
+/// expressions, starting with a string.
+fn note_from_json(value: &Value, path: &str) -> Result<Vec<NotePart>, AstError> {
+    let Some(items) = value.as_array() else {
+        return ast_error(
+            path,
+            format!("expected an array of strings and expressions, not {value}"),
+        );
+    };
+    let mut parts = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let path = format!("{path}[{i}]");
+        parts.push(match item {
+            Value::String(s) if !s.contains(['"', '\n']) => NotePart::Text(s.clone()),
+            Value::String(s) => {
+                return ast_error(&path, format!("expected a string on one line, not {s:?}"))
+            }
+            item => NotePart::Expr(expr_from_json(item, &path)?),
+        });
+    }
+    if !matches!(parts.first(), Some(NotePart::Text(_))) {
+        return ast_error(path, "a note must start with a string".to_string());
+    }
+    Ok(parts)
+}
+
+/// The entries of a `meta` block, each `{"key": ..., "value": ...}` with
+/// an optional span. A string value must be one the parser could read back.
+fn meta_from_json(value: &Value, path: &str) -> Result<Vec<MetaEntry>, AstError> {
+    let Some(items) = value.as_array() else {
+        return ast_error(
+            path,
+            format!("expected an array of meta entries, not {value}"),
+        );
+    };
+    let mut entries: Vec<MetaEntry> = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let path = format!("{path}[{i}]");
+        let fields = match item.as_object() {
+            Some(fields) if fields.contains_key("key") && fields.contains_key("value") => fields,
+            _ => {
+                return ast_error(
+                    &path,
+                    format!("expected {{\"key\": <name>, \"value\": <value>}}, not {item}"),
+                )
+            }
+        };
+        if let Some(key) = fields
+            .keys()
+            .find(|key| !matches!(key.as_str(), "key" | "value" | "span"))
+        {
+            return ast_error(&path, format!("unexpected key `{key}` in a meta entry"));
+        }
+        let Some(key) = fields["key"].as_str() else {
+            let key = &fields["key"];
+            return ast_error(
+                &format!("{path}.key"),
+                format!("expected a string, not {key}"),
+            );
+        };
+        if entries.iter().any(|entry| entry.key.as_str() == key) {
+            return ast_error(&path, format!("duplicate meta key `{key}`"));
+        }
+        let value = match &fields["value"] {
+            Value::String(s) if !s.contains(['"', '\n']) => MetaValue::Str(s.clone()),
+            value => match value.as_i64() {
+                Some(n) => MetaValue::Number(n),
+                None => {
+                    return ast_error(
+                        &format!("{path}.value"),
+                        format!("expected a string on one line or a 64-bit integer, not {value}"),
+                    )
+                }
+            },
+        };
+        // The span is checked the way a statement's is.
+        let node = Node {
+            kind: "meta entry",
+            fields,
+            path: &path,
+        };
+        entries.push(MetaEntry {
+            key: Symbol::intern(key),
+            value,
+            span: node.span()?,
+        });
+    }
+    Ok(entries)
+}
+
+fn expr_from_json(value: &Value, path: &str) -> Result<Expr, AstError> {
+    let node = Node::new(value, path, "an expression")?;
+    let span = node.span()?;
+    Ok(match node.kind {
+        "number" => {
+            node.expect_keys(&["value"])?;
+            match node.fields["value"].as_i64() {
+                Some(n) => Expr::number(n, span),
+                None => {
+                    let value = &node.fields["value"];
+                    return ast_error(
+                        &node.child_path("value"),
+                        format!("expected a 64-bit integer, not {value}"),
+                    );
+                }
+            }
+        }
+        "var" => {
+            node.expect_keys(&["name"])?;
+            Expr::var(Symbol::intern(node.str("name")?), span)
+        }
+        "binary" => {
+            node.expect_keys(&["op", "lhs", "rhs"])?;
+            let op = match node.str("op")? {
+                "+" => '+',
+                "-" => '-',
+                "*" => '*',
+                "/" => '/',
+                other => {
+                    return ast_error(
+                        &node.child_path("op"),
+                        format!("unknown operator `{other}`"),
+                    )
+                }
+            };
+            Expr::binary_at(node.expr("lhs")?, op, node.expr("rhs")?, span)
+        }
+        "call" => {
+            node.expect_keys(&["name", "args"])?;
+            let name = Symbol::intern(node.str("name")?);
+            Expr::call(name, node.exprs("args")?, span)
+        }
+        "stitches_to" => {
+            node.expect_keys(&["marker"])?;
+            Expr::stitches_to(Symbol::intern(node.str("marker")?), span)
+        }
+        "count" => {
+            node.expect_keys(&["needle"])?;
+            Expr::count(Symbol::intern(node.str("needle")?), span)
+        }
+        "sizes" => {
+            node.expect_keys(&["values"])?;
+            let values = node.fields["values"].as_array().and_then(|values| {
+                let values: Option<Vec<i64>> = values.iter().map(Value::as_i64).collect();
+                values.filter(|values| values.len() >= 2)
+            });
+            let Some(values) = values else {
+                let values = &node.fields["values"];
+                return ast_error(
+                    &node.child_path("values"),
+                    format!("expected an array of at least two 64-bit integers, not {values}"),
+                );
+            };
+            Expr::sizes(values, span)
+        }
+        other => return ast_error(path, format!("unknown expression kind `{other}`")),
+    })
+}
+
+/// A stitch worked into the fabric.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stitch {
+    Knit,
+    Purl,
+    /// A cable crossing `n` stitches over the next `n`, so it works `2n`
+    /// stitches at once: `c4f` is `Cable(2, Cross::Front)`.
+    Cable(u32, Cross),
+    /// Knit two together, a decrease leaning right.
+    K2tog,
+    /// Slip, slip, knit the two slipped stitches together, a decrease
+    /// leaning left.
+    Ssk,
+    /// A yarn over: the yarn wrapped round the needle to make a stitch and
+    /// an eyelet below it, working none off the needle. In lace, a
+    /// decrease beside it keeps the count.
+    YarnOver,
+    /// Make one: a stitch worked into the strand between two, an increase
+    /// that works none off the needle and leaves no eyelet.
+    M1,
+    /// A stitch bound off by `bind_off <count>;`, worked off the needle and
+    /// leaving none for the next row. There is no abbreviation for it in
+    /// source, as only `bind_off` works it.
+    BindOff,
+}
+
+/// A classic stitch pattern the language knows, worked across whole rows by
+/// a statement such as `seed(4);`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Knit on the right side and purl on the wrong, so every stitch shows
+    /// as a knit.
+    Stockinette,
+    /// Every stitch shows as a knit on one row and a purl on the next, in
+    /// ridges: knit every row flat, or knit and purl rounds alternately.
+    Garter,
+    /// Knits and purls alternate across every row, each stitch the other
+    /// of the one below it.
+    Seed,
+    /// `k` knits then `p` purls across the row, lined up from row to row.
+    Rib,
+}
+
+impl Pattern {
+    /// Every pattern, in the order `:help` lists them.
+    pub const ALL: [Pattern; 4] = [
+        Pattern::Stockinette,
+        Pattern::Garter,
+        Pattern::Seed,
+        Pattern::Rib,
+    ];
+
+    /// The pattern a statement's name stands for, such as `seed`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|pattern| pattern.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pattern::Stockinette => "stockinette",
+            Pattern::Garter => "garter",
+            Pattern::Seed => "seed",
+            Pattern::Rib => "rib",
+        }
+    }
+
+    /// The names of the arguments it takes, in order. The last is always
+    /// the number of rows.
+    pub fn params(self) -> &'static [&'static str] {
+        match self {
+            Pattern::Rib => &["k", "p", "rows"],
+            _ => &["rows"],
+        }
+    }
+
+    /// What it works, in a line.
+    pub fn description(self) -> &'static str {
+        match self {
+            Pattern::Stockinette => "knit on the right side, purl on the wrong side",
+            Pattern::Garter => "knit every row, or knit and purl rounds alternately",
+            Pattern::Seed => "k1, p1, with knits over purls and purls over knits",
+            Pattern::Rib => "k knits and p purls, knits over knits and purls over purls",
+        }
+    }
+}
+
+/// Which way a cable crosses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cross {
+    /// The first stitches are held in front of the work, so the cable leans
+    /// left.
+    Front,
+    /// The first stitches are held behind the work, so the cable leans
+    /// right.
+    Back,
+}
+
+impl Cross {
+    /// The cross a `cable` statement's last word stands for, `front` or
+    /// `back`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "front" => Some(Cross::Front),
+            "back" => Some(Cross::Back),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cross::Front => "front",
+            Cross::Back => "back",
+        }
+    }
+}
+
+/// Which way a `distribute` statement shapes the row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shaping {
+    /// Increases, each an `m1`.
+    Increase,
+    /// Decreases, each a `k2tog`.
+    Decrease,
+}
+
+impl Shaping {
+    /// The shaping a `distribute` statement's word stands for, `inc` or
+    /// `dec`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "inc" => Some(Shaping::Increase),
+            "dec" => Some(Shaping::Decrease),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Shaping::Increase => "inc",
+            Shaping::Decrease => "dec",
+        }
+    }
+
+    /// What working one is called, as in "cannot increase".
+    pub fn verb(self) -> &'static str {
+        match self {
+            Shaping::Increase => "increase",
+            Shaping::Decrease => "decrease",
+        }
+    }
+
+    /// The stitch each one is worked as.
+    pub fn stitch(self) -> Stitch {
+        match self {
+            Shaping::Increase => Stitch::M1,
+            Shaping::Decrease => Stitch::K2tog,
+        }
+    }
+
+    /// The stitches that work `width` stitches with `count` of these spread
+    /// evenly among them, in order, each with how many times it is worked
+    /// in a row. The stitches not in a decrease are knitted, in `count + 1`
+    /// stretches between the shaping, and where they do not divide evenly
+    /// the longer stretches are spaced out among the shorter, starting with
+    /// a shorter one: 10 stitches with 3 increases are
+    /// `k2, m1, k3, m1, k2, m1, k3`. Empty stretches are left out, but the
+    /// last is never empty unless a decrease ends the row. `width` must be
+    /// at least twice `count` for decreases.
+    ///
+    /// ```
+    /// use knitlang::{Shaping, Stitch};
+    /// let row: Vec<_> = Shaping::Decrease.spread(2, 11).collect();
+    /// let (k, k2tog) = (Stitch::Knit, Stitch::K2tog);
+    /// assert_eq!(row, [(k, 2), (k2tog, 1), (k, 2), (k2tog, 1), (k, 3)]);
+    /// ```
+    pub fn spread(self, count: usize, width: usize) -> impl Iterator<Item = (Stitch, usize)> {
+        let knits = match self {
+            Shaping::Increase => width,
+            Shaping::Decrease => width - 2 * count,
+        } as u128;
+        let stretches = count as u128 + 1;
+        let end = move |i: u128| (knits * i / stretches) as usize;
+        let stitch = self.stitch();
+        (0..=count).flat_map(move |i| {
+            let i = i as u128;
+            let knit = (Stitch::Knit, end(i + 1) - end(i));
+            let shaping = (i + 1 < stretches).then_some((stitch, 1));
+            iter::once(knit).filter(|&(_, n)| n > 0).chain(shaping)
+        })
+    }
+}
+
+/// How a piece is knitted, set by a `mode` statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Back and forth in rows, turning at the end of each, so rows
+    /// alternate between the right side and the wrong side.
+    #[default]
+    Flat,
+    /// Round and round on circular needles, so every round is worked on the
+    /// right side.
+    Round,
+}
+
+impl Mode {
+    /// The mode a `mode` statement's word stands for, `flat` or `round`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "flat" => Some(Mode::Flat),
+            "round" => Some(Mode::Round),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Flat => "flat",
+            Mode::Round => "round",
+        }
+    }
+}
+
+impl Stitch {
+    /// The stitch an abbreviation stands for: `k` for a knit stitch, `p` for
+    /// a purl stitch, `k2tog` and `ssk` for the decreases, `yo` for a yarn
+    /// over, `m1` to make one, and `c` with an even number of stitches and
+    /// `f` or `b` for a cable, as in `c4f`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "k" => return Some(Stitch::Knit),
+            "p" => return Some(Stitch::Purl),
+            "k2tog" => return Some(Stitch::K2tog),
+            "ssk" => return Some(Stitch::Ssk),
+            "yo" => return Some(Stitch::YarnOver),
+            "m1" => return Some(Stitch::M1),
+            _ => {}
+        }
+        let rest = name.strip_prefix('c')?;
+        let (width, cross) = match rest.strip_suffix('f') {
+            Some(width) => (width, Cross::Front),
+            None => (rest.strip_suffix('b')?, Cross::Back),
+        };
+        if width.starts_with('0') || !width.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let width: u32 = width.parse().ok()?;
+        width
+            .is_multiple_of(2)
+            .then_some(Stitch::Cable(width / 2, cross))
+    }
+
+    /// Live stitches this stitch works off the left needle.
+    pub fn consumes(self) -> usize {
+        match self {
+            Stitch::Knit | Stitch::Purl => 1,
+            Stitch::Cable(n, _) => 2 * n as usize,
+            Stitch::K2tog | Stitch::Ssk => 2,
+            Stitch::YarnOver | Stitch::M1 => 0,
+            Stitch::BindOff => 1,
+        }
+    }
+
+    /// Stitches this stitch leaves on the right needle for the next row.
+    pub fn produces(self) -> usize {
+        match self {
+            Stitch::Knit
+            | Stitch::Purl
+            | Stitch::K2tog
+            | Stitch::Ssk
+            | Stitch::YarnOver
+            | Stitch::M1 => 1,
+            Stitch::Cable(n, _) => 2 * n as usize,
+            Stitch::BindOff => 0,
+        }
+    }
+
+    /// The stitch worked in this one's place when a row is mirrored, leaning
+    /// the other way: `k2tog` and `ssk` swap, as do `c4f` and `c4b`, and
+    /// knits, purls, yarn overs and `m1`s, which lean neither way, stay as
+    /// they are. `None` for a stitch with no counterpart, or a bound-off
+    /// one.
+    pub fn mirror(self) -> Option<Self> {
+        match self {
+            Stitch::Knit => Some(Stitch::Knit),
+            Stitch::Purl => Some(Stitch::Purl),
+            Stitch::YarnOver => Some(Stitch::YarnOver),
+            Stitch::M1 => Some(Stitch::M1),
+            Stitch::K2tog => Some(Stitch::Ssk),
+            Stitch::Ssk => Some(Stitch::K2tog),
+            Stitch::Cable(n, Cross::Front) => Some(Stitch::Cable(n, Cross::Back)),
+            Stitch::Cable(n, Cross::Back) => Some(Stitch::Cable(n, Cross::Front)),
+            Stitch::BindOff => None,
+        }
+    }
+
+    /// Whether a statement working this stitch must say how many: `k` and
+    /// `p` do, while cables and decreases are worked once unless given a
+    /// count, as in `k2tog;`.
+    pub fn needs_count(self) -> bool {
+        matches!(self, Stitch::Knit | Stitch::Purl)
+    }
+}
+
+/// The stitch's abbreviation, which [`Stitch::from_name`] reads back, but
+/// for a bound-off stitch's `bo`, which is not one in source.
+impl fmt::Display for Stitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stitch::Knit => f.write_str("k"),
+            Stitch::Purl => f.write_str("p"),
+            Stitch::Cable(n, Cross::Front) => write!(f, "c{}f", 2 * u64::from(*n)),
+            Stitch::Cable(n, Cross::Back) => write!(f, "c{}b", 2 * u64::from(*n)),
+            Stitch::K2tog => f.write_str("k2tog"),
+            Stitch::Ssk => f.write_str("ssk"),
+            Stitch::YarnOver => f.write_str("yo"),
+            Stitch::M1 => f.write_str("m1"),
+            Stitch::BindOff => f.write_str("bo"),
+        }
+    }
+}
+
+/// What is left to write of an expression being displayed.
+enum Step {
+    Node(ExprId),
+    Op(char),
+    Comma,
+    Close,
+}
+
+/// Writes the expression in source order. The walk keeps its own stack of
+/// what is left to write, so even a very long chain cannot overflow.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut steps = vec![Step::Node(self.root())];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Node(id) => match &self.node(id).kind {
+                    ExprKind::Number(n) => write!(f, "{n}")?,
+                    ExprKind::Var(name) => f.write_str(name.as_str())?,
+                    ExprKind::Binary(lhs, op, rhs) => {
+                        steps.extend([Step::Node(*rhs), Step::Op(*op), Step::Node(*lhs)])
+                    }
+                    ExprKind::StitchesTo(marker) => write!(f, "stitches_to({marker})")?,
+                    ExprKind::Count(needle) => write!(f, "count({needle})")?,
+                    ExprKind::Sizes(values) => {
+                        let rest: Vec<String> = values[1..].iter().map(i64::to_string).collect();
+                        write!(f, "{} ({})", values[0], rest.join(", "))?
+                    }
+                    ExprKind::Call(name, args) => {
+                        write!(f, "{name}(")?;
+                        steps.push(Step::Close);
+                        for (i, &arg) in args.iter().enumerate().rev() {
+                            steps.push(Step::Node(arg));
+                            if i > 0 {
+                                steps.push(Step::Comma);
+                            }
+                        }
+                    }
+                },
+                Step::Op(op) => write!(f, " {op} ")?,
+                Step::Comma => f.write_str(", ")?,
+                Step::Close => f.write_str(")")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+const INDENT: &str = "    ";
+
+/// Pretty-prints `stmts` in the canonical style used by `--fmt`: one
+/// statement per line and block bodies indented by four spaces. Comments are
+/// not part of the tree, so they are lost; see [`format_with_comments`].
+pub fn format_program(stmts: &[Stmt]) -> String {
+    let mut printer = Printer::new("", Vec::new());
+    printer.stmts(stmts, 0);
+    printer.out
+}
+
+/// Like [`format_program`], but keeps the comments of `src`, the source
+/// `stmts` was parsed from. A comment on its own line stays on its own line
+/// before the statement that follows it, and one at the end of a line stays at
+/// the end of that statement's line. A comment inside a statement moves to
+/// the line before it. Blank lines between statements, which split a pattern
+/// into sections, stay as one blank line however many there were:
+///
+/// ```
+/// let src = "cast_on 4;\n\n\n// ribbing\nk 2; p 2;\n\nbind_off;\n";
+/// let stmts = knitlang::parse_src(src).unwrap();
+/// assert_eq!(
+///     knitlang::ast::format_with_comments(&stmts, src),
+///     "cast_on 4;\n\n// ribbing\nk 2;\np 2;\n\nbind_off;\n"
+/// );
+/// ```
+pub fn format_with_comments(stmts: &[Stmt], src: &str) -> String {
+    let comments = match lex_with_trivia(src) {
+        Ok((tokens, trailing)) => tokens
+            .into_iter()
+            .flat_map(|t| t.leading)
+            .chain(trailing)
+            .filter(|t| t.kind == TriviaKind::Comment)
+            .map(|t| t.span)
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let mut printer = Printer::new(src, comments);
+    printer.stmts(stmts, 0);
+    printer.comments_before(usize::MAX, 0);
+    printer.out
+}
+
+/// Writes statements out, interleaving the comments of the source they came
+/// from in order.
+struct Printer<'a> {
+    src: &'a str,
+    comments: Vec<Span>,
+    /// Index of the first comment not yet written.
+    next: usize,
+    /// Whether a blank line here is left out: at the start of the output or
+    /// of a block, and right after another.
+    block_start: bool,
+    out: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(src: &'a str, comments: Vec<Span>) -> Self {
+        Self {
+            src,
+            comments,
+            next: 0,
+            block_start: true,
+            out: String::new(),
+        }
+    }
+
+    fn comment(&self, span: Span) -> &'a str {
+        self.src[span.start..span.end].trim_end()
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        self.out.push_str(&INDENT.repeat(depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+        self.block_start = false;
+    }
+
+    /// Writes the line that opens a block.
+    fn open(&mut self, depth: usize, text: &str) {
+        self.line(depth, text);
+        self.block_start = true;
+    }
+
+    /// Writes a blank line if the source has one or more just before
+    /// `offset`, unless nothing has been written in the block yet.
+    fn blank_lines_before(&mut self, offset: usize) {
+        let Some(before) = self.src.get(..offset) else {
+            return;
+        };
+        let gap = &before[before.trim_end().len()..];
+        if gap.matches('\n').count() > 1 && !self.block_start {
+            self.out.push('\n');
+            self.block_start = true;
+        }
+    }
+
+    /// Writes every comment starting before `offset` on a line of its own.
+    fn comments_before(&mut self, offset: usize, depth: usize) {
+        while let Some(&span) = self.comments.get(self.next) {
+            if span.start >= offset {
+                break;
+            }
+            self.blank_lines_before(span.start);
+            self.line(depth, self.comment(span));
+            self.next += 1;
+        }
+    }
+
+    /// Appends the next comment to the line just written if, in the source,
+    /// only spaces or the opening brace of a block separate it from `offset`.
+    fn trailing_comment(&mut self, offset: usize) {
+        let Some(&span) = self.comments.get(self.next) else {
+            return;
+        };
+        let gap = &self.src[offset.min(span.start)..span.start];
+        if span.start >= offset && gap.chars().all(|c| c == '{' || c == ' ' || c == '\t') {
+            self.out.pop();
+            self.out.push(' ');
+            self.out.push_str(self.comment(span));
+            self.out.push('\n');
+            self.next += 1;
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt], depth: usize) {
+        for stmt in stmts {
+            self.comments_before(stmt.span.start, depth);
+            self.blank_lines_before(stmt.span.start);
+            let line = match &stmt.kind {
+                StmtKind::CastOn(name, e) => format!("cast_on {name} = {e};"),
+                StmtKind::CastOnStitches(e) => format!("cast_on {e};"),
+                StmtKind::Knit(name, e) => format!("knit {name} = {e};"),
+                StmtKind::Purl(e) => format!("purl {e};"),
+                StmtKind::Work(stitch, e)
+                    if !stitch.needs_count() && matches!(e.kind(), ExprKind::Number(1)) =>
+                {
+                    format!("{stitch};")
+                }
+                StmtKind::Work(stitch, e) => format!("{stitch} {e};"),
+                StmtKind::Cable(n, cross) => format!("cable {n} {};", cross.name()),
+                StmtKind::Repeat(count, body) => {
+                    self.comments_before(count.span().end, depth);
+                    self.open(depth, &format!("repeat {count} {{"));
+                    self.trailing_comment(count.span().end);
+                    self.stmts(body, depth + 1);
+                    // Everything left before the closing brace belongs to the body.
+                    // Trees built by hand may have empty spans.
+                    self.comments_before(stmt.span.end.saturating_sub(1), depth + 1);
+                    self.line(depth, "}");
+                    self.trailing_comment(stmt.span.end);
+                    continue;
+                }
+                StmtKind::Mirror(body) => {
+                    self.comments_before(stmt.span.start, depth);
+                    self.open(depth, "mirror {");
+                    self.trailing_comment(stmt.span.start + "mirror".len());
+                    self.stmts(body, depth + 1);
+                    self.comments_before(stmt.span.end.saturating_sub(1), depth + 1);
+                    self.line(depth, "}");
+                    self.trailing_comment(stmt.span.end);
+                    continue;
+                }
+                StmtKind::Alternate(bodies) => {
+                    // Each body after the first opens on the line closing
+                    // the one before; comments at the end of a body are
+                    // kept with the next.
+                    self.comments_before(stmt.span.start, depth);
+                    self.open(depth, "alternate {");
+                    self.trailing_comment(stmt.span.start + "alternate".len());
+                    for (i, body) in bodies.iter().enumerate() {
+                        if i > 0 {
+                            self.open(depth, "} {");
+                        }
+                        self.stmts(body, depth + 1);
+                    }
+                    self.comments_before(stmt.span.end.saturating_sub(1), depth + 1);
+                    self.line(depth, "}");
+                    self.trailing_comment(stmt.span.end);
+                    continue;
+                }
+                StmtKind::Meta(entries) => {
+                    self.comments_before(stmt.span.start, depth);
+                    self.open(depth, "meta {");
+                    self.trailing_comment(stmt.span.start + "meta".len());
+                    for entry in entries {
+                        let value = match &entry.value {
+                            MetaValue::Str(s) => format!("\"{s}\""),
+                            MetaValue::Number(n) => n.to_string(),
+                        };
+                        self.comments_before(entry.span.end, depth + 1);
+                        self.line(depth + 1, &format!("{} = {value};", entry.key));
+                        self.trailing_comment(entry.span.end);
+                    }
+                    self.comments_before(stmt.span.end.saturating_sub(1), depth + 1);
+                    self.line(depth, "}");
+                    self.trailing_comment(stmt.span.end);
+                    continue;
+                }
+                StmtKind::Note(parts) => {
+                    let parts: Vec<String> = parts
+                        .iter()
+                        .map(|part| match part {
+                            NotePart::Text(text) => format!("\"{text}\""),
+                            NotePart::Expr(e) => e.to_string(),
+                        })
+                        .collect();
+                    format!("note {};", parts.join(", "))
+                }
+                StmtKind::CastOnNeedle(name, e) => format!("cast_on {name} = needle({e});"),
+                StmtKind::Transfer(from, to, e) => format!("transfer {from} {to} {e};"),
+                StmtKind::Hold(name) => format!("hold {name};"),
+                StmtKind::Resume(name) => format!("resume {name};"),
+                StmtKind::Chart(None) => "chart;".to_string(),
+                StmtKind::Chart(Some(label)) => format!("chart \"{label}\";"),
+                StmtKind::BindOff => "bind_off;".to_string(),
+                StmtKind::BindOffStitches(e) => format!("bind_off {e};"),
+                StmtKind::ExpectStitches(e) => format!("expect_stitches {e};"),
+                StmtKind::ExpectStitchesWarning(e) => format!("expect_stitches? {e};"),
+                StmtKind::Yarn(name, colour) => format!("yarn {name} = \"{colour}\";"),
+                StmtKind::Color(name) => format!("color {name};"),
+                StmtKind::WrapTurn => "wrap_turn;".to_string(),
+                StmtKind::Mode(mode) => format!("mode {};", mode.name()),
+                StmtKind::PlaceMarker(name) => format!("place_marker {name};"),
+                StmtKind::SlipMarker(name) => format!("slip_marker {name};"),
+                StmtKind::Use(path, None) => format!("use \"{path}\";"),
+                StmtKind::Sizes(names) => {
+                    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+                    format!("sizes {};", names.join(", "))
+                }
+                StmtKind::Gauge(stitches, rows) => format!("gauge {stitches}, {rows};"),
+                StmtKind::Distribute(shaping, count) => {
+                    format!("distribute {} {count};", shaping.name())
+                }
+                StmtKind::Use(path, Some(alias)) => format!("use \"{path}\" as {alias};"),
+                StmtKind::Pattern(pattern, args) => {
+                    let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                    format!("{}({});", pattern.name(), args.join(", "))
+                }
+            };
+            self.comments_before(stmt.span.end, depth);
+            self.line(depth, &line);
+            self.trailing_comment(stmt.span.end);
+        }
+    }
+}
+
+/// Total number of statements in `stmts`, including nested ones.
+pub fn count_stmts(stmts: &[Stmt]) -> usize {
+    stmts
+        .iter()
+        .map(|s| {
+            1 + s
+                .kind
+                .bodies()
+                .iter()
+                .map(|body| count_stmts(body))
+                .sum::<usize>()
+        })
+        .sum()
+}
@@ -0,0 +1,123 @@
+//! Timing repeated runs, for `knitlang run --bench` and the benchmarks in
+//! `benches/`. [`measure`] runs a piece of work a few times untimed to warm
+//! up, then times each of the runs after, and the [`Timings`] it returns
+//! give the fastest, median and mean of them and the rate some count of
+//! work was done at.
+
+use std::time::{Duration, Instant};
+
+/// Wall times of the measured runs of some work, without the warmup runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timings {
+    /// How many runs were left out to warm up.
+    pub warmup: u32,
+    /// One entry per measured run, fastest first.
+    times: Vec<Duration>,
+}
+
+impl Timings {
+    /// Timings of runs that took `times`, in any order, after `warmup` runs
+    /// left out.
+    ///
+    /// # Panics
+    ///
+    /// If `times` is empty, as there is nothing to take statistics of.
+    pub fn new(warmup: u32, mut times: Vec<Duration>) -> Self {
+        assert!(!times.is_empty(), "timings of no runs");
+        times.sort();
+        Timings { warmup, times }
+    }
+
+    /// The time of every measured run, fastest first.
+    pub fn times(&self) -> &[Duration] {
+        &self.times
+    }
+
+    /// How many runs were measured.
+    pub fn runs(&self) -> usize {
+        self.times.len()
+    }
+
+    /// The fastest run.
+    pub fn min(&self) -> Duration {
+        self.times[0]
+    }
+
+    /// The middle run, or the mean of the two middle ones when there is an
+    /// even number of them:
+    ///
+    /// ```
+    /// use knitlang::bench::Timings;
+    /// use std::time::Duration;
+    ///
+    /// let ms = Duration::from_millis;
+    /// let timings = Timings::new(0, vec![ms(4), ms(1), ms(9), ms(2)]);
+    /// assert_eq!(timings.median(), ms(3));
+    /// assert_eq!(timings.mean(), ms(4));
+    /// ```
+    pub fn median(&self) -> Duration {
+        let mid = self.times.len() / 2;
+        if self.times.len().is_multiple_of(2) {
+            (self.times[mid - 1] + self.times[mid]) / 2
+        } else {
+            self.times[mid]
+        }
+    }
+
+    /// The mean of every run.
+    pub fn mean(&self) -> Duration {
+        self.total() / self.times.len() as u32
+    }
+
+    /// The time every run took together.
+    pub fn total(&self) -> Duration {
+        self.times.iter().sum()
+    }
+
+    /// How many of something each run did `count` of it was done a second,
+    /// over the median run.
+    pub fn per_sec(&self, count: f64) -> f64 {
+        count / self.median().as_secs_f64().max(1e-9)
+    }
+}
+
+/// Runs `work` `warmup + iterations` times, timing all but the first
+/// `warmup` of them. Each run gets what `setup` makes, untimed, such as a
+/// fresh interpreter. The first error ends the measuring and is returned.
+///
+/// # Panics
+///
+/// If `iterations` is 0.
+///
+/// ```
+/// let stmts = knitlang::parse_src("repeat 100 { purl 1; }").unwrap();
+/// let timings = knitlang::bench::measure(
+///     1,
+///     5,
+///     || knitlang::Interpreter::with_output(Box::new(std::io::sink())),
+///     |mut interp| interp.run(&stmts),
+/// )
+/// .unwrap();
+/// assert_eq!((timings.runs(), timings.warmup), (5, 1));
+/// assert!(timings.min() <= timings.median());
+/// ```
+pub fn measure<S, T, E>(
+    warmup: u32,
+    iterations: u32,
+    mut setup: impl FnMut() -> S,
+    mut work: impl FnMut(S) -> Result<T, E>,
+) -> Result<Timings, E> {
+    assert!(iterations > 0, "measuring no runs");
+    let mut times = Vec::with_capacity(iterations as usize);
+    for i in 0..warmup + iterations {
+        let input = setup();
+        let started = Instant::now();
+        let result = work(input);
+        let elapsed = started.elapsed();
+        result?;
+        if i >= warmup {
+            times.push(elapsed);
+        }
+    }
+    Ok(Timings::new(warmup, times))
+}
@@ -0,0 +1,1704 @@
+//! Charts of a [`Fabric`]: a grid with one cell per stitch, read the way
+//! knitting charts are. Row 1 is at the bottom, and each row is drawn as it
+//! looks from the right side of the work: blank for a knit stitch, a dot for
+//! a purl stitch. A row worked in the round, or on the right side of a piece
+//! knitted flat, starts on the right, so its stitch 1 is in the rightmost
+//! column and its number is on the right. A wrong-side row of a flat piece is
+//! read from the left instead, with its number on the left, and a stitch
+//! knitted on that side is a purl from the right side, and a purl a knit. A
+//! cable spans the columns of all the stitches it crosses, with a line for
+//! each group that leans the way the group on top moves, and a decrease
+//! spans the two stitches it works together, with a line leaning the way it
+//! does. A yarn over works no stitch off the needle but has a cell of its
+//! own, an `o` or a ring, so a lace row is charted a cell wider for each;
+//! the decreases balancing them are still two cells each. An `m1` has a
+//! cell of its own too, a `^` or a peak. A stitch marker is a thicker line
+//! between cells in the drawn charts. A stitch worked in a yarn from a
+//! `color` statement is filled with the yarn's colour. A short
+//! row starts as far in from its edge as the stitches an earlier one left
+//! unworked, and the stitch it wrapped before turning is shaded. A stitch
+//! bound off by `bind_off <count>;` is shaded too, and the rows after
+//! stitches bound off at the right edge start as many columns to the left,
+//! over the stitches they work. Only completed rows are charted.
+//!
+//! An [`Orientation`] can turn any of this off: draw row 1 at the top, and
+//! chart the wrong-side rows of a flat piece as they were worked, from the
+//! right like every other row, or with their stitches as they were knitted.
+//! A [`ChartStyle`] charts each kind of stitch with other symbols, and every
+//! chart ends with a legend of those it uses.
+//!
+//! [`text`] draws the chart for a terminal, [`text_rows`] only some of its
+//! rows, as a `chart` statement does, [`svg`] as a standalone SVG
+//! document for printing, and `png`, with the `png` feature, as an image
+//! for sharing; the last two are laid out by a [`Style`].
+
+use crate::ast::{Cross, Stitch};
+use crate::interp::{Fabric, Side};
+use crate::suggest::did_you_mean;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// How [`svg`] draws a chart. Sizes are in SVG user units, which are pixels
+/// unless the document is scaled, and colours are anything SVG accepts, such
+/// as `#000000` or `black`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Style {
+    /// The width and height of the cell of one stitch.
+    pub cell_size: u32,
+    /// The size of the row and stitch numbers.
+    pub font_size: u32,
+    pub background: String,
+    /// The colour of the lines between cells.
+    pub grid: String,
+    /// The colour of stitch symbols and numbers.
+    pub ink: String,
+    pub orientation: Orientation,
+    /// What each kind of cell is drawn with.
+    pub symbols: ChartStyle,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            cell_size: 20,
+            font_size: 10,
+            background: "#ffffff".into(),
+            grid: "#999999".into(),
+            ink: "#000000".into(),
+            orientation: Orientation::default(),
+            symbols: ChartStyle::default(),
+        }
+    }
+}
+
+/// Which way up a chart is drawn, and how the wrong-side rows of a piece
+/// knitted flat read. The default is the way knitting charts are read;
+/// rows worked in the round have no wrong side, so only `origin` changes
+/// their chart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    /// The edge row 1 is at.
+    pub origin: Origin,
+    /// Whether a wrong-side row is charted from the left, so each of its
+    /// stitches sits over the stitch it was worked into, rather than from
+    /// the right in the order it was worked.
+    pub reverse_wrong_side: bool,
+    /// Whether a wrong-side row shows its stitches as they look from the
+    /// right side, a knit as a purl and a purl as a knit, rather than as
+    /// they were worked.
+    pub invert_wrong_side: bool,
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self {
+            origin: Origin::Bottom,
+            reverse_wrong_side: true,
+            invert_wrong_side: true,
+        }
+    }
+}
+
+/// Where row 1 of a chart is, with the later rows going away from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Bottom,
+    Top,
+}
+
+/// What a cell of a chart shows: a kind of stitch, a stitch bound off, or
+/// the stitch a short row wrapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CellKind {
+    Knit,
+    Purl,
+    K2tog,
+    Ssk,
+    YarnOver,
+    M1,
+    /// A cable crossing in front, of any width.
+    CableFront,
+    CableBack,
+    BoundOff,
+    Wrap,
+}
+
+impl CellKind {
+    /// Every kind, in the order a legend lists them.
+    pub const ALL: [CellKind; 10] = [
+        CellKind::Knit,
+        CellKind::Purl,
+        CellKind::K2tog,
+        CellKind::Ssk,
+        CellKind::YarnOver,
+        CellKind::M1,
+        CellKind::CableFront,
+        CellKind::CableBack,
+        CellKind::BoundOff,
+        CellKind::Wrap,
+    ];
+
+    /// The kind that charts `stitch`.
+    pub fn of(stitch: Stitch) -> Self {
+        match stitch {
+            Stitch::Knit => CellKind::Knit,
+            Stitch::Purl => CellKind::Purl,
+            Stitch::K2tog => CellKind::K2tog,
+            Stitch::Ssk => CellKind::Ssk,
+            Stitch::YarnOver => CellKind::YarnOver,
+            Stitch::M1 => CellKind::M1,
+            Stitch::Cable(_, Cross::Front) => CellKind::CableFront,
+            Stitch::Cable(_, Cross::Back) => CellKind::CableBack,
+            Stitch::BindOff => CellKind::BoundOff,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// The name a symbols file gives it, the stitch's own name where it has
+    /// one.
+    pub fn name(self) -> &'static str {
+        match self {
+            CellKind::Knit => "k",
+            CellKind::Purl => "p",
+            CellKind::K2tog => "k2tog",
+            CellKind::Ssk => "ssk",
+            CellKind::YarnOver => "yo",
+            CellKind::M1 => "m1",
+            CellKind::CableFront => "cable_front",
+            CellKind::CableBack => "cable_back",
+            CellKind::BoundOff => "bo",
+            CellKind::Wrap => "wrap",
+        }
+    }
+
+    /// What a legend says it is.
+    pub fn description(self) -> &'static str {
+        match self {
+            CellKind::Knit => "knit",
+            CellKind::Purl => "purl",
+            CellKind::K2tog => "knit two together",
+            CellKind::Ssk => "slip, slip, knit",
+            CellKind::YarnOver => "yarn over",
+            CellKind::M1 => "make one",
+            CellKind::CableFront => "cable, crossing in front",
+            CellKind::CableBack => "cable, crossing behind",
+            CellKind::BoundOff => "bind off",
+            CellKind::Wrap => "wrapped stitch",
+        }
+    }
+}
+
+/// A shape [`svg`] and `png` draw in a cell. A line goes corner to corner
+/// of the cell, across all the columns of a wide one; the rest are drawn in
+/// its middle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyph {
+    /// Nothing: an empty cell.
+    Blank,
+    Dot,
+    Ring,
+    /// An upturned V.
+    Peak,
+    /// A line going up to the right.
+    Rising,
+    /// A line going down to the right.
+    Falling,
+    Vertical,
+    Horizontal,
+    /// Two lines crossing, the falling one over the rising one, as a cable
+    /// crossing in front looks: the group on top moves left as it goes up.
+    CrossFront,
+    /// Two lines crossing, the rising one over the falling one.
+    CrossBack,
+    /// The cell filled with the grid colour.
+    Shade,
+    /// A character, such as `M`. `png` has no fonts, so it leaves these
+    /// cells empty.
+    Char(char),
+}
+
+impl Glyph {
+    /// Every glyph with a name, all but [`Glyph::Char`].
+    pub const NAMED: [Glyph; 11] = [
+        Glyph::Blank,
+        Glyph::Dot,
+        Glyph::Ring,
+        Glyph::Peak,
+        Glyph::Rising,
+        Glyph::Falling,
+        Glyph::Vertical,
+        Glyph::Horizontal,
+        Glyph::CrossFront,
+        Glyph::CrossBack,
+        Glyph::Shade,
+    ];
+
+    /// The glyph named `name` in a symbols file, or a character on its own.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(Glyph::Char(c)),
+            _ => Self::NAMED.into_iter().find(|glyph| glyph.name() == name),
+        }
+    }
+
+    /// Its name in a symbols file. A [`Glyph::Char`] is given there as the
+    /// character on its own, so this is only `char` for it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Glyph::Blank => "blank",
+            Glyph::Dot => "dot",
+            Glyph::Ring => "ring",
+            Glyph::Peak => "peak",
+            Glyph::Rising => "rising",
+            Glyph::Falling => "falling",
+            Glyph::Vertical => "vertical",
+            Glyph::Horizontal => "horizontal",
+            Glyph::CrossFront => "cross_front",
+            Glyph::CrossBack => "cross_back",
+            Glyph::Shade => "shade",
+            Glyph::Char(_) => "char",
+        }
+    }
+
+    /// Whether it is drawn with lines, not filled.
+    fn stroked(self) -> bool {
+        !matches!(
+            self,
+            Glyph::Blank | Glyph::Dot | Glyph::Shade | Glyph::Char(_)
+        )
+    }
+}
+
+/// How one kind of cell is charted: the character a [`text`] chart gives
+/// each of its columns, and what [`svg`] draws in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChartSymbol {
+    pub text: char,
+    pub glyph: Glyph,
+}
+
+impl ChartSymbol {
+    const fn new(text: char, glyph: Glyph) -> Self {
+        Self { text, glyph }
+    }
+}
+
+/// The symbol a chart gives each kind of cell, as a publisher's charts
+/// would. The default is [`ChartStyle::unicode`], and [`ChartStyle::ascii`]
+/// the same chart for terminals that cannot show it. A knit or purl stitch
+/// worked in a yarn from a `color` statement is the yarn's letter in a text
+/// chart whatever the style says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChartStyle {
+    pub knit: ChartSymbol,
+    pub purl: ChartSymbol,
+    pub k2tog: ChartSymbol,
+    pub ssk: ChartSymbol,
+    pub yarn_over: ChartSymbol,
+    pub m1: ChartSymbol,
+    pub cable_front: ChartSymbol,
+    pub cable_back: ChartSymbol,
+    pub bound_off: ChartSymbol,
+    pub wrap: ChartSymbol,
+}
+
+impl Default for ChartStyle {
+    fn default() -> Self {
+        Self::unicode()
+    }
+}
+
+/// Why a symbols file given to [`ChartStyle::with_overrides`] could not be
+/// used.
+#[derive(Debug)]
+pub struct SymbolsError {
+    /// Where in the file the problem is, e.g. `k2tog.glyph`.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for SymbolsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for SymbolsError {}
+
+fn symbols_error<T>(path: &str, message: String) -> Result<T, SymbolsError> {
+    Err(SymbolsError {
+        path: path.to_string(),
+        message,
+    })
+}
+
+impl ChartStyle {
+    /// The names [`ChartStyle::preset`] knows, in the order of the presets
+    /// below.
+    pub const PRESETS: [&'static str; 4] = ["unicode", "ascii", "craft-yarn-council", "japanese"];
+
+    /// The preset named `name`.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "unicode" => Some(Self::unicode()),
+            "ascii" => Some(Self::ascii()),
+            "craft-yarn-council" => Some(Self::craft_yarn_council()),
+            "japanese" => Some(Self::japanese()),
+            _ => None,
+        }
+    }
+
+    /// Box drawing and knitting symbols for text charts, one column wide in
+    /// any monospaced font: `│` for a knit stitch, `•` for a purl, `╱` and
+    /// `╲` for the decreases leaning right and left, `○` for a yarn over,
+    /// `∧` for an `m1`, `«` and `»` for cables crossing in front and
+    /// behind, `─` for a stitch bound off and `W` for a wrapped stitch. The
+    /// drawn charts are those of [`ChartStyle::ascii`].
+    pub const fn unicode() -> Self {
+        Self {
+            knit: ChartSymbol::new('│', Glyph::Blank),
+            purl: ChartSymbol::new('•', Glyph::Dot),
+            k2tog: ChartSymbol::new('╱', Glyph::Rising),
+            ssk: ChartSymbol::new('╲', Glyph::Falling),
+            yarn_over: ChartSymbol::new('○', Glyph::Ring),
+            m1: ChartSymbol::new('∧', Glyph::Peak),
+            cable_front: ChartSymbol::new('«', Glyph::CrossFront),
+            cable_back: ChartSymbol::new('»', Glyph::CrossBack),
+            bound_off: ChartSymbol::new('─', Glyph::Shade),
+            wrap: ChartSymbol::new('W', Glyph::Shade),
+        }
+    }
+
+    /// Plain ASCII for text charts, which any terminal shows: `|` for a
+    /// knit stitch, `-` for a purl, `>` and `<` for the decreases leaning
+    /// right and left, `o` for a yarn over, `^` for an `m1`, `\` and `/`
+    /// for cables crossing in front and behind, `#` for a stitch bound off
+    /// and `W` for a wrapped stitch. The drawn charts use the Craft Yarn
+    /// Council's shapes, but a peak for an `m1`.
+    pub const fn ascii() -> Self {
+        Self {
+            knit: ChartSymbol::new('|', Glyph::Blank),
+            purl: ChartSymbol::new('-', Glyph::Dot),
+            k2tog: ChartSymbol::new('>', Glyph::Rising),
+            ssk: ChartSymbol::new('<', Glyph::Falling),
+            yarn_over: ChartSymbol::new('o', Glyph::Ring),
+            m1: ChartSymbol::new('^', Glyph::Peak),
+            cable_front: ChartSymbol::new('\\', Glyph::CrossFront),
+            cable_back: ChartSymbol::new('/', Glyph::CrossBack),
+            bound_off: ChartSymbol::new('#', Glyph::Shade),
+            wrap: ChartSymbol::new('W', Glyph::Shade),
+        }
+    }
+
+    /// The Craft Yarn Council's standard symbols, used by most English
+    /// patterns: a blank square for a knit stitch, a dot for a purl, a
+    /// slash leaning the way a decrease does, a ring for a yarn over, an `M`
+    /// for an `m1` and a grey square for a stitch bound off.
+    pub const fn craft_yarn_council() -> Self {
+        Self {
+            knit: ChartSymbol::new('□', Glyph::Blank),
+            purl: ChartSymbol::new('•', Glyph::Dot),
+            k2tog: ChartSymbol::new('╱', Glyph::Rising),
+            ssk: ChartSymbol::new('╲', Glyph::Falling),
+            yarn_over: ChartSymbol::new('○', Glyph::Ring),
+            m1: ChartSymbol::new('M', Glyph::Char('M')),
+            cable_front: ChartSymbol::new('«', Glyph::CrossFront),
+            cable_back: ChartSymbol::new('»', Glyph::CrossBack),
+            bound_off: ChartSymbol::new('▒', Glyph::Shade),
+            wrap: ChartSymbol::new('W', Glyph::Shade),
+        }
+    }
+
+    /// The Japanese standard symbols: a vertical line for a knit stitch, a
+    /// horizontal one for a purl, 人 and 入 for the decreases leaning right
+    /// and left, and a ring for a yarn over. Text charts use the narrow
+    /// lines of box drawing for the decreases, so the columns stay in line.
+    pub const fn japanese() -> Self {
+        Self {
+            knit: ChartSymbol::new('│', Glyph::Vertical),
+            purl: ChartSymbol::new('─', Glyph::Horizontal),
+            k2tog: ChartSymbol::new('╱', Glyph::Char('人')),
+            ssk: ChartSymbol::new('╲', Glyph::Char('入')),
+            yarn_over: ChartSymbol::new('○', Glyph::Ring),
+            m1: ChartSymbol::new('Y', Glyph::Char('Y')),
+            cable_front: ChartSymbol::new('«', Glyph::CrossFront),
+            cable_back: ChartSymbol::new('»', Glyph::CrossBack),
+            bound_off: ChartSymbol::new('▒', Glyph::Shade),
+            wrap: ChartSymbol::new('W', Glyph::Shade),
+        }
+    }
+
+    /// The symbol of cells of `kind`.
+    pub fn symbol(&self, kind: CellKind) -> ChartSymbol {
+        match kind {
+            CellKind::Knit => self.knit,
+            CellKind::Purl => self.purl,
+            CellKind::K2tog => self.k2tog,
+            CellKind::Ssk => self.ssk,
+            CellKind::YarnOver => self.yarn_over,
+            CellKind::M1 => self.m1,
+            CellKind::CableFront => self.cable_front,
+            CellKind::CableBack => self.cable_back,
+            CellKind::BoundOff => self.bound_off,
+            CellKind::Wrap => self.wrap,
+        }
+    }
+
+    fn symbol_mut(&mut self, kind: CellKind) -> &mut ChartSymbol {
+        match kind {
+            CellKind::Knit => &mut self.knit,
+            CellKind::Purl => &mut self.purl,
+            CellKind::K2tog => &mut self.k2tog,
+            CellKind::Ssk => &mut self.ssk,
+            CellKind::YarnOver => &mut self.yarn_over,
+            CellKind::M1 => &mut self.m1,
+            CellKind::CableFront => &mut self.cable_front,
+            CellKind::CableBack => &mut self.cable_back,
+            CellKind::BoundOff => &mut self.bound_off,
+            CellKind::Wrap => &mut self.wrap,
+        }
+    }
+
+    /// This style with the symbols `overrides` gives in place of its own,
+    /// read from a symbols file in JSON, or TOML turned into JSON. It is an
+    /// object keyed by [`CellKind::name`]; each value is the one character
+    /// of a text chart, or an object with `text`, `glyph` or both, where
+    /// `glyph` is a [`Glyph::name`] or a character on its own. Anything
+    /// else, such as a kind of stitch that is not charted, is an error:
+    ///
+    /// ```
+    /// use knitlang::chart::{ChartStyle, Glyph};
+    ///
+    /// let file = serde_json::json!({ "p": { "text": "x", "glyph": "ring" }, "k": "." });
+    /// let style = ChartStyle::ascii().with_overrides(&file).unwrap();
+    /// assert_eq!((style.purl.text, style.purl.glyph), ('x', Glyph::Ring));
+    /// assert_eq!((style.knit.text, style.knit.glyph), ('.', Glyph::Blank));
+    ///
+    /// let file = serde_json::json!({ "k2tgo": "/" });
+    /// let error = ChartStyle::ascii().with_overrides(&file).unwrap_err();
+    /// assert_eq!(
+    ///     error.to_string(),
+    ///     "k2tgo: no stitch is charted as `k2tgo`; did you mean `k2tog`?"
+    /// );
+    /// ```
+    pub fn with_overrides(mut self, overrides: &serde_json::Value) -> Result<Self, SymbolsError> {
+        let Some(entries) = overrides.as_object() else {
+            return symbols_error("$", "expected an object of symbols by stitch".into());
+        };
+        for (name, value) in entries {
+            let Some(kind) = CellKind::from_name(name) else {
+                let known = CellKind::ALL.map(CellKind::name);
+                return symbols_error(
+                    name,
+                    format!(
+                        "no stitch is charted as `{name}`{}",
+                        did_you_mean(name, known)
+                    ),
+                );
+            };
+            let symbol = self.symbol_mut(kind);
+            match value {
+                serde_json::Value::String(text) => symbol.text = one_char(name, text)?,
+                serde_json::Value::Object(fields) => {
+                    for (field, value) in fields {
+                        let path = format!("{name}.{field}");
+                        let Some(value) = value.as_str() else {
+                            return symbols_error(&path, "expected a string".into());
+                        };
+                        match field.as_str() {
+                            "text" => symbol.text = one_char(&path, value)?,
+                            "glyph" => {
+                                let Some(glyph) = Glyph::from_name(value) else {
+                                    let known = Glyph::NAMED.map(Glyph::name);
+                                    return symbols_error(
+                                        &path,
+                                        format!(
+                                            "unknown glyph `{value}`{}",
+                                            did_you_mean(value, known)
+                                        ),
+                                    );
+                                };
+                                symbol.glyph = glyph;
+                            }
+                            _ => {
+                                return symbols_error(
+                                    &path,
+                                    format!("unknown key `{field}`; expected `text` or `glyph`"),
+                                )
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    return symbols_error(
+                        name,
+                        "expected a character, or an object with `text` and `glyph`".into(),
+                    )
+                }
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// The one character `text` is, for a text chart.
+fn one_char(path: &str, text: &str) -> Result<char, SymbolsError> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_control() => Ok(c),
+        _ => symbols_error(
+            path,
+            format!("a text symbol must be one character, not {text:?}"),
+        ),
+    }
+}
+
+/// `text` made safe to put inside an attribute value, or between tags.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
+
+/// The widest of rows `rows` of `fabric`, in columns, counting the
+/// stitches short rows left unworked and the columns of those bound off
+/// before a row.
+pub(crate) fn columns(fabric: &Fabric, rows: Range<usize>) -> usize {
+    rows.map(|r| fabric.offset(r) + needle(fabric, r))
+        .max()
+        .unwrap_or(0)
+}
+
+/// How many stitches were on the needles while row `r` of `fabric` was
+/// worked, in columns.
+fn needle(fabric: &Fabric, r: usize) -> usize {
+    fabric.indent(r) + width(&fabric.rows()[r]) + fabric.unworked(r)
+}
+
+/// Whether row `r` of `fabric` was worked on the wrong side.
+fn wrong_side(fabric: &Fabric, r: usize) -> bool {
+    fabric.side(r) == Some(Side::Wrong)
+}
+
+/// Whether row `r` of `fabric` is charted starting on the left, as a
+/// wrong-side row is unless `orientation` charts them as worked.
+fn from_left(fabric: &Fabric, r: usize, orientation: Orientation) -> bool {
+    orientation.reverse_wrong_side && wrong_side(fabric, r)
+}
+
+/// The columns `columns` of row `r` of `fabric` cover, counting from the
+/// edge the row started at, as columns of the chart: moved over for the
+/// stitches bound off at the right before it.
+fn from_edge(
+    fabric: &Fabric,
+    r: usize,
+    orientation: Orientation,
+    columns: Range<usize>,
+) -> Range<usize> {
+    let offset = fabric.offset(r);
+    if from_left(fabric, r, orientation) {
+        let needle = offset + needle(fabric, r);
+        needle - columns.end..needle - columns.start
+    } else {
+        offset + columns.start..offset + columns.end
+    }
+}
+
+/// The columns row `r` of `fabric` has cells in: its stitches, and the
+/// stitch it wrapped if it is a short row.
+fn extent(fabric: &Fabric, r: usize, orientation: Orientation) -> Range<usize> {
+    let start = fabric.indent(r);
+    let wrapped = usize::from(fabric.unworked(r) > 0);
+    let columns = start..start + width(&fabric.rows()[r]) + wrapped;
+    from_edge(fabric, r, orientation, columns)
+}
+
+/// The column of the stitch row `r` of `fabric` wrapped, if it is a short
+/// row.
+pub(crate) fn wrap(fabric: &Fabric, r: usize, orientation: Orientation) -> Option<usize> {
+    let extent = extent(fabric, r, orientation);
+    (fabric.unworked(r) > 0).then(|| {
+        if from_left(fabric, r, orientation) {
+            extent.start
+        } else {
+            extent.end - 1
+        }
+    })
+}
+
+/// How many columns `row` spans: one for each stitch it works off the
+/// needle, so a cable takes as many as it crosses, and one for each yarn
+/// over.
+fn width(row: &[Stitch]) -> usize {
+    row.iter().map(|&stitch| columns_of(stitch)).sum()
+}
+
+/// How many columns `stitch` takes. A yarn over or an `m1` works no
+/// stitches off the needle, but is charted in a cell of its own like the
+/// stitch it makes.
+fn columns_of(stitch: Stitch) -> usize {
+    stitch.consumes().max(1)
+}
+
+/// The column of row `row` that a marker after `at` of the stitches it
+/// worked off the needle sits before: the marker was slipped before the
+/// first stitch worked once `at` were, yarn overs included.
+fn mark_column(row: &[Stitch], at: usize) -> usize {
+    let mut worked = 0;
+    let mut column = 0;
+    for &stitch in row {
+        if worked == at {
+            break;
+        }
+        worked += stitch.consumes();
+        column += columns_of(stitch);
+    }
+    column
+}
+
+/// Each stitch of row `r` of `fabric` with its index in the row, the column
+/// its cell starts at, counting from the right, and the stitch as it is
+/// charted: as it looks from the right side, unless `orientation` shows
+/// wrong-side rows as worked.
+fn cells(
+    fabric: &Fabric,
+    r: usize,
+    orientation: Orientation,
+) -> impl Iterator<Item = (usize, usize, Stitch)> + '_ {
+    let row = &fabric.rows()[r];
+    let wrong_side = orientation.invert_wrong_side && wrong_side(fabric, r);
+    row.iter()
+        .enumerate()
+        .scan(fabric.indent(r), move |column, (index, &stitch)| {
+            let start = *column;
+            *column += columns_of(stitch);
+            let face = match stitch {
+                Stitch::Knit if wrong_side => Stitch::Purl,
+                Stitch::Purl if wrong_side => Stitch::Knit,
+                stitch => stitch,
+            };
+            let columns = from_edge(fabric, r, orientation, start..*column);
+            Some((index, columns.start, face))
+        })
+}
+
+/// Each stitch of row `r` of `fabric` as [`cells`] gives it, with every
+/// column its cell covers instead of the first, for forms that lay rows out
+/// as a chart does.
+pub(crate) fn row_cells(
+    fabric: &Fabric,
+    r: usize,
+    orientation: Orientation,
+) -> impl Iterator<Item = (usize, Range<usize>, Stitch)> + '_ {
+    cells(fabric, r, orientation).map(|(i, c, stitch)| (i, c..c + columns_of(stitch), stitch))
+}
+
+/// The chart as text, one line per row, and a line giving the final digit
+/// of every stitch number. A round's number is on the right; a flat row's
+/// number and side are on the right for a right-side row, as `1 RS`, and on
+/// the left for a wrong-side one, as `WS 2`. Each stitch is charted with the
+/// symbols of [`ChartStyle::unicode`], in every column it spans, so `k2tog`
+/// is `╱╱`, unless it is a knit or purl stitch worked in a yarn: then it is
+/// the yarn's letter, `A` for the first yarn declared, `B` for the second
+/// and so on, in capitals for a knit stitch and small letters for a purl
+/// one. Yarns after the 26th all get `*`. Markers are not shown. A legend
+/// giving each symbol the chart uses and what it is follows, then, if there
+/// are yarns, each one's letter, name and colour:
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 3; k 2; p 1; p 3;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// assert_eq!(
+///     knitlang::chart::text(interp.fabric()),
+///     "WS 2 │││\n     •││ 1 RS\n     321\n\
+///      │ knit on RS, purl on WS\n• purl on RS, knit on WS\n"
+/// );
+/// ```
+pub fn text(fabric: &Fabric) -> String {
+    text_oriented(fabric, Orientation::default())
+}
+
+/// The chart as [`text`] draws it, turned as `orientation` says. A
+/// wrong-side row charted from the right has its number there too, as
+/// `2 WS`:
+///
+/// ```
+/// use knitlang::chart::{Orientation, Origin};
+///
+/// let stmts = knitlang::parse_src("cast_on 3; k 2; p 1; p 3;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let worked = Orientation {
+///     origin: Origin::Top,
+///     reverse_wrong_side: false,
+///     invert_wrong_side: false,
+/// };
+/// assert_eq!(
+///     knitlang::chart::text_oriented(interp.fabric(), worked),
+///     "•││ 1 RS\n••• 2 WS\n321\n│ knit\n• purl\n"
+/// );
+/// ```
+pub fn text_oriented(fabric: &Fabric, orientation: Orientation) -> String {
+    text_styled(fabric, orientation, &ChartStyle::default())
+}
+
+/// The chart as [`text_oriented`] draws it, with the symbols of `symbols`:
+///
+/// ```
+/// use knitlang::chart::{ChartStyle, Orientation};
+///
+/// let stmts = knitlang::parse_src("cast_on 4; k 1; k2tog; k 1;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let symbols = ChartStyle::craft_yarn_council();
+/// assert_eq!(
+///     knitlang::chart::text_styled(interp.fabric(), Orientation::default(), &symbols),
+///     "□╱╱□ 1 RS\n4321\n□ knit\n╱ knit two together\n"
+/// );
+/// ```
+pub fn text_styled(fabric: &Fabric, orientation: Orientation, symbols: &ChartStyle) -> String {
+    text_rows(fabric, 0..fabric.rows().len(), orientation, symbols)
+}
+
+/// The chart of completed rows `rows` of `fabric` alone, counting from 0,
+/// as [`text_styled`] draws the whole of it. The rows keep their numbers,
+/// and the legend gives only the symbols they use:
+///
+/// ```
+/// use knitlang::chart::{ChartStyle, Orientation};
+///
+/// let stmts = knitlang::parse_src("cast_on 3; k 3; k 3; p 1; k2tog;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let chart = knitlang::chart::text_rows(
+///     interp.fabric(),
+///     2..3,
+///     Orientation::default(),
+///     &ChartStyle::default(),
+/// );
+/// assert_eq!(chart, "╱╱• 3 RS\n321\n• purl\n╱ knit two together\n");
+/// ```
+pub fn text_rows(
+    fabric: &Fabric,
+    rows: Range<usize>,
+    orientation: Orientation,
+    symbols: &ChartStyle,
+) -> String {
+    let columns = columns(fabric, rows.clone());
+    let left = |r: usize| format!("WS {} ", r + 1);
+    let margin = rows
+        .clone()
+        .filter(|&r| from_left(fabric, r, orientation))
+        .map(|r| left(r).len())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for i in 0..rows.len() {
+        let r = match orientation.origin {
+            Origin::Bottom => rows.end - 1 - i,
+            Origin::Top => rows.start + i,
+        };
+        let line = row_symbols(fabric, r, orientation, columns, symbols);
+        let _ = match fabric.side(r) {
+            _ if from_left(fabric, r, orientation) => {
+                writeln!(out, "{:>margin$}{}", left(r), line.trim_end())
+            }
+            Some(side) => {
+                let side = side.abbreviation();
+                writeln!(out, "{:margin$}{line} {} {side}", "", r + 1)
+            }
+            None => writeln!(out, "{:margin$}{line} {}", "", r + 1),
+        };
+    }
+    if columns > 0 {
+        out.extend(std::iter::repeat_n(' ', margin));
+        out.extend(
+            (1..=columns)
+                .rev()
+                .map(|n| char::from(b'0' + (n % 10) as u8)),
+        );
+        out.push('\n');
+    }
+    for (kind, description) in legend(fabric, rows, orientation, true) {
+        let _ = writeln!(out, "{} {description}", symbols.symbol(kind).text);
+    }
+    for (index, yarn) in fabric.yarns().iter().enumerate() {
+        let _ = writeln!(out, "{} {} {}", letter(index), yarn.name, yarn.colour);
+    }
+    out
+}
+
+/// Each kind of cell a chart of rows `rows` of `fabric`, charted as
+/// `orientation` says, shows, in the order of [`CellKind::ALL`], and what
+/// its legend says it is. With `letters`, the knit and purl stitches a text
+/// chart gives a yarn's letter are left out.
+fn legend(
+    fabric: &Fabric,
+    rows: Range<usize>,
+    orientation: Orientation,
+    letters: bool,
+) -> Vec<(CellKind, &'static str)> {
+    let mut used = [false; CellKind::ALL.len()];
+    for r in rows.clone() {
+        if wrap(fabric, r, orientation).is_some() {
+            used[CellKind::Wrap as usize] = true;
+        }
+        for (i, _, stitch) in cells(fabric, r, orientation) {
+            let lettered =
+                matches!(stitch, Stitch::Knit | Stitch::Purl) && fabric.yarn_at(r, i).is_some();
+            if !(letters && lettered) {
+                used[CellKind::of(stitch) as usize] = true;
+            }
+        }
+    }
+    // Shown as they look from the right side, wrong-side stitches were
+    // worked as the other.
+    let inverted = orientation.invert_wrong_side && rows.into_iter().any(|r| wrong_side(fabric, r));
+    CellKind::ALL
+        .into_iter()
+        .filter(|&kind| used[kind as usize])
+        .map(|kind| {
+            let description = match kind {
+                CellKind::Knit if inverted => "knit on RS, purl on WS",
+                CellKind::Purl if inverted => "purl on RS, knit on WS",
+                kind => kind.description(),
+            };
+            (kind, description)
+        })
+        .collect()
+}
+
+/// Completed row `r` of `fabric` on its own, as a line of [`text`] gives
+/// it but with no margin, so a wrong-side row starts with its number:
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 3; k 2; p 1; p 3;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// assert_eq!(knitlang::chart::text_row(interp.fabric(), 0), "•││ 1 RS");
+/// assert_eq!(knitlang::chart::text_row(interp.fabric(), 1), "WS 2 │││");
+/// ```
+pub fn text_row(fabric: &Fabric, r: usize) -> String {
+    text_row_styled(fabric, r, &ChartStyle::default())
+}
+
+/// Completed row `r` of `fabric` as [`text_row`] gives it, with the symbols
+/// of `symbols`:
+///
+/// ```
+/// use knitlang::chart::ChartStyle;
+///
+/// let stmts = knitlang::parse_src("cast_on 3; k 2; p 1;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let row = knitlang::chart::text_row_styled(interp.fabric(), 0, &ChartStyle::ascii());
+/// assert_eq!(row, "-|| 1 RS");
+/// ```
+pub fn text_row_styled(fabric: &Fabric, r: usize, symbols: &ChartStyle) -> String {
+    let columns = columns(fabric, 0..fabric.rows().len());
+    let line = row_symbols(fabric, r, Orientation::default(), columns, symbols);
+    match fabric.side(r) {
+        Some(Side::Wrong) => format!("WS {} {}", r + 1, line.trim_end()),
+        Some(Side::Right) => format!("{line} {} RS", r + 1),
+        None => format!("{line} {}", r + 1),
+    }
+}
+
+/// The symbols of row `r` of `fabric` across `columns` columns, from the
+/// left, charted as `orientation` says with the symbols of `symbols`.
+fn row_symbols(
+    fabric: &Fabric,
+    r: usize,
+    orientation: Orientation,
+    columns: usize,
+    symbols: &ChartStyle,
+) -> String {
+    let mut line = vec![' '; columns];
+    let mut put = |column: usize, symbol: char| line[columns - 1 - column] = symbol;
+    if let Some(c) = wrap(fabric, r, orientation) {
+        put(c, symbols.wrap.text);
+    }
+    for (i, c, stitch) in cells(fabric, r, orientation) {
+        let symbol = match (fabric.yarn_at(r, i), stitch) {
+            (Some(yarn), Stitch::Knit) => letter(yarn),
+            (Some(yarn), Stitch::Purl) => letter(yarn).to_ascii_lowercase(),
+            (_, stitch) => symbols.symbol(CellKind::of(stitch)).text,
+        };
+        for column in c..c + columns_of(stitch) {
+            put(column, symbol);
+        }
+    }
+    line.into_iter().collect()
+}
+
+/// The letter of yarn `index` in a text chart.
+fn letter(index: usize) -> char {
+    match u8::try_from(index) {
+        Ok(index @ 0..26) => char::from(b'A' + index),
+        _ => '*',
+    }
+}
+
+/// Where everything goes in a drawn chart, in user units for [`svg`] and
+/// pixels for `png`.
+struct Layout {
+    cell: usize,
+    font: usize,
+    margin: usize,
+    /// Where the grid starts across, past the numbers of rows read from
+    /// the left if there are any.
+    left: usize,
+    orientation: Orientation,
+    rows: usize,
+    columns: usize,
+    grid_width: usize,
+    grid_height: usize,
+    /// Where the legend starts down, under the stitch numbers.
+    legend_top: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Layout {
+    /// The layout of a chart of `fabric` drawn in `style`, with room under
+    /// it for a legend of `legend` entries.
+    fn new(fabric: &Fabric, style: &Style, legend: &[(CellKind, &str)]) -> Self {
+        let rows = fabric.rows().len();
+        let columns = columns(fabric, 0..rows);
+        let cell = style.cell_size.max(1) as usize;
+        let font = style.font_size as usize;
+        let margin = cell / 2;
+        // Room for the longest row number, at about 0.6 em a digit.
+        let digits = rows.max(1).to_string().len();
+        let numbers = margin + (font * 6 * digits).div_ceil(10) + margin;
+        let orientation = style.orientation;
+        let left = if (0..rows).any(|r| from_left(fabric, r, orientation)) {
+            numbers
+        } else {
+            margin
+        };
+        let bottom = margin + font + margin;
+        let (grid_width, grid_height) = (columns * cell, rows * cell);
+        let legend_top = margin + grid_height + bottom;
+        // Each entry is a cell and its description, a row of its own, with
+        // room for about 0.8 em a character, as `png` draws them.
+        let described = legend
+            .iter()
+            .map(|(_, description)| {
+                let text = (font * 8 * description.chars().count()).div_ceil(10);
+                margin + cell + margin + text + margin
+            })
+            .max()
+            .unwrap_or(0);
+        Self {
+            cell,
+            font,
+            margin,
+            left,
+            orientation,
+            rows,
+            columns,
+            grid_width,
+            grid_height,
+            legend_top,
+            width: (left + grid_width + numbers).max(described),
+            height: legend_top + legend.len() * (cell + margin),
+        }
+    }
+
+    /// Where the cell of legend entry `i` starts down.
+    fn legend_y(&self, i: usize) -> usize {
+        self.legend_top + i * (self.cell + self.margin)
+    }
+
+    /// Where the cell of stitch `column` starts across, counting from 0:
+    /// stitches go left from the right.
+    fn x(&self, column: usize) -> usize {
+        self.left + self.grid_width - (column + 1) * self.cell
+    }
+
+    /// Where the cell of `stitch`, starting at `column`, starts across, and
+    /// how wide it is.
+    fn span(&self, column: usize, stitch: Stitch) -> (usize, usize) {
+        let columns = columns_of(stitch);
+        (self.x(column + columns - 1), columns * self.cell)
+    }
+
+    /// Where the cell of row `row` starts down, counting from 0: rows go up
+    /// from the bottom, or down from the top.
+    fn y(&self, row: usize) -> usize {
+        match self.orientation.origin {
+            Origin::Bottom => self.margin + self.grid_height - (row + 1) * self.cell,
+            Origin::Top => self.margin + row * self.cell,
+        }
+    }
+
+    /// The row whose cells pixel line `py` of the grid is in.
+    #[cfg(feature = "png")]
+    fn row_at(&self, py: usize) -> usize {
+        let row = (py - self.margin) / self.cell;
+        match self.orientation.origin {
+            Origin::Bottom => self.rows - 1 - row,
+            Origin::Top => row,
+        }
+    }
+
+    /// The row drawn under row `row`, if there is one.
+    #[cfg(feature = "png")]
+    fn below(&self, row: usize) -> Option<usize> {
+        match self.orientation.origin {
+            Origin::Bottom => row.checked_sub(1),
+            Origin::Top => Some(row + 1).filter(|&below| below < self.rows),
+        }
+    }
+
+    /// Where the numbers of rows read from the right start across.
+    fn row_numbers(&self) -> usize {
+        self.left + self.grid_width + self.margin
+    }
+
+    /// Where the numbers of rows read from the left end across.
+    fn wrong_side_numbers(&self) -> usize {
+        self.left - self.margin
+    }
+
+    /// The middle of the stitch numbers, down.
+    fn stitch_numbers(&self) -> usize {
+        self.margin + self.grid_height + self.margin + self.font / 2
+    }
+}
+
+/// A cell [`svg`] draws, in the grid or the legend.
+struct Placed {
+    x: usize,
+    y: usize,
+    width: usize,
+    glyph: Glyph,
+    /// The yarn it was worked in, if it is a stitch from a `color`
+    /// statement.
+    yarn: Option<usize>,
+}
+
+/// The chart as a standalone SVG document: a thin grid with one cell per
+/// stitch, each drawn with the glyph of the style's symbols, row numbers
+/// along the right edge, or the left for wrong-side rows, stitch numbers
+/// along the bottom, and under them a legend of each kind of cell the chart
+/// uses. It needs no fonts or files of its own, and scales to fit whatever
+/// shows it. The style's orientation turns it as it does a
+/// [`text_oriented`] chart.
+pub fn svg(fabric: &Fabric, style: &Style) -> String {
+    let rows = fabric.rows();
+    let legend = legend(fabric, 0..rows.len(), style.orientation, false);
+    let layout = Layout::new(fabric, style, &legend);
+    let Layout {
+        cell,
+        font,
+        margin,
+        width,
+        height,
+        ..
+    } = layout;
+    let glyph = |kind: CellKind| style.symbols.symbol(kind).glyph;
+    let mut placed = Vec::new();
+    for r in 0..rows.len() {
+        for (i, c, stitch) in cells(fabric, r, layout.orientation) {
+            let (x, width) = layout.span(c, stitch);
+            placed.push(Placed {
+                x,
+                y: layout.y(r),
+                width,
+                glyph: glyph(CellKind::of(stitch)),
+                yarn: fabric.yarn_at(r, i),
+            });
+        }
+        if let Some(c) = wrap(fabric, r, layout.orientation) {
+            placed.push(Placed {
+                x: layout.x(c),
+                y: layout.y(r),
+                width: cell,
+                glyph: glyph(CellKind::Wrap),
+                yarn: None,
+            });
+        }
+    }
+    for (i, &(kind, _)) in legend.iter().enumerate() {
+        placed.push(Placed {
+            x: margin,
+            y: layout.legend_y(i),
+            width: cell,
+            glyph: glyph(kind),
+            yarn: None,
+        });
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {width} {height}\" \
+         width=\"{width}\" height=\"{height}\">"
+    );
+    let _ = writeln!(
+        out,
+        "<rect width=\"{width}\" height=\"{height}\" fill=\"{}\"/>",
+        escape(&style.background)
+    );
+    let mut filled = false;
+    for place in &placed {
+        let Some(yarn) = place.yarn else {
+            continue;
+        };
+        if !filled {
+            out.push_str("<g stroke=\"none\">\n");
+            filled = true;
+        }
+        let _ = writeln!(
+            out,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{cell}\" fill=\"{}\"/>",
+            place.x,
+            place.y,
+            place.width,
+            escape(&fabric.yarns()[yarn].colour)
+        );
+    }
+    if filled {
+        out.push_str("</g>\n");
+    }
+    let grid = escape(&style.grid);
+    let _ = writeln!(
+        out,
+        "<g fill=\"none\" stroke=\"{grid}\" stroke-width=\"1\">"
+    );
+    for place in &placed {
+        let shade = if place.glyph == Glyph::Shade {
+            format!(" fill=\"{grid}\"")
+        } else {
+            String::new()
+        };
+        let _ = writeln!(
+            out,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{cell}\"{shade}/>",
+            place.x, place.y, place.width
+        );
+    }
+    out.push_str("</g>\n");
+    let ink = escape(&style.ink);
+    let _ = writeln!(out, "<g fill=\"{ink}\">");
+    let radius = (cell / 6).max(1);
+    for place in placed.iter().filter(|place| place.glyph == Glyph::Dot) {
+        let _ = writeln!(
+            out,
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{radius}\"/>",
+            place.x + place.width / 2,
+            place.y + cell / 2
+        );
+    }
+    out.push_str("</g>\n");
+    let mut stroked = false;
+    for place in placed.iter().filter(|place| place.glyph.stroked()) {
+        if !stroked {
+            let _ = writeln!(
+                out,
+                "<g fill=\"none\" stroke=\"{ink}\" stroke-width=\"2\" stroke-linecap=\"round\">"
+            );
+            stroked = true;
+        }
+        stroke(&mut out, place, cell, &style.background);
+    }
+    if stroked {
+        out.push_str("</g>\n");
+    }
+    let mut lettered = false;
+    for place in &placed {
+        let Glyph::Char(c) = place.glyph else {
+            continue;
+        };
+        if !lettered {
+            let _ = writeln!(
+                out,
+                "<g fill=\"{ink}\" font-family=\"sans-serif\" font-size=\"{}\" \
+                 text-anchor=\"middle\">",
+                (cell * 3 / 5).max(1)
+            );
+            lettered = true;
+        }
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" dy=\"0.35em\">{}</text>",
+            place.x + place.width / 2,
+            place.y + cell / 2,
+            escape(&c.to_string())
+        );
+    }
+    if lettered {
+        out.push_str("</g>\n");
+    }
+    if (0..rows.len()).any(|r| !fabric.marks(r).is_empty()) {
+        let _ = writeln!(out, "<g stroke=\"{ink}\" stroke-width=\"3\">");
+        for r in 0..rows.len() {
+            for x in markers(fabric, &layout, r) {
+                let _ = writeln!(
+                    out,
+                    "<line x1=\"{x}\" y1=\"{}\" x2=\"{x}\" y2=\"{}\"/>",
+                    layout.y(r),
+                    layout.y(r) + cell
+                );
+            }
+        }
+        out.push_str("</g>\n");
+    }
+    let _ = writeln!(
+        out,
+        "<g fill=\"{ink}\" font-family=\"sans-serif\" font-size=\"{font}\">"
+    );
+    for r in 0..layout.rows {
+        let (x, anchor) = if from_left(fabric, r, layout.orientation) {
+            (layout.wrong_side_numbers(), " text-anchor=\"end\"")
+        } else {
+            (layout.row_numbers(), "")
+        };
+        let _ = writeln!(
+            out,
+            "<text x=\"{x}\" y=\"{}\" dy=\"0.35em\"{anchor}>{}</text>",
+            layout.y(r) + cell / 2,
+            r + 1
+        );
+    }
+    for c in 0..layout.columns {
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" dy=\"0.35em\" text-anchor=\"middle\">{}</text>",
+            layout.x(c) + cell / 2,
+            layout.stitch_numbers(),
+            c + 1
+        );
+    }
+    for (i, (_, description)) in legend.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" dy=\"0.35em\">{description}</text>",
+            margin + cell + margin,
+            layout.legend_y(i) + cell / 2
+        );
+    }
+    out.push_str("</g>\n</svg>\n");
+    out
+}
+
+/// Draws the lines of `place`'s glyph, in a cell `cell` units high, for
+/// [`svg`]. The glyph is one [`Glyph::stroked`] says is drawn with lines.
+fn stroke(out: &mut String, place: &Placed, cell: usize, background: &str) {
+    let (left, top) = (place.x, place.y);
+    let (right, bottom) = (left + place.width, top + cell);
+    let (middle, centre, quarter) = (left + place.width / 2, top + cell / 2, cell / 4);
+    let line = |(x1, y1, x2, y2): (usize, usize, usize, usize), extra: &str| {
+        format!("<line x1=\"{x1}\" y1=\"{y1}\" x2=\"{x2}\" y2=\"{y2}\"{extra}/>\n")
+    };
+    let (rising, falling) = ((left, bottom, right, top), (left, top, right, bottom));
+    match place.glyph {
+        // The eyelet a yarn over leaves.
+        Glyph::Ring => {
+            let _ = writeln!(
+                out,
+                "<circle cx=\"{middle}\" cy=\"{centre}\" r=\"{}\"/>",
+                quarter.max(1)
+            );
+        }
+        // For the strand an `m1` lifts.
+        Glyph::Peak => {
+            let _ = writeln!(
+                out,
+                "<polyline points=\"{},{} {middle},{} {},{}\"/>",
+                middle - quarter,
+                bottom - quarter,
+                top + quarter,
+                middle + quarter,
+                bottom - quarter
+            );
+        }
+        Glyph::Rising => out.push_str(&line(rising, "")),
+        Glyph::Falling => out.push_str(&line(falling, "")),
+        Glyph::Vertical => {
+            out.push_str(&line((middle, top + quarter, middle, bottom - quarter), ""))
+        }
+        Glyph::Horizontal => {
+            out.push_str(&line((left + quarter, centre, right - quarter, centre), ""))
+        }
+        // The line behind is drawn first, then the one in front over it
+        // with a border of background so the two read as one over the
+        // other.
+        Glyph::CrossFront | Glyph::CrossBack => {
+            let (over, under) = if place.glyph == Glyph::CrossFront {
+                (falling, rising)
+            } else {
+                (rising, falling)
+            };
+            let gap = format!(" stroke=\"{}\" stroke-width=\"6\"", escape(background));
+            out.push_str(&line(under, ""));
+            out.push_str(&line(over, &gap));
+            out.push_str(&line(over, ""));
+        }
+        Glyph::Blank | Glyph::Dot | Glyph::Shade | Glyph::Char(_) => {}
+    }
+}
+
+/// Where across the markers row `r` of `fabric` passed are, on the lines
+/// between cells.
+fn markers<'a>(
+    fabric: &'a Fabric,
+    layout: &'a Layout,
+    r: usize,
+) -> impl Iterator<Item = usize> + 'a {
+    let indent = fabric.indent(r);
+    fabric.marks(r).iter().map(move |&at| {
+        let at = mark_column(&fabric.rows()[r], at);
+        let column = from_edge(fabric, r, layout.orientation, indent + at..indent + at).start;
+        layout.left + layout.grid_width - column * layout.cell
+    })
+}
+
+/// The dots of `c` for [`png`], which has no fonts to draw with: five rows
+/// of three pixels each, the high bit on the left. Letters are all drawn as
+/// capitals, and anything there is no drawing for is left blank.
+#[cfg(feature = "png")]
+fn dots(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b011, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        _ => [0; 5],
+    }
+}
+
+/// `#rrggbb` or `#rgb` as red, green and blue.
+pub(crate) fn rgb(colour: &str) -> Option<[u8; 3]> {
+    let hex = colour.strip_prefix('#').filter(|hex| hex.is_ascii())?;
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+    match hex.len() {
+        6 => Some([
+            channel(&hex[0..2])?,
+            channel(&hex[2..4])?,
+            channel(&hex[4..6])?,
+        ]),
+        3 => {
+            let short = |i: usize| channel(&hex[i..=i]).map(|v| v * 17);
+            Some([short(0)?, short(1)?, short(2)?])
+        }
+        _ => None,
+    }
+}
+
+/// The chart as a PNG image, laid out like [`svg`] with one pixel per user
+/// unit, so `cell_size` sets the resolution, and its legend written in
+/// capitals of dots. Colours must be `#rrggbb` or `#rgb`. The image is
+/// written a line of pixels at a time, so memory stays small however large
+/// the chart is.
+#[cfg(feature = "png")]
+pub fn png(fabric: &Fabric, style: &Style, out: impl std::io::Write) -> std::io::Result<()> {
+    use std::io::{self, Write};
+
+    let colour = |colour: &str| {
+        rgb(colour).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot draw the colour `{colour}` in a PNG; use #rrggbb"),
+            )
+        })
+    };
+    let (background, grid, ink) = (
+        colour(&style.background)?,
+        colour(&style.grid)?,
+        colour(&style.ink)?,
+    );
+    let yarns = fabric
+        .yarns()
+        .iter()
+        .map(|yarn| colour(&yarn.colour))
+        .collect::<io::Result<Vec<_>>>()?;
+    let rows = fabric.rows();
+    let legend = legend(fabric, 0..rows.len(), style.orientation, false);
+    let layout = Layout::new(fabric, style, &legend);
+    let cell = layout.cell;
+    let (Ok(width), Ok(height)) = (u32::try_from(layout.width), u32::try_from(layout.height))
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "chart too large for a PNG",
+        ));
+    };
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+
+    // Characters are drawn `scale` pixels to a dot, and sit one dot apart.
+    let scale = (layout.font / 5).max(1);
+    let text_width = |text: &str| (text.chars().count() * 4).saturating_sub(1) * scale;
+    let extents: Vec<_> = (0..rows.len())
+        .map(|r| extent(fabric, r, layout.orientation))
+        .collect();
+    // Half the thickness of a cable's lines, and of the gap either side of
+    // the one in front.
+    let thickness = (cell / 20).max(1) as i64;
+    let glyph = |kind: CellKind| style.symbols.symbol(kind).glyph;
+    let mut line = vec![0u8; layout.width * 3];
+    // Draws the dots of `text` on pixel line `dy` of it, starting `left`
+    // pixels across.
+    let write = |line: &mut [u8], text: &str, left: usize, dy: usize| {
+        let dot_row = dy / scale;
+        for (i, c) in text.chars().enumerate() {
+            let bits = dots(c)[dot_row];
+            for px in 0..3 * scale {
+                if bits & (0b100 >> (px / scale)) != 0 {
+                    let x = left + i * 4 * scale + px;
+                    if let Some(pixel) = line.get_mut(x * 3..x * 3 + 3) {
+                        pixel.copy_from_slice(&ink);
+                    }
+                }
+            }
+        }
+    };
+    // Draws pixel line `dy` of a cell of `glyph`, `w` pixels wide, starting
+    // `x0` pixels across, over whatever is under it.
+    let draw = |line: &mut [u8], glyph: Glyph, x0: usize, w: usize, dy: usize| {
+        for dx in 0..w {
+            let colour = match glyph {
+                Glyph::Shade => grid,
+                glyph if ink_at(glyph, dx, dy, w, cell, thickness) => ink,
+                _ => continue,
+            };
+            let x = (x0 + dx) * 3;
+            line[x..x + 3].copy_from_slice(&colour);
+        }
+    };
+    let glyph_height = 5 * scale;
+    // The pixel line of a line of text `glyph_height` high, centred on
+    // `middle`, that `py` is on, if it is on one.
+    let text_line = |py: usize, middle: usize| {
+        (py + glyph_height / 2)
+            .checked_sub(middle)
+            .filter(|&dy| dy < glyph_height)
+    };
+    for py in 0..layout.height {
+        for pixel in line.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&background);
+        }
+        let in_grid = (layout.margin..layout.margin + layout.grid_height).contains(&py);
+        if in_grid {
+            let r = layout.row_at(py);
+            let dy = (py - layout.margin) % cell;
+            if let Some(c) = wrap(fabric, r, layout.orientation) {
+                draw(&mut line, glyph(CellKind::Wrap), layout.x(c), cell, dy);
+            }
+            let first = extents[r].start
+                + usize::from(wrap(fabric, r, layout.orientation) == Some(extents[r].start));
+            for (i, c, stitch) in cells(fabric, r, layout.orientation) {
+                let (x0, w) = layout.span(c, stitch);
+                let fill = fabric.yarn_at(r, i).map(|yarn| yarns[yarn]);
+                let glyph = glyph(CellKind::of(stitch));
+                for dx in 0..w {
+                    // Every cell draws its top and left edges; the bottom
+                    // and right edges are another cell's unless nothing is
+                    // there.
+                    let column = c + (w - 1 - dx) / cell;
+                    let bottom_edge = dy == cell - 1
+                        && layout
+                            .below(r)
+                            .is_none_or(|below| !extents[below].contains(&column));
+                    let edge = dx == 0 || dy == 0 || bottom_edge || (dx == w - 1 && c == first);
+                    let colour = match fill {
+                        _ if edge => grid,
+                        _ if ink_at(glyph, dx, dy, w, cell, thickness) => ink,
+                        _ if glyph == Glyph::Shade => grid,
+                        Some(fill) => fill,
+                        None => continue,
+                    };
+                    let x = (x0 + dx) * 3;
+                    line[x..x + 3].copy_from_slice(&colour);
+                }
+            }
+            for x in markers(fabric, &layout, r) {
+                for x in x.saturating_sub(1)..(x + 2).min(layout.width) {
+                    line[x * 3..x * 3 + 3].copy_from_slice(&ink);
+                }
+            }
+            // Row numbers sit in the middle of their row.
+            if let Some(dy) = text_line(py, layout.y(r) + cell / 2) {
+                let number = (r + 1).to_string();
+                let left = if from_left(fabric, r, layout.orientation) {
+                    layout.wrong_side_numbers() - text_width(&number)
+                } else {
+                    layout.row_numbers()
+                };
+                write(&mut line, &number, left, dy);
+            }
+        }
+        if let Some(dy) = text_line(py, layout.stitch_numbers()) {
+            for c in 0..layout.columns {
+                let number = (c + 1).to_string();
+                let centre = layout.x(c) + cell / 2;
+                let left = centre.saturating_sub(text_width(&number) / 2);
+                write(&mut line, &number, left, dy);
+            }
+        }
+        for (i, &(kind, description)) in legend.iter().enumerate() {
+            let top = layout.legend_y(i);
+            let Some(dy) = py.checked_sub(top).filter(|&dy| dy < cell) else {
+                continue;
+            };
+            draw(&mut line, glyph(kind), layout.margin, cell, dy);
+            for dx in 0..cell {
+                if dx == 0 || dy == 0 || dx == cell - 1 || dy == cell - 1 {
+                    let x = (layout.margin + dx) * 3;
+                    line[x..x + 3].copy_from_slice(&grid);
+                }
+            }
+            if let Some(dy) = text_line(py, top + cell / 2) {
+                let left = layout.margin + cell + layout.margin;
+                write(&mut line, description, left, dy);
+            }
+        }
+        stream.write_all(&line)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+/// Whether pixel `dx`, `dy` of a cell of `glyph`, `width` pixels wide and
+/// `cell` high, is inked. A shaded cell is filled, not inked, and `png`
+/// cannot draw a character.
+#[cfg(feature = "png")]
+fn ink_at(glyph: Glyph, dx: usize, dy: usize, width: usize, cell: usize, thickness: i64) -> bool {
+    let radius = (cell / 6).max(1) as isize;
+    let quarter = (cell / 4) as isize;
+    let t = thickness as isize;
+    let (ox, oy) = (
+        dx as isize - (width / 2) as isize,
+        dy as isize - (cell / 2) as isize,
+    );
+    match glyph {
+        Glyph::Dot => ox * ox + oy * oy <= radius * radius,
+        Glyph::Ring => {
+            let ring = ox * ox + oy * oy - 4 * radius * radius;
+            ring.abs() <= 4 * radius * t
+        }
+        Glyph::Peak => peak(ox, oy, cell, thickness),
+        Glyph::Rising => diagonal(true, dx, dy, width, cell, thickness),
+        Glyph::Falling => diagonal(false, dx, dy, width, cell, thickness),
+        Glyph::Vertical => ox.abs() <= t && oy.abs() <= (cell / 2) as isize - quarter,
+        Glyph::Horizontal => oy.abs() <= t && ox.abs() <= (width / 2) as isize - quarter,
+        Glyph::CrossFront => cable(Cross::Front, dx, dy, width, cell, thickness) == Some(true),
+        Glyph::CrossBack => cable(Cross::Back, dx, dy, width, cell, thickness) == Some(true),
+        Glyph::Blank | Glyph::Shade | Glyph::Char(_) => false,
+    }
+}
+
+/// Whether pixel `dx`, `dy` of a cell `width` by `height` pixels is on the
+/// line from corner to corner of it, going up to the right if `rising` and
+/// down to the right if not.
+#[cfg(feature = "png")]
+fn diagonal(
+    rising: bool,
+    dx: usize,
+    dy: usize,
+    width: usize,
+    height: usize,
+    thickness: i64,
+) -> bool {
+    let (x, y, w, h) = (dx as i64, dy as i64, width as i64, height as i64);
+    // The distance to the line, times its length, as for a cable.
+    let distance = if rising {
+        x * h + y * w - w * h
+    } else {
+        y * w - x * h
+    };
+    distance * distance <= thickness * thickness * (w * w + h * h)
+}
+
+/// Whether pixel `ox`, `oy` from the middle of an `m1`'s cell, `cell`
+/// pixels square, is on the peak drawn in it, which goes up from a quarter
+/// of the cell either side of the middle to a quarter above it.
+#[cfg(feature = "png")]
+fn peak(ox: isize, oy: isize, cell: usize, thickness: i64) -> bool {
+    let (quarter, thickness) = ((cell / 4) as isize, thickness as isize);
+    if ox.abs() > quarter + thickness || oy.abs() > quarter + thickness {
+        return false;
+    }
+    // The distance to the side of the peak, times the side's length over
+    // its height.
+    let distance = 2 * ox.abs() - oy - quarter;
+    distance * distance <= 5 * thickness * thickness
+}
+
+/// Whether pixel `dx`, `dy` of a cable's cell, `width` by `height` pixels,
+/// is on one of its lines, `Some(true)`, or in the gap around the line in
+/// front, `Some(false)`.
+#[cfg(feature = "png")]
+fn cable(
+    cross: Cross,
+    dx: usize,
+    dy: usize,
+    width: usize,
+    height: usize,
+    thickness: i64,
+) -> Option<bool> {
+    let (x, y, w, h) = (dx as i64, dy as i64, width as i64, height as i64);
+    let length = w * w + h * h;
+    // Distances to the line from the top left to the bottom right, and to
+    // the one from the bottom left to the top right, times their length.
+    let falling = y * w - x * h;
+    let rising = x * h + y * w - w * h;
+    let (over, under) = match cross {
+        Cross::Front => (falling, rising),
+        Cross::Back => (rising, falling),
+    };
+    let within = |distance: i64, t: i64| distance * distance <= t * t * length;
+    if within(over, thickness) {
+        Some(true)
+    } else if within(over, 3 * thickness) {
+        Some(false)
+    } else if within(under, thickness) {
+        Some(true)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,764 @@
+//! Stable identifiers for every kind of error and warning, such as `K0301`
+//! for division by zero, with a longer explanation of each for
+//! `knitlang explain`. The first two digits group them: `K01` lexing,
+//! syntax and imports, `K02` names and function calls, `K03` running a
+//! program, `K04` lint warnings and `K05` syntax trees loaded from JSON.
+
+use std::fmt;
+
+/// One kind of diagnostic. Every variant has exactly one entry in
+/// [`REGISTRY`], so no two can share an identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    MissingSemicolon,
+    UnexpectedCharacter,
+    NumberTooLarge,
+    ExpectedToken,
+    ExpectedExpression,
+    UnknownStatement,
+    UnclosedRepeat,
+    NestingTooDeep,
+    UnterminatedString,
+    InvalidColour,
+    ImportNotFound,
+    ImportCycle,
+    NestedImport,
+    SizeCount,
+    AlternateOutsideRepeat,
+    MisplacedMeta,
+    DuplicateMetaKey,
+    UnknownFunction,
+    WrongArgumentCount,
+    FunctionFailed,
+    UndefinedVariable,
+    UndeclaredYarn,
+    UnplacedMarker,
+    AmbiguousName,
+    UnknownImportedName,
+    UndeclaredNeedle,
+    DivisionByZero,
+    Overflow,
+    NegativeCount,
+    NoStitches,
+    CastOnMidRow,
+    StepLimit,
+    Timeout,
+    OutputFailed,
+    Aborted,
+    Interrupted,
+    MemoryLimit,
+    CableTooWide,
+    EmptyShortRow,
+    ModeConflict,
+    MarkerOutOfReach,
+    MarkerSplit,
+    UnloadedImport,
+    UnknownSize,
+    Unmirrorable,
+    UnworkablePattern,
+    InvalidGauge,
+    TooManyDecreases,
+    TooManyBoundOff,
+    WrongStitchCount,
+    TooManyTransferred,
+    NeedleMidRow,
+    EmptyCable,
+    UnusedVariable,
+    UnreachableStatement,
+    StitchCountMismatch,
+    UnbalancedLace,
+    UnknownMetaKey,
+    StitchCountWarning,
+    InvalidAst,
+}
+
+/// What `knitlang explain` says about a [`Code`].
+#[derive(Debug)]
+pub struct Explanation {
+    pub code: Code,
+    /// The identifier printed with the diagnostic, e.g. `K0301`.
+    pub id: &'static str,
+    /// A few words naming the problem.
+    pub title: &'static str,
+    pub description: &'static str,
+    /// A program that causes the diagnostic.
+    pub example: &'static str,
+    /// How to make it go away.
+    pub fix: &'static str,
+}
+
+/// Every code, in the order of [`Code`]'s variants.
+pub static REGISTRY: &[Explanation] = &[
+    Explanation {
+        code: Code::MissingSemicolon,
+        id: "K0101",
+        title: "missing semicolon",
+        description: "Every statement other than `repeat` ends with a `;`. Without it the \
+                      statement runs into whatever comes next, so the error points at the end of \
+                      the statement rather than at the next line.",
+        example: "cast_on width = 4\npurl width;",
+        fix: "Add a `;` where the error points: `cast_on width = 4;`.",
+    },
+    Explanation {
+        code: Code::UnexpectedCharacter,
+        id: "K0102",
+        title: "unexpected character",
+        description: "The source holds a character that is not part of the language. Programs \
+                      are made of names, whole numbers, `\"` strings, `+ - * /`, `=`, `;`, `,`, \
+                      parentheses, braces and `//` comments.",
+        example: "purl 3 % 2;",
+        fix: "Remove the character, or write what it was meant to do with the operators \
+              there are.",
+    },
+    Explanation {
+        code: Code::NumberTooLarge,
+        id: "K0103",
+        title: "number literal too large",
+        description: "Numbers are 64-bit signed integers, so a literal cannot be larger than \
+                      9223372036854775807.",
+        example: "purl 99999999999999999999;",
+        fix: "Use a smaller number.",
+    },
+    Explanation {
+        code: Code::ExpectedToken,
+        id: "K0104",
+        title: "expected a different token",
+        description: "A statement was cut short or has something out of place: the parser \
+                      needed a particular token, such as the `=` after the name in `knit` or \
+                      the `{` after a repeat count, and found another.",
+        example: "knit rows 5;",
+        fix: "Write the statement in full, as the error message describes: \
+              `knit rows = 5;`.",
+    },
+    Explanation {
+        code: Code::ExpectedExpression,
+        id: "K0105",
+        title: "expected an expression",
+        description: "A number, a variable, a function call or an arithmetic expression was \
+                      needed, but the source has something else, often an operator with \
+                      nothing on one side of it.",
+        example: "cast_on width = 4 * ;",
+        fix: "Complete the expression: `cast_on width = 4 * 2;`.",
+    },
+    Explanation {
+        code: Code::UnknownStatement,
+        id: "K0106",
+        title: "unknown statement",
+        description: "Statements start with `cast_on`, `knit`, `purl`, `repeat`, `bind_off`, \
+                      `yarn`, `color`, `cable`, `wrap_turn` (or `w&t`), `mode`, `place_marker`, \
+                      `slip_marker`, `use`, or a stitch (`k`, `p`, a decrease such as `k2tog` \
+                      or a cable such as `c4f`). Anything else, such as a misspelt keyword or \
+                      a bare expression in a file, is not a statement.",
+        example: "knitt rows = 1;",
+        fix: "Check the spelling of the first word of the statement.",
+    },
+    Explanation {
+        code: Code::UnclosedRepeat,
+        id: "K0107",
+        title: "unclosed repeat",
+        description: "A `repeat`, `mirror` or `meta` body opened with `{` runs to the end of the source \
+                      without a closing `}`. The error also points at the `{` that was never closed.",
+        example: "repeat 3 {\n    k 4;\n",
+        fix: "Add the `}` where the body should end.",
+    },
+    Explanation {
+        code: Code::NestingTooDeep,
+        id: "K0108",
+        title: "nesting too deep",
+        description: "Blocks inside blocks, or calls inside the arguments of calls, go deeper \
+                      than the parser allows: 256 levels unless `--max-nesting` or the \
+                      application sets another limit. The error points at the first \
+                      block or call past the limit.",
+        example: "// knitlang --max-nesting 2\nrepeat 1 { repeat 1 { repeat 1 { k 0; } } }",
+        fix: "Nest less deeply, for instance by multiplying the counts of repeats that \
+              only hold each other, or raise the limit.",
+    },
+    Explanation {
+        code: Code::UnterminatedString,
+        id: "K0109",
+        title: "unterminated string",
+        description: "A string literal, such as the colour in a `yarn` declaration, starts \
+                      with `\"` but has no closing `\"` before the end of its line.",
+        example: "yarn MC = \"#1d3557;",
+        fix: "Close the string on the same line: `yarn MC = \"#1d3557\";`.",
+    },
+    Explanation {
+        code: Code::InvalidColour,
+        id: "K0110",
+        title: "invalid colour",
+        description: "The colour of a `yarn` is not a hex colour. Colours are written as `#` \
+                      followed by three or six hex digits, as in `#f00` or `#1d3557`, so that \
+                      every chart format can draw them.",
+        example: "yarn MC = \"navy\";",
+        fix: "Write the colour in hex: `yarn MC = \"#000080\";`.",
+    },
+    Explanation {
+        code: Code::ImportNotFound,
+        id: "K0111",
+        title: "imported file not found",
+        description: "A `use` names a file that could not be read. A relative path is looked                       for next to the file that imports it first, then in each directory of                       `KNIT_PATH` and each `--path`.",
+        example: "use \"stitches/missing.knit\";",
+        fix: "Check the spelling of the path, or add the directory the file is in to \
+              `KNIT_PATH`.",
+    },
+    Explanation {
+        code: Code::ImportCycle,
+        id: "K0112",
+        title: "import cycle",
+        description: "A file imports itself, directly or through the files it imports, so \
+                      there is no order to run them in. The error lists every file in the \
+                      loop, starting and ending with the same one.",
+        example: "// saved as pattern.knit\nuse \"pattern.knit\";",
+        fix: "Move what both files need into a third file that they each import.",
+    },
+    Explanation {
+        code: Code::NestedImport,
+        id: "K0113",
+        title: "import inside a repeat",
+        description: "A `use` runs another file once, where it is first imported, so it only \
+                      goes at the top level of a file and never inside a `repeat`.",
+        example: "repeat 2 {\n    use \"ribbing.knit\";\n}",
+        fix: "Move the `use` to the top of the file.",
+    },
+    Explanation {
+        code: Code::SizeCount,
+        id: "K0114",
+        title: "wrong number of sizes",
+        description: "A size tuple such as `88 (96, 104)` gives one number for each size the \
+                      `sizes` declaration before it names, in the same order, so it must have \
+                      exactly as many. A tuple with no `sizes` declaration before it has no \
+                      sizes to choose between.",
+        example: "sizes S, M, L;\ncast_on 88 (96);",
+        fix: "Give a number for every size, `cast_on 88 (96, 104);`, or declare the sizes \
+              first.",
+    },
+    Explanation {
+        code: Code::AlternateOutsideRepeat,
+        id: "K0115",
+        title: "alternate outside a repeat",
+        description: "An `alternate` block takes turns between its bodies on the passes of the \
+                      `repeat` around it, the first body on the first pass, the second on the \
+                      second and so on, so it only goes inside a `repeat`: outside one there \
+                      are no passes to take turns over.",
+        example: "cast_on 4;\nalternate { k 4; } { p 4; }",
+        fix: "Put the `alternate` in the `repeat` it should take turns over: \
+              `repeat 10 { alternate { k 4; } { p 4; } }`.",
+    },
+    Explanation {
+        code: Code::MisplacedMeta,
+        id: "K0116",
+        title: "misplaced meta block",
+        description: "A `meta` block gives the title, author and other details of a pattern \
+                      for the header of its instructions. A program has at most one, and it \
+                      comes first, before any other statement and outside any block, so the \
+                      details are where a reader looks for them.",
+        example: "cast_on 4;\nmeta { title = \"Swatch\"; }",
+        fix: "Move the `meta` block to the top of the program, or merge it into the one \
+              already there.",
+    },
+    Explanation {
+        code: Code::DuplicateMetaKey,
+        id: "K0117",
+        title: "duplicate meta key",
+        description: "A key of a `meta` block is given more than once, so there is no telling \
+                      which value was meant. The error also points at the first.",
+        example: "meta {\n    title = \"Hat\";\n    title = \"Winter Hat\";\n}",
+        fix: "Keep one of the values and remove the other.",
+    },
+    Explanation {
+        code: Code::UnknownFunction,
+        id: "K0201",
+        title: "unknown function",
+        description: "The program calls a function that does not exist. The language has no \
+                      functions of its own; an application running knitlang can provide some \
+                      with `register_builtin`.",
+        example: "purl double(4);",
+        fix: "Call one of the functions the application provides, or compute the value with \
+              arithmetic: `purl 4 * 2;`.",
+    },
+    Explanation {
+        code: Code::WrongArgumentCount,
+        id: "K0202",
+        title: "wrong number of arguments",
+        description: "A function, or a stitch pattern such as `rib(k, p, rows)`, was called \
+                      with more or fewer arguments than it takes.",
+        example: "// with `max` registered to take two arguments\npurl max(1, 2, 3);",
+        fix: "Pass exactly as many arguments as the message says.",
+    },
+    Explanation {
+        code: Code::FunctionFailed,
+        id: "K0203",
+        title: "function failed",
+        description: "A function provided by the application running knitlang returned an \
+                      error. The message is the function's own.",
+        example: "// with `sqrt` registered to reject negative numbers\npurl sqrt(0 - 4);",
+        fix: "See the function's documentation for the arguments it accepts.",
+    },
+    Explanation {
+        code: Code::UndefinedVariable,
+        id: "K0204",
+        title: "undefined variable",
+        description: "The program reads a variable before any `cast_on` or `knit` has given it \
+                      a value. This is usually found before the program starts, so nothing runs. Names \
+                      are case-sensitive, so `Width` and `width` are different variables. If a \
+                      defined name is close, the message suggests it.",
+        example: "cast_on total = 4;\npurl totla;",
+        fix: "Fix the spelling, or cast the variable on before reading it: \
+              `cast_on totla = 0;`.",
+    },
+    Explanation {
+        code: Code::UndeclaredYarn,
+        id: "K0205",
+        title: "undeclared yarn",
+        description: "A `color` statement names a yarn that no `yarn` declaration has run \
+                      before it. Names are case-sensitive, and if a declared yarn is close, \
+                      the message suggests it.",
+        example: "yarn MC = \"#1d3557\";\ncolor CC;",
+        fix: "Declare the yarn before using it: `yarn CC = \"#e63946\";`.",
+    },
+    Explanation {
+        code: Code::UnplacedMarker,
+        id: "K0206",
+        title: "unplaced marker",
+        description: "A `slip_marker` statement or a `stitches_to` call names a stitch marker \
+                      that no `place_marker` has put on the needles. Names are \
+                      case-sensitive, and if a placed marker is close, the message suggests \
+                      it.",
+        example: "cast_on 8;\nk 4;\nslip_marker side;",
+        fix: "Place the marker on an earlier row: `place_marker side;`.",
+    },
+    Explanation {
+        code: Code::AmbiguousName,
+        id: "K0207",
+        title: "ambiguous name",
+        description: "A file reads a variable that it does not define itself, and more than one \
+                      of the files it imports without `as` define it, so there is no telling \
+                      which it means. The message lists each. This is also reported for a \
+                      file that gives two different imports the same name.",
+        example: "// with lib.knit and edge.knit both casting on `width`\nuse \"lib.knit\";\n\
+                  use \"edge.knit\";\npurl width;",
+        fix: "Qualify the name with the import it is from, `purl lib.width;`, or cast on a \
+              `width` of the file's own.",
+    },
+    Explanation {
+        code: Code::UnknownImportedName,
+        id: "K0208",
+        title: "unknown qualified name",
+        description: "A qualified name such as `edgings.width` names an import the file does \
+                      not have, or a variable the imported file never casts on or knits. \
+                      Only the files a file imports itself can qualify its names. If a name \
+                      is close, the message suggests it.",
+        example: "// with edgings.knit casting on `width` but not `depth`\n\
+                  use \"edgings.knit\" as edgings;\npurl edgings.depth;",
+        fix: "Fix the spelling, or `use` the file the variable is from.",
+    },
+    Explanation {
+        code: Code::UndeclaredNeedle,
+        id: "K0209",
+        title: "undeclared needle",
+        description: "`transfer`, `resume` or `count(...)` names a needle that no \
+                      `cast_on name = needle(count);`, `hold` or `transfer` has made yet. \
+                      Needles are not variables, so a variable of the same name does not \
+                      count. If a needle's name is close, the message suggests it.",
+        example: "cast_on left = needle(6);\npurl count(rigth);",
+        fix: "Fix the spelling, or cast the needle on before using it.",
+    },
+    Explanation {
+        code: Code::DivisionByZero,
+        id: "K0301",
+        title: "division by zero",
+        description: "The right-hand side of a `/` was 0 when the program ran. The error points \
+                      at the divisor.",
+        example: "cast_on rows = 0;\npurl 12 / rows;",
+        fix: "Make sure the divisor cannot be 0, for instance by casting the variable on with \
+              a sensible starting value.",
+    },
+    Explanation {
+        code: Code::Overflow,
+        id: "K0302",
+        title: "arithmetic overflow",
+        description: "The result of `+`, `-`, `*` or `/` does not fit in a 64-bit signed \
+                      integer.",
+        example: "cast_on big = 9223372036854775807;\npurl big + 1;",
+        fix: "Keep the numbers smaller; a runaway loop doubling a variable is a common cause.",
+    },
+    Explanation {
+        code: Code::NegativeCount,
+        id: "K0303",
+        title: "negative count",
+        description: "A stitch count, in `cast_on <count>;`, `k <count>;` or `p <count>;`, or \
+                      the count of a `repeat` or an argument of a stitch pattern, evaluated to \
+                      a negative number. A count of 0 is fine: it works no stitches, or skips \
+                      the repeat.",
+        example: "cast_on width = 2;\ncast_on 4;\nk width - 3;",
+        fix: "Check the expression for the count; it must be 0 or more.",
+    },
+    Explanation {
+        code: Code::NoStitches,
+        id: "K0304",
+        title: "no stitches on the needles",
+        description: "The program works stitches before casting any on.",
+        example: "k 4;",
+        fix: "Cast stitches on first: `cast_on 4;` before `k 4;`.",
+    },
+    Explanation {
+        code: Code::CastOnMidRow,
+        id: "K0305",
+        title: "cast on in the middle of a row",
+        description: "Stitches can only be cast on between rows, once every stitch of the \
+                      current row has been worked, and not after a `wrap_turn`: the next row \
+                      then starts away from the edge of the needles.",
+        example: "cast_on 4;\nk 2;\ncast_on 2;",
+        fix: "Finish the row first: work the stitches the message says are left.",
+    },
+    Explanation {
+        code: Code::StepLimit,
+        id: "K0306",
+        title: "step limit reached",
+        description: "The run did more units of work (statements, loop passes and stitches) than \
+                      the step limit set by the application allows.",
+        example: "// with a step limit of 1000 set by the application\ncast_on 1;\nrepeat 1000000000 { k 1; }",
+        fix: "Make the program do less work, or raise the limit if it is meant to.",
+    },
+    Explanation {
+        code: Code::Timeout,
+        id: "K0307",
+        title: "timed out",
+        description: "The run took longer than `--timeout` allows, and was stopped. The process \
+                      exits with code 4.",
+        example: "// knitlang --timeout 1s\nrepeat 1000000000 { repeat 1000000000 { k 0; } }",
+        fix: "Make the program do less work, or raise the timeout.",
+    },
+    Explanation {
+        code: Code::OutputFailed,
+        id: "K0308",
+        title: "could not write output",
+        description: "Writing what `purl` printed failed, for instance because the disk is full \
+                      or the other end of a pipe was closed.",
+        example:
+            "// knitlang pattern.knit | head -n 1, for a pattern that purls many lines\npurl 1;",
+        fix: "Check where output is going: the `--output` file, or the program reading it.",
+    },
+    Explanation {
+        code: Code::Aborted,
+        id: "K0309",
+        title: "aborted by the application",
+        description: "An execution hook installed by the application running knitlang stopped \
+                      the run before a statement. The message is the hook's own.",
+        example: "// with a hook that allows 10 statements\nrepeat 100 { purl 1; }",
+        fix: "See the application's documentation for what it allows.",
+    },
+    Explanation {
+        code: Code::Interrupted,
+        id: "K0310",
+        title: "interrupted",
+        description: "The run was stopped from outside, by Ctrl-C on the command line or by \
+                      an application setting the interpreter's interrupt flag. The usual \
+                      `--summary` still prints, `-i` still starts the REPL, and otherwise the \
+                      process exits with code 130. A second Ctrl-C before the run stops ends \
+                      the process at once.",
+        example: "// press Ctrl-C while it runs\ncast_on 1;\nrepeat 1000000000 { k 1; }",
+        fix: "Nothing is wrong with the program; let it run longer, or make it do less \
+              work.",
+    },
+    Explanation {
+        code: Code::MemoryLimit,
+        id: "K0311",
+        title: "memory limit exceeded",
+        description: "The variables and the rows of fabric the run holds came to more bytes than \
+                      `--max-memory` or the application allows. Every stitch of every row is \
+                      kept, so a long loop working many stitches is the usual cause.",
+        example: "// knitlang --max-memory 1K\ncast_on 1;\nrepeat 1000 { k 1; }",
+        fix: "Make the program knit less, or raise the limit if it is meant to.",
+    },
+    Explanation {
+        code: Code::CableTooWide,
+        id: "K0312",
+        title: "cable wider than the row",
+        description: "A cable works all of its stitches at once, and a decrease such as \
+                      `k2tog` both of its, so they must all be left in the row it starts in; \
+                      a stitch cannot carry on into the next row. The error says how many \
+                      stitches were left.",
+        example: "cast_on 6;\nk 4;\nc4f;",
+        fix: "Work the cable earlier in the row, or make it narrower: `c2f;`.",
+    },
+    Explanation {
+        code: Code::EmptyShortRow,
+        id: "K0313",
+        title: "wrap and turn before any stitch",
+        description: "`wrap_turn` ends the row it is in, so at least one stitch of the row \
+                      must have been worked first; turning straight back would make a row of \
+                      no stitches. Right after a turn, or at the start of any row, there is \
+                      nothing to turn back over.",
+        example: "cast_on 6;\nk 3;\nwrap_turn;\nwrap_turn;",
+        fix: "Work some stitches of the row before turning: `p 2; wrap_turn;`.",
+    },
+    Explanation {
+        code: Code::ModeConflict,
+        id: "K0314",
+        title: "mode conflict",
+        description: "A piece changes between `mode flat;` and `mode round;` only between \
+                      rows, once the last row has been worked to the edge; a row cannot be \
+                      part flat and part in the round. Short rows turn the work, so \
+                      `wrap_turn` only works in flat knitting.",
+        example: "cast_on 8;\nk 4;\nmode round;",
+        fix: "Change mode before the row starts or after it ends, and work short rows \
+              flat: `mode flat;`.",
+    },
+    Explanation {
+        code: Code::MarkerOutOfReach,
+        id: "K0315",
+        title: "marker out of reach",
+        description: "`slip_marker` slips a marker the row has just reached, so the stitches \
+                      before it must have been worked first; working stitches past a marker \
+                      slips it anyway, so the statement checks the count is right. \
+                      `stitches_to` counts the stitches to a marker ahead of the work. A row \
+                      worked flat cannot reach a marker it has already passed; in the round \
+                      the count goes on into the next round.",
+        example: "cast_on 8;\nk 2;\nplace_marker side;\nk 6;\nk 3;\nslip_marker side;",
+        fix: "Work up to the marker first: `k stitches_to(side);`.",
+    },
+    Explanation {
+        code: Code::MarkerSplit,
+        id: "K0316",
+        title: "stitch across a marker",
+        description: "A marker sits between two stitches, so a cable or a decrease cannot work \
+                      the stitches either side of it together.",
+        example: "cast_on 8;\nk 3;\nplace_marker side;\nk 5;\nk 4;\nk2tog;",
+        fix: "Work the stitch so it ends at the marker, or starts after it: `k 3; k2tog; \
+              slip_marker side;`.",
+    },
+    Explanation {
+        code: Code::UnloadedImport,
+        id: "K0317",
+        title: "import not loaded",
+        description: "The interpreter never reads files itself, so the files a program \
+                      imports are loaded before it runs, as the command line does for \
+                      programs run from a file. A `use` reached while running was not, as \
+                      when an application runs the program with `run_source`.",
+        example: "// with knitlang::run_source\nuse \"ribbing.knit\";",
+        fix: "Run the program from a file with `knitlang`, or load its imports with \
+              `knitlang::imports::Importer` first.",
+    },
+    Explanation {
+        code: Code::UnknownSize,
+        id: "K0318",
+        title: "unknown size",
+        description: "The size chosen with `--size`, or by the application running knitlang, is \
+                      not one of the sizes the pattern's `sizes` declaration names. Size \
+                      names are case-sensitive, and if a declared size is close, the message \
+                      suggests it.",
+        example: "// run with --size XXL\nsizes S, M, L;",
+        fix: "Choose one of the declared sizes, or add the size to the declaration and a \
+              number for it to every size tuple.",
+    },
+    Explanation {
+        code: Code::Unmirrorable,
+        id: "K0319",
+        title: "cannot mirror",
+        description: "A `mirror` block works its stitches with each row reversed and every \
+                      stitch leaning the other way, so it can only hold stitches and the \
+                      statements that do not touch the needles, such as assignments and \
+                      `color`. Casting on, a wrap and turn, markers, a change of `mode` and \
+                      `bind_off` have no mirror image, and neither does a stitch that leans \
+                      one way with no counterpart leaning the other.",
+        example: "cast_on 8;\nmirror {\n    k 4;\n    wrap_turn;\n}",
+        fix: "Move the statement out of the `mirror` block, before or after it, or write \
+              the mirrored rows out by hand.",
+    },
+    Explanation {
+        code: Code::UnworkablePattern,
+        id: "K0320",
+        title: "cannot work pattern",
+        description: "A builtin stitch pattern such as `seed` works whole rows, so it must start \
+                      where a row does, not part way through one. A `rib` also needs at least \
+                      one knit or purl in its repeat.",
+        example: "cast_on 6;\nk 2;\nseed(4);",
+        fix: "Finish the row before the pattern starts, or give the rib a knit or a purl.",
+    },
+    Explanation {
+        code: Code::InvalidGauge,
+        id: "K0321",
+        title: "invalid gauge",
+        description: "A `gauge` statement gives how many stitches and how many rows make 10 cm \
+                      of fabric, which finished measurements are worked out from. Both must \
+                      be at least 1.",
+        example: "gauge 22, 0;",
+        fix: "Count the stitches and rows across 10 cm of a swatch knitted in the pattern, \
+              and give those.",
+    },
+    Explanation {
+        code: Code::TooManyDecreases,
+        id: "K0322",
+        title: "too many decreases for the row",
+        description: "`distribute dec` works the rest of the row with its decreases spread \
+                      evenly across it, each a `k2tog` working two stitches together, so the \
+                      row must have at least twice as many stitches left as there are \
+                      decreases. The error says how many were left.",
+        example: "cast_on 10;\ndistribute dec 6;",
+        fix: "Decrease fewer stitches in the row, and the rest in the next: \
+              `distribute dec 5;`.",
+    },
+    Explanation {
+        code: Code::TooManyBoundOff,
+        id: "K0323",
+        title: "too many stitches to bind off",
+        description: "`bind_off <count>;` binds off that many stitches from where the row has \
+                      got to, and the rest of the row goes on from there, so the row must have \
+                      that many stitches left. A bare `bind_off;` binds off every stitch and \
+                      ends the piece. The error says how many were left.",
+        example: "cast_on 10;\nk 6;\nbind_off 5;",
+        fix: "Bind off only the stitches left in the row, or finish the row and bind off \
+              the rest at the start of the next.",
+    },
+    Explanation {
+        code: Code::WrongStitchCount,
+        id: "K0324",
+        title: "wrong stitch count at a checkpoint",
+        description: "`expect_stitches <count>;` checks that there are that many live \
+                      stitches on the needles when it runs, as a pattern's \"you should have \
+                      96 sts\" does, counting the stitches the current row has made and those \
+                      it has still to work. The error gives both counts. \
+                      `expect_stitches? <count>;` only warns instead (K0406).",
+        example: "cast_on 10;\nk2tog;\nk 8;\nexpect_stitches 10;",
+        fix: "Find the shaping before the checkpoint that worked more or fewer stitches than \
+              it should, or correct the count the checkpoint gives.",
+    },
+    Explanation {
+        code: Code::TooManyTransferred,
+        id: "K0325",
+        title: "transferring more stitches than a needle holds",
+        description: "`transfer from to count;` moves stitches off needle `from`, which only \
+                      holds so many. Moving more than it has, or a negative count, is an \
+                      error giving what it holds.",
+        example: "cast_on left = needle(6);\ncast_on right = needle(0);\ntransfer left right 8;",
+        fix: "Transfer at most `count(from)` stitches, or put more on the needle first.",
+    },
+    Explanation {
+        code: Code::NeedleMidRow,
+        id: "K0326",
+        title: "holding or resuming part way through a row",
+        description: "`hold` takes every stitch off the working needle, and `resume` puts a \
+                      needle's stitches back on it for the next row to work, so both only go \
+                      between rows: not while a row has stitches left to work, nor after a \
+                      wrap and turn has left stitches behind.",
+        example: "cast_on 8;\nk 4;\nhold left;",
+        fix: "Finish the row first, or move the statement to where the row starts.",
+    },
+    Explanation {
+        code: Code::EmptyCable,
+        id: "K0327",
+        title: "cable of no stitches",
+        description: "`cable <count> front;` crosses `count` stitches over as many more, so a \
+                      count of 0 would cross nothing and work nothing; it is more likely a \
+                      mistake than meant. The count must be at least 1.",
+        example: "cast_on width = 0;\ncast_on 4;\ncable width front;\nk 4;",
+        fix: "Check the expression for the count, or leave the cable out where it would \
+              cross no stitches.",
+    },
+    Explanation {
+        code: Code::UnusedVariable,
+        id: "K0401",
+        title: "unused variable",
+        description: "A variable is cast on but never read, which often means a typo where it \
+                      was meant to be used. Switch the warning off with `-W no-unused`.",
+        example: "cast_on widht = 4;\ncast_on 4;",
+        fix: "Use the variable, fix the misspelt name, or remove the `cast_on`.",
+    },
+    Explanation {
+        code: Code::UnreachableStatement,
+        id: "K0402",
+        title: "unreachable statement",
+        description: "Statements after `bind_off` in the same block can never run. Switch the \
+                      warning off with `-W no-unreachable`.",
+        example: "cast_on 4;\nk 4;\nbind_off;\nk 4;",
+        fix: "Remove the statements, or move the `bind_off` after them.",
+    },
+    Explanation {
+        code: Code::StitchCountMismatch,
+        id: "K0403",
+        title: "stitch count mismatch",
+        description: "A row works more or fewer stitches than are on the needles, so it is \
+                      left part worked where a row has to end: at a `cast_on`, a stitch pattern, \
+                      a change of `mode` or the end of the program. Only counts known before \
+                      the run are checked; `check -v` says where a count that depends on the \
+                      run stops the check. Switch the warning off with `-W no-stitch-count`.",
+        example: "cast_on 22;\nrepeat 4 { k 5; }",
+        fix: "Make the row's stitches add up to the stitches on the needles, counting a \
+              decrease such as `k2tog` as two.",
+    },
+    Explanation {
+        code: Code::UnbalancedLace,
+        id: "K0404",
+        title: "unbalanced lace row",
+        description: "A row works yarn overs, each making a stitch, but its decreases lose a \
+                      different number, so the stitch count drifts from one lace row to the \
+                      next. A `k2tog` or `ssk` loses one stitch. Rows knitted from counts only \
+                      the run knows are not checked. For a row that increases on purpose, put \
+                      `// knitlang: allow(lace)` on the line before its first statement, or \
+                      switch the warning off everywhere with `-W no-lace`.",
+        example: "cast_on 4;\nk 1; yo; k 3;",
+        fix: "Add the missing decreases or take out the extra yarn overs, or mark an \
+              increase row with `// knitlang: allow(lace)`.",
+    },
+    Explanation {
+        code: Code::UnknownMetaKey,
+        id: "K0405",
+        title: "unknown meta key",
+        description: "A `meta` block has a key other than `title`, `author`, `yarn`, `needle` \
+                      and `gauge`, which the headers of written instructions leave out, so it is \
+                      often a misspelling of one of them. Tools reading the details with \
+                      `--json` still see it. Switch the warning off with `-W no-meta`.",
+        example: "meta { autor = \"A. Knitter\"; }",
+        fix: "Correct the key, or remove it if nothing reads it.",
+    },
+    Explanation {
+        code: Code::StitchCountWarning,
+        id: "K0406",
+        title: "wrong stitch count at a warning checkpoint",
+        description: "`expect_stitches? <count>;` checks the live stitches on the needles as \
+                      `expect_stitches` does (K0324), but a wrong count is a warning and the \
+                      run goes on. It is reported when the checkpoint runs, once each time, so \
+                      one in a `repeat` can warn on some passes and not others. Switch the \
+                      warning off with `-W no-checkpoint`, or make it an error with \
+                      `-W error`.",
+        example: "cast_on 10;\nexpect_stitches? 12;",
+        fix: "Find the shaping before the checkpoint that worked more or fewer stitches than \
+              it should, or correct the count the checkpoint gives.",
+    },
+    Explanation {
+        code: Code::InvalidAst,
+        id: "K0501",
+        title: "invalid syntax tree",
+        description: "A file given to `run --from-ast` is not a syntax tree in the JSON shape \
+                      `parse --format json` prints. The message gives the path in the document \
+                      of the first problem, e.g. `$[2].body[0]`.",
+        example: "[{\"kind\": \"purl\"}]",
+        fix: "Compare the node with the output of `knitlang parse --format json` for the same \
+              statement.",
+    },
+];
+
+impl Code {
+    /// The code registered with identifier `id`, e.g. `K0301`.
+    pub fn from_id(id: &str) -> Option<Self> {
+        REGISTRY
+            .iter()
+            .find(|entry| entry.id.eq_ignore_ascii_case(id))
+            .map(|entry| entry.code)
+    }
+
+    pub fn id(self) -> &'static str {
+        self.explanation().id
+    }
+
+    pub fn explanation(self) -> &'static Explanation {
+        &REGISTRY[self as usize]
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
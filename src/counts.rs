@@ -0,0 +1,501 @@
+//! Stitch counts, checked before a program runs: following the stitches on
+//! the needles through every row, to find a row that works more or fewer
+//! stitches than there are, such as `repeat 4 { k 5; }` across 22 stitches.
+//!
+//! The run itself knits on into the next row whenever one is used up, so a
+//! count that is off only shows as a row left part worked where the next
+//! stitch must start a row of its own: at a `cast_on`, a stitch pattern, a
+//! change of `mode` or the end of the program. The check follows counts the
+//! source gives as numbers, or that arithmetic on numbers and variables set
+//! from them gives. It stops at the first count only the run can know, such
+//! as one read from a marker or in a graded pattern, and at a short row, and
+//! says where, as it can then no longer tell either way.
+//!
+//! Along the way it checks lace: a row with yarn overs should lose as many
+//! stitches to decreases as the yarn overs make, or the count drifts from
+//! row to row. Rows with no yarn overs are shaping, such as `m1`s or a
+//! `distribute`, and are left alone.
+
+use crate::ast::{Expr, ExprKind, Mode, Shaping, Stitch, Stmt, StmtKind};
+use crate::interp::{arithmetic, Value, SIZE_VARIABLE};
+use crate::lexer::Span;
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+/// The most statements the check follows, counting each pass of a `repeat`
+/// body again, before it stops. Passes that leave the needles as they found
+/// them are not followed, so this only limits loops whose counts keep
+/// changing.
+const MAX_STEPS: usize = 100_000;
+
+/// What [`check`] found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Rows left part worked where a row must end, in the order the program
+    /// reaches them, each once.
+    pub mismatches: Vec<Mismatch>,
+    /// Rows whose yarn overs are not matched by decreases, in the order the
+    /// program reaches them, once for each stretch of source working them.
+    pub unbalanced: Vec<Unbalanced>,
+    /// Where the check stopped before the end of the program, if it did.
+    pub unverified: Option<Unverified>,
+}
+
+/// A row that works a different number of stitches from the number on the
+/// needles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// The row, counting from 1.
+    pub row: usize,
+    /// The statements that work the row's stitches.
+    pub span: Span,
+    /// Stitches the row works off the needle.
+    pub worked: usize,
+    /// Stitches on the needle for the row.
+    pub expected: usize,
+    /// The statement the row has to be finished for, or `None` for the end
+    /// of the program.
+    pub before: Option<Span>,
+}
+
+/// A row with yarn overs that makes more or fewer stitches with them than
+/// its decreases lose.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unbalanced {
+    /// The row, counting from 1.
+    pub row: usize,
+    /// The statements that work the row's stitches.
+    pub span: Span,
+    pub yarn_overs: usize,
+    /// Stitches the row's decreases lose.
+    pub decreases: usize,
+}
+
+/// A statement whose stitches the check could not follow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unverified {
+    pub span: Span,
+    /// Why, as a clause such as `its count is only known when it runs`.
+    pub reason: &'static str,
+}
+
+/// The stitch counts of `stmts`, run on their own from empty needles.
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 22;\nrepeat 4 { k 5; }").unwrap();
+/// let report = knitlang::counts::check(&stmts);
+/// let row = &report.mismatches[0];
+/// assert_eq!((row.row, row.worked, row.expected), (1, 20, 22));
+/// ```
+pub fn check(stmts: &[Stmt]) -> Report {
+    let mut counter = Counter::default();
+    let unverified = match counter.stmts(stmts) {
+        Ok(()) => {
+            counter.end_row_for(None);
+            None
+        }
+        Err(Stop::Unverified(unverified)) => Some(unverified),
+        Err(Stop::Ended | Stop::Fails) => None,
+    };
+    Report {
+        mismatches: counter.mismatches,
+        unbalanced: counter.unbalanced,
+        unverified,
+    }
+}
+
+/// After how many passes of a `repeat` with body `stmts` the bodies its
+/// `alternate`s work come round again: 1 with none, or the least common
+/// multiple of how many bodies each has. 0 if that is too many to count.
+fn cycle(stmts: &[Stmt]) -> usize {
+    let mut passes = 1;
+    for stmt in stmts {
+        let inner = match &stmt.kind {
+            StmtKind::Repeat(..) => continue,
+            StmtKind::Alternate(bodies) => bodies
+                .iter()
+                .map(|body| cycle_of(bodies.len(), cycle(body)))
+                .fold(1, cycle_of),
+            kind => kind
+                .bodies()
+                .iter()
+                .map(|body| cycle(body))
+                .fold(1, cycle_of),
+        };
+        passes = cycle_of(passes, inner);
+    }
+    passes
+}
+
+/// The least common multiple of `a` and `b`, or 0 if either is 0 or it
+/// overflows.
+fn cycle_of(a: usize, b: usize) -> usize {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    match x {
+        0 => 0,
+        gcd => (a / gcd).checked_mul(b).unwrap_or(0),
+    }
+}
+
+/// Why the check stopped following the program.
+enum Stop {
+    /// `bind_off` ended it.
+    Ended,
+    /// It fails at run time at this point, which the run reports.
+    Fails,
+    Unverified(Unverified),
+}
+
+/// The needles, as far as a check can follow them.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Needles {
+    /// Stitches on the needle for the current row.
+    width: usize,
+    /// Stitches the current row has worked off the needle.
+    consumed: usize,
+    /// Stitches the current row has made for the next one.
+    produced: usize,
+    /// Yarn overs the current row has worked, and stitches it has lost to
+    /// decreases.
+    yarn_overs: usize,
+    decreased: usize,
+    mode: Mode,
+    /// Variables with the values they are known to have, or `None` for
+    /// those only known at run time.
+    vars: HashMap<Symbol, Option<Value>>,
+}
+
+#[derive(Default)]
+struct Counter {
+    needles: Needles,
+    /// Rows completed.
+    rows: usize,
+    /// The source the current row's stitches were worked by, once it has
+    /// worked one.
+    row_span: Option<Span>,
+    steps: usize,
+    /// The pass of each `repeat` being followed, innermost last.
+    passes: Vec<usize>,
+    mismatches: Vec<Mismatch>,
+    unbalanced: Vec<Unbalanced>,
+}
+
+impl Counter {
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<(), Stop> {
+        stmts.iter().try_for_each(|stmt| self.stmt(stmt))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), Stop> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return unverified(stmt.span, "it runs too long to follow");
+        }
+        match &stmt.kind {
+            StmtKind::CastOn(name, e) | StmtKind::Knit(name, e) => {
+                let value = self.eval(e);
+                self.needles.vars.insert(*name, value);
+            }
+            StmtKind::CastOnStitches(e) => {
+                let n = self.count(e, stmt.span)?;
+                self.end_row_for(Some(stmt.span));
+                self.needles.width += n;
+            }
+            StmtKind::Work(stitch, e) => {
+                let n = self.count(e, stmt.span)?;
+                self.work(*stitch, n, stmt.span)?;
+            }
+            StmtKind::Distribute(shaping, e) => {
+                let n = self.count(e, stmt.span)?;
+                self.distribute(*shaping, n, stmt.span)?;
+            }
+            StmtKind::Cable(e, cross) => match self.count(e, stmt.span)? {
+                0 => return Err(Stop::Fails),
+                n => {
+                    let n = u32::try_from(n).map_err(|_| Stop::Fails)?;
+                    self.work(Stitch::Cable(n, *cross), 1, stmt.span)?;
+                }
+            },
+            StmtKind::Repeat(count, body) => {
+                let n = self.count(count, stmt.span)?;
+                self.repeat(n, body, stmt.span)?;
+            }
+            StmtKind::Alternate(bodies) => {
+                let Some(&pass) = self.passes.last() else {
+                    return Err(Stop::Fails);
+                };
+                if let Some(body) = bodies.get(pass % bodies.len().max(1)) {
+                    let row = self.rows;
+                    self.stmts(body)?;
+                    self.widen_row(row, stmt.span);
+                }
+            }
+            StmtKind::Mirror(body) => {
+                // A mirrored row works the same stitches in another order.
+                let row = self.rows;
+                self.stmts(body)?;
+                self.widen_row(row, stmt.span);
+            }
+            StmtKind::Pattern(_, args) => {
+                let rows = self.count(args.last().expect("a pattern has rows"), stmt.span)?;
+                self.end_row_for(Some(stmt.span));
+                if rows > 0 && self.needles.width == 0 {
+                    return Err(Stop::Fails);
+                }
+                // Every stitch of a pattern is a knit or a purl.
+                self.rows += rows;
+            }
+            StmtKind::Mode(mode) => {
+                if *mode != self.needles.mode {
+                    self.end_row_for(Some(stmt.span));
+                    self.needles.mode = *mode;
+                }
+            }
+            StmtKind::WrapTurn => return unverified(stmt.span, "short rows are not followed"),
+            StmtKind::Use(..) => {
+                return unverified(stmt.span, "an imported file may work stitches of its own")
+            }
+            StmtKind::Sizes(_) => {
+                let size = Symbol::intern(SIZE_VARIABLE);
+                self.needles.vars.insert(size, None);
+            }
+            StmtKind::Hold(_) | StmtKind::Resume(_) => {
+                return unverified(
+                    stmt.span,
+                    "stitches moved on and off holders are not followed",
+                )
+            }
+            StmtKind::BindOff => return Err(Stop::Ended),
+            StmtKind::BindOffStitches(e) => {
+                let n = self.count(e, stmt.span)?;
+                let needles = &self.needles;
+                let left = needles.width - needles.consumed;
+                if needles.width == 0 || n > left {
+                    return Err(Stop::Fails);
+                }
+                // Every stitch on the needles bound off ends the piece.
+                if n == left && needles.produced == 0 {
+                    return Err(Stop::Ended);
+                }
+                self.work(Stitch::BindOff, n, stmt.span)?;
+            }
+            StmtKind::Purl(_)
+            | StmtKind::ExpectStitches(_)
+            | StmtKind::ExpectStitchesWarning(_)
+            | StmtKind::Yarn(..)
+            | StmtKind::Color(_)
+            | StmtKind::PlaceMarker(_)
+            | StmtKind::SlipMarker(_)
+            | StmtKind::Gauge(..)
+            | StmtKind::Meta(_)
+            | StmtKind::Note(_)
+            | StmtKind::Chart(_)
+            | StmtKind::CastOnNeedle(..)
+            | StmtKind::Transfer(..) => {}
+        }
+        Ok(())
+    }
+
+    /// Runs `n` passes of `body`, leaving out those after one that changes
+    /// nothing but the number of rows, as the rest would do the same.
+    fn repeat(&mut self, n: usize, body: &[Stmt], span: Span) -> Result<(), Stop> {
+        let row = self.rows;
+        // Passes only differ in the bodies of `alternate`s they work, so
+        // they come round again every `cycle`.
+        let cycle = match cycle(body) {
+            0 => n,
+            passes => passes,
+        };
+        let mut pass = 0;
+        while pass < n {
+            let (before, rows) = (self.needles.clone(), self.rows);
+            for _ in 0..cycle.min(n - pass) {
+                self.passes.push(pass);
+                self.stmts(body)?;
+                self.passes.pop();
+                pass += 1;
+            }
+            if self.needles == before {
+                let skipped = (n - pass) / cycle;
+                self.rows += (self.rows - rows) * skipped;
+                pass += skipped * cycle;
+            }
+        }
+        self.widen_row(row, span);
+        Ok(())
+    }
+
+    /// Works `n` of `stitch`, a row at a time.
+    fn work(&mut self, stitch: Stitch, mut n: usize, span: Span) -> Result<(), Stop> {
+        let (consumes, produces) = (stitch.consumes(), stitch.produces());
+        // Bound-off stitches are not decreases to balance yarn overs.
+        let loses = match stitch {
+            Stitch::BindOff => 0,
+            _ => consumes.saturating_sub(produces),
+        };
+        if consumes == 0 {
+            // A yarn over or an `m1`, which makes a stitch without working
+            // one.
+            if self.needles.width == 0 {
+                return Err(Stop::Fails);
+            }
+            self.row_span = Some(self.row_span.map_or(span, |row| join(row, span)));
+            self.needles.produced += n * produces;
+            if stitch == Stitch::YarnOver {
+                self.needles.yarn_overs += n;
+            }
+            return Ok(());
+        }
+        while n > 0 {
+            let needles = &mut self.needles;
+            let left = needles.width - needles.consumed;
+            if consumes > left {
+                // No stitches, or too few for the last one.
+                return Err(Stop::Fails);
+            }
+            self.row_span = Some(self.row_span.map_or(span, |row| join(row, span)));
+            let fit = left / consumes;
+            if n < fit {
+                needles.consumed += n * consumes;
+                needles.produced += n * produces;
+                needles.decreased += n * loses;
+                return Ok(());
+            }
+            needles.consumed += fit * consumes;
+            needles.produced += fit * produces;
+            needles.decreased += fit * loses;
+            n -= fit;
+            if needles.consumed < needles.width {
+                // The rest of the row is too narrow for the next stitch.
+                return Err(Stop::Fails);
+            }
+            self.end_row();
+            let width = self.needles.width;
+            if consumes == produces && width > 0 && width.is_multiple_of(consumes) {
+                // Whole rows of the stitch leave the width as it is.
+                let per_row = width / consumes;
+                self.rows += n / per_row;
+                n %= per_row;
+            }
+        }
+        Ok(())
+    }
+
+    /// Works the rest of the row with `n` increases or decreases, all at
+    /// once however many there are.
+    fn distribute(&mut self, shaping: Shaping, n: usize, span: Span) -> Result<(), Stop> {
+        let needles = &mut self.needles;
+        let left = needles.width - needles.consumed;
+        if left == 0 || (shaping == Shaping::Decrease && n.saturating_mul(2) > left) {
+            return Err(Stop::Fails);
+        }
+        self.row_span = Some(self.row_span.map_or(span, |row| join(row, span)));
+        needles.consumed = needles.width;
+        match shaping {
+            Shaping::Increase => needles.produced += left + n,
+            Shaping::Decrease => {
+                needles.produced += left - n;
+                needles.decreased += n;
+            }
+        }
+        self.end_row();
+        Ok(())
+    }
+
+    fn end_row(&mut self) {
+        let needles = &mut self.needles;
+        let span = self.row_span.unwrap_or_default();
+        if needles.yarn_overs > 0
+            && needles.yarn_overs != needles.decreased
+            && !self.unbalanced.iter().any(|row| row.span == span)
+        {
+            self.unbalanced.push(Unbalanced {
+                row: self.rows + 1,
+                span,
+                yarn_overs: needles.yarn_overs,
+                decreases: needles.decreased,
+            });
+        }
+        needles.width = needles.produced;
+        needles.consumed = 0;
+        needles.produced = 0;
+        needles.yarn_overs = 0;
+        needles.decreased = 0;
+        self.rows += 1;
+        self.row_span = None;
+    }
+
+    /// Notes the current row if it is part worked where `before`, or the end
+    /// of the program, needs a row to have ended, and starts the next.
+    fn end_row_for(&mut self, before: Option<Span>) {
+        let needles = &self.needles;
+        if needles.consumed == 0 {
+            return;
+        }
+        let mismatch = Mismatch {
+            row: self.rows + 1,
+            span: self.row_span.unwrap_or_default(),
+            worked: needles.consumed,
+            expected: needles.width,
+            before,
+        };
+        if !self.mismatches.iter().any(|m| m.before == before) {
+            self.mismatches.push(mismatch);
+        }
+        // The rest of the row is taken as worked, to check the next rows
+        // against the stitches they should have.
+        let left = needles.width - needles.consumed;
+        self.needles.produced += left;
+        self.end_row();
+    }
+
+    /// Counts `span`, a block whose stitches all went into the current row,
+    /// in as working that row, if the row was `row` when the block started.
+    fn widen_row(&mut self, row: usize, span: Span) {
+        if row == self.rows {
+            self.row_span = self.row_span.map(|row| join(row, span));
+        }
+    }
+
+    /// The value of the count `e`, if it is known and not negative.
+    fn count(&self, e: &Expr, span: Span) -> Result<usize, Stop> {
+        match self.eval(e) {
+            Some(n) => usize::try_from(n).map_err(|_| Stop::Fails),
+            None => unverified(span, "its count is only known when the program runs"),
+        }
+    }
+
+    /// The value of `e`, if it is known before the run.
+    fn eval(&self, e: &Expr) -> Option<Value> {
+        let mut stack: Vec<Option<Value>> = Vec::new();
+        for node in e.nodes() {
+            let value = match node.kind {
+                ExprKind::Number(n) => Some(n),
+                ExprKind::Var(name) => self.needles.vars.get(&name).copied().flatten(),
+                ExprKind::StitchesTo(_) | ExprKind::Count(_) | ExprKind::Sizes(_) => None,
+                ExprKind::Call(_, ref args) => {
+                    stack.truncate(stack.len() - args.len());
+                    None
+                }
+                ExprKind::Binary(_, op, _) => {
+                    let rhs = stack.pop().expect("operator without operands");
+                    let lhs = stack.pop().expect("operator without operands");
+                    lhs.zip(rhs).and_then(|(a, b)| arithmetic(a, op, b))
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().flatten()
+    }
+}
+
+/// The smallest span covering `a` and `b`.
+fn join(a: Span, b: Span) -> Span {
+    Span::new(a.start.min(b.start), a.end.max(b.end))
+}
+
+fn unverified<T>(span: Span, reason: &'static str) -> Result<T, Stop> {
+    Err(Stop::Unverified(Unverified { span, reason }))
+}
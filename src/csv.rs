@@ -0,0 +1,88 @@
+//! The completed rows of a [`Fabric`] as CSV, for spreadsheets and
+//! tooling: a header row, then a record for each row of the piece with a
+//! field for each column of its chart.
+//!
+//! The fields are laid out as [`chart`](crate::chart) lays out cells, in
+//! stitch-number order from the chart's right edge, so a column holds the
+//! same stitch all the way up however the rows around it are shaped. A
+//! stitch is its abbreviation, such as `k` or `C4F`, followed by the name of
+//! its yarn if it was worked in one, as `k MC`; a stitch that spans several
+//! columns, such as a cable or `k2tog`, is given in the first and leaves the
+//! rest empty. Columns a row has no stitch in, such as those a short row
+//! left unworked or bound off before it, hold [`NO_STITCH`] instead, and the
+//! stitch a short row wrapped is `w&t`.
+//!
+//! Records end in CRLF and fields are quoted as [RFC 4180] says: only those
+//! with a comma, a double quote or a line break in them, with each double
+//! quote doubled.
+//!
+//! [RFC 4180]: https://www.rfc-editor.org/rfc/rfc4180
+
+use crate::chart::{self, Orientation};
+use crate::interp::Fabric;
+use crate::written;
+use std::borrow::Cow;
+
+/// The field for a column with no stitch in it.
+pub const NO_STITCH: &str = ".";
+
+/// The CSV for `fabric`, the stitches of its wrong-side rows shown as
+/// `orientation` says. The header is `row` and `side`, then a stitch number
+/// for every column; each record then gives its row number and side, `RS`
+/// or `WS`, or `rnd` for a round. A piece with no completed rows is the
+/// header alone:
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 3; k 2; p 1; bind_off 1; p 2;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// assert_eq!(
+///     knitlang::csv::csv(interp.fabric(), Default::default()),
+///     "row,side,1,2,3\r\n1,RS,k,k,p\r\n2,WS,k,k,BO\r\n"
+/// );
+/// ```
+pub fn csv(fabric: &Fabric, orientation: Orientation) -> String {
+    let rows = fabric.rows();
+    let columns = chart::columns(fabric, 0..rows.len());
+    let mut out = String::from("row,side");
+    for c in 1..=columns {
+        out.push(',');
+        out.push_str(&c.to_string());
+    }
+    out.push_str("\r\n");
+    for r in 0..rows.len() {
+        let mut fields: Vec<String> = vec![NO_STITCH.to_string(); columns];
+        for (i, cells, stitch) in chart::row_cells(fabric, r, orientation) {
+            let mut field = written::abbreviation(stitch);
+            if let Some(yarn) = fabric.yarn_at(r, i) {
+                field.push(' ');
+                field.push_str(&fabric.yarns()[yarn].name);
+            }
+            for c in cells.clone() {
+                fields[c].clear();
+            }
+            fields[cells.start] = field;
+        }
+        if let Some(c) = chart::wrap(fabric, r, orientation) {
+            fields[c] = "w&t".to_string();
+        }
+        let side = fabric.side(r).map_or("rnd", |side| side.abbreviation());
+        out.push_str(&format!("{},{side}", r + 1));
+        for field in &fields {
+            out.push(',');
+            out.push_str(&quote(field));
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// `field` as a CSV field: in double quotes, with each of its own doubled,
+/// if it has a comma, a double quote or a line break in it.
+fn quote(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
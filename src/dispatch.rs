@@ -0,0 +1,287 @@
+//! Which command line does what. [`action`] maps the parsed arguments to
+//! the [`Action`] they ask for without touching the process, so that every
+//! route can be checked without running anything, and [`run`] then takes
+//! it.
+
+use crate::{
+    animate_run, cmd_bench, cmd_check, cmd_completions, cmd_examples, cmd_explain, cmd_fmt,
+    cmd_init, cmd_man, cmd_parse, cmd_repl, cmd_run, cmd_tokens, knit_along, watch, CheckArgs, Cli,
+    CompletionArgs, ExamplesArgs, ExecArgs, ExplainArgs, Failure, FmtArgs, InitArgs, ParseArgs,
+    ReplArgs, RunArgs, Subcommand, WarningArgs,
+};
+use clap::error::ErrorKind;
+use std::io::{self, IsTerminal};
+
+/// What a command line asks for, with the arguments it takes.
+pub(crate) enum Action<'a> {
+    /// `run`, or no subcommand at all.
+    Run(&'a RunArgs, RunMode),
+    Repl(&'a ReplArgs),
+    Check(&'a CheckArgs),
+    Fmt(&'a FmtArgs),
+    Parse(&'a ParseArgs),
+    Examples(&'a ExamplesArgs),
+    Init(&'a InitArgs),
+    Explain(&'a ExplainArgs),
+    GenerateCompletions(&'a CompletionArgs),
+    GenerateMan,
+}
+
+/// What `run` does with its program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RunMode {
+    /// `--tokens`: print its tokens instead.
+    Tokens,
+    /// `--watch`: run it again whenever one of its files changes.
+    Watch,
+    /// `--bench`: time repeated runs of it.
+    Bench,
+    /// `--knit-along`: step through it a row at a time.
+    KnitAlong,
+    /// `--animate` with a terminal to draw in.
+    Animate,
+    /// The prompt, as there is no program and stdin is a terminal, or
+    /// `--interactive` was given without one.
+    Repl,
+    /// Run it, then start the prompt if `--interactive` asked for one.
+    Program,
+}
+
+/// Which of stdin and stdout are terminals, as some command lines do
+/// something else without one.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Terminals {
+    pub(crate) stdin: bool,
+    pub(crate) stdout: bool,
+}
+
+impl Terminals {
+    /// The terminals this process has.
+    pub(crate) fn detect() -> Self {
+        Terminals {
+            stdin: io::stdin().is_terminal(),
+            stdout: io::stdout().is_terminal(),
+        }
+    }
+}
+
+/// The action `cli` asks for, run with `terminals`. Options for `run` given
+/// before another subcommand are an error rather than ignored.
+pub(crate) fn action(cli: &Cli, terminals: Terminals) -> Result<Action<'_>, clap::Error> {
+    if cli.command.is_some() && cli.run.is_used() {
+        // `knitlang --tokens check x` would otherwise drop `--tokens` silently.
+        return Err(<Cli as clap::CommandFactory>::command().error(
+            ErrorKind::ArgumentConflict,
+            "options for `run` cannot be used with a subcommand; put them after it",
+        ));
+    }
+    Ok(match &cli.command {
+        None => Action::Run(&cli.run, run_mode(&cli.run, terminals)),
+        Some(Subcommand::Run(args)) => Action::Run(args, run_mode(args, terminals)),
+        Some(Subcommand::Repl(args)) => Action::Repl(args),
+        Some(Subcommand::Check(args)) => Action::Check(args),
+        Some(Subcommand::Fmt(args)) => Action::Fmt(args),
+        Some(Subcommand::Parse(args)) => Action::Parse(args),
+        Some(Subcommand::Examples(args)) => Action::Examples(args),
+        Some(Subcommand::Init(args)) => Action::Init(args),
+        Some(Subcommand::Explain(args)) => Action::Explain(args),
+        Some(Subcommand::GenerateCompletions(args)) => Action::GenerateCompletions(args),
+        Some(Subcommand::GenerateMan) => Action::GenerateMan,
+    })
+}
+
+fn run_mode(args: &RunArgs, terminals: Terminals) -> RunMode {
+    let no_program = args.eval.is_empty() && args.files.is_empty();
+    let piped = args.stdin || !terminals.stdin;
+    if args.tokens {
+        RunMode::Tokens
+    } else if args.watch {
+        RunMode::Watch
+    } else if args.bench {
+        RunMode::Bench
+    } else if args.knit_along {
+        RunMode::KnitAlong
+    } else if args.animate && terminals.stdout {
+        RunMode::Animate
+    } else if no_program && (args.interactive || !piped) {
+        RunMode::Repl
+    } else {
+        RunMode::Program
+    }
+}
+
+impl Action<'_> {
+    /// The options of the commands that run programs.
+    pub(crate) fn exec(&self) -> Option<&ExecArgs> {
+        match self {
+            Action::Run(args, _) => Some(&args.exec),
+            Action::Examples(args) => Some(&args.exec),
+            _ => None,
+        }
+    }
+
+    /// The `-W` settings of the commands that take them.
+    pub(crate) fn warnings(&self) -> Option<&WarningArgs> {
+        match self {
+            Action::Check(args) => Some(&args.warnings),
+            _ => self.exec().map(|exec| &exec.warnings),
+        }
+    }
+}
+
+/// Takes `action`.
+pub(crate) fn run(action: Action<'_>) -> Result<(), Failure> {
+    match action {
+        Action::Run(args, mode) => match mode {
+            RunMode::Tokens => cmd_tokens(args),
+            RunMode::Watch => watch(args),
+            RunMode::Bench => cmd_bench(args),
+            RunMode::KnitAlong => knit_along(args),
+            RunMode::Animate => animate_run(args),
+            RunMode::Repl => cmd_repl(&ReplArgs {
+                search: args.search.clone(),
+                output: args.exec.output.clone(),
+            }),
+            RunMode::Program => cmd_run(args),
+        },
+        Action::Repl(args) => cmd_repl(args),
+        Action::Check(args) => cmd_check(args),
+        Action::Fmt(args) => cmd_fmt(args),
+        Action::Parse(args) => cmd_parse(args),
+        Action::Examples(args) => cmd_examples(args),
+        Action::Init(args) => cmd_init(args),
+        Action::Explain(args) => cmd_explain(args),
+        Action::GenerateCompletions(args) => cmd_completions(args),
+        Action::GenerateMan => cmd_man(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const TERMINAL: Terminals = Terminals {
+        stdin: true,
+        stdout: true,
+    };
+    const PIPED: Terminals = Terminals {
+        stdin: false,
+        stdout: false,
+    };
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        <Cli as clap::Parser>::try_parse_from(
+            std::iter::once("knitlang").chain(args.iter().copied()),
+        )
+    }
+
+    /// The `run` mode `args` ask for, panicking on any other action.
+    fn mode(args: &[&str], terminals: Terminals) -> RunMode {
+        match action(&parse(args).unwrap(), terminals).unwrap() {
+            Action::Run(_, mode) => mode,
+            _ => panic!("{args:?} is not `run`"),
+        }
+    }
+
+    /// A command line and whether an action is the one it should get.
+    type Route = (&'static [&'static str], fn(&Action) -> bool);
+
+    #[test]
+    fn every_subcommand_has_its_own_action() {
+        let routes: &[Route] = &[
+            (&["repl"], |a| matches!(a, Action::Repl(_))),
+            (&["check", "a.knit"], |a| matches!(a, Action::Check(_))),
+            (&["fmt", "a.knit"], |a| matches!(a, Action::Fmt(_))),
+            (&["parse"], |a| matches!(a, Action::Parse(_))),
+            (&["examples"], |a| matches!(a, Action::Examples(_))),
+            (&["init", "hat"], |a| matches!(a, Action::Init(_))),
+            (&["explain", "K0301"], |a| matches!(a, Action::Explain(_))),
+            (&["generate-completions", "bash"], |a| {
+                matches!(a, Action::GenerateCompletions(_))
+            }),
+            (&["generate-man"], |a| matches!(a, Action::GenerateMan)),
+            (&["run", "a.knit"], |a| matches!(a, Action::Run(..))),
+            (&["a.knit"], |a| matches!(a, Action::Run(..))),
+        ];
+        for (args, routed) in routes {
+            let cli = parse(args).unwrap();
+            assert!(routed(&action(&cli, PIPED).unwrap()), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn run_without_a_subcommand_takes_the_top_level_arguments() {
+        let cli = parse(&["-e", "purl 1;", "--rows", "3"]).unwrap();
+        let Ok(Action::Run(args, RunMode::Program)) = action(&cli, TERMINAL) else {
+            panic!("not a run");
+        };
+        assert_eq!(args.eval, ["purl 1;"]);
+        assert_eq!(args.exec.rows, Some(3));
+
+        let cli = parse(&["run", "a.knit", "b.knit"]).unwrap();
+        let Ok(Action::Run(args, _)) = action(&cli, TERMINAL) else {
+            panic!("not a run");
+        };
+        assert_eq!(
+            args.files,
+            [PathBuf::from("a.knit"), PathBuf::from("b.knit")]
+        );
+    }
+
+    #[test]
+    fn run_options_before_a_subcommand_are_a_conflict() {
+        for args in [
+            &["--tokens", "check", "a.knit"][..],
+            &["-e", "purl 1;", "repl"],
+        ] {
+            let Err(e) = action(&parse(args).unwrap(), PIPED) else {
+                panic!("{args:?} was accepted");
+            };
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict, "{args:?}");
+        }
+        // After it, they belong to `run` itself.
+        assert_eq!(mode(&["run", "--tokens", "a.knit"], PIPED), RunMode::Tokens);
+    }
+
+    #[test]
+    fn run_modes_follow_the_flags_and_the_terminals() {
+        assert_eq!(mode(&["--tokens", "a.knit"], TERMINAL), RunMode::Tokens);
+        assert_eq!(mode(&["--watch", "a.knit"], TERMINAL), RunMode::Watch);
+        assert_eq!(mode(&["--bench", "a.knit"], PIPED), RunMode::Bench);
+        assert_eq!(
+            mode(&["--knit-along", "a.knit"], TERMINAL),
+            RunMode::KnitAlong
+        );
+        // Animating needs somewhere to draw.
+        assert_eq!(mode(&["--animate", "a.knit"], TERMINAL), RunMode::Animate);
+        assert_eq!(mode(&["--animate", "a.knit"], PIPED), RunMode::Program);
+        // With nothing to run, a terminal gets the prompt and a pipe is read.
+        assert_eq!(mode(&[], TERMINAL), RunMode::Repl);
+        assert_eq!(mode(&["run"], TERMINAL), RunMode::Repl);
+        assert_eq!(mode(&[], PIPED), RunMode::Program);
+        assert_eq!(mode(&["--stdin"], TERMINAL), RunMode::Program);
+        assert_eq!(mode(&["-i"], PIPED), RunMode::Repl);
+        assert_eq!(mode(&["-i", "a.knit"], TERMINAL), RunMode::Program);
+    }
+
+    #[test]
+    fn only_commands_that_run_programs_take_their_options() {
+        let exec = |args: &[&str]| {
+            action(&parse(args).unwrap(), PIPED)
+                .unwrap()
+                .exec()
+                .is_some()
+        };
+        assert!(exec(&["a.knit"]));
+        assert!(exec(&["examples", "hello"]));
+        assert!(!exec(&["check", "a.knit"]));
+        assert!(!exec(&["repl"]));
+
+        let cli = parse(&["check", "-W", "error", "a.knit"]).unwrap();
+        let action = action(&cli, PIPED).unwrap();
+        assert_eq!(action.warnings().unwrap().warnings.len(), 1);
+        let cli = parse(&["fmt", "a.knit"]).unwrap();
+        assert!(super::action(&cli, PIPED).unwrap().warnings().is_none());
+    }
+}
@@ -0,0 +1,133 @@
+//! What a published pattern says, before it is written in any one form:
+//! its title and details from the `meta` block, then its sections, each a
+//! heading over the notes from before the cast on, the written instructions
+//! or the chart. [`html`](crate::html) and [`markdown`](crate::markdown)
+//! each write a [`Document`], so the two always have the same content in
+//! the same order, and only how it is marked up differs.
+
+use crate::interp::Fabric;
+use crate::written::{self, Line};
+
+/// The title of a pattern whose `meta` block gives none.
+pub const UNTITLED: &str = "Knitting pattern";
+
+/// The content of a published pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    /// The pattern's `meta` title, or [`UNTITLED`].
+    pub title: String,
+    /// The other entries of the `meta` block, each a label and its value:
+    /// the author, yarn, needle and gauge first, under labels like those of
+    /// [`written::header`], then the keys it does not know, in the order
+    /// they were written.
+    pub details: Vec<(String, String)>,
+    /// The sections that have anything in them, in order.
+    pub sections: Vec<Section>,
+}
+
+/// A section of a [`Document`] under its own heading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub heading: &'static str,
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    /// The notes from before anything was cast on, one paragraph each.
+    Notes(Vec<String>),
+    /// The lines of the written instructions, the notes among them.
+    Instructions(Vec<Line>),
+    /// The chart of the completed rows, which each form draws its own way.
+    Chart,
+}
+
+impl Body {
+    /// A name for the kind of section, such as `notes`, for forms that mark
+    /// sections up by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Body::Notes(_) => "notes",
+            Body::Instructions(_) => "instructions",
+            Body::Chart => "chart",
+        }
+    }
+}
+
+impl Document {
+    /// The document for `fabric`. Sections it would have nothing in, such
+    /// as the chart of a piece with no completed rows, are left out:
+    ///
+    /// ```
+    /// use knitlang::document::{Body, Document};
+    ///
+    /// let src = "note \"Use 4mm needles\"; cast_on 4; k 4;";
+    /// let stmts = knitlang::parse_src(src).unwrap();
+    /// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+    /// interp.run(&stmts).unwrap();
+    /// let document = Document::new(interp.fabric());
+    /// assert_eq!(document.title, "Knitting pattern");
+    /// let headings: Vec<_> = document.sections.iter().map(|s| s.heading).collect();
+    /// assert_eq!(headings, ["Notes", "Instructions", "Chart"]);
+    /// assert_eq!(document.sections[0].body, Body::Notes(vec!["Use 4mm needles".into()]));
+    /// ```
+    pub fn new(fabric: &Fabric) -> Self {
+        let meta = fabric.meta();
+        let title = match meta.and_then(|meta| meta.title.as_ref()) {
+            Some(title) => title.to_string(),
+            None => UNTITLED.to_string(),
+        };
+        let mut details = Vec::new();
+        if let Some(meta) = meta {
+            let labelled = [
+                ("Author", &meta.author),
+                ("Yarn", &meta.yarn),
+                ("Needle", &meta.needle),
+                ("Gauge", &meta.gauge),
+            ];
+            for (label, value) in labelled {
+                if let Some(value) = value {
+                    details.push((label.to_string(), value.to_string()));
+                }
+            }
+            for (key, value) in &meta.other {
+                details.push((key.clone(), value.to_string()));
+            }
+        }
+
+        // The notes before the first step are the pattern's preamble.
+        let mut lines = written::lines(fabric);
+        let preamble = lines
+            .iter()
+            .take_while(|line| matches!(line, Line::Note(_)))
+            .count();
+        let notes: Vec<String> = lines
+            .drain(..preamble)
+            .map(|line| line.text().to_string())
+            .collect();
+        let mut sections = Vec::new();
+        if !notes.is_empty() {
+            sections.push(Section {
+                heading: "Notes",
+                body: Body::Notes(notes),
+            });
+        }
+        if !lines.is_empty() {
+            sections.push(Section {
+                heading: "Instructions",
+                body: Body::Instructions(lines),
+            });
+        }
+        if !fabric.rows().is_empty() {
+            sections.push(Section {
+                heading: "Chart",
+                body: Body::Chart,
+            });
+        }
+        Document {
+            title,
+            details,
+            sections,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! What a running program does, as a stream of events for tools that follow
+//! along, such as a view of the needles that animates as rows are knitted.
+//! Attach a sink with [`Interpreter::set_event_sink`]; without one the
+//! interpreter does no work to describe what it is doing.
+//!
+//! [`Interpreter::set_event_sink`]: crate::Interpreter::set_event_sink
+
+use crate::interp::Value;
+use crate::lexer::Span;
+use serde_json::json;
+use std::io::{self, Write};
+
+/// One thing a running program did, in the order it did it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event<'a> {
+    /// A statement is about to run. Statements inside a `repeat` start once
+    /// per pass, after the `repeat` itself.
+    Statement { kind: &'static str, span: Span },
+    /// `cast_on` or `knit` set a variable; `old` is `None` if it was new.
+    Assign {
+        name: &'a str,
+        old: Option<Value>,
+        new: Value,
+    },
+    /// Row `index` (counting from 0) is complete and left `width` stitches on
+    /// the needles for the next one.
+    Row { index: usize, width: usize },
+    /// `purl` printed `text`, or `chart` a chart, without its last newline.
+    Output { text: &'a str },
+    /// An `expect_stitches?` checkpoint found the wrong stitch count, and
+    /// the run went on.
+    Warning { message: &'a str, span: Span },
+    /// The run failed with this runtime error.
+    Error { message: &'a str, span: Span },
+}
+
+impl Event<'_> {
+    /// The event as a JSON object whose `"event"` names what happened.
+    pub fn to_json(&self) -> serde_json::Value {
+        match *self {
+            Event::Statement { kind, span } => {
+                json!({ "event": "statement", "kind": kind, "span": span.to_json() })
+            }
+            Event::Assign { name, old, new } => {
+                json!({ "event": "assign", "name": name, "old": old, "new": new })
+            }
+            Event::Row { index, width } => {
+                json!({ "event": "row", "index": index, "width": width })
+            }
+            Event::Output { text } => json!({ "event": "output", "text": text }),
+            Event::Warning { message, span } => {
+                json!({ "event": "warning", "message": message, "span": span.to_json() })
+            }
+            Event::Error { message, span } => {
+                json!({ "event": "error", "message": message, "span": span.to_json() })
+            }
+        }
+    }
+}
+
+/// Receives the events of a run as they happen. Any `FnMut(&Event)` is one.
+pub trait EventSink {
+    fn event(&mut self, event: &Event<'_>);
+
+    /// Called when the interpreter's output is flushed.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&Event<'_>)> EventSink for F {
+    fn event(&mut self, event: &Event<'_>) {
+        self(event)
+    }
+}
+
+/// Writes each event to a writer as one line of JSON. The first write error
+/// is kept and returned by the next `flush`; events after it are dropped.
+pub struct JsonLines<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, error: None }
+    }
+}
+
+impl<W: Write> EventSink for JsonLines<W> {
+    fn event(&mut self, event: &Event<'_>) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", event.to_json()) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
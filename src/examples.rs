@@ -0,0 +1,107 @@
+//! The examples `knitlang examples` runs: those compiled into the binary,
+//! and the `.knit` files in an `examples` directory along the search path.
+
+use crate::search::SearchPath;
+use knitlang::suggest::closest_matches;
+use std::fs;
+use std::path::Path;
+
+const EXAMPLES_DIR: &str = "examples";
+
+/// Examples compiled into the binary, so `--example` works from any directory.
+const EMBEDDED_EXAMPLES: &[(&str, &str)] = &[
+    ("cable_panel", include_str!("../examples/cable_panel.knit")),
+    ("hello", include_str!("../examples/hello.knit")),
+    ("swatch", include_str!("../examples/swatch.knit")),
+];
+
+/// Names of the embedded examples and the `.knit` files in the `examples`
+/// directory of each search path entry, sorted and without duplicates. Missing
+/// directories just contribute nothing.
+pub(crate) fn example_names(search: &SearchPath) -> Vec<String> {
+    let mut names: Vec<String> = EMBEDDED_EXAMPLES
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let dir = Path::new(EXAMPLES_DIR);
+    let entries = search
+        .candidates(dir)
+        .flat_map(|dir| fs::read_dir(dir).into_iter().flatten().flatten());
+    for entry in entries {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "knit") {
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                names.push(stem.to_string());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Loads the example called `name`, preferring the embedded copy and falling
+/// back to `examples/name.knit` along the search path. Returns the name to use
+/// in diagnostics along with the source, or an explanation of what is
+/// available.
+pub(crate) fn load_example(search: &SearchPath, name: &str) -> Result<(String, String), String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(format!(
+            "invalid example name `{name}`: names must not contain path separators or `..`"
+        ));
+    }
+    let dir = Path::new(EXAMPLES_DIR);
+    let path = dir.join(format!("{name}.knit"));
+    if let Some((_, src)) = EMBEDDED_EXAMPLES.iter().find(|(n, _)| *n == name) {
+        return Ok((path.display().to_string(), src.to_string()));
+    }
+    if let Ok(path) = search.resolve(&path) {
+        return fs::read_to_string(&path)
+            .map(|src| (path.display().to_string(), src))
+            .map_err(|e| format!("could not read {}: {e}", path.display()));
+    }
+    let names = example_names(search);
+    let mut message = format!("no example named `{name}` in {}", dir.display());
+    if names.is_empty() {
+        message.push_str("\nno examples are available");
+    } else {
+        message.push_str(&format!("\navailable examples: {}", names.join(", ")));
+    }
+    if let Some(suggestion) = closest_matches(name, names.iter().map(String::as_str)).first() {
+        message.push_str(&format!("\ndid you mean `{suggestion}`?"));
+    }
+    Err(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_example_loads_and_runs() {
+        let search = SearchPath::new(&[]);
+        let names = example_names(&search);
+        for (name, _) in EMBEDDED_EXAMPLES {
+            assert!(names.iter().any(|n| n == name), "{name} is not listed");
+        }
+        for name in &names {
+            let (origin, src) = load_example(&search, name).unwrap();
+            assert_eq!(origin, format!("examples/{name}.knit"));
+            let stmts = knitlang::parse_src(&src).unwrap_or_else(|e| panic!("{name}: {e:?}"));
+            let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+            interp
+                .run(&stmts)
+                .unwrap_or_else(|e| panic!("{name}: {e:?}"));
+        }
+    }
+
+    #[test]
+    fn missing_examples_list_the_ones_there_are() {
+        let search = SearchPath::new(&[]);
+        let message = load_example(&search, "helo").unwrap_err();
+        assert!(message.starts_with("no example named `helo` in examples\n"));
+        assert!(message.contains(&example_names(&search).join(", ")));
+        assert!(message.ends_with("\ndid you mean `hello`?"));
+        assert!(load_example(&search, "../hello").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! A C interface for embedding the interpreter, enabled by the `ffi` feature.
+//! `include/knitlang.h` is generated from this file by cbindgen.
+//!
+//! Ownership rules:
+//! - A `KnitInterp` is created by `knit_interp_new` and must be released with
+//!   `knit_interp_free`, exactly once.
+//! - Strings passed in are NUL-terminated UTF-8 and are only read during the
+//!   call; the caller keeps ownership.
+//! - Strings handed out (`knit_last_error`, the output callback's text) are
+//!   owned by the library and only valid until the next call on the same
+//!   interpreter. Copy them to keep them.
+//!
+//! Every entry point catches panics, so none unwinds into C; an internal error
+//! is reported as `KNIT_ERR_INTERNAL`.
+
+use crate::parser::DEFAULT_MAX_NESTING;
+use crate::{execute, line_col, Interpreter, KnitError};
+use std::cell::Cell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io::{self, LineWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+
+/// The program ran to the end or to `bind_off`.
+pub const KNIT_OK: c_int = 0;
+/// The program failed while running.
+pub const KNIT_ERR_RUNTIME: c_int = 1;
+/// The source did not lex or parse; nothing ran.
+pub const KNIT_ERR_PARSE: c_int = 2;
+/// A pointer was NULL or a string was not valid UTF-8.
+pub const KNIT_ERR_ARGUMENT: c_int = 3;
+/// The library hit a bug. The interpreter may be left in any state but is
+/// still safe to free.
+pub const KNIT_ERR_INTERNAL: c_int = 4;
+
+/// Receives program output. `text` is one line, including its newline, as
+/// NUL-terminated UTF-8 that is only valid during the call; `user_data` is the
+/// pointer given to `knit_set_output`.
+pub type KnitOutputFn = Option<unsafe extern "C" fn(user_data: *mut c_void, text: *const c_char)>;
+
+/// An interpreter session. Variables and the fabric carry over from one
+/// `knit_run_source` call to the next.
+pub struct KnitInterp {
+    interp: Interpreter,
+    output: Rc<Cell<(KnitOutputFn, *mut c_void)>>,
+    last_error: Option<CString>,
+}
+
+/// Passes output on to the host's callback, if it has set one.
+struct HostOutput {
+    output: Rc<Cell<(KnitOutputFn, *mut c_void)>>,
+}
+
+impl Write for HostOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let (Some(callback), user_data) = self.output.get() {
+            // Program output never contains NUL, so this only fails on a bug.
+            let text = CString::new(buf).map_err(io::Error::other)?;
+            // SAFETY: the host promised `callback` accepts `user_data` when it
+            // passed both to `knit_set_output`.
+            unsafe { callback(user_data, text.as_ptr()) };
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `f`, turning a panic into `on_panic`.
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// Creates an interpreter that discards output until `knit_set_output` is
+/// called. Free it with `knit_interp_free`. Returns NULL only on an internal
+/// error.
+#[no_mangle]
+pub extern "C" fn knit_interp_new() -> *mut KnitInterp {
+    guard(ptr::null_mut(), || {
+        let output = Rc::new(Cell::new((None, ptr::null_mut())));
+        let host = HostOutput {
+            output: Rc::clone(&output),
+        };
+        let interp = KnitInterp {
+            interp: Interpreter::with_output(Box::new(LineWriter::new(host))),
+            output,
+            last_error: None,
+        };
+        Box::into_raw(Box::new(interp))
+    })
+}
+
+/// Frees an interpreter from `knit_interp_new`. NULL is ignored.
+///
+/// # Safety
+///
+/// `interp` must be NULL or a pointer from `knit_interp_new` that has not been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn knit_interp_free(interp: *mut KnitInterp) {
+    if !interp.is_null() {
+        guard((), || drop(Box::from_raw(interp)));
+    }
+}
+
+/// Sends `purl` output to `callback` one line at a time, passing `user_data`
+/// along; a NULL callback discards output again.
+///
+/// # Safety
+///
+/// `interp` must be NULL or a live interpreter, and `callback` must be safe to
+/// call with `user_data` for as long as it is set.
+#[no_mangle]
+pub unsafe extern "C" fn knit_set_output(
+    interp: *mut KnitInterp,
+    callback: KnitOutputFn,
+    user_data: *mut c_void,
+) {
+    if let Some(interp) = interp.as_mut() {
+        interp.output.set((callback, user_data));
+    }
+}
+
+/// Runs the NUL-terminated UTF-8 program `src`, returning `KNIT_OK` or one of
+/// the `KNIT_ERR_*` codes. On failure `knit_last_error` describes it.
+///
+/// # Safety
+///
+/// `interp` must be NULL or a live interpreter, and `src` NULL or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn knit_run_source(interp: *mut KnitInterp, src: *const c_char) -> c_int {
+    let Some(interp) = interp.as_mut() else {
+        return KNIT_ERR_ARGUMENT;
+    };
+    interp.last_error = None;
+    let Some(src) = utf8_arg(interp, src, "source") else {
+        return KNIT_ERR_ARGUMENT;
+    };
+    let result = guard(None, || {
+        Some(execute(src, &mut interp.interp, DEFAULT_MAX_NESTING))
+    });
+    let (code, message) = match result {
+        Some(Ok(_)) => return KNIT_OK,
+        Some(Err(e @ KnitError::Runtime(_))) => (KNIT_ERR_RUNTIME, located(src, &e)),
+        Some(Err(e)) => (KNIT_ERR_PARSE, located(src, &e)),
+        None => (KNIT_ERR_INTERNAL, "internal error".to_string()),
+    };
+    set_error(interp, message);
+    code
+}
+
+/// Describes the last failed call on `interp` as NUL-terminated UTF-8, or
+/// returns NULL if it succeeded. The string belongs to the interpreter and is
+/// valid until the next call on it.
+///
+/// # Safety
+///
+/// `interp` must be NULL or a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn knit_last_error(interp: *const KnitInterp) -> *const c_char {
+    match interp
+        .as_ref()
+        .and_then(|interp| interp.last_error.as_ref())
+    {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Stores the value of variable `name` in `*out` and returns true, or returns
+/// false and leaves `*out` alone if there is no such variable.
+///
+/// # Safety
+///
+/// `interp` must be NULL or a live interpreter, `name` NULL or a
+/// NUL-terminated string, and `out` NULL or valid for writing.
+#[no_mangle]
+pub unsafe extern "C" fn knit_get_var(
+    interp: *mut KnitInterp,
+    name: *const c_char,
+    out: *mut i64,
+) -> bool {
+    let Some(interp) = interp.as_mut() else {
+        return false;
+    };
+    interp.last_error = None;
+    let Some(name) = utf8_arg(interp, name, "variable name") else {
+        return false;
+    };
+    if out.is_null() {
+        set_error(interp, "output pointer is NULL".to_string());
+        return false;
+    }
+    match interp.interp.get_var(name) {
+        Some(value) => {
+            *out = value;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Reads a string argument, recording why it is unusable if it is.
+unsafe fn utf8_arg<'a>(
+    interp: &mut KnitInterp,
+    text: *const c_char,
+    what: &str,
+) -> Option<&'a str> {
+    if text.is_null() {
+        set_error(interp, format!("{what} is NULL"));
+        return None;
+    }
+    match CStr::from_ptr(text).to_str() {
+        Ok(text) => Some(text),
+        Err(e) => {
+            set_error(interp, format!("{what} is not valid UTF-8: {e}"));
+            None
+        }
+    }
+}
+
+/// `line:column: message`, for errors the host shows without the source.
+fn located(src: &str, error: &KnitError) -> String {
+    let (line, col) = line_col(src, error.span().start);
+    format!("{line}:{col}: {error}")
+}
+
+fn set_error(interp: &mut KnitInterp, message: String) {
+    // Messages never contain NUL in practice; strip any rather than lose the
+    // whole message.
+    interp.last_error = CString::new(message.replace('\0', "")).ok();
+}
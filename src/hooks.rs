@@ -0,0 +1,32 @@
+//! Hooks that watch a program run statement by statement and can step in,
+//! for debuggers, custom execution budgets or a teaching tool that pauses
+//! between rows. Unlike an [`EventSink`](crate::EventSink), a hook runs
+//! before each statement and decides whether it runs at all.
+
+use crate::ast::Stmt;
+use crate::interp::Interpreter;
+
+/// What an [`ExecHook`] wants done with the statement about to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    /// Leave this statement out (a `repeat` with its whole body) and carry
+    /// on with the next.
+    Skip,
+    /// End the run before this statement, as a `bind_off` there would.
+    Stop,
+    /// Stop the run with a runtime error at this statement, with this
+    /// message.
+    Abort(String),
+}
+
+/// Called around every statement, including those inside `repeat` bodies.
+/// Attach one with [`Interpreter::set_hook`].
+pub trait ExecHook {
+    /// Called before `stmt` runs, with the interpreter as it stands.
+    fn before_stmt(&mut self, stmt: &Stmt, interp: &Interpreter) -> HookAction;
+
+    /// Called once `stmt` has run without an error. A `repeat` finishes
+    /// after the last statement in its body.
+    fn after_stmt(&mut self, _stmt: &Stmt, _interp: &Interpreter) {}
+}
@@ -0,0 +1,109 @@
+//! A pattern as one self-contained HTML page, for publishing on the web:
+//! its [`Document`], with a header of its `meta` block, the notes from
+//! before anything was cast on, its [written instructions](crate::written)
+//! and its [chart](crate::chart) as an inline SVG. The page needs nothing but
+//! itself, with its style sheet in a `<style>` element and no scripts, and
+//! its sections are the semantic elements a printed page keeps apart:
+//! `<header>`, `<section>`s of the `<article>`, and a `<figure>` for the
+//! chart.
+//!
+//! Every string from the pattern, such as its title or a note, is escaped,
+//! so `<` in a title shows as itself rather than starting a tag.
+
+use crate::chart::{self, escape, Style};
+use crate::document::{Body, Document};
+use crate::interp::Fabric;
+use crate::written::Line;
+use std::fmt::Write;
+
+/// The style sheet of every page: wide enough margins to read comfortably
+/// on screen, and none of them on paper.
+const STYLE: &str = "\
+body { font-family: Georgia, 'Times New Roman', serif; line-height: 1.5; \
+max-width: 42em; margin: 2em auto; padding: 0 1em; color: #222; }
+h1, h2 { font-family: Helvetica, Arial, sans-serif; line-height: 1.2; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 0.25em 1em; }
+dt { font-weight: bold; }
+dd { margin: 0; }
+.instructions p { margin: 0.25em 0; }
+.instructions .note { font-style: italic; }
+figure { margin: 0; }
+figure svg { max-width: 100%; height: auto; }
+@media print {
+  body { max-width: none; margin: 0; }
+  section, figure { break-inside: avoid; }
+}
+";
+
+/// The page for `fabric`, its chart drawn with `style`. A pattern without a
+/// `meta` title is titled [`UNTITLED`](crate::document::UNTITLED), and one
+/// without completed rows has no chart:
+///
+/// ```
+/// let src = "meta { title = \"Socks <3\"; } note \"Use 2.5mm needles\"; cast_on 4; k 4;";
+/// let stmts = knitlang::parse_src(src).unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let html = knitlang::html::html(interp.fabric(), &Default::default());
+/// assert!(html.starts_with("<!DOCTYPE html>\n"));
+/// assert!(html.contains("<h1>Socks &lt;3</h1>\n"));
+/// assert!(html.contains("<p>Use 2.5mm needles</p>\n"));
+/// assert!(html.contains("<p>Row 1 (RS): k4.</p>\n"));
+/// assert!(html.contains("<figure>\n<svg "));
+/// ```
+pub fn html(fabric: &Fabric, style: &Style) -> String {
+    let document = Document::new(fabric);
+    let title = escape(&document.title);
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(out, "<title>{title}</title>");
+    let _ = write!(
+        out,
+        "<style>\n{STYLE}</style>\n</head>\n<body>\n<article>\n"
+    );
+
+    out.push_str("<header>\n");
+    let _ = writeln!(out, "<h1>{title}</h1>");
+    if !document.details.is_empty() {
+        out.push_str("<dl>\n");
+        for (label, value) in &document.details {
+            let _ = writeln!(out, "<dt>{}</dt><dd>{}</dd>", escape(label), escape(value));
+        }
+        out.push_str("</dl>\n");
+    }
+    out.push_str("</header>\n");
+
+    for section in &document.sections {
+        let _ = writeln!(
+            out,
+            "<section class=\"{}\">\n<h2>{}</h2>",
+            section.body.kind(),
+            escape(section.heading)
+        );
+        match &section.body {
+            Body::Notes(notes) => {
+                for note in notes {
+                    let _ = writeln!(out, "<p>{}</p>", escape(note));
+                }
+            }
+            Body::Instructions(lines) => {
+                for line in lines {
+                    let _ = match line {
+                        Line::Note(text) => {
+                            writeln!(out, "<p class=\"note\">{}</p>", escape(text))
+                        }
+                        Line::Step(text) => writeln!(out, "<p>{}</p>", escape(text)),
+                    };
+                }
+            }
+            Body::Chart => {
+                out.push_str("<figure>\n");
+                out.push_str(&chart::svg(fabric, style));
+                out.push_str("</figure>\n");
+            }
+        }
+        out.push_str("</section>\n");
+    }
+    out.push_str("</article>\n</body>\n</html>\n");
+    out
+}
@@ -0,0 +1,578 @@
+//! `use "file.knit";`, which runs the statements of another file as part of
+//! a program. The interpreter never reads files, so an [`Importer`] loads
+//! them before the run: it splits each program at its `use` statements and
+//! puts the file each one names in its place, giving the [`Part`]s of every
+//! file in the order they run, one after another against one interpreter.
+//!
+//! A file runs where it is first imported, after the statements before the
+//! `use` and before those after it, and the files it imports run at their
+//! own `use`s in the same way. Importing a file that has already run, from
+//! anywhere, does nothing, so a program sees the variables, yarns and
+//! stitches of a file once however many of its files import it. A file that
+//! imports itself, directly or through others, is an error naming each file
+//! in the loop.
+//!
+//! # Names
+//!
+//! Every imported file keeps its variables under a namespace of its own,
+//! named after the file or what it is first imported `as`, so the `width`
+//! of `edgings.knit` runs as `edgings.width` and never meets the `width` of
+//! another file. Programs themselves have no namespace. Yarns and stitch
+//! markers belong to the fabric rather than a file, so they are shared, as
+//! is the size being knitted and with it the variable `SIZE`.
+//!
+//! A file reads a variable by a plain name such as `width`:
+//!
+//! - if the file casts on or knits `width` anywhere, it is that variable,
+//!   so a file's own variables shadow those of its imports;
+//! - otherwise, if exactly one of the files it imports without `as` defines
+//!   `width`, it is that file's;
+//! - if more than one does, it is an error listing them, and the file must
+//!   say which it means with a qualified name;
+//! - otherwise it is the file's own, and reading it before it has a value is
+//!   an error when the program runs.
+//!
+//! A qualified name such as `edgings.width` reads `width` from the file the
+//! reading file imports `as edgings`, or which it imports without `as` and
+//! has the namespace `edgings`, and it is an error if that file does not
+//! define `width`. Only the files a file imports itself are searched, not
+//! the ones they import in turn.
+//!
+//! ```
+//! use knitlang::imports::{Importer, Source};
+//!
+//! let mut importer = Importer::new(|_, path| match path {
+//!     "rib.knit" => Ok(Source::new("rib.knit", "cast_on rib = 2;")),
+//!     _ => Err(format!("no file {path}")),
+//! });
+//! let src = "use \"rib.knit\" as r;\npurl r.rib;\nuse \"rib.knit\";\n";
+//! let stmts = knitlang::parse_src(src).unwrap();
+//! let parts = importer.program(Source::new("main.knit", src), stmts).unwrap();
+//! let files: Vec<_> = parts.iter().map(|part| importer.files()[part.file].origin.as_str()).collect();
+//! assert_eq!(files, ["rib.knit", "main.knit"]);
+//! assert_eq!(knitlang::ast::format_program(&parts[1].stmts), "purl r.rib;\n");
+//! ```
+
+use crate::ast::{Expr, ExprKind, NotePart, Stmt, StmtKind};
+use crate::codes::Code;
+use crate::interp::SIZE_VARIABLE;
+use crate::lexer::Span;
+use crate::parser::{ParseError, Parser, DEFAULT_MAX_NESTING};
+use crate::resolve;
+use crate::suggest::did_you_mean;
+use crate::symbol::Symbol;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+/// A file as the loader of an [`Importer`] finds it.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// What tells files apart however a `use` names them, such as their
+    /// canonical path. Files with the same key are the same file.
+    pub key: String,
+    /// The name diagnostics give the file.
+    pub origin: String,
+    pub src: Rc<str>,
+}
+
+impl Source {
+    /// A source whose key is its origin.
+    pub fn new(origin: &str, src: &str) -> Self {
+        Self {
+            key: origin.to_string(),
+            origin: origin.to_string(),
+            src: src.into(),
+        }
+    }
+}
+
+/// A file an [`Importer`] has loaded, with its statements, `use`s included
+/// and variables renamed to the names they run as.
+#[derive(Debug)]
+pub struct File {
+    pub origin: String,
+    pub src: Rc<str>,
+    pub stmts: Vec<Stmt>,
+    /// What the file's variables are qualified with, such as `edgings` for
+    /// `edgings.width`; empty for a program.
+    pub namespace: String,
+    /// The variables the file casts on or knits, by their plain names.
+    defines: BTreeSet<Symbol>,
+    /// The files it imports without `as`, which plain names are looked up
+    /// in.
+    imports: Vec<usize>,
+    /// The files its qualified names can name, by qualifier.
+    qualifiers: BTreeMap<Symbol, usize>,
+}
+
+/// Statements of one file that run together, with no `use` among them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    /// The file they are from, as an index into [`Importer::files`].
+    pub file: usize,
+    pub stmts: Vec<Stmt>,
+}
+
+/// A file that could not be imported, or failed to parse, together with the
+/// name and text of the file the error points into.
+#[derive(Debug)]
+pub struct ImportError {
+    pub origin: String,
+    pub src: Rc<str>,
+    pub error: ParseError,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.origin, self.error)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Finds the file that a `use` in the file with the first origin names by
+/// the second path.
+type Load<'a> = Box<dyn FnMut(&str, &str) -> Result<Source, String> + 'a>;
+
+/// Resolves the `use` statements of programs, loading every file they
+/// import once.
+pub struct Importer<'a> {
+    load: Load<'a>,
+    files: Vec<File>,
+    /// The file each key was last loaded as.
+    keys: HashMap<String, usize>,
+    /// Files part way through being imported, each importing the next.
+    loading: Vec<usize>,
+    max_nesting: usize,
+}
+
+/// Where a `use` is and what it imports.
+struct Use<'s> {
+    path: &'s str,
+    alias: Option<Symbol>,
+    span: Span,
+}
+
+impl<'a> Importer<'a> {
+    /// An importer that finds files with `load(importer, path)`, given the
+    /// origin of the file with the `use` and the path it names. The loader
+    /// returns a message, such as where it looked, for a file it cannot find.
+    pub fn new(load: impl FnMut(&str, &str) -> Result<Source, String> + 'a) -> Self {
+        Self {
+            load: Box::new(load),
+            files: Vec::new(),
+            keys: HashMap::new(),
+            loading: Vec::new(),
+            max_nesting: DEFAULT_MAX_NESTING,
+        }
+    }
+
+    /// Parses imported files with this nesting limit; see
+    /// [`Parser::set_max_nesting`].
+    pub fn set_max_nesting(&mut self, limit: usize) {
+        self.max_nesting = limit;
+    }
+
+    /// Every file loaded so far, programs and the files they import, in the
+    /// order they were loaded.
+    pub fn files(&self) -> &[File] {
+        &self.files
+    }
+
+    /// The parts of `stmts`, parsed from `source`, and of every file it
+    /// imports, in the order they run. A program runs whole even if an
+    /// earlier one imported it, but importing it again afterwards does
+    /// nothing.
+    pub fn program(&mut self, source: Source, stmts: Vec<Stmt>) -> Result<Vec<Part>, ImportError> {
+        let file = self.add(source, stmts);
+        let mut parts = Vec::new();
+        self.expand(file, &mut parts)?;
+        Ok(parts)
+    }
+
+    /// Adds a program whose statements are run one at a time as they are
+    /// parsed rather than split into parts, so its `use`s are resolved one
+    /// at a time with [`Importer::import`] and its other statements with
+    /// [`Importer::resolve`]. Returns its index in [`Importer::files`].
+    pub fn add(&mut self, source: Source, stmts: Vec<Stmt>) -> usize {
+        self.add_file(source, stmts, String::new())
+    }
+
+    /// The parts of the file that `use path;`, or `use path as alias;`, at
+    /// `span` in file `file` imports, and of every file it imports in turn;
+    /// none if it has run already.
+    pub fn import(
+        &mut self,
+        file: usize,
+        path: &str,
+        alias: Option<Symbol>,
+        span: Span,
+    ) -> Result<Vec<Part>, ImportError> {
+        let mut parts = Vec::new();
+        self.loading.push(file);
+        let result = self.import_into(file, &Use { path, alias, span }, &mut parts);
+        self.loading.pop();
+        result.map(|()| parts)
+    }
+
+    /// Renames the variables of `stmt`, the next statement of file `file`,
+    /// to the names they run as. Only the statements so far decide which
+    /// variables are the file's own, so a variable it casts on later does
+    /// not shadow one of an import that it reads before.
+    pub fn resolve(&mut self, file: usize, stmt: &mut Stmt) -> Result<(), ImportError> {
+        let mut defines = BTreeSet::new();
+        collect_defines(std::slice::from_ref(stmt), &mut defines);
+        self.files[file].defines.extend(defines);
+        self.resolve_stmts(file, std::slice::from_mut(stmt))?;
+        resolve::number_slots(std::slice::from_mut(stmt));
+        Ok(())
+    }
+
+    fn add_file(&mut self, source: Source, stmts: Vec<Stmt>, namespace: String) -> usize {
+        let file = self.files.len();
+        let mut defines = BTreeSet::new();
+        collect_defines(&stmts, &mut defines);
+        self.keys.insert(source.key, file);
+        self.files.push(File {
+            origin: source.origin,
+            src: source.src,
+            stmts,
+            namespace,
+            defines,
+            imports: Vec::new(),
+            qualifiers: BTreeMap::new(),
+        });
+        file
+    }
+
+    /// Pushes the parts of file `file` onto `parts`, with the files it
+    /// imports in place of its `use`s. Every import is loaded before the
+    /// file's own names are resolved, as any of them may define one.
+    fn expand(&mut self, file: usize, parts: &mut Vec<Part>) -> Result<(), ImportError> {
+        self.loading.push(file);
+        let mut stmts = self.files[file].stmts.clone();
+        let mut imported = Vec::new();
+        let mut result = Ok(());
+        for stmt in &stmts {
+            let StmtKind::Use(path, alias) = &stmt.kind else {
+                continue;
+            };
+            let mut parts = Vec::new();
+            let import = Use {
+                path,
+                alias: *alias,
+                span: stmt.span,
+            };
+            result = self.import_into(file, &import, &mut parts);
+            if result.is_err() {
+                break;
+            }
+            imported.push(parts);
+        }
+        self.loading.pop();
+        result?;
+        self.resolve_stmts(file, &mut stmts)?;
+        resolve::number_slots(&mut stmts);
+        let mut imported = imported.into_iter();
+        let mut run = Vec::new();
+        for stmt in &stmts {
+            if !matches!(stmt.kind, StmtKind::Use(..)) {
+                run.push(stmt.clone());
+                continue;
+            }
+            if !run.is_empty() {
+                let stmts = std::mem::take(&mut run);
+                parts.push(Part { file, stmts });
+            }
+            parts.extend(imported.next().expect("parts for every use"));
+        }
+        if !run.is_empty() {
+            parts.push(Part { file, stmts: run });
+        }
+        self.files[file].stmts = stmts;
+        Ok(())
+    }
+
+    /// What [`Importer::import`] does, with `file` already being loaded.
+    fn import_into(
+        &mut self,
+        file: usize,
+        import: &Use,
+        parts: &mut Vec<Part>,
+    ) -> Result<(), ImportError> {
+        let Use { path, alias, span } = *import;
+        let source = (self.load)(&self.files[file].origin, path).map_err(|message| {
+            self.error(
+                file,
+                Code::ImportNotFound,
+                format!("Cannot import `{path}`: {message}"),
+                span,
+            )
+        })?;
+        let imported = match self.keys.get(&source.key) {
+            Some(&imported) => {
+                if let Some(at) = self.loading.iter().position(|&f| f == imported) {
+                    let mut chain: Vec<&str> = self.loading[at..]
+                        .iter()
+                        .map(|&f| self.files[f].origin.as_str())
+                        .collect();
+                    chain.push(&source.origin);
+                    let message = format!("Import cycle: {}", chain.join(" -> "));
+                    return Err(self.error(file, Code::ImportCycle, message, span));
+                }
+                imported
+            }
+            None => {
+                let mut parser = Parser::from_source(&source.src);
+                parser.set_max_nesting(self.max_nesting);
+                let stmts = match parser.parse() {
+                    Ok(stmts) => stmts,
+                    Err(error) => {
+                        return Err(ImportError {
+                            origin: source.origin,
+                            src: source.src,
+                            error,
+                        })
+                    }
+                };
+                let namespace = self.namespace(alias, path);
+                let imported = self.add_file(source, stmts, namespace);
+                self.expand(imported, parts)?;
+                imported
+            }
+        };
+        let qualifier = match alias {
+            Some(alias) => Some(alias),
+            None => {
+                let imports = &mut self.files[file].imports;
+                if !imports.contains(&imported) {
+                    imports.push(imported);
+                }
+                let namespace = &self.files[imported].namespace;
+                (!namespace.is_empty()).then(|| Symbol::intern(namespace))
+            }
+        };
+        let Some(qualifier) = qualifier else {
+            return Ok(());
+        };
+        match self.files[file].qualifiers.insert(qualifier, imported) {
+            Some(other) if other != imported => {
+                let message = format!(
+                    "`{qualifier}` already names the import of {}",
+                    self.files[other].origin
+                );
+                Err(self.error(file, Code::AmbiguousName, message, span))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A namespace no file has yet for a file first imported by `path`, as
+    /// `alias` if it has one: the alias, or the letters, digits and
+    /// underscores of the file's name without its extension, numbered if
+    /// another file has it already.
+    fn namespace(&self, alias: Option<Symbol>, path: &str) -> String {
+        let name = match alias {
+            Some(alias) => alias.as_str().to_string(),
+            None => {
+                let stem = Path::new(path).file_stem().unwrap_or_default();
+                let name: String = stem
+                    .to_string_lossy()
+                    .chars()
+                    .filter(|&c| c.is_ascii_alphanumeric() || c == '_')
+                    .collect();
+                if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                    name
+                } else {
+                    format!("file{name}")
+                }
+            }
+        };
+        let taken = |name: &str| self.files.iter().any(|file| file.namespace == name);
+        if !taken(&name) {
+            return name;
+        }
+        (2..)
+            .map(|n| format!("{name}{n}"))
+            .find(|name| !taken(name))
+            .expect("a free namespace")
+    }
+
+    /// Renames the variables of `stmts`, from file `file`, to the names they
+    /// run as.
+    fn resolve_stmts(&self, file: usize, stmts: &mut [Stmt]) -> Result<(), ImportError> {
+        for stmt in stmts {
+            match &mut stmt.kind {
+                StmtKind::CastOn(name, e) | StmtKind::Knit(name, e) => {
+                    *name = self.qualify(file, *name);
+                    self.resolve_expr(file, e)?;
+                }
+                StmtKind::CastOnStitches(e)
+                | StmtKind::Purl(e)
+                | StmtKind::Work(_, e)
+                | StmtKind::Cable(e, _)
+                | StmtKind::Distribute(_, e)
+                | StmtKind::BindOffStitches(e)
+                | StmtKind::ExpectStitches(e)
+                | StmtKind::ExpectStitchesWarning(e)
+                | StmtKind::CastOnNeedle(_, e)
+                | StmtKind::Transfer(_, _, e) => self.resolve_expr(file, e)?,
+                StmtKind::Repeat(count, body) => {
+                    self.resolve_expr(file, count)?;
+                    self.resolve_stmts(file, body)?;
+                }
+                StmtKind::Mirror(body) => self.resolve_stmts(file, body)?,
+                StmtKind::Alternate(bodies) => {
+                    for body in bodies {
+                        self.resolve_stmts(file, body)?;
+                    }
+                }
+                StmtKind::Pattern(_, args) => {
+                    for arg in args {
+                        self.resolve_expr(file, arg)?;
+                    }
+                }
+                StmtKind::Gauge(stitches, rows) => {
+                    self.resolve_expr(file, stitches)?;
+                    self.resolve_expr(file, rows)?;
+                }
+                StmtKind::Note(parts) => {
+                    for part in parts {
+                        if let NotePart::Expr(e) = part {
+                            self.resolve_expr(file, e)?;
+                        }
+                    }
+                }
+                StmtKind::BindOff
+                | StmtKind::Chart(_)
+                | StmtKind::Hold(_)
+                | StmtKind::Resume(_)
+                | StmtKind::Yarn(..)
+                | StmtKind::Color(_)
+                | StmtKind::WrapTurn
+                | StmtKind::Mode(_)
+                | StmtKind::PlaceMarker(_)
+                | StmtKind::SlipMarker(_)
+                | StmtKind::Use(..)
+                | StmtKind::Sizes(_)
+                | StmtKind::Meta(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_expr(&self, file: usize, e: &mut Expr) -> Result<(), ImportError> {
+        let mut names = HashMap::new();
+        for node in e.nodes() {
+            if let ExprKind::Var(name) = node.kind {
+                if let Entry::Vacant(entry) = names.entry(name) {
+                    entry.insert(self.resolve_name(file, name, node.span)?);
+                }
+            }
+        }
+        e.rename_vars(|name| names[&name]);
+        Ok(())
+    }
+
+    /// What file `file` means by a variable it reads as `name`, at `span`.
+    fn resolve_name(&self, file: usize, name: Symbol, span: Span) -> Result<Symbol, ImportError> {
+        let from = &self.files[file];
+        if name.as_str() == SIZE_VARIABLE {
+            return Ok(name);
+        }
+        if let Some((qualifier, name)) = name.as_str().split_once('.') {
+            let Some(&imported) = from.qualifiers.get(&Symbol::intern(qualifier)) else {
+                let known = from.qualifiers.keys().map(|q| q.as_str());
+                let message = format!(
+                    "No import is named `{qualifier}`{}",
+                    did_you_mean(qualifier, known)
+                );
+                return Err(self.error(file, Code::UnknownImportedName, message, span));
+            };
+            let target = &self.files[imported];
+            let name = Symbol::intern(name);
+            if !target.defines.contains(&name) {
+                let known = target.defines.iter().map(|n| n.as_str());
+                let message = format!(
+                    "{} does not define `{name}`{}",
+                    target.origin,
+                    did_you_mean(name.as_str(), known)
+                );
+                return Err(self.error(file, Code::UnknownImportedName, message, span));
+            }
+            return Ok(self.qualify(imported, name));
+        }
+        if from.defines.contains(&name) {
+            return Ok(self.qualify(file, name));
+        }
+        let candidates: Vec<usize> = from
+            .imports
+            .iter()
+            .copied()
+            .filter(|&f| self.files[f].defines.contains(&name))
+            .collect();
+        match candidates[..] {
+            [] => Ok(self.qualify(file, name)),
+            [only] => Ok(self.qualify(only, name)),
+            _ => {
+                let names: Vec<String> = candidates
+                    .iter()
+                    .map(|&f| format!("`{}` from {}", self.qualify(f, name), self.files[f].origin))
+                    .collect();
+                let message = format!("Ambiguous name `{name}`: it could be {}", list(&names));
+                Err(self.error(file, Code::AmbiguousName, message, span))
+            }
+        }
+    }
+
+    /// Variable `name` of file `file` as it runs.
+    fn qualify(&self, file: usize, name: Symbol) -> Symbol {
+        match self.files[file].namespace.as_str() {
+            "" => name,
+            namespace => Symbol::intern(&format!("{namespace}.{name}")),
+        }
+    }
+
+    /// An error at `span` in file `file`.
+    fn error(&self, file: usize, code: Code, message: String, span: Span) -> ImportError {
+        let file = &self.files[file];
+        ImportError {
+            origin: file.origin.clone(),
+            src: Rc::clone(&file.src),
+            error: ParseError {
+                code,
+                message,
+                span,
+                labels: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Adds the names `stmts` cast on or knit, at any depth, to `defines`.
+fn collect_defines(stmts: &[Stmt], defines: &mut BTreeSet<Symbol>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::CastOn(name, _) | StmtKind::Knit(name, _) => {
+                defines.insert(*name);
+            }
+            kind => {
+                for body in kind.bodies() {
+                    collect_defines(body, defines);
+                }
+            }
+        }
+    }
+}
+
+/// `a`, `a or b`, or `a, b or c`.
+fn list(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [init @ .., last] => format!("{} or {last}", init.join(", ")),
+    }
+}
@@ -0,0 +1,3075 @@
+//! Executes programs and keeps track of the fabric they knit.
+
+use crate::ast::{
+    Cross, Expr, ExprKind, MetaEntry, MetaValue, Mode, NotePart, Op, Operand, Pattern, Shaping,
+    Stitch, Stmt, StmtKind,
+};
+use crate::chart::{self, ChartStyle, Orientation};
+use crate::codes::Code;
+use crate::events::{Event, EventSink};
+use crate::hooks::{ExecHook, HookAction};
+use crate::lexer::Span;
+use crate::resolve::{self, Slot};
+use crate::suggest::did_you_mean;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::{format_duration, plural};
+use serde_json::{json, Value as Json};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+// `std::time::Instant` panics on wasm32-unknown-unknown; web-time provides one
+// backed by `performance.now()` there and is `std`'s everywhere else.
+#[cfg(not(feature = "wasm"))]
+use std::time::Instant;
+#[cfg(feature = "wasm")]
+use web_time::Instant;
+
+/// What knitlang programs compute with. For now every value is an integer.
+pub type Value = i64;
+
+/// A host function, given its arguments in order.
+type BuiltinFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A host function callable from programs; see
+/// [`Interpreter::register_builtin`].
+struct Builtin {
+    arity: usize,
+    call: Box<BuiltinFn>,
+}
+
+/// An error raised while a program runs, pointing at the code that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+fn runtime_error<T>(code: Code, message: String, span: Span) -> Result<T, RuntimeError> {
+    Err(RuntimeError {
+        code,
+        message,
+        span,
+    })
+}
+
+/// The version of the format [`Interpreter::save_state`] writes. Bump it
+/// whenever the format changes; [`Interpreter::load_state`] refuses versions
+/// it does not know rather than guess at them.
+pub const STATE_VERSION: u64 = 12;
+
+/// The variable a `sizes` declaration sets to where the size being knitted
+/// comes among the declared sizes, from 0. It is the same variable in every
+/// file, whichever imports it.
+pub const SIZE_VARIABLE: &str = "SIZE";
+
+/// Why a session could not be saved or restored.
+#[derive(Debug)]
+pub struct StateError {
+    pub message: String,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for StateError {}
+
+fn state_error<T>(message: impl Into<String>) -> Result<T, StateError> {
+    Err(StateError {
+        message: message.into(),
+    })
+}
+
+/// A yarn declared with `yarn name = "#colour";`, which a `color` statement
+/// knits the stitches after it in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Yarn {
+    pub name: String,
+    /// `#rgb` or `#rrggbb`.
+    pub colour: String,
+}
+
+/// The tension a piece is knitted at, declared with `gauge 22, 30;`: how
+/// many stitches across and rows up make 10 cm of fabric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gauge {
+    pub stitches: u32,
+    pub rows: u32,
+}
+
+/// A `note` in a pattern, and where it goes between the rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    /// How many rows were complete when it ran, or `None` if nothing had
+    /// been cast on yet and it belongs to the preamble of the pattern.
+    pub row: Option<usize>,
+    pub text: String,
+}
+
+/// What a `meta` block says about a pattern, for the header of its written
+/// instructions. Each key is optional; the ones it does not know are kept
+/// too, in the order they were written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatternMeta {
+    pub title: Option<MetaValue>,
+    pub author: Option<MetaValue>,
+    pub yarn: Option<MetaValue>,
+    pub needle: Option<MetaValue>,
+    pub gauge: Option<MetaValue>,
+    pub other: Vec<(String, MetaValue)>,
+}
+
+impl PatternMeta {
+    /// The keys a `meta` block is expected to have.
+    pub const KEYS: [&str; 5] = ["title", "author", "yarn", "needle", "gauge"];
+
+    /// The details of the entries of a `meta` block. A key given twice,
+    /// which the parser never allows, keeps its last value.
+    pub fn from_entries(entries: &[MetaEntry]) -> Self {
+        let mut meta = PatternMeta::default();
+        for entry in entries {
+            let value = Some(entry.value.clone());
+            match entry.key.as_str() {
+                "title" => meta.title = value,
+                "author" => meta.author = value,
+                "yarn" => meta.yarn = value,
+                "needle" => meta.needle = value,
+                "gauge" => meta.gauge = value,
+                key => meta.other.push((key.to_string(), entry.value.clone())),
+            }
+        }
+        meta
+    }
+
+    /// Every key that has a value, with it: the known keys in the order of
+    /// [`PatternMeta::KEYS`], then the others.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &MetaValue)> {
+        let known = [
+            &self.title,
+            &self.author,
+            &self.yarn,
+            &self.needle,
+            &self.gauge,
+        ];
+        Self::KEYS
+            .into_iter()
+            .zip(known)
+            .filter_map(|(key, value)| Some((key, value.as_ref()?)))
+            .chain(self.other.iter().map(|(key, value)| (key.as_str(), value)))
+    }
+
+    /// An object with a member for every key that has a value.
+    pub fn to_json(&self) -> Json {
+        let members = self
+            .entries()
+            .map(|(key, value)| (key.to_string(), value.to_json()));
+        Json::Object(members.collect())
+    }
+}
+
+/// Which side of a piece knitted flat faces the knitter while a row is
+/// worked. The first row is worked on the right side, and every turn at the
+/// end of a row changes side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Right,
+    Wrong,
+}
+
+impl Side {
+    /// `RS` or `WS`, as printed patterns write them.
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Side::Right => "RS",
+            Side::Wrong => "WS",
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Side::Right => Side::Wrong,
+            Side::Wrong => Side::Right,
+        }
+    }
+}
+
+/// Where a stitch marker sits on the needles: in a gap between stitches,
+/// counted from where the current row started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Place {
+    /// Among the stitches a short row left behind the start of the current
+    /// row, this many in from the far end.
+    Behind(usize),
+    /// After this many of the stitches the current row has made.
+    Worked(usize),
+    /// On the left needle, after this many of the stitches the current row
+    /// started with.
+    Ahead(usize),
+}
+
+impl Place {
+    fn name(self) -> &'static str {
+        match self {
+            Place::Behind(_) => "behind",
+            Place::Worked(_) => "worked",
+            Place::Ahead(_) => "ahead",
+        }
+    }
+
+    fn at(self) -> usize {
+        match self {
+            Place::Behind(at) | Place::Worked(at) | Place::Ahead(at) => at,
+        }
+    }
+}
+
+/// The knitted piece: every completed row plus the row on the needles. A row
+/// is complete once every live stitch has been worked, and the stitches it
+/// produced become the live stitches of the next row.
+///
+/// A short row ends early at a `wrap_turn`: the stitch after it is wrapped and
+/// the work turned, so the next row goes back over the stitches just worked,
+/// and the ones left unworked wait on the needle. The rows after it then
+/// start that many stitches in from their edge, until one is worked to the
+/// end.
+///
+/// Stitch markers sit between stitches and move with them from row to row.
+/// Working a stitch past a marker slips it onto the right needle.
+///
+/// `bind_off <count>;` works stitches off the needle that leave none for
+/// the next row. Bound off where a row starts at the right edge of the
+/// piece, as seen from the right side, they move the rows after it over to
+/// the left.
+#[derive(Debug, Default)]
+pub struct Fabric {
+    /// Completed rows, in the order they were worked.
+    rows: Vec<Vec<Stitch>>,
+    /// Stitches worked so far in the current row.
+    current: Vec<Stitch>,
+    /// Yarns in the order they were first declared.
+    yarns: Vec<Yarn>,
+    /// The yarn each stitch of `rows` and `current` was worked in, as an
+    /// index into `yarns`; `None` before any `color`.
+    row_yarns: Vec<Vec<Option<u32>>>,
+    current_yarns: Vec<Option<u32>>,
+    /// The yarn stitches are worked in now.
+    yarn: Option<u32>,
+    /// Live stitches at the start of the current row; 0 before `cast_on`.
+    width: usize,
+    /// Live stitches a short row left unworked, between the edge the
+    /// current row starts from and the stitch it starts with.
+    behind: usize,
+    /// For every completed row, how far in from its edge it started, and
+    /// how many stitches it left unworked at a turn.
+    indents: Vec<usize>,
+    unworked: Vec<usize>,
+    /// How many stitches rows have bound off the right edge of the piece
+    /// before the current row, and before every completed one.
+    offset: usize,
+    offsets: Vec<usize>,
+    /// How rows are worked now, and the side each completed row was worked
+    /// on, `None` for rounds.
+    mode: Mode,
+    sides: Vec<Option<Side>>,
+    /// The markers on the needles, in the order they were first placed.
+    markers: Vec<(Symbol, Place)>,
+    /// For every completed row and the current one, where it passed a
+    /// marker, as the stitches it had worked off the needle before it.
+    marks: Vec<Vec<usize>>,
+    current_marks: Vec<usize>,
+    /// Stitches held in `rows` and `current` together.
+    held: usize,
+    /// Live stitches the current row has worked and produced so far.
+    consumed: usize,
+    produced: usize,
+    /// Totals over the whole piece: stitches worked, and stitches gained or
+    /// lost to shaping.
+    worked: usize,
+    increases: usize,
+    decreases: usize,
+    /// The gauge of the last `gauge` statement, if there was one.
+    gauge: Option<Gauge>,
+    /// What the first `meta` block run said about the pattern.
+    meta: Option<PatternMeta>,
+    /// Every `note` run, in order.
+    notes: Vec<Note>,
+    /// The completed rows when the last `chart` statement ran.
+    charted: usize,
+    /// The needles besides the working one, with the stitches each holds,
+    /// in the order they were first declared.
+    needles: Vec<(Symbol, usize)>,
+    /// Stitches `hold` took off the working needle and `resume` put back on
+    /// it since the last row, and before every completed row.
+    off: usize,
+    on: usize,
+    offs: Vec<usize>,
+    ons: Vec<usize>,
+}
+
+impl Fabric {
+    /// Completed rows, in the order they were worked.
+    pub fn rows(&self) -> &[Vec<Stitch>] {
+        &self.rows
+    }
+
+    /// Live stitches on the needles at the start of the current row,
+    /// including any a short row left unworked.
+    pub fn width(&self) -> usize {
+        self.width + self.behind
+    }
+
+    /// How many stitches in from the edge it was worked from completed row
+    /// `row` started: the stitches of short rows before it that stay
+    /// unworked. 0 for a row that started at the edge, or no such row.
+    pub fn indent(&self, row: usize) -> usize {
+        self.indents.get(row).copied().unwrap_or(0)
+    }
+
+    /// How many stitches earlier rows bound off the right edge of the
+    /// piece, as seen from the right side, before completed row `row`, so a
+    /// chart starts it that many columns further left. 0 for no such row.
+    pub fn offset(&self, row: usize) -> usize {
+        self.offsets.get(row).copied().unwrap_or(0)
+    }
+
+    /// How rows are worked from now on, as the last `mode` statement set.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Which side completed row `row` was worked on if it was worked flat:
+    /// `None` for a round, or no such row.
+    pub fn side(&self, row: usize) -> Option<Side> {
+        self.sides.get(row).copied().flatten()
+    }
+
+    /// Where stitch markers sat in completed row `row`, as how many of the
+    /// stitches it worked off the needle came before each, in order.
+    pub fn marks(&self, row: usize) -> &[usize] {
+        self.marks.get(row).map_or(&[], Vec::as_slice)
+    }
+
+    /// How many stitches completed row `row` left unworked when it ended at
+    /// a `wrap_turn`, the first of them the wrapped stitch. 0 for a row
+    /// worked to the end, or no such row.
+    pub fn unworked(&self, row: usize) -> usize {
+        self.unworked.get(row).copied().unwrap_or(0)
+    }
+
+    /// Live stitches on the needles now: those the current row has made,
+    /// those it has still to work and any a short row left behind it. At
+    /// the start of a row this is [`Fabric::width`].
+    pub fn live(&self) -> usize {
+        self.produced + self.remaining() + self.behind
+    }
+
+    /// Stitches still waiting to be worked in the current row.
+    pub fn remaining(&self) -> usize {
+        self.width - self.consumed
+    }
+
+    /// Every yarn declared so far, in the order they were first declared.
+    pub fn yarns(&self) -> &[Yarn] {
+        &self.yarns
+    }
+
+    /// The gauge the piece is knitted at, as the last `gauge` statement
+    /// declared it.
+    pub fn gauge(&self) -> Option<Gauge> {
+        self.gauge
+    }
+
+    /// What the pattern's `meta` block says about it, if it has one.
+    pub fn meta(&self) -> Option<&PatternMeta> {
+        self.meta.as_ref()
+    }
+
+    /// Every `note` the pattern ran, in the order they ran.
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// Every needle besides the working one, with the stitches it holds, in
+    /// the order they were first declared.
+    pub fn needles(&self) -> &[(Symbol, usize)] {
+        &self.needles
+    }
+
+    /// How many stitches `hold` took off the working needle before completed
+    /// row `row`, counting from 0, and how many `resume` put back on,
+    /// since the row before. Row `rows().len()` is the one being worked.
+    pub fn moved(&self, row: usize) -> (usize, usize) {
+        if row == self.offs.len() {
+            return (self.off, self.on);
+        }
+        let off = self.offs.get(row).copied().unwrap_or(0);
+        (off, self.ons.get(row).copied().unwrap_or(0))
+    }
+
+    /// The stitches on needle `name`, declaring it empty if it is new.
+    fn needle_mut(&mut self, name: Symbol) -> &mut usize {
+        let i = match self.needles.iter().position(|(needle, _)| *needle == name) {
+            Some(i) => i,
+            None => {
+                self.needles.push((name, 0));
+                self.needles.len() - 1
+            }
+        };
+        &mut self.needles[i].1
+    }
+
+    /// How many rows were complete when the last `chart` statement ran, so
+    /// the next charts only those after them; 0 before one has.
+    pub fn charted(&self) -> usize {
+        self.charted
+    }
+
+    /// The yarn stitch `stitch` of completed row `row`, both counting from
+    /// 0, was worked in, as an index into [`Fabric::yarns`]. `None` if the
+    /// stitch was worked before any `color` statement, or there is no such
+    /// stitch.
+    pub fn yarn_at(&self, row: usize, stitch: usize) -> Option<usize> {
+        let yarn = *self.row_yarns.get(row)?.get(stitch)?;
+        yarn.map(|yarn| yarn as usize)
+    }
+
+    /// Roughly how much memory the rows take up, in bytes.
+    fn bytes(&self) -> usize {
+        let row = mem::size_of::<Vec<Stitch>>()
+            + mem::size_of::<Vec<Option<u32>>>()
+            + 2 * mem::size_of::<usize>()
+            + mem::size_of::<Option<Side>>()
+            + mem::size_of::<Vec<usize>>();
+        let stitch = mem::size_of::<Stitch>() + mem::size_of::<Option<u32>>();
+        self.held * stitch + (self.rows.len() + 1) * row
+    }
+
+    /// Declares yarn `name`, or gives it a new colour if it already is one;
+    /// stitches worked in it before then change colour too.
+    fn declare_yarn(&mut self, name: &str, colour: &str) {
+        match self.yarns.iter_mut().find(|yarn| yarn.name == name) {
+            Some(yarn) => yarn.colour = colour.to_string(),
+            None => self.yarns.push(Yarn {
+                name: name.to_string(),
+                colour: colour.to_string(),
+            }),
+        }
+    }
+
+    /// Works the stitches after this in yarn `name`, returning `false` if no
+    /// yarn of that name is declared.
+    fn use_yarn(&mut self, name: &str) -> bool {
+        let Some(index) = self.yarns.iter().position(|yarn| yarn.name == name) else {
+            return false;
+        };
+        self.yarn = Some(index as u32);
+        true
+    }
+
+    /// Where marker `name` is, if it has been placed.
+    fn marker(&self, name: Symbol) -> Option<Place> {
+        self.markers
+            .iter()
+            .find(|(marker, _)| *marker == name)
+            .map(|&(_, place)| place)
+    }
+
+    /// Whether `place` is where the current row has got to.
+    fn at_work(&self, place: Place) -> bool {
+        place == Place::Ahead(self.consumed) || place == Place::Worked(self.produced)
+    }
+
+    /// How many stitches the current row has to work to reach `place`: the
+    /// stitches before it on the left needle, or in the round, those left
+    /// in this round and the ones before it in the next. `None` for a
+    /// marker a flat row has passed.
+    fn stitches_to(&self, place: Place) -> Option<usize> {
+        match place {
+            Place::Ahead(at) => Some(at - self.consumed),
+            Place::Worked(at) if self.mode == Mode::Round => Some(self.remaining() + at),
+            _ if self.at_work(place) => Some(0),
+            Place::Worked(_) | Place::Behind(_) => None,
+        }
+    }
+
+    /// Puts marker `name` on the right needle after the last stitch worked,
+    /// moving it there if it is already on the needles.
+    fn place_marker(&mut self, name: Symbol) {
+        let place = Place::Worked(self.produced);
+        match self.markers.iter_mut().find(|(marker, _)| *marker == name) {
+            Some((_, old)) => *old = place,
+            None => self.markers.push((name, place)),
+        }
+        self.current_marks.push(self.consumed);
+    }
+
+    /// The marker a stitch working `consumes` stitches would split, since it
+    /// sits between two of them.
+    fn marker_within(&self, consumes: usize) -> Option<Symbol> {
+        let within = self.consumed + 1..self.consumed + consumes;
+        self.markers.iter().find_map(|&(name, place)| match place {
+            Place::Ahead(at) if within.contains(&at) => Some(name),
+            _ => None,
+        })
+    }
+
+    /// Moves the markers the current row has reached onto the right
+    /// needle.
+    fn slip_markers(&mut self) {
+        for (_, place) in &mut self.markers {
+            if *place == Place::Ahead(self.consumed) {
+                *place = Place::Worked(self.produced);
+                self.current_marks.push(self.consumed);
+            }
+        }
+    }
+
+    /// Works one stitch, returning `true` if it completed the row.
+    fn work(&mut self, stitch: Stitch) -> bool {
+        let (consumes, produces) = (stitch.consumes(), stitch.produces());
+        self.slip_markers();
+        self.current.push(stitch);
+        self.current_yarns.push(self.yarn);
+        self.held += 1;
+        self.consumed += consumes;
+        self.produced += produces;
+        self.worked += 1;
+        self.increases += produces.saturating_sub(consumes);
+        if stitch != Stitch::BindOff {
+            self.decreases += consumes.saturating_sub(produces);
+        }
+        if self.remaining() > 0 {
+            return false;
+        }
+        self.end_row();
+        true
+    }
+
+    /// Wraps the next stitch and turns, ending the current row before its
+    /// last stitch. The row must have worked at least one stitch.
+    fn wrap_turn(&mut self) {
+        self.end_row();
+    }
+
+    /// The side the current row is worked on: rows worked flat alternate,
+    /// starting on the right side after a cast on or a round.
+    fn next_side(&self) -> Option<Side> {
+        match self.mode {
+            Mode::Round => None,
+            Mode::Flat => Some(match self.sides.last() {
+                Some(Some(side)) => side.other(),
+                _ => Side::Right,
+            }),
+        }
+    }
+
+    /// How many stitches the current row, as it ends, bound off the right
+    /// edge of the piece: those it starts with if it starts there, rather
+    /// than on the wrong side or in from the edge, or else those it ends
+    /// with if it is a wrong-side row worked to the edge.
+    fn bound_off_right(&self) -> usize {
+        let bound_off = |stitch: &&Stitch| **stitch == Stitch::BindOff;
+        let wrong_side = self.next_side() == Some(Side::Wrong);
+        if !wrong_side && self.behind == 0 {
+            self.current.iter().take_while(bound_off).count()
+        } else if wrong_side && self.remaining() == 0 {
+            self.current.iter().rev().take_while(bound_off).count()
+        } else {
+            0
+        }
+    }
+
+    /// Completes the current row. Turning, the stitches it produced and
+    /// those behind it are the ones the next row works, and any it left are
+    /// behind that row in turn.
+    fn end_row(&mut self) {
+        self.slip_markers();
+        // The markers move to where they are counting from the start of the
+        // next row: turning, it starts where this one stopped and goes back
+        // over the stitches it made, then those behind it, and its own
+        // unworked stitches are behind it in turn. In the round it starts
+        // where this one did.
+        let (width, behind, produced) = (self.width, self.behind, self.produced);
+        let turns = self.mode == Mode::Flat;
+        for (_, place) in &mut self.markers {
+            *place = match *place {
+                Place::Ahead(at) => Place::Behind(width - at),
+                Place::Worked(at) if turns => Place::Ahead(produced - at),
+                Place::Worked(at) if at == produced => Place::Ahead(0),
+                Place::Worked(at) => Place::Ahead(at),
+                Place::Behind(at) => Place::Ahead(produced + behind - at),
+            };
+        }
+        let bound_off = self.bound_off_right();
+        let mut marks = std::mem::take(&mut self.current_marks);
+        marks.sort_unstable();
+        marks.dedup();
+        self.marks.push(marks);
+        self.rows.push(std::mem::take(&mut self.current));
+        self.row_yarns.push(std::mem::take(&mut self.current_yarns));
+        self.indents.push(self.behind);
+        self.unworked.push(self.remaining());
+        self.offsets.push(self.offset);
+        self.offs.push(mem::take(&mut self.off));
+        self.ons.push(mem::take(&mut self.on));
+        self.sides.push(self.next_side());
+        let left = self.remaining();
+        self.offset += bound_off;
+        self.width = std::mem::take(&mut self.produced) + self.behind;
+        self.behind = left;
+        self.consumed = 0;
+    }
+}
+
+impl Fabric {
+    /// A fabric from the `"fabric"` object of a saved session, in format
+    /// `version`.
+    fn from_state(state: &Json, version: u64) -> Result<Self, StateError> {
+        let stitches = |row: &Json, what: &str| -> Result<Vec<Stitch>, StateError> {
+            let Some(row) = row.as_array() else {
+                return state_error(format!("saved {what} should be a list of stitches"));
+            };
+            row.iter()
+                .map(|stitch| match stitch.as_str().and_then(saved_stitch) {
+                    Some(stitch) => Ok(stitch),
+                    None => state_error(format!("unknown stitch {stitch} in saved {what}")),
+                })
+                .collect()
+        };
+        let Some(rows) = state_field(state, "rows")?.as_array() else {
+            return state_error("saved `rows` should be a list of rows");
+        };
+        let rows: Vec<Vec<Stitch>> = rows
+            .iter()
+            .map(|row| stitches(row, "row"))
+            .collect::<Result<_, _>>()?;
+        let current: Vec<Stitch> = stitches(state_field(state, "current")?, "current row")?;
+        let held = rows.iter().map(Vec::len).sum::<usize>() + current.len();
+        // Sessions from before yarns were saved have none.
+        let (yarns, yarn, row_yarns, current_yarns) = if version < 2 {
+            let row_yarns = rows.iter().map(|row| vec![None; row.len()]).collect();
+            (Vec::new(), None, row_yarns, vec![None; current.len()])
+        } else {
+            yarns_from_state(state, &rows, &current)?
+        };
+        // Sessions from before short rows were saved have none.
+        let (behind, indents, unworked) = if version < 3 {
+            (0, vec![0; rows.len()], vec![0; rows.len()])
+        } else {
+            (
+                state_count(state, "behind")?,
+                counts_from_state(state, "indents", rows.len())?,
+                counts_from_state(state, "unworked", rows.len())?,
+            )
+        };
+        // Sessions from before modes were saved were all knitted flat.
+        let (mode, sides) = if version < 4 {
+            let sides = (0..rows.len())
+                .map(|r| Some(if r % 2 == 0 { Side::Right } else { Side::Wrong }))
+                .collect();
+            (Mode::Flat, sides)
+        } else {
+            sides_from_state(state, rows.len())?
+        };
+        // Nor markers before they were.
+        let (markers, marks, current_marks) = if version < 5 {
+            (Vec::new(), vec![Vec::new(); rows.len()], Vec::new())
+        } else {
+            markers_from_state(state, rows.len())?
+        };
+        // Nor a gauge before one was.
+        let gauge = if version < 7 {
+            None
+        } else {
+            gauge_from_state(state)?
+        };
+        // Nor what a `meta` block said.
+        let meta = if version < 8 {
+            None
+        } else {
+            meta_from_state(state)?
+        };
+        // Nor stitches bound off before `bind_off` took a count.
+        let (offset, offsets) = if version < 9 {
+            (0, vec![0; rows.len()])
+        } else {
+            (
+                state_count(state, "offset")?,
+                counts_from_state(state, "offsets", rows.len())?,
+            )
+        };
+        // Nor notes.
+        let notes = if version < 10 {
+            Vec::new()
+        } else {
+            notes_from_state(state, rows.len())?
+        };
+        // Nor which rows `chart` had charted.
+        let charted = if version < 11 {
+            0
+        } else {
+            match state_count(state, "charted")? {
+                charted if charted <= rows.len() => charted,
+                charted => {
+                    return state_error(format!(
+                        "saved `charted` is {charted}, but only {} are saved",
+                        plural(rows.len(), "row")
+                    ))
+                }
+            }
+        };
+        // Nor needles besides the working one.
+        let (needles, off, on, offs, ons) = if version < 12 {
+            (Vec::new(), 0, 0, vec![0; rows.len()], vec![0; rows.len()])
+        } else {
+            (
+                needles_from_state(state)?,
+                state_count(state, "off")?,
+                state_count(state, "on")?,
+                counts_from_state(state, "offs", rows.len())?,
+                counts_from_state(state, "ons", rows.len())?,
+            )
+        };
+        let fabric = Fabric {
+            rows,
+            held,
+            mode,
+            sides,
+            markers,
+            marks,
+            current_marks,
+            behind,
+            indents,
+            unworked,
+            offset,
+            offsets,
+            yarns,
+            yarn,
+            row_yarns,
+            current_yarns,
+            width: state_count(state, "width")?,
+            consumed: current.iter().map(|s| s.consumes()).sum(),
+            produced: current.iter().map(|s| s.produces()).sum(),
+            current,
+            worked: state_count(state, "worked")?,
+            increases: state_count(state, "increases")?,
+            decreases: state_count(state, "decreases")?,
+            gauge,
+            meta,
+            notes,
+            charted,
+            needles,
+            off,
+            on,
+            offs,
+            ons,
+        };
+        // A row that used up every stitch would have been completed.
+        if !fabric.current.is_empty() && fabric.consumed >= fabric.width {
+            return state_error(format!(
+                "saved current row works {} of only {} on the needles",
+                plural(fabric.consumed, "stitch"),
+                fabric.width
+            ));
+        }
+        Ok(fabric)
+    }
+}
+
+/// The stitch `name` stands for in a saved session: a [`Stitch::from_name`]
+/// abbreviation, or `bo` for a bound-off stitch.
+fn saved_stitch(name: &str) -> Option<Stitch> {
+    match name {
+        "bo" => Some(Stitch::BindOff),
+        name => Stitch::from_name(name),
+    }
+}
+
+/// The yarns of the `"fabric"` object of a saved session: every yarn
+/// declared, the one in use, and the yarn of each stitch of `rows` and
+/// `current`.
+#[expect(clippy::type_complexity)]
+fn yarns_from_state(
+    state: &Json,
+    rows: &[Vec<Stitch>],
+    current: &[Stitch],
+) -> Result<
+    (
+        Vec<Yarn>,
+        Option<u32>,
+        Vec<Vec<Option<u32>>>,
+        Vec<Option<u32>>,
+    ),
+    StateError,
+> {
+    let Some(saved) = state_field(state, "yarns")?.as_array() else {
+        return state_error("saved `yarns` should be a list of yarns");
+    };
+    let yarns: Vec<Yarn> = saved
+        .iter()
+        .map(|yarn| {
+            let text = |key: &str| yarn.get(key).and_then(Json::as_str);
+            match (text("name"), text("color")) {
+                (Some(name), Some(colour)) => Ok(Yarn {
+                    name: name.to_string(),
+                    colour: colour.to_string(),
+                }),
+                _ => state_error(format!("saved yarn {yarn} should have a name and a color")),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    let index = |value: &Json| -> Result<Option<u32>, StateError> {
+        match value {
+            Json::Null => Ok(None),
+            _ => match value.as_u64() {
+                Some(i) if (i as usize) < yarns.len() => Ok(Some(i as u32)),
+                _ => state_error(format!("saved yarn {value} is not one of the saved yarns")),
+            },
+        }
+    };
+    let indices = |row: &Json, len: usize, what: &str| -> Result<Vec<Option<u32>>, StateError> {
+        match row.as_array() {
+            Some(row) if row.len() == len => row.iter().map(index).collect(),
+            _ => state_error(format!(
+                "saved yarns of the {what} should be a list of one yarn per stitch"
+            )),
+        }
+    };
+    let yarn = index(state_field(state, "yarn")?)?;
+    let Some(saved_rows) = state_field(state, "row_yarns")?.as_array() else {
+        return state_error("saved `row_yarns` should be a list of rows");
+    };
+    if saved_rows.len() != rows.len() {
+        return state_error("saved `row_yarns` should have one list for every row");
+    }
+    let row_yarns = saved_rows
+        .iter()
+        .zip(rows)
+        .map(|(saved, row)| indices(saved, row.len(), "rows"))
+        .collect::<Result<_, _>>()?;
+    let current_yarns = indices(
+        state_field(state, "current_yarns")?,
+        current.len(),
+        "current row",
+    )?;
+    Ok((yarns, yarn, row_yarns, current_yarns))
+}
+
+/// The sizes a saved session declared, and where the one it knitted comes
+/// among them.
+fn sizes_from_state(state: &Json) -> Result<(Vec<Symbol>, Option<usize>), StateError> {
+    let sizes = state_field(state, "sizes")?.as_array().and_then(|sizes| {
+        sizes
+            .iter()
+            .map(|size| size.as_str().map(Symbol::intern))
+            .collect::<Option<Vec<_>>>()
+    });
+    let Some(sizes) = sizes else {
+        return state_error("saved `sizes` should be a list of size names");
+    };
+    let size = state_field(state, "size")?;
+    match size.as_u64().map(|size| size as usize) {
+        Some(size) if size < sizes.len() => Ok((sizes, Some(size))),
+        None if size.is_null() && sizes.is_empty() => Ok((sizes, None)),
+        _ => state_error(format!(
+            "saved size {size} should be the position of one of the {} saved",
+            plural(sizes.len(), "size")
+        )),
+    }
+}
+
+/// The gauge of the `"fabric"` object of a saved session, saved as
+/// `[stitches, rows]` or `null`.
+fn gauge_from_state(state: &Json) -> Result<Option<Gauge>, StateError> {
+    let gauge = state_field(state, "gauge")?;
+    if gauge.is_null() {
+        return Ok(None);
+    }
+    let count = |n: &Json| {
+        n.as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .filter(|&n| n > 0)
+    };
+    match gauge.as_array().map(Vec::as_slice) {
+        Some([stitches, rows]) => match (count(stitches), count(rows)) {
+            (Some(stitches), Some(rows)) => Ok(Some(Gauge { stitches, rows })),
+            _ => state_error(format!("saved gauge {gauge} should be two positive counts")),
+        },
+        _ => state_error(format!(
+            "saved gauge {gauge} should be [stitches, rows] or null"
+        )),
+    }
+}
+
+/// What the `meta` block said about the pattern, from the `"fabric"` object
+/// of a saved session, saved as a list of `[key, value]` pairs or `null`.
+fn meta_from_state(state: &Json) -> Result<Option<PatternMeta>, StateError> {
+    let meta = state_field(state, "meta")?;
+    if meta.is_null() {
+        return Ok(None);
+    }
+    let entry = |entry: &Json| {
+        let (key, value) = match entry.as_array().map(Vec::as_slice) {
+            Some([Json::String(key), Json::String(value)]) => (key, MetaValue::Str(value.clone())),
+            Some([Json::String(key), value]) => (key, MetaValue::Number(value.as_i64()?)),
+            _ => return None,
+        };
+        Some(MetaEntry {
+            key: Symbol::intern(key),
+            value,
+            span: Span::default(),
+        })
+    };
+    let entries: Option<Vec<MetaEntry>> = meta
+        .as_array()
+        .and_then(|entries| entries.iter().map(entry).collect());
+    match entries {
+        Some(entries) => Ok(Some(PatternMeta::from_entries(&entries))),
+        None => state_error(format!(
+            "saved meta {meta} should be a list of [key, value] pairs or null"
+        )),
+    }
+}
+
+/// The notes of the `"fabric"` object of a saved session, each saved as
+/// `[row, text]` with a `row` of `null` for the preamble, and at most
+/// `rows` rows in.
+fn notes_from_state(state: &Json, rows: usize) -> Result<Vec<Note>, StateError> {
+    let notes = state_field(state, "notes")?;
+    let note = |note: &Json| match note.as_array().map(Vec::as_slice) {
+        Some([row, Json::String(text)]) => {
+            let row = match row.as_u64() {
+                Some(row) if row as usize <= rows => Some(row as usize),
+                Some(_) => return None,
+                None if row.is_null() => None,
+                None => return None,
+            };
+            Some(Note {
+                row,
+                text: text.clone(),
+            })
+        }
+        _ => None,
+    };
+    let parsed: Option<Vec<Note>> = notes
+        .as_array()
+        .and_then(|notes| notes.iter().map(note).collect());
+    match parsed {
+        Some(notes) => Ok(notes),
+        None => state_error(format!(
+            "saved notes {notes} should be a list of [row, text] pairs, each row no more than \
+             the {} saved",
+            plural(rows, "row")
+        )),
+    }
+}
+
+/// The needles of the `"fabric"` object of a saved session besides the
+/// working one, each saved as `[name, stitches]`.
+fn needles_from_state(state: &Json) -> Result<Vec<(Symbol, usize)>, StateError> {
+    let needles = state_field(state, "needles")?;
+    let needle = |needle: &Json| match needle.as_array().map(Vec::as_slice) {
+        Some([Json::String(name), stitches]) => {
+            Some((Symbol::intern(name), stitches.as_u64()? as usize))
+        }
+        _ => None,
+    };
+    let parsed: Option<Vec<(Symbol, usize)>> = needles
+        .as_array()
+        .and_then(|needles| needles.iter().map(needle).collect());
+    match parsed {
+        Some(needles) => Ok(needles),
+        None => state_error(format!(
+            "saved needles {needles} should be a list of [name, stitches] pairs"
+        )),
+    }
+}
+
+/// The mode of the `"fabric"` object of a saved session, and the side each
+/// of its `rows` rows was worked on.
+fn sides_from_state(state: &Json, rows: usize) -> Result<(Mode, Vec<Option<Side>>), StateError> {
+    let mode = state_field(state, "mode")?;
+    let Some(mode) = mode.as_str().and_then(Mode::from_name) else {
+        return state_error(format!("saved mode {mode} should be \"flat\" or \"round\""));
+    };
+    let sides = state_field(state, "sides")?.as_array().and_then(|sides| {
+        let sides: Option<Vec<Option<Side>>> = sides
+            .iter()
+            .map(|side| match side.as_str() {
+                Some("RS") => Some(Some(Side::Right)),
+                Some("WS") => Some(Some(Side::Wrong)),
+                None if side.is_null() => Some(None),
+                _ => None,
+            })
+            .collect();
+        sides.filter(|sides| sides.len() == rows)
+    });
+    match sides {
+        Some(sides) => Ok((mode, sides)),
+        None => {
+            state_error("saved `sides` should be a list of \"RS\", \"WS\" or null for every row")
+        }
+    }
+}
+
+/// The markers of the `"fabric"` object of a saved session, where each of
+/// its `rows` rows passed them, and where the current row has.
+#[expect(clippy::type_complexity)]
+fn markers_from_state(
+    state: &Json,
+    rows: usize,
+) -> Result<(Vec<(Symbol, Place)>, Vec<Vec<usize>>, Vec<usize>), StateError> {
+    let Some(saved) = state_field(state, "markers")?.as_array() else {
+        return state_error("saved `markers` should be a list of markers");
+    };
+    let markers = saved
+        .iter()
+        .map(|marker| {
+            let text = |key: &str| marker.get(key).and_then(Json::as_str);
+            let at = marker
+                .get("at")
+                .and_then(Json::as_u64)
+                .and_then(|at| usize::try_from(at).ok());
+            let place = match (text("on"), at) {
+                (Some("behind"), Some(at)) => Place::Behind(at),
+                (Some("worked"), Some(at)) => Place::Worked(at),
+                (Some("ahead"), Some(at)) => Place::Ahead(at),
+                _ => return state_error(format!("saved marker {marker} has no place")),
+            };
+            match text("name") {
+                Some(name) => Ok((Symbol::intern(name), place)),
+                None => state_error(format!("saved marker {marker} has no name")),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    let counts = |value: &Json| -> Option<Vec<usize>> {
+        value
+            .as_array()?
+            .iter()
+            .map(|n| n.as_u64().and_then(|n| usize::try_from(n).ok()))
+            .collect()
+    };
+    let marks = state_field(state, "marks")?
+        .as_array()
+        .and_then(|marks| marks.iter().map(counts).collect::<Option<Vec<_>>>())
+        .filter(|marks| marks.len() == rows);
+    let current_marks = counts(state_field(state, "current_marks")?);
+    match (marks, current_marks) {
+        (Some(marks), Some(current_marks)) => Ok((markers, marks, current_marks)),
+        _ => state_error(
+            "saved `marks` should be a list of counts for every row and the current one",
+        ),
+    }
+}
+
+/// The list `key` of the `"fabric"` object of a saved session, which has a
+/// count for each of its `rows` rows.
+fn counts_from_state(state: &Json, key: &str, rows: usize) -> Result<Vec<usize>, StateError> {
+    let counts = state_field(state, key)?.as_array().and_then(|counts| {
+        let counts: Option<Vec<usize>> = counts
+            .iter()
+            .map(|n| n.as_u64().map(|n| n as usize))
+            .collect();
+        counts.filter(|counts| counts.len() == rows)
+    });
+    match counts {
+        Some(counts) => Ok(counts),
+        None => state_error(format!(
+            "saved `{key}` should be a list of one count for every row"
+        )),
+    }
+}
+
+fn state_field<'a>(state: &'a Json, key: &str) -> Result<&'a Json, StateError> {
+    match state.get(key) {
+        Some(value) => Ok(value),
+        None => state_error(format!("saved session is missing `{key}`")),
+    }
+}
+
+fn state_count(state: &Json, key: &str) -> Result<usize, StateError> {
+    match state_field(state, key)?.as_u64() {
+        Some(n) => Ok(n as usize),
+        None => state_error(format!("saved `{key}` should be a count")),
+    }
+}
+
+/// Counters for one interpreter's runs so far, as shown by `--summary`. They
+/// are always kept, and cost a few additions per statement.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    /// Completed rows.
+    pub rows: usize,
+    /// Stitches worked, including any in an unfinished row.
+    pub stitches: usize,
+    pub increases: usize,
+    pub decreases: usize,
+    /// Variables defined at the end.
+    pub variables: usize,
+    /// The most variables defined at once.
+    pub peak_variables: usize,
+    pub statements: u64,
+    /// Expressions evaluated, counting each whole expression once.
+    pub expressions: u64,
+    /// How deeply nested the innermost `repeat` entered was, 0 if none was.
+    pub max_depth: usize,
+    /// Wall time spent executing (not parsing).
+    pub elapsed: Duration,
+}
+
+impl RunStats {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "rows": self.rows,
+            "stitches": self.stitches,
+            "increases": self.increases,
+            "decreases": self.decreases,
+            "variables": self.variables,
+            "peak_variables": self.peak_variables,
+            "statements": self.statements,
+            "expressions": self.expressions,
+            "max_depth": self.max_depth,
+            "elapsed_ns": self.elapsed.as_nanos() as u64,
+        })
+    }
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows worked: {}", self.rows)?;
+        writeln!(f, "stitches:    {}", self.stitches)?;
+        writeln!(f, "increases:   {}", self.increases)?;
+        writeln!(f, "decreases:   {}", self.decreases)?;
+        writeln!(f, "variables:   {}", self.variables)?;
+        writeln!(f, "peak vars:   {}", self.peak_variables)?;
+        writeln!(f, "statements:  {}", self.statements)?;
+        writeln!(f, "expressions: {}", self.expressions)?;
+        writeln!(f, "max depth:   {}", self.max_depth)?;
+        writeln!(f, "wall time:   {:?}", self.elapsed)
+    }
+}
+
+/// Executes programs. Variables and the fabric persist between runs, so
+/// several programs can be run one after another as one session.
+pub struct Interpreter {
+    /// Variable values, each in the slot `slots` gives its name; `None` for
+    /// a name with no value yet.
+    vars: Vec<Option<i64>>,
+    /// The slot in `vars` of every name a run or a host has used.
+    slots: SymbolMap<u32>,
+    /// The name in each slot of `vars`.
+    names: Vec<Symbol>,
+    /// For each slot of the program running, as [`resolve::number_slots`]
+    /// numbered them, the slot of `vars` it stands for. Empty between runs.
+    frame: Vec<u32>,
+    /// Values [`Interpreter::load_state`] read for names nothing had
+    /// interned, kept by name until a run or a host uses them and they move
+    /// into `vars`. A session file is untrusted, and the interner never
+    /// frees a name.
+    saved: HashMap<Box<str>, i64>,
+    /// How many variables hold a value, in `vars` or `saved`.
+    var_count: usize,
+    /// Functions programs can call, by name.
+    builtins: HashMap<Symbol, Builtin>,
+    /// Scratch space for evaluating expressions.
+    stack: Vec<i64>,
+    /// Where `purl` output goes.
+    out: Box<dyn Write>,
+    /// Statements executed and time spent running; the rest of the stats are
+    /// read off the fabric and variables by `stats`.
+    stats: RunStats,
+    fabric: Fabric,
+    /// Stop once this many rows are complete, as `--rows` asks.
+    row_limit: Option<u64>,
+    /// Whether the run stopped because it reached `row_limit`.
+    truncated: bool,
+    /// Abort with a runtime error once this much wall time has passed since
+    /// `started`, as `--timeout` asks.
+    timeout: Option<Duration>,
+    /// When the current top-level run started; see
+    /// [`Interpreter::restart_timeout`].
+    started: Instant,
+    /// Whether the run was aborted because it ran past `timeout`.
+    timed_out: bool,
+    /// Abort with a runtime error after this many units of work in total.
+    step_limit: Option<u64>,
+    /// Units of work (statements, loop iterations, stitches) done so far.
+    steps: u64,
+    /// Abort with a runtime error once variables and the fabric take up more
+    /// than this many bytes.
+    memory_limit: Option<usize>,
+    /// Set from elsewhere, such as a Ctrl-C handler, to stop the run.
+    interrupt: Option<Arc<AtomicBool>>,
+    /// Whether the last run was stopped by `interrupt`.
+    interrupted: bool,
+    /// Where to report what the program does, if anywhere.
+    events: Option<Box<dyn EventSink>>,
+    /// Consulted around every statement, if set.
+    hook: Option<Box<dyn ExecHook>>,
+    /// What `chart` statements chart each kind of stitch with.
+    chart_symbols: ChartStyle,
+    /// The sizes the last `sizes` declaration named, and where the one being
+    /// knitted comes among them.
+    sizes: Vec<Symbol>,
+    size: Option<usize>,
+    /// The size the host asked for, if not the first.
+    chosen_size: Option<Symbol>,
+    /// The stitches each `mirror` block being run has put off working until
+    /// the rest of their row is known, innermost block last, and how many
+    /// live stitches they all work off the needle together.
+    mirrors: Vec<Vec<Deferred>>,
+    deferred: usize,
+    /// Warnings from `expect_stitches?` checkpoints since the host last took
+    /// them.
+    warnings: Vec<RuntimeError>,
+}
+
+/// A stitch a `mirror` block has put off working.
+struct Deferred {
+    /// The stitch to work, and its mirror image, which takes its place if
+    /// the stitch is mirrored again by a block around this one.
+    stitch: Stitch,
+    other: Stitch,
+    /// The yarn it was worked in.
+    yarn: Option<u32>,
+    span: Span,
+}
+
+impl Deferred {
+    fn mirrored(self) -> Self {
+        Self {
+            stitch: self.other,
+            other: self.stitch,
+            ..self
+        }
+    }
+}
+
+/// The stitch `pattern`, given `args`, works as stitch `worked` of the
+/// `row`th row it works, both counting from 0, in a row `width` stitches
+/// wide worked on `side`.
+fn pattern_stitch(
+    pattern: Pattern,
+    args: &[usize],
+    row: usize,
+    worked: usize,
+    width: usize,
+    side: Option<Side>,
+) -> Stitch {
+    // Columns count from the right-hand edge of the right side, where a
+    // wrong-side row ends.
+    let column = match side {
+        Some(Side::Wrong) => width - 1 - worked,
+        _ => worked,
+    };
+    // Whether the stitch shows as a knit on the right side.
+    let knit = match pattern {
+        Pattern::Stockinette => true,
+        Pattern::Garter => match side {
+            Some(side) => side == Side::Right,
+            None => row.is_multiple_of(2),
+        },
+        // Seed starts with a knit whichever side faces, and works each
+        // stitch the same way as the one below it, which is in the same
+        // place in the round and at the other end of a turned row, so it
+        // shows the other way.
+        Pattern::Seed => {
+            let below = match side {
+                Some(_) => row * (width + 1),
+                None => row,
+            };
+            return match (worked + below) % 2 {
+                0 => Stitch::Knit,
+                _ => Stitch::Purl,
+            };
+        }
+        Pattern::Rib => column % (args[0] + args[1]) < args[0],
+    };
+    if knit == (side == Some(Side::Wrong)) {
+        Stitch::Purl
+    } else {
+        Stitch::Knit
+    }
+}
+
+/// What a statement of `kind` does that a `mirror` block cannot mirror,
+/// such as `wrap and turn`, if anything.
+fn unmirrorable(kind: &StmtKind) -> Option<&'static str> {
+    match kind {
+        StmtKind::CastOnStitches(_) => Some("cast on"),
+        StmtKind::WrapTurn => Some("wrap and turn"),
+        StmtKind::Mode(_) => Some("change mode"),
+        StmtKind::PlaceMarker(_) => Some("place a marker"),
+        StmtKind::SlipMarker(_) => Some("slip a marker"),
+        StmtKind::BindOff | StmtKind::BindOffStitches(_) => Some("bind off"),
+        StmtKind::Hold(_) | StmtKind::Resume(_) => Some("move stitches between needles"),
+        StmtKind::ExpectStitches(_) | StmtKind::ExpectStitchesWarning(_) => {
+            Some("check the stitch count")
+        }
+        _ => None,
+    }
+}
+
+/// `a op b`, or `None` if it overflows or divides by zero.
+pub(crate) fn arithmetic(a: i64, op: char, b: i64) -> Option<i64> {
+    match op {
+        '+' => a.checked_add(b),
+        '-' => a.checked_sub(b),
+        '*' => a.checked_mul(b),
+        '/' => a.checked_div(b),
+        _ => panic!("Unknown binary op: {}", op),
+    }
+}
+
+/// What is left to do once a statement has started.
+enum Step<'a> {
+    /// It has run; `true` if it ended the run with `bind_off` or the row
+    /// limit.
+    Done(bool),
+    /// A `repeat` whose count came to the given number of passes, with the
+    /// body still to run.
+    Repeat(i64, &'a [Stmt]),
+    /// A `mirror` whose body is still to run.
+    Mirror(&'a [Stmt]),
+    /// An `alternate`, one of whose bodies is still to run.
+    Alternate(&'a [Vec<Stmt>]),
+}
+
+/// A `repeat` part way through its passes, a `mirror` or `alternate` part
+/// way through its body, or the program itself at the bottom of the stack.
+struct Frame<'a> {
+    block: Option<&'a Stmt>,
+    body: &'a [Stmt],
+    /// The index in `body` of the next statement of this pass.
+    next: usize,
+    /// Passes still to start once this one ends.
+    passes: i64,
+    /// The pass under way, counting from 0, which chooses the body of an
+    /// `alternate` inside it.
+    pass: i64,
+}
+
+/// How many units of work pass between looks at the clock and the interrupt
+/// flag, so `--timeout` and Ctrl-C cost next to nothing in hot loops.
+const TICKS_PER_DEADLINE_CHECK: u64 = 1024;
+
+impl Interpreter {
+    /// An interpreter that writes `purl` output to `out`.
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        Self {
+            vars: Vec::new(),
+            slots: SymbolMap::default(),
+            names: Vec::new(),
+            frame: Vec::new(),
+            saved: HashMap::new(),
+            var_count: 0,
+            builtins: HashMap::new(),
+            stack: Vec::new(),
+            out,
+            stats: RunStats::default(),
+            fabric: Fabric::default(),
+            row_limit: None,
+            truncated: false,
+            timeout: None,
+            started: Instant::now(),
+            timed_out: false,
+            step_limit: None,
+            steps: 0,
+            memory_limit: None,
+            interrupt: None,
+            interrupted: false,
+            events: None,
+            hook: None,
+            chart_symbols: ChartStyle::default(),
+            sizes: Vec::new(),
+            size: None,
+            chosen_size: None,
+            mirrors: Vec::new(),
+            deferred: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Stops runs once `rows` rows are complete; `None` lifts the limit.
+    pub fn set_row_limit(&mut self, rows: Option<u64>) {
+        self.row_limit = rows;
+    }
+
+    /// Aborts runs once `limit` has passed since the current top-level run
+    /// started, as set by [`Interpreter::restart_timeout`].
+    pub fn set_timeout(&mut self, limit: Option<Duration>) {
+        self.timeout = limit;
+    }
+
+    /// Starts the time the timeout allows over from now. Call it once per
+    /// top-level run, such as a whole program or one REPL entry, rather
+    /// than per [`Interpreter::run`], as a program run a statement at a
+    /// time would otherwise never time out; time spent between runs, such
+    /// as waiting at the prompt, then never counts.
+    pub fn restart_timeout(&mut self) {
+        self.started = Instant::now();
+    }
+
+    /// Aborts runs once this interpreter has done `steps` units of work
+    /// (statements, loop iterations and stitches). Unlike a timeout this is
+    /// deterministic, so the same program always stops at the same point.
+    pub fn set_step_limit(&mut self, steps: Option<u64>) {
+        self.step_limit = steps;
+    }
+
+    /// Aborts runs once what they hold, as counted by
+    /// [`Interpreter::memory_used`], comes to more than `bytes`.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.memory_limit = bytes;
+    }
+
+    /// Roughly how many bytes the variables and the fabric take up: a slot
+    /// for every variable with a value, and every stitch in every row.
+    /// Not exact, but it only grows as the program assigns variables and
+    /// works stitches, and goes back down when [`Interpreter::load_state`]
+    /// replaces them.
+    pub fn memory_used(&self) -> usize {
+        self.var_count * mem::size_of::<Option<i64>>() + self.fabric.bytes()
+    }
+
+    /// Stops runs with a runtime error soon after `flag` is set, and clears
+    /// it again, so the next run starts afresh. `None` stops watching.
+    pub fn set_interrupt_flag(&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.interrupt = flag;
+    }
+
+    /// Reports every statement, assignment, completed row, line of output and
+    /// runtime error to `sink` as it happens; `None` stops reporting.
+    pub fn set_event_sink(&mut self, sink: Option<Box<dyn EventSink>>) {
+        self.events = sink;
+    }
+
+    /// Runs every statement past `hook` first, which may skip it or abort the
+    /// run; `None` removes the hook.
+    pub fn set_hook(&mut self, hook: Option<Box<dyn ExecHook>>) {
+        self.hook = hook;
+    }
+
+    /// Charts each kind of stitch with `symbols` in the output of `chart`
+    /// statements, such as [`ChartStyle::ascii`] for a terminal that cannot
+    /// show the default's.
+    pub fn set_chart_symbols(&mut self, symbols: ChartStyle) {
+        self.chart_symbols = symbols;
+    }
+
+    /// Knits the size named `size` of those a program's `sizes` declaration
+    /// names, or the first of them for `None`. Declaring sizes without this
+    /// one among them is a runtime error.
+    ///
+    /// ```
+    /// let src = "sizes S, M, L; cast_on width = 88 (96, 104);";
+    /// let stmts = knitlang::parse_src(src).unwrap();
+    /// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+    /// interp.set_size(Some("M"));
+    /// interp.run(&stmts).unwrap();
+    /// assert_eq!((interp.get_var("width"), interp.get_var("SIZE")), (Some(96), Some(1)));
+    /// ```
+    pub fn set_size(&mut self, size: Option<&str>) {
+        self.chosen_size = size.map(Symbol::intern);
+    }
+
+    /// The sizes the last `sizes` declaration named; none if nothing has
+    /// declared any.
+    pub fn sizes(&self) -> &[Symbol] {
+        &self.sizes
+    }
+
+    /// Where the size being knitted comes in [`Interpreter::sizes`].
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// Makes `f` callable from programs as `name(...)` with exactly `arity`
+    /// arguments. If `f` returns an error, the run stops with that message
+    /// as a runtime error at the call. Registering a name again replaces the
+    /// function it had. Functions and variables are looked up separately, so
+    /// a function may share its name with a variable.
+    pub fn register_builtin(
+        &mut self,
+        name: &str,
+        arity: usize,
+        f: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let builtin = Builtin {
+            arity,
+            call: Box::new(f),
+        };
+        self.builtins.insert(Symbol::intern(name), builtin);
+    }
+
+    /// The warnings runs have given since this was last called, in the
+    /// order they were given: checkpoints, each a [`Code::StitchCountWarning`],
+    /// that found the wrong number of stitches but let the run go on.
+    pub fn take_warnings(&mut self) -> Vec<RuntimeError> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Whether the last run stopped because it reached the row limit.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Whether the last run was aborted by the timeout.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Whether the last run was stopped by the interrupt flag.
+    pub fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    /// Every variable defined so far and its value, sorted by name.
+    ///
+    /// ```
+    /// let src = "cast_on width = 4; cast_on height = width * 2;";
+    /// let interp = knitlang::run(src, Box::new(std::io::sink())).unwrap();
+    /// let vars: Vec<_> = interp.vars().collect();
+    /// assert_eq!(vars, [("height", &8), ("width", &4)]);
+    /// ```
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &Value)> {
+        let mut vars: Vec<_> = self
+            .names
+            .iter()
+            .zip(&self.vars)
+            .filter_map(|(name, value)| Some((name.as_str(), value.as_ref()?)))
+            .chain(self.saved.iter().map(|(name, value)| (&**name, value)))
+            .collect();
+        vars.sort_unstable_by_key(|&(name, _)| name);
+        vars.into_iter()
+    }
+
+    /// The value of variable `name`, if it has been cast on.
+    ///
+    /// ```
+    /// let interp = knitlang::run("cast_on width = 4;", Box::new(std::io::sink())).unwrap();
+    /// assert_eq!(interp.get_var("width"), Some(4));
+    /// assert_eq!(interp.get_var("height"), None);
+    /// ```
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        match Symbol::lookup(name) {
+            Some(name) => self.var(name),
+            None => self.saved.get(name).copied(),
+        }
+    }
+
+    fn var(&self, name: Symbol) -> Option<Value> {
+        match self.slots.get(&name) {
+            Some(&slot) => self.vars[slot as usize],
+            None => self.saved.get(name.as_str()).copied(),
+        }
+    }
+
+    /// The slot of `vars` for `name`, giving it one the first time, with
+    /// the value a loaded session saved for it if there is one.
+    fn slot(&mut self, name: Symbol) -> u32 {
+        if let Some(&slot) = self.slots.get(&name) {
+            return slot;
+        }
+        let slot = self.vars.len() as u32;
+        self.slots.insert(name, slot);
+        self.names.push(name);
+        self.vars.push(self.saved.remove(name.as_str()));
+        slot
+    }
+
+    /// Gives `name` a value, returning the one it had.
+    fn set_var(&mut self, name: Symbol, value: Value) -> Option<Value> {
+        let slot = self.slot(name) as usize;
+        self.set_slot(slot, value)
+    }
+
+    /// [`Interpreter::set_var`] for an assignment outside a numbered run,
+    /// kept out of line so the common path stays small.
+    #[cold]
+    #[inline(never)]
+    fn set_var_by_name(&mut self, name: Symbol, value: Value) -> Option<Value> {
+        self.set_var(name, value)
+    }
+
+    /// Gives the variable in slot `slot` of `vars` a value, returning the
+    /// one it had.
+    fn set_slot(&mut self, slot: usize, value: Value) -> Option<Value> {
+        let old = self.vars[slot].replace(value);
+        if old.is_none() {
+            self.var_count += 1;
+        }
+        old
+    }
+
+    /// Everything knitted so far.
+    ///
+    /// ```
+    /// let src = "cast_on 3; k 3; p 2;";
+    /// let interp = knitlang::run(src, Box::new(std::io::sink())).unwrap();
+    /// let fabric = interp.fabric();
+    /// assert_eq!(fabric.rows().len(), 1);
+    /// assert_eq!((fabric.width(), fabric.remaining()), (3, 1));
+    /// ```
+    pub fn fabric(&self) -> &Fabric {
+        &self.fabric
+    }
+
+    /// Counts a unit of work, failing once the run is past its step limit or
+    /// timeout, or has been interrupted.
+    fn tick(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if let Some(limit) = self.step_limit.filter(|&limit| self.steps > limit) {
+            return runtime_error(
+                Code::StepLimit,
+                format!("step limit of {limit} reached"),
+                span,
+            );
+        }
+        if !self.steps.is_multiple_of(TICKS_PER_DEADLINE_CHECK) {
+            return Ok(());
+        }
+        self.check_deadline(span)
+    }
+
+    /// Fails if the run holds more than the memory limit allows.
+    fn check_memory(&self, span: Span) -> Result<(), RuntimeError> {
+        match self.memory_limit {
+            Some(limit) if self.memory_used() > limit => runtime_error(
+                Code::MemoryLimit,
+                format!(
+                    "memory limit exceeded: the run holds {} bytes, more than the {limit} allowed",
+                    self.memory_used()
+                ),
+                span,
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// The part of `tick` that runs once every `TICKS_PER_DEADLINE_CHECK`
+    /// units, kept out of line so the common path stays small.
+    #[cold]
+    #[inline(never)]
+    fn check_deadline(&mut self, span: Span) -> Result<(), RuntimeError> {
+        if let Some(flag) = &self.interrupt {
+            if flag.swap(false, Ordering::Relaxed) {
+                self.interrupted = true;
+                return runtime_error(Code::Interrupted, "interrupted".to_string(), span);
+            }
+        }
+        match self.timeout {
+            Some(limit) if self.started.elapsed() >= limit => {
+                self.timed_out = true;
+                runtime_error(
+                    Code::Timeout,
+                    format!("timed out after {}", format_duration(limit)),
+                    span,
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A snapshot of the variables and fabric as a JSON object. Object keys
+    /// are sorted, so reports for the same program are byte-for-byte equal.
+    pub fn state_json(&self) -> serde_json::Value {
+        let variables: serde_json::Map<String, serde_json::Value> = self
+            .vars()
+            .map(|(name, value)| {
+                let value = serde_json::json!({ "type": "int", "value": value });
+                (name.to_string(), value)
+            })
+            .collect();
+        let mut state = serde_json::json!({
+            "variables": variables,
+            "fabric": {
+                "rows": self.fabric.rows.len(),
+                "stitches": self.fabric.width(),
+            },
+        });
+        if let Some(meta) = &self.fabric.meta {
+            state["meta"] = meta.to_json();
+        }
+        state
+    }
+
+    /// Writes the variables, the fabric and the run counters to `writer` as
+    /// JSON, for [`Interpreter::load_state`] to pick up later. Output, limits
+    /// and builtins are the host's to set up again and are not saved.
+    pub fn save_state(&self, mut writer: impl Write) -> Result<(), StateError> {
+        let stitches = |row: &[Stitch]| row.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let fabric = &self.fabric;
+        let state = json!({
+            "version": STATE_VERSION,
+            "variables": self.vars().collect::<BTreeMap<_, _>>(),
+            "fabric": {
+                "rows": fabric.rows.iter().map(|row| stitches(row)).collect::<Vec<_>>(),
+                "current": stitches(&fabric.current),
+                "yarns": fabric
+                    .yarns
+                    .iter()
+                    .map(|yarn| json!({ "name": yarn.name, "color": yarn.colour }))
+                    .collect::<Vec<_>>(),
+                "yarn": fabric.yarn,
+                "row_yarns": fabric.row_yarns,
+                "current_yarns": fabric.current_yarns,
+                "width": fabric.width,
+                "behind": fabric.behind,
+                "indents": fabric.indents,
+                "unworked": fabric.unworked,
+                "offset": fabric.offset,
+                "offsets": fabric.offsets,
+                "mode": fabric.mode.name(),
+                "sides": fabric
+                    .sides
+                    .iter()
+                    .map(|side| side.map(Side::abbreviation))
+                    .collect::<Vec<_>>(),
+                "markers": fabric
+                    .markers
+                    .iter()
+                    .map(|(name, place)| json!({ "name": name, "on": place.name(), "at": place.at() }))
+                    .collect::<Vec<_>>(),
+                "marks": fabric.marks,
+                "current_marks": fabric.current_marks,
+                "worked": fabric.worked,
+                "increases": fabric.increases,
+                "decreases": fabric.decreases,
+                "gauge": fabric.gauge.map(|gauge| json!([gauge.stitches, gauge.rows])),
+                "meta": fabric.meta.as_ref().map(|meta| {
+                    meta.entries()
+                        .map(|(key, value)| json!([key, value.to_json()]))
+                        .collect::<Vec<_>>()
+                }),
+                "notes": fabric
+                    .notes
+                    .iter()
+                    .map(|note| json!([note.row, note.text]))
+                    .collect::<Vec<_>>(),
+                "charted": fabric.charted,
+                "needles": fabric
+                    .needles
+                    .iter()
+                    .map(|(name, stitches)| json!([name, stitches]))
+                    .collect::<Vec<_>>(),
+                "off": fabric.off,
+                "on": fabric.on,
+                "offs": fabric.offs,
+                "ons": fabric.ons,
+            },
+            "statements": self.stats.statements,
+            "expressions": self.stats.expressions,
+            "max_depth": self.stats.max_depth,
+            "peak_variables": self.stats.peak_variables,
+            "steps": self.steps,
+            "sizes": self.sizes,
+            "size": self.size,
+        });
+        let written = serde_json::to_writer_pretty(&mut writer, &state)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"));
+        match written {
+            Ok(()) => Ok(()),
+            Err(e) => state_error(format!("could not write session: {e}")),
+        }
+    }
+
+    /// Replaces the variables, fabric and run counters with those saved by
+    /// [`Interpreter::save_state`], so running the rest of a program carries
+    /// on where the saved run stopped. Nothing changes if `reader` does not
+    /// hold a session this version can read.
+    pub fn load_state(&mut self, reader: impl Read) -> Result<(), StateError> {
+        let state: Json = match serde_json::from_reader(reader) {
+            Ok(state) => state,
+            Err(e) => return state_error(format!("not a saved session: {e}")),
+        };
+        let version = match state.get("version").and_then(Json::as_u64) {
+            Some(version @ 1..=STATE_VERSION) => version,
+            Some(version) => {
+                return state_error(format!(
+                    "session was saved in format version {version}, but this knitlang only \
+                     reads versions 1 to {STATE_VERSION}"
+                ))
+            }
+            None => return state_error("not a saved session: it has no format version"),
+        };
+        let variables = match state_field(&state, "variables")?.as_object() {
+            Some(variables) => variables,
+            None => return state_error("saved `variables` should be an object"),
+        };
+        let vars: Vec<_> = variables
+            .iter()
+            .map(|(name, value)| match value.as_i64() {
+                Some(value) => Ok((name, value)),
+                None => state_error(format!("saved variable `{name}` is not an integer")),
+            })
+            .collect::<Result<_, _>>()?;
+        let fabric = Fabric::from_state(state_field(&state, "fabric")?, version)?;
+        let statements = state_count(&state, "statements")?;
+        let expressions = state_count(&state, "expressions")?;
+        let max_depth = state_count(&state, "max_depth")?;
+        let peak_variables = state_count(&state, "peak_variables")?;
+        let steps = state_count(&state, "steps")?;
+        let (sizes, size) = if version < 6 {
+            (Vec::new(), None)
+        } else {
+            sizes_from_state(&state)?
+        };
+
+        self.vars.clear();
+        self.slots.clear();
+        self.names.clear();
+        self.saved.clear();
+        self.var_count = 0;
+        for (name, value) in vars {
+            match Symbol::lookup(name) {
+                Some(name) => {
+                    self.set_var(name, value);
+                }
+                None => {
+                    self.saved.insert(name.as_str().into(), value);
+                    self.var_count += 1;
+                }
+            }
+        }
+        self.fabric = fabric;
+        self.stats.statements = statements as u64;
+        self.stats.expressions = expressions as u64;
+        self.stats.max_depth = max_depth;
+        self.stats.peak_variables = peak_variables.max(self.var_count);
+        self.steps = steps as u64;
+        self.sizes = sizes;
+        self.size = size;
+        self.truncated = false;
+        self.timed_out = false;
+        Ok(())
+    }
+
+    /// Flushes any program output still buffered in the sink, and the event
+    /// sink if there is one.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(events) = &mut self.events {
+            events.flush()?;
+        }
+        self.out.flush()
+    }
+
+    fn emit(&mut self, event: Event<'_>) {
+        if let Some(events) = &mut self.events {
+            events.event(&event);
+        }
+    }
+
+    /// Gives `name`, in slot `target` of the program running, the value
+    /// `new`.
+    fn assign(&mut self, name: Symbol, target: Slot, new: Value) {
+        // Outside a numbered run, the frame is empty and the name is looked
+        // up instead.
+        let old = match self.frame.get(target.0 as usize) {
+            Some(&slot) if (slot as usize) < self.vars.len() => self.set_slot(slot as usize, new),
+            _ => self.set_var_by_name(name, new),
+        };
+        if old.is_none() {
+            self.stats.peak_variables = self.stats.peak_variables.max(self.var_count);
+        }
+        // Looking the name up takes the interner's lock, so it is only done
+        // for a sink that will see it.
+        if let Some(events) = &mut self.events {
+            events.event(&Event::Assign {
+                name: name.as_str(),
+                old,
+                new,
+            });
+        }
+    }
+
+    /// The error for the read `found`, suggesting a close name among the
+    /// variables it saw set, those set before the run and the builtins.
+    fn undefined<T>(&self, found: &resolve::Undefined) -> Result<T, RuntimeError> {
+        let vars = self.vars().map(|(name, _)| name);
+        let builtins = self.builtins.keys().map(|n| n.as_str());
+        Err(found.to_error(vars.chain(builtins)))
+    }
+
+    /// Evaluates `e` against the variables defined so far.
+    pub fn eval(&mut self, e: &Expr) -> Result<Value, RuntimeError> {
+        self.eval_expr(e)
+    }
+
+    fn eval_expr(&mut self, e: &Expr) -> Result<i64, RuntimeError> {
+        self.stats.expressions += 1;
+        // Arithmetic that cannot fail is worked out from the expression's
+        // steps. Anything else goes the long way round, which reports the
+        // error.
+        match self.quick(e.ops()) {
+            Some(value) => Ok(value),
+            None => self.eval_slowly(e),
+        }
+    }
+
+    /// Evaluates `e` node by node, reporting what goes wrong. Kept out of
+    /// line so the common path through [`Interpreter::eval_expr`] stays
+    /// small.
+    #[inline(never)]
+    fn eval_slowly(&mut self, e: &Expr) -> Result<i64, RuntimeError> {
+        // The scratch stack is reused so evaluation never allocates once it
+        // has grown to fit the deepest expression.
+        let mut stack = mem::take(&mut self.stack);
+        stack.clear();
+        let result = self.eval_nodes(e, &mut stack);
+        self.stack = stack;
+        result
+    }
+
+    /// The value of `ops`, or `None` if there are none or one of them
+    /// fails: a variable with no value, a division by zero or an overflow.
+    fn quick(&mut self, ops: &[Op]) -> Option<i64> {
+        let (vars, frame) = (&self.vars, &self.frame);
+        // A slot outside the frame is an expression evaluated outside a run,
+        // which the long way round looks up by name.
+        let operand = |operand| match operand {
+            Operand::Number(n) => Some(n),
+            Operand::Var(_, slot) => vars
+                .get(*frame.get(slot.0 as usize)? as usize)
+                .copied()
+                .flatten(),
+        };
+        let stack = &mut self.stack;
+        stack.clear();
+        // The first step pushes this, so the stack is never empty.
+        let mut value = 0;
+        for &op in ops {
+            value = match op {
+                Op::Push(x) => {
+                    stack.push(value);
+                    operand(x)?
+                }
+                Op::Apply(op, x) => arithmetic(value, op, operand(x)?)?,
+                Op::Pop(op) => arithmetic(stack.pop()?, op, value)?,
+            };
+        }
+        (!ops.is_empty()).then_some(value)
+    }
+
+    /// Operands come before their operator, so one pass pushing each value
+    /// and combining the top two at each operator evaluates `e`.
+    fn eval_nodes(&self, e: &Expr, stack: &mut Vec<i64>) -> Result<i64, RuntimeError> {
+        for node in e.nodes() {
+            let value = match node.kind {
+                ExprKind::Number(n) => n,
+                ExprKind::Var(name) => match self.var(name) {
+                    Some(value) => value,
+                    None => {
+                        let found = resolve::Undefined {
+                            name,
+                            span: node.span,
+                            assigned: Vec::new(),
+                        };
+                        return self.undefined(&found);
+                    }
+                },
+                ExprKind::StitchesTo(marker) => self.stitches_to(marker, node.span)?,
+                ExprKind::Count(needle) => self.needle(needle, node.span)? as Value,
+                ExprKind::Sizes(ref values) => self.size_value(values, node.span)?,
+                ExprKind::Call(name, ref args) => {
+                    let first = stack.len() - args.len();
+                    let value = self.call(name, &stack[first..], node.span)?;
+                    stack.truncate(first);
+                    value
+                }
+                ExprKind::Binary(_, op, rhs) => {
+                    let b = stack.pop().expect("operator without operands");
+                    let a = stack.pop().expect("operator without operands");
+                    if op == '/' && b == 0 {
+                        return runtime_error(
+                            Code::DivisionByZero,
+                            "division by zero".into(),
+                            e.node(rhs).span,
+                        );
+                    }
+                    match arithmetic(a, op, b) {
+                        Some(v) => v,
+                        None => {
+                            return runtime_error(
+                                Code::Overflow,
+                                format!("arithmetic overflow in {a} {op} {b}"),
+                                node.span,
+                            )
+                        }
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        Ok(stack.pop().expect("expression without a root"))
+    }
+
+    /// Calls builtin `name` on `args`, for the call at `span`.
+    fn call(&self, name: Symbol, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+        let Some(builtin) = self.builtins.get(&name) else {
+            let names = self.builtins.keys().map(|n| n.as_str());
+            return runtime_error(
+                Code::UnknownFunction,
+                format!(
+                    "unknown function `{name}`{}",
+                    did_you_mean(name.as_str(), names)
+                ),
+                span,
+            );
+        };
+        if args.len() != builtin.arity {
+            return runtime_error(
+                Code::WrongArgumentCount,
+                format!(
+                    "`{name}` takes {}, but was given {}",
+                    plural(builtin.arity, "argument"),
+                    args.len()
+                ),
+                span,
+            );
+        }
+        (builtin.call)(args).or_else(|message| runtime_error(Code::FunctionFailed, message, span))
+    }
+
+    /// Evaluates a stitch count, which must not be negative.
+    fn eval_count(&mut self, e: &Expr, what: &str) -> Result<usize, RuntimeError> {
+        let n = self.eval_expr(e)?;
+        match usize::try_from(n) {
+            Ok(n) => Ok(n),
+            Err(_) => runtime_error(
+                Code::NegativeCount,
+                format!("cannot {what} {n} stitches"),
+                e.span(),
+            ),
+        }
+    }
+
+    /// Evaluates the stitches or rows, `what`, of a gauge, which must be at
+    /// least 1.
+    fn eval_gauge(&mut self, e: &Expr, what: &str) -> Result<u32, RuntimeError> {
+        let n = self.eval_expr(e)?;
+        match u32::try_from(n) {
+            Ok(n) if n > 0 => Ok(n),
+            _ => runtime_error(
+                Code::InvalidGauge,
+                format!("a gauge of {n} {what} per 10 cm cannot be knitted; it needs at least 1"),
+                e.span(),
+            ),
+        }
+    }
+
+    /// Works `count` stitches, returning `Ok(true)` if the row limit was
+    /// reached part way through.
+    fn work(&mut self, stitch: Stitch, count: &Expr, span: Span) -> Result<bool, RuntimeError> {
+        let n = self.eval_count(count, "work")?;
+        for _ in 0..n {
+            if self.work_stitch(stitch, span)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Binds off `count` stitches from where the current row has got to,
+    /// returning `Ok(true)` if that ended the piece or reached the row
+    /// limit. Binding off every stitch on the needles ends the piece as a
+    /// bare `bind_off;` does.
+    fn bind_off(&mut self, count: &Expr, span: Span) -> Result<bool, RuntimeError> {
+        let n = self.eval_count(count, "bind off")?;
+        if self.fabric.width == 0 {
+            return runtime_error(
+                Code::NoStitches,
+                "no stitches to bind off; cast some on with `cast_on <count>;`".into(),
+                span,
+            );
+        }
+        let left = self.fabric.remaining();
+        if n > left {
+            return runtime_error(
+                Code::TooManyBoundOff,
+                format!(
+                    "cannot bind off {} with only {} left in the row",
+                    plural(n, "stitch"),
+                    plural(left, "stitch")
+                ),
+                span,
+            );
+        }
+        if n == left && self.fabric.produced == 0 && self.fabric.behind == 0 {
+            return Ok(true);
+        }
+        for _ in 0..n {
+            if self.work_stitch(Stitch::BindOff, span)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Records a `note` from its `parts` where the fabric has got to: before
+    /// the row being worked, or in the preamble if nothing is cast on yet.
+    fn note(&mut self, parts: &[NotePart]) -> Result<(), RuntimeError> {
+        let mut text = String::new();
+        for part in parts {
+            match part {
+                NotePart::Text(s) => text.push_str(s),
+                NotePart::Expr(e) => {
+                    let value = self.eval(e)?;
+                    text.push_str(&value.to_string());
+                }
+            }
+        }
+        let fabric = &mut self.fabric;
+        let row = (fabric.width > 0 || !fabric.rows.is_empty()).then_some(fabric.rows.len());
+        fabric.notes.push(Note { row, text });
+        Ok(())
+    }
+
+    /// Writes a text chart of the rows completed since the last `chart`
+    /// statement, or since the start, to the output, under `label` if it
+    /// has one.
+    fn chart(&mut self, label: Option<&str>, span: Span) -> Result<(), RuntimeError> {
+        let rows = self.fabric.charted..self.fabric.rows.len();
+        self.fabric.charted = rows.end;
+        let mut text = label.map(|label| format!("{label}\n")).unwrap_or_default();
+        let orientation = Orientation::default();
+        text += &chart::text_rows(&self.fabric, rows, orientation, &self.chart_symbols);
+        if let Err(e) = self.out.write_all(text.as_bytes()) {
+            return runtime_error(
+                Code::OutputFailed,
+                format!("could not write output: {e}"),
+                span,
+            );
+        }
+        if let Some(events) = &mut self.events {
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            events.event(&Event::Output { text });
+        }
+        Ok(())
+    }
+
+    /// Checks that there are `count` live stitches on the needles. A wrong
+    /// count fails with `code`, or if that is [`Code::StitchCountWarning`]
+    /// is kept as a warning for the host and the run goes on.
+    fn expect_stitches(
+        &mut self,
+        count: &Expr,
+        code: Code,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        let expected = self.eval_count(count, "expect")?;
+        let live = self.fabric.live();
+        if live == expected {
+            return Ok(());
+        }
+        let message = format!(
+            "expected {} on the needles, but there {} {live}",
+            plural(expected, "stitch"),
+            if live == 1 { "is" } else { "are" }
+        );
+        if code != Code::StitchCountWarning {
+            return runtime_error(code, message, span);
+        }
+        self.emit(Event::Warning {
+            message: &message,
+            span,
+        });
+        self.warnings.push(RuntimeError {
+            code,
+            message,
+            span,
+        });
+        Ok(())
+    }
+
+    /// Works one cable crossing `count` stitches over as many again,
+    /// returning `Ok(true)` if it reached the row limit.
+    fn cable(&mut self, count: &Expr, cross: Cross, span: Span) -> Result<bool, RuntimeError> {
+        match self.eval_count(count, "cable")? {
+            0 => runtime_error(
+                Code::EmptyCable,
+                "cannot cable 0 stitches; a cable crosses at least 1".to_string(),
+                count.span(),
+            ),
+            n => match u32::try_from(n) {
+                Ok(n) => self.work_stitch(Stitch::Cable(n, cross), span),
+                Err(_) => runtime_error(
+                    Code::CableTooWide,
+                    format!("cannot cable {n} stitches over {n}; the row is not that wide"),
+                    span,
+                ),
+            },
+        }
+    }
+
+    /// Works the rest of the row with `count` increases or decreases spread
+    /// evenly across it, as [`Shaping::spread`] lays them out, returning
+    /// `Ok(true)` if it reached the row limit.
+    fn distribute(
+        &mut self,
+        shaping: Shaping,
+        count: &Expr,
+        span: Span,
+    ) -> Result<bool, RuntimeError> {
+        let n = self.eval_count(count, shaping.verb())?;
+        if self.fabric.width == 0 {
+            return runtime_error(
+                Code::NoStitches,
+                format!(
+                    "no stitches to {} across; cast some on with `cast_on <count>;`",
+                    shaping.verb()
+                ),
+                span,
+            );
+        }
+        // Stitches a `mirror` block has put off are not left to shape.
+        let left = self.fabric.remaining() - self.deferred;
+        if shaping == Shaping::Decrease && n.saturating_mul(2) > left {
+            return runtime_error(
+                Code::TooManyDecreases,
+                format!(
+                    "cannot decrease {} evenly across {}; each decrease works 2",
+                    plural(n, "stitch"),
+                    plural(left, "stitch")
+                ),
+                span,
+            );
+        }
+        for (stitch, times) in shaping.spread(n, left) {
+            for _ in 0..times {
+                if self.work_stitch(stitch, span)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Works `stitch` once, or inside a `mirror` block puts it off, returning
+    /// `Ok(true)` if it reached the row limit.
+    fn work_stitch(&mut self, stitch: Stitch, span: Span) -> Result<bool, RuntimeError> {
+        if self.mirrors.is_empty() {
+            self.knit_stitch(stitch, span)
+        } else {
+            self.defer_stitch(stitch, span)
+        }
+    }
+
+    /// Works `stitch` once into the fabric, returning `Ok(true)` if it
+    /// reached the row limit.
+    fn knit_stitch(&mut self, stitch: Stitch, span: Span) -> Result<bool, RuntimeError> {
+        if self.fabric.width == 0 {
+            return runtime_error(
+                Code::NoStitches,
+                format!("no stitches to work {stitch}; cast some on with `cast_on <count>;`"),
+                span,
+            );
+        }
+        if stitch.consumes() > self.fabric.remaining() {
+            return runtime_error(
+                Code::CableTooWide,
+                format!(
+                    "cannot work {stitch} with only {} left in the row",
+                    plural(self.fabric.remaining(), "stitch")
+                ),
+                span,
+            );
+        }
+        if let Some(marker) = self.fabric.marker_within(stitch.consumes()) {
+            return runtime_error(
+                Code::MarkerSplit,
+                format!("cannot work {stitch} across marker `{marker}`; slip it first"),
+                span,
+            );
+        }
+        self.tick(span)?;
+        let completed = self.fabric.work(stitch);
+        self.check_memory(span)?;
+        if !completed {
+            return Ok(false);
+        }
+        Ok(self.row_ended())
+    }
+
+    /// Puts off working `stitch` until the stitches of the innermost `mirror`
+    /// block complete the row, or the block ends, then works them reversed,
+    /// each leaning the other way. Returns `Ok(true)` if that reached the
+    /// row limit.
+    fn defer_stitch(&mut self, stitch: Stitch, span: Span) -> Result<bool, RuntimeError> {
+        let Some(other) = stitch.mirror() else {
+            return runtime_error(
+                Code::Unmirrorable,
+                format!("cannot mirror {stitch}; no stitch leans the other way"),
+                span,
+            );
+        };
+        // A stitch that does not fit in what is left of the row comes after
+        // the row's other stitches, and is worked as it is to say why.
+        if self.deferred + stitch.consumes() > self.fabric.remaining() {
+            if self.work_deferred()? {
+                return Ok(true);
+            }
+            if stitch.consumes() > self.fabric.remaining() {
+                return self.knit_stitch(stitch, span);
+            }
+        }
+        let deferred = Deferred {
+            stitch: other,
+            other: stitch,
+            yarn: self.fabric.yarn,
+            span,
+        };
+        self.mirrors
+            .last_mut()
+            .expect("a mirror block")
+            .push(deferred);
+        self.deferred += stitch.consumes();
+        if self.deferred == self.fabric.remaining() {
+            return self.work_deferred();
+        }
+        Ok(false)
+    }
+
+    /// Works every stitch the `mirror` blocks being run have put off, each
+    /// block's reversed within the one around it, returning `Ok(true)` if
+    /// they reached the row limit.
+    fn work_deferred(&mut self) -> Result<bool, RuntimeError> {
+        for block in (1..self.mirrors.len()).rev() {
+            let inner = mem::take(&mut self.mirrors[block]);
+            let reversed = inner.into_iter().rev().map(Deferred::mirrored);
+            self.mirrors[block - 1].extend(reversed);
+        }
+        let Some(outermost) = self.mirrors.first_mut() else {
+            return Ok(false);
+        };
+        let stitches = mem::take(outermost);
+        self.deferred = 0;
+        let yarn = self.fabric.yarn;
+        let mut result = Ok(false);
+        for deferred in stitches.into_iter().rev() {
+            self.fabric.yarn = deferred.yarn;
+            result = self.knit_stitch(deferred.stitch, deferred.span);
+            if !matches!(result, Ok(false)) {
+                break;
+            }
+        }
+        self.fabric.yarn = yarn;
+        result
+    }
+
+    /// Ends the innermost `mirror` block: the stitches it put off join the
+    /// block around it, mirrored again, or if there is none, are worked.
+    /// Returns `Ok(true)` if they reached the row limit.
+    fn end_mirror(&mut self) -> Result<bool, RuntimeError> {
+        if self.mirrors.len() == 1 {
+            let ended = self.work_deferred()?;
+            self.mirrors.pop();
+            return Ok(ended);
+        }
+        let inner = self.mirrors.pop().expect("a mirror block");
+        let outer = self.mirrors.last_mut().expect("a mirror block");
+        outer.extend(inner.into_iter().rev().map(Deferred::mirrored));
+        Ok(false)
+    }
+
+    /// Works whole rows of `pattern` across the stitches on the needles, as
+    /// many as its last argument says, returning `Ok(true)` if it reached the
+    /// row limit.
+    fn work_pattern(
+        &mut self,
+        pattern: Pattern,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<bool, RuntimeError> {
+        let name = pattern.name();
+        let mut values = Vec::with_capacity(args.len());
+        for (arg, param) in args.iter().zip(pattern.params()) {
+            let n = self.eval_expr(arg)?;
+            match usize::try_from(n) {
+                Ok(n) => values.push(n),
+                Err(_) => {
+                    return runtime_error(
+                        Code::NegativeCount,
+                        format!("cannot work {name} with {n} for `{param}`"),
+                        arg.span(),
+                    )
+                }
+            }
+        }
+        if self.fabric.width == 0 {
+            return runtime_error(
+                Code::NoStitches,
+                format!("no stitches to work {name} across; cast some on with `cast_on <count>;`"),
+                span,
+            );
+        }
+        if !self.fabric.current.is_empty() || self.deferred > 0 {
+            return runtime_error(
+                Code::UnworkablePattern,
+                format!(
+                    "cannot start {name} part way through a row ({} left to work)",
+                    plural(self.fabric.remaining() - self.deferred, "stitch")
+                ),
+                span,
+            );
+        }
+        if pattern == Pattern::Rib && values[0] + values[1] == 0 {
+            return runtime_error(
+                Code::UnworkablePattern,
+                "cannot work a rib of no knits and no purls".to_string(),
+                span,
+            );
+        }
+        let rows = values[values.len() - 1];
+        for row in 0..rows {
+            let width = self.fabric.remaining();
+            let side = self.fabric.next_side();
+            for worked in 0..width {
+                let stitch = pattern_stitch(pattern, &values, row, worked, width, side);
+                if self.work_stitch(stitch, span)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Ends the current row at a wrap and turn, returning `Ok(true)` if it
+    /// reached the row limit.
+    fn wrap_turn(&mut self, span: Span) -> Result<bool, RuntimeError> {
+        if self.fabric.width == 0 {
+            return runtime_error(
+                Code::NoStitches,
+                "no stitches to wrap and turn; cast some on with `cast_on <count>;`".to_string(),
+                span,
+            );
+        }
+        if self.fabric.mode == Mode::Round {
+            return runtime_error(
+                Code::ModeConflict,
+                "cannot wrap and turn in the round; work short rows flat with `mode flat;`"
+                    .to_string(),
+                span,
+            );
+        }
+        if self.fabric.current.is_empty() {
+            return runtime_error(
+                Code::EmptyShortRow,
+                "cannot wrap and turn before working a stitch of the row".to_string(),
+                span,
+            );
+        }
+        self.fabric.wrap_turn();
+        self.check_memory(span)?;
+        Ok(self.row_ended())
+    }
+
+    /// Knits the chosen size of `names` from here on, setting
+    /// [`SIZE_VARIABLE`] to where it comes among them.
+    fn declare_sizes(&mut self, names: &[Symbol], span: Span) -> Result<(), RuntimeError> {
+        let size = match self.chosen_size {
+            None => 0,
+            Some(chosen) => match names.iter().position(|&name| name == chosen) {
+                Some(size) => size,
+                None => {
+                    let declared: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+                    return runtime_error(
+                        Code::UnknownSize,
+                        format!(
+                            "there is no size `{chosen}`; the pattern is sized {}{}",
+                            declared.join(", "),
+                            did_you_mean(chosen.as_str(), declared.iter().copied())
+                        ),
+                        span,
+                    );
+                }
+            },
+        };
+        self.sizes = names.to_vec();
+        self.size = Some(size);
+        self.assign(Symbol::intern(SIZE_VARIABLE), Slot::NONE, size as Value);
+        Ok(())
+    }
+
+    /// The number in the size tuple `values` for the size being knitted.
+    fn size_value(&self, values: &[Value], span: Span) -> Result<Value, RuntimeError> {
+        match self.size {
+            Some(size) if values.len() == self.sizes.len() => Ok(values[size]),
+            Some(_) => runtime_error(
+                Code::SizeCount,
+                format!(
+                    "size tuple has {} but `sizes` declares {}",
+                    plural(values.len(), "number"),
+                    plural(self.sizes.len(), "size")
+                ),
+                span,
+            ),
+            None => runtime_error(
+                Code::SizeCount,
+                "size tuple has no sizes to choose from; declare them first with \
+                 `sizes <name>, ...;`"
+                    .to_string(),
+                span,
+            ),
+        }
+    }
+
+    /// Works the rows from the next one on in `mode`, which can only change
+    /// between rows worked to the edge.
+    fn set_mode(&mut self, mode: Mode, span: Span) -> Result<(), RuntimeError> {
+        if mode == self.fabric.mode {
+            return Ok(());
+        }
+        if !self.fabric.current.is_empty() {
+            return runtime_error(
+                Code::ModeConflict,
+                format!(
+                    "cannot change mode in the middle of a row ({} left to work)",
+                    plural(self.fabric.remaining(), "stitch")
+                ),
+                span,
+            );
+        }
+        if self.fabric.behind > 0 {
+            return runtime_error(
+                Code::ModeConflict,
+                format!(
+                    "cannot change mode after a wrap and turn ({} still behind the turn)",
+                    plural(self.fabric.behind, "stitch")
+                ),
+                span,
+            );
+        }
+        self.fabric.mode = mode;
+        Ok(())
+    }
+
+    /// Where marker `name` is, or an error at `span` if it is not on the
+    /// needles.
+    fn marker(&self, name: Symbol, span: Span) -> Result<Place, RuntimeError> {
+        if let Some(place) = self.fabric.marker(name) {
+            return Ok(place);
+        }
+        let markers = self.fabric.markers.iter().map(|(name, _)| name.as_str());
+        let suggestion = did_you_mean(name.as_str(), markers);
+        runtime_error(
+            Code::UnplacedMarker,
+            format!("marker `{name}` is not on the needles{suggestion}"),
+            span,
+        )
+    }
+
+    /// Slips marker `name`, which the current row must have reached.
+    fn slip_marker(&mut self, name: Symbol, span: Span) -> Result<(), RuntimeError> {
+        let place = self.marker(name, span)?;
+        if self.fabric.at_work(place) {
+            self.fabric.slip_markers();
+            return Ok(());
+        }
+        let message = match self.fabric.stitches_to(place) {
+            Some(n) => format!(
+                "cannot slip marker `{name}` yet; it is {} further on",
+                plural(n, "stitch")
+            ),
+            None => format!("cannot slip marker `{name}`; this row has already passed it"),
+        };
+        runtime_error(Code::MarkerOutOfReach, message, span)
+    }
+
+    /// How many stitches the current row has to work to reach marker
+    /// `name`, for `stitches_to` at `span`.
+    fn stitches_to(&self, name: Symbol, span: Span) -> Result<Value, RuntimeError> {
+        let place = self.marker(name, span)?;
+        match self.fabric.stitches_to(place) {
+            Some(n) => Ok(n as Value),
+            None => runtime_error(
+                Code::MarkerOutOfReach,
+                format!("marker `{name}` is behind the work; this row has already passed it"),
+                span,
+            ),
+        }
+    }
+
+    /// The stitches on needle `name`, or an error at `span` if nothing has
+    /// declared it.
+    fn needle(&self, name: Symbol, span: Span) -> Result<usize, RuntimeError> {
+        let needles = &self.fabric.needles;
+        if let Some(&(_, stitches)) = needles.iter().find(|(needle, _)| *needle == name) {
+            return Ok(stitches);
+        }
+        let suggestion = did_you_mean(name.as_str(), needles.iter().map(|(n, _)| n.as_str()));
+        runtime_error(
+            Code::UndeclaredNeedle,
+            format!("needle `{name}` is not declared{suggestion}"),
+            span,
+        )
+    }
+
+    /// `transfer from to count;`: moves `count` stitches from needle `from`
+    /// onto needle `to`, declaring `to` if it is new.
+    fn transfer(
+        &mut self,
+        from: Symbol,
+        to: Symbol,
+        count: &Expr,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        let held = self.needle(from, span)?;
+        let n = self.eval_count(count, "transfer")?;
+        if n > held {
+            return runtime_error(
+                Code::TooManyTransferred,
+                format!(
+                    "cannot transfer {} from needle `{from}`, which holds {held}",
+                    plural(n, "stitch")
+                ),
+                count.span(),
+            );
+        }
+        *self.fabric.needle_mut(from) -= n;
+        *self.fabric.needle_mut(to) += n;
+        Ok(())
+    }
+
+    /// Checks that no row is part way through for `what`, `hold` or
+    /// `resume`, to move stitches on or off the working needle.
+    fn between_rows(&self, what: &str, span: Span) -> Result<(), RuntimeError> {
+        let fabric = &self.fabric;
+        if !fabric.current.is_empty() {
+            return runtime_error(
+                Code::NeedleMidRow,
+                format!(
+                    "cannot {what} stitches in the middle of a row ({} left to work)",
+                    plural(fabric.remaining(), "stitch")
+                ),
+                span,
+            );
+        }
+        if fabric.behind > 0 {
+            return runtime_error(
+                Code::NeedleMidRow,
+                format!(
+                    "cannot {what} stitches after a wrap and turn ({} still behind the turn)",
+                    plural(fabric.behind, "stitch")
+                ),
+                span,
+            );
+        }
+        Ok(())
+    }
+
+    /// Reports the row just completed, returning `true` if it reached the
+    /// row limit.
+    fn row_ended(&mut self) -> bool {
+        let index = self.fabric.rows.len() - 1;
+        let width = self.fabric.width();
+        self.emit(Event::Row { index, width });
+        if self.row_limit == Some(self.fabric.rows.len() as u64) {
+            self.truncated = true;
+            return true;
+        }
+        false
+    }
+
+    /// Asks the hook, if there is one, what to do with `s`.
+    fn before_stmt(&mut self, s: &Stmt) -> HookAction {
+        // The hook is taken out while it runs so it can look at `self`.
+        let Some(mut hook) = self.hook.take() else {
+            return HookAction::Continue;
+        };
+        let action = hook.before_stmt(s, self);
+        self.hook = Some(hook);
+        action
+    }
+
+    fn after_stmt(&mut self, s: &Stmt) {
+        if let Some(mut hook) = self.hook.take() {
+            hook.after_stmt(s, self);
+            self.hook = Some(hook);
+        }
+    }
+
+    /// Starts `s`: a `repeat` only has its count evaluated, leaving the caller
+    /// to run its body, and anything else runs to completion.
+    fn start_stmt<'a>(&mut self, s: &'a Stmt) -> Result<Step<'a>, RuntimeError> {
+        self.stats.statements += 1;
+        self.tick(s.span)?;
+        self.emit(Event::Statement {
+            kind: s.kind.name(),
+            span: s.span,
+        });
+        if !self.mirrors.is_empty() {
+            if let Some(what) = unmirrorable(&s.kind) {
+                return runtime_error(
+                    Code::Unmirrorable,
+                    format!("cannot {what} inside `mirror`; only stitches can be mirrored"),
+                    s.span,
+                );
+            }
+        }
+        let ended = match &s.kind {
+            StmtKind::CastOn(name, expr) => {
+                let v = self.eval_expr(expr)?;
+                self.assign(*name, expr.target(), v);
+                self.check_memory(s.span)?;
+                false
+            }
+            StmtKind::CastOnStitches(expr) => {
+                let n = self.eval_count(expr, "cast on")?;
+                if self.fabric.current.is_empty() && self.fabric.behind > 0 {
+                    return runtime_error(
+                        Code::CastOnMidRow,
+                        format!(
+                            "cannot cast on after a wrap and turn ({} still behind the turn)",
+                            plural(self.fabric.behind, "stitch")
+                        ),
+                        s.span,
+                    );
+                }
+                if !self.fabric.current.is_empty() {
+                    return runtime_error(
+                        Code::CastOnMidRow,
+                        format!(
+                            "cannot cast on in the middle of a row ({} left to work)",
+                            plural(self.fabric.remaining(), "stitch")
+                        ),
+                        s.span,
+                    );
+                }
+                self.fabric.width += n;
+                false
+            }
+            StmtKind::Knit(name, expr) => {
+                let v = self.eval_expr(expr)?;
+                self.assign(*name, expr.target(), v);
+                self.check_memory(s.span)?;
+                false
+            }
+            StmtKind::Work(stitch, count) => self.work(*stitch, count, s.span)?,
+            StmtKind::Cable(count, cross) => self.cable(count, *cross, s.span)?,
+            StmtKind::Distribute(shaping, count) => self.distribute(*shaping, count, s.span)?,
+            StmtKind::WrapTurn => self.wrap_turn(s.span)?,
+            StmtKind::Mode(mode) => {
+                self.set_mode(*mode, s.span)?;
+                false
+            }
+            StmtKind::Purl(expr) => {
+                let v = self.eval_expr(expr)?;
+                if let Err(e) = writeln!(self.out, "{}", v) {
+                    return runtime_error(
+                        Code::OutputFailed,
+                        format!("could not write output: {e}"),
+                        s.span,
+                    );
+                }
+                if let Some(events) = &mut self.events {
+                    let text = v.to_string();
+                    events.event(&Event::Output { text: &text });
+                }
+                false
+            }
+            StmtKind::Repeat(count_expr, body) => {
+                let passes = self.eval_expr(count_expr)?;
+                if passes < 0 {
+                    return runtime_error(
+                        Code::NegativeCount,
+                        format!("cannot repeat {passes} times"),
+                        count_expr.span(),
+                    );
+                }
+                return Ok(Step::Repeat(passes, body));
+            }
+            StmtKind::Mirror(body) => {
+                self.mirrors.push(Vec::new());
+                return Ok(Step::Mirror(body));
+            }
+            StmtKind::Alternate(bodies) if !bodies.is_empty() => {
+                return Ok(Step::Alternate(bodies));
+            }
+            StmtKind::Alternate(_) => false,
+            StmtKind::Pattern(pattern, args) => self.work_pattern(*pattern, args, s.span)?,
+            StmtKind::BindOff => true,
+            StmtKind::BindOffStitches(count) => self.bind_off(count, s.span)?,
+            StmtKind::ExpectStitches(count) => {
+                self.expect_stitches(count, Code::WrongStitchCount, s.span)?;
+                false
+            }
+            StmtKind::ExpectStitchesWarning(count) => {
+                self.expect_stitches(count, Code::StitchCountWarning, s.span)?;
+                false
+            }
+            StmtKind::Yarn(name, colour) => {
+                self.fabric.declare_yarn(name.as_str(), colour);
+                false
+            }
+            StmtKind::Color(name) => {
+                if !self.fabric.use_yarn(name.as_str()) {
+                    let yarns = self.fabric.yarns.iter().map(|yarn| yarn.name.as_str());
+                    let suggestion = did_you_mean(name.as_str(), yarns);
+                    return runtime_error(
+                        Code::UndeclaredYarn,
+                        format!("yarn `{name}` is not declared{suggestion}"),
+                        s.span,
+                    );
+                }
+                false
+            }
+            StmtKind::PlaceMarker(name) => {
+                if self.fabric.width == 0 {
+                    return runtime_error(
+                        Code::NoStitches,
+                        format!(
+                            "no stitches to place marker `{name}` between; cast some on with \
+                             `cast_on <count>;`"
+                        ),
+                        s.span,
+                    );
+                }
+                self.fabric.place_marker(*name);
+                false
+            }
+            StmtKind::SlipMarker(name) => {
+                self.slip_marker(*name, s.span)?;
+                false
+            }
+            StmtKind::Sizes(names) => {
+                self.declare_sizes(names, s.span)?;
+                false
+            }
+            StmtKind::Gauge(stitches, rows) => {
+                let stitches = self.eval_gauge(stitches, "stitches")?;
+                let rows = self.eval_gauge(rows, "rows")?;
+                self.fabric.gauge = Some(Gauge { stitches, rows });
+                false
+            }
+            // A file imported after the program's own `meta` block runs
+            // cannot replace it.
+            StmtKind::Meta(entries) => {
+                if self.fabric.meta.is_none() {
+                    self.fabric.meta = Some(PatternMeta::from_entries(entries));
+                }
+                false
+            }
+            StmtKind::Note(parts) => {
+                self.note(parts)?;
+                false
+            }
+            StmtKind::CastOnNeedle(name, count) => {
+                let n = self.eval_count(count, "cast on")?;
+                *self.fabric.needle_mut(*name) += n;
+                false
+            }
+            StmtKind::Transfer(from, to, count) => {
+                self.transfer(*from, *to, count, s.span)?;
+                false
+            }
+            StmtKind::Hold(name) => {
+                self.between_rows("hold", s.span)?;
+                let fabric = &mut self.fabric;
+                let held = fabric.width + fabric.behind;
+                *fabric.needle_mut(*name) += held;
+                fabric.off += held;
+                fabric.width = 0;
+                // The markers come off with the stitches.
+                fabric.markers.clear();
+                false
+            }
+            StmtKind::Resume(name) => {
+                let held = self.needle(*name, s.span)?;
+                self.between_rows("resume", s.span)?;
+                let fabric = &mut self.fabric;
+                *fabric.needle_mut(*name) = 0;
+                fabric.on += held;
+                fabric.width += held;
+                false
+            }
+            StmtKind::Chart(label) => {
+                self.chart(label.as_deref(), s.span)?;
+                false
+            }
+            StmtKind::Use(path, _) => {
+                return runtime_error(
+                    Code::UnloadedImport,
+                    format!(
+                        "cannot run `use \"{path}\"`; imports are loaded before the program runs"
+                    ),
+                    s.span,
+                );
+            }
+        };
+        Ok(Step::Done(ended))
+    }
+
+    /// Points the slots of the program about to run at the variables they
+    /// stand for.
+    fn enter(&mut self, slots: &resolve::Slots) {
+        let mut frame = mem::take(&mut self.frame);
+        frame.clear();
+        frame.extend(
+            slots
+                .names
+                .iter()
+                .map(|name| name.map_or(u32::MAX, |name| self.slot(name))),
+        );
+        self.frame = frame;
+    }
+
+    /// Runs `stmts` in order, returning `Ok(true)` if the program ended with
+    /// `bind_off` or the row limit.
+    pub fn run(&mut self, stmts: &[Stmt]) -> Result<bool, RuntimeError> {
+        let started = Instant::now();
+        self.interrupted = false;
+        let result = match resolve::resolve(stmts, |name| self.var(name).is_some()) {
+            Err(found) => self.undefined(&found),
+            Ok(slots) => {
+                // A tree that is not numbered as one program, such as one put
+                // together by hand, runs with an empty frame, which looks
+                // every variable up by name.
+                if !slots.unnumbered {
+                    self.enter(&slots);
+                }
+                self.run_stmts(stmts)
+            }
+        };
+        self.frame.clear();
+        // A run that ended inside a `mirror` block leaves its stitches
+        // unworked, as if it had ended before them.
+        self.mirrors.clear();
+        self.deferred = 0;
+        self.stats.elapsed += started.elapsed();
+        if let Err(e) = &result {
+            self.emit(Event::Error {
+                message: &e.message,
+                span: e.span,
+            });
+        }
+        result
+    }
+
+    /// Runs `stmts`, returning `Ok(true)` if the program ended with
+    /// `bind_off` or the row limit. Each `repeat` being run is a [`Frame`] on
+    /// a stack rather than a call, so nesting is limited only by memory, and
+    /// ending the run early just leaves the stack behind.
+    fn run_stmts(&mut self, stmts: &[Stmt]) -> Result<bool, RuntimeError> {
+        let mut frames = vec![Frame {
+            block: None,
+            body: stmts,
+            next: 0,
+            passes: 0,
+            pass: 0,
+        }];
+        while let Some(frame) = frames.last_mut() {
+            let Some(s) = frame.body.get(frame.next) else {
+                match frame.block {
+                    Some(repeat) if frame.passes > 0 => {
+                        frame.passes -= 1;
+                        frame.pass += 1;
+                        frame.next = 0;
+                        self.tick(repeat.span)?;
+                    }
+                    Some(block) => {
+                        frames.pop();
+                        if matches!(block.kind, StmtKind::Mirror(_)) && self.end_mirror()? {
+                            return Ok(true);
+                        }
+                        self.after_stmt(block);
+                    }
+                    None => break,
+                }
+                continue;
+            };
+            frame.next += 1;
+            match self.before_stmt(s) {
+                HookAction::Continue => {}
+                HookAction::Skip => continue,
+                HookAction::Stop => return Ok(true),
+                HookAction::Abort(reason) => return runtime_error(Code::Aborted, reason, s.span),
+            }
+            match self.start_stmt(s)? {
+                Step::Done(ended) => {
+                    self.after_stmt(s);
+                    if ended {
+                        return Ok(true);
+                    }
+                }
+                Step::Repeat(passes, body) => {
+                    // The first pass starts like every other, at the end of
+                    // the one before.
+                    frames.push(Frame {
+                        block: Some(s),
+                        body,
+                        next: body.len(),
+                        passes,
+                        pass: -1,
+                    });
+                    self.stats.max_depth = self.stats.max_depth.max(frames.len() - 1);
+                }
+                Step::Mirror(body) => {
+                    frames.push(Frame {
+                        block: Some(s),
+                        body,
+                        next: 0,
+                        passes: 0,
+                        pass: 0,
+                    });
+                    self.stats.max_depth = self.stats.max_depth.max(frames.len() - 1);
+                }
+                Step::Alternate(bodies) => {
+                    // The innermost `repeat` running it takes turns between
+                    // its bodies, a pass each.
+                    let repeat = frames.iter().rev().find(|frame| {
+                        frame
+                            .block
+                            .is_some_and(|block| matches!(block.kind, StmtKind::Repeat(..)))
+                    });
+                    let Some(repeat) = repeat else {
+                        return runtime_error(
+                            Code::AlternateOutsideRepeat,
+                            "alternate blocks only go inside a repeat, whose passes they take \
+                             turns over"
+                                .to_string(),
+                            s.span,
+                        );
+                    };
+                    let body = &bodies[repeat.pass as usize % bodies.len()];
+                    frames.push(Frame {
+                        block: Some(s),
+                        body,
+                        next: 0,
+                        passes: 0,
+                        pass: 0,
+                    });
+                    self.stats.max_depth = self.stats.max_depth.max(frames.len() - 1);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// The counters for every run so far, consistent with the fabric. A run
+    /// that failed counts everything it did before the error.
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            rows: self.fabric.rows.len(),
+            stitches: self.fabric.worked,
+            increases: self.fabric.increases,
+            decreases: self.fabric.decreases,
+            variables: self.var_count,
+            ..self.stats.clone()
+        }
+    }
+}
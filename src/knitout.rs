@@ -0,0 +1,272 @@
+//! [Knitout] for a [`Fabric`], the instructions a knitting machine driven by
+//! it works through. Only flat pieces of plain knit and purl stitches can be
+//! exported so far, knitted on the front bed in one yarn, carrier 1, with
+//! the right side facing out of the machine.
+//!
+//! The stitches of the first row are cast on with alternating tucks, in two
+//! passes that end where the row starts. A right-side row is worked from the
+//! right, from the highest needle down, and a wrong-side row back from the
+//! left. Every stitch that shows as a purl on the right side is moved to the
+//! back bed with `xfer` before its row and knitted there, then moved back
+//! after it. Once the last row is done, a chain bind-off works back the
+//! other way, moving each stitch onto the next and knitting them together;
+//! the last stitch stays on its needle for the tail to be pulled through.
+//!
+//! [Knitout]: https://textiles-lab.github.io/knitout/knitout.html
+
+use crate::ast::Stitch;
+use crate::interp::{Fabric, Side};
+use crate::plural;
+use std::fmt::{self, Write};
+
+/// The carrier the yarn is in.
+const CARRIER: u32 = 1;
+
+/// Something knitted in rows `rows` that Knitout export cannot do yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+    /// What it is, in the plural, such as `cables`.
+    pub what: &'static str,
+    /// The rows it was knitted in, counting from 1, in order.
+    pub rows: Vec<usize>,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} are not supported for knitout export, ", self.what)?;
+        // Runs of rows one after another are written as ranges.
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for &row in &self.rows {
+            match runs.last_mut() {
+                Some((_, last)) if *last + 1 == row => *last = row,
+                _ => runs.push((row, row)),
+            }
+        }
+        let rows = self.rows.len() > 1;
+        f.write_str(if rows { "in rows " } else { "in row " })?;
+        for (i, &(first, last)) in runs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(if i + 1 == runs.len() { " and " } else { ", " })?;
+            }
+            if first == last {
+                write!(f, "{first}")?;
+            } else {
+                write!(f, "{first}–{last}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Which way the carriage moves along the beds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// Towards the higher needles, to the right.
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn of(side: Side) -> Self {
+        match side {
+            Side::Right => Direction::Down,
+            Side::Wrong => Direction::Up,
+        }
+    }
+
+    fn reverse(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+
+    /// Needles 1 to `needles` in this direction.
+    fn needles(self, needles: usize) -> Box<dyn Iterator<Item = usize>> {
+        match self {
+            Direction::Up => Box::new(1..=needles),
+            Direction::Down => Box::new((1..=needles).rev()),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Direction::Up => "+",
+            Direction::Down => "-",
+        })
+    }
+}
+
+/// The Knitout for `fabric`: a header, then the cast-on, its completed rows
+/// and a bind-off of the stitches left, with a comment before each. A
+/// fabric with no rows only casts on and binds off. If anything in it
+/// cannot be exported, every kind of thing that cannot is returned instead,
+/// in the order they were first knitted:
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 2; k 2;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let knitout = knitlang::knitout::knitout(interp.fabric()).unwrap();
+/// assert!(knitout.starts_with(";!knitout-2\n"));
+/// assert!(knitout.contains("; Row 1 (RS)\nknit - f2 1\nknit - f1 1\n"));
+///
+/// let stmts = knitlang::parse_src("cast_on 4; k 4; p 4; c4f;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let errors = knitlang::knitout::knitout(interp.fabric()).unwrap_err();
+/// assert_eq!(
+///     errors[0].to_string(),
+///     "cables are not supported for knitout export, in row 3"
+/// );
+/// ```
+pub fn knitout(fabric: &Fabric) -> Result<String, Vec<Unsupported>> {
+    check(fabric)?;
+    let rows = fabric.rows();
+    let needles = rows.first().map_or(fabric.width(), |row| row.len());
+    let mut out = String::from(";!knitout-2\n;;Carriers: 1 2 3 4 5 6 7 8 9 10\n");
+    if needles == 0 {
+        return Ok(out);
+    }
+    let first = Direction::of(fabric.side(0).unwrap_or(Side::Right));
+    let _ = writeln!(out, "; Cast on {}", plural(needles, "st"));
+    let _ = writeln!(out, "in {CARRIER}");
+    // The first pass tucks every other needle, starting at the far edge
+    // from where the first row starts, and the second the rest on the way
+    // back.
+    let from_start = |needle: usize| match first {
+        Direction::Down => needles - needle,
+        Direction::Up => needle - 1,
+    };
+    for (pass, direction) in [first, first.reverse()].into_iter().enumerate() {
+        let mut tucked = false;
+        for needle in direction.needles(needles) {
+            if from_start(needle) % 2 == pass {
+                let _ = writeln!(out, "tuck {direction} f{needle} {CARRIER}");
+                tucked = true;
+            }
+        }
+        if !tucked {
+            // One stitch: the carrier still goes back to where the row starts.
+            let _ = writeln!(out, "miss {direction} f1 {CARRIER}");
+        }
+    }
+
+    let mut last = first;
+    for (r, row) in rows.iter().enumerate() {
+        let side = fabric.side(r).unwrap_or(Side::Right);
+        let direction = Direction::of(side);
+        let _ = writeln!(out, "; Row {} ({})", r + 1, side.abbreviation());
+        // Stitch 1 is on the needle the row starts at.
+        let needle = |i: usize| match direction {
+            Direction::Down => needles - i,
+            Direction::Up => i + 1,
+        };
+        let back = |stitch: Stitch| (stitch == Stitch::Purl) == (side == Side::Right);
+        let mut moved: Vec<usize> = (0..row.len())
+            .filter(|&i| back(row[i]))
+            .map(needle)
+            .collect();
+        moved.sort_unstable();
+        for &n in &moved {
+            let _ = writeln!(out, "xfer f{n} b{n}");
+        }
+        for (i, &stitch) in row.iter().enumerate() {
+            let bed = if back(stitch) { 'b' } else { 'f' };
+            let _ = writeln!(out, "knit {direction} {bed}{} {CARRIER}", needle(i));
+        }
+        for &n in &moved {
+            let _ = writeln!(out, "xfer b{n} f{n}");
+        }
+        last = direction;
+    }
+
+    let direction = if rows.is_empty() {
+        first
+    } else {
+        last.reverse()
+    };
+    let _ = writeln!(out, "; Bind off {}", plural(needles, "st"));
+    let order: Vec<usize> = direction.needles(needles).collect();
+    let rack = match direction {
+        Direction::Up => "1",
+        Direction::Down => "-1",
+    };
+    for pair in order.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let _ = writeln!(out, "xfer f{from} b{from}");
+        let _ = writeln!(out, "rack {rack}");
+        let _ = writeln!(out, "xfer b{from} f{to}");
+        let _ = writeln!(out, "rack 0");
+        let _ = writeln!(out, "knit {direction} f{to} {CARRIER}");
+    }
+    let _ = writeln!(out, "out {CARRIER}");
+    Ok(out)
+}
+
+/// Everything in `fabric` that [`knitout`] cannot export.
+fn check(fabric: &Fabric) -> Result<(), Vec<Unsupported>> {
+    let mut unsupported: Vec<Unsupported> = Vec::new();
+    let mut found =
+        |what: &'static str, row: usize| match unsupported.iter_mut().find(|u| u.what == what) {
+            Some(u) if u.rows.last() == Some(&row) => {}
+            Some(u) => u.rows.push(row),
+            None => unsupported.push(Unsupported {
+                what,
+                rows: vec![row],
+            }),
+        };
+    // Live stitches on the needles after each row, and the one yarn the
+    // piece is knitted in.
+    let mut live = None;
+    let mut yarn = None;
+    for (r, row) in fabric.rows().iter().enumerate() {
+        if fabric.side(r).is_none() {
+            found("rounds", r + 1);
+        }
+        if fabric.indent(r) > 0 || fabric.unworked(r) > 0 {
+            found("short rows", r + 1);
+        }
+        if fabric.moved(r) != (0, 0) {
+            found("stitches on holders", r + 1);
+        }
+        for (i, &stitch) in row.iter().enumerate() {
+            match stitch {
+                Stitch::Knit | Stitch::Purl => {}
+                Stitch::Cable(..) => found("cables", r + 1),
+                Stitch::K2tog | Stitch::Ssk => found("decreases", r + 1),
+                Stitch::YarnOver => found("yarn overs", r + 1),
+                Stitch::M1 => found("increases", r + 1),
+                Stitch::BindOff => found("partial bind-offs", r + 1),
+            }
+            let worked = fabric.yarn_at(r, i);
+            match yarn {
+                None => yarn = Some(worked),
+                Some(first) if first != worked => found("stitches in a second yarn", r + 1),
+                Some(_) => {}
+            }
+        }
+        let before = fabric.indent(r) + fabric.unworked(r) + consumed(row);
+        if live.is_some_and(|live| before > live) {
+            found("stitches cast on between rows", r + 1);
+        }
+        live = Some(fabric.indent(r) + fabric.unworked(r) + produced(row));
+    }
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(unsupported)
+    }
+}
+
+fn consumed(row: &[Stitch]) -> usize {
+    row.iter().map(|stitch| stitch.consumes()).sum()
+}
+
+fn produced(row: &[Stitch]) -> usize {
+    row.iter().map(|stitch| stitch.produces()).sum()
+}
@@ -0,0 +1,352 @@
+//! Turns source text into tokens.
+
+use crate::codes::Code;
+use crate::symbol::Symbol;
+use std::fmt;
+
+/// A token of knitlang source. Keywords are recognised here; stitch names
+/// such as `k` and `p` stay identifiers and are resolved by the parser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+    CastOn,
+    Knit,
+    Purl,
+    BindOff,
+    Repeat,
+    Yarn,
+    Color,
+    /// `wrap_turn`, or its abbreviation `w&t`.
+    WrapTurn,
+    Ident(Symbol),
+    Number(i64),
+    /// A `"..."` literal, without its quotes.
+    Str(Symbol),
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+    /// `.`, between the parts of a qualified name such as `edgings.width`.
+    Dot,
+    Semicolon,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Equal,
+    /// `?`, after `expect_stitches` for a checkpoint that only warns.
+    Question,
+}
+
+/// Shows a token as it is written in source, so `Token::Equal` is `=` and
+/// `Token::Number(4)` is `4`.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::CastOn => "cast_on",
+            Token::Knit => "knit",
+            Token::Purl => "purl",
+            Token::BindOff => "bind_off",
+            Token::Repeat => "repeat",
+            Token::Yarn => "yarn",
+            Token::Color => "color",
+            Token::WrapTurn => "wrap_turn",
+            Token::Ident(name) => name.as_str(),
+            Token::Number(n) => return write!(f, "{n}"),
+            Token::Str(s) => return write!(f, "{:?}", s.as_str()),
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Semicolon => ";",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Equal => "=",
+            Token::Question => "?",
+        };
+        f.write_str(text)
+    }
+}
+
+/// A byte range into the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+/// 1-based line and column (in characters) of byte `offset` within `src`.
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(src.len());
+    let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = src[..line_start].matches('\n').count() + 1;
+    let col = src[line_start..offset].chars().count() + 1;
+    (line, col)
+}
+
+/// Whether a piece of [`Trivia`] is whitespace or a comment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    /// A run of whitespace, newlines included.
+    Whitespace,
+    /// A `//` comment, up to but not including the end of its line.
+    Comment,
+}
+
+/// Source text that is not part of any token. The parser never sees it; it
+/// is kept only for tools such as the formatter that must reproduce it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+/// A token together with the trivia that comes before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriviaToken {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+    pub span: Span,
+}
+
+/// A character that does not start any token.
+#[derive(Debug)]
+pub struct LexError {
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Produces one token at a time from source text; see [`lex_all`] for the
+/// whole stream at once.
+pub struct Lexer {
+    input: String,
+    /// Byte offset of the next character.
+    offset: usize,
+    /// The identifier being read, kept between tokens to reuse its buffer.
+    word: String,
+}
+
+impl Lexer {
+    pub fn new(src: &str) -> Self {
+        Self {
+            input: src.to_string(),
+            offset: 0,
+            word: String::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek();
+        if let Some(c) = ch {
+            self.offset += c.len_utf8();
+        }
+        ch
+    }
+
+    /// Skips whitespace and comments, passing each run of either to `record`.
+    fn skip_trivia(&mut self, mut record: impl FnMut(Trivia)) {
+        loop {
+            let start = self.offset;
+            let kind = match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    while self.peek().is_some_and(char::is_whitespace) {
+                        self.bump();
+                    }
+                    TriviaKind::Whitespace
+                }
+                Some('/') if self.input[self.offset..].starts_with("//") => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                    TriviaKind::Comment
+                }
+                _ => return,
+            };
+            record(Trivia {
+                kind,
+                span: Span::new(start, self.offset),
+            });
+        }
+    }
+
+    /// Reads an identifier or keyword into `self.word`.
+    fn read_ident(&mut self, first: char) {
+        self.word.clear();
+        self.word.push(first);
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                self.word.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Reads a number literal, or `None` if it does not fit in an `i64`.
+    fn read_number(&mut self, first: char) -> Option<i64> {
+        let digit = |c: char| c.to_digit(10).map(i64::from);
+        let mut n = digit(first);
+        while let Some(d) = self.peek().and_then(digit) {
+            self.bump();
+            n = n.and_then(|n| n.checked_mul(10)?.checked_add(d));
+        }
+        n
+    }
+
+    pub fn next_token(&mut self) -> Result<Option<(Token, Span)>, LexError> {
+        self.skip_trivia(|_| {});
+        self.lex_token()
+    }
+
+    /// Reads the token at the current position, which must not be trivia.
+    fn lex_token(&mut self) -> Result<Option<(Token, Span)>, LexError> {
+        let start = self.offset;
+        let token = match self.bump() {
+            Some('{') => Token::LBrace,
+            Some('}') => Token::RBrace,
+            Some('(') => Token::LParen,
+            Some(')') => Token::RParen,
+            Some(',') => Token::Comma,
+            Some('.') => Token::Dot,
+            Some(';') => Token::Semicolon,
+            Some('+') => Token::Plus,
+            Some('-') => Token::Minus,
+            Some('*') => Token::Star,
+            Some('/') => Token::Slash,
+            Some('=') => Token::Equal,
+            Some('?') => Token::Question,
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.read_ident(c);
+                match self.word.as_str() {
+                    "cast_on" => Token::CastOn,
+                    "knit" => Token::Knit,
+                    "purl" => Token::Purl,
+                    "bind_off" => Token::BindOff,
+                    "repeat" => Token::Repeat,
+                    "yarn" => Token::Yarn,
+                    "color" => Token::Color,
+                    "wrap_turn" => Token::WrapTurn,
+                    // `&` means nothing else, so `w&t` is never two tokens.
+                    "w" if self.input[self.offset..].starts_with("&t")
+                        && !self.input[self.offset + 2..]
+                            .starts_with(|c: char| c.is_alphanumeric() || c == '_') =>
+                    {
+                        self.offset += 2;
+                        Token::WrapTurn
+                    }
+                    other => Token::Ident(Symbol::intern(other)),
+                }
+            }
+            Some('"') => {
+                let text = &self.input[self.offset..];
+                match text
+                    .find(['"', '\n'])
+                    .filter(|&end| text[end..].starts_with('"'))
+                {
+                    Some(end) => {
+                        let token = Token::Str(Symbol::intern(&text[..end]));
+                        self.offset += end + 1;
+                        token
+                    }
+                    None => {
+                        self.offset += text.find('\n').unwrap_or(text.len());
+                        return Err(LexError {
+                            code: Code::UnterminatedString,
+                            message: "String literal is never closed; it must end with `\"` \
+                                      on the same line"
+                                .to_string(),
+                            span: Span::new(start, self.offset),
+                        });
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_digit() => match self.read_number(c) {
+                Some(n) => Token::Number(n),
+                None => {
+                    return Err(LexError {
+                        code: Code::NumberTooLarge,
+                        message: format!("Number literal too large; the largest is {}", i64::MAX),
+                        span: Span::new(start, self.offset),
+                    })
+                }
+            },
+            Some(c) => {
+                return Err(LexError {
+                    code: Code::UnexpectedCharacter,
+                    message: format!("Unexpected character: {c}"),
+                    span: Span::new(start, self.offset),
+                })
+            }
+            None => return Ok(None),
+        };
+        Ok(Some((token, Span::new(start, self.offset))))
+    }
+}
+
+/// Tokens one at a time, as [`Lexer::next_token`] produces them. After an
+/// error it carries on from just past the offending characters.
+impl Iterator for Lexer {
+    type Item = Result<(Token, Span), LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().transpose()
+    }
+}
+
+/// The tokens of `src` without their spans, stopping at the first error. Never
+/// panics, whatever the input.
+pub fn try_lex(src: &str) -> Result<Vec<Token>, LexError> {
+    Ok(lex_all(src)?.into_iter().map(|(token, _)| token).collect())
+}
+
+/// Lexes all of `src`, stopping at the first error.
+pub fn lex_all(src: &str) -> Result<Vec<(Token, Span)>, LexError> {
+    let mut lx = Lexer::new(src);
+    let mut tokens = Vec::new();
+    while let Some(token) = lx.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Lexes all of `src`, keeping whitespace and comments: each token carries the
+/// trivia before it, and the trivia after the last token is returned
+/// separately. Concatenating every span in order gives back `src` exactly.
+pub fn lex_with_trivia(src: &str) -> Result<(Vec<TriviaToken>, Vec<Trivia>), LexError> {
+    let mut lx = Lexer::new(src);
+    let mut tokens = Vec::new();
+    loop {
+        let mut leading = Vec::new();
+        lx.skip_trivia(|t| leading.push(t));
+        match lx.lex_token()? {
+            Some((token, span)) => tokens.push(TriviaToken {
+                leading,
+                token,
+                span,
+            }),
+            None => return Ok((tokens, leading)),
+        }
+    }
+}
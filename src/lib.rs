@@ -0,0 +1,342 @@
+//! knitlang: a tiny, playful programming language inspired by knitting terms.
+//!
+//! Source goes through three stages, each in its own module: [`lexer`] turns
+//! text into [`Token`]s, [`parser`] builds the [`ast`] from them, and
+//! [`interp`] executes it while knitting a [`Fabric`]. [`run_source`] does all
+//! three in one call and collects everything a caller might want to know about
+//! the run; [`run`] is the bare-bones version.
+
+pub mod animate;
+pub mod ast;
+pub mod bench;
+pub mod chart;
+pub mod codes;
+pub mod counts;
+pub mod csv;
+pub mod document;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod hooks;
+pub mod html;
+pub mod imports;
+pub mod interp;
+pub mod knitout;
+pub mod lexer;
+pub mod lint;
+pub mod markdown;
+pub mod optimize;
+pub mod parser;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod resolve;
+pub mod session;
+pub mod sexp;
+pub mod suggest;
+pub mod summary;
+pub mod symbol;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod written;
+
+pub use ast::{
+    ast_from_json, ast_to_json, format_program, AstError, Cross, Expr, ExprKind, MetaEntry,
+    MetaValue, Mode, NotePart, Pattern, Shaping, Stitch, Stmt, StmtKind,
+};
+pub use codes::{Code, Explanation, REGISTRY};
+pub use events::{Event, EventSink, JsonLines};
+pub use hooks::{ExecHook, HookAction};
+pub use interp::{
+    Fabric, Gauge, Interpreter, Note, PatternMeta, RunStats, RuntimeError, StateError, Value,
+    STATE_VERSION,
+};
+pub use lexer::{
+    lex_all, lex_with_trivia, line_col, try_lex, LexError, Lexer, Span, Token, Trivia, TriviaKind,
+    TriviaToken,
+};
+pub use parser::{
+    parse_expression, parse_src, statements, try_parse, ParseError, Parser, Statements,
+};
+pub use session::{FeedResult, Session};
+pub use sexp::ast_to_sexp;
+pub use summary::PatternSummary;
+pub use symbol::Symbol;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Anything that can stop [`run_source`]: the source failed to lex or parse, or the
+/// program failed while running.
+#[derive(Debug)]
+pub enum KnitError {
+    Lex(LexError),
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
+impl KnitError {
+    /// The identifier of this kind of error, for `knitlang explain`.
+    pub fn code(&self) -> Code {
+        match self {
+            KnitError::Lex(e) => e.code,
+            KnitError::Parse(e) => e.code,
+            KnitError::Runtime(e) => e.code,
+        }
+    }
+
+    /// Which stage failed: `"lex"`, `"parse"` or `"runtime"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            KnitError::Lex(_) => "lex",
+            KnitError::Parse(_) => "parse",
+            KnitError::Runtime(_) => "runtime",
+        }
+    }
+
+    /// Where in the source the error happened.
+    pub fn span(&self) -> Span {
+        match self {
+            KnitError::Lex(e) => e.span,
+            KnitError::Parse(e) => e.span,
+            KnitError::Runtime(e) => e.span,
+        }
+    }
+}
+
+impl fmt::Display for KnitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KnitError::Lex(e) => e.fmt(f),
+            KnitError::Parse(e) => e.fmt(f),
+            KnitError::Runtime(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for KnitError {}
+
+impl From<LexError> for KnitError {
+    fn from(e: LexError) -> Self {
+        KnitError::Lex(e)
+    }
+}
+
+impl From<ParseError> for KnitError {
+    fn from(e: ParseError) -> Self {
+        KnitError::Parse(e)
+    }
+}
+
+impl From<parser::Stopped> for KnitError {
+    fn from(stopped: parser::Stopped) -> Self {
+        match stopped {
+            parser::Stopped::Lex(e) => KnitError::Lex(e),
+            parser::Stopped::Syntax(e) => KnitError::Parse(e),
+        }
+    }
+}
+
+impl From<RuntimeError> for KnitError {
+    fn from(e: RuntimeError) -> Self {
+        KnitError::Runtime(e)
+    }
+}
+
+/// Lexes, parses and runs `src` on a fresh interpreter, writing `purl` output
+/// to `out`. The interpreter is returned so its variables, fabric and stats
+/// can be inspected afterwards.
+pub fn run(src: &str, out: Box<dyn Write>) -> Result<Interpreter, KnitError> {
+    let mut interp = Interpreter::with_output(out);
+    execute(src, &mut interp, parser::DEFAULT_MAX_NESTING)?;
+    Ok(interp)
+}
+
+/// Lex, parse and run `src` against `interp`, then flush its output. Returns
+/// whether the program ended early, as [`Interpreter::run`] does.
+fn execute(src: &str, interp: &mut Interpreter, max_nesting: usize) -> Result<bool, KnitError> {
+    let mut parser = Parser::from_source(src);
+    parser.set_max_nesting(max_nesting);
+    let stmts = parser.parse_program()?;
+    interp.restart_timeout();
+    let ended = interp.run(&stmts);
+    // Output before a runtime error is flushed too, so it is not held back
+    // until after the error is reported.
+    let flushed = flush(interp);
+    let ended = ended?;
+    flushed?;
+    Ok(ended)
+}
+
+/// Like [`execute`], but parses and runs one top-level statement at a time,
+/// so the program is never held in memory as a whole. Statements before a
+/// syntax error have already run by the time it is reported.
+fn execute_streaming(
+    src: &str,
+    interp: &mut Interpreter,
+    max_nesting: usize,
+) -> Result<bool, KnitError> {
+    interp.restart_timeout();
+    let mut stmts = statements(src);
+    stmts.set_max_nesting(max_nesting);
+    let mut run = || {
+        while let Some(stmt) = stmts.next_stmt() {
+            if interp.run(std::slice::from_ref(&stmt?))? {
+                return Ok(true);
+            }
+        }
+        Ok::<_, KnitError>(false)
+    };
+    let ended = run();
+    let flushed = flush(interp);
+    let ended = ended?;
+    flushed?;
+    Ok(ended)
+}
+
+fn flush(interp: &mut Interpreter) -> Result<(), RuntimeError> {
+    interp.flush().map_err(|e| RuntimeError {
+        code: Code::OutputFailed,
+        message: format!("could not write output: {e}"),
+        span: Span::default(),
+    })
+}
+
+/// Limits and sinks for [`run_source`]. The default runs to completion with
+/// output only captured.
+#[derive(Default)]
+pub struct RunOptions {
+    /// Stop once this many rows are complete.
+    pub row_limit: Option<u64>,
+    /// Abort with a runtime error once the run has taken this long.
+    pub timeout: Option<Duration>,
+    /// Abort with a runtime error after this many statements, loop iterations
+    /// and stitches.
+    pub step_limit: Option<u64>,
+    /// Abort with a runtime error once variables and the fabric take up
+    /// more than this many bytes, as counted by
+    /// [`Interpreter::memory_used`].
+    pub memory_limit: Option<usize>,
+    /// Also write `purl` output here as it is produced, e.g. to stream it to
+    /// stdout. [`RunOutput::stdout`] gets a copy either way.
+    pub output: Option<Box<dyn Write>>,
+    /// Parse and run one top-level statement at a time instead of parsing
+    /// the whole program first. Memory then stays flat however long the
+    /// program is, but a syntax error is only found once the statements
+    /// before it have run.
+    pub streaming: bool,
+    /// Reject programs whose repeats and calls nest more than this deep, as
+    /// a syntax error; `None` allows [`parser::DEFAULT_MAX_NESTING`].
+    pub max_nesting: Option<usize>,
+    /// Knit this one of the sizes the program's `sizes` declaration names;
+    /// `None` knits the first. See [`Interpreter::set_size`].
+    pub size: Option<String>,
+}
+
+/// Everything [`run_source`] learned from a successful run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutput {
+    /// Everything the program printed with `purl`.
+    pub stdout: String,
+    /// The variables at the end of the run, sorted by name.
+    pub variables: BTreeMap<String, i64>,
+    pub stats: RunStats,
+    /// Whether the program ended by reaching `bind_off`.
+    pub bound_off: bool,
+    /// Whether the run stopped early at [`RunOptions::row_limit`].
+    pub truncated: bool,
+    /// The warnings of `expect_stitches?` checkpoints that found the wrong
+    /// stitch count, in the order they ran.
+    pub warnings: Vec<RuntimeError>,
+}
+
+/// Collects program output while passing it on to an optional sink.
+struct Capture {
+    buffer: Rc<RefCell<Vec<u8>>>,
+    sink: Option<Box<dyn Write>>,
+}
+
+impl Capture {
+    /// A capture passing output on to `sink`, and the buffer it fills.
+    fn new(sink: Option<Box<dyn Write>>) -> (Self, Rc<RefCell<Vec<u8>>>) {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let capture = Self {
+            buffer: Rc::clone(&buffer),
+            sink,
+        };
+        (capture, buffer)
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(sink) = &mut self.sink {
+            sink.write_all(buf)?;
+        }
+        self.buffer.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lexes, parses and runs `src` on a fresh interpreter configured by
+/// `options`, returning its output, final variables and stats.
+pub fn run_source(src: &str, options: RunOptions) -> Result<RunOutput, KnitError> {
+    let (capture, buffer) = Capture::new(options.output);
+    let mut interp = Interpreter::with_output(Box::new(capture));
+    interp.set_row_limit(options.row_limit);
+    interp.set_timeout(options.timeout);
+    interp.set_step_limit(options.step_limit);
+    interp.set_memory_limit(options.memory_limit);
+    interp.set_size(options.size.as_deref());
+    let max_nesting = options.max_nesting.unwrap_or(parser::DEFAULT_MAX_NESTING);
+    let ended = if options.streaming {
+        execute_streaming(src, &mut interp, max_nesting)?
+    } else {
+        execute(src, &mut interp, max_nesting)?
+    };
+    let stdout = String::from_utf8_lossy(&buffer.borrow()).into_owned();
+    Ok(RunOutput {
+        stdout,
+        variables: interp
+            .vars()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect(),
+        stats: interp.stats(),
+        bound_off: ended && !interp.truncated(),
+        truncated: interp.truncated(),
+        warnings: interp.take_warnings(),
+    })
+}
+
+/// Formats `d` in the largest of m, s and ms that represents it exactly.
+pub fn format_duration(d: Duration) -> String {
+    let ms = d.as_millis();
+    if ms.is_multiple_of(60_000) {
+        format!("{}m", ms / 60_000)
+    } else if ms.is_multiple_of(1000) {
+        format!("{}s", ms / 1000)
+    } else {
+        format!("{ms}ms")
+    }
+}
+
+/// `n` followed by `word`, pluralised unless `n` is 1.
+pub fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        format!("{n} {word}")
+    } else if word.ends_with("ch") {
+        format!("{n} {word}es")
+    } else {
+        format!("{n} {word}s")
+    }
+}
@@ -0,0 +1,483 @@
+//! The static checks `knitlang check` makes, and `run` makes before running:
+//! warnings about variables that are never read, statements after a
+//! `bind_off`, rows that work the wrong number of stitches, unbalanced lace
+//! and unknown `meta` keys. None of them runs the program.
+//!
+//! [`check`] lints one file. A program made of several files, or importing
+//! others, shares its variables between them, so [`check_programs`] loads
+//! every import first and lints each file against the reads of all of them,
+//! as the run will see them.
+//!
+//! ```
+//! use knitlang::imports::{Importer, Source};
+//! use knitlang::lint;
+//!
+//! let src = "cast_on width = 4;\ncast_on unused = 1;\ncast_on width;\n";
+//! let stmts = knitlang::parse_src(src).unwrap();
+//! let mut importer = Importer::new(|_, path| Err(format!("no file {path}")));
+//! let checked = lint::check_programs(&mut importer, vec![(Source::new("a.knit", src), stmts)])
+//!     .unwrap();
+//! let messages: Vec<_> = checked.lints[0].diagnostics.iter().map(|d| &d.message).collect();
+//! assert_eq!(messages, ["unused variable `unused`"]);
+//! ```
+
+use crate::ast::{AstError, Expr, ExprKind, NotePart, Stmt, StmtKind};
+use crate::codes::Code;
+use crate::counts;
+use crate::imports::{ImportError, Importer, Part, Source};
+use crate::interp::{PatternMeta, RuntimeError};
+use crate::lexer::{lex_with_trivia, line_col, Span, TriviaKind};
+use crate::parser::ParseError;
+use crate::suggest::did_you_mean;
+use crate::symbol::Symbol;
+use crate::{plural, KnitError};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Whether a [`Diagnostic`] stops the program or only reports on it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// A named kind of warning, which `-W no-<name>` can switch off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningClass {
+    Unused,
+    Unreachable,
+    StitchCount,
+    Lace,
+    Meta,
+    Checkpoint,
+}
+
+impl WarningClass {
+    pub const ALL: [WarningClass; 6] = [
+        WarningClass::Unused,
+        WarningClass::Unreachable,
+        WarningClass::StitchCount,
+        WarningClass::Lace,
+        WarningClass::Meta,
+        WarningClass::Checkpoint,
+    ];
+
+    /// The name `-W` and `allow(...)` comments know the class by.
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningClass::Unused => "unused",
+            WarningClass::Unreachable => "unreachable",
+            WarningClass::StitchCount => "stitch-count",
+            WarningClass::Lace => "lace",
+            WarningClass::Meta => "meta",
+            WarningClass::Checkpoint => "checkpoint",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            WarningClass::Unused => "variables that are cast on but never read",
+            WarningClass::Unreachable => "statements after bind_off that can never run",
+            WarningClass::StitchCount => {
+                "rows that work more or fewer stitches than are on the needles"
+            }
+            WarningClass::Lace => "rows whose yarn overs are not balanced by decreases",
+            WarningClass::Meta => "meta keys that written instructions leave out",
+            WarningClass::Checkpoint => "`expect_stitches?` checkpoints with the wrong count",
+        }
+    }
+
+    pub fn code(self) -> Code {
+        match self {
+            WarningClass::Unused => Code::UnusedVariable,
+            WarningClass::Unreachable => Code::UnreachableStatement,
+            WarningClass::StitchCount => Code::StitchCountMismatch,
+            WarningClass::Lace => Code::UnbalancedLace,
+            WarningClass::Meta => Code::UnknownMetaKey,
+            WarningClass::Checkpoint => Code::StitchCountWarning,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|class| class.name() == name)
+    }
+}
+
+/// An error or warning about a place in one file.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+    /// Secondary spans, each rendered with its note.
+    pub labels: Vec<(Span, String)>,
+    /// Set for warnings, so `-W` can act on them by name.
+    pub class: Option<WarningClass>,
+}
+
+impl Diagnostic {
+    pub fn warning(class: WarningClass, message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            code: class.code(),
+            message,
+            span,
+            labels: Vec::new(),
+            class: Some(class),
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, note: &str) -> Self {
+        self.labels.push((span, note.to_string()));
+        self
+    }
+}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(e: RuntimeError) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: e.code,
+            message: e.message,
+            span: e.span,
+            labels: Vec::new(),
+            class: None,
+        }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: e.code,
+            message: e.message,
+            span: e.span,
+            labels: e.labels,
+            class: None,
+        }
+    }
+}
+
+impl From<KnitError> for Diagnostic {
+    fn from(e: KnitError) -> Self {
+        match e {
+            KnitError::Lex(e) => ParseError::from(e).into(),
+            KnitError::Parse(e) => e.into(),
+            KnitError::Runtime(e) => e.into(),
+        }
+    }
+}
+
+impl From<AstError> for Diagnostic {
+    fn from(e: AstError) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: Code::InvalidAst,
+            message: e.to_string(),
+            span: Span::default(),
+            labels: Vec::new(),
+            class: None,
+        }
+    }
+}
+
+/// What [`check`] found in one file.
+#[derive(Debug, Clone, Default)]
+pub struct Lints {
+    /// Warnings in source order, less those an `allow` comment switches off.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Names in `// knitlang: allow(...)` comments that are not warning
+    /// classes, each with the line of its comment.
+    pub unknown_classes: Vec<(usize, String)>,
+    /// The line stitch counts can no longer be followed from, and why; see
+    /// [`counts::Unverified`].
+    pub unverified: Option<(usize, &'static str)>,
+}
+
+/// Lints `stmts`, parsed from `src`. `reads` holds the variables read
+/// anywhere in the run, as files share their variables; see
+/// [`collect_reads`].
+pub fn check(src: &str, stmts: &[Stmt], reads: &HashSet<Symbol>) -> Lints {
+    let mut lints = Lints::default();
+    let diags = &mut lints.diagnostics;
+    check_unreachable(stmts, diags);
+    check_unused(stmts, reads, diags);
+    lints.unverified = check_stitch_counts(src, stmts, diags);
+    check_meta(stmts, diags);
+    let allowed = allowed_warnings(src, &mut lints.unknown_classes);
+    lints.diagnostics.retain(|d| {
+        let (line, _) = line_col(src, d.span.start);
+        !d.class
+            .is_some_and(|class| allowed.contains(&(line, class)))
+    });
+    lints.diagnostics.sort_by_key(|d| d.span.start);
+    lints
+}
+
+/// Programs with everything they import loaded, ready to run, and the lints
+/// of every file.
+#[derive(Debug)]
+pub struct Checked {
+    /// The parts of the programs and of the files they import, in the order
+    /// they run.
+    pub parts: Vec<Part>,
+    /// The lints of each file, in the order of [`Importer::files`].
+    pub lints: Vec<Lints>,
+}
+
+/// Loads the files `programs` import with `importer`, then lints every file
+/// against the reads of all of them. The programs run one after another
+/// against one interpreter, as the command line runs its files.
+pub fn check_programs(
+    importer: &mut Importer,
+    programs: Vec<(Source, Vec<Stmt>)>,
+) -> Result<Checked, ImportError> {
+    let mut parts = Vec::new();
+    for (source, stmts) in programs {
+        parts.extend(importer.program(source, stmts)?);
+    }
+    let mut reads = HashSet::new();
+    for file in importer.files() {
+        collect_reads(&file.stmts, &mut reads);
+    }
+    let lints = importer
+        .files()
+        .iter()
+        .map(|file| check(&file.src, &file.stmts, &reads))
+        .collect();
+    Ok(Checked { parts, lints })
+}
+
+/// The lines of `src` where a comment such as `// knitlang: allow(lace)`
+/// switches warnings of a class off, with the class. A comment on a line
+/// of its own covers the line after it; one after a statement covers its
+/// own line. Names that are no class are added to `unknown`.
+fn allowed_warnings(
+    src: &str,
+    unknown: &mut Vec<(usize, String)>,
+) -> HashSet<(usize, WarningClass)> {
+    let Ok((tokens, trailing)) = lex_with_trivia(src) else {
+        return HashSet::new();
+    };
+    let comments = tokens
+        .iter()
+        .flat_map(|token| &token.leading)
+        .chain(&trailing)
+        .filter(|trivia| trivia.kind == TriviaKind::Comment);
+    let mut allowed = HashSet::new();
+    for comment in comments {
+        let text = src[comment.span.start..comment.span.end].trim_start_matches('/');
+        let Some(names) = text
+            .trim()
+            .strip_prefix("knitlang: allow(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        else {
+            continue;
+        };
+        let (line, _) = line_col(src, comment.span.start);
+        let before = &src[..comment.span.start];
+        let own_line = before[before.rfind('\n').map_or(0, |i| i + 1)..]
+            .trim()
+            .is_empty();
+        let covered = if own_line { line + 1 } else { line };
+        for name in names.split(',') {
+            match WarningClass::from_name(name.trim()) {
+                Some(class) => {
+                    allowed.insert((covered, class));
+                }
+                None => unknown.push((line, name.trim().to_string())),
+            }
+        }
+    }
+    allowed
+}
+
+/// Warns about rows that work more or fewer stitches than are on the
+/// needles, as far as the counts are known before the run. Returns the line
+/// the counts stop being known from, and why.
+fn check_stitch_counts(
+    src: &str,
+    stmts: &[Stmt],
+    diags: &mut Vec<Diagnostic>,
+) -> Option<(usize, &'static str)> {
+    let report = counts::check(stmts);
+    for row in report.mismatches {
+        let diag = Diagnostic::warning(
+            WarningClass::StitchCount,
+            format!(
+                "row {} works {} but has {} on the needles",
+                row.row,
+                plural(row.worked, "stitch"),
+                row.expected
+            ),
+            row.span,
+        );
+        diags.push(match row.before {
+            Some(before) => diag.with_label(before, "the row has to be finished before this"),
+            None => diag,
+        });
+    }
+    for row in report.unbalanced {
+        let decreases = match row.decreases {
+            1 => "1 decrease".to_string(),
+            n => format!("{n} decreases"),
+        };
+        diags.push(Diagnostic::warning(
+            WarningClass::Lace,
+            format!("row {}: {} yo but {decreases}", row.row, row.yarn_overs),
+            row.span,
+        ));
+    }
+    report.unverified.map(|unverified| {
+        let (line, _) = line_col(src, unverified.span.start);
+        (line, unverified.reason)
+    })
+}
+
+/// Warns about keys of a `meta` block that written instructions leave out.
+fn check_meta(stmts: &[Stmt], diags: &mut Vec<Diagnostic>) {
+    let Some(StmtKind::Meta(entries)) = stmts.first().map(|stmt| &stmt.kind) else {
+        return;
+    };
+    for entry in entries {
+        let key = entry.key.as_str();
+        if !PatternMeta::KEYS.contains(&key) {
+            diags.push(Diagnostic::warning(
+                WarningClass::Meta,
+                format!(
+                    "unknown meta key `{key}`{}",
+                    did_you_mean(key, PatternMeta::KEYS)
+                ),
+                entry.span,
+            ));
+        }
+    }
+}
+
+/// Warns about statements following a `bind_off` in the same block.
+fn check_unreachable(stmts: &[Stmt], diags: &mut Vec<Diagnostic>) {
+    for (i, stmt) in stmts.iter().enumerate() {
+        match &stmt.kind {
+            StmtKind::Repeat(_, body) | StmtKind::Mirror(body) => check_unreachable(body, diags),
+            StmtKind::Alternate(bodies) => {
+                for body in bodies {
+                    check_unreachable(body, diags);
+                }
+            }
+            StmtKind::BindOff => {
+                if let (Some(first), Some(last)) = (stmts.get(i + 1), stmts.last()) {
+                    diags.push(
+                        Diagnostic::warning(
+                            WarningClass::Unreachable,
+                            "unreachable statement after bind_off".to_string(),
+                            Span::new(first.span.start, last.span.end),
+                        )
+                        .with_label(stmt.span, "the program ends here"),
+                    );
+                }
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_expr_reads(e: &Expr, reads: &mut HashSet<Symbol>) {
+    for node in e.nodes() {
+        if let ExprKind::Var(name) = node.kind {
+            reads.insert(name);
+        }
+    }
+}
+
+/// Adds every variable `stmts` read to `reads`.
+pub fn collect_reads(stmts: &[Stmt], reads: &mut HashSet<Symbol>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::CastOn(_, e)
+            | StmtKind::CastOnStitches(e)
+            | StmtKind::CastOnNeedle(_, e)
+            | StmtKind::Transfer(_, _, e)
+            | StmtKind::Knit(_, e)
+            | StmtKind::Purl(e)
+            | StmtKind::Work(_, e)
+            | StmtKind::Cable(e, _)
+            | StmtKind::Distribute(_, e)
+            | StmtKind::BindOffStitches(e)
+            | StmtKind::ExpectStitches(e)
+            | StmtKind::ExpectStitchesWarning(e) => collect_expr_reads(e, reads),
+            StmtKind::Repeat(count, body) => {
+                collect_expr_reads(count, reads);
+                collect_reads(body, reads);
+            }
+            StmtKind::Mirror(body) => collect_reads(body, reads),
+            StmtKind::Alternate(bodies) => {
+                for body in bodies {
+                    collect_reads(body, reads);
+                }
+            }
+            StmtKind::Pattern(_, args) => {
+                for arg in args {
+                    collect_expr_reads(arg, reads);
+                }
+            }
+            StmtKind::Gauge(stitches, rows) => {
+                collect_expr_reads(stitches, reads);
+                collect_expr_reads(rows, reads);
+            }
+            StmtKind::Note(parts) => {
+                for part in parts {
+                    if let NotePart::Expr(e) = part {
+                        collect_expr_reads(e, reads);
+                    }
+                }
+            }
+            StmtKind::BindOff
+            | StmtKind::Chart(_)
+            | StmtKind::Hold(_)
+            | StmtKind::Resume(_)
+            | StmtKind::Yarn(..)
+            | StmtKind::Color(_)
+            | StmtKind::WrapTurn
+            | StmtKind::Mode(_)
+            | StmtKind::PlaceMarker(_)
+            | StmtKind::SlipMarker(_)
+            | StmtKind::Use(..)
+            | StmtKind::Sizes(_)
+            | StmtKind::Meta(_) => {}
+        }
+    }
+}
+
+/// Warns about variables that are cast on but never read.
+fn check_unused(stmts: &[Stmt], reads: &HashSet<Symbol>, diags: &mut Vec<Diagnostic>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::CastOn(name, _) if !reads.contains(name) => {
+                diags.push(Diagnostic::warning(
+                    WarningClass::Unused,
+                    format!("unused variable `{name}`"),
+                    stmt.span,
+                ));
+            }
+            StmtKind::Repeat(_, body) | StmtKind::Mirror(body) => check_unused(body, reads, diags),
+            StmtKind::Alternate(bodies) => {
+                for body in bodies {
+                    check_unused(body, reads, diags);
+                }
+            }
+            _ => {}
+        }
+    }
+}
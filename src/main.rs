@@ -1,457 +1,3213 @@
-use std::collections::HashMap;
+use clap::error::ErrorKind;
+use knitlang::animate::{self, Animation};
+use knitlang::ast::{count_stmts, format_with_comments};
+use knitlang::bench::{self, Timings};
+use knitlang::chart;
+use knitlang::imports::{Importer, Source};
+use knitlang::lint::{self, Diagnostic, Lints, Severity, WarningClass};
+use knitlang::optimize::{self, optimize};
+use knitlang::parser::DEFAULT_MAX_NESTING;
+use knitlang::resolve;
+use knitlang::written;
+use knitlang::{
+    ast_from_json, ast_to_json, ast_to_sexp, lex_with_trivia, line_col, plural, statements, Code,
+    ExecHook, Fabric, FeedResult, HookAction, Interpreter, JsonLines, KnitError, Lexer, ParseError,
+    Parser, Pattern, PatternSummary, Session, Stitch, Stmt, StmtKind, Token, TriviaKind, REGISTRY,
+};
+use knitlang::{csv, html, knitout, markdown};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fmt;
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
-
-#[derive(Debug, Clone, PartialEq)]
-enum Token {
-    CastOn,
-    Knit,
-    Purl,
-    BindOff,
-    Repeat,
-    Ident(String),
-    Number(i64),
-    LBrace,
-    RBrace,
-    Semicolon,
-    Plus,
-    Minus,
-    Star,
-    Slash,
-    Equal,
-}
-
-struct Lexer {
-    input: Vec<char>,
-    pos: usize,
-}
-
-impl Lexer {
-    fn new(src: &str) -> Self {
-        Self {
-            input: src.chars().collect(),
-            pos: 0,
-        }
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod dispatch;
+mod examples;
+mod search;
+
+use dispatch::{Action, Terminals};
+use examples::{example_names, load_example};
+use search::{file_key, SearchPath};
+
+/// How much incidental output goes to stderr, set by `--quiet` and `-v`.
+/// Program output and errors are never affected.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Trace,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+fn set_verbosity(level: Verbosity) {
+    VERBOSITY.store(level as u8, Ordering::Relaxed);
+}
+
+fn log_enabled(level: Verbosity) -> bool {
+    level as u8 <= VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// How diagnostics are written to stderr, set by `--error-format`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    /// rustc-style messages with a source snippet
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+fn set_error_format(format: ErrorFormat) {
+    JSON_ERRORS.store(format == ErrorFormat::Json, Ordering::Relaxed);
+}
+
+fn json_errors() -> bool {
+    JSON_ERRORS.load(Ordering::Relaxed)
+}
+
+/// The stack everything past parsing the command line runs on. Passes over
+/// a syntax tree recurse a level for each repeat or call nested in another,
+/// so this, and not whatever stack the platform gives the main thread, is
+/// what [`MAX_NESTING_LIMIT`] is measured against.
+const STACK_SIZE: usize = 64 << 20;
+
+/// The most `--max-nesting` allows. The pass that needs the most stack a
+/// level, printing the tree as JSON in a debug build, uses about a third of
+/// [`STACK_SIZE`] at this depth.
+const MAX_NESTING_LIMIT: u32 = 1024;
+
+/// How deeply repeats and calls may nest, set by `--max-nesting`.
+static MAX_NESTING: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_NESTING);
+
+fn max_nesting() -> usize {
+    MAX_NESTING.load(Ordering::Relaxed)
+}
+
+/// Whether text charts use plain ASCII, set by `--ascii` or by a terminal
+/// that cannot show the default symbols.
+static ASCII_CHARTS: AtomicBool = AtomicBool::new(false);
+
+/// Whether the terminal can show text charts' box drawing and knitting
+/// symbols: not if `$TERM` is `dumb`, or the locale's encoding is not
+/// UTF-8. An unset locale says nothing either way, so it is taken to.
+fn unicode_output() -> bool {
+    if env::var_os("TERM").is_some_and(|term| term == "dumb") {
+        return false;
     }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty());
+    locale.is_none_or(|locale| {
+        let locale = locale.to_ascii_lowercase();
+        locale.contains("utf-8") || locale.contains("utf8")
+    })
+}
 
-    fn peek(&self) -> Option<char> {
-        self.input.get(self.pos).copied()
+/// What text charts chart stitches with unless `--chart-style` says.
+fn chart_symbols() -> chart::ChartStyle {
+    if ASCII_CHARTS.load(Ordering::Relaxed) {
+        chart::ChartStyle::ascii()
+    } else {
+        chart::ChartStyle::unicode()
     }
+}
+
+/// Lexes and parses a whole program, within the `--max-nesting` limit.
+fn parse_program(src: &str) -> Result<Vec<Stmt>, ParseError> {
+    let mut parser = Parser::from_source(src);
+    parser.set_max_nesting(max_nesting());
+    parser.parse()
+}
 
-    fn next(&mut self) -> Option<char> {
-        let ch = self.peek();
-        if ch.is_some() {
-            self.pos += 1;
+/// Prints a line to stderr if the current verbosity includes `$level`.
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        if log_enabled(Verbosity::$level) {
+            eprintln!($($arg)*);
         }
-        ch
+    };
+}
+
+const TAB_WIDTH: usize = 4;
+
+/// Terminal columns taken up by `text`, expanding tabs.
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// The bit of `class` in the `-W` settings.
+fn class_bit(class: WarningClass) -> u8 {
+    2 << class as u8
+}
+
+/// The `-W` settings: bit 0 promotes warnings to errors, and each class's bit
+/// disables it.
+static WARNING_POLICY: AtomicU8 = AtomicU8::new(0);
+const WARNINGS_AS_ERRORS: u8 = 1;
+
+fn set_warning_policy(as_errors: bool, disabled: &[WarningClass]) {
+    let bits = disabled
+        .iter()
+        .fold(u8::from(as_errors) * WARNINGS_AS_ERRORS, |bits, class| {
+            bits | class_bit(*class)
+        });
+    WARNING_POLICY.store(bits, Ordering::Relaxed);
+}
+
+/// The severity `diag` is reported with under the `-W` settings, or `None`
+/// if its class is switched off.
+fn effective_severity(diag: &Diagnostic) -> Option<Severity> {
+    let policy = WARNING_POLICY.load(Ordering::Relaxed);
+    match diag.class {
+        Some(class) if policy & class_bit(class) != 0 => None,
+        Some(_) if policy & WARNINGS_AS_ERRORS != 0 => Some(Severity::Error),
+        _ => Some(diag.severity),
     }
+}
 
-    fn skip_whitespace(&mut self) {
-        while let Some(c) = self.peek() {
-            if c.is_whitespace() {
-                self.next();
-            } else {
-                break;
-            }
-        }
+/// Prints a diagnostic to stderr in the `--error-format` style, after
+/// applying the `-W` settings, and returns the severity it was reported with.
+/// Every diagnostic goes through here. Warnings are not printed under
+/// `--quiet`, but still count.
+fn emit_diagnostic(origin: &str, src: &str, diag: &Diagnostic) -> Option<Severity> {
+    emit_diagnostic_from(origin, src, 1, diag)
+}
+
+/// [`emit_diagnostic`] for `src` that starts on line `first_line` of
+/// `origin`, such as a statement read from a stream.
+fn emit_diagnostic_from(
+    origin: &str,
+    src: &str,
+    first_line: usize,
+    diag: &Diagnostic,
+) -> Option<Severity> {
+    let severity = effective_severity(diag)?;
+    if severity == Severity::Warning && !log_enabled(Verbosity::Normal) {
+        return Some(severity);
+    }
+    let diag = &Diagnostic {
+        severity,
+        code: diag.code,
+        message: diag.message.clone(),
+        span: diag.span,
+        labels: diag.labels.clone(),
+        class: diag.class,
+    };
+    if json_errors() {
+        eprintln!("{}", diagnostic_json(origin, src, first_line, diag));
+    } else {
+        eprint!("{}", render_diagnostic(origin, src, first_line, diag));
+    }
+    Some(severity)
+}
+
+/// A diagnostic as one JSON object, with 1-based start and end positions,
+/// the lines counted from `first_line`.
+fn diagnostic_json(
+    origin: &str,
+    src: &str,
+    first_line: usize,
+    diag: &Diagnostic,
+) -> serde_json::Value {
+    let line_col = |offset| {
+        let (line, column) = line_col(src, offset);
+        (line + first_line - 1, column)
+    };
+    let (line, column) = line_col(diag.span.start);
+    let (end_line, end_column) = line_col(diag.span.end);
+    let mut json = serde_json::json!({
+        "severity": diag.severity.to_string(),
+        "code": diag.code.id(),
+        "message": diag.message,
+        "file": origin,
+        "line": line,
+        "column": column,
+        "end_line": end_line,
+        "end_column": end_column,
+    });
+    if let Some(class) = diag.class {
+        json["class"] = class.name().into();
     }
+    if !diag.labels.is_empty() {
+        json["labels"] = diag
+            .labels
+            .iter()
+            .map(|(span, note)| {
+                let (line, column) = line_col(span.start);
+                serde_json::json!({ "message": note, "line": line, "column": column })
+            })
+            .collect();
+    }
+    json
+}
 
-    fn read_ident(&mut self, first: char) -> String {
-        let mut s = String::new();
-        s.push(first);
-        while let Some(c) = self.peek() {
-            if c.is_alphanumeric() || c == '_' {
-                s.push(c);
-                self.next();
-            } else {
+/// The most source lines one span is shown with.
+const MAX_SPAN_LINES: usize = 4;
+
+/// Renders a diagnostic the way rustc does: a `--> origin:line:col` header,
+/// then every source line the diagnostic covers, with `^~~` under the span
+/// itself and `---` plus a note under each secondary label. The first line
+/// of `src` is numbered `line_base`.
+fn render_diagnostic(origin: &str, src: &str, line_base: usize, diag: &Diagnostic) -> String {
+    let Diagnostic {
+        severity,
+        code,
+        message,
+        span,
+        labels,
+        ..
+    } = diag;
+    if src.is_empty() {
+        // Programs loaded with `--from-ast` have no source text to point into.
+        return format!("{severity}[{code}]: {message}\n --> {origin}\n");
+    }
+    // Each line shown, by line number: its text and the marker rows under it.
+    let mut lines: BTreeMap<usize, (&str, Vec<String>)> = BTreeMap::new();
+    let marks = std::iter::once((*span, None)).chain(labels.iter().map(|(s, n)| (*s, Some(n))));
+    for (span, note) in marks {
+        let start = span.start.min(src.len());
+        let end = span.end.clamp(start, src.len());
+        let (first_line, _) = line_col(src, start);
+        // The last line is that of the span's last character, which may
+        // take more than one byte.
+        let last_char = src[..end]
+            .char_indices()
+            .next_back()
+            .map_or(start, |(i, _)| i);
+        let (last_line, _) = line_col(src, last_char.max(start));
+        let mut line_no = first_line;
+        let mut line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        loop {
+            let line_end = src[line_start..]
+                .find('\n')
+                .map_or(src.len(), |i| line_start + i);
+            let text = src[line_start..line_end].trim_end_matches('\r');
+            // Past the first line, the marker starts at the indented code.
+            let indented = line_start + (text.len() - text.trim_start().len());
+            let from = if line_start <= start { start } else { indented };
+            let to = end.min(line_start + text.len()).max(from);
+            let last = end <= line_end + 1;
+            let width = display_width(&src[from..to]).max(1);
+            let mut row = " ".repeat(display_width(&src[line_start..from]));
+            match note {
+                None if from == start => row.push_str(&format!("^{}", "~".repeat(width - 1))),
+                None => row.push_str(&"~".repeat(width)),
+                Some(note) => {
+                    row.push_str(&"-".repeat(width));
+                    if last {
+                        row.push_str(&format!(" {note}"));
+                    }
+                }
+            }
+            // Long spans show their first lines and last line, with `...`
+            // for the rest.
+            let elided = last_line - first_line >= MAX_SPAN_LINES
+                && line_no >= first_line + MAX_SPAN_LINES - 1
+                && line_no < last_line;
+            if !elided {
+                lines
+                    .entry(line_no)
+                    .or_insert((text, Vec::new()))
+                    .1
+                    .push(row);
+            }
+            if last || line_end == src.len() {
                 break;
             }
+            line_start = line_end + 1;
+            line_no += 1;
         }
-        s
     }
+    let number = |line_no: usize| line_no + line_base - 1;
+    let (line_no, col) = line_col(src, span.start);
+    let line_no = number(line_no);
+    let gutter = " ".repeat(
+        lines
+            .keys()
+            .last()
+            .map_or(1, |&n| number(n).to_string().len()),
+    );
+    let mut out = format!(
+        "{severity}[{code}]: {message}\n\
+         {gutter}--> {origin}:{line_no}:{col}\n\
+         {gutter} |\n"
+    );
+    let mut prev = None;
+    for (no, (text, rows)) in &lines {
+        if prev.is_some_and(|p| no - p > 1) {
+            out.push_str("...\n");
+        }
+        prev = Some(*no);
+        let text = text.replace('\t', &" ".repeat(TAB_WIDTH));
+        out.push_str(&format!("{:>w$} | {text}\n", number(*no), w = gutter.len()));
+        for row in rows {
+            out.push_str(&format!("{gutter} | {row}\n"));
+        }
+    }
+    out
+}
 
-    fn read_number(&mut self, first: char) -> i64 {
-        let mut s = String::new();
-        s.push(first);
-        while let Some(c) = self.peek() {
-            if c.is_ascii_digit() {
-                s.push(c);
-                self.next();
-            } else {
-                break;
-            }
+/// Why a command failed. Diagnostics have already been printed by the time
+/// one of these is returned; it only decides the process exit code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Failure {
+    Runtime,
+    Parse,
+    Io,
+    Timeout,
+    Interrupted,
+}
+
+const EXIT_RUNTIME: i32 = 1;
+const EXIT_PARSE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_TIMEOUT: i32 = 4;
+/// knitlang panicked: a bug in knitlang rather than in the program.
+const EXIT_INTERNAL: i32 = 5;
+/// Stopped by Ctrl-C, the status shells give a process ended by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Set by Ctrl-C while a program runs; the interpreter stops when it next
+/// looks, and clears it.
+static INTERRUPT: LazyLock<Arc<AtomicBool>> = LazyLock::new(Arc::default);
+/// Whether a program is running, so that Ctrl-C has something to interrupt.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// Whether the REPL is waiting for a line, which Ctrl-C cancels.
+static AT_PROMPT: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C stop the running program with an error, so its summary still
+/// prints and `-i` still reaches the REPL. At the REPL's prompt it throws away
+/// the input so far instead; the terminal discards the line being typed, and
+/// the REPL sees `INTERRUPT` set and drops any unfinished block. Ctrl-C with
+/// no program running, or a second one before the program has noticed the
+/// first, ends the process.
+fn install_interrupt_handler() {
+    let installed = ctrlc::set_handler(|| {
+        if AT_PROMPT.load(Ordering::Relaxed) {
+            INTERRUPT.store(true, Ordering::Relaxed);
+            eprint!("\n{PROMPT}");
+            return;
         }
-        s.parse().unwrap_or(0)
-    }
-
-    fn next_token(&mut self) -> Option<Token> {
-        self.skip_whitespace();
-        Some(match self.next() {
-            Some('{') => Token::LBrace,
-            Some('}') => Token::RBrace,
-            Some(';') => Token::Semicolon,
-            Some('+') => Token::Plus,
-            Some('-') => Token::Minus,
-            Some('*') => Token::Star,
-            Some('/') => Token::Slash,
-            Some('=') => Token::Equal,
-            Some(c) if c.is_ascii_alphabetic() => {
-                let ident = self.read_ident(c);
-                match ident.as_str() {
-                    "cast_on" => Token::CastOn,
-                    "knit" => Token::Knit,
-                    "purl" => Token::Purl,
-                    "bind_off" => Token::BindOff,
-                    "repeat" => Token::Repeat,
-                    other => Token::Ident(other.to_string()),
-                }
+        if !RUNNING.load(Ordering::Relaxed) || INTERRUPT.swap(true, Ordering::Relaxed) {
+            if ANIMATING.load(Ordering::Relaxed) {
+                print!("{}", animate::SHOW_CURSOR);
+                let _ = io::stdout().flush();
             }
-            Some(c) if c.is_ascii_digit() => Token::Number(self.read_number(c)),
-            Some(c) => {
-                panic!("Unexpected character: {c}")
-            }
-            None => return None,
-        })
+            process::exit(EXIT_INTERRUPTED);
+        }
+    });
+    if let Err(e) = installed {
+        log!(Verbose, "Ctrl-C will end knitlang at once: {e}");
     }
 }
 
-#[derive(Debug)]
-enum Expr {
-    Number(i64),
-    Var(String),
-    Binary(Box<Expr>, char, Box<Expr>),
+/// Marks a program as running until dropped, even if running it panics.
+struct Running;
+
+impl Running {
+    fn start() -> Self {
+        RUNNING.store(true, Ordering::Relaxed);
+        Running
+    }
 }
 
-#[derive(Debug)]
-enum Stmt {
-    CastOn(String, Expr), // cast_on name = expr;
-    Knit(String, Expr),   // knit name = expr;
-    Purl(Expr),           // purl expr;
-    Repeat(Expr, Vec<Stmt>),
-    BindOff,
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::Relaxed);
+        // A Ctrl-C the program finished before noticing is spent.
+        INTERRUPT.store(false, Ordering::Relaxed);
+    }
 }
 
-struct Parser {
-    tokens: Vec<Token>,
-    pos: usize,
+/// The file or other source being parsed or run, named when reporting an
+/// internal error.
+static PROCESSING: Mutex<String> = Mutex::new(String::new());
+
+fn set_processing(origin: &str) {
+    let mut processing = PROCESSING.lock().unwrap_or_else(|e| e.into_inner());
+    processing.clear();
+    processing.push_str(origin);
 }
 
-impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
-    }
+/// Replaces Rust's panic message and backtrace advice, which mean nothing to
+/// a knitter, with a short note that knitlang itself went wrong and how to
+/// report it.
+fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        let location = info.location().map_or(String::new(), |l| l.to_string());
+        let processing = PROCESSING.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let issues = concat!(env!("CARGO_PKG_REPOSITORY"), "/issues");
+        if json_errors() {
+            let error = serde_json::json!({
+                "severity": "error",
+                "message": format!("internal error in knitlang, this is a bug: {message}"),
+                "file": processing,
+                "location": location,
+            });
+            eprintln!("{error}");
+            return;
+        }
+        eprintln!("error: internal error in knitlang, this is a bug: {message}");
+        if !processing.is_empty() {
+            eprintln!("  while processing {processing}");
+        }
+        eprintln!("  please report it at {issues}, with the program that caused it");
+        eprintln!("  (panicked at {location})");
+    }));
+}
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+/// In debug builds run with `KNITLANG_DEBUG_PANIC` set, makes `debug_panic()`
+/// panic on purpose, so tests can check how internal errors are reported.
+/// Anywhere else it does not exist, and the REPL never lists or completes it.
+fn add_debug_builtins(interp: &mut Interpreter) {
+    if cfg!(debug_assertions) && env::var_os("KNITLANG_DEBUG_PANIC").is_some() {
+        interp.register_builtin("debug_panic", 0, |_| panic!("debug_panic() was called"));
     }
-    fn next(&mut self) -> Option<&Token> {
-        let t = self.tokens.get(self.pos);
-        if t.is_some() {
-            self.pos += 1;
+}
+
+impl Failure {
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::Runtime => EXIT_RUNTIME,
+            Failure::Parse => EXIT_PARSE,
+            Failure::Io => EXIT_IO,
+            Failure::Timeout => EXIT_TIMEOUT,
+            Failure::Interrupted => EXIT_INTERRUPTED,
         }
-        t
     }
+}
+
+/// Prints an I/O or usage error and returns the matching failure.
+fn io_failure(message: impl fmt::Display) -> Failure {
+    if json_errors() {
+        let error = serde_json::json!({ "severity": "error", "message": message.to_string() });
+        eprintln!("{error}");
+    } else {
+        eprintln!("error: {message}");
+    }
+    Failure::Io
+}
+
+/// Flushes the interpreter's output once a run is over, keeping the run's own
+/// failure if it had one.
+fn finish_output(interp: &mut Interpreter, result: Result<(), Failure>) -> Result<(), Failure> {
+    let flushed = interp
+        .flush()
+        .map_err(|e| io_failure(format_args!("could not write output: {e}")));
+    result.and(flushed)
+}
+
+fn run_src(
+    origin: &str,
+    src: &str,
+    search: &SearchPath,
+    interp: &mut Interpreter,
+) -> Result<(), Failure> {
+    run_sources(vec![(origin.to_string(), src.to_string())], search, interp)
+}
+
+/// Parses every file before running any of them, then executes them in order
+/// against one interpreter so later files see earlier files' variables. A
+/// `bind_off` in any file ends the whole run.
+fn run_files(
+    paths: &[PathBuf],
+    search: &SearchPath,
+    interp: &mut Interpreter,
+) -> Result<(), Failure> {
+    run_sources(read_files(paths, search)?, search, interp)
+}
 
-    fn expect_ident(&mut self) -> String {
-        match self.next() {
-            Some(Token::Ident(s)) => s.clone(),
-            other => panic!("Expected identifier, found: {:?}", other),
+/// Finds and reads every file, returning `(origin, src)` pairs in order.
+/// Every unreadable file is reported before giving up.
+fn read_files(paths: &[PathBuf], search: &SearchPath) -> Result<Vec<(String, String)>, Failure> {
+    let mut sources = Vec::new();
+    let mut failure = None;
+    for path in paths {
+        let path = match search.resolve(path) {
+            Ok(path) => path,
+            Err(message) => {
+                failure.get_or_insert(io_failure(message));
+                continue;
+            }
+        };
+        log!(Verbose, "reading {}", path.display());
+        match read_source(&path) {
+            Ok(source) => sources.push(source),
+            Err(e) => {
+                failure.get_or_insert(io_failure(format_args!(
+                    "could not read {}: {e}",
+                    path.display()
+                )));
+            }
         }
     }
+    failure.map_or(Ok(sources), Err)
+}
+
+/// A parsed source: its origin and text (for diagnostics) and its statements.
+/// The parts of one file split at its imports share its text.
+type Program = (String, Rc<str>, Vec<Stmt>);
+
+/// Parses each `(origin, src)` pair, then runs them in order against `interp`.
+/// Nothing runs if any of them fails to parse.
+fn run_sources(
+    sources: Vec<(String, String)>,
+    search: &SearchPath,
+    interp: &mut Interpreter,
+) -> Result<(), Failure> {
+    run_checked(parse_sources(sources)?, search, interp)
+}
 
-    #[expect(unused)]
-    fn expect_number_expr(&mut self) -> Expr {
-        match self.next() {
-            Some(&Token::Number(n)) => Expr::Number(n),
-            other => panic!("Expected number, found: {:?}", other),
+/// Like `run_files`, but each file holds a JSON syntax tree instead of source.
+/// Their programs have no source text, so diagnostics only name the file.
+fn run_ast_files(
+    paths: &[PathBuf],
+    search: &SearchPath,
+    interp: &mut Interpreter,
+) -> Result<(), Failure> {
+    let mut programs = Vec::new();
+    let mut failure = None;
+    for (origin, json) in read_files(paths, search)? {
+        match ast_from_json(&json) {
+            Ok(stmts) => programs.push((origin, "".into(), stmts)),
+            Err(e) => {
+                emit_diagnostic(&origin, "", &e.into());
+                failure.get_or_insert(Failure::Parse);
+            }
         }
     }
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
+    run_checked(programs, search, interp)
+}
 
-    fn parse_expr(&mut self) -> Expr {
-        self.parse_add_sub()
+/// Loads the files `programs` import, reports lint warnings for every file,
+/// then runs them, optimized if `-O` asked for it.
+fn run_checked(
+    programs: Vec<Program>,
+    search: &SearchPath,
+    interp: &mut Interpreter,
+) -> Result<(), Failure> {
+    let mut importer = importer(search);
+    // Warnings are reported before the run but never stop it; one promoted by
+    // `-W error` only decides the exit code afterwards.
+    let (parts, counts) = load_programs(&mut importer, programs)?;
+    let programs = optimize_programs(parts);
+    let result = run_programs(&programs, interp);
+    if counts.errors > 0 {
+        return result.and(Err(Failure::Parse));
     }
+    result
+}
 
-    fn parse_add_sub(&mut self) -> Expr {
-        let mut node = self.parse_mul_div();
-        loop {
-            match self.peek() {
-                Some(Token::Plus) => {
-                    self.next();
-                    let rhs = self.parse_mul_div();
-                    node = Expr::Binary(Box::new(node), '+', Box::new(rhs));
-                }
-                Some(Token::Minus) => {
-                    self.next();
-                    let rhs = self.parse_mul_div();
-                    node = Expr::Binary(Box::new(node), '-', Box::new(rhs));
-                }
-                _ => break,
+/// Parses each `(origin, src)` pair, reporting every parse error.
+fn parse_sources(sources: Vec<(String, String)>) -> Result<Vec<Program>, Failure> {
+    let mut programs = Vec::new();
+    let mut failure = None;
+    for (origin, src) in sources {
+        set_processing(&origin);
+        let started = Instant::now();
+        match parse_program(&src) {
+            Ok(stmts) => {
+                log!(
+                    Verbose,
+                    "parsed {} from {origin}",
+                    plural(count_stmts(&stmts), "statement")
+                );
+                log!(Trace, "parsing {origin} took {:?}", started.elapsed());
+                programs.push((origin, src.into(), stmts));
+            }
+            Err(e) => {
+                emit_diagnostic(&origin, &src, &e.into());
+                failure.get_or_insert(Failure::Parse);
             }
         }
-        node
     }
+    failure.map_or(Ok(programs), Err)
+}
 
-    fn parse_mul_div(&mut self) -> Expr {
-        let mut node = self.parse_term();
-        loop {
-            match self.peek() {
-                Some(Token::Star) => {
-                    self.next();
-                    let rhs = self.parse_term();
-                    node = Expr::Binary(Box::new(node), '*', Box::new(rhs));
-                }
-                Some(Token::Slash) => {
-                    self.next();
-                    let rhs = self.parse_term();
-                    node = Expr::Binary(Box::new(node), '/', Box::new(rhs));
-                }
-                _ => break,
+/// An importer finding files along `search`, within the `--max-nesting`
+/// limit.
+fn importer(search: &SearchPath) -> Importer<'_> {
+    let mut importer = Importer::new(|from, path| search.import(from, path));
+    importer.set_max_nesting(max_nesting());
+    importer
+}
+
+/// The parts of `programs` and the files they import, in the order they
+/// run, after reporting the lints of every file; see [`lint::check_programs`].
+/// Also returns how many were reported, where `-W error` counts warnings as
+/// errors.
+fn load_programs(
+    importer: &mut Importer,
+    programs: Vec<Program>,
+) -> Result<(Vec<Program>, CheckCounts), Failure> {
+    let programs = programs
+        .into_iter()
+        .map(|(origin, src, stmts)| {
+            let source = Source {
+                key: file_key(Path::new(&origin)),
+                origin,
+                src,
+            };
+            (source, stmts)
+        })
+        .collect();
+    let checked = lint::check_programs(importer, programs).map_err(|e| {
+        emit_diagnostic(&e.origin, &e.src, &e.error.into());
+        Failure::Parse
+    })?;
+    let files = importer.files();
+    let mut counts = CheckCounts::default();
+    for (file, lints) in files.iter().zip(&checked.lints) {
+        emit_lints(&file.origin, &file.src, lints, &mut counts);
+    }
+    let parts = checked
+        .parts
+        .into_iter()
+        .map(|part| {
+            let file = &files[part.file];
+            (file.origin.clone(), Rc::clone(&file.src), part.stmts)
+        })
+        .collect();
+    Ok((parts, counts))
+}
+
+/// Reports the lints of the file `origin`, adding them to `counts`.
+fn emit_lints(origin: &str, src: &str, lints: &Lints, counts: &mut CheckCounts) {
+    log_lint_notes(origin, lints);
+    for diag in &lints.diagnostics {
+        counts.add(emit_diagnostic(origin, src, diag));
+    }
+}
+
+/// Logs what the lints of the file `origin` could not check: names in
+/// `allow` comments that are no warning class, and, under `-v`, where stitch
+/// counts stop being followed.
+fn log_lint_notes(origin: &str, lints: &Lints) {
+    for (line, name) in &lints.unknown_classes {
+        log!(
+            Normal,
+            "{origin}:{line}: `{name}` is not a warning class; `-W help` lists them"
+        );
+    }
+    if let Some((line, reason)) = lints.unverified {
+        log!(
+            Verbose,
+            "{origin}: cannot verify stitch counts from line {line}, as {reason}"
+        );
+    }
+}
+
+/// The `-O` settings, if it was given.
+static OPTIMIZATION: OnceLock<optimize::Options> = OnceLock::new();
+
+/// `programs` as `-O` rewrites them, reporting what changed in each, or
+/// unchanged without it. Warnings are found before this, so they point at
+/// what was written and a copy of an unrolled body is never reported twice.
+fn optimize_programs(programs: Vec<Program>) -> Vec<Program> {
+    let Some(options) = OPTIMIZATION.get() else {
+        return programs;
+    };
+    programs
+        .into_iter()
+        .map(|(origin, src, stmts)| {
+            let (stmts, report) = optimize(stmts, options);
+            log_optimized(&origin, report);
+            (origin, src, stmts)
+        })
+        .collect()
+}
+
+fn log_optimized(origin: &str, report: optimize::Report) {
+    log!(
+        Verbose,
+        "optimized {origin}: folded {}, unrolled {}",
+        plural(report.folded, "constant operation"),
+        plural(report.unrolled, "repeat")
+    );
+}
+
+/// Runs parsed programs in order against `interp`, stopping at the first
+/// `bind_off`, row limit or runtime error.
+fn run_programs(programs: &[Program], interp: &mut Interpreter) -> Result<(), Failure> {
+    let started = Instant::now();
+    interp.restart_timeout();
+    let mut result = Ok(());
+    for (origin, src, stmts) in programs {
+        set_processing(origin);
+        match run_step(origin, src, stmts, interp) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(f) => {
+                result = Err(f);
+                break;
             }
         }
-        node
     }
+    log_run(interp, started);
+    checkpoints_passed(result)
+}
 
-    fn parse_term(&mut self) -> Expr {
-        match self.next() {
-            Some(Token::Number(n)) => Expr::Number(*n),
-            Some(Token::Ident(name)) => Expr::Var(name.clone()),
-            other => panic!("Unexpected token in term: {:?}", other),
+/// Like `run_files`, but parses and runs each file one top-level statement at
+/// a time, so no file is ever held as a whole syntax tree. Lint warnings need
+/// the whole program, so there are none.
+fn run_files_streaming(
+    paths: &[PathBuf],
+    search: &SearchPath,
+    interp: &mut Interpreter,
+) -> Result<(), Failure> {
+    let started = Instant::now();
+    interp.restart_timeout();
+    let mut result = Ok(());
+    let mut importer = importer(search);
+    for path in paths {
+        let ended = read_files(std::slice::from_ref(path), search).and_then(|mut sources| {
+            let (origin, src) = sources.swap_remove(0);
+            let source = Source {
+                key: file_key(Path::new(&origin)),
+                origin,
+                src: src.into(),
+            };
+            stream_source(importer.add(source, Vec::new()), &mut importer, interp)
+        });
+        match ended {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(f) => {
+                result = Err(f);
+                break;
+            }
         }
     }
+    log_run(interp, started);
+    checkpoints_passed(result)
+}
 
-    fn parse_stmt(&mut self) -> Option<Stmt> {
-        match self.peek()? {
-            Token::CastOn => {
-                self.next();
-                let name = self.expect_ident();
-                match self.next() {
-                    Some(Token::Equal) => {}
-                    other => panic!("Expected = after identifier in cast_on, found {:?}", other),
-                }
-                let expr = self.parse_expr();
-                match self.next() {
-                    Some(Token::Semicolon) => {}
-                    other => panic!("Expected ; after cast_on statement, found {:?}", other),
-                }
-                Some(Stmt::CastOn(name, expr))
-            }
-            Token::Knit => {
-                self.next();
-                let name = self.expect_ident();
-                match self.next() {
-                    Some(Token::Equal) => {}
-                    other => panic!("Expected = after identifier in knit, found {:?}", other),
-                }
-                let expr = self.parse_expr();
-                match self.next() {
-                    Some(Token::Semicolon) => {}
-                    other => panic!("Expected ; after knit statement, found {:?}", other),
-                }
-                Some(Stmt::Knit(name, expr))
-            }
-            Token::Purl => {
-                self.next();
-                let expr = self.parse_expr();
-                match self.next() {
-                    Some(Token::Semicolon) => {}
-                    other => panic!("Expected ; after purl statement, found {:?}", other),
-                }
-                Some(Stmt::Purl(expr))
-            }
-            Token::Repeat => {
-                self.next();
-                let count = self.parse_expr();
-                match self.next() {
-                    Some(Token::LBrace) => {}
-                    other => panic!("Expected '{{' after repeat count, found {:?}", other),
-                }
-                let mut body = Vec::new();
-                while !matches!(self.peek(), Some(Token::RBrace)) {
-                    if let Some(s) = self.parse_stmt() {
-                        body.push(s);
-                    } else {
-                        break;
-                    }
-                }
-                match self.next() {
-                    Some(Token::RBrace) => {}
-                    other => panic!("Expected '}}' after repeat body, found {:?}", other),
-                }
-                Some(Stmt::Repeat(count, body))
+/// Parses and runs file `file` of `importer` one top-level statement at a
+/// time, returning whether the run is over. The files it imports are parsed
+/// whole, each where its `use` is reached.
+fn stream_source(
+    file: usize,
+    importer: &mut Importer,
+    interp: &mut Interpreter,
+) -> Result<bool, Failure> {
+    let mut optimized = optimize::Report::default();
+    let result = stream_statements(file, importer, interp, &mut optimized);
+    if OPTIMIZATION.get().is_some() {
+        log_optimized(&importer.files()[file].origin, optimized);
+    }
+    result
+}
+
+/// The loop of [`stream_source`], adding what `-O` changes in each statement
+/// to `optimized`.
+fn stream_statements(
+    file: usize,
+    importer: &mut Importer,
+    interp: &mut Interpreter,
+    optimized: &mut optimize::Report,
+) -> Result<bool, Failure> {
+    let origin = importer.files()[file].origin.clone();
+    let src = Rc::clone(&importer.files()[file].src);
+    let mut stream = statements(&src);
+    stream.set_max_nesting(max_nesting());
+    for stmt in stream {
+        let mut stmt = stmt.map_err(|e| {
+            emit_diagnostic(&origin, &src, &e.into());
+            Failure::Parse
+        })?;
+        let imported = match &stmt.kind {
+            StmtKind::Use(path, alias) => importer.import(file, path, *alias, stmt.span),
+            _ => importer.resolve(file, &mut stmt).map(|()| {
+                vec![knitlang::imports::Part {
+                    file,
+                    stmts: vec![stmt],
+                }]
+            }),
+        };
+        let parts = imported.map_err(|e| {
+            emit_diagnostic(&e.origin, &e.src, &e.error.into());
+            Failure::Parse
+        })?;
+        for part in parts {
+            let mut stmts = part.stmts;
+            if let Some(options) = OPTIMIZATION.get() {
+                let report;
+                (stmts, report) = optimize(stmts, options);
+                optimized.folded += report.folded;
+                optimized.unrolled += report.unrolled;
             }
-            Token::BindOff => {
-                self.next();
-                match self.next() {
-                    Some(Token::Semicolon) => {}
-                    other => panic!("Expected ; after bind_off, found {:?}", other),
-                }
-                Some(Stmt::BindOff)
+            let from = &importer.files()[part.file];
+            if run_step(&from.origin, &from.src, &stmts, interp)? {
+                return Ok(true);
             }
-            other => panic!("Unknown statement start: {:?}", other),
         }
     }
+    Ok(false)
+}
+
+/// Whether `-W error` has reported a warning from an `expect_stitches?`
+/// checkpoint as an error, which fails the run once it is over.
+static CHECKPOINT_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// `result` from a run, failed if `-W error` made a checkpoint's warning an
+/// error along the way.
+fn checkpoints_passed(result: Result<(), Failure>) -> Result<(), Failure> {
+    if CHECKPOINT_FAILED.swap(false, Ordering::Relaxed) {
+        return result.and(Err(Failure::Runtime));
+    }
+    result
+}
 
-    fn parse(&mut self) -> Vec<Stmt> {
-        let mut stmts = Vec::new();
-        while self.peek().is_some() {
-            if let Some(s) = self.parse_stmt() {
-                stmts.push(s);
+/// Runs `stmts` from `origin`, returning whether the run is over because of
+/// a `bind_off` or the row limit. Runtime errors, and the warnings of
+/// checkpoints that let the run go on, are reported here.
+fn run_step(
+    origin: &str,
+    src: &str,
+    stmts: &[Stmt],
+    interp: &mut Interpreter,
+) -> Result<bool, Failure> {
+    let notes = interp.fabric().notes().len();
+    let running = Running::start();
+    let result = interp.run(stmts);
+    drop(running);
+    // Stdout is buffered, so what the run printed is flushed before anything
+    // is reported on stderr, to keep the two in order. A write that fails is
+    // reported when the output is flushed at the end.
+    let _ = interp.flush();
+    for note in &interp.fabric().notes()[notes..] {
+        log!(Verbose, "note: {}", note.text);
+    }
+    for warning in interp.take_warnings() {
+        let diag = Diagnostic::warning(WarningClass::Checkpoint, warning.message, warning.span);
+        if emit_diagnostic(origin, src, &diag) == Some(Severity::Error) {
+            CHECKPOINT_FAILED.store(true, Ordering::Relaxed);
+        }
+    }
+    match result {
+        Ok(true) if interp.truncated() => {
+            log!(
+                Verbose,
+                "stopped in {origin} after {} (--rows)",
+                plural(interp.fabric().rows().len(), "row")
+            );
+            Ok(true)
+        }
+        Ok(true) => {
+            log!(Verbose, "bind_off in {origin} ended the run");
+            Ok(true)
+        }
+        Ok(false) => Ok(false),
+        Err(e) => {
+            emit_diagnostic(origin, src, &e.into());
+            Err(if interp.timed_out() {
+                Failure::Timeout
+            } else if interp.interrupted() {
+                Failure::Interrupted
             } else {
-                break;
+                Failure::Runtime
+            })
+        }
+    }
+}
+
+/// Logs what a run started at `started` did, under `-v`.
+fn log_run(interp: &Interpreter, started: Instant) {
+    log!(
+        Verbose,
+        "executed {} in {:?}",
+        plural(interp.stats().statements as usize, "statement"),
+        started.elapsed()
+    );
+    if interp.fabric().width() > 0 {
+        log!(
+            Verbose,
+            "worked {}, {} on the needles",
+            plural(interp.fabric().rows().len(), "row"),
+            plural(interp.fabric().width(), "stitch")
+        );
+    }
+}
+
+/// Writes one `line:col  Token` line per token in `src`. Lexing errors are
+/// reported in-stream and lexing resumes after the offending character.
+/// Returns `false` if any errors were reported.
+fn dump_tokens(src: &str, out: &mut impl Write) -> io::Result<bool> {
+    let mut lx = Lexer::new(src);
+    let mut ok = true;
+    loop {
+        match lx.next_token() {
+            Ok(Some((token, span))) => {
+                let (line, col) = line_col(src, span.start);
+                writeln!(out, "{line}:{col}  {token:?}")?;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                let (line, col) = line_col(src, e.span.start);
+                writeln!(out, "{line}:{col}  error: {}", e.message)?;
+                ok = false;
             }
         }
-        stmts
+    }
+    Ok(ok)
+}
+
+fn editor_command() -> String {
+    match std::env::var("EDITOR") {
+        Ok(editor) if !editor.trim().is_empty() => editor,
+        _ if cfg!(windows) => "notepad".to_string(),
+        _ => "vi".to_string(),
     }
 }
 
-struct Interpreter {
-    vars: HashMap<String, i64>,
+/// Opens `text` in the user's editor and returns the edited contents, or
+/// `None` if the editor could not be started or exited unsuccessfully.
+fn edit_in_editor(text: &str) -> Option<String> {
+    let path = std::env::temp_dir().join(format!("knitlang-edit-{}.knit", process::id()));
+    if let Err(e) = fs::write(&path, text) {
+        eprintln!("error: could not write {}: {e}", path.display());
+        return None;
+    }
+    let editor = editor_command();
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program).args(parts).arg(&path).status();
+    let edited = match status {
+        Ok(status) if status.success() => fs::read_to_string(&path).ok(),
+        Ok(_) => {
+            eprintln!("edit cancelled");
+            None
+        }
+        Err(e) => {
+            eprintln!("error: could not launch editor `{editor}`: {e}");
+            None
+        }
+    };
+    let _ = fs::remove_file(&path);
+    edited
+}
+
+/// The REPL's prompt for a new statement.
+const PROMPT: &str = "knit> ";
+
+/// The REPL's commands around a [`Session`], which does the running.
+struct Repl<'a> {
+    session: &'a mut Session,
+    /// Name used for this session's input in diagnostics.
+    origin: &'static str,
+    /// Where `:load` looks for files.
+    search: SearchPath,
+    /// How many lines have been fed, for input read as one stream whose
+    /// diagnostics number its lines from the start of it rather than from
+    /// the start of the statement that failed.
+    lines_fed: Option<usize>,
 }
 
-impl Interpreter {
-    fn new() -> Self {
+impl<'a> Repl<'a> {
+    fn new(origin: &'static str, session: &'a mut Session) -> Self {
+        set_processing(origin);
         Self {
-            vars: HashMap::new(),
-        }
-    }
-
-    fn eval_expr(&mut self, e: &Expr) -> i64 {
-        match e {
-            Expr::Number(n) => *n,
-            Expr::Var(name) => *self.vars.get(name).unwrap_or(&0),
-            Expr::Binary(lhs, op, rhs) => {
-                let a = self.eval_expr(lhs);
-                let b = self.eval_expr(rhs);
-                match op {
-                    '+' => a + b,
-                    '-' => a - b,
-                    '*' => a * b,
-                    '/' => a / b,
-                    _ => panic!("Unknown binary op: {}", op),
-                }
-            }
+            session,
+            origin,
+            search: SearchPath::new(&[]),
+            lines_fed: None,
         }
     }
 
-    fn exec_stmt(&mut self, s: &Stmt) -> bool {
-        match s {
-            Stmt::CastOn(name, expr) => {
-                let v = self.eval_expr(expr);
-                self.vars.insert(name.clone(), v);
-                false
-            }
-            Stmt::Knit(name, expr) => {
-                let v = self.eval_expr(expr);
-                self.vars.insert(name.clone(), v);
-                false
-            }
-            Stmt::Purl(expr) => {
-                let v = self.eval_expr(expr);
-                println!("{}", v);
-                false
-            }
-            Stmt::Repeat(count_expr, body) => {
-                let n = self.eval_expr(count_expr);
-                for _ in 0..n {
-                    for st in body {
-                        if self.exec_stmt(st) {
-                            return true;
-                        }
-                    }
+    fn prompt(&self) -> &'static str {
+        if self.session.pending().is_empty() {
+            PROMPT
+        } else {
+            "...> "
+        }
+    }
+
+    /// Feeds a line of source to the session, printing any error.
+    fn feed(&mut self, line: &str) -> Result<FeedResult, Failure> {
+        let running = Running::start();
+        let result = self.session.feed(line);
+        drop(running);
+        if let Some(fed) = &mut self.lines_fed {
+            *fed += line.matches('\n').count() + usize::from(!line.ends_with('\n'));
+        }
+        self.report(result)
+    }
+
+    /// Runs whatever is still pending, reporting the unterminated block.
+    fn finish(&mut self) -> Result<FeedResult, Failure> {
+        let running = Running::start();
+        let result = self.session.finish();
+        drop(running);
+        self.report(result)
+    }
+
+    fn report(&self, result: Result<FeedResult, KnitError>) -> Result<FeedResult, Failure> {
+        result.map_err(|e| {
+            let failure = match e {
+                KnitError::Runtime(_) if self.session.interpreter().interrupted() => {
+                    Failure::Interrupted
                 }
-                false
-            }
-            Stmt::BindOff => true,
+                KnitError::Runtime(_) => Failure::Runtime,
+                KnitError::Lex(_) | KnitError::Parse(_) => Failure::Parse,
+            };
+            let src = self.session.last_source();
+            // The input that failed is the last of what has been fed.
+            let first_line = self
+                .lines_fed
+                .map_or(1, |fed| fed.saturating_sub(src.matches('\n').count()) + 1);
+            emit_diagnostic_from(self.origin, src, first_line, &e.into());
+            failure
+        })
+    }
+
+    fn edit(&mut self) {
+        let text = if self.session.pending().is_empty() {
+            self.session.last_source().to_string()
+        } else {
+            self.session.pending().to_string()
+        };
+        if let Some(edited) = edit_in_editor(&text) {
+            self.session.clear_pending();
+            let _ = self.feed(&edited);
         }
     }
 
-    fn run(&mut self, stmts: &[Stmt]) {
-        for s in stmts {
-            if self.exec_stmt(s) {
-                break;
+    /// Lists every variable, sorted by name, imported ones by their qualified
+    /// names such as `edgings.width`, and the state of the fabric.
+    fn print_vars(&self) {
+        let interp = self.session.interpreter();
+        let mut vars = interp.vars().peekable();
+        if vars.peek().is_none() {
+            println!("no variables");
+        }
+        for (name, value) in vars {
+            println!("{name} = {value}");
+        }
+        let fabric = interp.fabric();
+        if fabric.width() > 0 {
+            println!(
+                "fabric: {}, {} on the needles",
+                plural(fabric.rows().len(), "row"),
+                plural(fabric.width(), "stitch")
+            );
+        }
+    }
+
+    /// Runs the file `name`, found along the search path, in this session.
+    fn load(&mut self, name: &str) {
+        if name.is_empty() {
+            eprintln!("error: usage: :load <file>");
+            return;
+        }
+        let _ = run_files(
+            &[PathBuf::from(name)],
+            &self.search,
+            self.session.interpreter_mut(),
+        );
+    }
+
+    /// Writes the session's variables and fabric to `path`.
+    fn save_session(&self, path: &str) {
+        if path.is_empty() {
+            eprintln!("error: usage: :save-session <file>");
+            return;
+        }
+        let saved = fs::File::create(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                let interp = self.session.interpreter();
+                interp
+                    .save_state(io::BufWriter::new(file))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = saved {
+            eprintln!("error: could not save session to {path}: {e}");
+        }
+    }
+
+    /// Restores variables and fabric saved with `:save-session`.
+    fn load_session(&mut self, path: &str) {
+        if path.is_empty() {
+            eprintln!("error: usage: :load-session <file>");
+            return;
+        }
+        let loaded = fs::File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                let interp = self.session.interpreter_mut();
+                interp
+                    .load_state(io::BufReader::new(file))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = loaded {
+            eprintln!("error: could not load session from {path}: {e}");
+        }
+    }
+
+    /// Handles one line of input, returning `false` when the session should end.
+    fn handle_line(&mut self, line: &str) -> bool {
+        let trimmed = line.trim();
+        if self.session.pending().is_empty() {
+            let (command, rest) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+            match command {
+                "exit" | "quit" => return false,
+                ":edit" => {
+                    self.edit();
+                    return true;
+                }
+                ":load" => {
+                    self.load(rest.trim());
+                    return true;
+                }
+                ":vars" => {
+                    self.print_vars();
+                    return true;
+                }
+                ":help" => {
+                    print_repl_help();
+                    return true;
+                }
+                ":save-session" => {
+                    self.save_session(rest.trim());
+                    return true;
+                }
+                ":load-session" => {
+                    self.load_session(rest.trim());
+                    return true;
+                }
+                "" => return true,
+                _ => {}
             }
+        } else if trimmed == ":edit" {
+            self.edit();
+            return true;
+        }
+        if let Ok(FeedResult::Value(value)) = self.feed(line) {
+            println!("{value}");
         }
+        true
     }
 }
 
-fn lex_all(src: &str) -> Vec<Token> {
-    let mut lx = Lexer::new(src);
-    let mut tokens = Vec::new();
-    while let Some(token) = lx.next_token() {
-        tokens.push(token);
+/// The REPL's commands and the builtin stitch patterns, for `:help`.
+fn print_repl_help() {
+    let commands = [
+        (":edit", "edit the pending input in $EDITOR, then run it"),
+        (":vars", "list every variable and the state of the fabric"),
+        (":load <file>", "run a file in this session"),
+        (":save-session <file>", "save the variables and the fabric"),
+        (":load-session <file>", "pick up a saved session"),
+        (":help", "show this list"),
+        ("exit, quit", "leave the REPL"),
+    ];
+    let patterns: Vec<(String, &str)> = Pattern::ALL
+        .iter()
+        .map(|pattern| {
+            let call = format!("{}({});", pattern.name(), pattern.params().join(", "));
+            (call, pattern.description())
+        })
+        .collect();
+    let width = commands
+        .iter()
+        .map(|(command, _)| command.len())
+        .chain(patterns.iter().map(|(call, _)| call.len()))
+        .max()
+        .unwrap_or(0);
+    println!("Commands:");
+    for (command, what) in commands {
+        println!("  {command:width$}  {what}");
+    }
+    println!("Stitch patterns, worked across whole rows:");
+    for (call, what) in &patterns {
+        println!("  {call:width$}  {what}");
     }
-    tokens
 }
 
-fn run_src(src: &str) {
-    let tokens = lex_all(src);
-    let mut parser = Parser::new(tokens);
-    let stmts = parser.parse();
-    let mut interp = Interpreter::new();
-    interp.run(&stmts);
+/// Runs statements read from `input` until EOF against a single interpreter,
+/// without a banner or prompts. Keeps going after a failing statement, but
+/// reports the first failure once input runs out.
+fn run_stdin(input: impl BufRead, session: &mut Session) -> Result<(), Failure> {
+    let mut repl = Repl::new("<stdin>", session);
+    repl.lines_fed = Some(0);
+    let mut failure = None;
+    for line in input.lines() {
+        let line = line.map_err(|e| io_failure(format_args!("could not read stdin: {e}")))?;
+        if let Err(f) = repl.feed(&line) {
+            failure.get_or_insert(f);
+        }
+        let interp = repl.session.interpreter();
+        if interp.truncated() {
+            log!(
+                Verbose,
+                "stopped after {} (--rows)",
+                plural(interp.fabric().rows().len(), "row")
+            );
+            return failure.map_or(Ok(()), Err);
+        }
+    }
+    if let Err(f) = repl.finish() {
+        failure.get_or_insert(f);
+    }
+    failure.map_or(Ok(()), Err)
 }
 
-fn repl() {
+fn repl(session: &mut Session, search: SearchPath) {
     let mut buf = String::new();
-    let mut interp = Interpreter::new();
+    let mut repl = Repl::new("<repl>", session);
+    repl.search = search;
     loop {
-        print!("knit> ");
-        io::stdout().flush().unwrap();
+        // Prompts go to stderr with the rest of the chatter, so stdout holds
+        // only what the session prints.
+        eprint!("{}", repl.prompt());
         buf.clear();
-        if io::stdin().read_line(&mut buf).is_err() {
+        AT_PROMPT.store(true, Ordering::Relaxed);
+        let read = io::stdin().read_line(&mut buf);
+        AT_PROMPT.store(false, Ordering::Relaxed);
+        if read.is_err() {
             break;
         }
         if buf.is_empty() {
             // Reached EOF
             break;
         }
-        let line = buf.trim();
-        if line == "exit" || line == "quit" {
-            break;
+        if INTERRUPT.swap(false, Ordering::Relaxed) {
+            // Ctrl-C at the prompt; this line was typed after it.
+            repl.session.clear_pending();
         }
-        // try to parse a single statement
-        let tokens = lex_all(line);
-        let mut parser = Parser::new(tokens);
-        if let Some(stmt) = parser.parse_stmt() {
-            interp.exec_stmt(&stmt);
+        // A bug in knitlang should not cost the whole session; the panic
+        // hook has already said what went wrong.
+        match panic::catch_unwind(AssertUnwindSafe(|| repl.handle_line(&buf))) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(_) => repl.session.clear_pending(),
+        }
+        if let Err(e) = repl.session.interpreter_mut().flush() {
+            eprintln!("error: could not write output: {e}");
         }
     }
 }
 
 #[derive(clap::Parser)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Path to a Knitlang source file to run
-    file: Option<PathBuf>,
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Running without a subcommand is the same as `knitlang run`."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Subcommand>,
 
-    /// Run REPL even if no file is provided
-    #[arg(short, long)]
-    repl: bool,
+    #[command(flatten)]
+    run: RunArgs,
 
-    /// Run a named example from the examples/ directory (e.g. --example hello)
-    #[arg(long)]
-    example: Option<String>,
+    /// Only print program output and errors: no banner, warnings or summaries
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print what knitlang is doing to stderr (repeat for more detail)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// How to print errors and warnings
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+
+    /// Chart with plain ASCII, such as `|` for knit and `-` for purl, rather
+    /// than box drawing and knitting symbols, as knitlang does by itself
+    /// when `$TERM` is `dumb` or the locale is not UTF-8
+    #[arg(long, global = true)]
+    ascii: bool,
+
+    /// Reject programs whose repeats and calls nest more than N deep, at
+    /// most 1024
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        default_value_t = DEFAULT_MAX_NESTING as u32,
+        value_parser = clap::value_parser!(u32).range(..=i64::from(MAX_NESTING_LIMIT))
+    )]
+    max_nesting: u32,
 }
 
-fn main() {
-    let args = <Args as clap::Parser>::parse();
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Run programs from files, `-e` or stdin (the default)
+    Run(RunArgs),
+    /// Start the interactive prompt
+    Repl(ReplArgs),
+    /// Lex, parse and validate programs without executing them
+    Check(CheckArgs),
+    /// Rewrite files in canonical style
+    Fmt(FmtArgs),
+    /// Print the syntax tree of a program without running it
+    Parse(ParseArgs),
+    /// Run a named example, or list the available ones
+    Examples(ExamplesArgs),
+    /// Create a new pattern project with a starter program
+    Init(InitArgs),
+    /// Explain an error or warning code, or list them all
+    Explain(ExplainArgs),
+    /// Print a completion script for SHELL to stdout
+    GenerateCompletions(CompletionArgs),
+    /// Print a man page in roff format to stdout
+    GenerateMan,
+}
 
-    if let Some(name) = args.example {
-        let path = format!("examples/{}.knit", name);
-        let src = fs::read_to_string(&path).expect("Failed to read example file");
-        run_src(&src);
-        return;
+#[derive(clap::Args, Clone)]
+struct OutputArgs {
+    /// Write program output to FILE instead of stdout (`-` means stdout)
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// One `-W` setting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WarningSetting {
+    /// `-W error`
+    Error,
+    /// `-W no-<class>`
+    Disable(WarningClass),
+    /// `-W help`
+    Help,
+}
+
+fn parse_warning_setting(text: &str) -> Result<WarningSetting, String> {
+    match text {
+        "error" => return Ok(WarningSetting::Error),
+        "help" => return Ok(WarningSetting::Help),
+        _ => {}
     }
+    text.strip_prefix("no-")
+        .and_then(WarningClass::from_name)
+        .map(WarningSetting::Disable)
+        .ok_or_else(|| format!("unknown warning setting `{text}`; `-W help` lists them"))
+}
 
-    if let Some(path) = args.file {
-        let src = fs::read_to_string(path).expect("Failed to read file");
-        run_src(&src);
-        return;
+#[derive(clap::Args)]
+struct WarningArgs {
+    /// `error` turns warnings into errors, `no-<class>` switches a class of
+    /// warning off, and `help` lists the classes (repeatable)
+    #[arg(short = 'W', value_name = "SETTING", value_parser = parse_warning_setting)]
+    warnings: Vec<WarningSetting>,
+}
+
+impl WarningArgs {
+    /// Applies the settings for the rest of the process. Returns `false` if
+    /// `-W help` asked for the list of classes instead, which is printed here.
+    fn apply(&self) -> bool {
+        if self.warnings.contains(&WarningSetting::Help) {
+            println!("warning classes (switch one off with -W no-<class>):");
+            for class in WarningClass::ALL {
+                println!("  {:<12} {}", class.name(), class.description());
+            }
+            println!("-W error turns every warning that is still on into an error");
+            return false;
+        }
+        let disabled: Vec<WarningClass> = self
+            .warnings
+            .iter()
+            .filter_map(|w| match w {
+                WarningSetting::Disable(class) => Some(*class),
+                _ => None,
+            })
+            .collect();
+        set_warning_policy(self.warnings.contains(&WarningSetting::Error), &disabled);
+        true
     }
+}
 
-    if args.repl || args.file.is_none() {
-        println!("KNITLANG v2 - type 'exit' to quit. Try an example program as a .knit file and pass it as an argument.");
-        repl();
+/// Where to look for files named by a relative path.
+#[derive(clap::Args, Clone)]
+struct SearchArgs {
+    /// Also look for files in DIR, after the current directory and
+    /// `KNIT_PATH` (repeatable)
+    #[arg(long = "path", value_name = "DIR")]
+    path: Vec<PathBuf>,
+}
+
+impl SearchArgs {
+    fn search_path(&self) -> SearchPath {
+        SearchPath::new(&self.path)
+    }
+}
+
+/// Parses a duration such as `500ms`, `5s` or `2m`.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (digits, unit) = text.split_at(split);
+    let invalid = || format!("invalid duration `{text}`: expected a number followed by ms, s or m");
+    let n: u64 = digits.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(n),
+        "s" => Duration::from_secs(n),
+        "m" => Duration::from_secs(n.saturating_mul(60)),
+        _ => return Err(invalid()),
+    };
+    if duration.is_zero() {
+        return Err(format!(
+            "invalid duration `{text}`: must be longer than zero"
+        ));
+    }
+    Ok(duration)
+}
+
+/// A size for `--max-memory`: a number of bytes, or a number followed by K,
+/// M or G for kibibytes, mebibytes or gibibytes.
+fn parse_size(text: &str) -> Result<usize, String> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (digits, unit) = text.split_at(split);
+    let invalid = || {
+        format!("invalid size `{text}`: expected a number of bytes, or one followed by K, M or G")
+    };
+    let n: usize = digits.parse().map_err(|_| invalid())?;
+    let scale: usize = match unit {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" | "m" => 1 << 20,
+        "G" | "g" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    let Some(size) = n.checked_mul(scale) else {
+        return Err(format!("invalid size `{text}`: too large"));
+    };
+    if size == 0 {
+        return Err(format!("invalid size `{text}`: must be more than zero"));
+    }
+    Ok(size)
+}
+
+/// Runs a program again on the interpreter it is given.
+type RerunFn<'a> = dyn Fn(&mut Interpreter) -> Result<(), Failure> + 'a;
+
+/// How to run a program again, if it can be: one read from stdin cannot.
+type Rerun<'a> = Option<&'a RerunFn<'a>>;
+
+/// Options shared by everything that runs a whole program.
+#[derive(clap::Args)]
+struct ExecArgs {
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Stop cleanly after the first N rows are complete, as if the pattern
+    /// bound off there
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    rows: Option<u64>,
+
+    /// Abort the run with an error once it has taken longer than DURATION,
+    /// e.g. `500ms`, `5s` or `2m`
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Abort the run with an error once its variables and fabric take up
+    /// more than SIZE bytes, e.g. `65536`, `512K` or `64M`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_memory: Option<usize>,
+
+    /// After running, print the final variables, fabric and exit status as
+    /// one JSON object
+    #[arg(long)]
+    json: bool,
+
+    /// After running, print rows, stitches, shaping, variable and statement
+    /// counts, and the time taken to stderr
+    #[arg(long)]
+    summary: bool,
+
+    /// After running, print a summary of the piece to stderr: its rows, the
+    /// stitches of each stretch of rows, shaping, widest row, and its
+    /// finished size if the pattern declares a `gauge`. With `--json`, the
+    /// report gets a `pattern` key
+    #[arg(long)]
+    pattern_summary: bool,
+
+    /// With `--pattern-summary`, estimate the yarn the piece takes from MM
+    /// millimetres of it a stitch, rounded up to whole metres
+    #[arg(
+        long,
+        value_name = "MM",
+        requires = "pattern_summary",
+        value_parser = clap::value_parser!(u32).range(1..=10_000)
+    )]
+    yarn_per_stitch: Option<u32>,
+
+    /// Write the `--json` report to FILE instead of stdout (implies `--json`)
+    #[arg(long, value_name = "FILE")]
+    json_out: Option<PathBuf>,
+
+    /// After running, write a chart of the completed rows in FORMAT where
+    /// program output would go, instead of the program output
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    chart_format: Option<ChartFormat>,
+
+    /// With `--chart-format svg` or `png`, make each stitch's cell N pixels
+    /// square
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 20,
+        requires = "chart_format",
+        value_parser = clap::value_parser!(u32).range(1..=1000)
+    )]
+    cell_size: u32,
+
+    /// With `--chart-format`, put row 1 at the EDGE of the chart, with the
+    /// later rows going away from it
+    #[arg(
+        long,
+        value_enum,
+        value_name = "EDGE",
+        default_value_t = ChartOrigin::Bottom,
+        requires = "chart_format"
+    )]
+    chart_origin: ChartOrigin,
+
+    /// With `--chart-format`, chart the wrong-side rows of a piece knitted
+    /// flat as READING says
+    #[arg(
+        long,
+        value_enum,
+        value_name = "READING",
+        default_value_t = FlatReading::RightSide,
+        requires = "chart_format"
+    )]
+    chart_flat_reading: FlatReading,
+
+    /// With `--chart-format`, chart each kind of stitch with the symbols
+    /// publishers in the tradition of STYLE use [default: unicode, or ascii
+    /// with `--ascii`]
+    #[arg(long, value_enum, value_name = "STYLE", requires = "chart_format")]
+    chart_style: Option<SymbolPreset>,
+
+    /// With `--chart-format`, take the symbols of the stitches FILE names
+    /// from it instead of `--chart-style`: a TOML file if its name ends in
+    /// `.toml`, and JSON otherwise, keyed by stitch, such as `k2tog = "/"`
+    #[arg(long, value_name = "FILE", requires = "chart_format")]
+    chart_symbols: Option<PathBuf>,
+
+    /// After running, write the completed rows as written instructions in
+    /// standard abbreviations, such as `Row 1 (RS): k2, p2.`, where program
+    /// output would go, instead of the program output
+    #[arg(long, conflicts_with = "chart_format")]
+    written: bool,
+
+    /// After running, write the completed rows in FORMAT where program
+    /// output would go, instead of the program output. Only flat pieces of
+    /// knit and purl stitches in one yarn can be exported to Knitout
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with_all = ["chart_format", "written"]
+    )]
+    export: Option<ExportFormat>,
+
+    /// With `--export markdown`, write the chart as a PNG image to FILE and
+    /// link to it from the Markdown, instead of drawing a text chart in it
+    #[arg(long, value_name = "FILE", requires = "export")]
+    chart_image: Option<PathBuf>,
+
+    /// Knit size NAME of those the pattern's `sizes` declaration names,
+    /// instead of the first. Without it, `--written` writes a graded pattern
+    /// for every size at once, as `Cast on 88 (96, 104) sts.`
+    #[arg(long, value_name = "NAME")]
+    size: Option<String>,
+
+    /// Write every statement, assignment, completed row, line of output and
+    /// error to FILE as it happens, one JSON object per line; `-` is stderr
+    #[arg(long, value_name = "FILE")]
+    events: Option<PathBuf>,
+
+    /// Fold arithmetic on literals and unroll `repeat`s with small constant
+    /// counts before running. Output is the same; statement counts, steps and
+    /// events follow the rewritten program
+    #[arg(short = 'O', long)]
+    optimize: bool,
+
+    /// With `-O`, unroll a `repeat` only if its count is at most N
+    #[arg(long, value_name = "N", default_value_t = 8, requires = "optimize")]
+    unroll_limit: u64,
+
+    #[command(flatten)]
+    warnings: WarningArgs,
+}
+
+impl ExecArgs {
+    /// The optimizations `-O` asked for, if it was given.
+    fn optimization(&self) -> Option<optimize::Options> {
+        self.optimize.then(|| optimize::Options {
+            unroll_limit: self.unroll_limit,
+            ..optimize::Options::default()
+        })
+    }
+
+    /// An interpreter writing to the requested output, with the requested
+    /// limits applied.
+    fn interpreter(&self) -> Result<Interpreter, Failure> {
+        let out = if self.replaces_output() {
+            Box::new(io::sink())
+        } else {
+            open_output(&self.output)?
+        };
+        self.interpreter_writing_to(out)
+    }
+
+    /// Whether something written after the run, such as a chart, takes the
+    /// place of the program's output.
+    fn replaces_output(&self) -> bool {
+        self.chart_format.is_some() || self.written || self.export.is_some()
+    }
+
+    /// An interpreter writing program output to `out`, with the requested
+    /// limits applied.
+    fn interpreter_writing_to(&self, out: Box<dyn Write>) -> Result<Interpreter, Failure> {
+        let mut interp = Interpreter::with_output(out);
+        interp.set_row_limit(self.rows);
+        interp.set_timeout(self.timeout);
+        interp.set_memory_limit(self.max_memory);
+        interp.set_size(self.size.as_deref());
+        interp.set_interrupt_flag(Some(INTERRUPT.clone()));
+        interp.set_chart_symbols(chart_symbols());
+        add_debug_builtins(&mut interp);
+        if let Some(path) = &self.events {
+            let out: Box<dyn Write> = if path == Path::new("-") {
+                Box::new(io::stderr())
+            } else {
+                match fs::File::create(path) {
+                    Ok(file) => Box::new(io::BufWriter::new(file)),
+                    Err(e) => {
+                        return Err(io_failure(format_args!(
+                            "could not create {}: {e}",
+                            path.display()
+                        )))
+                    }
+                }
+            };
+            interp.set_event_sink(Some(Box::new(JsonLines::new(out))));
+        }
+        Ok(interp)
+    }
+
+    /// Writes a chart of `fabric` where `--output` says program output goes.
+    fn write_chart(&self, format: ChartFormat, fabric: &Fabric) -> Result<(), Failure> {
+        let style = self.chart_style()?;
+        let mut out = open_output(&self.output)?;
+        let written = match format {
+            ChartFormat::Text => {
+                let text = chart::text_styled(fabric, style.orientation, &style.symbols);
+                out.write_all(text.as_bytes())
+            }
+            ChartFormat::Svg => out.write_all(chart::svg(fabric, &style).as_bytes()),
+            ChartFormat::Png => chart::png(fabric, &style, &mut out),
+        };
+        written
+            .and_then(|()| out.flush())
+            .map_err(|e| io_failure(format_args!("could not write chart: {e}")))
+    }
+
+    /// The style the chart flags ask for.
+    fn chart_style(&self) -> Result<chart::Style, Failure> {
+        let worked = self.chart_flat_reading == FlatReading::Worked;
+        let orientation = chart::Orientation {
+            origin: match self.chart_origin {
+                ChartOrigin::Bottom => chart::Origin::Bottom,
+                ChartOrigin::Top => chart::Origin::Top,
+            },
+            reverse_wrong_side: !worked,
+            invert_wrong_side: !worked,
+        };
+        Ok(chart::Style {
+            cell_size: self.cell_size,
+            orientation,
+            symbols: self.chart_symbols()?,
+            ..chart::Style::default()
+        })
+    }
+
+    /// The symbols `--chart-style` names, with those `--chart-symbols`
+    /// gives in place of its own.
+    fn chart_symbols(&self) -> Result<chart::ChartStyle, Failure> {
+        let preset = match self.chart_style {
+            None => chart_symbols(),
+            Some(SymbolPreset::Unicode) => chart::ChartStyle::unicode(),
+            Some(SymbolPreset::Ascii) => chart::ChartStyle::ascii(),
+            Some(SymbolPreset::CraftYarnCouncil) => chart::ChartStyle::craft_yarn_council(),
+            Some(SymbolPreset::Japanese) => chart::ChartStyle::japanese(),
+        };
+        let Some(path) = &self.chart_symbols else {
+            return Ok(preset);
+        };
+        let failure = |e: &dyn fmt::Display| {
+            io_failure(format_args!(
+                "could not load chart symbols from {}: {e}",
+                path.display()
+            ))
+        };
+        let text = fs::read_to_string(path).map_err(|e| failure(&e))?;
+        let overrides: serde_json::Value = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&text).map_err(|e| failure(&e))?
+        } else {
+            serde_json::from_str(&text).map_err(|e| failure(&e))?
+        };
+        preset.with_overrides(&overrides).map_err(|e| failure(&e))
+    }
+
+    /// Writes `fabric` in `format` where `--output` says program output
+    /// goes, or reports what in it cannot be written without writing
+    /// anything.
+    fn write_export(&self, format: ExportFormat, fabric: &Fabric) -> Result<(), Failure> {
+        if self.chart_image.is_some() && !matches!(format, ExportFormat::Markdown) {
+            return Err(io_failure(
+                "--chart-image only works with --export markdown",
+            ));
+        }
+        let text = match format {
+            ExportFormat::Knitout => knitout::knitout(fabric),
+            ExportFormat::Html => Ok(html::html(fabric, &self.chart_style()?)),
+            ExportFormat::Csv => Ok(csv::csv(fabric, self.chart_style()?.orientation)),
+            ExportFormat::Markdown => {
+                let style = self.chart_style()?;
+                let image = match &self.chart_image {
+                    Some(path) if !fabric.rows().is_empty() => {
+                        Some(write_chart_image(path, fabric, &style)?)
+                    }
+                    _ => None,
+                };
+                Ok(markdown::markdown(fabric, &style, image.as_deref()))
+            }
+        };
+        let text = text.map_err(|unsupported| {
+            let mut failure = Failure::Io;
+            for unsupported in unsupported {
+                failure = io_failure(unsupported);
+            }
+            failure
+        })?;
+        let mut out = open_output(&self.output)?;
+        out.write_all(text.as_bytes())
+            .and_then(|()| out.flush())
+            .map_err(|e| io_failure(format_args!("could not write export: {e}")))
+    }
+
+    /// Writes instructions for what `interp` knitted where `--output` says
+    /// program output goes, for every size of a graded pattern if no
+    /// `--size` chose one and `rerun` can run it again.
+    fn write_instructions(&self, interp: &Interpreter, rerun: Rerun) -> Result<(), Failure> {
+        let text = match rerun {
+            Some(rerun) if self.size.is_none() && interp.sizes().len() > 1 => {
+                self.all_sizes(interp, rerun)?
+            }
+            _ => written::instructions(interp.fabric()),
+        };
+        let mut out = open_output(&self.output)?;
+        out.write_all(text.as_bytes())
+            .and_then(|()| out.flush())
+            .map_err(|e| io_failure(format_args!("could not write instructions: {e}")))
+    }
+
+    /// Instructions for every size of the graded pattern that `interp`
+    /// knitted in its first size, knitting each of the others with `rerun`
+    /// on an interpreter of its own. The first run reported any warnings, so
+    /// the others run quietly.
+    fn all_sizes(&self, interp: &Interpreter, rerun: &RerunFn) -> Result<String, Failure> {
+        let sizes: Vec<&str> = interp.sizes().iter().map(|size| size.as_str()).collect();
+        let verbosity = VERBOSITY.swap(Verbosity::Quiet as u8, Ordering::Relaxed);
+        let others: Result<Vec<Interpreter>, Failure> = sizes[1..]
+            .iter()
+            .map(|&size| {
+                let mut other = Interpreter::with_output(Box::new(io::sink()));
+                other.set_row_limit(self.rows);
+                other.set_timeout(self.timeout);
+                other.set_memory_limit(self.max_memory);
+                other.set_size(Some(size));
+                other.set_interrupt_flag(Some(INTERRUPT.clone()));
+                add_debug_builtins(&mut other);
+                rerun(&mut other).map(|()| other)
+            })
+            .collect();
+        VERBOSITY.store(verbosity, Ordering::Relaxed);
+        let others = others?;
+        let fabrics: Vec<&Fabric> = std::iter::once(interp.fabric())
+            .chain(others.iter().map(Interpreter::fabric))
+            .collect();
+        Ok(written::graded(&sizes, &fabrics))
+    }
+
+    /// Flushes program output once a run is over and writes the `--json`
+    /// report, keeping the run's own failure if it had one. `rerun` runs
+    /// the program again, if it can be, for `--written` to knit other sizes.
+    fn finish(
+        &self,
+        interp: &mut Interpreter,
+        result: Result<(), Failure>,
+        rerun: Rerun,
+    ) -> Result<(), Failure> {
+        let result = finish_output(interp, result);
+        let rerun = rerun.filter(|_| result.is_ok());
+        let result = match self.chart_format {
+            Some(format) => result.and(self.write_chart(format, interp.fabric())),
+            None if self.written => result.and(self.write_instructions(interp, rerun)),
+            None => match self.export {
+                Some(format) => result.and(self.write_export(format, interp.fabric())),
+                None => result,
+            },
+        };
+        if self.summary && log_enabled(Verbosity::Normal) {
+            eprint!("{}", interp.stats());
+        }
+        let pattern = self
+            .pattern_summary
+            .then(|| PatternSummary::new(interp.fabric(), self.yarn_per_stitch));
+        if let Some(pattern) = pattern.as_ref().filter(|_| log_enabled(Verbosity::Normal)) {
+            eprint!("{pattern}");
+        }
+        if !self.json && self.json_out.is_none() {
+            return result;
+        }
+        let mut report = interp.state_json();
+        report["exit_status"] = result.err().map_or(0, Failure::exit_code).into();
+        if self.summary {
+            report["stats"] = interp.stats().to_json();
+        }
+        if let Some(pattern) = &pattern {
+            report["pattern"] = pattern.to_json();
+        }
+        let text = format!("{report}\n");
+        let written = match &self.json_out {
+            Some(path) if path != Path::new("-") => fs::write(path, text)
+                .map_err(|e| io_failure(format_args!("could not write {}: {e}", path.display()))),
+            _ => io::stdout()
+                .write_all(text.as_bytes())
+                .map_err(|e| io_failure(format_args!("could not write JSON report: {e}"))),
+        };
+        result.and(written)
+    }
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
+    /// Knitlang source files to run in order against one interpreter, or `-`
+    /// to read a whole program from stdin
+    files: Vec<PathBuf>,
+
+    /// Run SOURCE as a program instead of a file. Repeated flags are joined
+    /// with newlines, so `<eval>:2:1` is the start of the second one
+    #[arg(short, long = "eval", value_name = "SOURCE", conflicts_with = "files")]
+    eval: Vec<String>,
+
+    /// Read statements from stdin until EOF without prompts or banner
+    /// (the default when stdin is not a terminal)
+    #[arg(long, conflicts_with_all = ["files", "eval"])]
+    stdin: bool,
+
+    /// Print the token stream with positions and exit without running
+    #[arg(long)]
+    tokens: bool,
+
+    /// Treat FILES as JSON syntax trees, as printed by `knitlang parse`, and
+    /// run them without lexing or parsing
+    #[arg(long, requires = "files", conflicts_with_all = ["tokens", "watch", "bench"])]
+    from_ast: bool,
+
+    /// Parse and run each file one top-level statement at a time instead of
+    /// parsing everything first, so memory stays flat for huge generated
+    /// programs. Lint warnings are skipped, and a syntax error only stops
+    /// the run once the statements before it have run
+    #[arg(
+        long,
+        requires = "files",
+        conflicts_with_all = ["tokens", "from_ast", "watch", "bench"]
+    )]
+    streaming: bool,
+
+    /// Run the files, then re-run them with a fresh interpreter whenever one
+    /// changes, until interrupted
+    #[arg(long, requires = "files", conflicts_with_all = ["tokens", "stdin"])]
+    watch: bool,
+
+    /// Time repeated runs with output discarded instead of running once
+    #[arg(long, conflicts_with_all = ["tokens", "stdin", "watch"])]
+    bench: bool,
+
+    /// After running, start the REPL with the program's variables and fabric,
+    /// even if it bound off or failed
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["tokens", "stdin", "watch", "bench"]
+    )]
+    interactive: bool,
+
+    /// Pause after every row to show its instructions, and knit on after
+    /// Enter: `b` goes back a row, `s` shows progress and `q` quits, saving
+    /// the session to FILE.session, where FILE is the first file without
+    /// its extension, for the next `--knit-along` to resume from. Needs a
+    /// terminal
+    #[arg(
+        long,
+        requires = "files",
+        conflicts_with_all = ["tokens", "watch", "bench", "interactive"]
+    )]
+    knit_along: bool,
+
+    /// With `--knit-along`, show each row's line of the text chart too
+    #[arg(long, requires = "knit_along")]
+    along_chart: bool,
+
+    /// Redraw the text chart in the terminal as each row is knitted, under
+    /// a status line of the rows and stitches so far, leaving the program's
+    /// output until the run is over. Without a terminal on stdout the run
+    /// is as it would be without it
+    #[arg(
+        long,
+        conflicts_with_all = ["tokens", "watch", "bench", "interactive", "knit_along"]
+    )]
+    animate: bool,
+
+    /// With `--animate`, wait DURATION after each row is drawn, e.g. `50ms`
+    /// or `1s`
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "100ms",
+        value_parser = parse_duration,
+        requires = "animate"
+    )]
+    animate_delay: Duration,
+
+    /// Number of timed runs for `--bench`
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "bench"
+    )]
+    iterations: u32,
+
+    /// Untimed runs before the timed ones for `--bench`
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "bench")]
+    warmup: u32,
+
+    /// Report format for `--bench`
+    #[arg(long, value_enum, default_value_t = BenchFormat::Text, requires = "bench")]
+    bench_format: BenchFormat,
+
+    #[command(flatten)]
+    search: SearchArgs,
+
+    #[command(flatten)]
+    exec: ExecArgs,
+}
+
+impl RunArgs {
+    /// Whether any of these options were given at all.
+    fn is_used(&self) -> bool {
+        !self.files.is_empty()
+            || !self.eval.is_empty()
+            || self.stdin
+            || self.tokens
+            || self.from_ast
+            || self.streaming
+            || self.watch
+            || self.bench
+            || self.interactive
+            || self.knit_along
+            || self.animate
+            || self.exec.output.output.is_some()
+            || self.exec.rows.is_some()
+            || self.exec.timeout.is_some()
+            || self.exec.max_memory.is_some()
+            || self.exec.json
+            || self.exec.summary
+            || self.exec.pattern_summary
+            || !self.exec.warnings.warnings.is_empty()
+            || self.exec.json_out.is_some()
+            || self.exec.chart_format.is_some()
+            || self.exec.written
+            || self.exec.export.is_some()
+            || self.exec.optimize
+            || !self.search.path.is_empty()
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum ChartFormat {
+    /// One character a stitch, `|` for knit and `-` for purl unless
+    /// `--chart-style` says otherwise
+    Text,
+    /// A standalone SVG document
+    Svg,
+    /// A PNG image
+    Png,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum SymbolPreset {
+    /// `│` for knit and `•` for purl in text; the Craft Yarn Council's
+    /// shapes when drawn
+    Unicode,
+    /// `|` for knit and `-` for purl in text, for terminals that cannot
+    /// show the Unicode symbols; drawn as `unicode` is
+    Ascii,
+    /// The Craft Yarn Council's standard symbols, used by most English
+    /// patterns
+    CraftYarnCouncil,
+    /// The Japanese standard symbols
+    Japanese,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum ExportFormat {
+    /// Knitout, for knitting machines
+    Knitout,
+    /// One self-contained HTML page of the pattern, with its chart
+    Html,
+    /// Markdown, for READMEs and pattern pages
+    Markdown,
+    /// CSV of every stitch, one record per row, for spreadsheets
+    Csv,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum ChartOrigin {
+    /// Row 1 at the bottom, as knitting charts are drawn
+    Bottom,
+    /// Row 1 at the top
+    Top,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum FlatReading {
+    /// From the left, each stitch as it looks from the right side, as
+    /// knitting charts read
+    RightSide,
+    /// From the right like right-side rows, each stitch as it was worked
+    Worked,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum BenchFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::Args)]
+struct ReplArgs {
+    #[command(flatten)]
+    search: SearchArgs,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(clap::Args)]
+struct CheckArgs {
+    /// Files to check, or `-` for stdin (the default when there are none)
+    files: Vec<PathBuf>,
+
+    /// Check SOURCE instead of a file, joined like `run -e`
+    #[arg(short, long = "eval", value_name = "SOURCE", conflicts_with = "files")]
+    eval: Vec<String>,
+
+    #[command(flatten)]
+    search: SearchArgs,
+
+    #[command(flatten)]
+    warnings: WarningArgs,
+}
+
+#[derive(clap::Args)]
+struct InitArgs {
+    /// Directory to create; the project is named after it
+    #[arg(value_name = "DIR")]
+    dir: PathBuf,
+
+    /// Write into DIR even if it already has files, replacing any starter files
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(clap::Args)]
+struct CompletionArgs {
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
+}
+
+#[derive(clap::Args)]
+struct FmtArgs {
+    /// Files to rewrite in place, or `-` to format stdin to stdout
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct ParseArgs {
+    /// File to parse, or `-` for stdin
+    #[arg(value_name = "FILE", default_value = "-")]
+    file: PathBuf,
+
+    /// How to print the tree
+    #[arg(long, value_enum, default_value_t = AstFormat::Json)]
+    format: AstFormat,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum AstFormat {
+    /// Nested objects tagged with their `kind`, with byte spans
+    Json,
+    /// One S-expression per statement, such as `(purl (var x))`, without
+    /// spans
+    Sexp,
+}
+
+#[derive(clap::Args)]
+struct ExamplesArgs {
+    /// Example to run, built in or from ./examples; `list` prints their names
+    #[arg(value_name = "NAME", default_value = "list")]
+    name: String,
+
+    #[command(flatten)]
+    search: SearchArgs,
+
+    #[command(flatten)]
+    exec: ExecArgs,
+}
+
+#[derive(clap::Args)]
+struct ExplainArgs {
+    /// Code to explain, e.g. K0301, as printed with an error; without one,
+    /// every code is listed
+    #[arg(value_name = "CODE")]
+    code: Option<String>,
+}
+
+/// Reads a program named on the command line, where `-` means all of stdin.
+/// Returns the name to use in diagnostics along with the source.
+fn read_source(path: &Path) -> io::Result<(String, String)> {
+    if path == Path::new("-") {
+        Ok(("<stdin>".to_string(), io::read_to_string(io::stdin())?))
+    } else {
+        Ok((path.display().to_string(), fs::read_to_string(path)?))
+    }
+}
+
+/// The diagnostics a check reported, and the files it could not read.
+#[derive(Default)]
+struct CheckCounts {
+    errors: usize,
+    warnings: usize,
+    unreadable: usize,
+}
+
+impl CheckCounts {
+    /// Counts a diagnostic reported with `severity`, if it was reported.
+    fn add(&mut self, severity: Option<Severity>) {
+        match severity {
+            Some(Severity::Error) => self.errors += 1,
+            Some(Severity::Warning) => self.warnings += 1,
+            None => {}
+        }
+    }
+
+    fn result(&self) -> Result<(), Failure> {
+        if self.errors > 0 {
+            Err(Failure::Parse)
+        } else if self.unreadable > 0 {
+            Err(Failure::Io)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Checks the `-e` sources, or every file on the command line (stdin when
+/// there are none), and prints a summary.
+fn cmd_check(args: &CheckArgs) -> Result<(), Failure> {
+    let mut counts = CheckCounts::default();
+    let sources = if args.eval.is_empty() {
+        read_check_files(args, &mut counts)
+    } else {
+        vec![("<eval>".to_string(), args.eval.join("\n"))]
+    };
+    if counts.unreadable == 0 {
+        check_sources(sources, &args.search.search_path(), &mut counts);
+    }
+    log!(
+        Normal,
+        "checked {}: {}, {}",
+        plural(args.files.len().max(1), "file"),
+        plural(counts.errors + counts.unreadable, "error"),
+        plural(counts.warnings, "warning"),
+    );
+    counts.result()
+}
+
+/// Finds and reads the files `args` names, or stdin when there are none,
+/// counting those that cannot be read.
+fn read_check_files(args: &CheckArgs, counts: &mut CheckCounts) -> Vec<(String, String)> {
+    let search = args.search.search_path();
+    let stdin = [PathBuf::from("-")];
+    let paths = if args.files.is_empty() {
+        &stdin[..]
+    } else {
+        &args.files
+    };
+    let mut sources = Vec::new();
+    for path in paths {
+        let read = search.resolve(path).and_then(|path| {
+            read_source(&path).map_err(|e| format!("could not read {}: {e}", path.display()))
+        });
+        match read {
+            Ok(source) => sources.push(source),
+            Err(message) => {
+                io_failure(message);
+                counts.unreadable += 1;
+            }
+        }
+    }
+    sources
+}
+
+/// Checks `sources` as `run` would take them before running: every file is
+/// parsed, then the files they import are loaded and linted, and finally
+/// each variable read is checked to have a value by then. Like `run`, none
+/// of that happens if any file fails to parse.
+fn check_sources(sources: Vec<(String, String)>, search: &SearchPath, counts: &mut CheckCounts) {
+    let mut programs = Vec::new();
+    for (origin, src) in sources {
+        set_processing(&origin);
+        match parse_program(&src) {
+            Ok(stmts) => programs.push((origin, src.into(), stmts)),
+            Err(e) => counts.add(emit_diagnostic(&origin, &src, &e.into())),
+        }
+    }
+    if counts.errors > 0 {
+        return;
+    }
+    let mut importer = importer(search);
+    let parts = match load_programs(&mut importer, programs) {
+        Ok((parts, lints)) => {
+            counts.errors += lints.errors;
+            counts.warnings += lints.warnings;
+            parts
+        }
+        Err(_) => {
+            counts.errors += 1;
+            return;
+        }
+    };
+    let stmts = parts.iter().map(|(_, _, stmts)| &stmts[..]);
+    if let Some((i, undefined)) = resolve::first_undefined_in_order(stmts) {
+        let (origin, src, _) = &parts[i];
+        counts.add(emit_diagnostic(origin, src, &undefined.to_error([]).into()));
+    }
+}
+
+/// Formats one source, refusing if it does not parse. The formatted text is
+/// checked to lex to the same tokens and comments as the original, so
+/// formatting can only ever change whitespace and where comments sit.
+fn format_src(origin: &str, src: &str) -> Result<String, Failure> {
+    let stmts = parse_program(src).map_err(|e| {
+        emit_diagnostic(origin, src, &e.into());
+        Failure::Parse
+    })?;
+    let formatted = format_with_comments(&stmts, src);
+    if tokens_and_comments(src) != tokens_and_comments(&formatted) {
+        return Err(io_failure(format_args!(
+            "formatting {origin} would change its meaning; leaving it untouched"
+        )));
+    }
+    Ok(formatted)
+}
+
+/// The tokens of `src` and the text of its comments, or `None` if it does not
+/// lex.
+fn tokens_and_comments(src: &str) -> Option<(Vec<Token>, Vec<&str>)> {
+    let (tokens, trailing) = lex_with_trivia(src).ok()?;
+    let comments = tokens
+        .iter()
+        .flat_map(|t| &t.leading)
+        .chain(&trailing)
+        .filter(|t| t.kind == TriviaKind::Comment)
+        .map(|t| src[t.span.start..t.span.end].trim_end())
+        .collect();
+    Some((tokens.into_iter().map(|t| t.token).collect(), comments))
+}
+
+/// Rewrites each file in canonical style. The new contents go to a temporary
+/// file next to the original, which is then renamed over it, so a file is
+/// never left half-written. `-` formats stdin to stdout.
+fn format_files(paths: &[PathBuf]) -> Result<(), Failure> {
+    let mut failure = None;
+    for path in paths {
+        let (origin, src) = match read_source(path) {
+            Ok(source) => source,
+            Err(e) => {
+                failure.get_or_insert(io_failure(format_args!(
+                    "could not read {}: {e}",
+                    path.display()
+                )));
+                continue;
+            }
+        };
+        let formatted = match format_src(&origin, &src) {
+            Ok(formatted) => formatted,
+            Err(f) => {
+                failure.get_or_insert(f);
+                continue;
+            }
+        };
+        if path == Path::new("-") {
+            print!("{formatted}");
+            continue;
+        }
+        if formatted == src {
+            log!(Verbose, "{origin} is already formatted");
+            continue;
+        }
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{file_name}.fmt-tmp"));
+        if let Err(e) = fs::write(&tmp, &formatted).and_then(|()| fs::rename(&tmp, path)) {
+            let _ = fs::remove_file(&tmp);
+            failure.get_or_insert(io_failure(format_args!(
+                "could not write {}: {e}",
+                path.display()
+            )));
+            continue;
+        }
+        log!(Verbose, "formatted {origin}");
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+/// Writes the chart of `fabric` as a PNG image to `path` for Markdown to
+/// link to, returning the path as the link gives it.
+fn write_chart_image(
+    path: &Path,
+    fabric: &Fabric,
+    style: &chart::Style,
+) -> Result<String, Failure> {
+    let failure = |e: io::Error| {
+        io_failure(format_args!(
+            "could not write chart image {}: {e}",
+            path.display()
+        ))
+    };
+    let mut out = io::BufWriter::new(fs::File::create(path).map_err(failure)?);
+    chart::png(fabric, style, &mut out)
+        .and_then(|()| out.flush())
+        .map_err(failure)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Opens the sink for program output: stdout, or the `--output` file,
+/// created or truncated and buffered.
+fn open_output(args: &OutputArgs) -> Result<Box<dyn Write>, Failure> {
+    match &args.output {
+        Some(path) if path != Path::new("-") => match fs::File::create(path) {
+            Ok(file) => Ok(Box::new(io::BufWriter::new(file))),
+            Err(e) => Err(io_failure(format_args!(
+                "could not create {}: {e}",
+                path.display()
+            ))),
+        },
+        // Stdout on its own writes every line as it comes, which dominates
+        // programs that purl a lot. Runs flush when they finish, and the REPL
+        // after every line, so output still shows up before the next prompt.
+        _ => Ok(Box::new(io::BufWriter::new(io::stdout()))),
+    }
+}
+
+/// `knitlang run --tokens`: dumps the tokens of the `-e` sources, the one file
+/// given, or stdin.
+fn cmd_tokens(args: &RunArgs) -> Result<(), Failure> {
+    let src = if !args.eval.is_empty() {
+        args.eval.join("\n")
+    } else if args.files.len() > 1 {
+        return Err(io_failure("--tokens takes a single file"));
+    } else if let Some(path) = args.files.first() {
+        read_source(path)
+            .map_err(|e| io_failure(format_args!("could not read {}: {e}", path.display())))?
+            .1
+    } else {
+        io::read_to_string(io::stdin())
+            .map_err(|e| io_failure(format_args!("could not read stdin: {e}")))?
+    };
+    match dump_tokens(&src, &mut io::stdout().lock()) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Failure::Parse),
+        Err(e) => Err(io_failure(format_args!("could not write tokens: {e}"))),
+    }
+}
+
+/// `knitlang run`: the `-e` sources, the files, or stdin. The other ways to
+/// run, such as `--bench` or falling back to the REPL with nothing to read,
+/// are [`dispatch`]ed before this.
+fn cmd_run(args: &RunArgs) -> Result<(), Failure> {
+    if args.interactive && args.exec.chart_format.is_some() {
+        return Err(io_failure(
+            "--chart-format cannot be used with --interactive",
+        ));
+    }
+    if args.interactive && args.exec.written {
+        return Err(io_failure("--written cannot be used with --interactive"));
+    }
+    if args.interactive && args.exec.export.is_some() {
+        return Err(io_failure("--export cannot be used with --interactive"));
+    }
+    let mut session = Session::with_interpreter(args.exec.interpreter()?);
+    session.set_max_nesting(max_nesting());
+    let from_stdin = args.eval.is_empty() && args.files.is_empty();
+    let run = |interp: &mut Interpreter| run_program(args, interp);
+    let result = if from_stdin {
+        run_stdin(io::stdin().lock(), &mut session)
+    } else {
+        run(session.interpreter_mut())
+    };
+    let rerun: Rerun = if from_stdin { None } else { Some(&run) };
+    let result = args.exec.finish(session.interpreter_mut(), result, rerun);
+    if !args.interactive {
+        return result;
+    }
+    print_banner();
+    repl(&mut session, args.search.search_path());
+    Ok(())
+}
+
+/// Runs the program from `-e` or the files `args` names against `interp`.
+fn run_program(args: &RunArgs, interp: &mut Interpreter) -> Result<(), Failure> {
+    let search = args.search.search_path();
+    if !args.eval.is_empty() {
+        run_src("<eval>", &args.eval.join("\n"), &search, interp)
+    } else if args.from_ast {
+        run_ast_files(&args.files, &search, interp)
+    } else if args.streaming {
+        run_files_streaming(&args.files, &search, interp)
+    } else {
+        run_files(&args.files, &search, interp)
+    }
+}
+
+/// Wall times of repeated runs of one program, from `--bench`.
+struct BenchReport {
+    timings: Timings,
+    /// Statements executed by each run.
+    statements: u64,
+}
+
+impl BenchReport {
+    fn statements_per_sec(&self) -> f64 {
+        let total = self.statements * self.timings.runs() as u64;
+        total as f64 / self.timings.total().as_secs_f64().max(1e-9)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let timings = &self.timings;
+        serde_json::json!({
+            "iterations": timings.runs(),
+            "warmup": timings.warmup,
+            "min_ns": timings.min().as_nanos() as u64,
+            "median_ns": timings.median().as_nanos() as u64,
+            "mean_ns": timings.mean().as_nanos() as u64,
+            "statements": self.statements,
+            "statements_per_sec": self.statements_per_sec().round() as u64,
+        })
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timings = &self.timings;
+        writeln!(
+            f,
+            "iterations:   {} ({} warmup)",
+            timings.runs(),
+            timings.warmup
+        )?;
+        writeln!(f, "min:          {:?}", timings.min())?;
+        writeln!(f, "median:       {:?}", timings.median())?;
+        writeln!(f, "mean:         {:?}", timings.mean())?;
+        writeln!(f, "statements:   {}", self.statements)?;
+        writeln!(f, "statements/s: {:.0}", self.statements_per_sec())
+    }
+}
+
+/// Runs `programs` `warmup + iterations` times, each against a fresh
+/// interpreter whose output is discarded, timing all but the warmup runs. A
+/// runtime error is reported once and ends the benchmark.
+fn bench(
+    programs: &[Program],
+    iterations: u32,
+    warmup: u32,
+    row_limit: Option<u64>,
+) -> Result<BenchReport, Failure> {
+    let mut statements = 0;
+    let setup = || {
+        let mut interp = Interpreter::with_output(Box::new(io::sink()));
+        interp.set_row_limit(row_limit);
+        // Timed with Ctrl-C watched for, as real runs are.
+        interp.set_interrupt_flag(Some(INTERRUPT.clone()));
+        interp
+    };
+    let timings = bench::measure(warmup, iterations, setup, |mut interp| {
+        let _running = Running::start();
+        for (origin, src, stmts) in programs {
+            match interp.run(stmts) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => {
+                    emit_diagnostic(origin, src, &e.into());
+                    return Err(if interp.interrupted() {
+                        Failure::Interrupted
+                    } else {
+                        Failure::Runtime
+                    });
+                }
+            }
+        }
+        statements = interp.stats().statements;
+        // Handed back so that it is dropped after the clock stops.
+        Ok(interp)
+    })?;
+    Ok(BenchReport {
+        timings,
+        statements,
+    })
+}
+
+/// `knitlang run --bench`: parses once, then times repeated runs.
+fn cmd_bench(args: &RunArgs) -> Result<(), Failure> {
+    let sources = if args.eval.is_empty() {
+        read_files(&args.files, &args.search.search_path())?
+    } else {
+        vec![("<eval>".to_string(), args.eval.join("\n"))]
+    };
+    let search = args.search.search_path();
+    let mut importer = importer(&search);
+    let (programs, _) = load_programs(&mut importer, parse_sources(sources)?)?;
+    let programs = optimize_programs(programs);
+    let report = bench(&programs, args.iterations, args.warmup, args.exec.rows)?;
+    match args.bench_format {
+        BenchFormat::Text => print!("{report}"),
+        BenchFormat::Json => println!("{}", report.to_json()),
+    }
+    Ok(())
+}
+
+/// How often `--watch` looks at the files' modification times.
+const WATCH_POLL: Duration = Duration::from_millis(100);
+
+/// Modification times of the files `args` runs, `None` for any that can't be
+/// read right now (e.g. mid-save by an editor that writes via rename).
+fn watch_stamps(args: &RunArgs) -> Vec<Option<SystemTime>> {
+    let search = args.search.search_path();
+    args.files
+        .iter()
+        .map(|path| {
+            let path = search.resolve(path).ok()?;
+            fs::metadata(path).and_then(|m| m.modified()).ok()
+        })
+        .collect()
+}
+
+/// The current UTC time of day as `HH:MM:SS`.
+fn clock_time() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (h, m, s) = ((secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+    format!("{h:02}:{m:02}:{s:02} UTC")
+}
+
+/// `knitlang run --watch`: runs the files against a fresh interpreter each
+/// time any of them changes. Failures are reported and watching carries on;
+/// the loop only ends when the process is interrupted.
+fn watch(args: &RunArgs) -> ! {
+    let search = args.search.search_path();
+    loop {
+        let stamps = watch_stamps(args);
+        if let Ok(mut interp) = args.exec.interpreter() {
+            let result = run_files(&args.files, &search, &mut interp);
+            let rerun = |interp: &mut Interpreter| run_files(&args.files, &search, interp);
+            let _ = args.exec.finish(&mut interp, result, Some(&rerun));
+        }
+        log!(
+            Normal,
+            "-- watching {} for changes (Ctrl-C to stop)",
+            plural(args.files.len(), "file")
+        );
+        // Wait for a change, then for the files to settle so a burst of
+        // writes from one save only triggers a single run.
+        while watch_stamps(args) == stamps {
+            thread::sleep(WATCH_POLL);
+        }
+        let mut settled = watch_stamps(args);
+        loop {
+            thread::sleep(WATCH_POLL);
+            let now = watch_stamps(args);
+            if now == settled && now.iter().all(Option::is_some) {
+                break;
+            }
+            settled = now;
+        }
+        if io::stdout().is_terminal() {
+            print!("\x1b[2J\x1b[H");
+        }
+        log!(Normal, "== {} re-running ==", clock_time());
+    }
+}
+
+/// How many rows back `b` can go while knitting along.
+const ALONG_HISTORY: usize = 20;
+
+/// The state of a knit-along, shared between the hook that paces the run
+/// and the code that started it.
+struct Along {
+    /// Rows paced so far; the next row to show is this one.
+    shown: usize,
+    /// The row the knitter is looking at, which `b` moves back from the
+    /// last one shown.
+    view: usize,
+    /// A saved session for each of the last rows shown, as `save_state`
+    /// writes it once the row is complete.
+    snapshots: VecDeque<(usize, Vec<u8>)>,
+    /// The rows of a session saved by an earlier knit-along, until the run
+    /// has knitted as many to compare them with.
+    resume: Option<Vec<Vec<Stitch>>>,
+    session: PathBuf,
+    chart: bool,
+    started: Instant,
+    quit: bool,
+}
+
+/// The hook that stops the run after each row for [`Along::pace`].
+struct AlongHook(Rc<RefCell<Along>>);
+
+impl ExecHook for AlongHook {
+    fn before_stmt(&mut self, _stmt: &Stmt, _interp: &Interpreter) -> HookAction {
+        if self.0.borrow().quit {
+            HookAction::Stop
+        } else {
+            HookAction::Continue
+        }
+    }
+
+    fn after_stmt(&mut self, _stmt: &Stmt, interp: &Interpreter) {
+        self.0.borrow_mut().pace(interp);
+    }
+}
+
+impl Along {
+    /// Shows every row `interp` has completed since the last call, waiting
+    /// for a command after each, unless the knitter has quit.
+    fn pace(&mut self, interp: &Interpreter) {
+        let fabric = interp.fabric();
+        let rows = fabric.rows().len();
+        if let Some(saved) = &self.resume {
+            if rows < saved.len() {
+                return;
+            }
+            if fabric.rows()[..saved.len()] == saved[..] {
+                self.shown = saved.len();
+                eprintln!(
+                    "resuming at row {}, where {} left off",
+                    self.shown + 1,
+                    self.session.display()
+                );
+            } else {
+                eprintln!(
+                    "{} was saved from another version of the pattern; starting at row 1",
+                    self.session.display()
+                );
+            }
+            self.resume = None;
+        }
+        while self.shown < rows && !self.quit {
+            let r = self.shown;
+            let mut snapshot = Vec::new();
+            if interp.save_state(&mut snapshot).is_ok() {
+                if self.snapshots.len() == ALONG_HISTORY {
+                    self.snapshots.pop_front();
+                }
+                self.snapshots.push_back((r, snapshot));
+            }
+            self.view = r;
+            self.show(fabric);
+            self.commands(interp);
+            self.shown += 1;
+        }
+    }
+
+    /// Prints the row being looked at.
+    fn show(&self, fabric: &Fabric) {
+        println!("{}", written::row_instructions(fabric, self.view));
+        if self.chart {
+            println!(
+                "{}",
+                chart::text_row_styled(fabric, self.view, &chart_symbols())
+            );
+        }
+    }
+
+    /// Reads commands until one knits on or quits.
+    fn commands(&mut self, interp: &Interpreter) {
+        let latest = self.shown;
+        loop {
+            eprint!("along> ");
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                // End of input quits as `q` does.
+                eprintln!();
+                self.save_and_quit();
+                return;
+            }
+            match line.trim() {
+                "" if self.view < latest => {
+                    self.view += 1;
+                    self.show(interp.fabric());
+                }
+                "" => return,
+                "b" => match self.snapshots.front() {
+                    _ if self.view == 0 => eprintln!("this is the first row"),
+                    Some(&(oldest, _)) if self.view > oldest => {
+                        self.view -= 1;
+                        self.show(interp.fabric());
+                    }
+                    _ => eprintln!("cannot go back before row {}", self.view + 1),
+                },
+                "s" => self.summary(interp),
+                "q" => {
+                    self.save_and_quit();
+                    return;
+                }
+                other => eprintln!(
+                    "unknown command `{other}`; press Enter for the next row, or type b, s or q"
+                ),
+            }
+        }
+    }
+
+    /// Prints how far the knitting has got.
+    fn summary(&self, interp: &Interpreter) {
+        let stats = interp.stats();
+        eprintln!(
+            "on row {} of the {} knitted so far, with {} on the needles",
+            self.view + 1,
+            stats.rows,
+            plural(interp.fabric().width(), "stitch")
+        );
+        eprintln!(
+            "{} worked, {}, {}, in {}",
+            plural(stats.stitches, "stitch"),
+            plural(stats.increases, "increase"),
+            plural(stats.decreases, "decrease"),
+            knitlang::format_duration(self.started.elapsed())
+        );
+    }
+
+    /// Saves the session as it was after the row being looked at, for the
+    /// next knit-along to resume from, and stops the run.
+    fn save_and_quit(&mut self) {
+        self.quit = true;
+        let Some((_, snapshot)) = self.snapshots.iter().find(|(r, _)| *r == self.view) else {
+            return;
+        };
+        match fs::write(&self.session, snapshot) {
+            Ok(()) => eprintln!(
+                "stopped after row {}; saved to {}",
+                self.view + 1,
+                self.session.display()
+            ),
+            Err(e) => {
+                io_failure(format_args!(
+                    "could not save {}: {e}",
+                    self.session.display()
+                ));
+            }
+        }
+    }
+}
+
+/// `knitlang run --knit-along`: runs the files, stopping after every row for
+/// the knitter to catch up. A session an earlier knit-along saved is picked
+/// up where it left off if the pattern still knits the same rows up to it.
+fn knit_along(args: &RunArgs) -> Result<(), Failure> {
+    if !io::stdin().is_terminal() {
+        return Err(io_failure(
+            "--knit-along waits for commands from a terminal, but stdin is not one",
+        ));
+    }
+    let session = args.files[0].with_extension("session");
+    let resume = fs::File::open(&session).ok().and_then(|file| {
+        let mut saved = Interpreter::with_output(Box::new(io::sink()));
+        saved.load_state(io::BufReader::new(file)).ok()?;
+        Some(saved.fabric().rows().to_vec())
+    });
+    let along = Rc::new(RefCell::new(Along {
+        shown: 0,
+        view: 0,
+        snapshots: VecDeque::new(),
+        resume,
+        session,
+        chart: args.along_chart,
+        started: Instant::now(),
+        quit: false,
+    }));
+    log!(
+        Normal,
+        "press Enter after each row to knit on; b goes back a row, s shows progress, q quits"
+    );
+    let mut interp = args.exec.interpreter()?;
+    interp.set_hook(Some(Box::new(AlongHook(along.clone()))));
+    let result = run_program(args, &mut interp);
+    let mut along = along.borrow_mut();
+    if along.resume.take().is_some() {
+        eprintln!(
+            "the pattern has fewer rows than {} now; starting at row 1",
+            along.session.display()
+        );
+        along.pace(&interp);
+    }
+    if result.is_ok() && !along.quit {
+        log!(Normal, "finished, after {}", plural(along.shown, "row"));
+        let _ = fs::remove_file(&along.session);
+    }
+    let rerun = |interp: &mut Interpreter| run_program(args, interp);
+    args.exec.finish(&mut interp, result, Some(&rerun))
+}
+
+/// Whether `--animate` is drawing, so that a Ctrl-C that ends knitlang at
+/// once shows the cursor again first.
+static ANIMATING: AtomicBool = AtomicBool::new(false);
+
+/// Program output held back while `--animate` draws on stdout, and written
+/// there once the run is over and flushes it.
+#[derive(Default)]
+struct Held(Vec<u8>);
+
+impl Write for Held {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&self.0)?;
+        self.0.clear();
+        stdout.flush()
+    }
+}
+
+/// Draws a frame whenever a statement completes a row, then waits.
+struct AnimateHook {
+    animation: Animation<io::Stdout>,
+    shown: usize,
+    delay: Duration,
+}
+
+impl ExecHook for AnimateHook {
+    fn before_stmt(&mut self, _stmt: &Stmt, _interp: &Interpreter) -> HookAction {
+        HookAction::Continue
+    }
+
+    fn after_stmt(&mut self, _stmt: &Stmt, interp: &Interpreter) {
+        let rows = interp.fabric().rows().len();
+        if rows > self.shown {
+            self.shown = rows;
+            let _ = self.animation.draw(interp.fabric());
+            thread::sleep(self.delay);
+        }
+    }
+}
+
+impl Drop for AnimateHook {
+    fn drop(&mut self) {
+        let _ = self.animation.finish();
+        ANIMATING.store(false, Ordering::Relaxed);
+    }
+}
+
+/// `--animate` with a terminal on stdout: runs the program with the chart
+/// redrawn after every row, then finishes the run as usual.
+fn animate_run(args: &RunArgs) -> Result<(), Failure> {
+    if args.eval.is_empty() && args.files.is_empty() {
+        return Err(io_failure("--animate needs a program from a file or -e"));
+    }
+    let exec = &args.exec;
+    let to_stdout = exec
+        .output
+        .output
+        .as_deref()
+        .is_none_or(|path| path == Path::new("-"));
+    let mut interp = if to_stdout && !exec.replaces_output() {
+        exec.interpreter_writing_to(Box::new(Held::default()))?
+    } else {
+        exec.interpreter()?
+    };
+    let (columns, lines) = terminal_size();
+    ANIMATING.store(true, Ordering::Relaxed);
+    interp.set_hook(Some(Box::new(AnimateHook {
+        animation: Animation::new(io::stdout(), chart_symbols(), columns, lines),
+        shown: 0,
+        delay: args.animate_delay,
+    })));
+    let result = run_program(args, &mut interp);
+    // Dropping the hook shows the cursor again before anything else prints.
+    interp.set_hook(None);
+    let rerun = |interp: &mut Interpreter| run_program(args, interp);
+    exec.finish(&mut interp, result, Some(&rerun))
+}
+
+/// The columns and lines of the terminal: `$COLUMNS` and `$LINES` if they
+/// are set, else what `stty` says of the terminal, else 80 by 24.
+fn terminal_size() -> (usize, usize) {
+    let var = |name: &str| env::var(name).ok()?.trim().parse::<usize>().ok();
+    if let (Some(columns), Some(lines)) = (var("COLUMNS"), var("LINES")) {
+        return (columns, lines);
+    }
+    let stty = fs::File::open("/dev/tty").ok().and_then(|tty| {
+        let out = Command::new("stty").arg("size").stdin(tty).output().ok()?;
+        let text = String::from_utf8(out.stdout).ok()?;
+        let mut numbers = text.split_whitespace().map(|n| n.parse::<usize>().ok());
+        let lines = numbers.next()??;
+        let columns = numbers.next()??;
+        Some((
+            var("COLUMNS").unwrap_or(columns),
+            var("LINES").unwrap_or(lines),
+        ))
+    });
+    stty.filter(|&(columns, lines)| columns > 0 && lines > 0)
+        .unwrap_or((var("COLUMNS").unwrap_or(80), var("LINES").unwrap_or(24)))
+}
+
+/// `knitlang repl`: the interactive prompt, even when stdin is a pipe.
+fn cmd_repl(args: &ReplArgs) -> Result<(), Failure> {
+    let mut interp = Interpreter::with_output(open_output(&args.output)?);
+    interp.set_interrupt_flag(Some(INTERRUPT.clone()));
+    interp.set_chart_symbols(chart_symbols());
+    add_debug_builtins(&mut interp);
+    let mut session = Session::with_interpreter(interp);
+    session.set_max_nesting(max_nesting());
+    print_banner();
+    repl(&mut session, args.search.search_path());
+    Ok(())
+}
+
+fn print_banner() {
+    if log_enabled(Verbosity::Normal) {
+        eprintln!("KNITLANG v2 - type 'exit' to quit, ':help' for commands, ':edit' to open the current input in $EDITOR. Try an example program as a .knit file and pass it as an argument.");
+    }
+}
+
+/// `knitlang fmt`: see [`format_files`].
+fn cmd_fmt(args: &FmtArgs) -> Result<(), Failure> {
+    format_files(&args.files)
+}
+
+/// `knitlang parse`: prints the syntax tree of one program.
+fn cmd_parse(args: &ParseArgs) -> Result<(), Failure> {
+    let (origin, src) = read_source(&args.file)
+        .map_err(|e| io_failure(format_args!("could not read {}: {e}", args.file.display())))?;
+    let stmts = parse_program(&src).map_err(|e| {
+        emit_diagnostic(&origin, &src, &e.into());
+        Failure::Parse
+    })?;
+    match args.format {
+        AstFormat::Json => println!("{}", ast_to_json(&stmts)),
+        AstFormat::Sexp => print!("{}", ast_to_sexp(&stmts)),
+    }
+    Ok(())
+}
+
+/// `knitlang examples`: lists the examples or runs the named one.
+fn cmd_examples(args: &ExamplesArgs) -> Result<(), Failure> {
+    let search = args.search.search_path();
+    if args.name == "list" {
+        example_names(&search)
+            .iter()
+            .for_each(|name| println!("{name}"));
+        return Ok(());
+    }
+    let (origin, src) = load_example(&search, &args.name).map_err(io_failure)?;
+    let mut interp = args.exec.interpreter()?;
+    let result = run_src(&origin, &src, &search, &mut interp);
+    let rerun = |interp: &mut Interpreter| run_src(&origin, &src, &search, interp);
+    args.exec.finish(&mut interp, result, Some(&rerun))
+}
+
+/// `knitlang explain`: the long description of one code, with an example
+/// and how to fix it, or a list of every code and its title.
+fn cmd_explain(args: &ExplainArgs) -> Result<(), Failure> {
+    let Some(id) = &args.code else {
+        for entry in REGISTRY {
+            println!("{}  {}", entry.id, entry.title);
+        }
+        return Ok(());
+    };
+    let Some(code) = Code::from_id(id) else {
+        return Err(io_failure(format_args!(
+            "unknown code `{id}`; `knitlang explain` lists them all"
+        )));
+    };
+    let entry = code.explanation();
+    let example: String = entry
+        .example
+        .lines()
+        .map(|l| format!("    {l}\n"))
+        .collect();
+    println!(
+        "{}: {}\n\n{}\n\nFor example:\n\n{example}\n{}",
+        entry.id, entry.title, entry.description, entry.fix
+    );
+    Ok(())
+}
+
+/// Files written by `knitlang init`, relative to the project directory.
+const INIT_FILES: &[(&str, &str)] = &[
+    ("main.knit", include_str!("../templates/main.knit")),
+    (".gitignore", "*.out\n"),
+];
+
+/// `knitlang init`: scaffolds a project directory, refusing to touch one that
+/// already has files in it unless `--force` is given.
+fn cmd_init(args: &InitArgs) -> Result<(), Failure> {
+    let dir = &args.dir;
+    let occupied = match fs::read_dir(dir) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => {
+            return Err(io_failure(format_args!(
+                "could not read {}: {e}",
+                dir.display()
+            )))
+        }
+    };
+    if occupied && !args.force {
+        return Err(io_failure(format_args!(
+            "{} is not empty; use --force to write the starter files anyway",
+            dir.display()
+        )));
+    }
+    fs::create_dir_all(dir)
+        .map_err(|e| io_failure(format_args!("could not create {}: {e}", dir.display())))?;
+    for (name, contents) in INIT_FILES {
+        let path = dir.join(name);
+        fs::write(&path, contents)
+            .map_err(|e| io_failure(format_args!("could not write {}: {e}", path.display())))?;
+        log!(Verbose, "wrote {}", path.display());
+    }
+    log!(
+        Normal,
+        "created {}; run it with `knitlang {}`",
+        dir.display(),
+        dir.join("main.knit").display()
+    );
+    Ok(())
+}
+
+/// `knitlang generate-completions`: generated from the same definition the
+/// parser uses, so it always matches the real CLI.
+fn cmd_completions(args: &CompletionArgs) -> Result<(), Failure> {
+    let mut command = <Cli as clap::CommandFactory>::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut io::stdout());
+    Ok(())
+}
+
+/// `knitlang generate-man`: the man page for the same definition.
+fn cmd_man() -> Result<(), Failure> {
+    let command = <Cli as clap::CommandFactory>::command();
+    clap_mangen::Man::new(command)
+        .render(&mut io::stdout())
+        .map_err(|e| io_failure(format_args!("could not write man page: {e}")))
+}
+
+/// Prints a clap error and exits. clap reports --help and --version as
+/// "errors" too; they print to stdout and are a successful exit, everything
+/// else is a usage error.
+fn exit_usage(e: clap::Error) -> ! {
+    let _ = e.print();
+    process::exit(match e.kind() {
+        ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => 0,
+        _ => EXIT_IO,
+    });
+}
+
+fn real_main(action: Action<'_>) -> Result<(), Failure> {
+    if action.warnings().is_some_and(|w| !w.apply()) {
+        return Ok(());
+    }
+    if let Some(options) = action.exec().and_then(ExecArgs::optimization) {
+        let _ = OPTIMIZATION.set(options);
+    }
+    dispatch::run(action)
+}
+
+fn main() {
+    let cli = match <Cli as clap::Parser>::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => exit_usage(e),
+    };
+    let action = match dispatch::action(&cli, Terminals::detect()) {
+        Ok(action) => action,
+        Err(e) => exit_usage(e),
+    };
+    set_verbosity(match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::Trace,
+    });
+    set_error_format(cli.error_format);
+    MAX_NESTING.store(cli.max_nesting as usize, Ordering::Relaxed);
+    ASCII_CHARTS.store(cli.ascii || !unicode_output(), Ordering::Relaxed);
+    install_panic_hook();
+    install_interrupt_handler();
+    let worker = thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || real_main(action))
+            .map(|worker| worker.join())
+    });
+    match worker {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(failure))) => process::exit(failure.exit_code()),
+        Ok(Err(_)) => process::exit(EXIT_INTERNAL),
+        Err(e) => {
+            eprintln!("error: cannot start a thread to run on: {e}");
+            process::exit(EXIT_INTERNAL);
+        }
     }
 }
@@ -0,0 +1,103 @@
+//! A pattern as Markdown, for READMEs and pattern pages that take it: its
+//! [`Document`], with the title as a heading over a list of the details of
+//! its `meta` block, the notes from before anything was cast on as
+//! paragraphs, the written instructions as a list of one item per line,
+//! and the chart as a fenced block of the text chart or an image.
+//!
+//! Every string from the pattern is escaped, so the `*` of a repeat such as
+//! `*k2, p2; rep from *` stays a star rather than starting emphasis.
+
+use crate::chart::{self, Style};
+use crate::document::{Body, Document};
+use crate::interp::Fabric;
+use crate::written::Line;
+use std::fmt::Write;
+
+/// The Markdown for `fabric`. Its chart is the text chart drawn with the
+/// orientation and symbols of `style`, or, given the path of an image of
+/// it, such as a PNG written alongside, a link to that:
+///
+/// ```
+/// let src = "cast_on 4; k 2; p 2;";
+/// let stmts = knitlang::parse_src(src).unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let markdown = knitlang::markdown::markdown(interp.fabric(), &Default::default(), None);
+/// assert!(markdown.starts_with("# Knitting pattern\n\n## Instructions\n\n"));
+/// assert!(markdown.contains("- Cast on 4 sts.\n- Row 1 (RS): k2, p2.\n"));
+/// assert!(markdown.ends_with("## Chart\n\n```text\n••││ 1 RS\n4321\n│ knit\n• purl\n```\n"));
+///
+/// let image = Some("rib.png");
+/// let markdown = knitlang::markdown::markdown(interp.fabric(), &Default::default(), image);
+/// assert!(markdown.ends_with("## Chart\n\n![Chart](<rib.png>)\n"));
+/// ```
+pub fn markdown(fabric: &Fabric, style: &Style, image: Option<&str>) -> String {
+    let document = Document::new(fabric);
+    let mut out = String::new();
+    let _ = writeln!(out, "# {}", escape(&document.title));
+    if !document.details.is_empty() {
+        out.push('\n');
+        for (label, value) in &document.details {
+            let _ = writeln!(out, "- **{}:** {}", escape(label), escape(value));
+        }
+    }
+    for section in &document.sections {
+        let _ = write!(out, "\n## {}\n\n", section.heading);
+        match &section.body {
+            Body::Notes(notes) => {
+                for (i, note) in notes.iter().enumerate() {
+                    if i > 0 {
+                        out.push('\n');
+                    }
+                    let _ = writeln!(out, "{}", escape(note));
+                }
+            }
+            Body::Instructions(lines) => {
+                for line in lines {
+                    let _ = match line {
+                        Line::Note(text) if !text.is_empty() => {
+                            writeln!(out, "- *{}*", escape(text))
+                        }
+                        line => writeln!(out, "- {}", escape(line.text())),
+                    };
+                }
+            }
+            Body::Chart => match image {
+                Some(path) => {
+                    let path = path.replace('<', "%3C").replace('>', "%3E");
+                    let _ = writeln!(out, "![Chart](<{path}>)");
+                }
+                None => {
+                    let text = chart::text_styled(fabric, style.orientation, &style.symbols);
+                    let fence = fence(&text);
+                    let _ = write!(out, "{fence}text\n{text}{fence}\n");
+                }
+            },
+        }
+    }
+    out
+}
+
+/// `text` with a backslash before everything Markdown could take as markup,
+/// and before a `#` that starts it, which would make a heading of it.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '&' | '|' | '~'
+        ) || (i == 0 && c == '#')
+        {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// A fence of backticks longer than any run of them in `text`, so that the
+/// code block it starts cannot end inside it.
+fn fence(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
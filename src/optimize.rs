@@ -0,0 +1,245 @@
+//! Optimization: rewriting a program before it runs so it does the same
+//! with less work. Arithmetic on literals is folded into one number, and a
+//! `repeat` with a small constant count is replaced by that many copies of
+//! its body, so the loop and its count cost nothing at run time.
+//!
+//! Output and errors are unchanged, down to the spans errors point at, but
+//! the work counted by [`RunStats`](crate::RunStats) and a step limit, the
+//! statements an [`ExecHook`](crate::ExecHook) sees and the events of a run
+//! follow the rewritten program rather than the source.
+
+use crate::ast::{Expr, ExprKind, NotePart, Stmt, StmtKind};
+use crate::interp::arithmetic;
+use crate::resolve;
+use std::mem;
+
+/// How far [`optimize`] goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// The largest `repeat` count that is unrolled; 0 unrolls nothing.
+    pub unroll_limit: u64,
+    /// The most statements, counting those inside `repeat` bodies, that the
+    /// copies of one unrolled body may come to.
+    pub max_unrolled: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            unroll_limit: 8,
+            max_unrolled: 256,
+        }
+    }
+}
+
+/// What [`optimize`] changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Operators whose operands were both literals, replaced by their result.
+    pub folded: usize,
+    /// `repeat` statements replaced by copies of their body.
+    pub unrolled: usize,
+}
+
+/// `stmts` with constants folded and small repeats unrolled, innermost
+/// first. A `repeat` is unrolled when its count is, or folds to, a number
+/// from 1 to the unroll limit and its body has no `bind_off`. Arithmetic
+/// that would fail, such as a division by zero, is left for the run to
+/// report.
+pub fn optimize(stmts: Vec<Stmt>, options: &Options) -> (Vec<Stmt>, Report) {
+    let mut optimizer = Optimizer {
+        options,
+        report: Report::default(),
+    };
+    let mut stmts = optimizer.stmts(stmts);
+    resolve::number_slots(&mut stmts);
+    (stmts, optimizer.report)
+}
+
+struct Optimizer<'a> {
+    options: &'a Options,
+    report: Report,
+}
+
+impl Optimizer<'_> {
+    fn stmts(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let mut out = Vec::with_capacity(stmts.len());
+        for stmt in stmts {
+            self.stmt(stmt, &mut out);
+        }
+        out
+    }
+
+    /// Pushes the optimized `stmt` onto `out`: one statement, or the copies
+    /// of an unrolled body.
+    fn stmt(&mut self, mut stmt: Stmt, out: &mut Vec<Stmt>) {
+        // `Stmt` frees its own body when dropped, so the kind is swapped out
+        // rather than moved.
+        let kind = match mem::replace(&mut stmt.kind, StmtKind::BindOff) {
+            StmtKind::CastOn(name, e) => StmtKind::CastOn(name, self.fold(e)),
+            StmtKind::CastOnStitches(e) => StmtKind::CastOnStitches(self.fold(e)),
+            StmtKind::Knit(name, e) => StmtKind::Knit(name, self.fold(e)),
+            StmtKind::Purl(e) => StmtKind::Purl(self.fold(e)),
+            StmtKind::Work(stitch, e) => StmtKind::Work(stitch, self.fold(e)),
+            StmtKind::Cable(e, cross) => StmtKind::Cable(self.fold(e), cross),
+            StmtKind::Distribute(shaping, e) => StmtKind::Distribute(shaping, self.fold(e)),
+            StmtKind::BindOffStitches(e) => StmtKind::BindOffStitches(self.fold(e)),
+            StmtKind::ExpectStitches(e) => StmtKind::ExpectStitches(self.fold(e)),
+            StmtKind::ExpectStitchesWarning(e) => StmtKind::ExpectStitchesWarning(self.fold(e)),
+            StmtKind::CastOnNeedle(name, e) => StmtKind::CastOnNeedle(name, self.fold(e)),
+            StmtKind::Transfer(from, to, e) => StmtKind::Transfer(from, to, self.fold(e)),
+            StmtKind::Repeat(count, body) => {
+                let count = self.fold(count);
+                let body = self.stmts(body);
+                if let Some(n) = self.unroll_count(&count, &body) {
+                    self.report.unrolled += 1;
+                    for _ in 1..n {
+                        out.extend(body.iter().cloned());
+                    }
+                    out.extend(body);
+                    return;
+                }
+                StmtKind::Repeat(count, body)
+            }
+            StmtKind::Mirror(body) => StmtKind::Mirror(self.stmts(body)),
+            StmtKind::Alternate(bodies) => {
+                StmtKind::Alternate(bodies.into_iter().map(|body| self.stmts(body)).collect())
+            }
+            StmtKind::Pattern(pattern, args) => {
+                StmtKind::Pattern(pattern, args.into_iter().map(|e| self.fold(e)).collect())
+            }
+            StmtKind::Gauge(stitches, rows) => {
+                StmtKind::Gauge(self.fold(stitches), self.fold(rows))
+            }
+            StmtKind::Note(parts) => StmtKind::Note(
+                parts
+                    .into_iter()
+                    .map(|part| match part {
+                        NotePart::Expr(e) => NotePart::Expr(self.fold(e)),
+                        text => text,
+                    })
+                    .collect(),
+            ),
+            kind @ (StmtKind::BindOff
+            | StmtKind::Chart(_)
+            | StmtKind::Hold(_)
+            | StmtKind::Resume(_)
+            | StmtKind::Yarn(..)
+            | StmtKind::Color(_)
+            | StmtKind::WrapTurn
+            | StmtKind::Mode(_)
+            | StmtKind::PlaceMarker(_)
+            | StmtKind::SlipMarker(_)
+            | StmtKind::Use(..)
+            | StmtKind::Sizes(_)
+            | StmtKind::Meta(_)) => kind,
+        };
+        out.push(Stmt {
+            kind,
+            span: stmt.span,
+        });
+    }
+
+    /// How many copies of `body` should replace a `repeat count`, if it
+    /// should be unrolled at all.
+    ///
+    /// A count of 0 or less is never unrolled: the loop still comes after
+    /// any variables its body sets, so reading them later is only an error
+    /// once the run gets there, not before it starts.
+    fn unroll_count(&self, count: &Expr, body: &[Stmt]) -> Option<usize> {
+        let ExprKind::Number(n) = *count.kind() else {
+            return None;
+        };
+        let n = u64::try_from(n).ok().filter(|&n| n >= 1)?;
+        if n > self.options.unroll_limit || binds_off(body) || alternates(body) {
+            return None;
+        }
+        let n = usize::try_from(n).ok()?;
+        let size = size(body).checked_mul(n)?;
+        (size <= self.options.max_unrolled).then_some(n)
+    }
+
+    /// `e` with every operator on two literals replaced by its result,
+    /// working up from the operands so `2 * 3 + 4` becomes `10`.
+    fn fold(&mut self, e: Expr) -> Expr {
+        let foldable = e.nodes().iter().any(|node| match node.kind {
+            ExprKind::Binary(lhs, _, rhs) => {
+                is_number(&e.node(lhs).kind) && is_number(&e.node(rhs).kind)
+            }
+            _ => false,
+        });
+        if !foldable {
+            return e;
+        }
+        let mut stack: Vec<Expr> = Vec::new();
+        for node in e.nodes() {
+            let folded = match node.kind {
+                ExprKind::Number(n) => Expr::number(n, node.span),
+                ExprKind::Var(name) => Expr::var(name, node.span),
+                ExprKind::StitchesTo(marker) => Expr::stitches_to(marker, node.span),
+                ExprKind::Count(needle) => Expr::count(needle, node.span),
+                ExprKind::Sizes(ref values) => Expr::sizes(values.clone(), node.span),
+                ExprKind::Call(name, ref args) => {
+                    let args = stack.split_off(stack.len() - args.len());
+                    Expr::call(name, args, node.span)
+                }
+                ExprKind::Binary(_, op, _) => {
+                    let rhs = stack.pop().expect("operator without operands");
+                    let lhs = stack.pop().expect("operator without operands");
+                    match (lhs.kind(), rhs.kind()) {
+                        (&ExprKind::Number(a), &ExprKind::Number(b)) => {
+                            match arithmetic(a, op, b) {
+                                Some(value) => {
+                                    self.report.folded += 1;
+                                    Expr::number(value, node.span)
+                                }
+                                None => Expr::binary_at(lhs, op, rhs, node.span),
+                            }
+                        }
+                        _ => Expr::binary_at(lhs, op, rhs, node.span),
+                    }
+                }
+            };
+            stack.push(folded);
+        }
+        stack.pop().expect("expression without a root")
+    }
+}
+
+fn is_number(kind: &ExprKind) -> bool {
+    matches!(kind, ExprKind::Number(_))
+}
+
+/// Whether any statement in `stmts`, at any depth, is `bind_off`, which
+/// may end the piece even with a count.
+fn binds_off(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.kind {
+        StmtKind::BindOff | StmtKind::BindOffStitches(_) => true,
+        kind => kind.bodies().iter().any(|body| binds_off(body)),
+    })
+}
+
+/// Whether `stmts`, the body of a `repeat`, has an `alternate` taking turns
+/// over its passes, which unrolled copies of the body would lose.
+fn alternates(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Alternate(_) => true,
+        StmtKind::Repeat(..) => false,
+        kind => kind.bodies().iter().any(|body| alternates(body)),
+    })
+}
+
+/// The number of statements in `stmts`, counting those in `repeat` bodies.
+fn size(stmts: &[Stmt]) -> usize {
+    stmts
+        .iter()
+        .map(|stmt| {
+            1 + stmt
+                .kind
+                .bodies()
+                .iter()
+                .map(|body| size(body))
+                .sum::<usize>()
+        })
+        .sum()
+}
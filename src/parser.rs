@@ -0,0 +1,1267 @@
+//! Builds the syntax tree from tokens.
+
+use crate::ast::{
+    Cross, Expr, MetaEntry, MetaValue, Mode, NotePart, Pattern, Shaping, Stitch, Stmt, StmtKind,
+};
+use crate::chart::rgb;
+use crate::codes::Code;
+use crate::lexer::{LexError, Lexer, Span, Token};
+use crate::plural;
+use crate::resolve;
+use crate::suggest::did_you_mean;
+use crate::symbol::Symbol;
+use std::collections::VecDeque;
+use std::{fmt, iter, mem, slice, vec};
+
+/// A syntax error, or a lex error the parser ran into.
+#[derive(Debug)]
+pub struct ParseError {
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+    /// Other places worth pointing at, each with a short note, such as the
+    /// `{` of a repeat that is never closed.
+    pub labels: Vec<(Span, String)>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        Self {
+            code: e.code,
+            message: e.message,
+            span: e.span,
+            labels: Vec::new(),
+        }
+    }
+}
+
+/// The token a parse error was given instead of the one it wanted, shown as
+/// source text in backticks, or as `end of input` when there is none.
+struct Found(Option<Token>);
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(token) => write!(f, "`{token}`"),
+            None => f.write_str("end of input"),
+        }
+    }
+}
+
+/// The words a statement can start with, for suggesting one in place of a
+/// misspelling.
+const STATEMENT_KEYWORDS: [&str; 37] = [
+    "cast_on",
+    "knit",
+    "purl",
+    "repeat",
+    "bind_off",
+    "yarn",
+    "color",
+    "cable",
+    "wrap_turn",
+    "mode",
+    "place_marker",
+    "slip_marker",
+    "sizes",
+    "gauge",
+    "distribute",
+    "expect_stitches",
+    "use",
+    "mirror",
+    "alternate",
+    "meta",
+    "note",
+    "chart",
+    "transfer",
+    "hold",
+    "resume",
+    "stockinette",
+    "garter",
+    "seed",
+    "rib",
+    "k",
+    "p",
+    "k2tog",
+    "ssk",
+    "yo",
+    "m1",
+    "c4f",
+    "c4b",
+];
+
+/// How deeply repeats and calls may nest before parsing gives up, unless
+/// [`Parser::set_max_nesting`] says otherwise. Passes over the tree that
+/// recurse take stack for every level, and at this depth they fit in the
+/// 8 MiB main thread Linux and macOS give a program, even in a debug build.
+pub const DEFAULT_MAX_NESTING: usize = 256;
+
+/// An already-lexed token stream, as [`Parser::new`] takes it.
+pub type Tokens =
+    iter::Map<vec::IntoIter<(Token, Span)>, fn((Token, Span)) -> Result<(Token, Span), LexError>>;
+
+/// Why parsing stopped. Lex errors are kept apart so [`crate::KnitError`]
+/// can tell them from syntax errors.
+pub(crate) enum Stopped {
+    Lex(LexError),
+    Syntax(ParseError),
+}
+
+impl From<Stopped> for ParseError {
+    fn from(stopped: Stopped) -> Self {
+        match stopped {
+            Stopped::Lex(e) => e.into(),
+            Stopped::Syntax(e) => e,
+        }
+    }
+}
+
+/// A recursive-descent parser. It pulls tokens from `I` as it goes, usually
+/// straight from a [`Lexer`], holding on to no more than it needs to look
+/// ahead.
+pub struct Parser<I = Tokens> {
+    tokens: I,
+    /// Tokens pulled from `tokens` but not consumed yet; this grammar never
+    /// looks more than two ahead.
+    lookahead: VecDeque<(Token, Span)>,
+    /// The lex error `tokens` stopped at. It is reported in place of the
+    /// syntax error that running out of tokens there causes.
+    lex_error: Option<LexError>,
+    /// The most recently consumed token. Errors at the end of input point
+    /// just past it.
+    prev: Option<Span>,
+    /// Block bodies the parser is inside, so error recovery knows how many
+    /// `}` to skip past, and the keyword of the innermost.
+    depth: usize,
+    block: &'static str,
+    /// Repeat bodies the parser is inside, which an `alternate` needs one
+    /// of.
+    repeats: usize,
+    /// Repeats and calls the parser is inside, checked against
+    /// `max_nesting`.
+    nesting: usize,
+    max_nesting: usize,
+    /// How many sizes the last `sizes` declaration named, which every size
+    /// tuple after it must match.
+    sizes: Option<usize>,
+    /// Top-level statements started so far, which a `meta` block must come
+    /// before.
+    started: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<(Token, Span)>) -> Self {
+        Self::from_tokens(tokens.into_iter().map(Ok))
+    }
+}
+
+impl Parser<Lexer> {
+    /// A parser that lexes `src` only as far as it has parsed.
+    pub fn from_source(src: &str) -> Self {
+        Self::from_tokens(Lexer::new(src))
+    }
+}
+
+impl<I: Iterator<Item = Result<(Token, Span), LexError>>> Parser<I> {
+    pub fn from_tokens(tokens: I) -> Self {
+        Self {
+            tokens,
+            lookahead: VecDeque::with_capacity(2),
+            lex_error: None,
+            prev: None,
+            depth: 0,
+            block: "repeat",
+            repeats: 0,
+            nesting: 0,
+            max_nesting: DEFAULT_MAX_NESTING,
+            sizes: None,
+            started: 0,
+        }
+    }
+
+    /// Fails with a syntax error at any repeat or call nested more than
+    /// `limit` deep, counting itself. A limit well past
+    /// [`DEFAULT_MAX_NESTING`] needs a thread with a larger stack than the
+    /// main one for the passes over the tree.
+    pub fn set_max_nesting(&mut self, limit: usize) {
+        self.max_nesting = limit;
+    }
+
+    /// Goes one level deeper into the repeat or call starting at `span`, or
+    /// fails if that passes the limit.
+    fn enter(&mut self, span: Span) -> Result<(), ParseError> {
+        if self.nesting == self.max_nesting {
+            return Err(ParseError {
+                code: Code::NestingTooDeep,
+                message: format!("Nesting too deep (limit {})", self.max_nesting),
+                span,
+                labels: Vec::new(),
+            });
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
+    /// The token `ahead` places past the one about to be consumed, and its
+    /// span.
+    fn peek_entry(&mut self, ahead: usize) -> Option<(Token, Span)> {
+        while self.lookahead.len() <= ahead && self.lex_error.is_none() {
+            match self.tokens.next() {
+                Some(Ok(token)) => self.lookahead.push_back(token),
+                Some(Err(e)) => self.lex_error = Some(e),
+                None => break,
+            }
+        }
+        self.lookahead.get(ahead).copied()
+    }
+
+    /// The token `ahead` places past the one about to be consumed.
+    fn peek_at(&mut self, ahead: usize) -> Option<Token> {
+        self.peek_entry(ahead).map(|(t, _)| t)
+    }
+
+    fn peek(&mut self) -> Option<Token> {
+        self.peek_at(0)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.peek();
+        let (t, span) = self.lookahead.pop_front()?;
+        self.prev = Some(span);
+        Some(t)
+    }
+
+    /// Span of the token about to be consumed, or a zero-width span just past
+    /// the last token at the end of input.
+    fn peek_span(&mut self) -> Span {
+        self.peek_entry(0).map_or_else(
+            || {
+                let end = self.prev_end();
+                Span::new(end, end)
+            },
+            |(_, span)| span,
+        )
+    }
+
+    /// Builds an error pointing at the token about to be consumed.
+    fn error<T>(&mut self, code: Code, message: String) -> Result<T, ParseError> {
+        Err(ParseError {
+            code,
+            message,
+            span: self.peek_span(),
+            labels: Vec::new(),
+        })
+    }
+
+    /// The error to report for syntax error `e`. Lex errors win, as they
+    /// would if the whole source had been lexed first, so this looks through
+    /// the rest of the tokens for one.
+    fn lex_error_or(&mut self, e: ParseError) -> Stopped {
+        match self.lex_error.take() {
+            Some(lex) => Stopped::Lex(lex),
+            None => match self.tokens.find_map(Result::err) {
+                Some(lex) => Stopped::Lex(lex),
+                None => Stopped::Syntax(e),
+            },
+        }
+    }
+
+    /// Consumes `want`, or fails with `Expected {what}, found ...`.
+    fn expect(&mut self, want: Token, what: &str) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(t) if t == want => {
+                self.next();
+                Ok(())
+            }
+            other => {
+                let message = format!("Expected {what}, found {}", Found(other));
+                let code = match want {
+                    Token::Semicolon => Code::MissingSemicolon,
+                    Token::RBrace => Code::UnclosedRepeat,
+                    _ => Code::ExpectedToken,
+                };
+                // A missing `;` belongs at the end of the statement, not at
+                // whatever happens to follow it (often on the next line).
+                match self.prev {
+                    Some(prev) if want == Token::Semicolon => Err(ParseError {
+                        code,
+                        message,
+                        span: Span::new(prev.end, prev.end),
+                        labels: Vec::new(),
+                    }),
+                    _ => self.error(code, message),
+                }
+            }
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<Symbol, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(s)) => {
+                self.next();
+                Ok(s)
+            }
+            other => self.error(
+                Code::ExpectedToken,
+                format!("Expected identifier, found: {}", Found(other)),
+            ),
+        }
+    }
+
+    #[expect(unused)]
+    fn expect_number_expr(&mut self) -> Result<Expr, ParseError> {
+        let span = self.peek_span();
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                self.next();
+                Ok(Expr::number(n, span))
+            }
+            other => self.error(
+                Code::ExpectedToken,
+                format!("Expected number, found: {}", Found(other)),
+            ),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.parse_add_sub()
+    }
+
+    fn parse_add_sub(&mut self) -> Result<Expr, ParseError> {
+        let mut node = self.parse_mul_div()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.next();
+                    let rhs = self.parse_mul_div()?;
+                    node = Expr::binary(node, '+', rhs);
+                }
+                Some(Token::Minus) => {
+                    self.next();
+                    let rhs = self.parse_mul_div()?;
+                    node = Expr::binary(node, '-', rhs);
+                }
+                _ => break,
+            }
+        }
+        Ok(node)
+    }
+
+    fn parse_mul_div(&mut self) -> Result<Expr, ParseError> {
+        let mut node = self.parse_term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.next();
+                    let rhs = self.parse_term()?;
+                    node = Expr::binary(node, '*', rhs);
+                }
+                Some(Token::Slash) => {
+                    self.next();
+                    let rhs = self.parse_term()?;
+                    node = Expr::binary(node, '/', rhs);
+                }
+                _ => break,
+            }
+        }
+        Ok(node)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, ParseError> {
+        let span = self.peek_span();
+        let expr = match self.peek() {
+            Some(Token::Number(n)) if self.peek_at(1) == Some(Token::LParen) => {
+                return self.parse_sizes(n);
+            }
+            Some(Token::Number(n)) => Expr::number(n, span),
+            Some(Token::Ident(name))
+                if name.as_str() == "stitches_to" && self.peek_at(1) == Some(Token::LParen) =>
+            {
+                return self.parse_stitches_to();
+            }
+            Some(Token::Ident(name))
+                if name.as_str() == "count" && self.peek_at(1) == Some(Token::LParen) =>
+            {
+                return self.parse_count();
+            }
+            Some(Token::Ident(_)) => return self.parse_name(),
+            other => {
+                return self.error(
+                    Code::ExpectedExpression,
+                    format!("Unexpected token in term: {}", Found(other)),
+                )
+            }
+        };
+        self.next();
+        Ok(expr)
+    }
+
+    /// `first (next, ...)`, a number for each size, with `first` still the
+    /// next token. It must have a number for every size the last `sizes`
+    /// declaration names; with none, that is left for the run to check.
+    fn parse_sizes(&mut self, first: i64) -> Result<Expr, ParseError> {
+        let start = self.peek_span().start;
+        self.next();
+        self.next();
+        let mut values = vec![first];
+        loop {
+            match self.peek() {
+                Some(Token::Number(n)) => {
+                    self.next();
+                    values.push(n);
+                }
+                other => {
+                    return self.error(
+                        Code::ExpectedToken,
+                        format!("Expected a number for a size, found: {}", Found(other)),
+                    )
+                }
+            }
+            if self.peek() != Some(Token::Comma) {
+                break;
+            }
+            self.next();
+        }
+        self.expect(Token::RParen, ") after the sizes")?;
+        let span = Span::new(start, self.prev_end());
+        match self.sizes {
+            Some(sizes) if sizes != values.len() => Err(ParseError {
+                code: Code::SizeCount,
+                message: format!(
+                    "This size tuple has {} but `sizes` declares {}",
+                    plural(values.len(), "number"),
+                    plural(sizes, "size")
+                ),
+                span,
+                labels: Vec::new(),
+            }),
+            _ => Ok(Expr::sizes(values, span)),
+        }
+    }
+
+    /// A variable or a call, with its name still the next token. The name
+    /// may be qualified by what a file is imported as, as in
+    /// `edgings.width`.
+    fn parse_name(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek_span().start;
+        let mut name = self.expect_ident()?;
+        if self.peek() == Some(Token::Dot) {
+            self.next();
+            let member = self.expect_ident()?;
+            name = Symbol::intern(&format!("{name}.{member}"));
+        }
+        let span = Span::new(start, self.prev_end());
+        if self.peek() == Some(Token::LParen) {
+            return self.parse_call(name, span);
+        }
+        Ok(Expr::var(name, span))
+    }
+
+    /// `name(arg, ...)`, with `name`, at `name_span`, already read and the
+    /// `(` next.
+    fn parse_call(&mut self, name: Symbol, name_span: Span) -> Result<Expr, ParseError> {
+        self.enter(name_span)?;
+        let start = name_span.start;
+        self.next();
+        let mut args = Vec::new();
+        if self.peek() != Some(Token::RParen) {
+            loop {
+                args.push(self.parse_expr()?);
+                if self.peek() != Some(Token::Comma) {
+                    break;
+                }
+                self.next();
+            }
+        }
+        self.expect(Token::RParen, ") after function arguments")?;
+        self.nesting -= 1;
+        Ok(Expr::call(name, args, Span::new(start, self.prev_end())))
+    }
+
+    /// A builtin stitch pattern such as `rib(2, 2, 8);`, with its name, `name`,
+    /// still the next token. It must be given the arguments it takes.
+    fn parse_pattern(&mut self, name: Symbol) -> Result<StmtKind, ParseError> {
+        let pattern = Pattern::from_name(name.as_str()).expect("checked by the caller");
+        let start = self.peek_span().start;
+        self.next();
+        self.next();
+        let mut args = Vec::new();
+        if self.peek() != Some(Token::RParen) {
+            loop {
+                args.push(self.parse_expr()?);
+                if self.peek() != Some(Token::Comma) {
+                    break;
+                }
+                self.next();
+            }
+        }
+        self.expect(Token::RParen, &format!(") after {name} arguments"))?;
+        let params = pattern.params();
+        if args.len() != params.len() {
+            return Err(ParseError {
+                code: Code::WrongArgumentCount,
+                message: format!(
+                    "`{name}` takes {} ({}), but was given {}",
+                    plural(params.len(), "argument"),
+                    params.join(", "),
+                    args.len()
+                ),
+                span: Span::new(start, self.prev_end()),
+                labels: Vec::new(),
+            });
+        }
+        self.expect(Token::Semicolon, &format!("; after {name} statement"))?;
+        Ok(StmtKind::Pattern(pattern, args))
+    }
+
+    /// `stitches_to(marker)`, with `stitches_to` still the next token. The
+    /// marker is a name, not an expression, so it is not a variable read.
+    fn parse_stitches_to(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek_span().start;
+        self.next();
+        self.next();
+        let marker = self.marker_name("stitches_to(")?;
+        self.expect(Token::RParen, ") after the marker")?;
+        Ok(Expr::stitches_to(marker, Span::new(start, self.prev_end())))
+    }
+
+    /// `count(needle)`, with `count` still the next token. Like a marker,
+    /// the needle is a name and not a variable read.
+    fn parse_count(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek_span().start;
+        self.next();
+        self.next();
+        let needle = self.needle_name("count(")?;
+        self.expect(Token::RParen, ") after the needle")?;
+        Ok(Expr::count(needle, Span::new(start, self.prev_end())))
+    }
+
+    /// The name of a needle, after `what`.
+    fn needle_name(&mut self, what: &str) -> Result<Symbol, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                self.next();
+                Ok(name)
+            }
+            other => self.error(
+                Code::ExpectedToken,
+                format!(
+                    "Expected a needle name after {what}, found: {}",
+                    Found(other)
+                ),
+            ),
+        }
+    }
+
+    /// The name of a stitch marker, after `what`.
+    fn marker_name(&mut self, what: &str) -> Result<Symbol, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                self.next();
+                Ok(name)
+            }
+            other => self.error(
+                Code::ExpectedToken,
+                format!(
+                    "Expected a marker name after {what}, found: {}",
+                    Found(other)
+                ),
+            ),
+        }
+    }
+
+    /// An expression and an optional `;`, making up the rest of the input.
+    fn parse_lone_expr(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.parse_expr()?;
+        if self.peek() == Some(Token::Semicolon) {
+            self.next();
+        }
+        match self.peek() {
+            None => Ok(expr),
+            other => self.error(
+                Code::ExpectedToken,
+                format!("Expected end of input, found {}", Found(other)),
+            ),
+        }
+    }
+
+    /// End of the most recently consumed token.
+    fn prev_end(&self) -> usize {
+        self.prev.map_or(0, |span| span.end)
+    }
+
+    fn parse_stmt(&mut self) -> Result<Option<Stmt>, ParseError> {
+        let start = self.peek_span().start;
+        let Some(token) = self.peek() else {
+            return Ok(None);
+        };
+        let first = self.depth == 0 && self.started == 0;
+        if self.depth == 0 {
+            self.started += 1;
+        }
+        // The other statements are parsed out of line, so a level of nested
+        // repeats costs as little stack as it can.
+        let kind = match token {
+            Token::Repeat => self.parse_repeat()?,
+            // `mirror` is only a keyword before a block, so it still works
+            // as a variable name.
+            Token::Ident(name)
+                if name.as_str() == "mirror" && self.peek_at(1) == Some(Token::LBrace) =>
+            {
+                self.parse_mirror()?
+            }
+            Token::Ident(name)
+                if name.as_str() == "alternate" && self.peek_at(1) == Some(Token::LBrace) =>
+            {
+                self.parse_alternate()?
+            }
+            Token::Ident(name)
+                if name.as_str() == "meta" && self.peek_at(1) == Some(Token::LBrace) =>
+            {
+                self.parse_meta(first)?
+            }
+            token => self.parse_simple_stmt(token)?,
+        };
+        Ok(Some(Stmt {
+            kind,
+            span: Span::new(start, self.prev_end()),
+        }))
+    }
+
+    /// `repeat count { ... }`, with `repeat` still the next token.
+    fn parse_repeat(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword = self.peek_span();
+        self.enter(keyword)?;
+        self.next();
+        let count = self.parse_expr()?;
+        self.repeats += 1;
+        let body = self.parse_block("repeat", "repeat count")?;
+        self.repeats -= 1;
+        self.nesting -= 1;
+        Ok(StmtKind::Repeat(count, body))
+    }
+
+    /// `mirror { ... }`, with `mirror` still the next token.
+    fn parse_mirror(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword = self.peek_span();
+        self.enter(keyword)?;
+        self.next();
+        let body = self.parse_block("mirror", "mirror")?;
+        self.nesting -= 1;
+        Ok(StmtKind::Mirror(body))
+    }
+
+    /// `alternate { ... } { ... }`, with `alternate` still the next token:
+    /// one body or more, each straight after the one before.
+    fn parse_alternate(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword = self.peek_span();
+        if self.repeats == 0 {
+            return self.error(
+                Code::AlternateOutsideRepeat,
+                "Alternate blocks only go inside a repeat, whose passes they take turns over"
+                    .to_string(),
+            );
+        }
+        self.enter(keyword)?;
+        self.next();
+        let mut bodies = vec![self.parse_block("alternate", "alternate")?];
+        while self.peek() == Some(Token::LBrace) {
+            bodies.push(self.parse_block("alternate", "alternate body")?);
+        }
+        self.nesting -= 1;
+        Ok(StmtKind::Alternate(bodies))
+    }
+
+    /// `meta { key = value; ... }`, with `meta` still the next token. It
+    /// must be the `first` statement of the program, and has each key at
+    /// most once.
+    fn parse_meta(&mut self, first: bool) -> Result<StmtKind, ParseError> {
+        if !first {
+            return self.error(
+                Code::MisplacedMeta,
+                "A meta block only goes at the top of a program, before any other statement"
+                    .to_string(),
+            );
+        }
+        let open = self
+            .peek_entry(1)
+            .map_or_else(|| self.peek_span(), |(_, span)| span);
+        self.next();
+        self.next();
+        self.depth += 1;
+        let outer = mem::replace(&mut self.block, "meta");
+        let mut entries: Vec<MetaEntry> = Vec::new();
+        loop {
+            let start = self.peek_span().start;
+            // `yarn` is a keyword everywhere else.
+            let key = match self.peek() {
+                Some(Token::RBrace) | None => break,
+                Some(Token::Ident(key)) => key,
+                Some(Token::Yarn) => Symbol::intern("yarn"),
+                other => {
+                    return self.error(
+                        Code::ExpectedToken,
+                        format!("Expected a meta key, found: {}", Found(other)),
+                    )
+                }
+            };
+            let key_span = self.peek_span();
+            self.next();
+            self.expect(Token::Equal, &format!("= after meta key `{key}`"))?;
+            let negative = self.peek() == Some(Token::Minus);
+            if negative {
+                self.next();
+            }
+            let value = match self.peek() {
+                Some(Token::Str(s)) if !negative => MetaValue::Str(s.as_str().to_string()),
+                Some(Token::Number(n)) => MetaValue::Number(if negative { -n } else { n }),
+                other => {
+                    return self.error(
+                        Code::ExpectedToken,
+                        format!("Expected a string or a number, found: {}", Found(other)),
+                    )
+                }
+            };
+            self.next();
+            self.expect(Token::Semicolon, "; after meta entry")?;
+            if let Some(earlier) = entries.iter().find(|entry| entry.key == key) {
+                return Err(ParseError {
+                    code: Code::DuplicateMetaKey,
+                    message: format!("Duplicate meta key `{key}`"),
+                    span: key_span,
+                    labels: vec![(earlier.span, "first given here".to_string())],
+                });
+            }
+            entries.push(MetaEntry {
+                key,
+                value,
+                span: Span::new(start, self.prev_end()),
+            });
+        }
+        self.expect(Token::RBrace, "'}' after meta body")
+            .map_err(|mut e| {
+                e.labels.push((open, "meta opened here".to_string()));
+                e
+            })?;
+        self.block = outer;
+        self.depth -= 1;
+        Ok(StmtKind::Meta(entries))
+    }
+
+    /// The body of a `block` statement, `{ ... }`, with `{` the next token
+    /// and `after` what comes before it.
+    fn parse_block(&mut self, block: &'static str, after: &str) -> Result<Vec<Stmt>, ParseError> {
+        let open = self.peek_span();
+        self.expect(Token::LBrace, &format!("'{{' after {after}"))?;
+        self.depth += 1;
+        let outer = mem::replace(&mut self.block, block);
+        let mut body = Vec::new();
+        while !matches!(self.peek(), Some(Token::RBrace)) {
+            if let Some(s) = self.parse_stmt()? {
+                body.push(s);
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RBrace, &format!("'}}' after {block} body"))
+            .map_err(|mut e| {
+                e.labels.push((open, format!("{block} opened here")));
+                e
+            })?;
+        self.block = outer;
+        self.depth -= 1;
+        Ok(body)
+    }
+
+    /// Any statement but a `repeat`, starting with `token`.
+    #[inline(never)]
+    fn parse_simple_stmt(&mut self, token: Token) -> Result<StmtKind, ParseError> {
+        let kind = match token {
+            Token::CastOn => {
+                self.next();
+                // `cast_on name = ...` declares a variable; anything else is a
+                // stitch count.
+                let declares = matches!(
+                    (self.peek(), self.peek_at(1)),
+                    (Some(Token::Ident(_)), Some(Token::Equal))
+                );
+                if !declares {
+                    let count = self.parse_expr()?;
+                    self.expect(Token::Semicolon, "; after cast_on statement")?;
+                    return Ok(StmtKind::CastOnStitches(count));
+                }
+                let name = self.expect_ident()?;
+                self.expect(Token::Equal, "= after identifier in cast_on")?;
+                // `cast_on name = needle(n);` declares a needle instead.
+                if matches!(self.peek(), Some(Token::Ident(word)) if word.as_str() == "needle")
+                    && self.peek_at(1) == Some(Token::LParen)
+                {
+                    self.next();
+                    self.next();
+                    let count = self.parse_expr()?;
+                    self.expect(Token::RParen, ") after the needle's stitch count")?;
+                    self.expect(Token::Semicolon, "; after cast_on statement")?;
+                    return Ok(StmtKind::CastOnNeedle(name, count));
+                }
+                let expr = self.parse_expr()?;
+                self.expect(Token::Semicolon, "; after cast_on statement")?;
+                StmtKind::CastOn(name, expr)
+            }
+            Token::Knit => {
+                self.next();
+                let name = self.expect_ident()?;
+                self.expect(Token::Equal, "= after identifier in knit")?;
+                let expr = self.parse_expr()?;
+                self.expect(Token::Semicolon, "; after knit statement")?;
+                StmtKind::Knit(name, expr)
+            }
+            Token::Purl => {
+                self.next();
+                let expr = self.parse_expr()?;
+                self.expect(Token::Semicolon, "; after purl statement")?;
+                StmtKind::Purl(expr)
+            }
+            Token::BindOff => {
+                self.next();
+                // A count binds off that many stitches; on its own it binds
+                // off every stitch and ends the piece.
+                if !matches!(self.peek(), Some(Token::Semicolon) | None) {
+                    let count = self.parse_expr()?;
+                    self.expect(Token::Semicolon, "; after bind_off statement")?;
+                    return Ok(StmtKind::BindOffStitches(count));
+                }
+                self.expect(Token::Semicolon, "; after bind_off")?;
+                StmtKind::BindOff
+            }
+            Token::WrapTurn => {
+                self.next();
+                self.expect(Token::Semicolon, "; after wrap_turn")?;
+                StmtKind::WrapTurn
+            }
+            Token::Yarn => {
+                self.next();
+                let name = self.expect_ident()?;
+                self.expect(Token::Equal, "= after identifier in yarn")?;
+                let colour = match self.peek() {
+                    Some(Token::Str(colour)) if rgb(colour.as_str()).is_some() => colour,
+                    Some(Token::Str(colour)) => {
+                        return self.error(
+                            Code::InvalidColour,
+                            format!("`{colour}` is not a colour; write it as #rgb or #rrggbb"),
+                        )
+                    }
+                    other => {
+                        return self.error(
+                            Code::ExpectedToken,
+                            format!("Expected colour string, found: {}", Found(other)),
+                        )
+                    }
+                };
+                self.next();
+                self.expect(Token::Semicolon, "; after yarn statement")?;
+                StmtKind::Yarn(name, colour.as_str().to_string())
+            }
+            Token::Color => {
+                self.next();
+                let name = self.expect_ident()?;
+                self.expect(Token::Semicolon, "; after color statement")?;
+                StmtKind::Color(name)
+            }
+            // `cable`, `mode`, the marker statements, `sizes`, `gauge`, `use`, the
+            // stitch patterns and the words after them only have a meaning
+            // here, so they still work as variable names.
+            Token::Ident(name) if name.as_str() == "cable" => {
+                self.next();
+                let count = self.parse_expr()?;
+                let cross = match self.peek() {
+                    Some(Token::Ident(word)) => Cross::from_name(word.as_str()),
+                    _ => None,
+                };
+                let Some(cross) = cross else {
+                    let found = self.peek();
+                    return self.error(
+                        Code::ExpectedToken,
+                        format!("Expected front or back, found: {}", Found(found)),
+                    );
+                };
+                self.next();
+                self.expect(Token::Semicolon, "; after cable statement")?;
+                StmtKind::Cable(count, cross)
+            }
+            Token::Ident(name) if name.as_str() == "mode" => {
+                self.next();
+                let mode = match self.peek() {
+                    Some(Token::Ident(word)) => Mode::from_name(word.as_str()),
+                    _ => None,
+                };
+                let Some(mode) = mode else {
+                    let found = self.peek();
+                    return self.error(
+                        Code::ExpectedToken,
+                        format!("Expected flat or round, found: {}", Found(found)),
+                    );
+                };
+                self.next();
+                self.expect(Token::Semicolon, "; after mode statement")?;
+                StmtKind::Mode(mode)
+            }
+            Token::Ident(name) if name.as_str() == "place_marker" => {
+                self.next();
+                let name = self.marker_name("place_marker")?;
+                self.expect(Token::Semicolon, "; after place_marker statement")?;
+                StmtKind::PlaceMarker(name)
+            }
+            Token::Ident(name) if name.as_str() == "slip_marker" => {
+                self.next();
+                let name = self.marker_name("slip_marker")?;
+                self.expect(Token::Semicolon, "; after slip_marker statement")?;
+                StmtKind::SlipMarker(name)
+            }
+            Token::Ident(name)
+                if name.as_str() == "sizes" && matches!(self.peek_at(1), Some(Token::Ident(_))) =>
+            {
+                self.next();
+                let mut names = vec![self.expect_ident()?];
+                while self.peek() == Some(Token::Comma) {
+                    self.next();
+                    names.push(self.expect_ident()?);
+                }
+                self.expect(Token::Semicolon, "; after sizes declaration")?;
+                self.sizes = Some(names.len());
+                StmtKind::Sizes(names)
+            }
+            Token::Ident(name) if name.as_str() == "gauge" => {
+                self.next();
+                let stitches = self.parse_expr()?;
+                self.expect(Token::Comma, ", between the stitches and rows of a gauge")?;
+                let rows = self.parse_expr()?;
+                self.expect(Token::Semicolon, "; after gauge statement")?;
+                StmtKind::Gauge(stitches, rows)
+            }
+            Token::Ident(name) if name.as_str() == "expect_stitches" => {
+                self.next();
+                // `expect_stitches?` only warns when the count is wrong.
+                let warns = self.peek() == Some(Token::Question);
+                if warns {
+                    self.next();
+                }
+                let count = self.parse_expr()?;
+                self.expect(Token::Semicolon, "; after expect_stitches statement")?;
+                if warns {
+                    StmtKind::ExpectStitchesWarning(count)
+                } else {
+                    StmtKind::ExpectStitches(count)
+                }
+            }
+            Token::Ident(name)
+                if name.as_str() == "note" && matches!(self.peek_at(1), Some(Token::Str(_))) =>
+            {
+                self.next();
+                let mut parts = Vec::new();
+                loop {
+                    match self.peek() {
+                        Some(Token::Str(text)) => {
+                            self.next();
+                            parts.push(NotePart::Text(text.as_str().to_string()));
+                        }
+                        _ => parts.push(NotePart::Expr(self.parse_expr()?)),
+                    }
+                    if self.peek() != Some(Token::Comma) {
+                        break;
+                    }
+                    self.next();
+                }
+                self.expect(Token::Semicolon, "; after note statement")?;
+                StmtKind::Note(parts)
+            }
+            Token::Ident(name)
+                if name.as_str() == "transfer"
+                    && matches!(
+                        (self.peek_at(1), self.peek_at(2)),
+                        (Some(Token::Ident(_)), Some(Token::Ident(_)))
+                    ) =>
+            {
+                self.next();
+                let from = self.needle_name("transfer")?;
+                let to = self.needle_name("the needle to transfer from")?;
+                let count = self.parse_expr()?;
+                self.expect(Token::Semicolon, "; after transfer statement")?;
+                StmtKind::Transfer(from, to, count)
+            }
+            Token::Ident(name)
+                if matches!(name.as_str(), "hold" | "resume")
+                    && matches!(self.peek_at(1), Some(Token::Ident(_)))
+                    && self.peek_at(2) == Some(Token::Semicolon) =>
+            {
+                self.next();
+                let needle = self.needle_name(name.as_str())?;
+                self.expect(Token::Semicolon, &format!("; after {name} statement"))?;
+                if name.as_str() == "hold" {
+                    StmtKind::Hold(needle)
+                } else {
+                    StmtKind::Resume(needle)
+                }
+            }
+            Token::Ident(name)
+                if name.as_str() == "chart"
+                    && matches!(self.peek_at(1), Some(Token::Semicolon | Token::Str(_))) =>
+            {
+                self.next();
+                let label = match self.peek() {
+                    Some(Token::Str(label)) => {
+                        self.next();
+                        Some(label.as_str().to_string())
+                    }
+                    _ => None,
+                };
+                self.expect(Token::Semicolon, "; after chart statement")?;
+                StmtKind::Chart(label)
+            }
+            Token::Ident(name) if name.as_str() == "distribute" => {
+                self.next();
+                let shaping = match self.peek() {
+                    Some(Token::Ident(word)) => Shaping::from_name(word.as_str()),
+                    _ => None,
+                };
+                let Some(shaping) = shaping else {
+                    let found = self.peek();
+                    return self.error(
+                        Code::ExpectedToken,
+                        format!("Expected inc or dec, found: {}", Found(found)),
+                    );
+                };
+                self.next();
+                let count = self.parse_expr()?;
+                self.expect(Token::Semicolon, "; after distribute statement")?;
+                StmtKind::Distribute(shaping, count)
+            }
+            Token::Ident(name)
+                if name.as_str() == "use" && matches!(self.peek_at(1), Some(Token::Str(_))) =>
+            {
+                if self.depth > 0 {
+                    return self.error(
+                        Code::NestedImport,
+                        format!(
+                            "Imports only go at the top level of a file, not inside a {}",
+                            self.block
+                        ),
+                    );
+                }
+                self.next();
+                let Some(Token::Str(path)) = self.next() else {
+                    unreachable!("checked above");
+                };
+                // `as` only means something here too.
+                let alias = match self.peek() {
+                    Some(Token::Ident(word)) if word.as_str() == "as" => {
+                        self.next();
+                        Some(self.expect_ident()?)
+                    }
+                    _ => None,
+                };
+                self.expect(Token::Semicolon, "; after use statement")?;
+                StmtKind::Use(path.as_str().to_string(), alias)
+            }
+            Token::Ident(name)
+                if Pattern::from_name(name.as_str()).is_some()
+                    && self.peek_at(1) == Some(Token::LParen) =>
+            {
+                self.parse_pattern(name)?
+            }
+            Token::Ident(name) if Stitch::from_name(name.as_str()).is_some() => {
+                let stitch = Stitch::from_name(name.as_str()).unwrap();
+                let name_span = self.peek_span();
+                self.next();
+                // A cable or a decrease is usually worked once, so `c4f;`
+                // needs no count.
+                let count = match self.peek() {
+                    Some(Token::Semicolon) if !stitch.needs_count() => Expr::number(1, name_span),
+                    _ => self.parse_expr()?,
+                };
+                self.expect(Token::Semicolon, &format!("; after {stitch} statement"))?;
+                StmtKind::Work(stitch, count)
+            }
+            other => {
+                let suggestion = match other {
+                    Token::Ident(name) => did_you_mean(name.as_str(), STATEMENT_KEYWORDS),
+                    _ => String::new(),
+                };
+                return self.error(
+                    Code::UnknownStatement,
+                    format!(
+                        "Unknown statement start: {}{suggestion}",
+                        Found(Some(other))
+                    ),
+                );
+            }
+        };
+        Ok(kind)
+    }
+
+    /// Parses every remaining statement.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        Ok(self.parse_program()?)
+    }
+
+    /// Like [`Parser::parse`], but says whether it stopped at a lex error.
+    pub(crate) fn parse_program(&mut self) -> Result<Vec<Stmt>, Stopped> {
+        let mut stmts = Vec::new();
+        loop {
+            match self.parse_stmt() {
+                Ok(Some(s)) => stmts.push(s),
+                Ok(None) => break,
+                Err(e) => return Err(self.lex_error_or(e)),
+            }
+        }
+        match self.lex_error.take() {
+            Some(e) => Err(Stopped::Lex(e)),
+            None => {
+                resolve::number_slots(&mut stmts);
+                Ok(stmts)
+            }
+        }
+    }
+
+    /// Like [`Parser::parse`], but carries on past syntax errors to report
+    /// every one of them.
+    pub fn parse_all(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let mut stmts = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            match self.parse_stmt() {
+                Ok(Some(s)) => stmts.push(s),
+                Ok(None) => break,
+                Err(e) => {
+                    errors.push(e);
+                    self.recover();
+                }
+            }
+        }
+        // Parsing stops at a lex error, as there is no telling what the
+        // tokens after it were meant to be.
+        errors.extend(self.lex_error.take().map(ParseError::from));
+        if errors.is_empty() {
+            resolve::number_slots(&mut stmts);
+            Ok(stmts)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Skips past the rest of the top-level statement an error was found in:
+    /// up to the next `;` outside any block, or the `}` that closes the
+    /// outermost block and any blocks straight after it, as an `alternate`
+    /// has. Always consumes at least one token if there is one.
+    fn recover(&mut self) {
+        let mut depth = std::mem::take(&mut self.depth);
+        self.nesting = 0;
+        self.repeats = 0;
+        while let Some(token) = self.next() {
+            match token {
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth <= 1 && self.peek() == Some(Token::LBrace) => depth = 0,
+                Token::RBrace if depth <= 1 => return,
+                Token::RBrace => depth -= 1,
+                Token::Semicolon if depth == 0 => return,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Lexes and parses a whole program, reporting every lex error or, if there
+/// are none, every syntax error. Never panics, whatever the input.
+pub fn try_parse(src: &str) -> Result<Vec<Stmt>, Vec<ParseError>> {
+    let mut lexer = Lexer::new(src);
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    loop {
+        match lexer.next_token() {
+            Ok(Some(token)) => tokens.push(token),
+            Ok(None) => break,
+            // The offending characters have been consumed, so lexing resumes
+            // right after them.
+            Err(e) => errors.push(e.into()),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Parser::new(tokens).parse_all()
+}
+
+/// Parses a program one top-level statement at a time, lexing only as far as
+/// the statement it returns, so a huge program never has all of its tokens or
+/// syntax tree in memory at once. Yields the same statements as
+/// [`parse_src`], and stops after the first error.
+pub struct Statements {
+    parser: Parser<Lexer>,
+    failed: bool,
+}
+
+/// The top-level statements of `src`, parsed as they are asked for.
+pub fn statements(src: &str) -> Statements {
+    Statements {
+        parser: Parser::from_source(src),
+        failed: false,
+    }
+}
+
+impl Statements {
+    /// See [`Parser::set_max_nesting`].
+    pub fn set_max_nesting(&mut self, limit: usize) {
+        self.parser.set_max_nesting(limit);
+    }
+
+    /// The next statement, or why there is none.
+    pub(crate) fn next_stmt(&mut self) -> Option<Result<Stmt, Stopped>> {
+        if self.failed {
+            return None;
+        }
+        let result = match self.parser.parse_stmt() {
+            Ok(Some(mut stmt)) => {
+                resolve::number_slots(slice::from_mut(&mut stmt));
+                return Some(Ok(stmt));
+            }
+            Ok(None) => Err(Stopped::Lex(self.parser.lex_error.take()?)),
+            Err(e) => Err(self.parser.lex_error_or(e)),
+        };
+        self.failed = true;
+        Some(result)
+    }
+}
+
+impl Iterator for Statements {
+    type Item = Result<Stmt, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_stmt()?.map_err(ParseError::from))
+    }
+}
+
+/// Parses `src` as a single expression, optionally followed by `;`.
+pub fn parse_expression(src: &str) -> Result<Expr, ParseError> {
+    lone_expression(Parser::from_source(src))
+}
+
+/// What [`parse_expression`] does, with `parser`'s limits.
+pub(crate) fn lone_expression(mut parser: Parser<Lexer>) -> Result<Expr, ParseError> {
+    match parser.parse_lone_expr() {
+        Ok(expr) => match parser.lex_error.take() {
+            Some(e) => Err(e.into()),
+            None => Ok(expr),
+        },
+        Err(e) => Err(parser.lex_error_or(e).into()),
+    }
+}
+
+/// Lexes and parses a whole program.
+pub fn parse_src(src: &str) -> Result<Vec<Stmt>, ParseError> {
+    Parser::from_source(src).parse()
+}
@@ -0,0 +1,166 @@
+//! Python bindings, enabled by the `python` feature and built into an
+//! importable module with maturin (see `pyproject.toml`):
+//!
+//! ```python
+//! import knitlang
+//! result = knitlang.run("cast_on width = 4; purl width;")
+//! session = knitlang.Session()
+//! session.feed("cast_on width = 4;")
+//! ```
+//!
+//! Errors are `knitlang.KnitError` exceptions. PyO3 turns a panic in any of
+//! these functions into a `pyo3_runtime.PanicException` before it can unwind
+//! into Python.
+
+use crate::interp::Value;
+use crate::parser::DEFAULT_MAX_NESTING;
+use crate::{execute, line_col, Capture, FeedResult, Interpreter, Session};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::io::{self, Write};
+
+create_exception!(
+    knitlang,
+    KnitError,
+    PyException,
+    "A program failed to lex, parse or run. `kind` is \"lex\", \"parse\" or \
+     \"runtime\"; `line` and `column` are 1-based and say where."
+);
+
+/// A knitlang value as the Python object it stands for. Every value is an
+/// integer, so for now this is always an `int`.
+fn value_to_py(py: Python<'_>, value: Value) -> PyResult<Bound<'_, PyAny>> {
+    Ok(value.into_pyobject(py)?.into_any())
+}
+
+/// The variables of `interp` as a `dict`, in name order.
+fn variables<'py>(py: Python<'py>, interp: &Interpreter) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (name, value) in interp.vars() {
+        dict.set_item(name, value_to_py(py, *value)?)?;
+    }
+    Ok(dict)
+}
+
+/// `error` as a [`KnitError`] exception pointing into `src`.
+fn knit_error(py: Python<'_>, src: &str, error: &crate::KnitError) -> PyErr {
+    let (line, column) = line_col(src, error.span().start);
+    let err = KnitError::new_err(error.to_string());
+    let value = err.value(py);
+    let located = (|| {
+        value.setattr("kind", error.kind())?;
+        value.setattr("line", line)?;
+        value.setattr("column", column)
+    })();
+    match located {
+        Ok(()) => err,
+        Err(e) => e,
+    }
+}
+
+/// Runs `src` on a fresh interpreter and returns
+/// `{"output": str, "variables": dict, "errors": list}`: the text printed
+/// with `purl`, the final variables by name, and a list of `KnitError`s (at
+/// most one, as the run stops at the first).
+#[pyfunction]
+fn run<'py>(py: Python<'py>, src: &str) -> PyResult<Bound<'py, PyDict>> {
+    let (capture, buffer) = Capture::new(None);
+    let mut interp = Interpreter::with_output(Box::new(capture));
+    let errors = PyList::empty(py);
+    if let Err(e) = execute(src, &mut interp, DEFAULT_MAX_NESTING) {
+        errors.append(knit_error(py, src, &e).into_value(py))?;
+    }
+    let result = PyDict::new(py);
+    result.set_item("output", String::from_utf8_lossy(&buffer.borrow()))?;
+    result.set_item("variables", variables(py, &interp)?)?;
+    result.set_item("errors", errors)?;
+    Ok(result)
+}
+
+/// Passes program output on to Python's `sys.stdout`, so it shows up
+/// wherever Python's own `print` does, notebooks included.
+struct PythonStdout;
+
+impl Write for PythonStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        Python::attach(|py| {
+            py.import("sys")?
+                .getattr("stdout")?
+                .call_method1("write", (text,))?;
+            Ok::<_, PyErr>(())
+        })
+        .map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Python::attach(|py| {
+            py.import("sys")?.getattr("stdout")?.call_method0("flush")?;
+            Ok::<_, PyErr>(())
+        })
+        .map_err(io::Error::other)
+    }
+}
+
+/// `knitlang.Session()`: source fed a piece at a time, as in the REPL, with
+/// variables and the fabric carried over from one feed to the next. Program
+/// output goes to `sys.stdout`.
+#[pyclass(name = "Session", unsendable)]
+struct PySession {
+    session: Session,
+}
+
+#[pymethods]
+impl PySession {
+    #[new]
+    fn new() -> Self {
+        let interp = Interpreter::with_output(Box::new(PythonStdout));
+        Self {
+            session: Session::with_interpreter(interp),
+        }
+    }
+
+    /// Feeds `src`, running it once its braces balance. Returns the value of
+    /// a bare expression and `None` for anything else; raises `KnitError` if
+    /// the input fails.
+    fn feed<'py>(&mut self, py: Python<'py>, src: &str) -> PyResult<Option<Bound<'py, PyAny>>> {
+        match self.session.feed(src) {
+            Ok(FeedResult::Value(value)) => Ok(Some(value_to_py(py, value)?)),
+            Ok(FeedResult::Executed(_) | FeedResult::NeedMore) => Ok(None),
+            Err(e) => Err(knit_error(py, self.session.last_source(), &e)),
+        }
+    }
+
+    /// Runs input still waiting for closing braces, raising `KnitError` for
+    /// the unclosed block.
+    fn finish(&mut self, py: Python<'_>) -> PyResult<()> {
+        match self.session.finish() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(knit_error(py, self.session.last_source(), &e)),
+        }
+    }
+
+    /// Whether fed input is waiting for closing braces.
+    #[getter]
+    fn needs_more(&self) -> bool {
+        !self.session.pending().is_empty()
+    }
+
+    /// Every variable so far, by name.
+    #[getter]
+    fn variables<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        variables(py, self.session.interpreter())
+    }
+}
+
+/// The `knitlang` Python module.
+#[pymodule]
+pub fn knitlang(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add_class::<PySession>()?;
+    m.add("KnitError", m.py().get_type::<KnitError>())?;
+    Ok(())
+}
@@ -0,0 +1,320 @@
+//! Resolution: numbering the variables of a program, and finding, before it
+//! runs, any variable it reads before anything has given it a value.
+//!
+//! Each program numbers the variables it reads from 0, in
+//! [`number_slots`], and its expressions keep the number with the name. A
+//! run maps those slots to where the interpreter keeps the values once, on
+//! the way in, so a read is an index into a table as long as the program,
+//! however many names other programs in the process have interned.
+//!
+//! Resolution follows the source, not the run: a variable set inside a
+//! `repeat` counts as set after it even if the loop runs no passes. The
+//! interpreter still checks each read as it happens, which catches those and
+//! anything a host changes between runs, such as a REPL's earlier lines.
+
+use crate::ast::{Expr, ExprKind, NotePart, Stmt, StmtKind};
+use crate::codes::Code;
+use crate::interp::{RuntimeError, SIZE_VARIABLE};
+use crate::lexer::Span;
+use crate::suggest::did_you_mean;
+use crate::symbol::Symbol;
+use std::collections::{HashMap, HashSet};
+
+/// Where a run keeps one of a program's variables, numbered by
+/// [`number_slots`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Slot(pub(crate) u32);
+
+impl Slot {
+    /// The slot of a variable that has not been numbered.
+    pub(crate) const NONE: Slot = Slot(u32::MAX);
+}
+
+/// Numbers the variables `stmts` read and assign, from 0 in the order they
+/// first appear. The parser, the importer and the optimizer number what they
+/// produce; a tree built or changed some other way is numbered again, on a
+/// copy, when it is run.
+pub fn number_slots(stmts: &mut [Stmt]) {
+    let mut slots = HashMap::new();
+    let mut number = |name| {
+        let next = Slot(slots.len() as u32);
+        *slots.entry(name).or_insert(next)
+    };
+    each_expr_mut(stmts, &mut |target, e| {
+        for (name, slot) in e.slots_mut() {
+            *slot = number(name);
+        }
+        if let Some(name) = target {
+            e.set_target(number(name));
+        }
+    });
+}
+
+/// The variable in each slot of a program, as [`resolve`] finds them.
+#[derive(Debug, Default)]
+pub(crate) struct Slots {
+    /// The name in each slot, or `None` for a slot the statements do not
+    /// use, as when they are one statement of a larger program.
+    pub(crate) names: Vec<Option<Symbol>>,
+    /// Whether a variable was not numbered, or two in one slot differ, as
+    /// when programs numbered apart are run as one.
+    pub(crate) unnumbered: bool,
+}
+
+impl Slots {
+    fn record(&mut self, e: &Expr) {
+        for (name, slot) in e.slots() {
+            self.record_slot(name, slot);
+        }
+    }
+
+    fn record_slot(&mut self, name: Symbol, slot: Slot) {
+        if slot == Slot::NONE {
+            self.unnumbered = true;
+            return;
+        }
+        let slot = slot.0 as usize;
+        if slot >= self.names.len() {
+            self.names.resize(slot + 1, None);
+        }
+        match self.names[slot] {
+            Some(known) => self.unnumbered |= known != name,
+            None => self.names[slot] = Some(name),
+        }
+    }
+}
+
+/// What a run needs to know before it starts: where its variables are, or
+/// the first read of one with no value, as [`first_undefined`] finds it.
+pub(crate) fn resolve(
+    stmts: &[Stmt],
+    defined: impl Fn(Symbol) -> bool,
+) -> Result<Slots, Undefined> {
+    let mut assigned = HashSet::new();
+    let mut slots = Slots::default();
+    match walk(stmts, &defined, &mut assigned, &mut slots) {
+        Some((name, span)) => Err(Undefined {
+            name,
+            span,
+            assigned: assigned.into_iter().collect(),
+        }),
+        None => Ok(slots),
+    }
+}
+
+/// A read of a variable with no value at that point in the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Undefined {
+    pub name: Symbol,
+    pub span: Span,
+    /// The variables the program has set by then, for suggesting a name.
+    pub assigned: Vec<Symbol>,
+}
+
+impl Undefined {
+    /// The error a run stops with for the read, suggesting a close name
+    /// among the variables set by then and `known`.
+    pub fn to_error<'a>(&self, known: impl IntoIterator<Item = &'a str>) -> RuntimeError {
+        let names = self.assigned.iter().map(|n| n.as_str()).chain(known);
+        RuntimeError {
+            code: Code::UndefinedVariable,
+            message: format!(
+                "undefined variable `{}`{}",
+                self.name,
+                did_you_mean(self.name.as_str(), names)
+            ),
+            span: self.span,
+        }
+    }
+}
+
+/// The first read in `stmts` of a variable that neither an earlier statement
+/// nor `defined`, the variables set before the program starts, gives a value.
+pub fn first_undefined(stmts: &[Stmt], defined: impl Fn(Symbol) -> bool) -> Option<Undefined> {
+    resolve(stmts, defined).err()
+}
+
+/// Like [`first_undefined`] for programs run one after another against one
+/// fresh interpreter, as the command line runs its files: each sees the
+/// variables the ones before it set. Returns the index of the program the
+/// read is in along with it.
+pub fn first_undefined_in_order<'a>(
+    programs: impl IntoIterator<Item = &'a [Stmt]>,
+) -> Option<(usize, Undefined)> {
+    let mut assigned = HashSet::new();
+    programs.into_iter().enumerate().find_map(|(i, stmts)| {
+        let found = walk(stmts, &|_| false, &mut assigned, &mut Slots::default())?;
+        let undefined = Undefined {
+            name: found.0,
+            span: found.1,
+            assigned: assigned.iter().copied().collect(),
+        };
+        Some((i, undefined))
+    })
+}
+
+fn walk(
+    stmts: &[Stmt],
+    defined: &impl Fn(Symbol) -> bool,
+    assigned: &mut HashSet<Symbol>,
+    slots: &mut Slots,
+) -> Option<(Symbol, Span)> {
+    // The bodies being walked, innermost last, kept on a stack rather than
+    // in calls so that no depth of nesting can overflow.
+    let mut bodies = vec![stmts.iter()];
+    while let Some(body) = bodies.last_mut() {
+        let Some(stmt) = body.next() else {
+            bodies.pop();
+            continue;
+        };
+        let is_set = |name| defined(name) || assigned.contains(&name);
+        match &stmt.kind {
+            StmtKind::CastOn(name, e) | StmtKind::Knit(name, e) => {
+                if let Some(found) = first_unset(slots, e, is_set) {
+                    return Some(found);
+                }
+                slots.record_slot(*name, e.target());
+                assigned.insert(*name);
+            }
+            StmtKind::CastOnStitches(e)
+            | StmtKind::Purl(e)
+            | StmtKind::Work(_, e)
+            | StmtKind::Cable(e, _)
+            | StmtKind::Distribute(_, e)
+            | StmtKind::BindOffStitches(e)
+            | StmtKind::ExpectStitches(e)
+            | StmtKind::ExpectStitchesWarning(e)
+            | StmtKind::CastOnNeedle(_, e)
+            | StmtKind::Transfer(_, _, e) => {
+                if let Some(found) = first_unset(slots, e, is_set) {
+                    return Some(found);
+                }
+            }
+            StmtKind::Repeat(count, body) => {
+                if let Some(found) = first_unset(slots, count, is_set) {
+                    return Some(found);
+                }
+                bodies.push(body.iter());
+            }
+            StmtKind::Mirror(body) => bodies.push(body.iter()),
+            // The bodies run in turn, so are walked in order, the first on
+            // top.
+            StmtKind::Alternate(alternates) => {
+                bodies.extend(alternates.iter().rev().map(|body| body.iter()));
+            }
+            StmtKind::Pattern(_, args) => {
+                if let Some(found) = args.iter().find_map(|arg| first_unset(slots, arg, is_set)) {
+                    return Some(found);
+                }
+            }
+            StmtKind::Gauge(stitches, rows) => {
+                if let Some(found) = first_unset(slots, stitches, is_set)
+                    .or_else(|| first_unset(slots, rows, is_set))
+                {
+                    return Some(found);
+                }
+            }
+            StmtKind::Note(parts) => {
+                let found = parts.iter().find_map(|part| match part {
+                    NotePart::Expr(e) => first_unset(slots, e, is_set),
+                    NotePart::Text(_) => None,
+                });
+                if found.is_some() {
+                    return found;
+                }
+            }
+            StmtKind::BindOff
+            | StmtKind::Chart(_)
+            | StmtKind::Hold(_)
+            | StmtKind::Resume(_)
+            | StmtKind::Yarn(..)
+            | StmtKind::Color(_)
+            | StmtKind::WrapTurn
+            | StmtKind::Mode(_)
+            | StmtKind::PlaceMarker(_)
+            | StmtKind::SlipMarker(_)
+            | StmtKind::Use(..)
+            | StmtKind::Meta(_) => {}
+            StmtKind::Sizes(_) => {
+                assigned.insert(Symbol::intern(SIZE_VARIABLE));
+            }
+        }
+    }
+    None
+}
+
+/// The first variable `e` reads that is not set. Its slots are added
+/// to `slots`.
+fn first_unset(
+    slots: &mut Slots,
+    e: &Expr,
+    is_set: impl Fn(Symbol) -> bool,
+) -> Option<(Symbol, Span)> {
+    slots.record(e);
+    e.nodes().iter().find_map(|node| match node.kind {
+        ExprKind::Var(name) if !is_set(name) => Some((name, node.span)),
+        _ => None,
+    })
+}
+
+/// Calls `f` on every expression in `stmts`, with the variable it is
+/// assigned to if it is the value of `cast_on` or `knit`.
+fn each_expr_mut(stmts: &mut [Stmt], f: &mut impl FnMut(Option<Symbol>, &mut Expr)) {
+    let mut bodies = vec![stmts.iter_mut()];
+    while let Some(body) = bodies.last_mut() {
+        let Some(stmt) = body.next() else {
+            bodies.pop();
+            continue;
+        };
+        match &mut stmt.kind {
+            StmtKind::CastOn(name, e) | StmtKind::Knit(name, e) => f(Some(*name), e),
+            StmtKind::CastOnStitches(e)
+            | StmtKind::Purl(e)
+            | StmtKind::Work(_, e)
+            | StmtKind::Cable(e, _)
+            | StmtKind::Distribute(_, e)
+            | StmtKind::BindOffStitches(e)
+            | StmtKind::ExpectStitches(e)
+            | StmtKind::ExpectStitchesWarning(e)
+            | StmtKind::CastOnNeedle(_, e)
+            | StmtKind::Transfer(_, _, e) => f(None, e),
+            StmtKind::Repeat(count, body) => {
+                f(None, count);
+                bodies.push(body.iter_mut());
+            }
+            StmtKind::Mirror(body) => bodies.push(body.iter_mut()),
+            StmtKind::Alternate(alternates) => {
+                bodies.extend(alternates.iter_mut().map(|body| body.iter_mut()));
+            }
+            StmtKind::Pattern(_, args) => {
+                for arg in args {
+                    f(None, arg);
+                }
+            }
+            StmtKind::Gauge(stitches, rows) => {
+                f(None, stitches);
+                f(None, rows);
+            }
+            StmtKind::Note(parts) => {
+                for part in parts {
+                    if let NotePart::Expr(e) = part {
+                        f(None, e);
+                    }
+                }
+            }
+            StmtKind::BindOff
+            | StmtKind::Chart(_)
+            | StmtKind::Hold(_)
+            | StmtKind::Resume(_)
+            | StmtKind::Yarn(..)
+            | StmtKind::Color(_)
+            | StmtKind::WrapTurn
+            | StmtKind::Mode(_)
+            | StmtKind::PlaceMarker(_)
+            | StmtKind::SlipMarker(_)
+            | StmtKind::Use(..)
+            | StmtKind::Meta(_)
+            | StmtKind::Sizes(_) => {}
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Where files named by a relative path are found: on the command line, by
+//! `examples`, by the REPL's `:load` and by `use` statements.
+
+use knitlang::imports::Source;
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories searched for source files named by a relative path: the
+/// current directory, then each entry of `KNIT_PATH` (separated like `PATH` on
+/// this platform), then each `--path`. The first match wins and repeated
+/// directories are only searched once.
+pub(crate) struct SearchPath {
+    /// The current directory first, then the rest in search order.
+    dirs: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    /// The search path of this process, with `extra` from `--path`.
+    pub(crate) fn new(extra: &[PathBuf]) -> Self {
+        Self::from_parts(Path::new("."), env::var_os("KNIT_PATH").as_deref(), extra)
+    }
+
+    /// The search path from `current`, standing in for the current
+    /// directory, then the entries of `knit_path`, then `extra`.
+    fn from_parts(current: &Path, knit_path: Option<&OsStr>, extra: &[PathBuf]) -> Self {
+        let mut dirs = vec![current.to_path_buf()];
+        if let Some(var) = knit_path {
+            dirs.extend(env::split_paths(var).filter(|d| !d.as_os_str().is_empty()));
+        }
+        dirs.extend(extra.iter().cloned());
+        let mut seen = HashSet::new();
+        dirs.retain(|d| seen.insert(d.clone()));
+        Self { dirs }
+    }
+
+    /// Where `name` would be in each directory, in search order. Files in the
+    /// current directory keep the name exactly as given.
+    pub(crate) fn candidates<'a>(&'a self, name: &'a Path) -> impl Iterator<Item = PathBuf> + 'a {
+        self.dirs.iter().map(move |dir| {
+            if dir == Path::new(".") {
+                name.to_path_buf()
+            } else {
+                dir.join(name)
+            }
+        })
+    }
+
+    /// Finds the file `name` refers to. Absolute paths and `-` are taken as
+    /// they are; otherwise the error lists every directory searched.
+    pub(crate) fn resolve(&self, name: &Path) -> Result<PathBuf, String> {
+        if name == Path::new("-") || name.is_absolute() {
+            return Ok(name.to_path_buf());
+        }
+        if let Some(found) = self.candidates(name).find(|path| path.is_file()) {
+            return Ok(found);
+        }
+        let searched: Vec<String> = self.dirs.iter().map(|d| d.display().to_string()).collect();
+        Err(format!(
+            "could not find {} (searched {})",
+            name.display(),
+            searched.join(", ")
+        ))
+    }
+
+    /// Finds and reads the file that `use name;` in the file `from` names:
+    /// next to `from` first, then in each directory of the search path but
+    /// the current one.
+    pub(crate) fn import(&self, from: &str, name: &str) -> Result<Source, String> {
+        let name = Path::new(name);
+        let here = Path::new(from).parent().unwrap_or(Path::new(""));
+        let mut dirs = vec![here];
+        dirs.extend(
+            self.dirs
+                .iter()
+                .skip(1)
+                .map(PathBuf::as_path)
+                .filter(|d| *d != here),
+        );
+        let path = if name.is_absolute() {
+            name.to_path_buf()
+        } else {
+            let found = dirs
+                .iter()
+                .map(|d| d.join(name))
+                .find(|path| path.is_file());
+            found.ok_or_else(|| {
+                let searched: Vec<String> = dirs
+                    .iter()
+                    .map(|d| match d.as_os_str().is_empty() {
+                        true => ".".to_string(),
+                        false => d.display().to_string(),
+                    })
+                    .collect();
+                format!("could not find it (searched {})", searched.join(", "))
+            })?
+        };
+        let src = fs::read_to_string(&path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        Ok(Source {
+            key: file_key(&path),
+            origin: path.display().to_string(),
+            src: src.into(),
+        })
+    }
+}
+
+/// What tells the file at `path` apart from others however it is named: its
+/// canonical path, or `path` itself for a name such as `<stdin>` that is not
+/// a file.
+pub(crate) fn file_key(path: &Path) -> String {
+    fs::canonicalize(path).map_or_else(
+        |_| path.display().to_string(),
+        |path| path.display().to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `cwd`, `a` and `b`, each with a `lib.knit`
+    /// that purls its own number, and a `KNIT_PATH` value of `a` then `b`.
+    fn dirs(name: &str) -> (PathBuf, PathBuf, PathBuf, std::ffi::OsString) {
+        let root = env::temp_dir().join(format!("knitlang-search-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (cwd, a, b) = (root.join("cwd"), root.join("a"), root.join("b"));
+        for (dir, n) in [(&cwd, 0), (&a, 1), (&b, 2)] {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("lib.knit"), format!("purl {n};\n")).unwrap();
+        }
+        let knit_path = env::join_paths([&a, &b]).unwrap();
+        (cwd, a, b, knit_path)
+    }
+
+    fn found(search: &SearchPath, name: &str) -> PathBuf {
+        search.resolve(Path::new(name)).unwrap()
+    }
+
+    #[test]
+    fn the_current_directory_comes_first_then_knit_path_then_path_flags() {
+        let (cwd, a, b, knit_path) = dirs("resolve-order");
+        let extra = [b.clone()];
+        let search = SearchPath::from_parts(&cwd, Some(&knit_path), &extra);
+        assert_eq!(found(&search, "lib.knit"), cwd.join("lib.knit"));
+
+        fs::remove_file(cwd.join("lib.knit")).unwrap();
+        assert_eq!(found(&search, "lib.knit"), a.join("lib.knit"));
+        // `--path` comes after every entry of `KNIT_PATH`.
+        let search = SearchPath::from_parts(&cwd, Some(a.as_os_str()), &extra);
+        assert_eq!(found(&search, "lib.knit"), a.join("lib.knit"));
+        let search = SearchPath::from_parts(&cwd, None, &extra);
+        assert_eq!(found(&search, "lib.knit"), b.join("lib.knit"));
+
+        // Absolute paths and stdin are not looked up at all.
+        let absolute = b.join("nowhere.knit");
+        assert_eq!(search.resolve(&absolute), Ok(absolute.clone()));
+        assert_eq!(search.resolve(Path::new("-")), Ok(PathBuf::from("-")));
+    }
+
+    #[test]
+    fn each_directory_is_searched_once_and_listed_when_nothing_is_found() {
+        let (cwd, a, b, _) = dirs("resolve-duplicates");
+        let knit_path = env::join_paths([&a, &b, &a, Path::new("")]).unwrap();
+        let search = SearchPath::from_parts(&cwd, Some(&knit_path), &[b.clone(), cwd.clone()]);
+        assert_eq!(
+            search.resolve(Path::new("nope.knit")),
+            Err(format!(
+                "could not find nope.knit (searched {}, {}, {})",
+                cwd.display(),
+                a.display(),
+                b.display()
+            ))
+        );
+    }
+
+    #[test]
+    fn imports_look_next_to_the_importing_file_then_along_the_path() {
+        let (cwd, a, b, knit_path) = dirs("import-order");
+        let pattern = cwd.join("pattern");
+        fs::create_dir_all(&pattern).unwrap();
+        let from = pattern.join("main.knit");
+        let from = from.to_str().unwrap();
+        let search = SearchPath::from_parts(&cwd, Some(&knit_path), std::slice::from_ref(&b));
+        let imported = |name| search.import(from, name).map(|source| source.origin);
+
+        // Not the current directory: only the importer's own, then the path.
+        assert_eq!(
+            imported("lib.knit"),
+            Ok(a.join("lib.knit").display().to_string())
+        );
+        fs::write(pattern.join("lib.knit"), "purl 3;\n").unwrap();
+        assert_eq!(
+            imported("lib.knit"),
+            Ok(pattern.join("lib.knit").display().to_string())
+        );
+
+        let search = SearchPath::from_parts(&cwd, None, std::slice::from_ref(&b));
+        let source = search.import(from, "../../b/lib.knit").unwrap();
+        assert_eq!(&*source.src, "purl 2;\n");
+        assert_eq!(
+            search.import(from, "nope.knit").map(|source| source.origin),
+            Err(format!(
+                "could not find it (searched {}, {})",
+                pattern.display(),
+                b.display()
+            ))
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Running a program a piece at a time as it is written, the way the REPL
+//! does: input is held back until its braces balance, and a bare expression
+//! is evaluated rather than rejected.
+
+use crate::ast::Expr;
+use crate::codes::Code;
+use crate::interp::{Interpreter, Value};
+use crate::lexer::{lex_all, Lexer, Token};
+use crate::parser::{lone_expression, Parser, Stopped, DEFAULT_MAX_NESTING};
+use crate::{flush, KnitError, RunOptions};
+use std::{io, mem};
+
+/// What [`Session::feed`] did with its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedResult {
+    /// The input ran; it held this many top-level statements.
+    Executed(usize),
+    /// The input opens more braces than it closes, so it is kept until a
+    /// later feed closes them.
+    NeedMore,
+    /// The input was a bare expression, and this is its value.
+    Value(Value),
+}
+
+/// An interpreter fed source one piece at a time. Variables and the fabric
+/// carry over from one feed to the next. A feed that fails to parse runs
+/// nothing, and one that fails at run time keeps the effects of the
+/// statements before the failing one, as a whole program would.
+pub struct Session {
+    interp: Interpreter,
+    /// Input waiting for its closing braces.
+    pending: String,
+    /// The most recent input that ran or failed to.
+    last: String,
+    /// Passed on to the parser for each input.
+    max_nesting: usize,
+}
+
+impl Session {
+    /// A session on a fresh interpreter with the limits and output sink of
+    /// `options`; without a sink, output is discarded. Input always runs as
+    /// it arrives, so `options.streaming` makes no difference.
+    pub fn new(options: RunOptions) -> Self {
+        let out = options.output.unwrap_or_else(|| Box::new(io::sink()));
+        let mut interp = Interpreter::with_output(out);
+        interp.set_row_limit(options.row_limit);
+        interp.set_timeout(options.timeout);
+        interp.set_step_limit(options.step_limit);
+        interp.set_memory_limit(options.memory_limit);
+        interp.set_size(options.size.as_deref());
+        let mut session = Self::with_interpreter(interp);
+        session.set_max_nesting(options.max_nesting.unwrap_or(DEFAULT_MAX_NESTING));
+        session
+    }
+
+    /// A session continuing from wherever `interp` is.
+    pub fn with_interpreter(interp: Interpreter) -> Self {
+        Self {
+            interp,
+            pending: String::new(),
+            last: String::new(),
+            max_nesting: DEFAULT_MAX_NESTING,
+        }
+    }
+
+    /// Rejects input whose repeats and calls nest more than `limit` deep;
+    /// see [`Parser::set_max_nesting`].
+    pub fn set_max_nesting(&mut self, limit: usize) {
+        self.max_nesting = limit;
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interp
+    }
+
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interp
+    }
+
+    pub fn into_interpreter(self) -> Interpreter {
+        self.interp
+    }
+
+    /// Input fed so far that is still waiting for its closing braces.
+    pub fn pending(&self) -> &str {
+        &self.pending
+    }
+
+    /// Forgets the pending input.
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    /// The most recent input that ran or failed to. The spans of errors
+    /// from [`Session::feed`] and [`Session::finish`] point into it.
+    pub fn last_source(&self) -> &str {
+        &self.last
+    }
+
+    /// Adds `src`, ending in a newline, to the pending input and runs it all
+    /// once its braces balance.
+    ///
+    /// ```
+    /// use knitlang::{FeedResult, Session};
+    ///
+    /// let mut session = Session::new(Default::default());
+    /// let mut feed = |src| session.feed(src).unwrap();
+    /// assert_eq!(feed("cast_on width = 4;"), FeedResult::Executed(1));
+    /// assert_eq!(feed("repeat 2 {"), FeedResult::NeedMore);
+    /// assert_eq!(feed("knit width = width + 1; }"), FeedResult::Executed(1));
+    /// assert_eq!(feed("width * 10"), FeedResult::Value(60));
+    /// ```
+    pub fn feed(&mut self, src: &str) -> Result<FeedResult, KnitError> {
+        self.pending.push_str(src);
+        if !self.pending.ends_with('\n') {
+            self.pending.push('\n');
+        }
+        if needs_continuation(&self.pending) {
+            return Ok(FeedResult::NeedMore);
+        }
+        self.run_pending()
+    }
+
+    /// Runs the pending input even though its braces do not balance, so the
+    /// unclosed block is reported, e.g. once input has run out.
+    pub fn finish(&mut self) -> Result<FeedResult, KnitError> {
+        if self.pending.trim().is_empty() {
+            self.pending.clear();
+            return Ok(FeedResult::Executed(0));
+        }
+        self.run_pending()
+    }
+
+    fn run_pending(&mut self) -> Result<FeedResult, KnitError> {
+        self.last = mem::take(&mut self.pending);
+        // Each input gets the whole timeout, however long it was waited for.
+        self.interp.restart_timeout();
+        let stmts = match self.parser().parse_program() {
+            Ok(stmts) => stmts,
+            // A stitch pattern given the wrong arguments is still a
+            // statement, though it reads like a call.
+            Err(Stopped::Syntax(e)) if e.code == Code::WrongArgumentCount => return Err(e.into()),
+            // Only try the input as an expression once it is not a program,
+            // so the error reported is the statement one.
+            Err(stopped) => match lone_expression(self.parser()) {
+                Ok(expr) => return self.eval(&expr),
+                Err(_) => return Err(stopped.into()),
+            },
+        };
+        let ran = self.interp.run(&stmts);
+        // Flushed whether or not it failed, so what the input printed comes
+        // out before its error is reported.
+        let flushed = flush(&mut self.interp);
+        ran?;
+        flushed?;
+        Ok(FeedResult::Executed(stmts.len()))
+    }
+
+    /// A parser for the latest input.
+    fn parser(&self) -> Parser<Lexer> {
+        let mut parser = Parser::from_source(&self.last);
+        parser.set_max_nesting(self.max_nesting);
+        parser
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<FeedResult, KnitError> {
+        Ok(FeedResult::Value(self.interp.eval(expr)?))
+    }
+}
+
+/// Whether `src` opens more braces than it closes, meaning more input is
+/// needed before it can be parsed.
+fn needs_continuation(src: &str) -> bool {
+    let Ok(tokens) = lex_all(src) else {
+        return false;
+    };
+    let mut depth = 0i64;
+    for (t, _) in &tokens {
+        match t {
+            Token::LBrace => depth += 1,
+            Token::RBrace => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
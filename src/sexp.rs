@@ -0,0 +1,255 @@
+//! The syntax tree as S-expressions, for `knitlang parse --format sexp`: a
+//! compact form of the same tree [`ast_to_json`](crate::ast_to_json) writes,
+//! easy to pick apart with `sed` or `awk` and to read when learning how a
+//! program parses.
+//!
+//! Each top-level statement is one list on a line of its own, headed by the
+//! `"kind"` its JSON gives it and followed by its parts in source order, so
+//! `repeat 4 { purl x; }` is `(repeat (num 4) ((purl (var x))))`. A block
+//! of statements is a list of them, and an expression is a list too:
+//! `(num 4)`, `(var x)`, `(+ (var x) (num 1))` for an operator,
+//! `(call name args...)`, `(stitches_to marker)`, `(count needle)` and
+//! `(sizes 88 96 104)`. Names, keywords and stitches such as `c4f` are
+//! bare atoms, numbers are written in decimal, and strings are in double
+//! quotes with `"` and `\` escaped by a backslash, and `\n`, `\r`, `\t` and
+//! `\u{…}` for control characters, so every atom reads back as itself.
+//! Spans are left out.
+
+use crate::ast::{Expr, ExprKind, MetaValue, NotePart, Stmt, StmtKind};
+use std::fmt::Write;
+use std::mem;
+
+/// The S-expression of every statement of a program, one per line:
+///
+/// ```
+/// let stmts = knitlang::parse_src("repeat 4 { purl x; } note \"Dec to \", n;").unwrap();
+/// assert_eq!(
+///     knitlang::ast_to_sexp(&stmts),
+///     "(repeat (num 4) ((purl (var x))))\n(note \"Dec to \" (var n))\n"
+/// );
+/// ```
+pub fn ast_to_sexp(stmts: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in stmts {
+        write_stmt(&mut out, stmt);
+        out.push('\n');
+    }
+    out
+}
+
+/// The S-expression of `expr` alone:
+///
+/// ```
+/// let expr = knitlang::parse_expression("width - 2 * count(back)").unwrap();
+/// assert_eq!(
+///     knitlang::sexp::expr_to_sexp(&expr),
+///     "(- (var width) (* (num 2) (count back)))"
+/// );
+/// ```
+pub fn expr_to_sexp(expr: &Expr) -> String {
+    let mut out = String::new();
+    write_expr(&mut out, expr);
+    out
+}
+
+fn write_stmt(out: &mut String, stmt: &Stmt) {
+    out.push('(');
+    out.push_str(stmt.kind.name());
+    let expr = |out: &mut String, expr: &Expr| {
+        out.push(' ');
+        write_expr(out, expr);
+    };
+    match &stmt.kind {
+        StmtKind::CastOn(name, value)
+        | StmtKind::CastOnNeedle(name, value)
+        | StmtKind::Knit(name, value) => {
+            let _ = write!(out, " {name}");
+            expr(out, value);
+        }
+        StmtKind::CastOnStitches(count)
+        | StmtKind::Purl(count)
+        | StmtKind::BindOffStitches(count)
+        | StmtKind::ExpectStitches(count)
+        | StmtKind::ExpectStitchesWarning(count) => expr(out, count),
+        StmtKind::Work(stitch, count) => {
+            let _ = write!(out, " {stitch}");
+            expr(out, count);
+        }
+        StmtKind::Repeat(count, body) => {
+            expr(out, count);
+            write_body(out, body);
+        }
+        StmtKind::BindOff | StmtKind::WrapTurn | StmtKind::Chart(None) => {}
+        StmtKind::Yarn(name, colour) => {
+            let _ = write!(out, " {name} ");
+            write_string(out, colour);
+        }
+        StmtKind::Color(name)
+        | StmtKind::PlaceMarker(name)
+        | StmtKind::SlipMarker(name)
+        | StmtKind::Hold(name)
+        | StmtKind::Resume(name) => {
+            let _ = write!(out, " {name}");
+        }
+        StmtKind::Cable(count, cross) => {
+            expr(out, count);
+            let _ = write!(out, " {}", cross.name());
+        }
+        StmtKind::Mode(mode) => {
+            let _ = write!(out, " {}", mode.name());
+        }
+        StmtKind::Use(path, alias) => {
+            out.push(' ');
+            write_string(out, path);
+            if let Some(alias) = alias {
+                let _ = write!(out, " {alias}");
+            }
+        }
+        StmtKind::Sizes(names) => {
+            for name in names {
+                let _ = write!(out, " {name}");
+            }
+        }
+        StmtKind::Gauge(stitches, rows) => {
+            expr(out, stitches);
+            expr(out, rows);
+        }
+        StmtKind::Distribute(shaping, count) => {
+            let _ = write!(out, " {}", shaping.name());
+            expr(out, count);
+        }
+        StmtKind::Mirror(body) => write_body(out, body),
+        StmtKind::Alternate(bodies) => {
+            for body in bodies {
+                write_body(out, body);
+            }
+        }
+        StmtKind::Pattern(pattern, args) => {
+            let _ = write!(out, " {}", pattern.name());
+            for arg in args {
+                expr(out, arg);
+            }
+        }
+        // Each entry is a list of its key and value.
+        StmtKind::Meta(entries) => {
+            for entry in entries {
+                let _ = write!(out, " ({} ", entry.key);
+                match &entry.value {
+                    MetaValue::Str(s) => write_string(out, s),
+                    MetaValue::Number(n) => {
+                        let _ = write!(out, "{n}");
+                    }
+                }
+                out.push(')');
+            }
+        }
+        StmtKind::Note(parts) => {
+            for part in parts {
+                match part {
+                    NotePart::Text(text) => {
+                        out.push(' ');
+                        write_string(out, text);
+                    }
+                    NotePart::Expr(e) => expr(out, e),
+                }
+            }
+        }
+        StmtKind::Chart(Some(label)) => {
+            out.push(' ');
+            write_string(out, label);
+        }
+        StmtKind::Transfer(from, to, count) => {
+            let _ = write!(out, " {from} {to}");
+            expr(out, count);
+        }
+    }
+    out.push(')');
+}
+
+/// A block of statements as a list of them, after a space.
+fn write_body(out: &mut String, body: &[Stmt]) {
+    out.push_str(" (");
+    for (i, stmt) in body.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        write_stmt(out, stmt);
+    }
+    out.push(')');
+}
+
+/// What is left to write of an expression: a node, after a space unless it
+/// is the root, or the end of a list.
+enum Step<'a> {
+    Node(&'a ExprKind),
+    Close,
+}
+
+/// Writes `expr` with a stack of its own rather than by recursion, as its
+/// `Display` does, so even a very long chain cannot overflow.
+fn write_expr(out: &mut String, expr: &Expr) {
+    let mut steps = vec![Step::Node(expr.kind())];
+    let mut root = true;
+    while let Some(step) = steps.pop() {
+        let kind = match step {
+            Step::Node(kind) => kind,
+            Step::Close => {
+                out.push(')');
+                continue;
+            }
+        };
+        if !mem::take(&mut root) {
+            out.push(' ');
+        }
+        let _ = match kind {
+            ExprKind::Number(n) => write!(out, "(num {n})"),
+            ExprKind::Var(name) => write!(out, "(var {name})"),
+            ExprKind::StitchesTo(marker) => write!(out, "(stitches_to {marker})"),
+            ExprKind::Count(needle) => write!(out, "(count {needle})"),
+            ExprKind::Sizes(values) => {
+                out.push_str("(sizes");
+                for value in values {
+                    let _ = write!(out, " {value}");
+                }
+                out.push(')');
+                Ok(())
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                steps.extend([
+                    Step::Close,
+                    Step::Node(&expr.node(*rhs).kind),
+                    Step::Node(&expr.node(*lhs).kind),
+                ]);
+                write!(out, "({op}")
+            }
+            ExprKind::Call(name, args) => {
+                steps.push(Step::Close);
+                steps.extend(
+                    args.iter()
+                        .rev()
+                        .map(|&arg| Step::Node(&expr.node(arg).kind)),
+                );
+                write!(out, "(call {name}")
+            }
+        };
+    }
+}
+
+/// `text` in double quotes, escaped so that it reads back as itself.
+fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{{{:x}}}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,53 @@
+//! "Did you mean" suggestions for misspelt names.
+
+/// Edit distance between `a` and `b`, counted in characters: the fewest
+/// insertions, deletions, substitutions and swaps of neighbouring characters
+/// that turn one into the other, so `totla` is one edit from `total`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut before: Vec<usize> = Vec::new();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitute.min(prev[j + 1] + 1).min(cur[j] + 1);
+            if i > 0 && j > 0 && ca == b[j - 1] && a[i - 1] == cb {
+                cur[j + 1] = cur[j + 1].min(before[j - 1] + 1);
+            }
+        }
+        before = std::mem::replace(&mut prev, cur);
+    }
+    prev[b.len()]
+}
+
+/// The one or two candidates closest to `name`, closest first, counting only
+/// those close enough to be a likely typo: about one edit for every three
+/// characters. Case is ignored, so `Total` finds `total`.
+pub fn closest_matches<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    let threshold = (name.chars().count() / 3).max(1);
+    let lower = name.to_lowercase();
+    let mut close: Vec<_> = candidates
+        .into_iter()
+        .filter(|&c| c != name)
+        .map(|c| (edit_distance(&lower, &c.to_lowercase()), c))
+        .filter(|&(d, _)| d <= threshold)
+        .collect();
+    close.sort_unstable();
+    close.dedup();
+    close.into_iter().take(2).map(|(_, c)| c).collect()
+}
+
+/// `"; did you mean `x`?"` for the closest matches to `name`, or nothing if
+/// none are close, to go on the end of an error message.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    match closest_matches(name, candidates)[..] {
+        [] => String::new(),
+        [only] => format!("; did you mean `{only}`?"),
+        [first, second, ..] => format!("; did you mean `{first}` or `{second}`?"),
+    }
+}
@@ -0,0 +1,201 @@
+//! A summary of the piece a run knitted, as `--pattern-summary` prints it:
+//! how many rows it has, how many stitches each stretch of rows was worked
+//! over, the shaping, and how big it comes out. Where [`RunStats`] counts
+//! the work the interpreter did, this only looks at the [`Fabric`], so it
+//! reads the same however the program was written.
+//!
+//! Finished measurements come from the gauge of the last `gauge` statement,
+//! given as the stitches and rows in 10 cm. The width is the widest row
+//! over the stitches in 10 cm, all the way round for a piece knitted in the
+//! round, and the length every row, short rows too, over the rows in 10 cm.
+//! Both are rounded to the nearest millimetre, halves up. A yarn estimate
+//! takes a length of yarn for every stitch cast on or made by a stitch, and
+//! is rounded up to whole metres so there is never too little.
+//!
+//! [`RunStats`]: crate::RunStats
+
+use crate::interp::{Fabric, Gauge};
+use crate::plural;
+use std::fmt;
+
+/// What the completed rows of a fabric come to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternSummary {
+    /// Completed rows.
+    pub rows: usize,
+    /// The rows in runs that each start with the same number of stitches on
+    /// the needles, in order.
+    pub sections: Vec<Section>,
+    /// Stitches gained and lost to shaping.
+    pub increases: usize,
+    pub decreases: usize,
+    /// The most stitches on the needles before or after any row.
+    pub max_width: usize,
+    /// The gauge the piece was declared to be knitted at.
+    pub gauge: Option<Gauge>,
+    /// The finished size, if there is a gauge.
+    pub dimensions: Option<Dimensions>,
+    /// Metres of yarn the piece takes, if the yarn a stitch takes is known.
+    pub yarn_metres: Option<u64>,
+}
+
+/// Rows `first` to `last`, counting from 1, all started with `stitches` on
+/// the needles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub first: usize,
+    pub last: usize,
+    pub stitches: usize,
+}
+
+/// A finished size, in millimetres.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dimensions {
+    pub width_mm: u64,
+    pub length_mm: u64,
+}
+
+impl PatternSummary {
+    /// The summary of `fabric`, estimating the yarn from
+    /// `mm_per_stitch` millimetres of it a stitch, if given.
+    ///
+    /// ```
+    /// let stmts = knitlang::parse_src("gauge 20, 28; cast_on 10; k 70;").unwrap();
+    /// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+    /// interp.run(&stmts).unwrap();
+    /// let summary = knitlang::PatternSummary::new(interp.fabric(), Some(25));
+    /// let size = summary.dimensions.unwrap();
+    /// assert_eq!((size.width_mm, size.length_mm), (50, 25));
+    /// assert_eq!(summary.yarn_metres, Some(2));
+    /// ```
+    pub fn new(fabric: &Fabric, mm_per_stitch: Option<u32>) -> Self {
+        let rows = fabric.rows();
+        let mut sections: Vec<Section> = Vec::new();
+        let (mut increases, mut decreases, mut max_width) = (0, 0, 0);
+        // Stitches cast on or made, and those on the needles before a row.
+        let (mut made, mut live) = (0, 0);
+        for (r, row) in rows.iter().enumerate() {
+            let idle = fabric.indent(r) + fabric.unworked(r);
+            let before = idle + row.iter().map(|s| s.consumes()).sum::<usize>();
+            let after = idle + row.iter().map(|s| s.produces()).sum::<usize>();
+            made += before.saturating_sub(live) + (after - idle);
+            live = after;
+            max_width = max_width.max(before).max(after);
+            for stitch in row {
+                increases += stitch.produces().saturating_sub(stitch.consumes());
+                decreases += stitch.consumes().saturating_sub(stitch.produces());
+            }
+            match sections.last_mut() {
+                Some(section) if section.stitches == before => section.last = r + 1,
+                _ => sections.push(Section {
+                    first: r + 1,
+                    last: r + 1,
+                    stitches: before,
+                }),
+            }
+        }
+        let gauge = fabric.gauge();
+        let dimensions = gauge.map(|gauge| Dimensions {
+            width_mm: per_10cm(max_width, gauge.stitches),
+            length_mm: per_10cm(rows.len(), gauge.rows),
+        });
+        let yarn_metres = mm_per_stitch.map(|mm| (made as u64 * u64::from(mm)).div_ceil(1000));
+        Self {
+            rows: rows.len(),
+            sections,
+            increases,
+            decreases,
+            max_width,
+            gauge,
+            dimensions,
+            yarn_metres,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "rows": self.rows,
+            "sections": self
+                .sections
+                .iter()
+                .map(|section| serde_json::json!({
+                    "first": section.first,
+                    "last": section.last,
+                    "stitches": section.stitches,
+                }))
+                .collect::<Vec<_>>(),
+            "increases": self.increases,
+            "decreases": self.decreases,
+            "max_width": self.max_width,
+            "gauge": self.gauge.map(|gauge| serde_json::json!({
+                "stitches": gauge.stitches,
+                "rows": gauge.rows,
+            })),
+            "dimensions": self.dimensions.map(|size| serde_json::json!({
+                "width_mm": size.width_mm,
+                "length_mm": size.length_mm,
+            })),
+            "yarn_m": self.yarn_metres,
+        })
+    }
+}
+
+/// How long `n` stitches or rows measure, in millimetres, at `gauge` of
+/// them in 10 cm, rounded to the nearest millimetre with halves up.
+fn per_10cm(n: usize, gauge: u32) -> u64 {
+    let gauge = u64::from(gauge);
+    (n as u64 * 200 + gauge) / (2 * gauge)
+}
+
+/// `mm` as centimetres to one decimal place, such as `12.5 cm`.
+fn cm(mm: u64) -> String {
+    format!("{}.{} cm", mm / 10, mm % 10)
+}
+
+fn stitches(n: usize) -> String {
+    match n {
+        1 => "1 st".into(),
+        n => format!("{n} sts"),
+    }
+}
+
+/// The summary as a table, one line a figure and a line a section. Lines
+/// for the size and yarn are left out when they are not known.
+impl fmt::Display for PatternSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows:        {}", self.rows)?;
+        if self.sections.is_empty() {
+            writeln!(f, "sections:    none")?;
+        }
+        for (i, section) in self.sections.iter().enumerate() {
+            let label = if i == 0 { "sections:" } else { "" };
+            let rows = if section.first == section.last {
+                format!("row {}", section.first)
+            } else {
+                format!("rows {}–{}", section.first, section.last)
+            };
+            writeln!(f, "{label:<13}{rows}: {}", stitches(section.stitches))?;
+        }
+        writeln!(f, "increases:   {}", self.increases)?;
+        writeln!(f, "decreases:   {}", self.decreases)?;
+        writeln!(f, "max width:   {}", stitches(self.max_width))?;
+        if let (Some(gauge), Some(size)) = (self.gauge, self.dimensions) {
+            writeln!(
+                f,
+                "gauge:       {} and {} to 10 cm",
+                stitches(gauge.stitches as usize),
+                plural(gauge.rows as usize, "row")
+            )?;
+            writeln!(
+                f,
+                "finished:    {} wide, {} long",
+                cm(size.width_mm),
+                cm(size.length_mm)
+            )?;
+        }
+        if let Some(metres) = self.yarn_metres {
+            writeln!(f, "yarn:        {metres} m")?;
+        }
+        Ok(())
+    }
+}
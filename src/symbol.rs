@@ -0,0 +1,127 @@
+//! Interned identifiers. The lexer turns every name into a [`Symbol`], so the
+//! parser and interpreter copy and compare small integers instead of strings.
+
+use serde::{Serialize, Serializer};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::{LazyLock, Mutex};
+
+/// An identifier, standing for its name in the process-wide interner.
+/// Symbols are equal exactly when their names are, and order by name.
+///
+/// A symbol is valid, and the text [`Symbol::as_str`] gives for it kept, for
+/// the rest of the process: interning a name allocates it for good, even
+/// once every parse and interpreter that used it is gone. Names read from
+/// untrusted data that programs may never use, such as a saved session's,
+/// should be matched with [`Symbol::lookup`] rather than interned.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// Every name interned so far. Names are never freed; the programs a process
+/// parses only have so many, and keeping them lets [`Symbol::as_str`] hand
+/// out `'static` text.
+#[derive(Default)]
+struct Interner {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+}
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(Mutex::default);
+
+fn interner() -> std::sync::MutexGuard<'static, Interner> {
+    // The interner is never left half-updated, so a panic elsewhere while it
+    // was locked does not matter.
+    INTERNER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Symbol {
+    /// The symbol for `name`, adding it to the interner the first time.
+    pub fn intern(name: &str) -> Self {
+        let mut interner = interner();
+        if let Some(&symbol) = interner.ids.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(interner.names.len() as u32);
+        let name: &'static str = Box::leak(name.into());
+        interner.names.push(name);
+        interner.ids.insert(name, symbol);
+        symbol
+    }
+
+    /// The symbol for `name` if it has been interned, without adding it.
+    pub fn lookup(name: &str) -> Option<Self> {
+        interner().ids.get(name).copied()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner().names[self.0 as usize]
+    }
+}
+
+/// A map keyed by symbols, hashed by their numbers alone: a symbol is its
+/// name's index, so one multiplication spreads them well enough, and looking
+/// one up is far cheaper than with the default hasher.
+pub(crate) type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+#[derive(Default)]
+pub(crate) struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u32(u32::from(byte));
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (self.0 ^ u64::from(n)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self == other {
+            Ordering::Equal
+        } else {
+            self.as_str().cmp(other.as_str())
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Shows the name as a string literal, as `String` would, so token dumps and
+/// error messages read the same as before interning.
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
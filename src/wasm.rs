@@ -0,0 +1,59 @@
+//! JavaScript bindings for running programs in the browser, built with
+//! `--no-default-features --features wasm` for `wasm32-unknown-unknown`.
+//!
+//! Nothing here prints or panics: program output is captured and errors are
+//! returned to JavaScript as data.
+
+use crate::parser::DEFAULT_MAX_NESTING;
+use crate::{execute, line_col, Capture, Interpreter, KnitError};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// Runs `src` on a fresh interpreter and returns
+/// `{ output, errors, variables, chart }`: the text printed with `purl`, a list
+/// of errors (at most one, as the run stops at the first), the final variables
+/// by name, and the completed rows as arrays of stitch names.
+#[wasm_bindgen]
+pub fn run(src: &str) -> JsValue {
+    run_json(src)
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .unwrap_or(JsValue::NULL)
+}
+
+fn run_json(src: &str) -> serde_json::Value {
+    let (capture, buffer) = Capture::new(None);
+    let mut interp = Interpreter::with_output(Box::new(capture));
+    let errors = match execute(src, &mut interp, DEFAULT_MAX_NESTING) {
+        Ok(_) => Vec::new(),
+        Err(e) => vec![error_json(src, &e)],
+    };
+    let chart: Vec<Vec<String>> = interp
+        .fabric()
+        .rows()
+        .iter()
+        .map(|row| row.iter().map(|stitch| stitch.to_string()).collect())
+        .collect();
+    let output = String::from_utf8_lossy(&buffer.borrow()).into_owned();
+    json!({
+        "output": output,
+        "errors": errors,
+        "variables": interp.vars().collect::<BTreeMap<_, _>>(),
+        "chart": chart,
+    })
+}
+
+fn error_json(src: &str, error: &KnitError) -> serde_json::Value {
+    let span = error.span();
+    let (line, column) = line_col(src, span.start);
+    let (end_line, end_column) = line_col(src, span.end);
+    json!({
+        "kind": error.kind(),
+        "message": error.to_string(),
+        "line": line,
+        "column": column,
+        "end_line": end_line,
+        "end_column": end_column,
+    })
+}
@@ -0,0 +1,497 @@
+//! Written instructions for a [`Fabric`], in the abbreviations printed
+//! patterns use: `Row 1 (RS): *k2, p2; rep from * to end.` Each completed
+//! row becomes a line listing its stitches in the order they were worked,
+//! with identical neighbours counted together (`k12`) and a group repeated
+//! across the row written once as `*...; rep from *`. Identical rows one
+//! after another share a line, such as `Rows 3–8:`. Rows knitted flat say
+//! which side they are worked on, the right side (RS) or the wrong side (WS);
+//! rounds are `Rnd 1:` and `Rnds 3–8:`, and a line says where the work is
+//! joined to knit in the round or turned to knit back and forth. Stitches worked
+//! in a yarn from a `color` statement are followed by its name, as in
+//! `k2 MC, k2 CC`. A short row ends `w&t`, for the wrap and turn that stops
+//! it before the end of the needle, and stitches bound off part way through
+//! the piece are counted as `BO 6 sts`. A pattern graded across sizes is
+//! written once for all of them, as `Cast on 88 (96, 104) sts.`
+//!
+//! A pattern with a `meta` block starts with a header giving its title on a
+//! line of its own, then its author, yarn, needle and gauge, each labelled,
+//! and a blank line. Each `note` is a line of its own too, before the row
+//! that was being worked when it ran, or after the last row; notes from
+//! before anything was cast on come first.
+
+use crate::ast::Stitch;
+use crate::interp::{Fabric, PatternMeta, Yarn};
+use crate::plural;
+use std::fmt::Write;
+
+/// A stitch and the yarn it was worked in, as an index into
+/// [`Fabric::yarns`].
+type Cell = (Stitch, Option<usize>);
+
+/// The most stitches one repeated group may have. Repeats in printed
+/// patterns are rarely more than a few dozen stitches, and the limit keeps
+/// finding them linear in the length of a row.
+const MAX_REPEAT: usize = 64;
+
+/// The instructions for the completed rows of `fabric`, one line each,
+/// after its [`header`] and a line casting on the stitches of the first
+/// row. Stitches cast on between rows get a line of their own before the
+/// row that works them, as do stitches put on hold and back:
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 8; repeat 4 { k 2; p 2; }").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// assert_eq!(
+///     knitlang::written::instructions(interp.fabric()),
+///     "Cast on 8 sts.\nRows 1–2: *k2, p2; rep from * to end.\n"
+/// );
+/// ```
+pub fn instructions(fabric: &Fabric) -> String {
+    fabric.meta().map(header).unwrap_or_default() + &rows_of(fabric)
+}
+
+/// The header of `meta` that written instructions start with, ending in a
+/// blank line. Keys other than the title, author, yarn, needle and gauge
+/// are left out:
+///
+/// ```
+/// let src = "meta { needle = \"4mm\"; title = \"Winter Hat\"; }";
+/// let stmts = knitlang::parse_src(src).unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// let meta = interp.fabric().meta().unwrap();
+/// assert_eq!(knitlang::written::header(meta), "Winter Hat\nNeedle: 4mm\n\n");
+/// ```
+pub fn header(meta: &PatternMeta) -> String {
+    let mut out = String::new();
+    if let Some(title) = &meta.title {
+        let _ = writeln!(out, "{title}");
+    }
+    let labelled = [
+        ("Author", &meta.author),
+        ("Yarn", &meta.yarn),
+        ("Needle", &meta.needle),
+        ("Gauge", &meta.gauge),
+    ];
+    for (label, value) in labelled {
+        if let Some(value) = value {
+            let _ = writeln!(out, "{label}: {value}");
+        }
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// The instructions for the rows of `fabric` and its notes, without a
+/// header.
+fn rows_of(fabric: &Fabric) -> String {
+    let mut out = String::new();
+    for line in lines(fabric) {
+        let _ = writeln!(out, "{}", line.text());
+    }
+    out
+}
+
+/// One line of the instructions [`lines`] gives, without its newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    /// The text of a `note`.
+    Note(String),
+    /// A row or stretch of rows, or a step between them such as
+    /// `Cast on 8 sts.`
+    Step(String),
+}
+
+impl Line {
+    pub fn text(&self) -> &str {
+        match self {
+            Line::Note(text) | Line::Step(text) => text,
+        }
+    }
+}
+
+/// The lines of the instructions for the rows of `fabric` and its notes,
+/// as [`instructions`] writes them after the header, for writing in other
+/// forms:
+///
+/// ```
+/// use knitlang::written::Line;
+///
+/// let stmts = knitlang::parse_src("cast_on 4; note \"Rib\"; k 2; p 2;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// assert_eq!(
+///     knitlang::written::lines(interp.fabric()),
+///     [
+///         Line::Step("Cast on 4 sts.".into()),
+///         Line::Note("Rib".into()),
+///         Line::Step("Row 1 (RS): k2, p2.".into()),
+///     ]
+/// );
+/// ```
+pub fn lines(fabric: &Fabric) -> Vec<Line> {
+    let rows: Vec<Vec<Cell>> = (0..fabric.rows().len()).map(|r| cells(fabric, r)).collect();
+    // Stitches on the needles that a row does not work: those behind it,
+    // left by short rows before, and those a short row leaves unworked.
+    let idle = |r: usize| fabric.indent(r) + fabric.unworked(r);
+    let mut out = Vec::new();
+    // The notes that ran before each row, and in the preamble.
+    let notes = |row: Option<usize>| {
+        fabric
+            .notes()
+            .iter()
+            .filter(move |note| note.row == row)
+            .map(|note| Line::Note(note.text.clone()))
+    };
+    out.extend(notes(None));
+    // Live stitches on the needles before each row.
+    let mut live = 0;
+    let mut first = 0;
+    let round = |r: usize| fabric.side(r).is_none();
+    while first < rows.len() {
+        live = moved(&mut out, fabric, first, live);
+        let width = idle(first) + consumed(&rows[first]);
+        if width > live {
+            out.push(Line::Step(format!("Cast on {}.", stitches(width - live))));
+        }
+        if round(first) && (first == 0 || !round(first - 1)) {
+            out.push(Line::Step("Join to work in the round.".into()));
+        } else if !round(first) && first > 0 && round(first - 1) {
+            out.push(Line::Step("Work back and forth in rows.".into()));
+        }
+        out.extend(notes(Some(first)));
+        let mut last = first;
+        while last + 1 < rows.len()
+            && notes(Some(last + 1)).next().is_none()
+            && fabric.moved(last + 1) == (0, 0)
+            && rows[last + 1] == rows[first]
+            && fabric.indent(last + 1) == fabric.indent(first)
+            && fabric.unworked(last + 1) == fabric.unworked(first)
+            && round(last + 1) == round(first)
+            && idle(last + 1) + consumed(&rows[last + 1]) == idle(last) + produced(&rows[last])
+        {
+            last += 1;
+        }
+        let heading = match (fabric.side(first), first == last) {
+            (Some(side), true) => format!("Row {} ({}): ", first + 1, side.abbreviation()),
+            (Some(_), false) => format!("Rows {}–{}: ", first + 1, last + 1),
+            (None, true) => format!("Rnd {}: ", first + 1),
+            (None, false) => format!("Rnds {}–{}: ", first + 1, last + 1),
+        };
+        out.push(Line::Step(
+            heading + &stitches_of(fabric, first, &rows[first]),
+        ));
+        live = idle(last) + produced(&rows[last]);
+        first = last + 1;
+    }
+    moved(&mut out, fabric, rows.len(), 0);
+    if rows.is_empty() && fabric.width() > 0 {
+        out.push(Line::Step(format!("Cast on {}.", stitches(fabric.width()))));
+    }
+    out.extend(notes(Some(rows.len())));
+    out
+}
+
+/// The instructions for completed row `r` of `fabric` on their own, counting
+/// from 0, for following a pattern a row at a time:
+///
+/// ```
+/// let stmts = knitlang::parse_src("cast_on 4; k 4; p 2; k 2;").unwrap();
+/// let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+/// interp.run(&stmts).unwrap();
+/// assert_eq!(
+///     knitlang::written::row_instructions(interp.fabric(), 1),
+///     "Row 2 (WS): p2, k2."
+/// );
+/// ```
+pub fn row_instructions(fabric: &Fabric, r: usize) -> String {
+    let heading = match fabric.side(r) {
+        Some(side) => format!("Row {} ({}): ", r + 1, side.abbreviation()),
+        None => format!("Rnd {}: ", r + 1),
+    };
+    heading + &stitches_of(fabric, r, &cells(fabric, r))
+}
+
+/// Completed row `r` of `fabric`, with the yarn of each stitch.
+fn cells(fabric: &Fabric, r: usize) -> Vec<Cell> {
+    let row = &fabric.rows()[r];
+    let yarns = (0..row.len()).map(|c| fabric.yarn_at(r, c));
+    row.iter().copied().zip(yarns).collect()
+}
+
+/// What row `r` of `fabric`, made of `cells`, works, to the full stop.
+fn stitches_of(fabric: &Fabric, r: usize, cells: &[Cell]) -> String {
+    let mut out = row(cells, fabric.yarns());
+    if fabric.unworked(r) > 0 {
+        out.push_str(", w&t");
+    }
+    out.push('.');
+    out
+}
+
+/// Instructions for a pattern graded across `sizes`, given the fabric
+/// knitted in each size, in the same order. They start with a line naming
+/// the sizes, and where the sizes differ only in their numbers, each number
+/// that differs is written for the first size with those for the others in
+/// parentheses after it:
+///
+/// ```
+/// let knit = |size| {
+///     let src = "sizes S, M; cast_on 4 (6); repeat 2 (3) { k 4 (6); }";
+///     let stmts = knitlang::parse_src(src).unwrap();
+///     let mut interp = knitlang::Interpreter::with_output(Box::new(std::io::sink()));
+///     interp.set_size(Some(size));
+///     interp.run(&stmts).unwrap();
+///     interp
+/// };
+/// let (small, medium) = (knit("S"), knit("M"));
+/// assert_eq!(
+///     knitlang::written::graded(&["S", "M"], &[small.fabric(), medium.fabric()]),
+///     "Sizes: S (M).\nCast on 4 (6) sts.\nRows 1–2 (3): k4 (6).\n"
+/// );
+/// ```
+///
+/// Sizes whose instructions differ in more than their numbers, such as a
+/// shaping row one size works and another does not, are each written out
+/// in full under a heading of their own.
+pub fn graded(sizes: &[&str], fabrics: &[&Fabric]) -> String {
+    let texts: Vec<String> = fabrics.iter().map(|fabric| rows_of(fabric)).collect();
+    let split: Vec<Vec<(Vec<&str>, Vec<&str>)>> = texts
+        .iter()
+        .map(|text| text.lines().map(numbers).collect())
+        .collect();
+    let (first, others) = split.split_first().expect("at least one size");
+    let shared = others.iter().all(|lines| {
+        lines.len() == first.len()
+            && lines
+                .iter()
+                .zip(first)
+                .all(|((words, _), (first, _))| words == first)
+    });
+    // Every size runs the same `meta` block.
+    let mut out = fabrics[0].meta().map(header).unwrap_or_default();
+    if !shared {
+        for (i, (size, text)) in sizes.iter().zip(&texts).enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            let _ = writeln!(out, "Size {size}:");
+            out.push_str(text);
+        }
+        return out;
+    }
+    let _ = writeln!(out, "Sizes: {}.", alternatives(sizes));
+    for (line, (words, numbers)) in first.iter().enumerate() {
+        for (n, word) in words.iter().enumerate() {
+            out.push_str(word);
+            if n == numbers.len() {
+                break;
+            }
+            let values: Vec<&str> = split.iter().map(|lines| lines[line].1[n]).collect();
+            if values.iter().all(|&value| value == values[0]) {
+                out.push_str(values[0]);
+            } else {
+                out.push_str(&alternatives(&values));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `first (second, ...)`, or just `first` if there is one.
+fn alternatives(values: &[&str]) -> String {
+    match values {
+        [first] => first.to_string(),
+        [first, rest @ ..] => format!("{first} ({})", rest.join(", ")),
+        [] => String::new(),
+    }
+}
+
+/// `line` split around its numbers: the text before each number and after
+/// the last, and the numbers. Digits that are part of a stitch's name, as
+/// in `k2tog`, count as text.
+fn numbers(line: &str) -> (Vec<&str>, Vec<&str>) {
+    let mut words = Vec::new();
+    let mut numbers = Vec::new();
+    let mut from = 0;
+    let mut i = 0;
+    let bytes = line.as_bytes();
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        if bytes.get(i).is_some_and(u8::is_ascii_alphabetic) {
+            continue;
+        }
+        words.push(&line[from..start]);
+        numbers.push(&line[start..i]);
+        from = i;
+    }
+    words.push(&line[from..]);
+    (words, numbers)
+}
+
+/// Adds the lines for the stitches put on hold and back before row `r`,
+/// returning the `live` stitches on the needles before it once they have
+/// moved.
+fn moved(out: &mut Vec<Line>, fabric: &Fabric, r: usize, live: usize) -> usize {
+    let (off, on) = fabric.moved(r);
+    if off > 0 {
+        out.push(Line::Step(format!("Place {} on hold.", stitches(off))));
+    }
+    if on > 0 {
+        let text = format!("Return {} from hold to the needle.", stitches(on));
+        out.push(Line::Step(text));
+    }
+    live.saturating_sub(off) + on
+}
+
+/// Live stitches `row` works off the needle.
+fn consumed(row: &[Cell]) -> usize {
+    row.iter().map(|(s, _)| s.consumes()).sum()
+}
+
+/// Stitches `row` leaves for the next one.
+fn produced(row: &[Cell]) -> usize {
+    row.iter().map(|(s, _)| s.produces()).sum()
+}
+
+/// `1 st` or `n sts`.
+fn stitches(n: usize) -> String {
+    match n {
+        1 => "1 st".into(),
+        n => format!("{n} sts"),
+    }
+}
+
+/// One row's stitches, with the repeated group that leaves the fewest
+/// pieces to write written once, if that is fewer than listing every run of
+/// stitches. A group that starts or ends in the middle of a run counts as a
+/// piece more, so of ways with as many pieces the one that keeps runs whole
+/// wins, and listing the stitches splits none.
+fn row(stitches: &[Cell], yarns: &[Yarn]) -> String {
+    let mut best = None;
+    // Pieces with splits added, then splits, so ties go to fewer splits.
+    let mut fewest = (runs(stitches).len(), 0);
+    for size in 2..=MAX_REPEAT.min(stitches.len() / 2) {
+        let Some((from, length)) = repeated(stitches, size) else {
+            continue;
+        };
+        // The group may start anywhere in its first copy, which moves
+        // stitches between what comes before the repeats and what after.
+        for start in from..from + size {
+            let copies = (from + length - start) / size;
+            if copies < 2 {
+                break;
+            }
+            let end = start + size * copies;
+            let pieces = runs(&stitches[..start]).len()
+                + runs(&stitches[start..start + size]).len()
+                + runs(&stitches[end..]).len();
+            let splits = [start, end]
+                .into_iter()
+                .filter(|&at| at > 0 && at < stitches.len() && stitches[at - 1] == stitches[at])
+                .count();
+            if (pieces + splits, splits) < fewest {
+                fewest = (pieces + splits, splits);
+                best = Some((start, size, end));
+            }
+        }
+    }
+    let Some((start, size, end)) = best else {
+        return list(stitches, yarns);
+    };
+    let mut out = String::new();
+    if start > 0 {
+        out.push_str(&list(&stitches[..start], yarns));
+        out.push_str(", ");
+    }
+    let _ = write!(
+        out,
+        "*{}; rep from * to ",
+        list(&stitches[start..start + size], yarns)
+    );
+    let rest = list(&stitches[end..], yarns);
+    let _ = match consumed(&stitches[end..]) {
+        0 => write!(out, "end"),
+        1 => write!(out, "last st, {rest}"),
+        left => write!(out, "last {left} sts, {rest}"),
+    };
+    out
+}
+
+/// Where the longest stretch of `stitches` made of one group of `size`
+/// stitches over and over starts, and how long it is, if it holds at least
+/// two whole copies of the group.
+fn repeated(stitches: &[Cell], size: usize) -> Option<(usize, usize)> {
+    // A stretch repeats with period `size` exactly where every stitch in it
+    // matches the one `size` further on, so the longest is found in one pass.
+    let mut longest = (0, 0);
+    let mut from = 0;
+    for i in 0..stitches.len() - size {
+        if stitches[i] != stitches[i + size] {
+            from = i + 1;
+        } else if i + 1 - from > longest.1 {
+            longest = (from, i + 1 - from);
+        }
+    }
+    let (start, matching) = longest;
+    (matching >= size).then_some((start, matching + size))
+}
+
+/// The abbreviation of one `stitch`: a cable in capitals, as `C4F`, and a
+/// bound-off stitch as `BO`.
+pub(crate) fn abbreviation(stitch: Stitch) -> String {
+    match stitch {
+        Stitch::Cable(..) => stitch.to_string().to_uppercase(),
+        Stitch::BindOff => "BO".to_string(),
+        stitch => stitch.to_string(),
+    }
+}
+
+/// Runs of the same stitch in the same yarn in `stitches`, in order, with
+/// their lengths.
+fn runs(stitches: &[Cell]) -> Vec<(Cell, usize)> {
+    let mut runs: Vec<(Cell, usize)> = Vec::new();
+    for &stitch in stitches {
+        match runs.last_mut() {
+            Some((last, n)) if *last == stitch => *n += 1,
+            _ => runs.push((stitch, 1)),
+        }
+    }
+    runs
+}
+
+/// `stitches` as runs such as `k2, p2`, each followed by the name of its
+/// yarn if it has one.
+fn list(stitches: &[Cell], yarns: &[Yarn]) -> String {
+    let runs: Vec<String> = runs(stitches)
+        .into_iter()
+        .map(|((stitch, yarn), n)| {
+            // Cables are written in capitals, once for every cable, as `C4F`
+            // stands for the whole crossing, and decreases, yarn overs and
+            // increases once each too.
+            let run = match stitch {
+                Stitch::Cable(..) | Stitch::K2tog | Stitch::Ssk | Stitch::YarnOver | Stitch::M1 => {
+                    vec![abbreviation(stitch); n].join(", ")
+                }
+                Stitch::Knit | Stitch::Purl => format!("{stitch}{n}"),
+                Stitch::BindOff => format!("BO {}", plural(n, "st")),
+            };
+            match yarn {
+                Some(yarn) => format!("{run} {}", yarns[yarn].name),
+                None => run,
+            }
+        })
+        .collect();
+    runs.join(", ")
+}
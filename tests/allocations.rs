@@ -0,0 +1,85 @@
+//! Counts heap allocations, so it needs a test binary of its own for the
+//! global allocator.
+
+use knitlang::{run_source, RunOptions};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+/// Bytes allocated and not yet freed, and the most there have been since
+/// the last reset.
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The counters are global, so tests take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Allocations made while running `repeat iterations` of an assignment loop.
+fn allocations_running(iterations: u32) -> usize {
+    let src = format!("cast_on x = 0; repeat {iterations} {{ knit x = x + 1; knit y = x; }}");
+    let stmts = knitlang::parse_src(&src).unwrap();
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    interp.run(&stmts).unwrap();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+#[test]
+fn assignments_do_not_allocate_per_iteration() {
+    let _serial = SERIAL.lock().unwrap();
+    let few = allocations_running(10);
+    let many = allocations_running(100_000);
+    // Only the first assignment to each name grows the variable map.
+    assert!(
+        many - few < 10,
+        "{few} allocations for 10 iterations, {many} for 100000"
+    );
+}
+
+/// The most memory running `src` had allocated at once, beyond what was
+/// already allocated beforehand.
+fn peak_running(src: &str, streaming: bool) -> usize {
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let options = RunOptions {
+        streaming,
+        output: Some(Box::new(io::sink())),
+        ..RunOptions::default()
+    };
+    run_source(src, options).unwrap();
+    PEAK.load(Ordering::Relaxed) - before
+}
+
+#[test]
+fn streaming_keeps_memory_flat() {
+    let _serial = SERIAL.lock().unwrap();
+    let src = "cast_on x = 1;\nknit x = x * 3 - 2;\n".repeat(100_000);
+    let batch = peak_running(&src, false);
+    let streaming = peak_running(&src, true);
+    // Both hold a copy of the source; only batch mode also holds the whole
+    // syntax tree.
+    assert!(
+        streaming * 8 < batch,
+        "peak of {streaming} bytes streaming, {batch} in batch mode"
+    );
+}
@@ -0,0 +1,2632 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn knitlang(args: &[&str], stdin: &str) -> Output {
+    knitlang_in(Path::new("."), args, stdin)
+}
+
+fn knitlang_in(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    knitlang_with_path(dir, None, args, stdin)
+}
+
+/// Runs knitlang in `dir` with `KNIT_PATH` set to `knit_path`, or unset.
+fn knitlang_with_path(dir: &Path, knit_path: Option<&str>, args: &[&str], stdin: &str) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_knitlang_v2"));
+    match knit_path {
+        Some(value) => command.env("KNIT_PATH", value),
+        None => command.env_remove("KNIT_PATH"),
+    };
+    command.current_dir(dir).args(args);
+    output(&mut command, stdin)
+}
+
+/// Runs knitlang with `debug_panic()`, which only exists when the test
+/// build asks for it, to check how internal errors are reported.
+fn knitlang_panicking(args: &[&str], stdin: &str) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_knitlang_v2"));
+    command.env("KNITLANG_DEBUG_PANIC", "1").args(args);
+    output(&mut command, stdin)
+}
+
+/// Runs `command` with `stdin` as its input and what it prints collected.
+fn output(command: &mut Command, stdin: &str) -> Output {
+    // Charts fall back to ASCII in terminals that cannot show Unicode.
+    command.env("LC_ALL", "C.UTF-8").env_remove("TERM");
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn knitlang");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// A fresh, empty directory under the system temp dir, unique to `name`.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("knitlang-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn piped_stdin_prints_only_program_output() {
+    let out = knitlang(&[], "cast_on x = 2;\nrepeat 2 {\n  purl x;\n}\n");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "2\n2\n");
+    assert_eq!(stderr(&out), "");
+}
+
+#[test]
+fn stdin_flag_shares_one_interpreter() {
+    let out = knitlang(&["--stdin"], "cast_on x = 3;\nknit x = x * 7;\npurl x;\n");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "21\n");
+}
+
+#[test]
+fn stdin_failure_sets_exit_code_and_keeps_going() {
+    let out = knitlang(&["--stdin"], "purl 1;\npurl @;\npurl 2;\n");
+    assert!(!out.status.success());
+    assert_eq!(stdout(&out), "1\n2\n");
+    assert!(stderr(&out).contains("Unexpected character"));
+}
+
+#[test]
+fn stdin_errors_give_their_line_in_the_whole_input() {
+    let src = "cast_on 4;\nrepeat 2 {\n  k 4;\n}\npurl y;\n\n\n\n\n\npurl z;\n";
+    let out = knitlang(&["--stdin"], src);
+    assert_eq!(
+        stderr(&out),
+        "error[K0204]: undefined variable `y`\n \
+         --> <stdin>:5:6\n  \
+         |\n\
+         5 | purl y;\n  \
+         |      ^\n\
+         error[K0204]: undefined variable `z`\n  \
+         --> <stdin>:11:6\n   \
+         |\n\
+         11 | purl z;\n   \
+         |      ^\n"
+    );
+    // A pipe is read the same way without the flag.
+    assert_eq!(stderr(&knitlang(&[], src)), stderr(&out));
+}
+
+#[test]
+fn stdin_reports_unterminated_block() {
+    let out = knitlang(&["--stdin"], "repeat 2 {\n  purl 1;\n");
+    assert!(!out.status.success());
+    assert_eq!(stdout(&out), "");
+}
+
+#[test]
+fn parse_errors_point_at_the_offending_token() {
+    let out = knitlang(&["--stdin"], "repeat 2 {\n\tpurl repeat;\n}\n");
+    assert_eq!(
+        stderr(&out),
+        "error[K0105]: Unexpected token in term: `repeat`\n \
+         --> <stdin>:2:7\n  \
+         |\n\
+         2 |     purl repeat;\n  \
+         |          ^~~~~~\n"
+    );
+}
+
+#[test]
+fn unclosed_repeat_points_back_at_its_brace() {
+    let dir = temp_dir("unclosed-repeat");
+    fs::write(dir.join("open.knit"), "repeat 2 {\n  k 1;\n\n  p 1;\n").unwrap();
+    let out = knitlang_in(&dir, &["open.knit"], "");
+    assert_eq!(
+        stderr(&out),
+        "error[K0107]: Expected '}' after repeat body, found end of input\n \
+         --> open.knit:4:7\n  \
+         |\n\
+         1 | repeat 2 {\n  \
+         |          - repeat opened here\n\
+         ...\n\
+         4 |   p 1;\n  \
+         |       ^\n"
+    );
+}
+
+#[test]
+fn spans_ending_in_a_multibyte_character_render() {
+    let dir = temp_dir("multibyte-span");
+    fs::write(dir.join("wide.knit"), "k 1;\npurl 日;\n").unwrap();
+    for command in ["run", "check"] {
+        let out = knitlang_in(&dir, &[command, "wide.knit"], "");
+        assert_eq!(out.status.code(), Some(2), "{command}");
+        assert!(
+            stderr(&out).starts_with(
+                "error[K0102]: Unexpected character: 日\n \
+                 --> wide.knit:2:6\n  \
+                 |\n\
+                 2 | purl 日;\n  \
+                 |      ^\n"
+            ),
+            "{command}: {}",
+            stderr(&out)
+        );
+    }
+}
+
+#[test]
+fn multi_line_spans_underline_every_line() {
+    let src = "bind_off;\nrepeat 2 {\n  k 1;\n  p 1;\n  k 1;\n}\n";
+    let out = knitlang(&["check"], src);
+    assert_eq!(
+        stderr(&out),
+        "warning[K0402]: unreachable statement after bind_off\n \
+         --> <stdin>:2:1\n  \
+         |\n\
+         1 | bind_off;\n  \
+         | --------- the program ends here\n\
+         2 | repeat 2 {\n  \
+         | ^~~~~~~~~~\n\
+         3 |   k 1;\n  \
+         |   ~~~~\n\
+         4 |   p 1;\n  \
+         |   ~~~~\n\
+         ...\n\
+         6 | }\n  \
+         | ~\n\
+         checked 1 file: 0 errors, 1 warning\n"
+    );
+}
+
+#[test]
+fn tokens_dump_matches_golden_output() {
+    let out = knitlang(&["--tokens", "tests/golden/tokens.knit"], "");
+    assert_eq!(stdout(&out), include_str!("golden/tokens.out"));
+    // The stray `@` is reported in-stream, so the dump still fails overall.
+    assert!(!out.status.success());
+}
+
+#[test]
+fn tokens_dump_reads_stdin() {
+    let out = knitlang(&["--tokens"], "purl 42;");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "1:1  Purl\n1:6  Number(42)\n1:8  Semicolon\n");
+}
+
+const WARNS: &str = "cast_on unused = 1;\npurl 2;\nbind_off;\npurl 3;\n";
+
+#[test]
+fn check_never_executes_and_reports_warnings() {
+    let out = knitlang(&["check"], WARNS);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "");
+    let err = stderr(&out);
+    assert!(err.contains("warning[K0401]: unused variable `unused`"));
+    assert!(err.contains("warning[K0402]: unreachable statement after bind_off"));
+    assert!(err.ends_with("checked 1 file: 0 errors, 2 warnings\n"));
+}
+
+#[test]
+fn check_promotes_warnings_with_w_error() {
+    let out = knitlang(&["check", "-W", "error"], WARNS);
+    assert!(!out.status.success());
+    assert!(stderr(&out).ends_with("checked 1 file: 2 errors, 0 warnings\n"));
+}
+
+#[test]
+fn check_summarizes_multiple_files() {
+    let out = knitlang(&["check", "example.kl", "tests/golden/tokens.knit"], "");
+    assert!(!out.status.success());
+    assert!(stderr(&out).ends_with("checked 2 files: 1 error, 0 warnings\n"));
+}
+
+#[test]
+fn eval_flags_share_variables_in_order() {
+    let out = knitlang(&["-e", "cast_on x = 3;", "--eval", "purl x * 7;"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "21\n");
+}
+
+#[test]
+fn eval_errors_are_relative_to_the_eval_source() {
+    let out = knitlang(&["-e", "purl 1;", "-e", "purl @;"], "");
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("--> <eval>:2:6\n"));
+}
+
+#[test]
+fn eval_conflicts_with_file_argument() {
+    let out = knitlang(&["-e", "purl 1;", "example.kl"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+}
+
+#[test]
+fn dash_reads_one_whole_program_from_stdin() {
+    let out = knitlang(&["-"], "repeat 2\n{\n  purl 4;\n}\n");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "4\n4\n");
+}
+
+#[test]
+fn dash_labels_diagnostics_as_stdin() {
+    let out = knitlang(&["check", "-"], "cast_on x = 1;\n");
+    assert!(out.status.success());
+    assert!(stderr(&out).contains("--> <stdin>:1:1\n"));
+}
+
+fn examples_dir(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::create_dir(dir.join("examples")).unwrap();
+    fs::write(dir.join("examples/hello.knit"), "purl 1;\n").unwrap();
+    fs::write(dir.join("examples/ribbing.knit"), "purl 2;\n").unwrap();
+    fs::write(dir.join("examples/notes.txt"), "not an example").unwrap();
+    dir
+}
+
+#[test]
+fn example_list_enumerates_knit_files() {
+    let dir = examples_dir("example-list");
+    for args in [&["examples", "list"][..], &["examples"][..]] {
+        let out = knitlang_in(&dir, args, "");
+        assert!(out.status.success());
+        assert_eq!(stdout(&out), "cable_panel\nhello\nribbing\nswatch\n");
+    }
+}
+
+#[test]
+fn example_runs_by_name() {
+    let dir = examples_dir("example-run");
+    let out = knitlang_in(&dir, &["examples", "ribbing"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "2\n");
+}
+
+#[test]
+fn missing_example_lists_names_and_suggests() {
+    let dir = examples_dir("example-missing");
+    let out = knitlang_in(&dir, &["examples", "helo"], "");
+    assert!(!out.status.success());
+    assert_eq!(
+        stderr(&out),
+        "error: no example named `helo` in examples\n\
+         available examples: cable_panel, hello, ribbing, swatch\n\
+         did you mean `hello`?\n"
+    );
+}
+
+#[test]
+fn missing_example_without_close_match_has_no_suggestion() {
+    let dir = examples_dir("example-far");
+    let out = knitlang_in(&dir, &["examples", "cables"], "");
+    assert!(!out.status.success());
+    assert!(!stderr(&out).contains("did you mean"));
+}
+
+#[test]
+fn embedded_examples_work_from_any_directory() {
+    let dir = temp_dir("example-embedded");
+    let out = knitlang_in(&dir, &["examples", "hello"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "1\n2\n3\n");
+    let out = knitlang_in(&dir, &["examples", "list"], "");
+    assert_eq!(stdout(&out), "cable_panel\nhello\nswatch\n");
+}
+
+#[test]
+fn embedded_examples_take_precedence_over_disk() {
+    // examples_dir writes its own examples/hello.knit printing 1; the embedded
+    // one prints 1, 2, 3 and must win, while ribbing only exists on disk.
+    let dir = examples_dir("example-precedence");
+    let out = knitlang_in(&dir, &["examples", "hello"], "");
+    assert_eq!(stdout(&out), "1\n2\n3\n");
+    let out = knitlang_in(&dir, &["examples", "ribbing"], "");
+    assert_eq!(stdout(&out), "2\n");
+}
+
+#[test]
+fn example_names_cannot_traverse_paths() {
+    let dir = examples_dir("example-traversal");
+    fs::write(dir.join("secret.knit"), "purl 9;\n").unwrap();
+    for name in ["../secret", "sub/hello", "..", "a\\b"] {
+        let out = knitlang_in(&dir, &["examples", name], "");
+        assert!(!out.status.success());
+        assert_eq!(stdout(&out), "");
+        assert!(stderr(&out).contains("invalid example name"), "{name}");
+    }
+}
+
+fn pattern_files(name: &str, body: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("setup.knit"), "cast_on rows = 2;\n").unwrap();
+    fs::write(dir.join("body.knit"), body).unwrap();
+    fs::write(dir.join("edging.knit"), "purl rows + 100;\n").unwrap();
+    dir
+}
+
+#[test]
+fn multiple_files_share_one_interpreter() {
+    let dir = pattern_files("multi-shared", "knit rows = rows * 5;\npurl rows;\n");
+    let out = knitlang_in(&dir, &["setup.knit", "body.knit", "edging.knit"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "10\n110\n");
+}
+
+#[test]
+fn bind_off_in_an_earlier_file_stops_the_whole_run() {
+    let dir = pattern_files("multi-bind-off", "purl rows;\nbind_off;\n");
+    let out = knitlang_in(&dir, &["setup.knit", "body.knit", "edging.knit"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "2\n");
+}
+
+#[test]
+fn parse_error_in_one_file_runs_nothing_and_names_the_file() {
+    let dir = pattern_files("multi-parse-error", "purl rows\n");
+    let out = knitlang_in(&dir, &["setup.knit", "body.knit", "edging.knit"], "");
+    assert!(!out.status.success());
+    assert_eq!(stdout(&out), "");
+    assert!(stderr(&out).contains("--> body.knit:1:10\n"));
+}
+
+#[test]
+fn exit_code_is_zero_on_success() {
+    let out = knitlang(&["-e", "purl 1;"], "");
+    assert_eq!(out.status.code(), Some(0));
+}
+
+#[test]
+fn exit_code_for_runtime_errors_is_one() {
+    let out = knitlang(&["-e", "purl 1;", "-e", "purl 1 / 0;", "-e", "purl 2;"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stdout(&out), "1\n");
+    assert!(stderr(&out).starts_with("error[K0301]: division by zero\n --> <eval>:2:10\n"));
+}
+
+#[test]
+fn exit_code_for_lex_and_parse_errors_is_two() {
+    assert_eq!(knitlang(&["-e", "purl @;"], "").status.code(), Some(2));
+    assert_eq!(knitlang(&["-e", "purl 1"], "").status.code(), Some(2));
+    assert_eq!(knitlang(&["check", "-"], "purl 1").status.code(), Some(2));
+}
+
+#[test]
+fn exit_code_for_io_and_usage_errors_is_three() {
+    let out = knitlang(&["does/not/exist.knit"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).starts_with("error: could not find does/not/exist.knit (searched ."));
+    assert_eq!(knitlang(&["--no-such-flag"], "").status.code(), Some(3));
+    assert_eq!(knitlang(&["examples", "nope"], "").status.code(), Some(3));
+}
+
+#[test]
+fn exit_code_reflects_failures_in_pipe_mode() {
+    assert_eq!(
+        knitlang(&["--stdin"], "purl 1 / 0;\npurl 1;\n")
+            .status
+            .code(),
+        Some(1)
+    );
+    assert_eq!(knitlang(&["--stdin"], "purl 1\n").status.code(), Some(2));
+}
+
+#[test]
+fn output_flag_writes_program_output_to_a_file() {
+    let dir = temp_dir("output-file");
+    let out = knitlang_in(&dir, &["-e", "purl 1;", "--output", "out.txt"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "");
+    assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "1\n");
+}
+
+#[test]
+fn output_flag_keeps_partial_output_and_diagnostics_on_stderr() {
+    let dir = temp_dir("output-partial");
+    fs::write(dir.join("out.txt"), "stale contents\n").unwrap();
+    let out = knitlang_in(&dir, &["-e", "purl 1; purl 1 / 0;", "-o", "out.txt"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "1\n");
+    assert!(stderr(&out).starts_with("error[K0301]: division by zero"));
+}
+
+#[test]
+fn output_dash_means_stdout() {
+    let out = knitlang(&["-e", "purl 1;", "--output", "-"], "");
+    assert_eq!(stdout(&out), "1\n");
+}
+
+#[test]
+fn output_into_missing_directory_is_an_io_error() {
+    let dir = temp_dir("output-missing-dir");
+    let out = knitlang_in(&dir, &["-e", "purl 1;", "-o", "nope/out.txt"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).starts_with("error: could not create nope/out.txt: "));
+}
+
+#[test]
+fn quiet_stdout_is_identical_to_default() {
+    let normal = knitlang(&["example.kl"], "");
+    let quiet = knitlang(&["--quiet", "example.kl"], "");
+    assert!(quiet.status.success());
+    assert_eq!(quiet.stdout, normal.stdout);
+    assert_eq!(stderr(&quiet), "");
+}
+
+#[test]
+fn quiet_suppresses_warnings_and_summaries_but_not_errors() {
+    let out = knitlang(&["-q", "check"], WARNS);
+    assert!(out.status.success());
+    assert_eq!(stderr(&out), "");
+    let out = knitlang(&["-q", "-e", "purl 1 / 0;"], "");
+    assert!(stderr(&out).starts_with("error[K0301]: division by zero"));
+}
+
+#[test]
+fn verbose_reports_progress_on_stderr() {
+    let out = knitlang(&["-v", "example.kl"], "");
+    assert_eq!(stdout(&out), "1\n2\n3\n");
+    let err = stderr(&out);
+    assert!(err.contains("reading example.kl\n"));
+    assert!(err.contains("parsed 5 statements from example.kl\n"));
+    assert!(err.contains("executed 9 statements in "));
+    assert!(!err.contains("took"));
+    assert!(stderr(&knitlang(&["-vv", "example.kl"], "")).contains("parsing example.kl took "));
+}
+
+#[test]
+fn quiet_and_verbose_conflict() {
+    assert_eq!(
+        knitlang(&["-q", "-v", "example.kl"], "").status.code(),
+        Some(3)
+    );
+}
+
+#[test]
+fn help_prints_usage_to_stdout_and_succeeds() {
+    for flag in ["--help", "-h"] {
+        let out = knitlang(&[flag], "");
+        assert_eq!(out.status.code(), Some(0));
+        assert!(stdout(&out).contains("Usage: "), "{flag}");
+        assert!(stdout(&out).contains("examples"), "{flag}");
+        assert_eq!(stderr(&out), "", "{flag}");
+    }
+}
+
+#[test]
+fn version_prints_the_package_version() {
+    let out = knitlang(&["--version"], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        stdout(&out),
+        format!("knitlang_v2 {}\n", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn unknown_flag_is_a_usage_error() {
+    let out = knitlang(&["--bogus"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+    assert!(stderr(&out).contains("unexpected argument '--bogus'"));
+    assert!(!stderr(&out).contains("panicked"));
+}
+
+#[test]
+fn conflicting_flags_are_a_usage_error() {
+    let out = knitlang(&["-e", "purl 1;", "--stdin"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("cannot be used with"));
+    assert!(!stderr(&out).contains("panicked"));
+}
+
+#[test]
+fn fmt_rewrites_files_in_canonical_style() {
+    let dir = temp_dir("fmt-canonical");
+    let messy = "cast_on   x=1;knit x = x+2*3;\n\n\trepeat x{purl x;repeat 2 {} bind_off ;}\n";
+    fs::write(dir.join("messy.knit"), messy).unwrap();
+    let out = knitlang_in(&dir, &["fmt", "messy.knit"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "");
+    assert_eq!(
+        fs::read_to_string(dir.join("messy.knit")).unwrap(),
+        "cast_on x = 1;\n\
+         knit x = x + 2 * 3;\n\
+         \n\
+         repeat x {\n    \
+             purl x;\n    \
+             repeat 2 {\n    \
+             }\n    \
+             bind_off;\n\
+         }\n"
+    );
+    assert_eq!(
+        fs::read_dir(&dir).unwrap().count(),
+        1,
+        "temp file left behind"
+    );
+}
+
+#[test]
+fn fmt_keeps_comments() {
+    let dir = temp_dir("fmt-comments");
+    let src = "// rib\nrepeat 2{k 1;   // knit\n// purl next\np 1;}\n\n\n// the end";
+    fs::write(dir.join("commented.knit"), src).unwrap();
+    let out = knitlang_in(&dir, &["fmt", "commented.knit"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        fs::read_to_string(dir.join("commented.knit")).unwrap(),
+        "// rib\n\
+         repeat 2 {\n    \
+             k 1; // knit\n    \
+             // purl next\n    \
+             p 1;\n\
+         }\n\
+         \n\
+         // the end\n"
+    );
+}
+
+#[test]
+fn fmt_keeps_one_blank_line_between_sections() {
+    let out = knitlang(&["fmt", "-"], include_str!("golden/sections.knit"));
+    assert!(out.status.success(), "{}", stderr(&out));
+    let formatted = include_str!("golden/sections_fmt.knit");
+    assert_eq!(stdout(&out), formatted);
+    assert_eq!(stdout(&knitlang(&["fmt", "-"], formatted)), formatted);
+}
+
+#[test]
+fn fmt_is_idempotent_over_the_examples() {
+    let dir = temp_dir("fmt-idempotent");
+    let mut names = Vec::new();
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        fs::copy(&path, dir.join(&name)).unwrap();
+        names.push(name);
+    }
+    let args: Vec<&str> = ["fmt"]
+        .into_iter()
+        .chain(names.iter().map(String::as_str))
+        .collect();
+    assert!(knitlang_in(&dir, &args, "").status.success());
+    let once: Vec<Vec<u8>> = names
+        .iter()
+        .map(|n| fs::read(dir.join(n)).unwrap())
+        .collect();
+    assert!(knitlang_in(&dir, &args, "").status.success());
+    let twice: Vec<Vec<u8>> = names
+        .iter()
+        .map(|n| fs::read(dir.join(n)).unwrap())
+        .collect();
+    assert_eq!(once, twice);
+}
+
+#[test]
+fn fmt_refuses_to_touch_files_that_do_not_parse() {
+    let dir = temp_dir("fmt-parse-error");
+    fs::write(dir.join("broken.knit"), "purl   1\n").unwrap();
+    let out = knitlang_in(&dir, &["fmt", "broken.knit"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        fs::read_to_string(dir.join("broken.knit")).unwrap(),
+        "purl   1\n"
+    );
+}
+
+#[test]
+fn fmt_dash_formats_stdin_to_stdout() {
+    let out = knitlang(&["fmt", "-"], "purl 1+2;");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "purl 1 + 2;\n");
+}
+
+#[test]
+fn bare_file_is_an_alias_for_run() {
+    let bare = knitlang(&["example.kl"], "");
+    let run = knitlang(&["run", "example.kl"], "");
+    assert!(run.status.success());
+    assert_eq!(stdout(&run), "1\n2\n3\n");
+    assert_eq!(bare.stdout, run.stdout);
+}
+
+#[test]
+fn repl_subcommand_prompts_even_when_piped() {
+    let out = knitlang(&["repl"], "purl 4;\nexit\n");
+    assert!(out.status.success());
+    assert!(stderr(&out).contains("knit> "));
+    assert_eq!(stdout(&out), "4\n");
+}
+
+#[test]
+fn repl_prints_bare_expressions_and_waits_for_closing_braces() {
+    let input = "cast_on x = 4;\nrepeat 2 {\nknit x = x * 3;\n}\nx + 1\npurl x +;\nx\nexit\n";
+    let out = knitlang(&["repl", "-q"], input);
+    assert!(out.status.success());
+    // The failed statement leaves the variable as it was.
+    assert_eq!(stdout(&out), "37\n36\n");
+    let stderr = stderr(&out);
+    assert!(
+        stderr.starts_with("knit> knit> ...> ...> knit> knit> error"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Unexpected token in term"));
+    assert!(stderr.ends_with("knit> knit> "), "{stderr}");
+}
+
+#[test]
+fn repl_help_lists_commands_and_stitch_patterns() {
+    let out = knitlang(&["repl", "-q"], ":help\ncast_on 5;\nrib(1, 1, 2);\n:vars\n");
+    assert!(out.status.success());
+    let stdout = stdout(&out);
+    assert!(stdout.contains("  :load <file> "), "{stdout}");
+    assert!(
+        stdout.contains("  rib(k, p, rows);      k knits and p purls"),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("fabric: 2 rows, 5 stitches on the needles\n"));
+}
+
+#[test]
+fn knit_along_refuses_without_a_terminal() {
+    let dir = temp_dir("knit-along");
+    fs::write(dir.join("hat.knit"), "cast_on 4; k 8;\n").unwrap();
+    let out = knitlang_in(&dir, &["run", "--knit-along", "hat.knit"], "\n\n");
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(
+        stderr(&out),
+        "error: --knit-along waits for commands from a terminal, but stdin is not one\n"
+    );
+    assert_eq!(stdout(&out), "");
+    assert!(!dir.join("hat.session").exists());
+    let out = knitlang_in(&dir, &["run", "--along-chart", "hat.knit"], "");
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn subcommands_own_their_flags() {
+    let out = knitlang(&["fmt", "--tokens", "example.kl"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("unexpected argument '--tokens'"));
+    let out = knitlang(&["--tokens", "check", "example.kl"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("put them after it"));
+}
+
+#[test]
+fn global_flags_work_before_and_after_subcommands() {
+    for args in [&["-q", "check"][..], &["check", "-q"][..]] {
+        let out = knitlang(args, WARNS);
+        assert!(out.status.success());
+        assert_eq!(stderr(&out), "");
+    }
+}
+
+#[test]
+fn check_reports_reads_of_variables_never_assigned() {
+    let src = "cast_on 4;\nrepeat 0 {\n  k never;\n}\n";
+    let out = knitlang(&["check", "-"], src);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(stdout(&out), "");
+    let err = stderr(&out);
+    assert!(
+        err.starts_with("error[K0204]: undefined variable `never`\n --> <stdin>:3:5\n"),
+        "{err}"
+    );
+    assert!(
+        err.ends_with("checked 1 file: 1 error, 0 warnings\n"),
+        "{err}"
+    );
+}
+
+#[test]
+fn check_accepts_eval_sources() {
+    let out = knitlang(&["check", "-e", "purl 1;", "-e", "purl @;"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("--> <eval>:2:6"));
+}
+
+/// The diagnostics `check` and `run` print for `args` in `dir`, without the
+/// summary `check` ends with, asserting they are the same.
+fn check_agrees_with_run(dir: &Path, args: &[&str]) -> String {
+    let checked = knitlang_in(dir, &[&["check"], args].concat(), "");
+    let ran = knitlang_in(dir, &[&["run"], args].concat(), "");
+    let checked = stderr(&checked);
+    let (diagnostics, summary) = checked
+        .trim_end()
+        .rsplit_once('\n')
+        .unwrap_or(("", &checked));
+    assert!(summary.starts_with("checked "), "{checked}");
+    assert_eq!(diagnostics.trim_end(), stderr(&ran).trim_end(), "{args:?}");
+    checked
+}
+
+#[test]
+fn check_reports_what_run_reports_before_running() {
+    let dir = temp_dir("check-like-run");
+    let err = check_agrees_with_run(&dir, &["-e", "purl x;"]);
+    assert!(
+        err.starts_with("error[K0204]: undefined variable `x`\n"),
+        "{err}"
+    );
+
+    let err = check_agrees_with_run(&dir, &["-e", "use \"nonexistent.knit\";"]);
+    assert!(
+        err.starts_with("error[K0111]: Cannot import `nonexistent.knit`"),
+        "{err}"
+    );
+    assert!(
+        err.ends_with("checked 1 file: 1 error, 0 warnings\n"),
+        "{err}"
+    );
+
+    fs::write(
+        dir.join("edgings.knit"),
+        "cast_on unused_here = 1;\ncast_on width = 3;\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("main.knit"),
+        "use \"edgings.knit\" as edgings;\npurl edgings.width;\n",
+    )
+    .unwrap();
+    let err = check_agrees_with_run(&dir, &["main.knit"]);
+    assert!(
+        err.starts_with(
+            "warning[K0401]: unused variable `edgings.unused_here`\n --> edgings.knit:1:1\n"
+        ),
+        "{err}"
+    );
+
+    fs::write(dir.join("a1.knit"), "cast_on w = 2;\n").unwrap();
+    fs::write(dir.join("a2.knit"), "purl w;\n").unwrap();
+    let err = check_agrees_with_run(&dir, &["a1.knit", "a2.knit"]);
+    assert_eq!(err, "checked 2 files: 0 errors, 0 warnings\n");
+    let err = check_agrees_with_run(&dir, &["a2.knit", "a1.knit"]);
+    assert!(
+        err.starts_with("error[K0204]: undefined variable `w`\n --> a2.knit:1:6\n"),
+        "{err}"
+    );
+}
+
+#[test]
+fn stitches_fill_rows_of_the_cast_on_width() {
+    let out = knitlang(&["-v", "-e", "cast_on 4; k 6; p 2;"], "");
+    assert!(out.status.success());
+    assert!(stderr(&out).contains("worked 2 rows, 4 stitches on the needles\n"));
+    let out = knitlang(&["-e", "k 1;"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("error[K0304]: no stitches to work k"));
+}
+
+#[test]
+fn rows_flag_truncates_after_the_nth_row() {
+    let full = knitlang(&["examples", "swatch", "-v"], "");
+    assert!(full.status.success());
+    assert!(stderr(&full).contains("worked 10 rows, 12 stitches on the needles\n"));
+    assert_eq!(stdout(&full), "12\n");
+
+    let short = knitlang(&["examples", "swatch", "-v", "--rows", "3"], "");
+    assert_eq!(short.status.code(), Some(0));
+    assert!(stderr(&short).contains("after 3 rows (--rows)\n"));
+    assert!(stderr(&short).contains("worked 3 rows, 12 stitches on the needles\n"));
+    assert_eq!(stdout(&short), "");
+}
+
+#[test]
+fn rows_flag_rejects_zero() {
+    let out = knitlang(&["run", "--rows", "0", "example.kl"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+}
+
+#[test]
+fn json_reports_sorted_state_after_the_run() {
+    let out = knitlang(
+        &[
+            "--json",
+            "-e",
+            "cast_on b = 2; cast_on a = 1; cast_on 3; k 7;",
+        ],
+        "",
+    );
+    assert!(out.status.success());
+    assert_eq!(
+        stdout(&out),
+        "{\"exit_status\":0,\"fabric\":{\"rows\":2,\"stitches\":3},\
+         \"variables\":{\"a\":{\"type\":\"int\",\"value\":1},\"b\":{\"type\":\"int\",\"value\":2}}}\n"
+    );
+}
+
+#[test]
+fn json_includes_the_exit_status_of_a_failed_run() {
+    let out = knitlang(&["--json", "-e", "cast_on x = 1; purl x / 0;"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        stdout(&out),
+        "{\"exit_status\":1,\"fabric\":{\"rows\":0,\"stitches\":0},\
+         \"variables\":{\"x\":{\"type\":\"int\",\"value\":1}}}\n"
+    );
+}
+
+#[test]
+fn json_out_writes_the_report_to_a_file() {
+    let dir = temp_dir("json-out");
+    let out = knitlang_in(&dir, &["-e", "purl 5;", "--json-out", "state.json"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "5\n");
+    let report = fs::read_to_string(dir.join("state.json")).unwrap();
+    assert!(report.starts_with("{\"exit_status\":0,"));
+}
+
+#[test]
+fn events_writes_one_json_object_per_step() {
+    let dir = temp_dir("events");
+    let src = "cast_on 1; k 1; cast_on n = 2; purl n;";
+    let out = knitlang_in(&dir, &["-e", src, "--events", "events.jsonl"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "2\n");
+    let events = fs::read_to_string(dir.join("events.jsonl")).unwrap();
+    let kinds: Vec<_> = events
+        .lines()
+        .map(|line| {
+            let event: serde_json::Value = serde_json::from_str(line).unwrap();
+            event["event"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            "statement",
+            "statement",
+            "row",
+            "statement",
+            "assign",
+            "statement",
+            "output"
+        ]
+    );
+    assert!(events.contains("{\"event\":\"output\",\"text\":\"2\"}\n"));
+
+    let out = knitlang(&["-e", "purl 1 / 0;", "--events", "-"], "");
+    assert!(stderr(&out).starts_with("{\"event\":\"statement\","));
+    assert!(stderr(&out).contains("{\"event\":\"error\",\"message\":\"division by zero\","));
+}
+
+/// A working directory plus two library directories, each holding its own
+/// `lib.knit`, joined into a `KNIT_PATH` value.
+fn search_dirs(name: &str) -> (PathBuf, PathBuf, PathBuf, String) {
+    let root = temp_dir(name);
+    let (work, a, b) = (root.join("work"), root.join("a"), root.join("b"));
+    for (dir, n) in [(&a, 1), (&b, 2)] {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("lib.knit"), format!("purl {n};\n")).unwrap();
+    }
+    fs::create_dir_all(&work).unwrap();
+    fs::write(b.join("only_b.knit"), "purl 20;\n").unwrap();
+    let knit_path = std::env::join_paths([&a, &b]).unwrap();
+    (work, a, b, knit_path.into_string().unwrap())
+}
+
+#[test]
+fn knit_path_finds_files_in_order_after_the_current_directory() {
+    let (work, _, _, knit_path) = search_dirs("knit-path-order");
+    let out = knitlang_with_path(&work, Some(&knit_path), &["lib.knit", "only_b.knit"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "1\n20\n");
+
+    fs::write(work.join("lib.knit"), "purl 0;\n").unwrap();
+    let out = knitlang_with_path(&work, Some(&knit_path), &["lib.knit"], "");
+    assert_eq!(stdout(&out), "0\n");
+}
+
+#[test]
+fn path_flag_is_searched_after_knit_path() {
+    let (work, a, b, _) = search_dirs("knit-path-flag");
+    let b = b.to_str().unwrap();
+    let out = knitlang_with_path(&work, None, &["--path", b, "lib.knit"], "");
+    assert_eq!(stdout(&out), "2\n");
+    let out = knitlang_with_path(&work, a.to_str(), &["--path", b, "lib.knit"], "");
+    assert_eq!(stdout(&out), "1\n");
+}
+
+#[test]
+fn missing_files_report_each_searched_directory_once() {
+    let (work, a, b, _) = search_dirs("knit-path-missing");
+    let knit_path = std::env::join_paths([&a, &b, &a]).unwrap();
+    let out = knitlang_with_path(&work, knit_path.to_str(), &["nope.knit"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(
+        stderr(&out),
+        format!(
+            "error: could not find nope.knit (searched ., {}, {})\n",
+            a.display(),
+            b.display()
+        )
+    );
+}
+
+#[test]
+fn repl_load_runs_files_from_the_search_path() {
+    let (work, _, _, knit_path) = search_dirs("knit-path-load");
+    let out = knitlang_with_path(
+        &work,
+        Some(&knit_path),
+        &["repl", "-q"],
+        ":load only_b.knit\npurl 3;\n",
+    );
+    assert!(out.status.success());
+    assert!(stdout(&out).contains("20\n"));
+    assert!(stdout(&out).contains("3\n"));
+}
+
+#[test]
+fn use_runs_each_imported_file_once_where_it_is_first_imported() {
+    let (work, _, b, knit_path) = search_dirs("use-order");
+    let pattern = work.join("pattern");
+    fs::create_dir_all(&pattern).unwrap();
+    fs::write(
+        pattern.join("main.knit"),
+        "purl 0;\nuse \"lib.knit\";\nuse \"edge.knit\";\npurl width;\nuse \"lib.knit\";\n",
+    )
+    .unwrap();
+    fs::write(
+        pattern.join("edge.knit"),
+        "use \"lib.knit\";\nuse \"only_b.knit\";\ncast_on width = 5;\n",
+    )
+    .unwrap();
+    for streaming in [false, true] {
+        let args: &[&str] = if streaming {
+            &["run", "--streaming", "pattern/main.knit"]
+        } else {
+            &["pattern/main.knit"]
+        };
+        let out = knitlang_with_path(&work, Some(&knit_path), args, "");
+        assert!(out.status.success(), "{}", stderr(&out));
+        assert_eq!(stdout(&out), "0\n1\n20\n5\n", "streaming: {streaming}");
+    }
+
+    // A file next to the importer comes before any on the search path, and
+    // a variable read only by the importer is not unused.
+    fs::write(pattern.join("lib.knit"), "cast_on unread = 3;\n").unwrap();
+    let out = knitlang_with_path(&work, Some(&knit_path), &["pattern/main.knit"], "");
+    assert_eq!(stdout(&out), "0\n20\n5\n");
+    assert!(
+        stderr(&out).contains("unused variable `lib.unread`"),
+        "{}",
+        stderr(&out)
+    );
+    let out = knitlang_with_path(&work, None, &["pattern/main.knit"], "");
+    assert_eq!(stderr(&out), "error[K0111]: Cannot import `only_b.knit`: could not find it (searched pattern)\n --> pattern/edge.knit:2:1\n  |\n2 | use \"only_b.knit\";\n  | ^~~~~~~~~~~~~~~~~~\n");
+    assert_eq!(stdout(&out), "", "nothing runs when an import is missing");
+    let out = knitlang_with_path(
+        &work,
+        None,
+        &["--path", b.to_str().unwrap(), "pattern/main.knit"],
+        "",
+    );
+    assert_eq!(stdout(&out), "0\n20\n5\n");
+}
+
+#[test]
+fn errors_in_imported_files_name_them() {
+    let dir = temp_dir("use-errors");
+    fs::write(dir.join("a.knit"), "purl 1;\nuse \"b.knit\";\n").unwrap();
+    fs::write(dir.join("b.knit"), "use \"c.knit\";\n").unwrap();
+    fs::write(dir.join("c.knit"), "use \"a.knit\";\n").unwrap();
+    let out = knitlang_in(&dir, &["a.knit"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(
+        stderr(&out).starts_with(
+            "error[K0112]: Import cycle: a.knit -> b.knit -> c.knit -> a.knit\n --> c.knit:1:1\n"
+        ),
+        "{}",
+        stderr(&out)
+    );
+    assert_eq!(stdout(&out), "");
+
+    fs::write(dir.join("b.knit"), "purl 2;\npurl 1 / 0;\n").unwrap();
+    let out = knitlang_in(&dir, &["--error-format", "json", "a.knit"], "");
+    assert_eq!(stdout(&out), "1\n2\n");
+    let diag: serde_json::Value = serde_json::from_str(stderr(&out).trim()).unwrap();
+    assert_eq!(
+        (diag["file"].as_str(), diag["line"].as_u64()),
+        (Some("b.knit"), Some(2))
+    );
+
+    fs::write(dir.join("b.knit"), "purl 2 +;\n").unwrap();
+    let out = knitlang_in(&dir, &["a.knit"], "");
+    assert!(
+        stderr(&out).starts_with("error[K0105]: "),
+        "{}",
+        stderr(&out)
+    );
+    assert!(
+        stderr(&out).contains(" --> b.knit:1:9\n"),
+        "{}",
+        stderr(&out)
+    );
+
+    // Imports go only at the top level, and `-e` imports from the current
+    // directory.
+    fs::write(dir.join("b.knit"), "repeat 2 { use \"c.knit\"; }\n").unwrap();
+    let out = knitlang_in(&dir, &["a.knit"], "");
+    assert!(
+        stderr(&out).starts_with("error[K0113]: "),
+        "{}",
+        stderr(&out)
+    );
+    fs::write(dir.join("b.knit"), "purl 2;\n").unwrap();
+    let out = knitlang_in(&dir, &["-e", "use \"b.knit\"; use \"b.knit\";"], "");
+    assert_eq!(stdout(&out), "2\n");
+}
+
+#[test]
+fn imported_variables_are_read_and_listed_by_qualified_names() {
+    let dir = temp_dir("use-qualified");
+    fs::write(dir.join("edgings.knit"), "cast_on width = 3;\n").unwrap();
+    fs::write(dir.join("trim.knit"), "cast_on width = 5;\n").unwrap();
+    fs::write(
+        dir.join("main.knit"),
+        "use \"edgings.knit\" as edgings;\ncast_on width = edgings.width * 2;\npurl width;\n",
+    )
+    .unwrap();
+    for args in [&["main.knit"][..], &["run", "--streaming", "main.knit"]] {
+        let out = knitlang_in(&dir, args, "");
+        assert_eq!(stdout(&out), "6\n", "{args:?}: {}", stderr(&out));
+    }
+    let out = knitlang_in(
+        &dir,
+        &["repl", "-q"],
+        ":load main.knit\n:vars\npurl edgings.width;\n",
+    );
+    assert_eq!(stdout(&out), "6\nedgings.width = 3\nwidth = 6\n3\n");
+
+    fs::write(
+        dir.join("main.knit"),
+        "use \"edgings.knit\";\nuse \"trim.knit\";\npurl width;\n",
+    )
+    .unwrap();
+    for args in [&["main.knit"][..], &["run", "--streaming", "main.knit"]] {
+        let out = knitlang_in(&dir, args, "");
+        assert_eq!(out.status.code(), Some(2));
+        assert!(
+            stderr(&out).starts_with(
+                "error[K0207]: Ambiguous name `width`: it could be `edgings.width` from \
+                 edgings.knit or `trim.width` from trim.knit\n --> main.knit:3:6\n"
+            ),
+            "{args:?}: {}",
+            stderr(&out)
+        );
+        assert_eq!(stdout(&out), "");
+    }
+}
+
+#[test]
+fn size_flag_chooses_a_size_and_written_grades_the_rest() {
+    let dir = temp_dir("sizes");
+    fs::write(
+        dir.join("sweater.knit"),
+        "sizes S, M, L;\ncast_on width = 88 (96, 104);\ncast_on width;\n\
+         repeat 4 (6, 8) { k width; }\npurl SIZE;\n",
+    )
+    .unwrap();
+    let out = knitlang_in(&dir, &["sweater.knit", "--size", "M"], "");
+    assert_eq!(stdout(&out), "1\n", "{}", stderr(&out));
+    let out = knitlang_in(&dir, &["sweater.knit", "--written", "--size", "L"], "");
+    assert_eq!(stdout(&out), "Cast on 104 sts.\nRows 1–8: k104.\n");
+    // Without `--size`, every size is knitted and written at once.
+    for args in [
+        &["sweater.knit", "--written"][..],
+        &["run", "--streaming", "--written", "sweater.knit"],
+    ] {
+        let out = knitlang_in(&dir, args, "");
+        assert_eq!(
+            stdout(&out),
+            "Sizes: S (M, L).\nCast on 88 (96, 104) sts.\nRows 1–4 (6, 8): k88 (96, 104).\n",
+            "{args:?}: {}",
+            stderr(&out)
+        );
+    }
+    let out = knitlang_in(&dir, &["sweater.knit", "--size", "XL"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(
+        stderr(&out).starts_with(
+            "error[K0318]: there is no size `XL`; the pattern is sized S, M, L; \
+             did you mean `L`?\n --> sweater.knit:1:1\n"
+        ),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn repl_saves_and_resumes_sessions() {
+    let dir = temp_dir("repl-session");
+    let out = knitlang_in(
+        &dir,
+        &["repl", "-q"],
+        "cast_on width = 3;\ncast_on width;\nk 4;\n:save-session knit.session\n",
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    let out = knitlang_in(
+        &dir,
+        &["repl", "-q"],
+        ":load-session knit.session\n:vars\np 2;\n:vars\n:load-session nope.session\n",
+    );
+    let resumed = stdout(&out);
+    assert!(
+        resumed.contains("width = 3\nfabric: 1 row, 3 stitches"),
+        "{resumed}"
+    );
+    assert!(
+        resumed.contains("width = 3\nfabric: 2 rows, 3 stitches"),
+        "{resumed}"
+    );
+    assert!(stderr(&out).contains("knit> error: could not load session from nope.session: "));
+
+    fs::write(dir.join("new.session"), "{\"version\": 99}").unwrap();
+    let out = knitlang_in(&dir, &["repl", "-q"], ":load-session new.session\n:vars\n");
+    assert!(
+        stderr(&out).contains("saved in format version 99"),
+        "{}",
+        stderr(&out)
+    );
+    assert!(stdout(&out).contains("no variables\n"));
+}
+
+#[test]
+fn watch_reruns_with_a_fresh_interpreter_when_a_file_changes() {
+    use std::io::{BufRead, BufReader};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let dir = temp_dir("watch");
+    fs::write(dir.join("p.knit"), "purl 1 / 0;\n").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_knitlang_v2"))
+        .current_dir(&dir)
+        .env_remove("KNIT_PATH")
+        .args(["--watch", "p.knit"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn knitlang");
+    let (tx, rx) = mpsc::channel();
+    let err = BufReader::new(child.stderr.take().unwrap());
+    std::thread::spawn(move || {
+        for line in err.lines().map_while(Result::ok) {
+            let _ = tx.send(line);
+        }
+    });
+    let mut wait_for = |want: &str| loop {
+        match rx.recv_timeout(Duration::from_secs(10)) {
+            Ok(line) if line.contains(want) => break,
+            Ok(_) => {}
+            Err(_) => {
+                let _ = child.kill();
+                panic!("timed out waiting for {want:?}");
+            }
+        }
+    };
+    // The first run fails, but watch mode keeps going.
+    wait_for("error[K0301]: division by zero");
+    wait_for("-- watching 1 file for changes");
+    fs::write(dir.join("p.knit"), "cast_on 2;\nk 1;\npurl 1;\n").unwrap();
+    wait_for("re-running");
+    wait_for("-- watching 1 file for changes");
+    fs::write(dir.join("p.knit"), "cast_on 2;\nk 1;\npurl 1;\n\n").unwrap();
+    wait_for("re-running");
+    wait_for("-- watching 1 file for changes");
+    child.kill().unwrap();
+    let out = child.wait_with_output().unwrap();
+    // Each run starts from scratch; carried over, the half-worked row would
+    // stop the second `cast_on`.
+    assert_eq!(stdout(&out), "1\n1\n");
+}
+
+#[test]
+fn bench_reports_timings_without_program_output() {
+    let out = knitlang(&["--bench", "--iterations", "3", "example.kl"], "");
+    assert!(out.status.success());
+    let report = stdout(&out);
+    let labels: Vec<&str> = report
+        .lines()
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    assert_eq!(
+        labels,
+        [
+            "iterations",
+            "min",
+            "median",
+            "mean",
+            "statements",
+            "statements/s"
+        ]
+    );
+    assert!(report.starts_with("iterations:   3 (1 warmup)\n"));
+    assert!(report.contains("statements:   9\n"));
+}
+
+#[test]
+fn bench_format_json_is_one_object() {
+    let out = knitlang(
+        &[
+            "--bench",
+            "--bench-format",
+            "json",
+            "--warmup",
+            "0",
+            "-e",
+            "purl 1;",
+        ],
+        "",
+    );
+    assert!(out.status.success());
+    let report = stdout(&out);
+    assert!(report.starts_with("{\"iterations\":10,\"mean_ns\":"));
+    for key in [
+        "median_ns",
+        "min_ns",
+        "statements\":1,",
+        "statements_per_sec",
+        "warmup\":0}",
+    ] {
+        assert!(report.contains(key), "{key} missing from {report}");
+    }
+}
+
+#[test]
+fn bench_options_require_bench() {
+    let out = knitlang(&["--iterations", "3", "example.kl"], "");
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn interactive_keeps_the_programs_state_for_the_repl() {
+    let out = knitlang(&["-q", "-i", "example.kl"], ":vars\npurl stitches * 10;\n");
+    assert!(out.status.success());
+    let stdout = stdout(&out);
+    assert!(stdout.starts_with("1\n2\n3\n"), "{stdout}");
+    assert!(stdout.contains("stitches = 3\n"));
+    assert!(stdout.contains("30\n"));
+}
+
+#[test]
+fn interactive_still_starts_after_a_runtime_error() {
+    let out = knitlang(&["-q", "-i", "-e", "cast_on x = 4; purl x / 0;"], ":vars\n");
+    assert!(stderr(&out).contains("error[K0301]: division by zero"));
+    assert!(stdout(&out).contains("x = 4\n"));
+}
+
+#[test]
+fn internal_errors_are_reported_as_bugs() {
+    let out = knitlang_panicking(&["-e", "purl 1; purl debug_panic();"], "");
+    assert_eq!(out.status.code(), Some(5));
+    assert_eq!(stdout(&out), "1\n");
+    let stderr = stderr(&out);
+    assert!(
+        stderr.starts_with(
+            "error: internal error in knitlang, this is a bug: debug_panic() was called\n  \
+             while processing <eval>\n  please report it at "
+        ),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("RUST_BACKTRACE"), "{stderr}");
+}
+
+#[test]
+fn debug_panic_only_exists_when_a_test_asks_for_it() {
+    let out = knitlang(&["-e", "purl debug_panic();"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).starts_with("error[K0201]: unknown function `debug_panic`\n"));
+}
+
+#[test]
+fn repl_survives_internal_errors() {
+    let out = knitlang_panicking(
+        &["repl", "-q"],
+        "cast_on x = 3;\npurl debug_panic();\npurl x;\n",
+    );
+    assert!(out.status.success());
+    assert!(stderr(&out).contains("internal error in knitlang"));
+    assert!(stdout(&out).contains("3\n"));
+}
+
+#[test]
+fn timeout_aborts_long_runs_with_its_own_exit_code() {
+    let started = std::time::Instant::now();
+    let out = knitlang(
+        &[
+            "--timeout",
+            "200ms",
+            "-e",
+            "cast_on x = 0; repeat 1000000000 { repeat 1000000000 { knit x = x + 1; } }",
+        ],
+        "",
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(out.status.code(), Some(4));
+    assert!(stderr(&out).starts_with("error[K0307]: timed out after 200ms\n"));
+}
+
+#[test]
+fn timeout_leaves_quick_runs_alone_and_parses_units() {
+    for limit in ["5s", "2m", "1500ms"] {
+        let out = knitlang(&["--timeout", limit, "example.kl"], "");
+        assert!(out.status.success(), "{limit}");
+        assert_eq!(stdout(&out), "1\n2\n3\n");
+    }
+    for limit in ["5", "0s", "1h", "fast"] {
+        let out = knitlang(&["--timeout", limit, "example.kl"], "");
+        assert_eq!(out.status.code(), Some(3), "{limit}");
+    }
+}
+
+#[test]
+fn timeout_counts_from_each_entry_not_from_startup() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_knitlang_v2"))
+        .env_remove("KNIT_PATH")
+        .args(["-q", "-i", "--timeout", "500ms", "-e", "cast_on x = 2;"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn knitlang");
+    let mut input = child.stdin.take().unwrap();
+    // Idle at the prompt for longer than any one entry may take.
+    std::thread::sleep(std::time::Duration::from_millis(1200));
+    input
+        .write_all(b"purl x;\nrepeat 1000 { knit x = x + 1; }\npurl x;\n")
+        .unwrap();
+    drop(input);
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "2\n1002\n");
+}
+
+#[test]
+fn max_memory_stops_runs_that_hold_too_much() {
+    let out = knitlang(
+        &[
+            "--max-memory",
+            "1K",
+            "-e",
+            "cast_on 1; repeat 1000 { k 1; }",
+        ],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert!(
+        stderr(&out).starts_with("error[K0311]: memory limit exceeded: the run holds "),
+        "{}",
+        stderr(&out)
+    );
+    for limit in ["65536", "512K", "64M", "1g"] {
+        let out = knitlang(&["--max-memory", limit, "example.kl"], "");
+        assert!(out.status.success(), "{limit}");
+        assert_eq!(stdout(&out), "1\n2\n3\n");
+    }
+    // Only variables with values count, however many names the program
+    // mentions.
+    let unassigned: String = (0..20_000).map(|i| format!("knit a{i} = 1; ")).collect();
+    let src = format!("repeat 0 {{ {unassigned}}}\ncast_on w = 1;\npurl w;\n");
+    let out = knitlang(&["--max-memory", "64K", "-"], &src);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "1\n");
+    for limit in ["0", "1T", "lots", "-4K", "99999999999999999999G"] {
+        let out = knitlang(&["--max-memory", limit, "example.kl"], "");
+        assert_eq!(out.status.code(), Some(3), "{limit}");
+    }
+}
+
+#[test]
+fn chart_format_draws_the_fabric_instead_of_program_output() {
+    let out = knitlang(&["tests/golden/chart.knit", "--chart-format", "text"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), include_str!("golden/chart.txt"));
+
+    let dir = temp_dir("chart");
+    let path = dir.join("chart.svg");
+    let out = knitlang(
+        &[
+            "tests/golden/chart.knit",
+            "--chart-format",
+            "svg",
+            "--output",
+            path.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "");
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        include_str!("golden/chart.svg")
+    );
+
+    // A failed run still charts the rows it finished.
+    let out = knitlang(
+        &[
+            "--chart-format",
+            "text",
+            "-e",
+            "cast_on 2; k 2; p 1; purl 1 / 0;",
+        ],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stdout(&out), "││ 1 RS\n21\n│ knit\n");
+}
+
+#[test]
+fn chart_orientation_flags_turn_the_chart() {
+    let chart = |flags: &[&str]| {
+        let mut args = vec![
+            "--chart-format",
+            "text",
+            "-e",
+            "cast_on 3; k 2; p 1; p 1; k 2;",
+        ];
+        args.extend(flags);
+        let out = knitlang(&args, "");
+        assert!(out.status.success(), "{}", stderr(&out));
+        stdout(&out)
+    };
+    let legend = "│ knit on RS, purl on WS\n• purl on RS, knit on WS\n";
+    assert_eq!(
+        chart(&[]),
+        format!("WS 2 │••\n     •││ 1 RS\n     321\n{legend}")
+    );
+    assert_eq!(
+        chart(&[
+            "--chart-origin",
+            "bottom",
+            "--chart-flat-reading",
+            "right-side"
+        ]),
+        chart(&[])
+    );
+    assert_eq!(
+        chart(&["--chart-origin", "top", "--chart-flat-reading", "worked"]),
+        "•││ 1 RS\n││• 2 WS\n321\n│ knit\n• purl\n"
+    );
+
+    let out = knitlang(&["--chart-origin", "top", "-e", "cast_on 1;"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("--chart-format"), "{}", stderr(&out));
+}
+
+#[test]
+fn chart_style_and_symbols_files_choose_the_chart_symbols() {
+    let chart = |flags: &[&str]| {
+        let mut args = vec![
+            "--chart-format",
+            "text",
+            "-e",
+            "mode round; cast_on 3; k 1; p 1; k 1;",
+        ];
+        args.extend(flags);
+        knitlang(&args, "")
+    };
+    let out = chart(&["--chart-style", "japanese"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "│─│ 1\n321\n│ knit\n─ purl\n");
+    let out = chart(&["--chart-style", "craft-yarn-council"]);
+    assert_eq!(stdout(&out), "□•□ 1\n321\n□ knit\n• purl\n");
+
+    // A symbols file changes the symbols it names, on top of the style.
+    let dir = temp_dir("chart-symbols");
+    let json = dir.join("symbols.json");
+    fs::write(&json, r#"{ "p": "x" }"#).unwrap();
+    let toml = dir.join("symbols.toml");
+    fs::write(&toml, "k = \"v\"\n\n[p]\ntext = \"x\"\nglyph = \"ring\"\n").unwrap();
+    let out = chart(&["--chart-symbols", json.to_str().unwrap()]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "│x│ 1\n321\n│ knit\nx purl\n");
+    let out = chart(&[
+        "--chart-style",
+        "japanese",
+        "--chart-symbols",
+        toml.to_str().unwrap(),
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "vxv 1\n321\nv knit\nx purl\n");
+
+    // Anything it cannot use is an error before any chart is written.
+    let bad = dir.join("bad.toml");
+    fs::write(&bad, "[purl]\ntext = \"x\"\n").unwrap();
+    let out = chart(&["--chart-symbols", bad.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+    assert!(
+        stderr(&out).contains("purl: no stitch is charted as `purl`"),
+        "{}",
+        stderr(&out)
+    );
+    let out = chart(&[
+        "--chart-symbols",
+        dir.join("missing.json").to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(3));
+    assert!(
+        stderr(&out).contains("could not load chart symbols from"),
+        "{}",
+        stderr(&out)
+    );
+
+    let out = knitlang(&["--chart-style", "japanese", "-e", "cast_on 1;"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("--chart-format"), "{}", stderr(&out));
+}
+
+#[test]
+fn export_knitout_matches_the_golden_files() {
+    let dir = temp_dir("knitout");
+    for name in ["knitout_stockinette", "knitout_rib"] {
+        let path = dir.join(format!("{name}.k"));
+        let out = knitlang(
+            &[
+                &format!("tests/golden/{name}.knit"),
+                "--export",
+                "knitout",
+                "-o",
+                path.to_str().unwrap(),
+            ],
+            "",
+        );
+        assert!(out.status.success(), "{}", stderr(&out));
+        assert_eq!(stdout(&out), "");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            fs::read_to_string(format!("tests/golden/{name}.k")).unwrap(),
+            "{name}"
+        );
+    }
+
+    // What a machine cannot knit yet is an error naming its rows, and
+    // nothing is written.
+    let path = dir.join("cables.k");
+    let out = knitlang(
+        &[
+            "--export",
+            "knitout",
+            "-o",
+            path.to_str().unwrap(),
+            "-e",
+            "cast_on 8; k 8; p 8; repeat 2 { c4f; c4b; p 8; } k 4; w&t; p 4;",
+        ],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(
+        stderr(&out),
+        "error: cables are not supported for knitout export, in rows 3 and 5\n\
+         error: short rows are not supported for knitout export, in rows 7–8\n"
+    );
+    assert!(!path.exists());
+
+    let out = knitlang(
+        &["--export", "knitout", "--written", "-e", "cast_on 1;"],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn partial_bind_offs_are_charted_written_and_checked() {
+    let src = "cast_on 6; k 6; bind_off 2; p 4; k 4;";
+    let out = knitlang(&["--chart-format", "text", "-e", src], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        stdout(&out),
+        "       ││││ 3 RS\nWS 2 ──││││\n     ││││││ 1 RS\n     654321\n\
+         │ knit on RS, purl on WS\n─ bind off\n"
+    );
+    let out = knitlang(&["--written", "-e", src], "");
+    assert!(stdout(&out).contains("Row 2 (WS): BO 2 sts, p4.\n"));
+
+    let out = knitlang(&["--export", "knitout", "-e", src], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(
+        stderr(&out),
+        "error: partial bind-offs are not supported for knitout export, in row 2\n"
+    );
+
+    let out = knitlang(&["-e", "cast_on 6; k2tog; bind_off 5;"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).starts_with(
+        "error[K0323]: cannot bind off 5 stitches with only 4 stitches left in the row\n"
+    ));
+}
+
+#[test]
+fn cable_panel_example_charts_its_crossings() {
+    let out = knitlang(&["examples/cable_panel.knit", "--chart-format", "text"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), include_str!("golden/cable_panel.txt"));
+}
+
+#[test]
+fn written_prints_instructions_instead_of_program_output() {
+    let out = knitlang(&["tests/golden/ribbing.knit", "--written"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), include_str!("golden/ribbing.txt"));
+
+    let out = knitlang(&["--written", "-e", "cast_on 2; k 2; purl 7;"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "Cast on 2 sts.\nRow 1 (RS): k2.\n");
+
+    let out = knitlang(
+        &["--written", "--chart-format", "text", "-e", "cast_on 2;"],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn written_repeats_keep_runs_of_one_stitch_whole() {
+    let out = knitlang(&["tests/golden/repeats.knit", "--written"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), include_str!("golden/repeats.txt"));
+}
+
+#[test]
+fn png_charts_have_one_cell_size_square_per_stitch() {
+    let dir = temp_dir("png-chart");
+    let path = dir.join("chart.png");
+    let out = knitlang(
+        &[
+            "--chart-format",
+            "png",
+            "--cell-size",
+            "24",
+            "--output",
+            path.to_str().unwrap(),
+            "-e",
+            "cast_on 2; k 1; p 1;",
+        ],
+        "",
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    let decoder = png::Decoder::new(std::io::BufReader::new(fs::File::open(&path).unwrap()));
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    // Two 24-pixel cells, with half a cell of margin around the grid and
+    // room for the numbers on the right and along the bottom, then a
+    // legend of the two stitches, each a cell and a margin high.
+    assert_eq!((info.width, info.height), (92, 142));
+    assert_eq!(info.color_type, png::ColorType::Rgb);
+    let pixel = |x: usize, y: usize| {
+        let at = (y * info.width as usize + x) * 3;
+        [pixels[at], pixels[at + 1], pixels[at + 2]]
+    };
+    // Stitch 1 is on the right, so the purl dot is in the left cell.
+    assert_eq!(pixel(0, 0), [255, 255, 255]);
+    assert_eq!(pixel(24, 24), [0, 0, 0]);
+    assert_eq!(pixel(48, 24), [255, 255, 255]);
+    assert_eq!(pixel(12, 20), [0x99, 0x99, 0x99]);
+    assert_eq!(pixel(59, 35), [0x99, 0x99, 0x99]);
+
+    let out = knitlang(&["--cell-size", "24", "example.kl"], "");
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn summary_footer_matches_the_fabric() {
+    let out = knitlang(&["examples", "swatch", "--summary"], "");
+    assert!(out.status.success());
+    let err = stderr(&out);
+    let (stable, wall) = err.rsplit_once("wall time:   ").unwrap();
+    assert_eq!(
+        stable,
+        "rows worked: 10\nstitches:    120\nincreases:   0\ndecreases:   0\n\
+         variables:   1\npeak vars:   1\nstatements:  74\nexpressions: 74\nmax depth:   2\n"
+    );
+    assert!(wall.ends_with("s\n"));
+
+    let out = knitlang(&["examples", "swatch", "--summary", "--rows", "2"], "");
+    assert!(stderr(&out).starts_with("rows worked: 2\nstitches:    24\n"));
+    assert_eq!(
+        stderr(&knitlang(&["-q", "examples", "swatch", "--summary"], "")),
+        ""
+    );
+}
+
+#[test]
+fn summary_with_json_adds_a_stats_key() {
+    let out = knitlang(&["--summary", "--json", "-e", "cast_on 2; k 3;"], "");
+    let report = stdout(&out);
+    assert!(report.contains("\"stats\":{\"decreases\":0,\"elapsed_ns\":"));
+    assert!(report.contains(
+        ",\"expressions\":2,\"increases\":0,\"max_depth\":0,\"peak_variables\":0,\"rows\":1,\
+         \"statements\":2,\"stitches\":3,\"variables\":0}"
+    ));
+    assert!(!stdout(&knitlang(&["--json", "-e", "purl 1;"], "")).contains("stats"));
+}
+
+#[test]
+fn pattern_summary_measures_the_piece_at_its_gauge() {
+    let args = ["examples", "cable_panel", "--pattern-summary"];
+    let out = knitlang(&[&args[..], &["--yarn-per-stitch", "30"]].concat(), "");
+    assert!(out.status.success());
+    assert_eq!(stderr(&out), include_str!("golden/summary_cable_panel.txt"));
+    let out = knitlang(
+        &[
+            "tests/golden/raglan.knit",
+            "--pattern-summary",
+            "--yarn-per-stitch",
+            "25",
+        ],
+        "",
+    );
+    assert!(out.status.success());
+    assert_eq!(stderr(&out), include_str!("golden/summary_raglan.txt"));
+
+    let out = knitlang(&[&args[..], &["--json"]].concat(), "");
+    assert!(stdout(&out).contains(
+        "\"pattern\":{\"decreases\":0,\"dimensions\":{\"length_mm\":33,\"width_mm\":50},\
+         \"gauge\":{\"rows\":36,\"stitches\":28},\"increases\":0,\"max_width\":14,\"rows\":12,\
+         \"sections\":[{\"first\":1,\"last\":12,\"stitches\":14}],\"yarn_m\":null}"
+    ));
+    assert!(!stdout(&knitlang(&["examples", "cable_panel", "--json"], "")).contains("pattern"));
+    assert_eq!(
+        stderr(&knitlang(&[&["-q"][..], &args[..]].concat(), "")),
+        ""
+    );
+    // Without a gauge there is no size to give.
+    let out = knitlang(&["--pattern-summary", "-e", "cast_on 2; k 4;"], "");
+    assert_eq!(
+        stderr(&out),
+        "rows:        2\nsections:    rows 1–2: 2 sts\nincreases:   0\ndecreases:   0\n\
+         max width:   2 sts\n"
+    );
+    let out = knitlang(&["--yarn-per-stitch", "30", "-e", "cast_on 2;"], "");
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn error_format_json_prints_one_object_per_diagnostic() {
+    let dir = temp_dir("error-format-json");
+    fs::write(dir.join("bad.knit"), "purl 1;\npurl nope;\n").unwrap();
+    fs::write(dir.join("warn.knit"), "cast_on unused = 1;\n").unwrap();
+    let out = knitlang_in(
+        &dir,
+        &[
+            "check",
+            "-q",
+            "--error-format=json",
+            "bad.knit",
+            "warn.knit",
+        ],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(stderr(&out).lines().count(), 1, "-q drops the warning");
+
+    let out = knitlang_in(
+        &dir,
+        &["check", "--error-format", "json", "bad.knit", "warn.knit"],
+        "",
+    );
+    assert_eq!(
+        stderr(&out),
+        "{\"class\":\"unused\",\"code\":\"K0401\",\"column\":1,\"end_column\":20,\"end_line\":1,\"file\":\"warn.knit\",\"line\":1,\
+         \"message\":\"unused variable `unused`\",\"severity\":\"warning\"}\n\
+         {\"code\":\"K0204\",\"column\":6,\"end_column\":10,\"end_line\":2,\"file\":\"bad.knit\",\"line\":2,\
+         \"message\":\"undefined variable `nope`\",\"severity\":\"error\"}\n\
+         checked 2 files: 1 error, 1 warning\n"
+    );
+}
+
+/// Runs every registered code's example (those that need nothing from a
+/// host application), and checks that each diagnostic it prints carries a
+/// registered code, its own among them, and that every code has an
+/// explanation.
+#[test]
+fn every_diagnostic_has_a_code_with_an_explanation() {
+    let dir = temp_dir("codes");
+    for entry in knitlang::REGISTRY {
+        if !entry.example.starts_with("//") {
+            let ast = entry.code == knitlang::Code::InvalidAst;
+            let file = if ast { "example.json" } else { "example.knit" };
+            fs::write(dir.join(file), entry.example).unwrap();
+            let args: &[&str] = if ast {
+                &["run", "--from-ast"]
+            } else {
+                &["run"]
+            };
+            let args = [args, &["--error-format=json", file]].concat();
+            let out = knitlang_in(&dir, &args, "");
+            let codes: Vec<String> = stderr(&out)
+                .lines()
+                .map(|line| {
+                    let diag: serde_json::Value = serde_json::from_str(line).unwrap();
+                    let code = diag["code"].as_str().unwrap_or_default().to_string();
+                    assert!(knitlang::Code::from_id(&code).is_some(), "{line}");
+                    code
+                })
+                .collect();
+            assert!(
+                codes.iter().any(|c| c == entry.id),
+                "{}: {codes:?}",
+                entry.id
+            );
+        }
+
+        let out = knitlang(&["explain", entry.id], "");
+        assert!(out.status.success());
+        let explained = stdout(&out);
+        assert!(explained.starts_with(&format!("{}: {}\n\n", entry.id, entry.title)));
+        assert!(explained.contains(entry.fix));
+    }
+
+    let out = knitlang(&["explain"], "");
+    assert_eq!(stdout(&out).lines().count(), knitlang::REGISTRY.len());
+    let out = knitlang(&["explain", "K9999"], "");
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn error_format_json_covers_runtime_and_io_errors() {
+    let out = knitlang(&["--error-format=json", "-e", "purl 1 / 0;"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        stderr(&out),
+        "{\"code\":\"K0301\",\"column\":10,\"end_column\":11,\"end_line\":1,\"file\":\"<eval>\",\"line\":1,\
+         \"message\":\"division by zero\",\"severity\":\"error\"}\n"
+    );
+    let out = knitlang(&["--error-format=json", "does/not/exist.knit"], "");
+    assert!(stderr(&out).starts_with("{\"message\":\"could not find does/not/exist.knit"));
+}
+
+#[test]
+fn w_no_class_suppresses_only_that_class() {
+    let out = knitlang(&["check", "-W", "no-unused"], WARNS);
+    assert!(out.status.success());
+    let err = stderr(&out);
+    assert!(!err.contains("unused variable"));
+    assert!(err.contains("warning[K0402]: unreachable statement after bind_off"));
+    assert!(err.ends_with("checked 1 file: 0 errors, 1 warning\n"));
+
+    let out = knitlang(
+        &[
+            "check",
+            "-W",
+            "no-unused",
+            "-W",
+            "no-unreachable",
+            "-W",
+            "error",
+        ],
+        WARNS,
+    );
+    assert!(out.status.success());
+    assert!(stderr(&out).ends_with("checked 1 file: 0 errors, 0 warnings\n"));
+}
+
+#[test]
+fn run_reports_warnings_and_w_error_fails_after_running() {
+    let out = knitlang(&["-e", "cast_on unused = 1; purl 2;"], "");
+    assert!(out.status.success());
+    assert!(stderr(&out).starts_with("warning[K0401]: unused variable `unused`"));
+
+    let out = knitlang(&["-W", "error", "-e", "cast_on unused = 1; purl 2;"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).starts_with("error[K0401]: unused variable `unused`"));
+    // The warning was found before the run, which still went ahead.
+    assert_eq!(stdout(&out), "2\n");
+}
+
+#[test]
+fn check_follows_stitch_counts_until_they_depend_on_the_run() {
+    let out = knitlang(&["check", "-"], "cast_on 22;\nrepeat 4 { k 5; }\n");
+    assert!(out.status.success());
+    assert_eq!(
+        stderr(&out),
+        "warning[K0403]: row 1 works 20 stitches but has 22 on the needles\n \
+         --> <stdin>:2:1\n  |\n2 | repeat 4 { k 5; }\n  | ^~~~~~~~~~~~~~~~~\n\
+         checked 1 file: 0 errors, 1 warning\n"
+    );
+
+    // A count read from a marker can only be checked by running, so the
+    // check stops there quietly, saying so only with `-v`.
+    let unverifiable = "cast_on 22;\nplace_marker m;\nk 5;\nk stitches_to(m);\n";
+    let out = knitlang(&["check", "-"], unverifiable);
+    assert_eq!(stderr(&out), "checked 1 file: 0 errors, 0 warnings\n");
+    let out = knitlang(&["check", "-v", "-"], unverifiable);
+    assert!(stderr(&out).contains(
+        "<stdin>: cannot verify stitch counts from line 4, as its count is only known when \
+         the program runs\n"
+    ));
+    let out = knitlang(
+        &["check", "-W", "no-stitch-count", "-"],
+        "cast_on 3;\nk 2;\n",
+    );
+    assert!(stderr(&out).ends_with("checked 1 file: 0 errors, 0 warnings\n"));
+}
+
+#[test]
+fn check_pairs_yarn_overs_with_decreases() {
+    let balanced = include_str!("golden/feather_and_fan.knit");
+    let out = knitlang(&["check", "-"], balanced);
+    assert_eq!(stderr(&out), "checked 1 file: 0 errors, 0 warnings\n");
+
+    // One decrease fewer, with the row still working every stitch.
+    let unbalanced = balanced.replacen("k2tog 3;\nk 18;", "k 2;\nk2tog 2;\nk 18;", 1);
+    let out = knitlang(&["check", "-W", "no-stitch-count", "-"], &unbalanced);
+    assert!(out.status.success());
+    assert_eq!(
+        stderr(&out),
+        "warning[K0404]: row 3: 6 yo but 5 decreases\n  --> <stdin>:6:1\n   |\n \
+         6 | k2tog 3;\n   | ^~~~~~~~\n 7 | repeat 6 {\n   | ~~~~~~~~~~\n \
+         8 |     yo;\n   |     ~~~\n...\n12 | k2tog 2;\n   | ~~~~~~~~\n\
+         checked 1 file: 0 errors, 1 warning\n"
+    );
+
+    // An increase row can say it means to be one.
+    let increases = "cast_on 4;\n// knitlang: allow(lace)\nk 1; yo; k 2; yo; k 1;\n\
+                     k 6; yo; // knitlang: allow(lace, unused)\n";
+    let out = knitlang(&["check", "-W", "no-stitch-count", "-"], increases);
+    assert_eq!(stderr(&out), "checked 1 file: 0 errors, 0 warnings\n");
+    let out = knitlang(&["check", "-"], "cast_on 4;\nk 1; yo; k 3;\n");
+    assert!(stderr(&out).contains("row 1: 1 yo but 0 decreases"));
+    let out = knitlang(
+        &["check", "-W", "no-lace", "-"],
+        "cast_on 4;\nk 1; yo; k 3;\n",
+    );
+    assert!(stderr(&out).ends_with("0 warnings\n"));
+    let out = knitlang(&["check", "-"], "// knitlang: allow(lacy)\ncast_on 4;\n");
+    assert!(stderr(&out).contains("<stdin>:1: `lacy` is not a warning class"));
+}
+
+#[test]
+fn meta_blocks_head_the_written_instructions_and_json() {
+    let src = "meta {\n    title = \"Swatch\";\n    autor = \"A. Knitter\";\n}\ncast_on 2;\nk 2;\n";
+    let out = knitlang(&["check", "-"], src);
+    assert_eq!(
+        stderr(&out),
+        "warning[K0405]: unknown meta key `autor`; did you mean `author`?\n \
+         --> <stdin>:3:5\n  |\n3 |     autor = \"A. Knitter\";\n  |     ^~~~~~~~~~~~~~~~~~~~~\n\
+         checked 1 file: 0 errors, 1 warning\n"
+    );
+    let out = knitlang(&["check", "-W", "no-meta", "-"], src);
+    assert_eq!(stderr(&out), "checked 1 file: 0 errors, 0 warnings\n");
+
+    let out = knitlang(&["--written", "-W", "no-meta", "-"], src);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "Swatch\n\nCast on 2 sts.\nRow 1 (RS): k2.\n");
+    // Tools read every key, known or not.
+    let out = knitlang(&["--json", "-W", "no-meta", "-"], src);
+    let report: serde_json::Value = serde_json::from_str(stdout(&out).trim()).unwrap();
+    assert_eq!(
+        report["meta"],
+        serde_json::json!({ "title": "Swatch", "autor": "A. Knitter" })
+    );
+}
+
+#[test]
+fn w_help_lists_classes_and_unknown_names_are_rejected() {
+    let out = knitlang(&["check", "-W", "help"], "");
+    assert!(out.status.success());
+    assert!(stdout(&out).contains("  unused "));
+    assert!(stdout(&out).contains("  unreachable "));
+    assert!(stdout(&out).contains("  stitch-count "));
+    assert!(stdout(&out).contains("  lace "));
+
+    let out = knitlang(&["check", "-W", "no-bogus"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("unknown warning setting `no-bogus`"));
+}
+
+#[test]
+fn generate_completions_covers_every_subcommand() {
+    let out = knitlang(&["generate-completions", "bash"], "");
+    assert!(out.status.success());
+    let script = stdout(&out);
+    for sub in [
+        "run",
+        "repl",
+        "check",
+        "fmt",
+        "examples",
+        "generate-completions",
+        "generate-man",
+    ] {
+        assert!(script.contains(sub), "completion script is missing `{sub}`");
+    }
+
+    let out = knitlang(&["generate-completions", "tcsh"], "");
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn generate_man_emits_roff() {
+    let out = knitlang(&["generate-man"], "");
+    assert!(out.status.success());
+    assert!(stdout(&out).contains(".TH knitlang_v2 1"));
+    assert!(stdout(&out).contains("generate\\-completions"));
+}
+
+#[test]
+fn init_scaffolds_a_runnable_project() {
+    let dir = temp_dir("init");
+    let out = knitlang_in(&dir, &["init", "scarf"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(dir.join("scarf/.gitignore").is_file());
+
+    let out = knitlang_in(&dir, &["scarf/main.knit"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "20\n");
+
+    let out = knitlang_in(&dir, &["init", "scarf"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("scarf is not empty; use --force"));
+
+    fs::write(dir.join("scarf/main.knit"), "purl 1;").unwrap();
+    let out = knitlang_in(&dir, &["init", "scarf", "--force"], "");
+    assert!(out.status.success());
+    let out = knitlang_in(&dir, &["scarf/main.knit"], "");
+    assert_eq!(stdout(&out), "20\n");
+}
+
+#[test]
+fn parse_json_matches_golden_output() {
+    let out = knitlang(&["parse", "--format", "json", "tests/golden/ast.knit"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), include_str!("golden/ast.json"));
+
+    let out = knitlang(&["parse"], "repeat 2 {");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(stdout(&out), "");
+}
+
+#[test]
+fn parse_sexp_matches_golden_output() {
+    let out = knitlang(&["parse", "--format", "sexp", "tests/golden/sexp.knit"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), include_str!("golden/sexp.txt"));
+
+    let out = knitlang(&["parse", "--format", "sexp"], "repeat 2 { purl x; }");
+    assert_eq!(stdout(&out), "(repeat (num 2) ((purl (var x))))\n");
+}
+
+#[test]
+fn streaming_runs_statements_as_they_are_parsed() {
+    let dir = temp_dir("streaming");
+    fs::write(
+        dir.join("ok.knit"),
+        "cast_on x = 2;\nrepeat x {\n    purl x;\n}\n",
+    )
+    .unwrap();
+    fs::write(dir.join("broken.knit"), "purl 1;\npurl ;\npurl 2;\n").unwrap();
+    let out = knitlang_in(&dir, &["run", "--streaming", "ok.knit"], "");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "2\n2\n");
+    // The statement before the error has already run by the time it is found.
+    let out = knitlang_in(&dir, &["run", "--streaming", "broken.knit"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(stdout(&out), "1\n");
+    assert!(stderr(&out).contains("--> broken.knit:2:6\n"));
+}
+
+#[test]
+fn run_from_ast_executes_parse_output() {
+    let dir = temp_dir("from-ast");
+    let ast = knitlang(&["parse", "examples/swatch.knit"], "");
+    fs::write(dir.join("swatch.json"), &ast.stdout).unwrap();
+    let out = knitlang_in(&dir, &["run", "--from-ast", "swatch.json"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "12\n");
+
+    fs::write(
+        dir.join("bad.json"),
+        r#"[{"kind": "repeat", "count": {"kind": "number", "value": 2}}]"#,
+    )
+    .unwrap();
+    let out = knitlang_in(&dir, &["run", "--from-ast", "bad.json"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        stderr(&out),
+        "error[K0501]: $[0]: repeat is missing `body`\n --> bad.json\n"
+    );
+}
+
+#[test]
+fn program_output_and_diagnostics_go_to_separate_streams() {
+    let out = knitlang(
+        &["-e", "cast_on widht = 2;\npurl 1;\npurl 2;\npurl 1 / 0;"],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stdout(&out), "1\n2\n");
+    let diagnostics = stderr(&out);
+    assert!(
+        diagnostics.contains("error[K0301]: division by zero"),
+        "{diagnostics}"
+    );
+    assert!(!diagnostics.contains("\n1\n"), "{diagnostics}");
+
+    let out = knitlang(&["repl"], "purl 4;\npurl 4 / 0;\n4 + 1\n:vars\n");
+    assert_eq!(stdout(&out), "4\n5\nno variables\n");
+    let stderr = stderr(&out);
+    assert!(stderr.starts_with("KNITLANG v2"), "{stderr}");
+    assert!(stderr.contains("knit> "), "{stderr}");
+    assert!(stderr.contains("division by zero"), "{stderr}");
+}
+
+/// Runs knitlang with `stdin` and sends it SIGINT, as Ctrl-C does, once it has
+/// had time to start running, then writes `after` to stdin too.
+#[cfg(unix)]
+fn knitlang_interrupted(args: &[&str], stdin: &str, after: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_knitlang_v2"))
+        .env_remove("KNIT_PATH")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn knitlang");
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin.as_bytes()).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    // Knitlang may have exited already, and closed its end of the pipe.
+    let _ = input.write_all(after.as_bytes());
+    drop(input);
+    child.wait_with_output().unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_stops_the_run_but_still_reports_it() {
+    let endless = "cast_on 1; purl 7; repeat 1000000000 { k 1; }";
+    let out = knitlang_interrupted(&["-e", endless, "--summary"], "", "");
+    assert_eq!(out.status.code(), Some(130));
+    assert_eq!(stdout(&out), "7\n");
+    let report = stderr(&out);
+    assert!(report.contains("error[K0310]: interrupted"), "{report}");
+    assert!(report.contains("rows worked:"), "{report}");
+
+    // With `-i` the REPL starts with everything knitted so far.
+    let out = knitlang_interrupted(&["-q", "-i", "-e", endless], ":vars\n", "");
+    assert!(stderr(&out).contains("interrupted"));
+    assert!(
+        stdout(&out).contains("stitch on the needles"),
+        "{}",
+        stdout(&out)
+    );
+
+    // With nothing running, Ctrl-C ends knitlang as usual.
+    let out = knitlang_interrupted(&["--watch", "example.kl"], "", "");
+    assert_eq!(out.status.code(), Some(130));
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_in_the_repl_cancels_the_statement_not_the_session() {
+    // Interrupting a running statement keeps what came before it.
+    let out = knitlang_interrupted(
+        &["repl", "-q"],
+        "cast_on x = 5;\ncast_on 1;\nrepeat 1000000000 { k 1; knit x = 6; }\n",
+        "purl x;\nexit\n",
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stderr(&out).contains("error[K0310]: interrupted"));
+    assert_eq!(stdout(&out), "6\n");
+
+    // At the prompt it drops the unfinished block, and the session goes on.
+    let out = knitlang_interrupted(&["repl", "-q"], "repeat 2 {\npurl 1;\n", "purl 3;\n");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "3\n");
+    assert!(
+        stderr(&out).contains("...> \nknit> knit> "),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn repl_output_comes_before_the_next_prompt() {
+    let dir = temp_dir("repl-order");
+    let log = dir.join("both.txt");
+    let file = fs::File::create(&log).unwrap();
+    // Both streams into one file, as on a terminal, so their order shows.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_knitlang_v2"))
+        .env_remove("KNIT_PATH")
+        .args(["repl", "-q"])
+        .stdin(Stdio::piped())
+        .stdout(file.try_clone().unwrap())
+        .stderr(file)
+        .spawn()
+        .expect("failed to spawn knitlang");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"purl 1;\nrepeat 2 {\npurl 2;\n}\n3\n")
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "knit> 1\nknit> ...> ...> 2\n2\nknit> 3\nknit> "
+    );
+}
+
+/// Runs knitlang with stdout and stderr sent down one pipe, as `2>&1` does,
+/// so what comes out shows the order the two were written in.
+#[cfg(unix)]
+fn knitlang_interleaved(args: &[&str], stdin: &str) -> String {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg("\"$0\" \"$@\" 2>&1")
+        .arg(env!("CARGO_BIN_EXE_knitlang_v2"))
+        .args(args);
+    stdout(&output(&mut command, stdin))
+}
+
+#[cfg(unix)]
+#[test]
+fn errors_come_after_the_output_before_them() {
+    let both = knitlang_interleaved(&["-e", "purl 1; purl 2; purl 1 / 0; purl 3;"], "");
+    assert!(
+        both.starts_with("1\n2\nerror[K0301]: division by zero\n"),
+        "{both}"
+    );
+
+    let both = knitlang_interleaved(&["--stdin"], "purl 1;\npurl 1 / 0;\npurl 2; purl 2 / 0;\n");
+    // Without the snippets under each error.
+    let errors: Vec<&str> = both
+        .lines()
+        .filter(|line| !line.starts_with(' ') && !line.contains(" | "))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            "1",
+            "error[K0301]: division by zero",
+            "2",
+            "error[K0301]: division by zero"
+        ],
+        "{both}"
+    );
+}
+
+#[test]
+fn optimizing_never_changes_what_a_program_prints() {
+    let mut args: Vec<Vec<String>> = ["example.kl", "templates/main.knit"]
+        .into_iter()
+        .chain(["tests/golden/ast.knit", "tests/golden/tokens.knit"])
+        .map(|path| vec![path.to_string()])
+        .collect();
+    for entry in fs::read_dir("examples").unwrap() {
+        args.push(vec![entry.unwrap().path().display().to_string()]);
+    }
+    for src in [
+        "cast_on a = 0; repeat 2 { knit a = a + 1; purl a; } repeat 2 * 3 - 1 { purl a * 10; }",
+        "cast_on 2; repeat 3 { repeat 2 { k 1; p 1; } purl 7; }",
+        "repeat 3 { purl 4 / 2 - 2; purl 1 / (4 / 2 - 2); }",
+        "cast_on 4; repeat 3 { k 2; p 1; }",
+        "repeat 2 { purl 9223372036854775807 + 1; }",
+        "repeat 0 { cast_on unset = 1; } purl 1; purl unset;",
+        "repeat 1 - 3 { purl 1; } purl 2;",
+        "cast_on 1; repeat 4 { purl 1; k 1; bind_off; }",
+    ] {
+        args.push(vec!["-e".to_string(), src.to_string()]);
+        args.push(vec!["--rows".into(), "2".into(), "-e".into(), src.into()]);
+    }
+    for args in args {
+        let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let plain = knitlang(&args, "");
+        args.insert(0, "-O");
+        let optimized = knitlang(&args, "");
+        assert_eq!(optimized.stdout, plain.stdout, "{args:?}");
+        assert_eq!(stderr(&optimized), stderr(&plain), "{args:?}");
+        assert_eq!(optimized.status.code(), plain.status.code(), "{args:?}");
+    }
+}
+
+#[test]
+fn verbose_optimizing_reports_what_it_changed() {
+    let src = "cast_on a = 2 * 3; repeat 2 { repeat 1 + 1 { purl a; } } \
+               repeat 9 { purl a; } repeat 2 { bind_off; }";
+    let out = knitlang(&["-O", "-v", "-e", src], "");
+    assert_eq!(stdout(&out), "6\n".repeat(13));
+    assert!(
+        stderr(&out)
+            .contains("optimized <eval>: folded 2 constant operations, unrolled 2 repeats\n"),
+        "{}",
+        stderr(&out)
+    );
+    // The nested pair becomes four `purl`s, so 17 statements run instead of 20.
+    assert!(
+        stderr(&out).contains("executed 17 statements"),
+        "{}",
+        stderr(&out)
+    );
+    assert!(stderr(&knitlang(&["-v", "-e", src], "")).contains("executed 20 statements"));
+    let out = knitlang(&["-O", "--unroll-limit", "1", "-v", "-e", src], "");
+    assert!(stderr(&out).contains("unrolled 0 repeats\n"));
+    assert_eq!(
+        knitlang(&["--unroll-limit", "2", "-e", src], "")
+            .status
+            .code(),
+        Some(3)
+    );
+    // Without -O nothing is rewritten, or reported.
+    assert!(!stderr(&knitlang(&["-v", "-e", src], "")).contains("optimized"));
+}
+
+#[test]
+fn max_nesting_limits_every_command_that_parses() {
+    let two = "repeat 1 { repeat 1 { purl 1; } }";
+    let three = "repeat 1 { repeat 1 { repeat 1 { purl 1; } } }";
+    assert_eq!(
+        stdout(&knitlang(&["--max-nesting", "2", "-e", two], "")),
+        "1\n"
+    );
+    let out = knitlang(&["--max-nesting", "2", "-e", three], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        stderr(&out),
+        "error[K0108]: Nesting too deep (limit 2)\n \
+         --> <eval>:1:23\n  |\n\
+         1 | repeat 1 { repeat 1 { repeat 1 { purl 1; } } }\n  \
+         |                       ^~~~~~\n"
+    );
+    for args in [&["check", "-"][..], &["--stdin"], &["parse"], &["fmt", "-"]] {
+        let args: Vec<&str> = ["--max-nesting", "2"].iter().chain(args).copied().collect();
+        let out = knitlang(&args, three);
+        assert_eq!(out.status.code(), Some(2), "{args:?}");
+        assert!(stderr(&out).contains("error[K0108]"), "{args:?}");
+    }
+    assert!(knitlang(&["check", "-"], three).status.success());
+}
+
+#[test]
+fn max_nesting_allows_up_to_a_depth_every_command_survives() {
+    let depth = 1024;
+    let src = format!(
+        "cast_on 1;\n{}k 1;\n{}",
+        "repeat 1 {\n".repeat(depth),
+        "}\n".repeat(depth)
+    );
+    let limit = depth.to_string();
+    for args in [
+        &["--stdin"][..],
+        &["run", "-O", "-"],
+        &["run", "--written", "-"],
+        &["run", "--streaming", "-"],
+        &["check", "-"],
+        &["fmt", "-"],
+        &["parse", "-"],
+        &["parse", "--format", "sexp", "-"],
+    ] {
+        let args: Vec<&str> = ["--max-nesting", &limit]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect();
+        let out = knitlang(&args, &src);
+        assert!(out.status.success(), "{args:?}: {}", stderr(&out));
+    }
+
+    let out = knitlang(&["--max-nesting", "1025", "-e", "purl 1;"], "");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("1025 is not in 0..=1024"));
+}
+
+#[test]
+fn stitch_count_checkpoints_fail_or_warn() {
+    let out = knitlang(&["-e", "cast_on 4; k 4; expect_stitches 5;"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        stderr(&out),
+        "error[K0324]: expected 5 stitches on the needles, but there are 4\n \
+         --> <eval>:1:17\n  |\n\
+         1 | cast_on 4; k 4; expect_stitches 5;\n  \
+         |                 ^~~~~~~~~~~~~~~~~~\n"
+    );
+
+    let src = "cast_on 4; k 4; expect_stitches? 5;";
+    let out = knitlang(&["-e", src], "");
+    assert!(out.status.success());
+    assert_eq!(
+        stderr(&out),
+        "warning[K0406]: expected 5 stitches on the needles, but there are 4\n \
+         --> <eval>:1:17\n  |\n\
+         1 | cast_on 4; k 4; expect_stitches? 5;\n  \
+         |                 ^~~~~~~~~~~~~~~~~~~\n"
+    );
+    let out = knitlang(&["-W", "error", "-e", src], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).starts_with("error[K0406]"));
+    let out = knitlang(&["-W", "no-checkpoint", "-e", src], "");
+    assert!(out.status.success());
+    assert_eq!(stderr(&out), "");
+}
+
+#[test]
+fn notes_print_only_when_verbose() {
+    let src = "knit n = 4; cast_on n; note \"Cast on \", n, \" sts\"; k n;";
+    let out = knitlang(&["-e", src], "");
+    assert!(out.status.success());
+    assert!(!stderr(&out).contains("note"), "{}", stderr(&out));
+    assert!(stderr(&knitlang(&["-v", "-e", src], "")).contains("note: Cast on 4 sts\n"));
+    assert_eq!(
+        stdout(&knitlang(&["--written", "-e", src], "")),
+        "Cast on 4 sts.\nCast on 4 sts\nRow 1 (RS): k4.\n"
+    );
+}
+
+#[test]
+fn chart_statements_write_to_the_output_file() {
+    let dir = temp_dir("chart-statements");
+    let path = dir.join("pattern.txt");
+    let src = "cast_on 3; purl 3; k 3; chart \"Row 1\"; p 3; chart;";
+    let out = knitlang(&["--output", path.to_str().unwrap(), "-e", src], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "");
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "3\nRow 1\n│││ 1 RS\n321\n│ knit\nWS 2 │││\n     321\n│ knit on RS, purl on WS\n"
+    );
+}
+
+#[test]
+fn export_html_matches_the_golden_page() {
+    let dir = temp_dir("html");
+    let path = dir.join("hat.html");
+    let out = knitlang(
+        &[
+            "tests/golden/html_hat.knit",
+            "--export",
+            "html",
+            "-o",
+            path.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "");
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        include_str!("golden/html_hat.html")
+    );
+}
+
+#[test]
+fn export_markdown_matches_the_golden_files() {
+    for (source, golden) in [
+        ("examples/cable_panel.knit", "tests/golden/cable_panel.md"),
+        ("examples/swatch.knit", "tests/golden/swatch.md"),
+        ("tests/golden/html_hat.knit", "tests/golden/html_hat.md"),
+    ] {
+        let out = knitlang(&[source, "--export", "markdown"], "");
+        assert!(out.status.success(), "{}", stderr(&out));
+        assert_eq!(
+            stdout(&out),
+            fs::read_to_string(golden).unwrap(),
+            "{source}"
+        );
+    }
+
+    // With an image, the chart is written as a PNG and linked to.
+    let dir = temp_dir("markdown");
+    let image = dir.join("chart.png");
+    let image = image.to_str().unwrap();
+    let out = knitlang(
+        &[
+            "examples/swatch.knit",
+            "--export",
+            "markdown",
+            "--chart-image",
+            image,
+        ],
+        "",
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).ends_with(&format!("## Chart\n\n![Chart](<{image}>)\n")));
+    assert!(fs::read(image).unwrap().starts_with(b"\x89PNG"));
+
+    let out = knitlang(
+        &[
+            "--export",
+            "html",
+            "--chart-image",
+            image,
+            "-e",
+            "cast_on 1;",
+        ],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(3));
+    assert!(stderr(&out).contains("--chart-image only works with --export markdown"));
+}
+
+#[test]
+fn export_csv_writes_a_record_per_row() {
+    let out = knitlang(&["--export", "csv", "-e", "cast_on 2; k 1; p 1; k 2;"], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "row,side,1,2\r\n1,RS,k,p\r\n2,WS,p,p\r\n");
+}
+
+#[test]
+fn animate_without_a_terminal_runs_as_usual() {
+    let src = "cast_on 4; k 4; purl 4;";
+    let plain = knitlang(&["-e", src], "");
+    let out = knitlang(&["--animate", "--animate-delay", "1s", "-e", src], "");
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), stdout(&plain));
+    assert!(!stdout(&out).contains('\x1b'));
+    // The delay means nothing without the animation.
+    let out = knitlang(&["--animate-delay", "1s", "-e", src], "");
+    assert!(!out.status.success());
+}
+
+#[test]
+fn ascii_charts_for_terminals_that_cannot_show_unicode() {
+    let pattern = "cast_on 3; k 2; p 1;";
+    let chart = |env: &[(&str, &str)], flags: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_knitlang_v2"));
+        command.env("LC_ALL", "C.UTF-8").env_remove("TERM");
+        command.envs(env.iter().copied());
+        let out = command
+            .args(flags)
+            .args(["--chart-format", "text", "-e", pattern])
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", stderr(&out));
+        stdout(&out)
+    };
+    let (unicode, ascii) = (
+        "•││ 1 RS\n321\n│ knit\n• purl\n",
+        "-|| 1 RS\n321\n| knit\n- purl\n",
+    );
+    assert_eq!(chart(&[], &[]), unicode);
+    assert_eq!(chart(&[], &["--ascii"]), ascii);
+    assert_eq!(chart(&[("TERM", "dumb")], &[]), ascii);
+    assert_eq!(chart(&[("LC_ALL", "C")], &[]), ascii);
+    assert_eq!(
+        chart(&[("LC_ALL", ""), ("LANG", "en_GB.ISO-8859-1")], &[]),
+        ascii
+    );
+    assert_eq!(
+        chart(&[("LC_ALL", ""), ("LANG", "en_GB.utf8")], &[]),
+        unicode
+    );
+    // A style asked for by name is used whatever the terminal.
+    assert_eq!(
+        chart(&[("TERM", "dumb")], &["--chart-style", "unicode"]),
+        unicode
+    );
+    assert_eq!(
+        chart(&[], &["--ascii", "--chart-style", "japanese"]),
+        "─││ 1 RS\n321\n│ knit\n─ purl\n"
+    );
+
+    // `chart` statements follow it too.
+    let out = knitlang(&["--ascii", "-e", "cast_on 2; k 2; chart;"], "");
+    assert_eq!(stdout(&out), "|| 1 RS\n21\n| knit\n");
+}
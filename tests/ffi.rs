@@ -0,0 +1,81 @@
+//! Drives the C interface from Rust: `cargo test --features ffi --test ffi`.
+#![cfg(feature = "ffi")]
+
+use knitlang::ffi::*;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr;
+
+unsafe extern "C" fn collect(user_data: *mut c_void, text: *const c_char) {
+    let lines = &mut *(user_data as *mut Vec<String>);
+    lines.push(CStr::from_ptr(text).to_str().unwrap().to_string());
+}
+
+fn last_error(interp: *mut KnitInterp) -> Option<String> {
+    let message = unsafe { knit_last_error(interp) };
+    (!message.is_null()).then(|| {
+        unsafe { CStr::from_ptr(message) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    })
+}
+
+fn run(interp: *mut KnitInterp, src: &str) -> i32 {
+    let src = CString::new(src).unwrap();
+    unsafe { knit_run_source(interp, src.as_ptr()) }
+}
+
+#[test]
+fn runs_source_and_sends_output_to_the_callback() {
+    let mut lines: Vec<String> = Vec::new();
+    let interp = knit_interp_new();
+    unsafe { knit_set_output(interp, Some(collect), &mut lines as *mut _ as *mut c_void) };
+
+    assert_eq!(run(interp, "cast_on x = 6; purl x; purl x * 7;"), KNIT_OK);
+    assert_eq!(last_error(interp), None);
+    // Variables carry over to the next run.
+    assert_eq!(run(interp, "knit x = x + 1;"), KNIT_OK);
+
+    let mut value = 0;
+    let name = CString::new("x").unwrap();
+    assert!(unsafe { knit_get_var(interp, name.as_ptr(), &mut value) });
+    assert_eq!(value, 7);
+    let name = CString::new("missing").unwrap();
+    assert!(!unsafe { knit_get_var(interp, name.as_ptr(), &mut value) });
+
+    unsafe { knit_interp_free(interp) };
+    assert_eq!(lines, ["6\n", "42\n"]);
+}
+
+#[test]
+fn reports_errors_through_codes_and_last_error() {
+    let interp = knit_interp_new();
+    assert_eq!(run(interp, "purl 1;\nrepeat 2 {"), KNIT_ERR_PARSE);
+    assert!(last_error(interp).unwrap().starts_with("2:11: "));
+
+    assert_eq!(run(interp, "purl 1;\npurl 1 / 0;"), KNIT_ERR_RUNTIME);
+    assert_eq!(last_error(interp).unwrap(), "2:10: division by zero");
+
+    assert_eq!(
+        unsafe { knit_run_source(interp, ptr::null()) },
+        KNIT_ERR_ARGUMENT
+    );
+    assert_eq!(last_error(interp).unwrap(), "source is NULL");
+    let invalid = [0xff_u8, 0];
+    let status = unsafe { knit_run_source(interp, invalid.as_ptr() as *const c_char) };
+    assert_eq!(status, KNIT_ERR_ARGUMENT);
+    assert!(last_error(interp)
+        .unwrap()
+        .starts_with("source is not valid UTF-8"));
+
+    assert_eq!(run(interp, "purl 1;"), KNIT_OK);
+    assert_eq!(last_error(interp), None);
+    unsafe { knit_interp_free(interp) };
+
+    assert_eq!(
+        unsafe { knit_run_source(ptr::null_mut(), ptr::null()) },
+        KNIT_ERR_ARGUMENT
+    );
+    assert!(unsafe { knit_last_error(ptr::null()) }.is_null());
+    unsafe { knit_interp_free(ptr::null_mut()) };
+}
@@ -0,0 +1,3481 @@
+use knitlang::interp::Side;
+use knitlang::{run_source, Cross, KnitError, RunOptions, Stitch};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// An output sink the test can read back after handing it to the interpreter.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn library_runs_programs_and_exposes_the_pipeline() {
+    let interp = knitlang::run(
+        "cast_on width = 4; cast_on width; k width;",
+        Box::new(io::sink()),
+    )
+    .unwrap();
+    assert_eq!(interp.get_var("width"), Some(4));
+    assert_eq!(interp.fabric().rows().len(), 1);
+
+    let tokens = knitlang::lex_all("purl 1;").unwrap();
+    assert_eq!(tokens[0].0, knitlang::Token::Purl);
+    let stmts = knitlang::Parser::new(tokens).parse().unwrap();
+    assert_eq!(knitlang::ast::format_program(&stmts), "purl 1;\n");
+
+    let err = knitlang::run("purl 1 / 0;", Box::new(io::sink()))
+        .err()
+        .unwrap();
+    assert!(matches!(err, knitlang::KnitError::Runtime(_)));
+    assert_eq!(err.to_string(), "division by zero");
+    assert!(matches!(
+        knitlang::run("purl @;", Box::new(io::sink())),
+        Err(knitlang::KnitError::Lex(_))
+    ));
+}
+
+#[test]
+fn run_source_collects_output_variables_and_stats() {
+    let src = "cast_on width = 4;\ncast_on width;\nrepeat 3 {\n    k width;\n}\npurl width;\nbind_off;\npurl 99;\n";
+    let out = run_source(src, RunOptions::default()).unwrap();
+    assert_eq!(out.stdout, "4\n");
+    assert_eq!(
+        out.variables.into_iter().collect::<Vec<_>>(),
+        [("width".to_string(), 4)]
+    );
+    assert_eq!(out.stats.rows, 3);
+    assert_eq!(out.stats.stitches, 12);
+    assert!(out.bound_off);
+    assert!(!out.truncated);
+}
+
+#[test]
+fn run_source_honours_row_limit_and_sink() {
+    let sink = Shared::default();
+    let options = RunOptions {
+        row_limit: Some(2),
+        output: Some(Box::new(sink.clone())),
+        ..RunOptions::default()
+    };
+    let src = "cast_on 1; repeat 5 { purl 7; k 1; }";
+    let out = run_source(src, options).unwrap();
+    assert_eq!(out.stdout, "7\n7\n");
+    assert_eq!(*sink.0.borrow(), b"7\n7\n");
+    assert_eq!(out.stats.rows, 2);
+    assert!(out.truncated);
+    assert!(!out.bound_off);
+}
+
+#[test]
+fn run_source_reports_parse_and_runtime_errors() {
+    let err = run_source("repeat 2 {\n    purl 1;\n", RunOptions::default()).unwrap_err();
+    assert!(matches!(err, KnitError::Parse(_)), "{err:?}");
+
+    let err = run_source("purl 1;\nk 3;\n", RunOptions::default()).unwrap_err();
+    let KnitError::Runtime(e) = &err else {
+        panic!("expected a runtime error, got {err:?}");
+    };
+    assert_eq!(e.span.start, 8);
+    assert!(err.to_string().starts_with("no stitches to work k"));
+}
+
+#[test]
+fn ast_to_json_handles_large_programs() {
+    let src = "cast_on x = 0;\n".to_string() + &"knit x = x + 1;\n".repeat(10_000);
+    let stmts = knitlang::parse_src(&src).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&knitlang::ast_to_json(&stmts)).unwrap();
+    let program = json.as_array().unwrap();
+    assert_eq!(program.len(), 10_001);
+    assert_eq!(program[10_000]["kind"], "knit");
+    assert_eq!(program[10_000]["value"]["op"], "+");
+    // Serialize goes through the same shape.
+    assert_eq!(serde_json::to_value(&stmts[0]).unwrap(), program[0]);
+}
+
+#[test]
+fn sexp_golden_covers_every_kind_of_statement_and_expression() {
+    use knitlang::StmtKind;
+
+    // An arm for every variant, so a new kind of statement does not compile
+    // here until the golden program has one.
+    fn index(kind: &StmtKind) -> usize {
+        match kind {
+            StmtKind::CastOn(..) => 0,
+            StmtKind::CastOnStitches(_) => 1,
+            StmtKind::CastOnNeedle(..) => 2,
+            StmtKind::Knit(..) => 3,
+            StmtKind::Purl(_) => 4,
+            StmtKind::Work(..) => 5,
+            StmtKind::Repeat(..) => 6,
+            StmtKind::BindOff => 7,
+            StmtKind::BindOffStitches(_) => 8,
+            StmtKind::ExpectStitches(_) => 9,
+            StmtKind::ExpectStitchesWarning(_) => 10,
+            StmtKind::Yarn(..) => 11,
+            StmtKind::Color(_) => 12,
+            StmtKind::Cable(..) => 13,
+            StmtKind::WrapTurn => 14,
+            StmtKind::Mode(_) => 15,
+            StmtKind::PlaceMarker(_) => 16,
+            StmtKind::SlipMarker(_) => 17,
+            StmtKind::Use(..) => 18,
+            StmtKind::Sizes(_) => 19,
+            StmtKind::Gauge(..) => 20,
+            StmtKind::Distribute(..) => 21,
+            StmtKind::Mirror(_) => 22,
+            StmtKind::Alternate(_) => 23,
+            StmtKind::Pattern(..) => 24,
+            StmtKind::Meta(_) => 25,
+            StmtKind::Note(_) => 26,
+            StmtKind::Chart(_) => 27,
+            StmtKind::Transfer(..) => 28,
+            StmtKind::Hold(_) => 29,
+            StmtKind::Resume(_) => 30,
+        }
+    }
+    fn visit(stmts: &[knitlang::Stmt], seen: &mut [bool; 31]) {
+        for stmt in stmts {
+            seen[index(&stmt.kind)] = true;
+            for body in stmt.kind.bodies() {
+                visit(body, seen);
+            }
+        }
+    }
+    let src = include_str!("golden/sexp.knit");
+    let stmts = knitlang::parse_src(src).unwrap();
+    let mut seen = [false; 31];
+    visit(&stmts, &mut seen);
+    assert_eq!(seen.iter().position(|seen| !seen), None);
+
+    let sexp = knitlang::ast_to_sexp(&stmts);
+    for form in [
+        "(num ",
+        "(var ",
+        "(+ ",
+        "(call ",
+        "(stitches_to ",
+        "(sizes ",
+        "(count ",
+    ] {
+        assert!(sexp.contains(form), "{form}");
+    }
+    // The tree loaded from its JSON is written the same way.
+    let loaded = knitlang::ast_from_json(&knitlang::ast_to_json(&stmts)).unwrap();
+    assert_eq!(knitlang::ast_to_sexp(&loaded), sexp);
+}
+
+#[test]
+fn sexp_strings_are_escaped() {
+    // The lexer has no escapes, so the label is put in through the JSON,
+    // which takes anything on one line without a double quote.
+    let json = knitlang::ast_to_json(&knitlang::parse_src("chart \"x\";").unwrap());
+    let json = json.replace("\"x\"", r#""C:\\charts\tnew\u0007""#);
+    let stmts = knitlang::ast_from_json(&json).unwrap();
+    assert_eq!(
+        knitlang::ast_to_sexp(&stmts),
+        "(chart \"C:\\\\charts\\tnew\\u{7}\")\n"
+    );
+}
+
+fn run_stmts(stmts: &[knitlang::Stmt]) -> (String, Result<bool, String>) {
+    let out = Shared::default();
+    let mut interp = knitlang::Interpreter::with_output(Box::new(out.clone()));
+    let result = interp.run(stmts).map_err(|e| e.to_string());
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    (text, result)
+}
+
+#[test]
+fn ast_json_round_trips_to_the_same_run() {
+    let sources = [
+        include_str!("../examples/hello.knit"),
+        include_str!("../examples/swatch.knit"),
+        include_str!("golden/ast.knit"),
+        "cast_on 2; k 1; cast_on 1;",
+    ];
+    for src in sources {
+        let parsed = knitlang::parse_src(src).unwrap();
+        let loaded = knitlang::ast_from_json(&knitlang::ast_to_json(&parsed)).unwrap();
+        assert_eq!(
+            knitlang::ast_to_json(&loaded),
+            knitlang::ast_to_json(&parsed)
+        );
+        assert_eq!(run_stmts(&loaded), run_stmts(&parsed), "{src}");
+    }
+}
+
+#[test]
+fn ast_from_json_rejects_invalid_trees() {
+    let error = |json: &str| knitlang::ast_from_json(json).unwrap_err().to_string();
+    assert!(error("[1, 2").starts_with("$: invalid JSON: "));
+    assert_eq!(
+        error(r#"{"kind": "purl"}"#),
+        r#"$: expected an array of statements, not {"kind":"purl"}"#
+    );
+    assert_eq!(
+        error(r#"[{"kind": "frog"}]"#),
+        "$[0]: unknown statement kind `frog`"
+    );
+    assert_eq!(
+        error(r#"[{"kind": "bind_off"}, {"kind": "purl", "value": {"kind": "var"}}]"#),
+        "$[1].value: var is missing `name`"
+    );
+    assert_eq!(
+        error(r#"[{"kind": "bind_off", "count": 1}]"#),
+        "$[0]: unexpected key `count` in bind_off"
+    );
+    assert_eq!(
+        error(r#"[{"kind": "work", "stitch": "tbl", "count": {"kind": "number", "value": 1}}]"#),
+        "$[0].stitch: unknown stitch `tbl`"
+    );
+    assert_eq!(
+        error(r#"[{"kind": "purl", "value": {"kind": "number", "value": 1.5}}]"#),
+        "$[0].value.value: expected a 64-bit integer, not 1.5"
+    );
+    assert_eq!(
+        error(r#"[{"kind": "yarn", "name": "MC", "color": "blue"}]"#),
+        "$[0].color: `blue` is not a colour; write it as #rgb or #rrggbb"
+    );
+    assert_eq!(
+        error(r#"[{"kind": "cable", "count": {"kind": "number", "value": 2}, "cross": "up"}]"#),
+        "$[0].cross: a cable crosses `front` or `back`, not `up`"
+    );
+}
+
+#[test]
+fn try_parse_reports_known_crashers_as_errors() {
+    let messages = |src: &str| -> Vec<String> {
+        knitlang::try_parse(src)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.message)
+            .collect()
+    };
+    // Truncated statements end in an error at end of input.
+    for src in [
+        "cast_on",
+        "cast_on x =",
+        "knit x",
+        "repeat 3 {",
+        "k",
+        "purl 1 +",
+    ] {
+        assert_eq!(messages(src).len(), 1, "{src}");
+    }
+    assert_eq!(messages("}"), ["Unknown statement start: `}`"]);
+    assert_eq!(
+        messages("purl 99999999999999999999;"),
+        ["Number literal too large; the largest is 9223372036854775807"]
+    );
+    assert_eq!(
+        knitlang::try_lex("9223372036854775807").unwrap(),
+        [knitlang::Token::Number(i64::MAX)]
+    );
+    assert!(knitlang::try_lex("9223372036854775808").is_err());
+}
+
+#[test]
+fn parse_errors_show_tokens_as_they_are_written() {
+    let message = |src| knitlang::parse_src(src).unwrap_err().message;
+    assert_eq!(
+        message("purl x = 1;"),
+        "Expected ; after purl statement, found `=`"
+    );
+    assert_eq!(message("knit 4 = 1;"), "Expected identifier, found: `4`");
+    assert_eq!(
+        message("k 1; w&t w&t;"),
+        "Expected ; after wrap_turn, found `wrap_turn`"
+    );
+    assert_eq!(
+        message("meta { name = \"a\" \"b\" }"),
+        "Expected ; after meta entry, found `\"b\"`"
+    );
+    assert_eq!(message("purl"), "Unexpected token in term: end of input");
+}
+
+#[test]
+fn try_parse_recovers_to_report_every_error() {
+    let src = "purl ;\nrepeat 2 {\n    k ;\n    p 1;\n}\npurl 1;\n}\nknit = 2;\n";
+    let errors = knitlang::try_parse(src).unwrap_err();
+    let starts: Vec<_> = errors.iter().map(|e| e.span.start).collect();
+    // One error per broken statement: `purl ;`, the repeat, the stray `}`,
+    // and `knit = 2;`. The good `purl 1;` in between is not reported.
+    assert_eq!(starts, [5, 24, 45, 52]);
+
+    let errors = knitlang::try_parse("@ purl 1; #").unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(knitlang::try_parse("purl 1; k 2;").unwrap().len(), 2);
+}
+
+#[test]
+fn step_limit_stops_runaway_programs() {
+    let options = RunOptions {
+        step_limit: Some(100),
+        ..RunOptions::default()
+    };
+    let src = "cast_on x = 0; repeat 1000000000 { knit x = x + 1; }";
+    let err = run_source(src, options).unwrap_err();
+    assert_eq!(err.to_string(), "step limit of 100 reached");
+}
+
+#[test]
+fn memory_limit_stops_programs_that_hold_too_much() {
+    let limited = |bytes| RunOptions {
+        memory_limit: Some(bytes),
+        ..RunOptions::default()
+    };
+    // Every stitch of every row is kept, so a long loop of short rows grows
+    // the fabric without end.
+    let src = "cast_on 1; repeat 1000000000 { k 1; }";
+    let err = run_source(src, limited(64 * 1024)).unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("memory limit exceeded: the run holds "),
+        "{err}"
+    );
+    // So does a program with a great many variables.
+    let src: String = (0..10_000)
+        .map(|i| format!("cast_on v{i} = {i};\n"))
+        .collect();
+    let err = run_source(&src, limited(64 * 1024)).unwrap_err();
+    assert!(
+        err.to_string().starts_with("memory limit exceeded"),
+        "{err}"
+    );
+    assert!(run_source(&src, limited(1024 * 1024)).is_ok());
+
+    // What the run holds only grows as it goes, and reloading a smaller
+    // state gives the memory back.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 4;").unwrap())
+        .unwrap();
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut used = interp.memory_used();
+    for src in ["cast_on w = 1;", "k 4;", "knit w = 2;", "p 4; k 2;"] {
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        assert!(interp.memory_used() >= used, "{src}");
+        used = interp.memory_used();
+    }
+    interp.set_memory_limit(Some(used));
+    let err = interp
+        .run(&knitlang::parse_src("k 2;").unwrap())
+        .unwrap_err();
+    assert_eq!(err.code, knitlang::Code::MemoryLimit);
+    interp.load_state(&saved[..]).unwrap();
+    assert!(interp.memory_used() < used);
+}
+
+#[test]
+fn negative_repeat_counts_are_errors() {
+    let run = |src: &str| {
+        run_source(src, RunOptions::default())
+            .map(|out| out.stdout)
+            .map_err(|e| (e.to_string(), e.span()))
+    };
+    assert_eq!(
+        run("repeat 1 { purl 1; } repeat 0 { purl 0; }"),
+        Ok("1\n".into())
+    );
+    assert_eq!(
+        run("repeat 0 - 1 { purl 1; }"),
+        Err(("cannot repeat -1 times".into(), knitlang::Span::new(7, 12)))
+    );
+    assert_eq!(
+        run("repeat 0 - 9223372036854775807 - 1 { purl 1; }")
+            .unwrap_err()
+            .0,
+        "cannot repeat -9223372036854775808 times"
+    );
+    // A count that goes negative part way through stops the run there, after
+    // the passes that did run.
+    let src = "cast_on n = 2;\nrepeat 3 {\n    knit n = n - 1;\n    repeat n { purl n; }\n}";
+    let err = run_source(src, RunOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "cannot repeat -1 times");
+    assert_eq!(err.span(), knitlang::Span::new(57, 58));
+    assert_eq!(err.code(), knitlang::Code::NegativeCount);
+}
+
+#[test]
+fn colorwork_records_and_charts_the_yarn_of_every_stitch() {
+    let src = "yarn MC = \"#1d3557\";\nyarn CC = \"#e63946\";\ncast_on 4;\n\
+               repeat 2 {\n    repeat 2 { color MC; k 1; color CC; k 1; }\n    \
+               repeat 2 { color CC; k 1; color MC; k 1; }\n}\n";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let names: Vec<_> = fabric.yarns().iter().map(|y| y.name.as_str()).collect();
+    assert_eq!(names, ["MC", "CC"]);
+    assert_eq!(fabric.yarns()[1].colour, "#e63946");
+    for row in 0..4 {
+        for stitch in 0..4 {
+            let yarn = (row + stitch) % 2;
+            assert_eq!(fabric.yarn_at(row, stitch), Some(yarn), "{row}, {stitch}");
+        }
+    }
+    assert_eq!(fabric.yarn_at(4, 0), None);
+    assert_eq!(
+        knitlang::chart::text(fabric),
+        "WS 4 baba\n     BABA 3 RS\nWS 2 baba\n     BABA 1 RS\n     4321\nA MC #1d3557\nB CC #e63946\n"
+    );
+    let svg = knitlang::chart::svg(fabric, &Default::default());
+    assert!(
+        svg.contains("<rect x=\"86\" y=\"70\" width=\"20\" height=\"20\" fill=\"#1d3557\"/>"),
+        "{svg}"
+    );
+    assert!(knitlang::written::instructions(fabric)
+        .contains("Row 2 (WS): *k1 CC, k1 MC; rep from * to end.\n"));
+
+    // Stitches before any `color` have no yarn, and purls are small letters.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let src = "cast_on 3; k 1; yarn MC = \"#000\"; color MC; p 1; yarn MC = \"#fff\"; k 1;";
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    assert_eq!(interp.fabric().yarn_at(0, 0), None);
+    assert_eq!(interp.fabric().yarns()[0].colour, "#fff");
+    assert_eq!(
+        knitlang::chart::text(interp.fabric()),
+        "Aa│ 1 RS\n321\n│ knit\nA MC #fff\n"
+    );
+
+    // Saved sessions keep the yarns.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    assert_eq!(
+        knitlang::chart::text(resumed.fabric()),
+        knitlang::chart::text(interp.fabric())
+    );
+    resumed
+        .run(&knitlang::parse_src("cast_on 0; k 3;").unwrap())
+        .unwrap();
+    assert_eq!(resumed.fabric().yarn_at(1, 0), Some(0));
+}
+
+#[test]
+fn cables_cross_groups_of_stitches_in_one_cell() {
+    let run = |src: &str| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        interp
+    };
+    let interp = run("cast_on 6; p 1; c4b; p 1; k 1; cable 2 front; k 1;");
+    let fabric = interp.fabric();
+    assert_eq!(
+        fabric.rows()[0],
+        [Stitch::Purl, Stitch::Cable(2, Cross::Back), Stitch::Purl]
+    );
+    assert_eq!(fabric.rows()[1][1], Stitch::Cable(2, Cross::Front));
+    assert_eq!(fabric.width(), 6);
+    assert_eq!(
+        knitlang::chart::text(fabric),
+        "WS 2 •««««•\n     •»»»»• 1 RS\n     654321\n\
+         • purl on RS, knit on WS\n\
+         « cable, crossing in front\n» cable, crossing behind\n"
+    );
+    assert_eq!(
+        knitlang::written::instructions(fabric),
+        "Cast on 6 sts.\nRow 1 (RS): p1, C4B, p1.\nRow 2 (WS): k1, C4F, k1.\n"
+    );
+    let svg = knitlang::chart::svg(fabric, &Default::default());
+    assert!(
+        svg.contains("<rect x=\"46\" y=\"30\" width=\"80\" height=\"20\"/>"),
+        "{svg}"
+    );
+
+    // `c4f` works one cable unless given a count, and prints as written.
+    let text = |src: &str| knitlang::chart::text(run(src).fabric());
+    assert_eq!(
+        text("cast_on 8; c4f 2;"),
+        text("cast_on 8; cable 2 front; c4f;")
+    );
+    for src in ["c6b;\n", "c4f 2;\n", "cable 3 back;\n"] {
+        assert_eq!(
+            knitlang::format_program(&knitlang::parse_src(src).unwrap()),
+            src
+        );
+    }
+
+    // Saved sessions keep the cables.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    assert_eq!(resumed.fabric().rows(), interp.fabric().rows());
+}
+
+/// A flat swatch no flip or inversion leaves looking the same: two rows of
+/// four stitches, knitted `k2, p2` and then `p1, k3`.
+fn orientation_swatch() -> knitlang::Interpreter {
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 4; k 2; p 2; p 1; k 3;").unwrap())
+        .unwrap();
+    interp
+}
+
+#[test]
+fn charts_put_row_one_at_the_origin() {
+    use knitlang::chart::{Orientation, Origin};
+
+    let interp = orientation_swatch();
+    let fabric = interp.fabric();
+    let top = Orientation {
+        origin: Origin::Top,
+        ..Orientation::default()
+    };
+    assert_eq!(
+        knitlang::chart::text(fabric),
+        "WS 2 │•••\n     ••││ 1 RS\n     4321\n\
+         │ knit on RS, purl on WS\n• purl on RS, knit on WS\n"
+    );
+    assert_eq!(
+        knitlang::chart::text_oriented(fabric, top),
+        "     ••││ 1 RS\nWS 2 │•••\n     4321\n\
+         │ knit on RS, purl on WS\n• purl on RS, knit on WS\n"
+    );
+    // Row 1's cells move from the bottom of the grid to the top.
+    let svg = |orientation| {
+        let style = knitlang::chart::Style {
+            orientation,
+            ..Default::default()
+        };
+        knitlang::chart::svg(fabric, &style)
+    };
+    let (bottom, top) = (svg(Orientation::default()), svg(top));
+    let first = "<circle cx=\"56\" cy=\"";
+    assert!(
+        bottom.contains(&format!("{first}40\" r=\"3\"/>")),
+        "{bottom}"
+    );
+    assert!(top.contains(&format!("{first}20\" r=\"3\"/>")), "{top}");
+    assert!(top.contains("y=\"20\" dy=\"0.35em\">1</text>"), "{top}");
+}
+
+#[test]
+fn charts_read_wrong_side_rows_from_the_left() {
+    use knitlang::chart::Orientation;
+
+    let interp = orientation_swatch();
+    let from_right = Orientation {
+        reverse_wrong_side: false,
+        ..Orientation::default()
+    };
+    // Still shown from the right side, but in the order it was worked.
+    assert_eq!(
+        knitlang::chart::text_oriented(interp.fabric(), from_right),
+        "•••│ 2 WS\n••││ 1 RS\n4321\n\
+         │ knit on RS, purl on WS\n• purl on RS, knit on WS\n"
+    );
+}
+
+#[test]
+fn charts_show_wrong_side_stitches_from_the_right_side() {
+    use knitlang::chart::Orientation;
+
+    let interp = orientation_swatch();
+    let as_worked = Orientation {
+        invert_wrong_side: false,
+        ..Orientation::default()
+    };
+    // Still read from the left, but purls are purls.
+    assert_eq!(
+        knitlang::chart::text_oriented(interp.fabric(), as_worked),
+        "WS 2 •│││\n     ••││ 1 RS\n     4321\n│ knit\n• purl\n"
+    );
+    // Rounds have no wrong side for it to change.
+    let mut round = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    round
+        .run(&knitlang::parse_src("cast_on 2; mode round; k 1; p 1; p 1; k 1;").unwrap())
+        .unwrap();
+    assert_eq!(
+        knitlang::chart::text_oriented(round.fabric(), as_worked),
+        knitlang::chart::text(round.fabric())
+    );
+}
+
+#[test]
+fn unicode_and_ascii_charts_differ_only_in_their_symbols() {
+    use knitlang::chart::{CellKind, ChartStyle, Orientation};
+
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let src = "cast_on 8; k 1; yo; k2tog; m1; ssk; k 3; c4f; c4b; bind_off 2; k 5; p 1; \
+               p 4; w&t; k 4; p 6;";
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let chart =
+        |style: &ChartStyle| knitlang::chart::text_styled(fabric, Orientation::default(), style);
+    let (unicode, ascii) = (ChartStyle::unicode(), ChartStyle::ascii());
+    assert_eq!(ChartStyle::default(), unicode);
+    assert_eq!(ChartStyle::preset("unicode"), Some(unicode));
+    assert_eq!(knitlang::chart::text(fabric), chart(&unicode));
+    // The swatch has a cell of every kind, and the legend names them all.
+    let legend: Vec<_> = chart(&unicode)
+        .lines()
+        .skip(7)
+        .map(str::to_string)
+        .collect();
+    assert_eq!(legend.len(), CellKind::ALL.len(), "{legend:?}");
+    for (line, kind) in legend.iter().zip(CellKind::ALL) {
+        assert!(line.starts_with(unicode.symbol(kind).text), "{line}");
+    }
+    let swapped: String = chart(&unicode)
+        .chars()
+        .map(|c| {
+            CellKind::ALL
+                .into_iter()
+                .find(|&kind| unicode.symbol(kind).text == c)
+                .map_or(c, |kind| ascii.symbol(kind).text)
+        })
+        .collect();
+    assert_eq!(swapped, chart(&ascii));
+    assert!(chart(&ascii).is_ascii());
+    assert_ne!(chart(&unicode), chart(&ascii));
+    // Drawn charts are the same either way.
+    let svg = |symbols: ChartStyle| {
+        let style = knitlang::chart::Style {
+            symbols,
+            ..knitlang::chart::Style::default()
+        };
+        knitlang::chart::svg(fabric, &style)
+    };
+    assert_eq!(svg(unicode), svg(ascii));
+}
+
+#[test]
+fn chart_styles_change_only_the_symbols() {
+    use knitlang::chart::{CellKind, ChartStyle, Glyph, Orientation};
+
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let src = "cast_on 10; k 1; k2tog; c4f; ssk; p 1; k 2; p 4; k 2;";
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let chart =
+        |style: &ChartStyle| knitlang::chart::text_styled(fabric, Orientation::default(), style);
+    let (ascii, japanese) = (ChartStyle::ascii(), ChartStyle::japanese());
+    // Every cell, and every symbol in the legend, is the other style's
+    // symbol for the same kind of stitch; nothing else moves.
+    let used = [
+        CellKind::Knit,
+        CellKind::Purl,
+        CellKind::K2tog,
+        CellKind::Ssk,
+        CellKind::CableFront,
+    ];
+    let swapped: String = chart(&ascii)
+        .chars()
+        .map(|c| {
+            used.into_iter()
+                .find(|&kind| ascii.symbol(kind).text == c)
+                .map_or(c, |kind| japanese.symbol(kind).text)
+        })
+        .collect();
+    assert_eq!(swapped, chart(&japanese));
+    assert_ne!(chart(&ascii), chart(&japanese));
+    assert_eq!(chart(&ChartStyle::default()), knitlang::chart::text(fabric));
+    assert_eq!(ChartStyle::preset("japanese"), Some(japanese));
+    assert_eq!(ChartStyle::preset("jis"), None);
+
+    // Drawn charts draw each stitch with the style's glyph instead.
+    let svg = |symbols: ChartStyle| {
+        let style = knitlang::chart::Style {
+            symbols,
+            ..knitlang::chart::Style::default()
+        };
+        knitlang::chart::svg(fabric, &style)
+    };
+    let (dots, lines) = (svg(ascii), svg(japanese));
+    assert_eq!(dots.matches("<circle").count(), 6);
+    assert_eq!(lines.matches("<circle").count(), 0);
+    assert!(lines.contains(">人</text>"), "{lines}");
+    assert_eq!(
+        dots.lines().next(),
+        lines.lines().next(),
+        "the same size either way"
+    );
+    let mut plain = ascii;
+    plain.purl.glyph = Glyph::Blank;
+    assert_eq!(svg(plain).matches("<circle").count(), 0);
+}
+
+#[test]
+fn chart_legends_list_only_the_symbols_used() {
+    use knitlang::chart::{ChartStyle, Orientation, Style};
+
+    let run = |src: &str| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        interp
+    };
+    let legend = |text: &str| -> Vec<String> {
+        text.lines()
+            .skip_while(|line| !line.trim().chars().all(|c| c.is_ascii_digit()))
+            .skip(1)
+            .map(str::to_string)
+            .collect()
+    };
+    let interp = run("mode round; cast_on 4; k 1; yo; k2tog; k 1;");
+    assert_eq!(
+        legend(&knitlang::chart::text(interp.fabric())),
+        ["│ knit", "╱ knit two together", "○ yarn over"]
+    );
+    let symbols = ChartStyle::craft_yarn_council();
+    let text = knitlang::chart::text_styled(interp.fabric(), Orientation::default(), &symbols);
+    assert_eq!(
+        legend(&text),
+        ["□ knit", "╱ knit two together", "○ yarn over"]
+    );
+    let svg = knitlang::chart::svg(interp.fabric(), &Style::default());
+    for description in ["knit", "knit two together", "yarn over"] {
+        assert!(
+            svg.contains(&format!(" dy=\"0.35em\">{description}</text>")),
+            "{svg}"
+        );
+    }
+    assert!(!svg.contains("purl"), "{svg}");
+
+    // A text chart gives stitches in a yarn its letter, so only the yarns'
+    // legend says what they are; the drawn chart still draws them.
+    let interp = run("yarn MC = \"#000\"; cast_on 2; color MC; k 1; p 1;");
+    assert_eq!(
+        legend(&knitlang::chart::text(interp.fabric())),
+        ["A MC #000"]
+    );
+    let svg = knitlang::chart::svg(interp.fabric(), &Style::default());
+    assert!(svg.contains(">purl</text>"), "{svg}");
+
+    // Nothing knitted, nothing to explain.
+    assert_eq!(knitlang::chart::text(run("cast_on 2;").fabric()), "");
+}
+
+#[test]
+fn chart_symbol_overrides_are_checked_when_loaded() {
+    use knitlang::chart::{ChartStyle, Glyph};
+    use serde_json::json;
+
+    let style = ChartStyle::japanese()
+        .with_overrides(&json!({ "m1": { "glyph": "peak" }, "wrap": "w" }))
+        .unwrap();
+    assert_eq!(style.m1.glyph, Glyph::Peak);
+    assert_eq!(style.m1.text, ChartStyle::japanese().m1.text);
+    assert_eq!(style.wrap.text, 'w');
+    assert_eq!(style.knit, ChartStyle::japanese().knit);
+
+    let error = |overrides: serde_json::Value| {
+        ChartStyle::ascii()
+            .with_overrides(&overrides)
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(
+        error(json!({ "knit": "|" })),
+        "knit: no stitch is charted as `knit`"
+    );
+    assert_eq!(
+        error(json!({ "k2tog": "//" })),
+        "k2tog: a text symbol must be one character, not \"//\""
+    );
+    assert_eq!(
+        error(json!({ "p": { "glyph": "circle" } })),
+        "p.glyph: unknown glyph `circle`"
+    );
+    assert_eq!(
+        error(json!({ "yo": { "shape": "ring" } })),
+        "yo.shape: unknown key `shape`; expected `text` or `glyph`"
+    );
+    assert_eq!(
+        error(json!({ "yo": 1 })),
+        "yo: expected a character, or an object with `text` and `glyph`"
+    );
+    assert_eq!(
+        error(json!(["k"])),
+        "$: expected an object of symbols by stitch"
+    );
+}
+
+#[test]
+fn knitout_export_reports_what_it_cannot_knit() {
+    use knitlang::knitout::{knitout, Unsupported};
+
+    let export = |src: &str| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        knitout(interp.fabric())
+    };
+    let unsupported = |what, rows: &[usize]| Unsupported {
+        what,
+        rows: rows.to_vec(),
+    };
+    assert_eq!(
+        export(
+            "cast_on 6; k 2; yo; k2tog; k 2; p 6; mode round; k 6; mode flat; k 4; w&t; p 2; w&t;"
+        ),
+        Err(vec![
+            unsupported("yarn overs", &[1]),
+            unsupported("decreases", &[1]),
+            unsupported("rounds", &[3]),
+            unsupported("short rows", &[4, 5]),
+        ])
+    );
+    assert_eq!(
+        export("cast_on 2; k 2; cast_on 2; p 4;"),
+        Err(vec![unsupported("stitches cast on between rows", &[2])])
+    );
+    assert_eq!(
+        unsupported("cables", &[1, 2, 3, 5, 7, 8]).to_string(),
+        "cables are not supported for knitout export, in rows 1–3, 5 and 7–8"
+    );
+
+    // With nothing cast on there is only the header; with no rows, the
+    // stitches are cast on and bound off.
+    assert_eq!(
+        export("k 0;").unwrap(),
+        ";!knitout-2\n;;Carriers: 1 2 3 4 5 6 7 8 9 10\n"
+    );
+    let empty = export("cast_on 1;").unwrap();
+    assert!(
+        empty.ends_with("in 1\ntuck - f1 1\nmiss + f1 1\n; Bind off 1 st\nout 1\n"),
+        "{empty}"
+    );
+}
+
+#[test]
+fn short_rows_turn_a_heel() {
+    let src = include_str!("golden/heel_turn.knit");
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    assert_eq!(fabric.rows().len(), 12);
+    let shape: Vec<_> = (0..12)
+        .map(|r| (fabric.indent(r), fabric.rows()[r].len(), fabric.unworked(r)))
+        .collect();
+    assert_eq!(
+        shape,
+        [
+            (0, 11, 1),
+            (1, 10, 1),
+            (1, 9, 2),
+            (2, 8, 2),
+            (2, 7, 3),
+            (3, 6, 3),
+            (3, 7, 2),
+            (2, 8, 2),
+            (2, 9, 1),
+            (1, 10, 1),
+            (1, 11, 0),
+            (0, 12, 0),
+        ]
+    );
+    assert_eq!((fabric.width(), fabric.remaining()), (12, 12));
+    assert_eq!(
+        knitlang::chart::text(fabric),
+        include_str!("golden/heel_turn_chart.txt")
+    );
+    let svg = knitlang::chart::svg(fabric, &Default::default());
+    assert!(
+        svg.contains("<rect x=\"32\" y=\"230\" width=\"20\" height=\"20\" fill=\"#999999\"/>"),
+        "{svg}"
+    );
+
+    // Saved sessions carry on from a turn.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 6; k 4; w&t;").unwrap())
+        .unwrap();
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    resumed
+        .run(&knitlang::parse_src("p 4; k 6;").unwrap())
+        .unwrap();
+    assert_eq!(
+        knitlang::chart::text(resumed.fabric()),
+        "     ││││││ 3 RS\nWS 2   ││││\n      W││││ 1 RS\n     654321\n\
+         │ knit on RS, purl on WS\nW wrapped stitch\n"
+    );
+}
+
+#[test]
+fn short_rows_need_a_stitch_before_turning() {
+    let error = |src: &str| {
+        let err = run_source(src, RunOptions::default()).unwrap_err();
+        (err.code().id(), err.to_string())
+    };
+    assert_eq!(
+        error("cast_on 6; k 3; wrap_turn; wrap_turn;"),
+        (
+            "K0313",
+            "cannot wrap and turn before working a stitch of the row".to_string()
+        )
+    );
+    assert_eq!(error("w&t;").0, "K0304");
+    assert_eq!(
+        error("cast_on 6; k 3; w&t; cast_on 2;"),
+        (
+            "K0305",
+            "cannot cast on after a wrap and turn (3 stitches still behind the turn)".to_string()
+        )
+    );
+    // `w&t` is one token, but only on its own.
+    assert_eq!(
+        knitlang::parse_src("w&t;").unwrap(),
+        knitlang::parse_src("wrap_turn;").unwrap()
+    );
+    assert_eq!(error("cast_on 2; w&tt;").0, "K0102");
+    assert_eq!(error("cast_on 2; w &t;").0, "K0102");
+}
+
+#[test]
+fn flat_and_round_knitting_present_the_same_stitches_differently() {
+    let src = include_str!("golden/modes.knit");
+    for (mode, expected) in [
+        ("flat", include_str!("golden/modes_flat.txt")),
+        ("round", include_str!("golden/modes_round.txt")),
+    ] {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        let src = format!("mode {mode};\n{src}");
+        interp.run(&knitlang::parse_src(&src).unwrap()).unwrap();
+        let fabric = interp.fabric();
+        assert_eq!(
+            knitlang::chart::text(fabric) + &knitlang::written::instructions(fabric),
+            expected,
+            "{mode}"
+        );
+    }
+
+    // Pieces are knitted flat unless they say otherwise, and can change
+    // between rows.
+    let src = "cast_on 4; k 4; p 4; mode round; k 4; k 4; mode flat; k 4; p 4;";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let sides: Vec<_> = (0..6).map(|r| fabric.side(r)).collect();
+    let (rs, ws) = (Some(Side::Right), Some(Side::Wrong));
+    assert_eq!(sides, [rs, ws, None, None, rs, ws]);
+    assert_eq!(fabric.mode(), knitlang::Mode::Flat);
+    assert_eq!(
+        knitlang::written::instructions(fabric),
+        "Cast on 4 sts.\nRow 1 (RS): k4.\nRow 2 (WS): p4.\nJoin to work in the round.\n\
+         Rnds 3–4: k4.\nWork back and forth in rows.\nRow 5 (RS): k4.\nRow 6 (WS): p4.\n"
+    );
+    let svg = knitlang::chart::svg(fabric, &Default::default());
+    assert!(
+        svg.contains("<text x=\"16\" y=\"20\" dy=\"0.35em\" text-anchor=\"end\">6</text>"),
+        "{svg}"
+    );
+
+    // Saved sessions keep the mode and the side of every row.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    resumed.run(&knitlang::parse_src("k 4;").unwrap()).unwrap();
+    assert_eq!(resumed.fabric().side(5), ws);
+    assert_eq!(resumed.fabric().side(6), rs);
+}
+
+#[test]
+fn modes_change_only_between_rows() {
+    let error = |src: &str| {
+        let err = run_source(src, RunOptions::default()).unwrap_err();
+        (err.code().id(), err.to_string())
+    };
+    assert_eq!(
+        error("cast_on 8; k 4; mode round;"),
+        (
+            "K0314",
+            "cannot change mode in the middle of a row (4 stitches left to work)".to_string()
+        )
+    );
+    assert_eq!(
+        error("cast_on 6; k 4; w&t; p 2; w&t; mode round;").0,
+        "K0314"
+    );
+    assert_eq!(
+        error("mode round; cast_on 6; k 4; w&t;"),
+        (
+            "K0314",
+            "cannot wrap and turn in the round; work short rows flat with `mode flat;`".to_string()
+        )
+    );
+    assert_eq!(error("mode sideways;").0, "K0104");
+    // `mode` only means something at the start of a statement.
+    assert!(run_source("cast_on mode = 2; purl mode;", RunOptions::default()).is_ok());
+}
+
+#[test]
+fn markers_guide_raglan_decreases() {
+    let src = include_str!("golden/raglan.knit");
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let widths: Vec<_> = fabric.rows().iter().map(Vec::len).collect();
+    assert_eq!(widths, [32, 24, 24, 16, 16, 8, 8]);
+    assert_eq!(fabric.width(), 8);
+    assert_eq!(fabric.marks(1), [0, 8, 16, 24]);
+    assert_eq!(
+        knitlang::chart::text(fabric) + &knitlang::written::instructions(fabric),
+        include_str!("golden/raglan.txt")
+    );
+    let svg = knitlang::chart::svg(fabric, &Default::default());
+    assert!(
+        svg.contains("<g stroke=\"#000000\" stroke-width=\"3\">"),
+        "{svg}"
+    );
+
+    // Flat rows turn the work, so a marker is as far from the end of the
+    // next row as it was from the start of this one.
+    let output = |src: &str| run_source(src, RunOptions::default()).unwrap().stdout;
+    assert_eq!(
+        output("cast_on 10; k 3; place_marker m; k 7; purl stitches_to(m);"),
+        "7\n"
+    );
+    assert_eq!(
+        output("mode round; cast_on 10; k 3; place_marker m; k 7; purl stitches_to(m);"),
+        "3\n"
+    );
+    // Placing a marker that is already on the needles moves it.
+    assert_eq!(
+        output("cast_on 10; place_marker m; k 4; place_marker m; purl stitches_to(m);"),
+        "0\n"
+    );
+
+    // Saved sessions keep the markers.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    resumed
+        .run(&knitlang::parse_src("k stitches_to(left_back);").unwrap())
+        .unwrap();
+    assert_eq!(resumed.fabric().remaining(), 6);
+}
+
+#[test]
+fn markers_must_be_placed_and_reached() {
+    let error = |src: &str| {
+        let err = run_source(src, RunOptions::default()).unwrap_err();
+        (err.code().id(), err.to_string())
+    };
+    assert_eq!(
+        error("cast_on 6; k stitches_to(m);"),
+        ("K0206", "marker `m` is not on the needles".to_string())
+    );
+    assert_eq!(
+        error("cast_on 6; place_marker mine; slip_marker min;").0,
+        "K0206"
+    );
+    assert_eq!(
+        error("cast_on 6; k 2; place_marker m; k 4; p 1; slip_marker m;"),
+        (
+            "K0315",
+            "cannot slip marker `m` yet; it is 3 stitches further on".to_string()
+        )
+    );
+    assert_eq!(
+        error("cast_on 6; k 2; place_marker m; k 4; p 5; slip_marker m;"),
+        (
+            "K0315",
+            "cannot slip marker `m`; this row has already passed it".to_string()
+        )
+    );
+    assert_eq!(
+        error("cast_on 6; k 2; place_marker m; k 4; p 5; purl stitches_to(m);").0,
+        "K0315"
+    );
+    assert_eq!(
+        error("cast_on 6; k 1; place_marker m; k 5; k 4; k2tog;"),
+        (
+            "K0316",
+            "cannot work k2tog across marker `m`; slip it first".to_string()
+        )
+    );
+    assert_eq!(error("place_marker m;").0, "K0304");
+    // Decreases work one at a time unless given a count.
+    assert_eq!(
+        knitlang::parse_src("k2tog;").unwrap(),
+        knitlang::parse_src("k2tog 1;").unwrap()
+    );
+    assert_eq!(
+        knitlang::ast::format_program(&knitlang::parse_src("k2tog;ssk 2;").unwrap()),
+        "k2tog;\nssk 2;\n"
+    );
+}
+
+#[test]
+fn importers_run_each_file_once_in_place_of_its_first_use() {
+    use knitlang::imports::{Importer, Source};
+
+    let files = [
+        (
+            "main.knit",
+            "purl 0;\nuse \"a.knit\";\npurl 3;\nuse \"b.knit\";\n",
+        ),
+        ("a.knit", "use \"b.knit\";\npurl 1;\n"),
+        ("b.knit", "purl 2;\nuse \"a.knit\";\n"),
+        ("c.knit", "use \"b.knit\";\n"),
+    ];
+    let loaded = RefCell::new(Vec::new());
+    let load = |from: &str, path: &str| {
+        loaded.borrow_mut().push(format!("{from} {path}"));
+        let (origin, src) = files.iter().find(|(origin, _)| *origin == path).unwrap();
+        Ok(Source::new(origin, src))
+    };
+    let program = |importer: &mut Importer, origin: &str| {
+        let src = files.iter().find(|(o, _)| *o == origin).unwrap().1;
+        importer.program(Source::new(origin, src), knitlang::parse_src(src).unwrap())
+    };
+
+    // b.knit imports a.knit, which is still being imported.
+    let mut importer = Importer::new(load);
+    let err = program(&mut importer, "main.knit").unwrap_err();
+    assert_eq!(
+        (
+            err.origin.as_str(),
+            err.error.code.id(),
+            err.error.message.as_str()
+        ),
+        (
+            "b.knit",
+            "K0112",
+            "Import cycle: a.knit -> b.knit -> a.knit"
+        )
+    );
+    assert_eq!(err.error.span, knitlang::Span::new(8, 21));
+    drop(importer);
+    assert_eq!(
+        *loaded.borrow(),
+        ["main.knit a.knit", "a.knit b.knit", "b.knit a.knit"]
+    );
+
+    let files = [files[0], files[1], ("b.knit", "purl 2;\n"), files[3]];
+    let mut importer = Importer::new(|_, path| {
+        let (origin, src) = files.iter().find(|(origin, _)| *origin == path).unwrap();
+        Ok(Source::new(origin, src))
+    });
+    let program = |importer: &mut Importer, origin: &str| {
+        let src = files.iter().find(|(o, _)| *o == origin).unwrap().1;
+        importer.program(Source::new(origin, src), knitlang::parse_src(src).unwrap())
+    };
+    let parts = program(&mut importer, "main.knit").unwrap();
+    let order: Vec<_> = parts
+        .iter()
+        .map(|part| importer.files()[part.file].origin.as_str())
+        .collect();
+    assert_eq!(order, ["main.knit", "b.knit", "a.knit", "main.knit"]);
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let mut interp = knitlang::Interpreter::with_output(Box::new(Shared(Rc::clone(&buffer))));
+    for part in &parts {
+        interp.run(&part.stmts).unwrap();
+    }
+    assert_eq!(String::from_utf8(buffer.take()).unwrap(), "0\n2\n1\n3\n");
+    // A later program importing only files that have run adds nothing.
+    assert!(program(&mut importer, "c.knit").unwrap().is_empty());
+
+    let mut importer = Importer::new(|_, path| Err(format!("no {path} here")));
+    let err = importer
+        .program(
+            Source::new("main.knit", "use \"x.knit\";"),
+            knitlang::parse_src("use \"x.knit\";").unwrap(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "main.knit: Cannot import `x.knit`: no x.knit here"
+    );
+    assert_eq!(err.error.code.id(), "K0111");
+}
+
+#[test]
+fn use_is_only_a_statement_at_the_top_level() {
+    let err = knitlang::parse_src("repeat 2 { use \"a.knit\"; }").unwrap_err();
+    assert_eq!(
+        (err.code.id(), err.message.as_str()),
+        (
+            "K0113",
+            "Imports only go at the top level of a file, not inside a repeat"
+        )
+    );
+    // The interpreter never reads files, so imports must be loaded first.
+    let err = run_source("purl 1; use \"a.knit\";", RunOptions::default()).unwrap_err();
+    assert_eq!(
+        (err.code().id(), err.to_string()),
+        (
+            "K0317",
+            "cannot run `use \"a.knit\"`; imports are loaded before the program runs".to_string()
+        )
+    );
+    // `use` is still a variable name anywhere else.
+    let out = run_source("cast_on use = 2; purl use;", RunOptions::default()).unwrap();
+    assert_eq!(out.stdout, "2\n");
+    let stmts = knitlang::parse_src("use   \"lib/a.knit\" ;").unwrap();
+    assert_eq!(knitlang::format_program(&stmts), "use \"lib/a.knit\";\n");
+    let json = knitlang::ast_to_json(&stmts);
+    assert_eq!(knitlang::ast_from_json(&json).unwrap(), stmts);
+}
+
+#[test]
+fn imported_names_are_qualified_local_ones_shadow_them_and_ambiguity_is_an_error() {
+    use knitlang::imports::{Importer, Source};
+
+    let files = [
+        ("edgings.knit", "cast_on width = 3;\ncast_on depth = 1;\n"),
+        (
+            "lib.knit",
+            "use \"edgings.knit\";\ncast_on width = depth + 10;\ncast_on rows = 4;\n",
+        ),
+        ("trim.knit", "cast_on width = 7;\n"),
+    ];
+    let importer = || {
+        Importer::new(move |_, path| {
+            let (origin, src) = files.iter().find(|(origin, _)| *origin == path).unwrap();
+            Ok(Source::new(origin, src))
+        })
+    };
+    let run = |src: &str| {
+        let mut importer = importer();
+        let stmts = knitlang::parse_src(src).unwrap();
+        let parts = importer.program(Source::new("main.knit", src), stmts)?;
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let mut interp = knitlang::Interpreter::with_output(Box::new(Shared(Rc::clone(&buffer))));
+        for part in &parts {
+            interp.run(&part.stmts).unwrap();
+        }
+        let vars: Vec<String> = interp
+            .vars()
+            .map(|(name, v)| format!("{name}={v}"))
+            .collect();
+        Ok::<_, knitlang::imports::ImportError>((String::from_utf8(buffer.take()).unwrap(), vars))
+    };
+    let error = |src: &str| {
+        let err = run(src).unwrap_err();
+        (err.error.code.id(), err.error.message)
+    };
+
+    // lib.knit reads `depth` from edgings.knit but its own `width`, and the
+    // two widths are different variables under each file's namespace, which
+    // is named after the `use` that first imports it.
+    let (out, vars) =
+        run("use \"lib.knit\" as l;\nuse \"edgings.knit\" as e;\npurl l.width;\npurl e.width;\n")
+            .unwrap();
+    assert_eq!(out, "11\n3\n");
+    assert_eq!(
+        vars,
+        [
+            "edgings.depth=1",
+            "edgings.width=3",
+            "l.rows=4",
+            "l.width=11"
+        ]
+        .map(String::from)
+    );
+    // Plain names are looked up in imports without `as`, which are named
+    // after their files.
+    let (out, _) = run("use \"lib.knit\";\npurl rows;\npurl lib.rows;\n").unwrap();
+    assert_eq!(out, "4\n4\n");
+    // A name the program casts on is its own wherever it is read.
+    let (out, _) =
+        run("use \"trim.knit\";\ncast_on width = 1;\npurl width;\npurl trim.width;\n").unwrap();
+    assert_eq!(out, "1\n7\n");
+    // Imports of imports are not searched.
+    assert_eq!(
+        error("use \"lib.knit\";\npurl lib.depth;\n"),
+        ("K0208", "lib.knit does not define `depth`".to_string())
+    );
+    assert_eq!(
+        error("use \"lib.knit\" as l;\npurl lb.rows;\n"),
+        (
+            "K0208",
+            "No import is named `lb`; did you mean `l`?".to_string()
+        )
+    );
+    assert_eq!(
+        error("use \"lib.knit\";\npurl lib.row;\n").1,
+        "lib.knit does not define `row`; did you mean `rows`?"
+    );
+    assert_eq!(
+        error("use \"lib.knit\";\nuse \"trim.knit\";\npurl width;\n"),
+        (
+            "K0207",
+            "Ambiguous name `width`: it could be `lib.width` from lib.knit or `trim.width` \
+             from trim.knit"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        error("use \"lib.knit\" as x;\nuse \"trim.knit\" as x;\n"),
+        (
+            "K0207",
+            "`x` already names the import of lib.knit".to_string()
+        )
+    );
+
+    let stmts = knitlang::parse_src("use \"a.knit\"  as  a ;\npurl a . w + f.g(1);").unwrap();
+    assert_eq!(
+        knitlang::format_program(&stmts),
+        "use \"a.knit\" as a;\npurl a.w + f.g(1);\n"
+    );
+    let json = knitlang::ast_to_json(&stmts);
+    assert_eq!(knitlang::ast_from_json(&json).unwrap(), stmts);
+}
+
+#[test]
+fn cables_wider_than_the_rest_of_the_row_are_errors() {
+    let error = |src: &str| {
+        let err = run_source(src, RunOptions::default()).unwrap_err();
+        (err.code().id(), err.to_string())
+    };
+    assert_eq!(
+        error("cast_on 6; k 4; c4f;"),
+        (
+            "K0312",
+            "cannot work c4f with only 2 stitches left in the row".to_string()
+        )
+    );
+    assert_eq!(error("cast_on 4; cable 3 back;").0, "K0312");
+    assert_eq!(error("cast_on 4; cable 9999999999 back;").0, "K0312");
+    assert_eq!(error("cable 1 front;").0, "K0304");
+    assert_eq!(error("cast_on 4; cable 2 sideways;").0, "K0104");
+    // `c4f` only names a cable of an even number of stitches.
+    assert_eq!(error("cast_on 4; c3f;").0, "K0106");
+    // A cable crosses at least one stitch, and the error points at its count.
+    let src = "cast_on 4; cable 4 - 4 front; k 4;";
+    let Err(KnitError::Runtime(e)) = run_source(src, RunOptions::default()) else {
+        panic!("cable 0 ran");
+    };
+    assert_eq!(e.code.id(), "K0327");
+    assert_eq!(
+        e.message,
+        "cannot cable 0 stitches; a cable crosses at least 1"
+    );
+    assert_eq!(&src[e.span.start..e.span.end], "4 - 4");
+}
+
+#[test]
+fn undeclared_yarns_and_bad_colours_are_errors() {
+    let error = |src: &str| {
+        let err = run_source(src, RunOptions::default()).unwrap_err();
+        (err.code().id(), err.to_string())
+    };
+    assert_eq!(
+        error("yarn MC = \"#1d3557\"; cast_on 2; k 1; color Mc;"),
+        (
+            "K0205",
+            "yarn `Mc` is not declared; did you mean `MC`?".to_string()
+        )
+    );
+    assert_eq!(
+        error("yarn MC = \"#12345\";"),
+        (
+            "K0110",
+            "`#12345` is not a colour; write it as #rgb or #rrggbb".to_string()
+        )
+    );
+    assert_eq!(error("yarn MC = \"#fff").0, "K0109");
+    assert_eq!(error("yarn MC = 3;").0, "K0104");
+}
+
+#[test]
+fn svg_charts_follow_the_style() {
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    // With nothing knitted there is still a document, just an empty grid.
+    let empty = knitlang::chart::svg(interp.fabric(), &Default::default());
+    assert!(empty.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 "));
+    assert!(!empty.contains("<rect x="));
+    assert_eq!(knitlang::chart::text(interp.fabric()), "");
+
+    interp
+        .run(&knitlang::parse_src("cast_on 2; k 1; p 1;").unwrap())
+        .unwrap();
+    let style = knitlang::chart::Style {
+        cell_size: 40,
+        ink: "url(\"#ink\")".into(),
+        ..Default::default()
+    };
+    let svg = knitlang::chart::svg(interp.fabric(), &style);
+    assert!(
+        svg.contains("<rect x=\"60\" y=\"20\" width=\"40\" height=\"40\"/>"),
+        "{svg}"
+    );
+    assert!(
+        svg.contains("<circle cx=\"40\" cy=\"40\" r=\"6\"/>"),
+        "{svg}"
+    );
+    assert!(svg.contains("fill=\"url(&quot;#ink&quot;)\""), "{svg}");
+    assert!(svg.ends_with("</svg>\n"));
+}
+
+#[test]
+fn written_instructions_match_the_golden_files() {
+    let golden = [
+        (
+            include_str!("golden/ribbing.knit"),
+            include_str!("golden/ribbing.txt"),
+        ),
+        (
+            include_str!("golden/seed.knit"),
+            include_str!("golden/seed.txt"),
+        ),
+        (
+            include_str!("golden/border.knit"),
+            include_str!("golden/border.txt"),
+        ),
+        (
+            include_str!("golden/heel_turn.knit"),
+            include_str!("golden/heel_turn.txt"),
+        ),
+    ];
+    for (src, expected) in golden {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        assert_eq!(knitlang::written::instructions(interp.fabric()), expected);
+    }
+
+    let written = |src: &str| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        knitlang::written::instructions(interp.fabric())
+    };
+    assert_eq!(written(""), "");
+    // Only completed rows are written.
+    assert_eq!(written("cast_on 3; k 2;"), "Cast on 3 sts.\n");
+    assert_eq!(
+        written("cast_on 1; k 1; p 1;"),
+        "Cast on 1 st.\nRow 1 (RS): k1.\nRow 2 (WS): p1.\n"
+    );
+    // A group repeated only once is not worth a repeat.
+    assert_eq!(
+        written("cast_on 5; k 2; p 2; k 1;"),
+        "Cast on 5 sts.\nRow 1 (RS): k2, p2, k1.\n"
+    );
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn png_charts_take_only_hex_colours() {
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 2; k 1; p 1;").unwrap())
+        .unwrap();
+    let draw = |ink: &str| {
+        let style = knitlang::chart::Style {
+            ink: ink.into(),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        knitlang::chart::png(interp.fabric(), &style, &mut out).map(|()| out)
+    };
+    assert!(draw("#000").unwrap().starts_with(b"\x89PNG\r\n"));
+    assert!(draw("#1a2B3c").is_ok());
+    for ink in ["black", "#12345", "#ggg", "#ééé"] {
+        let err = draw(ink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{ink}");
+    }
+}
+
+#[test]
+fn trivia_lexing_reproduces_the_source_exactly() {
+    let src = "cast_on // stitches\n  w = 4;\n\n\n\npurl w; // done\n// end of file";
+    let (tokens, trailing) = knitlang::lex_with_trivia(src).unwrap();
+    let mut rebuilt = String::new();
+    for t in &tokens {
+        for trivia in &t.leading {
+            rebuilt.push_str(&src[trivia.span.start..trivia.span.end]);
+        }
+        rebuilt.push_str(&src[t.span.start..t.span.end]);
+    }
+    for trivia in &trailing {
+        rebuilt.push_str(&src[trivia.span.start..trivia.span.end]);
+    }
+    assert_eq!(rebuilt, src);
+
+    // The comment between `cast_on` and its arguments leads the `w` token...
+    let kinds: Vec<_> = tokens[1].leading.iter().map(|t| t.kind).collect();
+    use knitlang::TriviaKind::{Comment, Whitespace};
+    assert_eq!(kinds, [Whitespace, Comment, Whitespace]);
+    // ...the blank lines stay one run of whitespace, and the last comment
+    // trails the final token.
+    assert_eq!(
+        &src[tokens[5].leading[0].span.start..tokens[5].leading[0].span.end],
+        "\n\n\n\n"
+    );
+    assert_eq!(
+        trailing.iter().map(|t| t.kind).collect::<Vec<_>>(),
+        [Whitespace, Comment, Whitespace, Comment]
+    );
+
+    // The ordinary token stream never sees comments.
+    assert_eq!(
+        knitlang::try_lex(src).unwrap(),
+        knitlang::try_lex("cast_on w = 4; purl w;").unwrap()
+    );
+}
+
+#[test]
+fn format_with_comments_keeps_comments_in_place() {
+    let src =
+        "// header\nrepeat 2 { // twice\nk 1; // knit\n  // last\n}\npurl // moved\n2;\n// end";
+    let stmts = knitlang::parse_src(src).unwrap();
+    assert_eq!(
+        knitlang::ast::format_with_comments(&stmts, src),
+        "// header\n\
+         repeat 2 { // twice\n    \
+             k 1; // knit\n    \
+             // last\n\
+         }\n\
+         // moved\n\
+         purl 2;\n\
+         // end\n"
+    );
+}
+
+#[test]
+fn huge_expressions_run_and_drop_without_recursion() {
+    // Far deeper than the stack would allow a boxed tree to be dropped.
+    let terms = 1_000_000;
+    let src = format!("purl 0{};", " + 1".repeat(terms));
+    let stmts = knitlang::parse_src(&src).unwrap();
+    let out = run_source(&src, RunOptions::default()).unwrap();
+    assert_eq!(out.stdout, format!("{terms}\n"));
+    assert_eq!(knitlang::ast::format_program(&stmts), src + "\n");
+    drop(stmts);
+
+    let stmts = knitlang::parse_src("purl 1 * x;").unwrap();
+    let knitlang::StmtKind::Purl(e) = &stmts[0].kind else {
+        panic!("expected purl");
+    };
+    assert_eq!(
+        format!("{e:?}"),
+        "Expr { kind: Binary(Expr { kind: Number(1), span: Span { start: 5, end: 6 } }, '*', \
+         Expr { kind: Var(\"x\"), span: Span { start: 9, end: 10 } }), span: Span { start: 5, end: 10 } }"
+    );
+}
+
+/// `body` inside `depth` repeats, the outermost running `passes` times and
+/// every other once, followed by `after`.
+fn nested_repeats(depth: usize, passes: i64, body: &str, after: &str) -> Vec<knitlang::Stmt> {
+    use knitlang::{Expr, Span, Stmt, StmtKind};
+    let span = Span::new(0, 0);
+    let mut stmts = knitlang::parse_src(body).unwrap();
+    for level in (0..depth).rev() {
+        let count = Expr::number(if level == 0 { passes } else { 1 }, span);
+        stmts = vec![Stmt {
+            kind: StmtKind::Repeat(count, stmts),
+            span,
+        }];
+    }
+    let mut program = knitlang::parse_src("cast_on n = 0; cast_on 2;").unwrap();
+    program.extend(stmts);
+    program.extend(knitlang::parse_src(after).unwrap());
+    program
+}
+
+#[test]
+fn deeply_nested_repeats_run_and_drop_without_recursion() {
+    // Far deeper than the stack would allow one call per level.
+    let depth = 10_000;
+    let stmts = nested_repeats(depth, 3, "knit n = n + 1; k 1;", "purl n;");
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    assert!(!interp.run(&stmts).unwrap());
+    assert_eq!(interp.get_var("n"), Some(3));
+    assert_eq!(interp.stats().max_depth, depth);
+    drop(stmts);
+
+    // `bind_off` at the bottom ends the run through every level at once.
+    let stmts = nested_repeats(depth, 3, "purl n; bind_off;", "purl 2;");
+    assert_eq!(run_stmts(&stmts), ("0\n".to_string(), Ok(true)));
+}
+
+#[test]
+fn streaming_matches_batch_mode() {
+    let mut src = String::from("cast_on total = 0;\ncast_on 2;\n");
+    for i in 0..200_000 {
+        src.push_str(&format!("knit total = total + {i};\n"));
+        if i % 50_000 == 0 {
+            src.push_str("repeat 2 {\n    k 1;\n    purl total;\n    p 1;\n}\n");
+        }
+    }
+    src.push_str("purl total;\nbind_off;\npurl 0;\n");
+    let run = |src: &str, streaming| {
+        let options = RunOptions {
+            streaming,
+            ..RunOptions::default()
+        };
+        run_source(src, options).map(|mut out| {
+            out.stats.elapsed = Default::default();
+            out
+        })
+    };
+    let batch = run(&src, false).unwrap();
+    assert!(batch.bound_off);
+    assert_eq!(run(&src, true).unwrap(), batch);
+
+    // A syntax error is reported at the same place, but only once the
+    // statements before it have run.
+    let broken = "purl 1;\nrepeat 2 {\n    purl 2;\n";
+    let batch = run(broken, false).unwrap_err();
+    let streaming = run(broken, true).unwrap_err();
+    assert_eq!(streaming.to_string(), batch.to_string());
+    assert_eq!(streaming.span(), batch.span());
+    let bad_char = run("purl 1;\npurl @;", true).unwrap_err();
+    assert_eq!(bad_char.span(), knitlang::Span::new(13, 14));
+}
+
+#[test]
+fn parsing_from_the_lexer_matches_lexing_first() {
+    let lexed_first = |src: &str| {
+        let tokens = knitlang::lex_all(src).map_err(knitlang::ParseError::from)?;
+        knitlang::Parser::new(tokens).parse()
+    };
+    for src in [
+        "cast_on x = 2;\nrepeat x { k 1; p 1; }\npurl x * 3;",
+        "cast_on",
+        "repeat 3 {\n    k 1;\n",
+        "purl 1 +",
+        // A lex error wins over an earlier syntax error either way.
+        "knit = 1;\npurl @;",
+        "purl 1;\npurl 99999999999999999999;",
+        "}",
+    ] {
+        let lazy = knitlang::parse_src(src);
+        match (lazy, lexed_first(src)) {
+            (Ok(lazy), Ok(eager)) => assert_eq!(format!("{lazy:?}"), format!("{eager:?}")),
+            (Err(lazy), Err(eager)) => {
+                assert_eq!(lazy.message, eager.message, "{src}");
+                assert_eq!(lazy.span, eager.span, "{src}");
+            }
+            (lazy, eager) => panic!("{src}: {lazy:?} vs {eager:?}"),
+        }
+    }
+    let streaming = RunOptions {
+        streaming: true,
+        ..RunOptions::default()
+    };
+    assert!(matches!(
+        run_source("knit = 1;\npurl @;", streaming),
+        Err(KnitError::Lex(_))
+    ));
+}
+
+#[test]
+fn host_builtins_are_callable_from_programs() {
+    let out = Shared::default();
+    let mut interp = knitlang::Interpreter::with_output(Box::new(out.clone()));
+    let run = |interp: &mut knitlang::Interpreter, src: &str| {
+        let stmts = knitlang::parse_src(src).unwrap();
+        interp.run(&stmts).map_err(|e| (e.message, e.span))
+    };
+    // Registering after variables exist is fine, and a function may share a
+    // variable's name.
+    run(&mut interp, "cast_on lookup_yarn = 7;").unwrap();
+    interp.register_builtin("lookup_yarn", 1, |args| match args[0] {
+        1 => Ok(120),
+        id => Err(format!("no yarn with id {id}")),
+    });
+    interp.register_builtin("max", 2, |args| Ok(args[0].max(args[1])));
+    run(
+        &mut interp,
+        "purl lookup_yarn(1) * 2;\npurl max(lookup_yarn, max(3, 11)) + lookup_yarn;",
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out.0.borrow().clone()).unwrap(),
+        "240\n18\n"
+    );
+
+    let src = "purl 1 + lookup_yarn(2 + 3);";
+    let call = knitlang::Span::new(9, 27);
+    assert_eq!(
+        run(&mut interp, src),
+        Err(("no yarn with id 5".to_string(), call))
+    );
+    assert_eq!(
+        run(&mut interp, "purl 1 + max(1);"),
+        Err((
+            "`max` takes 2 arguments, but was given 1".to_string(),
+            knitlang::Span::new(9, 15)
+        ))
+    );
+    assert_eq!(
+        run(&mut interp, "purl width(4);").unwrap_err().0,
+        "unknown function `width`"
+    );
+    // A later registration replaces the earlier one.
+    interp.register_builtin("max", 3, |args| Ok(args.iter().copied().max().unwrap()));
+    run(&mut interp, "purl max(4, 9, 2);").unwrap();
+    assert!(String::from_utf8(out.0.borrow().clone())
+        .unwrap()
+        .ends_with("\n9\n"));
+
+    // Calls print and round-trip through JSON like any other expression.
+    let stmts = knitlang::parse_src("purl max(1,lookup_yarn( 2 ),f( ) );").unwrap();
+    let formatted = "purl max(1, lookup_yarn(2), f());\n";
+    assert_eq!(knitlang::ast::format_program(&stmts), formatted);
+    let json = knitlang::ast_to_json(&stmts);
+    let stmts = knitlang::ast_from_json(&json).unwrap();
+    assert_eq!(knitlang::ast::format_program(&stmts), formatted);
+}
+
+#[test]
+fn sessions_run_input_as_it_is_fed() {
+    use knitlang::{FeedResult, Session};
+
+    let out = Shared::default();
+    let mut session = Session::new(RunOptions {
+        output: Some(Box::new(out.clone())),
+        ..RunOptions::default()
+    });
+    assert_eq!(
+        session.feed("cast_on 2; cast_on rows = 0;").unwrap(),
+        FeedResult::Executed(2)
+    );
+    for line in ["repeat 3 {", "    k 2;", "    knit rows = rows + 1;"] {
+        assert_eq!(session.feed(line).unwrap(), FeedResult::NeedMore);
+    }
+    assert_eq!(
+        session.feed("} purl rows;").unwrap(),
+        FeedResult::Executed(2)
+    );
+    assert_eq!(session.feed("rows * 2;").unwrap(), FeedResult::Value(6));
+    assert_eq!(String::from_utf8(out.0.borrow().clone()).unwrap(), "3\n");
+
+    // Errors come back with spans into the failed input, which is dropped,
+    // and leave earlier state alone.
+    let err = session.feed("knit rows = ;").unwrap_err();
+    assert!(matches!(err, KnitError::Parse(_)));
+    assert_eq!(session.last_source(), "knit rows = ;\n");
+    assert_eq!(err.span(), knitlang::Span::new(12, 13));
+    assert!(matches!(
+        session.feed("knit rows = 1; purl 1 / 0;"),
+        Err(KnitError::Runtime(_))
+    ));
+    assert_eq!(session.pending(), "");
+    assert_eq!(session.interpreter().get_var("rows"), Some(1));
+    assert_eq!(session.interpreter().fabric().rows().len(), 3);
+
+    // Input still unbalanced when it runs out is reported by `finish`.
+    assert_eq!(session.feed("repeat 2 {").unwrap(), FeedResult::NeedMore);
+    let err = session.finish().unwrap_err();
+    assert!(err.to_string().starts_with("Expected '}'"), "{err}");
+    assert_eq!(session.finish().unwrap(), FeedResult::Executed(0));
+}
+
+#[test]
+fn saved_sessions_resume_where_they_stopped() {
+    let first =
+        "cast_on total = 0;\ncast_on 4;\nrepeat 3 {\n    k 2;\n    p 1;\n}\nknit total = 5;\n";
+    let second = "k 1;\np 2;\nrepeat 2 { k 1; }\nknit total = total * 3;\npurl total;\nbind_off;\n";
+    let finish = |interp: knitlang::Interpreter| {
+        let rows = interp.fabric().rows().to_vec();
+        let vars: Vec<_> = interp.vars().map(|(n, v)| (n.to_string(), *v)).collect();
+        let mut stats = interp.stats();
+        stats.elapsed = Default::default();
+        (rows, vars, stats)
+    };
+
+    let whole = Shared::default();
+    let straight = knitlang::run(&format!("{first}{second}"), Box::new(whole.clone())).unwrap();
+
+    let halted = knitlang::run(first, Box::new(io::sink())).unwrap();
+    let mut saved = Vec::new();
+    halted.save_state(&mut saved).unwrap();
+    // The first half stops mid-row, so the unfinished row must carry over.
+    assert_eq!(halted.fabric().remaining(), 3);
+
+    let resumed_out = Shared::default();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(resumed_out.clone()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    assert_eq!(resumed.get_var("total"), Some(5));
+    let mut session = knitlang::Session::with_interpreter(resumed);
+    session.feed(second).unwrap();
+
+    assert_eq!(
+        resumed_out.0.borrow().as_slice(),
+        whole.0.borrow().as_slice()
+    );
+    assert_eq!(finish(session.into_interpreter()), finish(straight));
+}
+
+#[test]
+fn loaded_sessions_only_intern_the_names_programs_use() {
+    use knitlang::symbol::Symbol;
+    let interp = knitlang::run("cast_on width = 4;", Box::new(io::sink())).unwrap();
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap().replace(
+        "\"width\": 4",
+        "\"width\": 4, \"only_in_the_session\": 2, \"read_after_loading\": 3",
+    );
+
+    let out = Shared::default();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(out.clone()));
+    resumed.load_state(saved.as_bytes()).unwrap();
+    assert_eq!(Symbol::lookup("only_in_the_session"), None);
+    assert_eq!(resumed.get_var("only_in_the_session"), Some(2));
+    let names: Vec<_> = resumed.vars().map(|(name, _)| name).collect();
+    assert_eq!(
+        names,
+        ["only_in_the_session", "read_after_loading", "width"]
+    );
+
+    let src = "knit read_after_loading = read_after_loading * width; purl read_after_loading;";
+    resumed.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    assert_eq!(out.0.borrow().as_slice(), b"12\n");
+    let mut again = Vec::new();
+    resumed.save_state(&mut again).unwrap();
+    let again = String::from_utf8(again).unwrap();
+    assert!(again.contains("\"only_in_the_session\": 2"), "{again}");
+    assert!(again.contains("\"read_after_loading\": 12"), "{again}");
+    assert_eq!(Symbol::lookup("only_in_the_session"), None);
+}
+
+#[test]
+fn load_state_rejects_unknown_versions_and_leaves_state_alone() {
+    let mut interp = knitlang::run("cast_on x = 7;", Box::new(io::sink())).unwrap();
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap();
+    assert!(saved.contains(&format!("\"version\": {}", knitlang::STATE_VERSION)));
+
+    let newer = saved.replace(
+        &format!("\"version\": {}", knitlang::STATE_VERSION),
+        "\"version\": 99",
+    );
+    let rejects = |interp: &mut knitlang::Interpreter, text: &str| {
+        interp.load_state(text.as_bytes()).unwrap_err().to_string()
+    };
+    assert_eq!(
+        rejects(&mut interp, &newer),
+        format!(
+            "session was saved in format version 99, but this knitlang only reads versions 1 to {}",
+            knitlang::STATE_VERSION
+        )
+    );
+    // Sessions saved before yarns were still load, with none.
+    let first = saved.replace(
+        &format!("\"version\": {}", knitlang::STATE_VERSION),
+        "\"version\": 1",
+    );
+    knitlang::Interpreter::with_output(Box::new(io::sink()))
+        .load_state(first.as_bytes())
+        .unwrap();
+    // And those saved before short rows, with none.
+    let second = saved.replace(
+        &format!("\"version\": {}", knitlang::STATE_VERSION),
+        "\"version\": 2",
+    );
+    knitlang::Interpreter::with_output(Box::new(io::sink()))
+        .load_state(second.as_bytes())
+        .unwrap();
+    // And those saved before modes, knitted flat.
+    let third = saved.replace(
+        &format!("\"version\": {}", knitlang::STATE_VERSION),
+        "\"version\": 3",
+    );
+    knitlang::Interpreter::with_output(Box::new(io::sink()))
+        .load_state(third.as_bytes())
+        .unwrap();
+    // And those saved before markers, with none.
+    let fourth = saved.replace(
+        &format!("\"version\": {}", knitlang::STATE_VERSION),
+        "\"version\": 4",
+    );
+    knitlang::Interpreter::with_output(Box::new(io::sink()))
+        .load_state(fourth.as_bytes())
+        .unwrap();
+    let bad_mode = saved.replace("\"mode\": \"flat\"", "\"mode\": \"spiral\"");
+    assert_eq!(
+        rejects(&mut interp, &bad_mode),
+        "saved mode \"spiral\" should be \"flat\" or \"round\""
+    );
+    let bad_indents = saved.replace("\"indents\": []", "\"indents\": [1]");
+    assert_eq!(
+        rejects(&mut interp, &bad_indents),
+        "saved `indents` should be a list of one count for every row"
+    );
+    assert_eq!(
+        rejects(&mut interp, "{\"variables\": {}}"),
+        "not a saved session: it has no format version"
+    );
+    assert!(rejects(&mut interp, "cast_on x = 1;").starts_with("not a saved session: "));
+    let bad_stitch = saved.replace("\"current\": []", "\"current\": [\"tbl\"]");
+    assert_eq!(
+        rejects(&mut interp, &bad_stitch),
+        "unknown stitch \"tbl\" in saved current row"
+    );
+    assert_eq!(interp.get_var("x"), Some(7));
+}
+
+#[test]
+fn event_sinks_see_each_step_in_order() {
+    use knitlang::{Event, Span};
+
+    let src = "cast_on 2;\ncast_on x = 1;\nrepeat 2 { k 2; knit x = x + 1; }\npurl x;\npurl x / 0;";
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let seen = events.clone();
+    interp.set_event_sink(Some(Box::new(move |event: &Event<'_>| {
+        seen.borrow_mut().push(event.to_json());
+    })));
+    let stmts = knitlang::parse_src(src).unwrap();
+    let err = interp.run(&stmts).unwrap_err();
+    assert_eq!(err.message, "division by zero");
+
+    let statement = |kind: &'static str, start, end| {
+        Event::Statement {
+            kind,
+            span: Span::new(start, end),
+        }
+        .to_json()
+    };
+    let assign = |old, new| {
+        Event::Assign {
+            name: "x",
+            old,
+            new,
+        }
+        .to_json()
+    };
+    let row = |index| Event::Row { index, width: 2 }.to_json();
+    let expected = [
+        statement("cast_on_stitches", 0, 10),
+        statement("cast_on", 11, 25),
+        assign(None, 1),
+        statement("repeat", 26, 59),
+        statement("work", 37, 41),
+        row(0),
+        statement("knit", 42, 57),
+        assign(Some(1), 2),
+        statement("work", 37, 41),
+        row(1),
+        statement("knit", 42, 57),
+        assign(Some(2), 3),
+        statement("purl", 60, 67),
+        Event::Output { text: "3" }.to_json(),
+        statement("purl", 68, 79),
+        serde_json::json!({
+            "event": "error",
+            "message": "division by zero",
+            "span": { "start": 77, "end": 78 },
+        }),
+    ];
+    assert_eq!(*events.borrow(), expected);
+}
+
+#[test]
+fn hooks_can_abort_runs_and_skip_statements() {
+    use knitlang::{ExecHook, HookAction, Interpreter, Stmt, StmtKind};
+
+    /// Lets 10 statements start, then aborts.
+    struct Budget(usize);
+    impl ExecHook for Budget {
+        fn before_stmt(&mut self, _: &Stmt, _: &Interpreter) -> HookAction {
+            self.0 += 1;
+            if self.0 > 10 {
+                return HookAction::Abort(format!("statement budget of {} spent", self.0 - 1));
+            }
+            HookAction::Continue
+        }
+    }
+
+    /// Skips every `purl` and records the rows knitted after each statement.
+    struct Quiet(Rc<RefCell<Vec<usize>>>);
+    impl ExecHook for Quiet {
+        fn before_stmt(&mut self, stmt: &Stmt, _: &Interpreter) -> HookAction {
+            match stmt.kind {
+                StmtKind::Purl(_) => HookAction::Skip,
+                _ => HookAction::Continue,
+            }
+        }
+        fn after_stmt(&mut self, _: &Stmt, interp: &Interpreter) {
+            self.0.borrow_mut().push(interp.fabric().rows().len());
+        }
+    }
+
+    let run = |src: &str, hook: Box<dyn ExecHook>| {
+        let out = Shared::default();
+        let mut interp = Interpreter::with_output(Box::new(out.clone()));
+        interp.set_hook(Some(hook));
+        let result = interp.run(&knitlang::parse_src(src).unwrap());
+        let printed = String::from_utf8(out.0.borrow().clone()).unwrap();
+        (result, printed, interp)
+    };
+
+    let (result, printed, interp) = run(
+        "cast_on x = 0;\nrepeat 100 {\n    knit x = x + 1;\n    purl x;\n}",
+        Box::new(Budget(0)),
+    );
+    let err = result.unwrap_err();
+    assert_eq!(err.message, "statement budget of 10 spent");
+    // The cast_on, the repeat and four passes of the body ran; the fifth
+    // `knit` was stopped.
+    assert_eq!(printed, "1\n2\n3\n4\n");
+    assert_eq!(interp.get_var("x"), Some(4));
+    assert_eq!(interp.stats().statements, 10);
+    assert_eq!(err.span, knitlang::Span::new(32, 47));
+
+    let rows = Rc::new(RefCell::new(Vec::new()));
+    let (result, printed, interp) = run(
+        "cast_on 2;\npurl 1;\nrepeat 2 { k 2; purl 2; }\nknit x = 3;\npurl x;",
+        Box::new(Quiet(rows.clone())),
+    );
+    assert!(!result.unwrap());
+    assert_eq!(printed, "");
+    assert_eq!(interp.get_var("x"), Some(3));
+    assert_eq!(*rows.borrow(), [0, 1, 2, 2, 2]);
+
+    /// Stops the run once a row is complete, as a knit-along pausing for
+    /// good would.
+    struct FirstRow;
+    impl ExecHook for FirstRow {
+        fn before_stmt(&mut self, _: &Stmt, interp: &Interpreter) -> HookAction {
+            match interp.fabric().rows().len() {
+                0 => HookAction::Continue,
+                _ => HookAction::Stop,
+            }
+        }
+    }
+    let (result, printed, interp) = run(
+        "cast_on 2;
+k 2;
+purl 1;
+k 2;",
+        Box::new(FirstRow),
+    );
+    assert!(result.unwrap(), "a stop ends the run as bind_off does");
+    assert_eq!(printed, "");
+    assert_eq!(interp.fabric().rows().len(), 1);
+}
+
+#[test]
+fn error_codes_are_registered_once_each() {
+    use knitlang::{Code, REGISTRY};
+    use std::collections::HashSet;
+
+    let mut ids = HashSet::new();
+    for (i, entry) in REGISTRY.iter().enumerate() {
+        assert_eq!(entry.code as usize, i, "{} is out of order", entry.id);
+        assert!(ids.insert(entry.id), "{} is registered twice", entry.id);
+        let digits = entry.id.strip_prefix('K').unwrap();
+        assert!(digits.len() == 4 && digits.bytes().all(|b| b.is_ascii_digit()));
+        assert_eq!(Code::from_id(entry.id), Some(entry.code));
+        assert_eq!(entry.code.to_string(), entry.id);
+    }
+    // The last variant, so with the order checked every variant has an entry.
+    assert_eq!(REGISTRY.last().unwrap().code, Code::InvalidAst);
+
+    let err = run_source("cast_on x = 0; purl 1 / x;", RunOptions::default()).unwrap_err();
+    assert_eq!(err.code(), Code::DivisionByZero);
+    let err = run_source("purl 1", RunOptions::default()).unwrap_err();
+    assert_eq!(err.code().id(), "K0101");
+}
+
+#[test]
+fn misspelt_names_get_suggestions() {
+    let message = |src: &str| match run_source(src, RunOptions::default()) {
+        Err(KnitError::Runtime(e)) => e.message,
+        Err(KnitError::Parse(e)) => e.message,
+        other => panic!("expected an error, got {other:?}"),
+    };
+    assert_eq!(
+        message("cast_on total = 1; purl totla;"),
+        "undefined variable `totla`; did you mean `total`?"
+    );
+    assert_eq!(
+        message("cast_on total = 1; purl Total;"),
+        "undefined variable `Total`; did you mean `total`?"
+    );
+    assert_eq!(
+        message("cast_on rows = 1; cast_on cols = 1; purl rowz + 1;"),
+        "undefined variable `rowz`; did you mean `rows`?"
+    );
+    assert_eq!(
+        message("cast_on row = 1; cast_on rat = 1; purl rot;"),
+        "undefined variable `rot`; did you mean `rat` or `row`?"
+    );
+    assert_eq!(
+        message("cast_on total = 1; purl zebra;"),
+        "undefined variable `zebra`"
+    );
+    assert!(message("repeet 3 { k 1; }").ends_with("; did you mean `repeat`?"));
+
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.register_builtin("double", 1, |args| Ok(args[0] * 2));
+    let stmts = knitlang::parse_src("purl doubel(2);").unwrap();
+    assert_eq!(
+        interp.run(&stmts).unwrap_err().message,
+        "unknown function `doubel`; did you mean `double`?"
+    );
+
+    assert_eq!(
+        knitlang::suggest::closest_matches("widht", ["width", "height", "widths"]),
+        ["width"]
+    );
+    assert_eq!(
+        knitlang::suggest::closest_matches("wdth", ["with", "width", "wdth"]),
+        ["width", "with"]
+    );
+}
+
+#[test]
+fn stats_count_the_run_up_to_any_failure() {
+    let src = "cast_on width = 4;\ncast_on width;\nrepeat 2 {\n    repeat 2 { k 1 + 1; }\n}\n\
+               cast_on extra = width / 2;\n";
+    let mut stats = run_source(src, RunOptions::default()).unwrap().stats;
+    stats.elapsed = Default::default();
+    assert_eq!(
+        stats,
+        knitlang::RunStats {
+            rows: 2,
+            stitches: 8,
+            increases: 0,
+            decreases: 0,
+            variables: 2,
+            peak_variables: 2,
+            statements: 10,
+            expressions: 10,
+            max_depth: 2,
+            elapsed: Default::default(),
+        }
+    );
+
+    // Every pass of a repeat adds to the counters; none ever go down.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let mut last = interp.stats();
+    for line in [
+        "cast_on n = 0;",
+        "cast_on 1;",
+        "repeat 3 { knit n = n + 1; k 1; }",
+    ] {
+        for _ in 0..3 {
+            interp.run(&knitlang::parse_src(line).unwrap()).unwrap();
+            let now = interp.stats();
+            assert!(now.statements > last.statements);
+            assert!(now.expressions > last.expressions);
+            assert!(now.rows >= last.rows && now.max_depth >= last.max_depth);
+            last = now;
+        }
+    }
+
+    // A failed run still counts what it did before the error.
+    let src = "cast_on d = 2; repeat 5 { knit d = d - 1; purl 1 / d; }";
+    let before = interp.stats();
+    assert!(interp.run(&knitlang::parse_src(src).unwrap()).is_err());
+    let after = interp.stats();
+    // The cast_on and repeat, a whole pass and the pass that divides by 0.
+    assert_eq!(after.statements - before.statements, 6);
+    assert_eq!(after.expressions - before.expressions, 6);
+    assert_eq!(after.peak_variables, 2);
+    assert_eq!(after.max_depth, 1);
+}
+
+#[test]
+fn undefined_variables_are_found_before_the_program_runs() {
+    let err = run_source(
+        "cast_on total = 1;\npurl total;\nrepeat 2 {\n    knit total = totl + 1;\n}\n",
+        RunOptions::default(),
+    )
+    .unwrap_err();
+    let KnitError::Runtime(e) = err else {
+        panic!("expected a runtime error, got {err:?}");
+    };
+    assert_eq!(
+        e.message,
+        "undefined variable `totl`; did you mean `total`?"
+    );
+    assert_eq!(e.span, knitlang::Span::new(59, 63));
+
+    // Nothing ran, so nothing was printed or counted.
+    let out = Shared::default();
+    let mut interp = knitlang::Interpreter::with_output(Box::new(out.clone()));
+    let stmts = knitlang::parse_src("purl 1; cast_on x = y;").unwrap();
+    assert!(interp.run(&stmts).is_err());
+    assert!(out.0.borrow().is_empty());
+    assert_eq!(interp.stats().statements, 0);
+
+    // Set later in the same loop body is still too late on the first pass.
+    let stmts = knitlang::parse_src("repeat 2 { purl m; cast_on m = 1; }").unwrap();
+    let found = knitlang::resolve::first_undefined(&stmts, |_| false).unwrap();
+    assert_eq!(found.name.as_str(), "m");
+
+    // Variables an earlier run set are defined, as in the REPL.
+    let mut session = knitlang::Session::with_interpreter(interp);
+    session.feed("cast_on y = 2;").unwrap();
+    session.feed("purl y * 3;").unwrap();
+    assert_eq!(String::from_utf8(out.0.borrow().clone()).unwrap(), "6\n");
+    // A loop that sets a variable counts as setting it, even with no passes;
+    // the read is still checked when it runs.
+    let err = session
+        .feed("repeat 0 { cast_on zeta = 1; }\npurl zeta;")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "undefined variable `zeta`");
+}
+
+#[test]
+fn programs_numbered_apart_still_run_as_one() {
+    // Each parse numbers its own variables from 0, so `b` and `a` swap
+    // slots between these two; run together, they are looked up by name.
+    let mut stmts = knitlang::parse_src("cast_on a = 1; cast_on b = 5;").unwrap();
+    stmts.extend(knitlang::parse_src("knit b = b - a; purl b * 10 + a;").unwrap());
+    assert_eq!(run_stmts(&stmts), ("41\n".to_string(), Ok(false)));
+
+    // So is a tree built by hand, which nothing has numbered.
+    use knitlang::{Expr, Span, Stmt, StmtKind};
+    let span = Span::new(0, 0);
+    let x = knitlang::Symbol::intern("x");
+    let value = Expr::binary(Expr::var(x, span), '*', Expr::number(3, span));
+    let stmts = [
+        Stmt {
+            kind: StmtKind::CastOn(x, Expr::number(7, span)),
+            span,
+        },
+        Stmt {
+            kind: StmtKind::Knit(x, value),
+            span,
+        },
+        Stmt {
+            kind: StmtKind::Purl(Expr::var(x, span)),
+            span,
+        },
+    ];
+    assert_eq!(run_stmts(&stmts), ("21\n".to_string(), Ok(false)));
+}
+
+#[test]
+fn optimize_folds_constants_and_unrolls_small_repeats() {
+    use knitlang::optimize::{optimize, Options, Report};
+    use knitlang::parse_src;
+
+    let (stmts, report) = optimize(
+        parse_src("repeat 1 + 1 { purl 2 * 3; } repeat 2 { k 1; bind_off; } repeat n { purl 1; }")
+            .unwrap(),
+        &Options::default(),
+    );
+    assert_eq!(
+        stmts,
+        parse_src("purl 6; purl 6; repeat 2 { k 1; bind_off; } repeat n { purl 1; }").unwrap()
+    );
+    assert_eq!(
+        report,
+        Report {
+            folded: 2,
+            unrolled: 1
+        }
+    );
+
+    // The inner loop fits in the size cap; four copies of it would not.
+    let options = Options {
+        unroll_limit: 8,
+        max_unrolled: 10,
+    };
+    let (stmts, report) = optimize(
+        parse_src("repeat 4 { repeat 4 { purl 1; } }").unwrap(),
+        &options,
+    );
+    assert_eq!(
+        stmts,
+        parse_src("repeat 4 { purl 1; purl 1; purl 1; purl 1; }").unwrap()
+    );
+    assert_eq!(report.unrolled, 1);
+
+    // What would fail at run time is left for the run to report.
+    let src = "purl 1 / 0; purl 9223372036854775807 + 1; repeat 0 { purl 1; }";
+    let (stmts, report) = optimize(parse_src(src).unwrap(), &options);
+    assert_eq!(stmts, parse_src(src).unwrap());
+    assert_eq!(report, Report::default());
+}
+
+#[test]
+fn nesting_is_limited_exactly_at_the_limit() {
+    use knitlang::parser::DEFAULT_MAX_NESTING;
+    use knitlang::Span;
+    let repeats = |depth| format!("{}k 0;{}", "repeat 1 { ".repeat(depth), " }".repeat(depth));
+    let calls = |depth| format!("purl {}1{};", "f(".repeat(depth), ")".repeat(depth));
+    // Where the first repeat keyword or function name past `limit` levels is.
+    let repeat_at = |limit| Span::new(11 * limit, 11 * limit + 6);
+    let call_at = |limit| Span::new(5 + 2 * limit, 6 + 2 * limit);
+
+    let limit = DEFAULT_MAX_NESTING;
+    assert!(knitlang::parse_src(&repeats(limit)).is_ok());
+    assert!(knitlang::parse_src(&calls(limit)).is_ok());
+    for (src, span) in [
+        (repeats(limit + 1), repeat_at(limit)),
+        (calls(limit + 1), call_at(limit)),
+    ] {
+        let err = knitlang::parse_src(&src).unwrap_err();
+        assert_eq!(err.code.id(), "K0108");
+        assert_eq!(err.message, "Nesting too deep (limit 256)");
+        assert_eq!(err.span, span);
+    }
+
+    // Ten thousand levels stop with the error instead of overflowing the stack.
+    let err = knitlang::parse_src(&repeats(10_000)).unwrap_err();
+    assert_eq!(err.span, repeat_at(limit));
+
+    let mut parser = knitlang::Parser::from_source(&repeats(3));
+    parser.set_max_nesting(2);
+    let err = parser.parse().unwrap_err();
+    assert_eq!(err.span, repeat_at(2));
+    assert_eq!(err.message, "Nesting too deep (limit 2)");
+
+    let options = |streaming| RunOptions {
+        max_nesting: Some(3),
+        streaming,
+        ..RunOptions::default()
+    };
+    assert!(run_source(&repeats(3), options(false)).is_ok());
+    let err = run_source(&calls(4), options(true)).unwrap_err();
+    assert_eq!(err.code().id(), "K0108");
+}
+
+#[test]
+fn arithmetic_gives_the_same_results_with_and_without_calls() {
+    // A call anywhere in an expression makes it go the long way round, so
+    // wrapping each variable in `id` checks the quick way against it.
+    let cases = [
+        "a * 3 + b",
+        "a + b * 4 - c / 3 + 2",
+        "a - b * c + b * b / a",
+        "0 - big - 1",
+        "big + a",
+        "a - big * b",
+        "0 - big - 1 / b",
+        "a / c",
+        "a + b / c * big",
+        "a + big * b / c",
+    ];
+    let run = |expr: &str| {
+        let src = format!(
+            "cast_on a = 7; cast_on b = 3; cast_on c = 0;\n\
+             cast_on big = 9223372036854775807;\npurl {expr};"
+        );
+        let stmts = knitlang::parse_src(&src).unwrap();
+        let out = Shared::default();
+        let mut interp = knitlang::Interpreter::with_output(Box::new(out.clone()));
+        interp.register_builtin("id", 1, |args| Ok(args[0]));
+        // Spans move with the added calls, so only codes and messages are
+        // compared.
+        let result = interp.run(&stmts).map_err(|e| (e.code, e.message));
+        let printed = String::from_utf8(out.0.borrow().clone()).unwrap();
+        (printed, result)
+    };
+    for expr in cases {
+        let called: Vec<String> = expr
+            .split(' ')
+            .map(|word| {
+                if word.starts_with(char::is_alphabetic) {
+                    format!("id({word})")
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect();
+        let called = called.join(" ");
+        assert_eq!(run(expr), run(&called), "{expr} and {called}");
+    }
+}
+
+#[test]
+fn sized_patterns_knit_the_numbers_of_the_chosen_size() {
+    let src = "sizes S, M, L;\n\
+               cast_on width = 8 (10, 12);\n\
+               cast_on width;\n\
+               repeat 2 (3, 4) { k width; }\n";
+    let knit = |size: Option<&str>| {
+        let options = RunOptions {
+            size: size.map(String::from),
+            ..RunOptions::default()
+        };
+        run_source(src, options)
+    };
+    for (size, index, width, rows) in [
+        (None, 0, 8, 2),
+        (Some("M"), 1, 10, 3),
+        (Some("L"), 2, 12, 4),
+    ] {
+        let out = knit(size).unwrap();
+        assert_eq!(out.variables["SIZE"], index, "{size:?}");
+        assert_eq!(out.variables["width"], width, "{size:?}");
+        assert_eq!(out.stats.rows, rows, "{size:?}");
+        assert_eq!(out.stats.stitches, width as usize * rows, "{size:?}");
+    }
+
+    // A size the pattern does not declare is an error at its declaration.
+    let err = knit(Some("XL")).unwrap_err();
+    assert_eq!(err.code().id(), "K0318");
+    assert_eq!(
+        err.to_string(),
+        "there is no size `XL`; the pattern is sized S, M, L; did you mean `L`?"
+    );
+    assert_eq!(err.span(), knitlang::Span::new(0, 14));
+
+    // A tuple of the wrong length is found by the parser, spanning the tuple.
+    let err = knitlang::parse_src("sizes S, M;\ncast_on 4 (6, 8);").unwrap_err();
+    assert_eq!(err.code.id(), "K0114");
+    assert_eq!(err.span, knitlang::Span::new(20, 28));
+    // Outside the parser's sight, as in a tree built by hand, the run
+    // reports it instead.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let tuple = knitlang::Expr::sizes(vec![4, 6], knitlang::Span::new(0, 5));
+    let stmts = [knitlang::Stmt {
+        kind: knitlang::StmtKind::Purl(tuple),
+        span: knitlang::Span::new(0, 5),
+    }];
+    assert_eq!(interp.run(&stmts).unwrap_err().code.id(), "K0114");
+
+    // Sizes are part of the tree and of saved state.
+    let stmts = knitlang::parse_src(src).unwrap();
+    let json = knitlang::ast_to_json(&stmts);
+    assert_eq!(knitlang::ast_from_json(&json).unwrap(), stmts);
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.set_size(Some("M"));
+    interp.run(&stmts[..2]).unwrap();
+    let mut restored = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let mut state = Vec::new();
+    interp.save_state(&mut state).unwrap();
+    restored.load_state(&state[..]).unwrap();
+    assert_eq!(restored.size(), Some(1));
+    assert_eq!(restored.get_var("width"), Some(10));
+}
+
+#[test]
+fn alternate_takes_turns_over_the_passes_of_its_repeat() {
+    let output = |src: &str| {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let sink = Shared(out.clone());
+        let mut interp = knitlang::Interpreter::with_output(Box::new(sink));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        let text = String::from_utf8(out.borrow().clone()).unwrap();
+        text.lines()
+            .map(|line| line.parse().unwrap())
+            .collect::<Vec<i64>>()
+    };
+    // Four bodies over ten passes go round twice and a half.
+    assert_eq!(
+        output("repeat 10 { alternate { purl 1; } { purl 2; } { purl 3; } { purl 4; } }"),
+        [1, 2, 3, 4, 1, 2, 3, 4, 1, 2]
+    );
+    // Each takes turns over the innermost repeat, starting again with it.
+    assert_eq!(
+        output(
+            "repeat 2 {
+                repeat 3 { alternate { purl 1; } { purl 2; } }
+                alternate { purl 10; } { purl 20; }
+             }"
+        ),
+        [1, 2, 1, 10, 1, 2, 1, 20]
+    );
+    // Bodies nested in one, or in a block inside the repeat, still follow it.
+    assert_eq!(
+        output(
+            "cast_on 4; repeat 4 { mirror { alternate { purl 1; } { alternate { purl 2; } \
+             { purl 3; } { purl 4; } } } }"
+        ),
+        [1, 3, 1, 2]
+    );
+
+    // The rows it works are followed by the stitch-count check and charted.
+    let src = "cast_on 4; repeat 10 { alternate { k 4; } { p 4; } }";
+    let stmts = knitlang::parse_src(src).unwrap();
+    assert_eq!(
+        knitlang::counts::check(&stmts),
+        knitlang::counts::Report::default()
+    );
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&stmts).unwrap();
+    let rows = interp.fabric().rows();
+    assert_eq!(rows.len(), 10);
+    assert!(rows.iter().step_by(2).all(|row| row == &[Stitch::Knit; 4]));
+    assert!(rows
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .all(|row| row == &[Stitch::Purl; 4]));
+    let check = |src| knitlang::counts::check(&knitlang::parse_src(src).unwrap());
+    // Passes that come round to the same needles are not worked one by one.
+    let report = check(
+        "cast_on 6; repeat 999999999 { alternate { k 6; } { k 2; k2tog; k 2; } { m1; p 5; } }
+         k 5;",
+    );
+    assert_eq!(report.unverified, None);
+    let rows: Vec<_> = report
+        .mismatches
+        .iter()
+        .map(|m| (m.row, m.worked, m.expected))
+        .collect();
+    assert_eq!(rows, [(1000000000, 5, 6)]);
+
+    // The optimizer leaves the repeat whole, as copies of its body could
+    // not take turns.
+    let (stmts, _) = knitlang::optimize::optimize(
+        knitlang::parse_src("repeat 2 { alternate { purl 1; } { purl 2; } }").unwrap(),
+        &Default::default(),
+    );
+    assert!(matches!(stmts[0].kind, knitlang::StmtKind::Repeat(..)));
+
+    // Outside a repeat there are no passes to take turns over.
+    let err = knitlang::parse_src("cast_on 4; mirror { alternate { k 4; } }").unwrap_err();
+    assert_eq!(err.code, knitlang::Code::AlternateOutsideRepeat);
+    assert_eq!(err.span, knitlang::Span::new(20, 29));
+    let errors = knitlang::try_parse("alternate { k 1; } { p 1; }\nk 1 k;").unwrap_err();
+    assert_eq!(errors.len(), 2, "{errors:?}");
+}
+
+#[test]
+fn mirrored_shaping_matches_the_other_side_written_by_hand() {
+    let knit = |src: &str| {
+        let stmts = knitlang::parse_src(src).unwrap();
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&stmts).map(|_| interp)
+    };
+    // The left front decreases at the neck edge, the end of each right-side
+    // row; the right front at its start, leaning the other way.
+    let left = "repeat 3 { knit w = w - 1; k w - 2; k2tog; k 1; p w; }";
+    let right =
+        knit("cast_on w = 12; cast_on w; repeat 3 { knit w = w - 1; k 1; ssk; k w - 2; p w; }")
+            .unwrap();
+    for mirrored in [
+        format!("cast_on w = 12; cast_on w; mirror {{ {left} }}"),
+        "cast_on w = 12; cast_on w; \
+         repeat 3 { knit w = w - 1; mirror { k w - 2; k2tog; k 1; } mirror { p w; } }"
+            .to_string(),
+    ] {
+        let mirrored = knit(&mirrored).unwrap();
+        assert_eq!(mirrored.fabric().rows(), right.fabric().rows());
+        assert_eq!(
+            knitlang::written::instructions(mirrored.fabric()),
+            knitlang::written::instructions(right.fabric())
+        );
+    }
+    assert_eq!(
+        knitlang::written::instructions(right.fabric()),
+        "Cast on 12 sts.\nRow 1 (RS): k1, ssk, k9.\nRow 2 (WS): p11.\n\
+         Row 3 (RS): k1, ssk, k8.\nRow 4 (WS): p10.\nRow 5 (RS): k1, ssk, k7.\nRow 6 (WS): p9.\n"
+    );
+
+    // Cables cross the other way, and mirroring twice undoes it; a block
+    // ending part way through a row reverses the stitches it has.
+    let twice = knit("cast_on 10; mirror { mirror { c4f; k 2; } c4b; }").unwrap();
+    assert_eq!(
+        twice.fabric().rows()[0],
+        [
+            Stitch::Cable(2, Cross::Front),
+            Stitch::Cable(2, Cross::Front),
+            Stitch::Knit,
+            Stitch::Knit
+        ]
+    );
+    let part = knit("cast_on 6; k 1; mirror { k2tog; p 1; } k 2;").unwrap();
+    assert_eq!(
+        part.fabric().rows()[0],
+        [
+            Stitch::Knit,
+            Stitch::Purl,
+            Stitch::Ssk,
+            Stitch::Knit,
+            Stitch::Knit
+        ]
+    );
+
+    // What is not a stitch cannot be mirrored.
+    let err = knit("cast_on 4; mirror { k 2; wrap_turn; }").err().unwrap();
+    assert_eq!(err.code.id(), "K0319");
+    assert_eq!(
+        err.message,
+        "cannot wrap and turn inside `mirror`; only stitches can be mirrored"
+    );
+    assert_eq!(err.span, knitlang::Span::new(25, 35));
+}
+
+#[test]
+fn builtin_patterns_chart_flat_and_in_the_round() {
+    let knit = |src: &str| {
+        let stmts = knitlang::parse_src(src).map_err(|e| (e.code.id(), e.message))?;
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        match interp.run(&stmts) {
+            Ok(_) => Ok(interp),
+            Err(e) => Err((e.code.id(), e.message)),
+        }
+    };
+    for (call, golden) in [
+        (
+            "stockinette(4)",
+            include_str!("golden/patterns/stockinette.txt"),
+        ),
+        ("garter(4)", include_str!("golden/patterns/garter.txt")),
+        ("seed(4)", include_str!("golden/patterns/seed.txt")),
+        ("rib(2, 1, 4)", include_str!("golden/patterns/rib.txt")),
+    ] {
+        let mut charts = String::new();
+        for mode in ["flat", "round"] {
+            let src = format!("mode {mode}; cast_on 7; {call};");
+            let interp = knit(&src).unwrap();
+            charts += &format!("{src}\n{}", knitlang::chart::text(interp.fabric()));
+        }
+        assert_eq!(charts, golden, "{call}");
+    }
+
+    // A pattern is the same stitches as writing them out, and starts afresh
+    // on whatever the needles hold.
+    let by_hand = include_str!("golden/seed.knit");
+    let built_in = knit("cast_on 11; k 11; seed(4); k 11;").unwrap();
+    assert_eq!(
+        knitlang::written::instructions(built_in.fabric()),
+        include_str!("golden/seed.txt")
+    );
+    assert_eq!(
+        built_in.fabric().rows(),
+        knit(by_hand).unwrap().fabric().rows()
+    );
+    // Across the five stitches a decrease leaves, worked back from the
+    // wrong side.
+    let narrowed = knit("cast_on 6; k 1; k2tog; k 3; rib(1, 1, 2);").unwrap();
+    assert_eq!(
+        narrowed.fabric().rows()[1],
+        [Stitch::Purl, Stitch::Knit]
+            .repeat(2)
+            .into_iter()
+            .chain([Stitch::Purl])
+            .collect::<Vec<_>>()
+    );
+
+    let error = |src| knit(src).err().unwrap();
+    assert_eq!(
+        error("seed(2);"),
+        (
+            "K0304",
+            "no stitches to work seed across; cast some on with `cast_on <count>;`".to_string()
+        )
+    );
+    assert_eq!(
+        error("cast_on 4; k 1; garter(2);"),
+        (
+            "K0320",
+            "cannot start garter part way through a row (3 stitches left to work)".to_string()
+        )
+    );
+    assert_eq!(error("cast_on 4; rib(0, 0, 1);").0, "K0320");
+    assert_eq!(
+        error("cast_on 4; stockinette(0 - 1);"),
+        (
+            "K0303",
+            "cannot work stockinette with -1 for `rows`".to_string()
+        )
+    );
+    assert_eq!(
+        error("cast_on 4; rib(2, 2);"),
+        (
+            "K0202",
+            "`rib` takes 3 arguments (k, p, rows), but was given 2".to_string()
+        )
+    );
+    // The names are only patterns before their arguments.
+    let interp = knit("cast_on seed = 2; purl seed;").unwrap();
+    assert_eq!(interp.get_var("seed"), Some(2));
+}
+
+#[test]
+fn stitch_counts_are_followed_through_repeats_and_decreases() {
+    let check = |src| knitlang::counts::check(&knitlang::parse_src(src).unwrap());
+    // Each pass of the repeat decreases a stitch and knits one fewer, so its
+    // rows line up with the needles; the rows after it do not.
+    let report = check(
+        "cast_on w = 8; cast_on 10; repeat 3 { k2tog; k w; knit w = w - 1; }
+         k 5; cast_on 4; k 3;",
+    );
+    assert_eq!(report.unverified, None);
+    let rows: Vec<_> = report
+        .mismatches
+        .iter()
+        .map(|m| (m.row, m.worked, m.expected, m.before.is_some()))
+        .collect();
+    assert_eq!(rows, [(4, 5, 7, true), (5, 3, 11, false)]);
+
+    // Variables set from numbers are followed; `repeat` passes that leave
+    // the needles as they were are not worked one by one.
+    let report = check("cast_on w = 12; cast_on w; repeat 1000000000 { k w / 2; p w / 2; }");
+    assert_eq!(report, knitlang::counts::Report::default());
+
+    // A graded count is only known once a size is chosen.
+    let report = check("sizes S, M; cast_on 4 (6); k 3;");
+    assert!(report.mismatches.is_empty());
+    let unverified = report.unverified.unwrap();
+    assert_eq!(unverified.span, knitlang::Span::new(12, 26));
+}
+
+#[test]
+fn yarn_overs_take_a_cell_and_are_balanced_by_decreases() {
+    let src = "cast_on 6; k 1; yo; k2tog; k 1; yo; ssk;";
+    let stmts = knitlang::parse_src(src).unwrap();
+    assert_eq!(
+        knitlang::counts::check(&stmts),
+        knitlang::counts::Report::default()
+    );
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&stmts).unwrap();
+    let fabric = interp.fabric();
+    assert_eq!(
+        knitlang::chart::text(fabric),
+        "╲╲○│╱╱○│ 1 RS\n87654321\n\
+         │ knit\n╱ knit two together\n╲ slip, slip, knit\n○ yarn over\n"
+    );
+    assert_eq!(
+        knitlang::written::instructions(fabric),
+        "Cast on 6 sts.\nRow 1 (RS): k1, yo, k2tog, k1, yo, ssk.\n"
+    );
+
+    // Every yarn over needs a decrease of its own; a cable is not one.
+    let check = |src| knitlang::counts::check(&knitlang::parse_src(src).unwrap());
+    assert_eq!(check("cast_on 10; yo; c4f; yo; k2tog;").unbalanced.len(), 1);
+    let report = check("cast_on 5; yo; k 1; k2tog; yo; k 2;");
+    assert!(report.mismatches.is_empty());
+    let rows: Vec<_> = report
+        .unbalanced
+        .iter()
+        .map(|row| (row.row, row.yarn_overs, row.decreases))
+        .collect();
+    assert_eq!(rows, [(1, 2, 1)]);
+}
+
+#[test]
+fn distribute_spaces_shaping_evenly_across_the_row() {
+    let row = |src: &str| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        let row = interp.fabric().rows()[0].iter();
+        row.map(|stitch| stitch.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    // Even divisions, and uneven ones with the longer stretches spread out
+    // and the last stretch never empty.
+    assert_eq!(
+        row("cast_on 20; distribute inc 4;"),
+        "k k k k m1 k k k k m1 k k k k m1 k k k k m1 k k k k"
+    );
+    assert_eq!(
+        row("cast_on 10; distribute inc 3;"),
+        "k k m1 k k k m1 k k m1 k k k"
+    );
+    assert_eq!(row("cast_on 3; distribute inc 3;"), "m1 k m1 k m1 k");
+    assert_eq!(
+        row("cast_on 24; distribute dec 4;"),
+        "k k k k2tog k k k k2tog k k k k2tog k k k k2tog k k k k"
+    );
+    assert_eq!(
+        row("cast_on 11; distribute dec 3;"),
+        "k k2tog k k2tog k k2tog k k"
+    );
+    assert_eq!(row("cast_on 4; distribute dec 2;"), "k2tog k2tog");
+    assert_eq!(row("cast_on 4; distribute inc 0;"), "k k k k");
+    // Part way through a row, only what is left of it is shaped.
+    assert_eq!(
+        row("cast_on 9; p 2; distribute dec 2; k 5;"),
+        "p p k k2tog k k2tog k"
+    );
+
+    // Charts and written instructions show the stitches it worked.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 8; distribute inc 2;").unwrap())
+        .unwrap();
+    let fabric = interp.fabric();
+    assert_eq!(
+        knitlang::written::instructions(fabric),
+        "Cast on 8 sts.\nRow 1 (RS): k2, *m1, k3; rep from * to end.\n"
+    );
+    assert!(knitlang::chart::text(fabric).starts_with("│││∧│││∧││ 1 RS\n"));
+
+    let spread: Vec<_> = knitlang::Shaping::Increase.spread(2, 7).collect();
+    let (k, m1) = (knitlang::Stitch::Knit, knitlang::Stitch::M1);
+    assert_eq!(spread, [(k, 2), (m1, 1), (k, 2), (m1, 1), (k, 3)]);
+
+    let err = |src| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap_err()
+    };
+    let e = err("cast_on 10; k 3; distribute dec 4;");
+    assert_eq!(e.code, knitlang::Code::TooManyDecreases);
+    assert_eq!(
+        e.message,
+        "cannot decrease 4 stitches evenly across 7 stitches; each decrease works 2"
+    );
+    assert_eq!(err("distribute inc 2;").code, knitlang::Code::NoStitches);
+    assert_eq!(
+        err("cast_on 4; distribute dec 0 - 1;").code,
+        knitlang::Code::NegativeCount
+    );
+
+    // The check follows the stitches it makes and loses.
+    let check = |src| knitlang::counts::check(&knitlang::parse_src(src).unwrap());
+    let report = check("cast_on 10; distribute inc 3; distribute dec 6; k 6;");
+    let rows: Vec<_> = report
+        .mismatches
+        .iter()
+        .map(|m| (m.row, m.worked, m.expected))
+        .collect();
+    assert_eq!(rows, [(3, 6, 7)]);
+    assert!(report.unbalanced.is_empty());
+}
+
+#[test]
+fn pattern_summary_rounds_measurements_to_the_millimetre() {
+    let summary = |src, mm| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        knitlang::PatternSummary::new(interp.fabric(), mm)
+    };
+    // 9 stitches at 24 to 10 cm are 37.5 mm, which rounds up; 5 rows at 30
+    // are 16.67 mm.
+    let piece = summary("gauge 24, 30; cast_on 9; k 45;", Some(33));
+    let size = piece.dimensions.unwrap();
+    assert_eq!((size.width_mm, size.length_mm), (38, 17));
+    // 54 stitches cast on and made take 1782 mm of yarn.
+    assert_eq!(piece.yarn_metres, Some(2));
+    assert!(piece
+        .to_string()
+        .contains("finished:    3.8 cm wide, 1.7 cm long\n"));
+
+    // A stitch cast on between rows counts once, and the rows after it are
+    // a section of their own until a decrease.
+    let piece = summary("cast_on 3; k 3; cast_on 1; k 4; k2tog; k 2;", Some(1000));
+    let sections: Vec<_> = piece
+        .sections
+        .iter()
+        .map(|s| (s.first, s.last, s.stitches))
+        .collect();
+    assert_eq!(sections, [(1, 1, 3), (2, 3, 4)]);
+    assert_eq!((piece.decreases, piece.max_width), (1, 4));
+    assert_eq!(piece.yarn_metres, Some(3 + 3 + 1 + 4 + 3));
+    assert_eq!(piece.dimensions, None);
+
+    let error = run_source("gauge 22, 0 - 30;", RunOptions::default()).unwrap_err();
+    assert_eq!(error.code().id(), "K0321");
+    // The gauge is saved with the rest of a session.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("gauge 22, 30;").unwrap())
+        .unwrap();
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    let gauge = resumed.fabric().gauge().unwrap();
+    assert_eq!((gauge.stitches, gauge.rows), (22, 30));
+}
+
+#[test]
+fn meta_blocks_are_parsed_checked_and_kept_with_the_fabric() {
+    use knitlang::{MetaValue, PatternMeta, StmtKind};
+    let src = "meta {\n    title = \"Winter Hat\";\n    yarn = \"DK wool\";\n    gauge = 22;\n    \
+               designer = \"A. Knitter\";\n}\ncast_on 4;\n";
+    let stmts = knitlang::parse_src(src).unwrap();
+    let StmtKind::Meta(entries) = &stmts[0].kind else {
+        panic!("{:?}", stmts[0]);
+    };
+    let keys: Vec<_> = entries.iter().map(|entry| entry.key.as_str()).collect();
+    assert_eq!(keys, ["title", "yarn", "gauge", "designer"]);
+    assert_eq!(entries[2].value, MetaValue::Number(22));
+    assert_eq!(
+        &src[entries[0].span.start..entries[0].span.end],
+        "title = \"Winter Hat\";"
+    );
+    assert_eq!(knitlang::format_program(&stmts), src);
+    let json = knitlang::ast_to_json(&stmts);
+    assert_eq!(knitlang::ast_from_json(&json).unwrap(), stmts);
+    // A string the parser could not read back is refused.
+    let quoted = json.replace("DK wool", "DK \\\"wool\\\"");
+    let err = knitlang::ast_from_json(&quoted).unwrap_err();
+    assert_eq!(err.path, "$[0].entries[1].value");
+
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&stmts).unwrap();
+    let meta = interp.fabric().meta().unwrap();
+    assert_eq!(meta.title, Some(MetaValue::Str("Winter Hat".to_string())));
+    assert_eq!(meta.author, None);
+    assert_eq!(
+        meta.other,
+        [(
+            "designer".to_string(),
+            MetaValue::Str("A. Knitter".to_string())
+        )]
+    );
+    assert_eq!(
+        interp.state_json()["meta"],
+        serde_json::json!({
+            "title": "Winter Hat",
+            "yarn": "DK wool",
+            "gauge": 22,
+            "designer": "A. Knitter",
+        })
+    );
+    // It is saved with the rest of a session.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    assert_eq!(resumed.fabric().meta(), Some(meta));
+    // Without a block there is nothing to report.
+    let plain = knitlang::run("cast_on 4;", Box::new(io::sink())).unwrap();
+    assert_eq!(plain.fabric().meta(), None::<&PatternMeta>);
+    assert!(plain.state_json().get("meta").is_none());
+
+    let error = |src: &str| {
+        let err = knitlang::parse_src(src).unwrap_err();
+        (err.code.id(), err.message, err.span, err.labels)
+    };
+    let (code, message, span, labels) =
+        error("meta {\n    title = \"Hat\";\n    title = \"Cap\";\n}");
+    assert_eq!(code, "K0117");
+    assert_eq!(message, "Duplicate meta key `title`");
+    assert_eq!((span.start, span.end), (30, 35));
+    assert_eq!(labels[0].0, knitlang::Span::new(11, 25));
+    // The block goes first, and only once.
+    for src in [
+        "cast_on 4; meta { title = \"Hat\"; }",
+        "meta { } meta { title = \"Hat\"; }",
+        "repeat 2 { meta { title = \"Hat\"; } }",
+    ] {
+        assert_eq!(error(src).0, "K0116", "{src}");
+    }
+    assert_eq!(
+        error("meta { title = 4 * 2; }").1,
+        "Expected ; after meta entry, found `*`"
+    );
+    // Elsewhere `meta` is still a name.
+    assert!(knitlang::parse_src("cast_on meta = 2; k meta;").is_ok());
+}
+
+#[test]
+fn written_instructions_start_with_the_meta_header() {
+    let knit = |src: &str, size: Option<&str>| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.set_size(size);
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        interp
+    };
+    let src = "meta { needle = \"4mm\"; title = \"Winter Hat\"; gauge = \"22 sts to 10 cm\"; \
+               author = \"A. Knitter\"; notes = \"unused\"; }\ncast_on 4; k 4;";
+    assert_eq!(
+        knitlang::written::instructions(knit(src, None).fabric()),
+        "Winter Hat\nAuthor: A. Knitter\nNeedle: 4mm\nGauge: 22 sts to 10 cm\n\n\
+         Cast on 4 sts.\nRow 1 (RS): k4.\n"
+    );
+    // A block with no keys written instructions show adds nothing.
+    assert_eq!(
+        knitlang::written::instructions(knit("meta { notes = 1; } cast_on 2;", None).fabric()),
+        "Cast on 2 sts.\n"
+    );
+
+    // A graded pattern has the header once, before the sizes.
+    let src = "meta { title = \"Scarf\"; } sizes S, M; cast_on 4 (6); k 4 (6);";
+    let (small, medium) = (knit(src, Some("S")), knit(src, Some("M")));
+    assert_eq!(
+        knitlang::written::graded(&["S", "M"], &[small.fabric(), medium.fabric()]),
+        "Scarf\n\nSizes: S (M).\nCast on 4 (6) sts.\nRow 1 (RS): k4 (6).\n"
+    );
+    let src = "meta { title = \"Scarf\"; } sizes S, M; cast_on 4; k 4; repeat SIZE { p 4; }";
+    let (small, medium) = (knit(src, Some("S")), knit(src, Some("M")));
+    assert_eq!(
+        knitlang::written::graded(&["S", "M"], &[small.fabric(), medium.fabric()]),
+        "Scarf\n\nSize S:\nCast on 4 sts.\nRow 1 (RS): k4.\n\n\
+         Size M:\nCast on 4 sts.\nRow 1 (RS): k4.\nRow 2 (WS): p4.\n"
+    );
+}
+
+#[test]
+fn partial_bind_offs_shape_an_armhole() {
+    // 20 stitches, 3 bound off at the start of the next 2 rows, then one
+    // decreased at each end.
+    let src = "cast_on 20;\nk 20;\np 20;\nbind_off 3; k 17;\nbind_off 3; p 14;\n\
+               k2tog; k 10; ssk;\np 12;\n";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let produced: Vec<usize> = fabric
+        .rows()
+        .iter()
+        .map(|row| row.iter().map(|stitch| stitch.produces()).sum())
+        .collect();
+    assert_eq!(produced, [20, 20, 17, 14, 12, 12]);
+    assert_eq!(fabric.width(), 12);
+    assert_eq!(
+        fabric.rows()[2][..4],
+        [
+            Stitch::BindOff,
+            Stitch::BindOff,
+            Stitch::BindOff,
+            Stitch::Knit
+        ]
+    );
+    // Only the stitches bound off at the right edge move the rows after.
+    let offsets: Vec<usize> = (0..6).map(|r| fabric.offset(r)).collect();
+    assert_eq!(offsets, [0, 0, 0, 3, 3, 3]);
+    assert_eq!(
+        knitlang::chart::text(fabric),
+        "WS 6      ││││││││││││\n        \
+         ╲╲││││││││││╱╱    5 RS\n\
+         WS 4 ───││││││││││││││\n     \
+         │││││││││││││││││─── 3 RS\n\
+         WS 2 ││││││││││││││││││││\n     \
+         ││││││││││││││││││││ 1 RS\n     \
+         09876543210987654321\n\
+         │ knit on RS, purl on WS\n╱ knit two together\n╲ slip, slip, knit\n─ bind off\n"
+    );
+    assert!(knitlang::written::instructions(fabric)
+        .contains("Row 3 (RS): BO 3 sts, k17.\nRow 4 (WS): BO 3 sts, p14.\n"));
+    // Bound-off stitches are not decreases.
+    assert_eq!(interp.stats().decreases, 2);
+
+    // The bound-off stitches and where the rows start are saved with the
+    // rest of a session.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    assert_eq!(
+        knitlang::chart::text(resumed.fabric()),
+        knitlang::chart::text(interp.fabric())
+    );
+
+    // Binding off more than the row has left fails; binding off every
+    // stitch ends the program as a bare `bind_off;` does.
+    let e = run_source("cast_on 10; k 6; bind_off 5;", RunOptions::default()).unwrap_err();
+    assert_eq!(e.code(), knitlang::Code::TooManyBoundOff);
+    assert_eq!(
+        e.to_string(),
+        "cannot bind off 5 stitches with only 4 stitches left in the row"
+    );
+    let all = run_source("cast_on 4; k 4; bind_off 4; purl 1;", RunOptions::default()).unwrap();
+    let bare = run_source("cast_on 4; k 4; bind_off; purl 1;", RunOptions::default()).unwrap();
+    assert!(all.bound_off && bare.bound_off);
+    assert_eq!((all.stdout, all.stats.rows), (bare.stdout, bare.stats.rows));
+
+    // The check follows the stitches the row has left.
+    let check = |src| knitlang::counts::check(&knitlang::parse_src(src).unwrap());
+    let report = check("cast_on 10; bind_off 2; k 8; k 7;");
+    let rows: Vec<_> = report
+        .mismatches
+        .iter()
+        .map(|m| (m.row, m.worked, m.expected))
+        .collect();
+    assert_eq!(rows, [(2, 7, 8)]);
+}
+
+#[test]
+fn expect_stitches_checks_the_live_stitch_count() {
+    // A checkpoint that passes, at the end of a row and part way through
+    // one, where the stitches made and those still to work both count.
+    let src = "cast_on 10; k2tog; k 8; expect_stitches 9; k 4; expect_stitches 9;";
+    assert!(run_source(src, RunOptions::default()).is_ok());
+
+    let src = "cast_on 10;\nk2tog;\nk 8;\nexpect_stitches 10;\npurl 1;\n";
+    let e = run_source(src, RunOptions::default()).unwrap_err();
+    assert_eq!(e.code(), knitlang::Code::WrongStitchCount);
+    assert_eq!(
+        e.to_string(),
+        "expected 10 stitches on the needles, but there are 9"
+    );
+    let KnitError::Runtime(e) = e else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(&src[e.span.start..e.span.end], "expect_stitches 10;");
+
+    // In a repeat it checks every pass, so it fails only once the
+    // decreases have taken the count below it.
+    let src = "cast_on 12;\nrepeat 3 {\n    k2tog; k 10;\n    expect_stitches 10;\n    p 11;\n}\n";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    let e = interp.run(&knitlang::parse_src(src).unwrap()).unwrap_err();
+    assert_eq!(e.code, knitlang::Code::WrongStitchCount);
+    assert_eq!(
+        e.message,
+        "expected 10 stitches on the needles, but there are 11"
+    );
+    assert_eq!(interp.fabric().rows().len(), 1);
+
+    // `expect_stitches?` only warns, once each time it finds the count
+    // wrong, and the run goes on.
+    let src =
+        "cast_on 4; expect_stitches? 5; m1; k 4; expect_stitches? 5; m1; k 5; expect_stitches? 5;";
+    let out = run_source(src, RunOptions::default()).unwrap();
+    assert_eq!(out.stats.rows, 2);
+    let warnings: Vec<_> = out.warnings.iter().map(|w| w.message.as_str()).collect();
+    assert_eq!(
+        warnings,
+        [
+            "expected 5 stitches on the needles, but there are 4",
+            "expected 5 stitches on the needles, but there are 6"
+        ]
+    );
+    assert!(out
+        .warnings
+        .iter()
+        .all(|w| w.code == knitlang::Code::StitchCountWarning));
+}
+
+#[test]
+fn notes_are_written_between_the_rows_they_ran_between() {
+    let src = "note \"Use 4mm needles\";\ncast_on 8;\nknit rows = 2;\n\
+               note \"Work \", rows, \" rows of rib\";\n\
+               repeat rows { k 2; p 2; k 2; p 2; }\n\
+               note \"Change to 3.5mm needles\";\nrepeat 2 { k 8; }\n\
+               k 4; note \"Place a marker here\"; k 4;\nnote \"Bind off loosely\";\n";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let rows: Vec<_> = fabric.notes().iter().map(|note| note.row).collect();
+    assert_eq!(rows, [None, Some(0), Some(2), Some(4), Some(5)]);
+    // The rows of stocking stitch either side of a note stay apart.
+    assert_eq!(
+        knitlang::written::instructions(fabric),
+        "Use 4mm needles\nCast on 8 sts.\nWork 2 rows of rib\n\
+         Rows 1–2: *k2, p2; rep from * to end.\nChange to 3.5mm needles\nRows 3–4: k8.\n\
+         Place a marker here\nRow 5 (RS): k8.\nBind off loosely\n"
+    );
+
+    // A note after a cast on but before any row goes after the cast on.
+    let src = "cast_on 4; note \"Join, taking care not to twist\";";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    assert_eq!(
+        knitlang::written::instructions(interp.fabric()),
+        "Cast on 4 sts.\nJoin, taking care not to twist\n"
+    );
+
+    // Saved sessions keep them, and where they go.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    assert_eq!(resumed.fabric().notes(), interp.fabric().notes());
+
+    // Notes knit nothing, but their expressions must still evaluate.
+    let out = run_source("cast_on 4; note \"k\", 4 / 0; k 4;", RunOptions::default());
+    assert_eq!(out.unwrap_err().code(), knitlang::Code::DivisionByZero);
+}
+
+#[test]
+fn chart_statements_chart_the_rows_since_the_last() {
+    let src = "cast_on 4;\nrepeat 2 { k 2; p 2; k 2; p 2; }\nchart \"ribbing\";\n\
+               purl 1;\nk 4; p 4;\nchart;\nchart \"nothing new\";\n";
+    let out = run_source(src, RunOptions::default()).unwrap();
+    assert_eq!(
+        out.stdout,
+        "ribbing\nWS 4 ••││\n     ••││ 3 RS\nWS 2 ••││\n     ••││ 1 RS\n     4321\n\
+         │ knit on RS, purl on WS\n• purl on RS, knit on WS\n\
+         1\n\
+         WS 6 ││││\n     ││││ 5 RS\n     4321\n│ knit on RS, purl on WS\n\
+         nothing new\n"
+    );
+
+    // The rows charted so far are kept in saved sessions.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 2; k 2; chart; p 2;").unwrap())
+        .unwrap();
+    assert_eq!(interp.fabric().charted(), 1);
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let out = Shared::default();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(out.clone()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    resumed
+        .run(&knitlang::parse_src("chart;").unwrap())
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out.0.borrow().clone()).unwrap(),
+        "WS 2 ││\n     21\n│ knit on RS, purl on WS\n"
+    );
+}
+
+#[test]
+fn needles_divide_a_piece_and_rejoin_it() {
+    // The back waits on a holder while the front is worked, then the front
+    // waits while the back is, and the two rejoin on the working needle.
+    let src = "cast_on 12;\nk 12;\nhold back;\ntransfer back front 6;\nresume front;\n\
+               p 6; k 6;\nhold front;\nresume back;\nk 6; p 6;\nresume front;\n\
+               purl count(front); purl count(back);\nk 12;\n";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let widths: Vec<usize> = fabric.rows().iter().map(Vec::len).collect();
+    assert_eq!(widths, [12, 6, 6, 6, 6, 12]);
+    assert_eq!(fabric.width(), 12);
+    let symbol = knitlang::Symbol::intern;
+    assert_eq!(
+        fabric.needles(),
+        [(symbol("back"), 0), (symbol("front"), 0)]
+    );
+    assert_eq!(fabric.moved(1), (12, 6));
+    assert_eq!(fabric.moved(3), (6, 6));
+    assert_eq!(fabric.moved(5), (0, 6));
+    assert_eq!(
+        knitlang::written::instructions(fabric),
+        "Cast on 12 sts.\nRow 1 (RS): k12.\nPlace 12 sts on hold.\n\
+         Return 6 sts from hold to the needle.\nRow 2 (WS): p6.\nRow 3 (RS): k6.\n\
+         Place 6 sts on hold.\nReturn 6 sts from hold to the needle.\nRow 4 (WS): k6.\n\
+         Row 5 (RS): p6.\nReturn 6 sts from hold to the needle.\nRow 6 (WS): k12.\n"
+    );
+
+    // Saved sessions keep the needles and what moved between them.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(saved.as_slice()).unwrap();
+    assert_eq!(resumed.fabric().needles(), interp.fabric().needles());
+    assert_eq!(resumed.fabric().moved(1), (12, 6));
+
+    let out = run_source(
+        "cast_on left = needle(6); purl count(left);",
+        RunOptions::default(),
+    );
+    assert_eq!(out.unwrap().stdout, "6\n");
+}
+
+#[test]
+fn needle_moves_that_cannot_happen_are_errors() {
+    let code = |src: &str| run_source(src, RunOptions::default()).unwrap_err().code();
+    assert_eq!(
+        code("cast_on left = needle(6); transfer left right 8;"),
+        knitlang::Code::TooManyTransferred
+    );
+    assert_eq!(
+        code("cast_on left = needle(6); transfer left right 0 - 1;"),
+        knitlang::Code::NegativeCount
+    );
+    assert_eq!(
+        code("transfer left right 1;"),
+        knitlang::Code::UndeclaredNeedle
+    );
+    assert_eq!(
+        code("cast_on 4; resume left;"),
+        knitlang::Code::UndeclaredNeedle
+    );
+    assert_eq!(
+        code("cast_on left = needle(6); cast_on 4; k 2; resume left;"),
+        knitlang::Code::NeedleMidRow
+    );
+    assert_eq!(
+        code("cast_on 8; k 4; hold left;"),
+        knitlang::Code::NeedleMidRow
+    );
+
+    let err = run_source(
+        "cast_on left = needle(6); purl count(lefft);",
+        RunOptions::default(),
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("needle `lefft` is not declared"),
+        "{err}"
+    );
+}
+
+#[test]
+fn html_pages_escape_the_pattern_strings() {
+    let src = "meta { title = \"<script>alert(1)</script>\"; author = \"Tom & Jerry\"; }\n\
+               note \"k2 <b>tog</b>\"; cast_on 2; note \"Then & again\"; k 2;";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let html = knitlang::html::html(interp.fabric(), &Default::default());
+    assert!(!html.contains("<script>"), "{html}");
+    assert!(!html.contains("<b>"), "{html}");
+    assert!(html.contains("<title>&lt;script>alert(1)&lt;/script></title>\n"));
+    assert!(html.contains("<h1>&lt;script>alert(1)&lt;/script></h1>\n"));
+    assert!(html.contains("<dt>Author</dt><dd>Tom &amp; Jerry</dd>\n"));
+    assert!(
+        html.contains("<section class=\"notes\">\n<h2>Notes</h2>\n<p>k2 &lt;b>tog&lt;/b></p>\n")
+    );
+    assert!(html.contains("<p class=\"note\">Then &amp; again</p>\n"));
+
+    // Without a title or rows the page still has a title, but no chart.
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 4;").unwrap())
+        .unwrap();
+    let html = knitlang::html::html(interp.fabric(), &Default::default());
+    assert!(html.contains("<title>Knitting pattern</title>\n"));
+    assert!(html.contains("<p>Cast on 4 sts.</p>\n"));
+    assert!(!html.contains("<svg"));
+}
+
+#[test]
+fn markdown_escapes_the_pattern_strings_and_follows_the_html() {
+    let src = "meta { title = \"#1 <Socks>\"; yarn = \"Sock_yarn [blue]\"; }\n\
+               note \"Use `magic loop`\"; cast_on 2; note \"*Or* DPNs\"; k 2;";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    let fabric = interp.fabric();
+    let markdown = knitlang::markdown::markdown(fabric, &Default::default(), None);
+    assert!(markdown.starts_with(
+        "# \\#1 \\<Socks\\>\n\n- **Yarn:** Sock\\_yarn \\[blue\\]\n\n\
+         ## Notes\n\nUse \\`magic loop\\`\n\n## Instructions\n\n\
+         - Cast on 2 sts.\n- *\\*Or\\* DPNs*\n- Row 1 (RS): k2.\n"
+    ));
+
+    // Both forms write the same document, section for section.
+    let html = knitlang::html::html(fabric, &Default::default());
+    let document = knitlang::document::Document::new(fabric);
+    for section in &document.sections {
+        assert!(markdown.contains(&format!("\n## {}\n", section.heading)));
+        assert!(html.contains(&format!("<h2>{}</h2>", section.heading)));
+    }
+    assert_eq!(document.sections.len(), 3);
+}
+
+#[test]
+fn csv_keeps_every_stitch_in_its_column() {
+    let csv = |src: &str| {
+        let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+        interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        knitlang::csv::csv(interp.fabric(), Default::default())
+    };
+    // Cables and decreases give their abbreviation once, in the first of
+    // the columns they span.
+    assert_eq!(
+        csv("cast_on 6; k 6; p 1; c4f; p 1; k2tog; k 4;"),
+        "row,side,1,2,3,4,5,6\r\n1,RS,k,k,k,k,k,k\r\n2,WS,k,C4F,,,,k\r\n\
+         3,RS,k2tog,,k,k,k,k\r\n"
+    );
+    // A short row pads the stitches it leaves unworked, and the wrapped
+    // one says so; stitches bound off at the right move later rows over.
+    assert_eq!(
+        csv("cast_on 6; k 6; p 4; w&t; k 4; p 6; bind_off 2; k 4; p 4;"),
+        "row,side,1,2,3,4,5,6\r\n1,RS,k,k,k,k,k,k\r\n2,WS,.,w&t,k,k,k,k\r\n\
+         3,RS,.,.,k,k,k,k\r\n4,WS,k,k,k,k,k,k\r\n5,RS,BO,BO,k,k,k,k\r\n6,WS,.,.,k,k,k,k\r\n"
+    );
+    // Rounds have no side, and nothing knitted is only the header.
+    assert_eq!(
+        csv("mode round; cast_on 2; k 2; p 2;"),
+        "row,side,1,2\r\n1,rnd,k,k\r\n2,rnd,p,p\r\n"
+    );
+    assert_eq!(csv("cast_on 2;"), "row,side\r\n");
+}
+
+#[test]
+fn csv_quotes_fields_as_rfc_4180_says() {
+    let src = "yarn mc = \"#000\"; yarn cc = \"#fff\"; cast_on 2; color mc; k 1; color cc; k 1;";
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp.run(&knitlang::parse_src(src).unwrap()).unwrap();
+    assert_eq!(
+        knitlang::csv::csv(interp.fabric(), Default::default()),
+        "row,side,1,2\r\n1,RS,k mc,k cc\r\n"
+    );
+    // Yarns restored from a saved session can have any name at all.
+    let mut saved = Vec::new();
+    interp.save_state(&mut saved).unwrap();
+    let mut state: serde_json::Value = serde_json::from_slice(&saved).unwrap();
+    state["fabric"]["yarns"][0]["name"] = "Navy, dark".into();
+    state["fabric"]["yarns"][1]["name"] = "\"Snow\"\nwhite".into();
+    let mut resumed = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    resumed.load_state(state.to_string().as_bytes()).unwrap();
+    assert_eq!(
+        knitlang::csv::csv(resumed.fabric(), Default::default()),
+        "row,side,1,2\r\n1,RS,\"k Navy, dark\",\"k \"\"Snow\"\"\nwhite\"\r\n"
+    );
+}
+
+#[test]
+fn animation_frames_fit_the_terminal_and_redraw_over_each_other() {
+    let mut interp = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    interp
+        .run(&knitlang::parse_src("cast_on 12; repeat 8 { k 12; }").unwrap())
+        .unwrap();
+    let symbols = knitlang::chart::ChartStyle::default();
+    // Seven lines leave room for the five lines of a two-row chart with its
+    // legend, and each is cut to ten columns.
+    let frame = knitlang::animate::frame(interp.fabric(), &symbols, 10, 7);
+    assert_eq!(frame.lines().count(), 6, "{frame:?}");
+    assert!(frame.lines().all(|line| line.chars().count() <= 10));
+    assert!(frame.starts_with("WS 8 •••"), "{frame}");
+    assert!(frame.ends_with("\nRow 8: 12 \n"), "{frame}");
+    // A terminal too small for even a row shows only the status line.
+    assert_eq!(
+        knitlang::animate::frame(interp.fabric(), &symbols, 80, 2),
+        "Row 8: 12 stitches on the needles\n"
+    );
+
+    let mut screen = Vec::new();
+    let mut animation = knitlang::animate::Animation::new(&mut screen, symbols, 80, 24);
+    let mut drawn = knitlang::Interpreter::with_output(Box::new(io::sink()));
+    for src in ["cast_on 2;", "k 2;", "p 2;"] {
+        drawn.run(&knitlang::parse_src(src).unwrap()).unwrap();
+        animation.draw(drawn.fabric()).unwrap();
+    }
+    animation.finish().unwrap();
+    drop(animation);
+    let screen = String::from_utf8(screen).unwrap();
+    // The cursor is hidden once and shown again once, and each frame after
+    // the first moves back over the whole of the one before it.
+    assert!(
+        screen.starts_with("\x1b[?25l2 stitches cast on\n"),
+        "{screen}"
+    );
+    assert!(screen.ends_with("Row 2: 2 stitches on the needles\n\x1b[?25h"));
+    assert_eq!(screen.matches("\x1b[?25h").count(), 1);
+    assert_eq!(screen.matches("\x1b[J").count(), 2);
+    assert!(screen.contains("cast on\n\x1b[1F\x1b[J"), "{screen}");
+    assert!(screen.contains("needles\n\x1b[4F\x1b[J"), "{screen}");
+}
+
+#[test]
+fn bench_timings_leave_out_warmup_and_stop_at_the_first_error() {
+    use knitlang::bench::{measure, Timings};
+    use std::time::Duration;
+
+    let ms = Duration::from_millis;
+    let timings = Timings::new(2, vec![ms(5), ms(1), ms(3)]);
+    assert_eq!(timings.times(), [ms(1), ms(3), ms(5)]);
+    assert_eq!(
+        (
+            timings.min(),
+            timings.median(),
+            timings.mean(),
+            timings.total()
+        ),
+        (ms(1), ms(3), ms(3), ms(9))
+    );
+    assert_eq!(timings.per_sec(30.0).round(), 10_000.0);
+
+    // Every warmup run is set up and run too, only not timed.
+    let (mut setups, mut runs) = (0, 0);
+    let timings = measure(
+        3,
+        4,
+        || setups += 1,
+        |()| {
+            runs += 1;
+            Ok::<_, String>(())
+        },
+    )
+    .unwrap();
+    assert_eq!((setups, runs, timings.runs(), timings.warmup), (7, 7, 4, 3));
+    assert!(timings.times().is_sorted());
+
+    let mut runs = 0;
+    let failed = measure(
+        1,
+        10,
+        || (),
+        |()| {
+            runs += 1;
+            if runs == 3 {
+                Err("third run")
+            } else {
+                Ok(())
+            }
+        },
+    );
+    assert_eq!((failed, runs), (Err("third run"), 3));
+}
@@ -0,0 +1,92 @@
+//! Drives the Python module from Rust, against an embedded interpreter:
+//! `cargo test --features python --test python`.
+#![cfg(feature = "python")]
+
+use knitlang::python::knitlang as module;
+use pyo3::prelude::*;
+use std::ffi::CStr;
+use std::sync::Once;
+
+/// Runs `code` with `knitlang` importable, panicking with the Python
+/// traceback if it raises.
+fn python(code: &CStr) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        pyo3::append_to_inittab!(module);
+        Python::initialize();
+    });
+    Python::attach(|py| {
+        if let Err(e) = py.run(code, None, None) {
+            e.display(py);
+            panic!("Python raised {e}");
+        }
+    });
+}
+
+#[test]
+fn run_returns_output_variables_and_errors() {
+    python(
+        cr#"
+import knitlang
+
+result = knitlang.run("cast_on width = 4; cast_on depth = width * 3; purl depth;")
+assert result["output"] == "12\n", result
+assert result["variables"] == {"width": 4, "depth": 12}, result
+assert list(result["variables"]) == ["depth", "width"]
+assert type(result["variables"]["width"]) is int
+assert result["errors"] == []
+
+result = knitlang.run("cast_on x = 2;\npurl x / 0;")
+[error] = result["errors"]
+assert isinstance(error, knitlang.KnitError)
+assert str(error) == "division by zero"
+assert (error.kind, error.line, error.column) == ("runtime", 2, 10), vars(error)
+assert result["variables"] == {"x": 2}
+
+[error] = knitlang.run("purl @;")["errors"]
+assert (error.kind, error.line, error.column) == ("lex", 1, 6)
+"#,
+    );
+}
+
+#[test]
+fn sessions_keep_state_and_raise_knit_errors() {
+    python(
+        cr#"
+import io
+import sys
+import knitlang
+
+session = knitlang.Session()
+printed, sys.stdout = sys.stdout, io.StringIO()
+try:
+    assert session.feed("cast_on rows = 0;") is None
+    assert session.feed("repeat 3 {") is None
+    assert session.needs_more
+    session.feed("knit rows = rows + 1; }")
+    assert not session.needs_more
+    session.feed("purl rows;")
+finally:
+    printed, sys.stdout = sys.stdout.getvalue(), printed
+assert printed == "3\n", printed
+assert session.feed("rows * 2") == 6
+assert session.variables == {"rows": 3}
+
+try:
+    session.feed("cast_on x = 1;\nknit rows = ;")
+except knitlang.KnitError as e:
+    assert (e.kind, e.line, e.column) == ("parse", 2, 13), vars(e)
+else:
+    raise AssertionError("expected a KnitError")
+assert session.variables == {"rows": 3}
+
+session.feed("repeat 2 {")
+try:
+    session.finish()
+except knitlang.KnitError as e:
+    assert e.kind == "parse"
+else:
+    raise AssertionError("expected a KnitError")
+"#,
+    );
+}
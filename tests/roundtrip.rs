@@ -0,0 +1,361 @@
+//! Property tests keeping the parser and the printer in step: any tree the
+//! printer writes out parses back to the same tree, and formatting is
+//! idempotent. A new kind of statement or expression only needs a case in
+//! the generators below to be covered.
+
+use knitlang::{
+    format_program, parse_src, Cross, Expr, MetaEntry, MetaValue, Mode, NotePart, Pattern, Shaping,
+    Span, Stitch, Stmt, StmtKind, Symbol,
+};
+use proptest::prelude::*;
+use std::fs;
+use std::path::Path;
+
+/// Variable names; none of them is a keyword or a stitch.
+const NAMES: &[&str] = &["width", "rows", "n", "total_2", "yarnOver"];
+/// Variables of an import, read by their qualified names.
+const QUALIFIED: &[&str] = &["edgings.width", "lib.n"];
+/// Function names, looked up separately from variables.
+const FUNCTIONS: &[&str] = &["max", "lookup", "math.max"];
+/// Yarn colours, in both the forms `yarn` accepts.
+const COLOURS: &[&str] = &["#1d3557", "#E63946", "#fff"];
+/// The number of sizes every `sizes` declaration names and every size tuple
+/// has, so a tuple always matches the declaration before it.
+const SIZES: usize = 3;
+/// Paths for `use`.
+const PATHS: &[&str] = &["ribbing.knit", "lib/edge trim.knit", ""];
+/// Keys for `meta` blocks, `yarn` among them though it is a keyword.
+const META_KEYS: &[&str] = &["title", "author", "yarn", "needle", "designer"];
+/// Strings for `note`s.
+const NOTES: &[&str] = &["Switch to 3.5mm needles", "", " sts; {n}, "];
+/// String values for `meta` keys.
+const META_STRINGS: &[&str] = &["Winter Hat", "4mm", "", "50% wool; 50% {alpaca}"];
+
+/// An expression to build, kept separate from [`Expr`] so the generators can
+/// clone and shrink it.
+#[derive(Debug, Clone)]
+enum GenExpr {
+    Number(i64),
+    Var(&'static str),
+    StitchesTo(&'static str),
+    Count(&'static str),
+    Sizes(Vec<i64>),
+    Call(&'static str, Vec<GenExpr>),
+    Binary(Box<GenExpr>, char, Box<GenExpr>),
+}
+
+#[derive(Debug, Clone)]
+enum GenStmt {
+    CastOn(&'static str, GenExpr),
+    CastOnStitches(GenExpr),
+    Knit(&'static str, GenExpr),
+    Purl(GenExpr),
+    Work(Stitch, GenExpr),
+    Cable(GenExpr, Cross),
+    Repeat(GenExpr, Vec<GenStmt>),
+    BindOff,
+    BindOffStitches(GenExpr),
+    ExpectStitches(GenExpr),
+    ExpectStitchesWarning(GenExpr),
+    Yarn(&'static str, &'static str),
+    Color(&'static str),
+    WrapTurn,
+    Mode(Mode),
+    PlaceMarker(&'static str),
+    SlipMarker(&'static str),
+    Use(&'static str, Option<&'static str>),
+    Sizes(Vec<&'static str>),
+    Gauge(GenExpr, GenExpr),
+    Distribute(Shaping, GenExpr),
+    Mirror(Vec<GenStmt>),
+    Alternate(Vec<Vec<GenStmt>>),
+    Pattern(Pattern, Vec<GenExpr>),
+    Meta(Vec<(&'static str, MetaValue)>),
+    /// The first string of a `note`, then its other parts: `Ok` for a
+    /// string and `Err` for an expression.
+    Note(&'static str, Vec<Result<&'static str, GenExpr>>),
+    Chart(Option<&'static str>),
+    CastOnNeedle(&'static str, GenExpr),
+    Transfer(&'static str, &'static str, GenExpr),
+    Hold(&'static str),
+    Resume(&'static str),
+}
+
+impl GenExpr {
+    fn build(&self) -> Expr {
+        let nowhere = Span::new(0, 0);
+        match self {
+            GenExpr::Number(n) => Expr::number(*n, nowhere),
+            GenExpr::Var(name) => Expr::var(Symbol::intern(name), nowhere),
+            GenExpr::StitchesTo(marker) => Expr::stitches_to(Symbol::intern(marker), nowhere),
+            GenExpr::Count(needle) => Expr::count(Symbol::intern(needle), nowhere),
+            GenExpr::Sizes(values) => Expr::sizes(values.clone(), nowhere),
+            GenExpr::Call(name, args) => Expr::call(
+                Symbol::intern(name),
+                args.iter().map(GenExpr::build).collect(),
+                nowhere,
+            ),
+            GenExpr::Binary(lhs, op, rhs) => Expr::binary(lhs.build(), *op, rhs.build()),
+        }
+    }
+}
+
+impl GenStmt {
+    fn build(&self) -> Stmt {
+        let kind = match self {
+            GenStmt::CastOn(name, e) => StmtKind::CastOn(Symbol::intern(name), e.build()),
+            GenStmt::CastOnStitches(e) => StmtKind::CastOnStitches(e.build()),
+            GenStmt::Knit(name, e) => StmtKind::Knit(Symbol::intern(name), e.build()),
+            GenStmt::Purl(e) => StmtKind::Purl(e.build()),
+            GenStmt::Work(stitch, e) => StmtKind::Work(*stitch, e.build()),
+            GenStmt::Cable(e, cross) => StmtKind::Cable(e.build(), *cross),
+            GenStmt::Repeat(count, body) => {
+                StmtKind::Repeat(count.build(), body.iter().map(GenStmt::build).collect())
+            }
+            GenStmt::BindOff => StmtKind::BindOff,
+            GenStmt::BindOffStitches(e) => StmtKind::BindOffStitches(e.build()),
+            GenStmt::ExpectStitches(e) => StmtKind::ExpectStitches(e.build()),
+            GenStmt::ExpectStitchesWarning(e) => StmtKind::ExpectStitchesWarning(e.build()),
+            GenStmt::Yarn(name, colour) => StmtKind::Yarn(Symbol::intern(name), colour.to_string()),
+            GenStmt::Color(name) => StmtKind::Color(Symbol::intern(name)),
+            GenStmt::WrapTurn => StmtKind::WrapTurn,
+            GenStmt::Mode(mode) => StmtKind::Mode(*mode),
+            GenStmt::PlaceMarker(name) => StmtKind::PlaceMarker(Symbol::intern(name)),
+            GenStmt::SlipMarker(name) => StmtKind::SlipMarker(Symbol::intern(name)),
+            GenStmt::Use(path, alias) => StmtKind::Use(path.to_string(), alias.map(Symbol::intern)),
+            GenStmt::Mirror(body) => StmtKind::Mirror(body.iter().map(GenStmt::build).collect()),
+            GenStmt::Alternate(bodies) => StmtKind::Alternate(
+                bodies
+                    .iter()
+                    .map(|body| body.iter().map(GenStmt::build).collect())
+                    .collect(),
+            ),
+            GenStmt::Pattern(pattern, args) => {
+                StmtKind::Pattern(*pattern, args.iter().map(GenExpr::build).collect())
+            }
+            GenStmt::Sizes(names) => {
+                StmtKind::Sizes(names.iter().map(|n| Symbol::intern(n)).collect())
+            }
+            GenStmt::Gauge(stitches, rows) => StmtKind::Gauge(stitches.build(), rows.build()),
+            GenStmt::Distribute(shaping, count) => StmtKind::Distribute(*shaping, count.build()),
+            GenStmt::Meta(entries) => StmtKind::Meta(
+                entries
+                    .iter()
+                    .map(|(key, value)| MetaEntry {
+                        key: Symbol::intern(key),
+                        value: value.clone(),
+                        span: Span::new(0, 0),
+                    })
+                    .collect(),
+            ),
+            GenStmt::Chart(label) => StmtKind::Chart(label.map(str::to_string)),
+            GenStmt::CastOnNeedle(name, e) => {
+                StmtKind::CastOnNeedle(Symbol::intern(name), e.build())
+            }
+            GenStmt::Transfer(from, to, e) => {
+                StmtKind::Transfer(Symbol::intern(from), Symbol::intern(to), e.build())
+            }
+            GenStmt::Hold(name) => StmtKind::Hold(Symbol::intern(name)),
+            GenStmt::Resume(name) => StmtKind::Resume(Symbol::intern(name)),
+            GenStmt::Note(first, rest) => StmtKind::Note(
+                std::iter::once(NotePart::Text(first.to_string()))
+                    .chain(rest.iter().map(|part| match part {
+                        Ok(text) => NotePart::Text(text.to_string()),
+                        Err(e) => NotePart::Expr(e.build()),
+                    }))
+                    .collect(),
+            ),
+        };
+        Stmt {
+            kind,
+            span: Span::new(0, 0),
+        }
+    }
+}
+
+fn name() -> impl Strategy<Value = &'static str> {
+    proptest::sample::select(NAMES)
+}
+
+/// Folds `first op rest...` to the left, as the parser groups operators of
+/// equal precedence.
+fn fold(first: GenExpr, rest: Vec<(char, GenExpr)>) -> GenExpr {
+    rest.into_iter().fold(first, |lhs, (op, rhs)| {
+        GenExpr::Binary(Box::new(lhs), op, Box::new(rhs))
+    })
+}
+
+/// A sum of products of terms. The language has no parentheses, so only
+/// trees shaped the way the grammar groups operators can be printed; nested
+/// expressions appear only as call arguments. `depth` bounds that nesting.
+fn expr(depth: u32) -> BoxedStrategy<GenExpr> {
+    let leaf = prop_oneof![
+        prop_oneof![0..1000i64, Just(i64::MAX)].prop_map(GenExpr::Number),
+        name().prop_map(GenExpr::Var),
+        proptest::sample::select(QUALIFIED).prop_map(GenExpr::Var),
+        name().prop_map(GenExpr::StitchesTo),
+        name().prop_map(GenExpr::Count),
+        prop::collection::vec(0..1000i64, SIZES).prop_map(GenExpr::Sizes),
+    ];
+    let term = if depth == 0 {
+        leaf.boxed()
+    } else {
+        let call = (
+            proptest::sample::select(FUNCTIONS),
+            prop::collection::vec(expr(depth - 1), 0..3),
+        )
+            .prop_map(|(name, args)| GenExpr::Call(name, args));
+        prop_oneof![3 => leaf, 1 => call].boxed()
+    };
+    let product = (
+        term.clone(),
+        prop::collection::vec((prop_oneof![Just('*'), Just('/')], term), 0..3),
+    )
+        .prop_map(|(first, rest)| fold(first, rest));
+    (
+        product.clone(),
+        prop::collection::vec((prop_oneof![Just('+'), Just('-')], product), 0..3),
+    )
+        .prop_map(|(first, rest)| fold(first, rest))
+        .boxed()
+}
+
+fn stmt() -> impl Strategy<Value = GenStmt> {
+    let simple = prop_oneof![
+        (name(), expr(2)).prop_map(|(n, e)| GenStmt::CastOn(n, e)),
+        expr(2).prop_map(GenStmt::CastOnStitches),
+        (name(), expr(2)).prop_map(|(n, e)| GenStmt::Knit(n, e)),
+        expr(2).prop_map(GenStmt::Purl),
+        (
+            prop_oneof![
+                Just(Stitch::Knit),
+                Just(Stitch::Purl),
+                Just(Stitch::Cable(2, Cross::Front)),
+                Just(Stitch::Cable(3, Cross::Back)),
+                Just(Stitch::K2tog),
+                Just(Stitch::Ssk),
+                Just(Stitch::YarnOver),
+                Just(Stitch::M1),
+            ],
+            expr(2)
+        )
+            .prop_map(|(s, e)| GenStmt::Work(s, e)),
+        (expr(2), prop_oneof![Just(Cross::Front), Just(Cross::Back)])
+            .prop_map(|(e, cross)| GenStmt::Cable(e, cross)),
+        Just(GenStmt::BindOff),
+        expr(2).prop_map(GenStmt::BindOffStitches),
+        expr(2).prop_map(GenStmt::ExpectStitches),
+        expr(2).prop_map(GenStmt::ExpectStitchesWarning),
+        (
+            proptest::sample::select(NOTES),
+            prop::collection::vec(
+                prop_oneof![
+                    proptest::sample::select(NOTES).prop_map(Ok),
+                    expr(2).prop_map(Err)
+                ],
+                0..3
+            )
+        )
+            .prop_map(|(first, rest)| GenStmt::Note(first, rest)),
+        proptest::option::of(proptest::sample::select(NOTES)).prop_map(GenStmt::Chart),
+        (name(), expr(2)).prop_map(|(n, e)| GenStmt::CastOnNeedle(n, e)),
+        (name(), name(), expr(2)).prop_map(|(from, to, e)| GenStmt::Transfer(from, to, e)),
+        name().prop_map(GenStmt::Hold),
+        name().prop_map(GenStmt::Resume),
+        (name(), proptest::sample::select(COLOURS)).prop_map(|(n, c)| GenStmt::Yarn(n, c)),
+        name().prop_map(GenStmt::Color),
+        Just(GenStmt::WrapTurn),
+        prop_oneof![Just(Mode::Flat), Just(Mode::Round)].prop_map(GenStmt::Mode),
+        name().prop_map(GenStmt::PlaceMarker),
+        name().prop_map(GenStmt::SlipMarker),
+        prop::collection::vec(name(), SIZES).prop_map(GenStmt::Sizes),
+        (expr(2), expr(2)).prop_map(|(stitches, rows)| GenStmt::Gauge(stitches, rows)),
+        (
+            prop_oneof![Just(Shaping::Increase), Just(Shaping::Decrease)],
+            expr(2)
+        )
+            .prop_map(|(shaping, count)| GenStmt::Distribute(shaping, count)),
+        proptest::sample::select(Pattern::ALL.to_vec()).prop_flat_map(|pattern| {
+            prop::collection::vec(expr(2), pattern.params().len())
+                .prop_map(move |args| GenStmt::Pattern(pattern, args))
+        }),
+    ];
+    simple.prop_recursive(3, 24, 4, |inner| {
+        // An `alternate` only parses inside a `repeat`.
+        let alternate = prop::collection::vec(prop::collection::vec(inner.clone(), 0..3), 1..4)
+            .prop_map(GenStmt::Alternate);
+        let in_repeat = prop_oneof![3 => inner.clone(), 1 => alternate];
+        prop_oneof![
+            (expr(1), prop::collection::vec(in_repeat, 0..4))
+                .prop_map(|(count, body)| GenStmt::Repeat(count, body)),
+            prop::collection::vec(inner, 0..4).prop_map(GenStmt::Mirror),
+        ]
+    })
+}
+
+/// A statement at the top level of a file, where `use` may go as well.
+fn top_level_stmt() -> impl Strategy<Value = GenStmt> {
+    prop_oneof![
+        8 => stmt(),
+        1 => (proptest::sample::select(PATHS), proptest::option::of(name()))
+            .prop_map(|(path, alias)| GenStmt::Use(path, alias)),
+    ]
+}
+
+/// A `meta` block, which only goes first, with each key at most once.
+fn meta() -> impl Strategy<Value = GenStmt> {
+    let value = prop_oneof![
+        proptest::sample::select(META_STRINGS).prop_map(|s| MetaValue::Str(s.to_string())),
+        (-1000..1000i64).prop_map(MetaValue::Number),
+    ];
+    proptest::sample::subsequence(META_KEYS, 0..=META_KEYS.len())
+        .prop_shuffle()
+        .prop_flat_map(move |keys| {
+            let n = keys.len();
+            prop::collection::vec(value.clone(), n)
+                .prop_map(move |values| GenStmt::Meta(keys.iter().copied().zip(values).collect()))
+        })
+}
+
+fn build(program: &[GenStmt]) -> Vec<Stmt> {
+    program.iter().map(GenStmt::build).collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn printed_trees_parse_back_to_themselves(
+        meta in proptest::option::of(meta()),
+        rest in prop::collection::vec(top_level_stmt(), 0..6),
+    ) {
+        let program: Vec<GenStmt> = meta.into_iter().chain(rest).collect();
+        let printed = format_program(&build(&program));
+        let parsed = parse_src(&printed);
+        prop_assert!(parsed.is_ok(), "{printed}\n{parsed:?}");
+        prop_assert_eq!(parsed.unwrap(), build(&program), "{}", printed);
+    }
+}
+
+#[test]
+fn formatting_is_idempotent_on_every_program_in_the_repo() {
+    let mut files = vec![
+        Path::new("example.kl").to_path_buf(),
+        Path::new("templates/main.knit").to_path_buf(),
+    ];
+    for entry in fs::read_dir("examples").unwrap() {
+        files.push(entry.unwrap().path());
+    }
+    for path in files {
+        let src = fs::read_to_string(&path).unwrap();
+        let stmts = parse_src(&src).unwrap();
+        let once = format_program(&stmts);
+        let reparsed = parse_src(&once).unwrap();
+        assert_eq!(reparsed, stmts, "{} changed meaning", path.display());
+        assert_eq!(format_program(&reparsed), once, "{}", path.display());
+        let commented = knitlang::ast::format_with_comments(&stmts, &src);
+        let again =
+            knitlang::ast::format_with_comments(&parse_src(&commented).unwrap(), &commented);
+        assert_eq!(again, commented, "{}", path.display());
+    }
+}
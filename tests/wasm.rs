@@ -0,0 +1,28 @@
+//! Runs under `wasm-bindgen-test-runner` (see .cargo/config.toml):
+//! `cargo test --target wasm32-unknown-unknown --no-default-features --features wasm --test wasm`
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use wasm_bindgen_test::wasm_bindgen_test;
+
+fn run(src: &str) -> serde_json::Value {
+    serde_wasm_bindgen::from_value(knitlang::wasm::run(src)).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn runs_the_swatch_example() {
+    let result = run(include_str!("../examples/swatch.knit"));
+    assert_eq!(result["output"], "12\n");
+    assert_eq!(result["errors"], serde_json::json!([]));
+    assert_eq!(result["variables"]["width"], 12);
+    assert_eq!(result["chart"].as_array().unwrap().len(), 10);
+    assert_eq!(result["chart"][0][2], "p");
+}
+
+#[wasm_bindgen_test]
+fn reports_errors_as_data() {
+    let result = run("purl 1;\npurl 1 / 0;\n");
+    assert_eq!(result["output"], "1\n");
+    assert_eq!(result["errors"][0]["kind"], "runtime");
+    assert_eq!(result["errors"][0]["message"], "division by zero");
+    assert_eq!(result["errors"][0]["line"], 2);
+}